@@ -0,0 +1,28 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use pahkat_client::{PackageKey, PackageStore};
+
+pub(crate) async fn bundle(
+    store: Arc<dyn PackageStore>,
+    packages: &[String],
+    output_path: &Path,
+) -> Result<(), anyhow::Error> {
+    let keys: Vec<PackageKey> = packages
+        .iter()
+        .map(|id| {
+            store
+                .find_package_by_id(id)
+                .map(|x| x.0)
+                .ok_or_else(|| anyhow::anyhow!("Could not find package for: `{}`", id))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let bundled = pahkat_client::bundle::export(store, keys, output_path).await?;
+
+    println!("Wrote {}:", output_path.display());
+    for key in bundled.iter() {
+        println!(" - {}", key);
+    }
+    Ok(())
+}
@@ -6,6 +6,11 @@ use structopt::clap::AppSettings::*;
 use structopt::StructOpt;
 pub(crate) trait ConfigPath {
     fn config_path(&self) -> Option<&Path>;
+
+    /// The name of a registered prefix, used when no config path is given.
+    fn prefix(&self) -> Option<&str> {
+        None
+    }
 }
 
 pub(crate) trait Platform {
@@ -35,9 +40,19 @@ pub(crate) enum Args {
     #[structopt(template(SUB_TEMPLATE))]
     Uninstall(command::Uninstall),
     #[structopt(template(SUB_TEMPLATE))]
+    Bundle(command::Bundle),
+    #[structopt(template(SUB_TEMPLATE))]
     Status(command::Status),
+    #[structopt(template(SUB_TEMPLATE))]
+    DepsStatus(command::DepsStatus),
+    #[structopt(template(SUB_TEMPLATE))]
+    Show(command::Show),
+    #[structopt(template(SUB_TEMPLATE))]
+    Report(command::Report),
     #[structopt(template(SUBC_TEMPLATE))]
     Config(command::Config),
+    #[structopt(template(SUBC_TEMPLATE))]
+    State(command::State),
 }
 
 impl ConfigPath for Args {
@@ -48,8 +63,31 @@ impl ConfigPath for Args {
             Args::Download(x) => x.config_path(),
             Args::Install(x) => x.config_path(),
             Args::Uninstall(x) => x.config_path(),
+            Args::Bundle(x) => x.config_path(),
             Args::Config(x) => x.config_path(),
             Args::Status(x) => x.config_path(),
+            Args::DepsStatus(x) => x.config_path(),
+            Args::Show(x) => x.config_path(),
+            Args::Report(x) => x.config_path(),
+            Args::State(x) => x.config_path(),
+        }
+    }
+
+    #[cfg(feature = "prefix")]
+    #[inline]
+    fn prefix(&self) -> Option<&str> {
+        match self {
+            Args::Init(x) => x.prefix(),
+            Args::Download(x) => x.prefix(),
+            Args::Install(x) => x.prefix(),
+            Args::Uninstall(x) => x.prefix(),
+            Args::Bundle(x) => x.prefix(),
+            Args::Config(x) => x.prefix(),
+            Args::Status(x) => x.prefix(),
+            Args::DepsStatus(x) => x.prefix(),
+            Args::Show(x) => x.prefix(),
+            Args::Report(x) => x.prefix(),
+            Args::State(x) => x.prefix(),
         }
     }
 }
@@ -62,7 +100,12 @@ impl Platform for Args {
             Args::Download(x) => x.platform(),
             Args::Install(x) => x.platform(),
             Args::Uninstall(x) => x.platform(),
+            Args::Bundle(x) => x.platform(),
             Args::Status(x) => x.platform(),
+            Args::DepsStatus(x) => x.platform(),
+            Args::Show(x) => x.platform(),
+            Args::Report(x) => x.platform(),
+            Args::State(x) => x.platform(),
             Args::Config(x) => None,
         }
     }
@@ -78,6 +121,13 @@ struct GlobalOpts {
     )]
     config_path: Option<PathBuf>,
 
+    #[cfg(feature = "prefix")]
+    #[structopt(
+        long,
+        help = "Name of a registered prefix [default: the default prefix]"
+    )]
+    prefix: Option<String>,
+
     #[cfg_attr(
         windows,
         structopt(short = "P", long, help = "Target platform [default: windows]")
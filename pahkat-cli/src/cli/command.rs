@@ -1,4 +1,5 @@
 pub(crate) mod config;
+pub(crate) mod state;
 
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
@@ -78,8 +79,45 @@ impl FromStr for PackageSpec {
 #[derive(Debug, StructOpt)]
 #[structopt(about = "Install packages from configured repositories")]
 pub struct Install {
-    #[structopt(required = true, help = "Packages to install")]
+    #[structopt(
+        required_unless = "from-bundle",
+        help = "Packages to install [default: every package the bundle was made for]"
+    )]
     pub packages: Vec<PackageSpec>,
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Install from an offline bundle without network access"
+    )]
+    pub from_bundle: Option<PathBuf>,
+    #[structopt(
+        long,
+        help = "Install releases even if they are marked as critically deprecated"
+    )]
+    pub allow_deprecated: bool,
+    #[structopt(
+        long,
+        help = "Install every package or none, undoing fresh installs if one fails"
+    )]
+    pub atomic: bool,
+    #[structopt(flatten)]
+    global_opts: super::GlobalOpts,
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(about = "Export packages and their dependencies into an offline bundle")]
+pub struct Bundle {
+    #[structopt(required = true, help = "Packages to bundle")]
+    pub packages: Vec<String>,
+
+    #[structopt(
+        short,
+        long = "output",
+        help = "Path of the bundle to write",
+        parse(from_os_str)
+    )]
+    pub output_path: PathBuf,
+
     #[structopt(flatten)]
     global_opts: super::GlobalOpts,
 }
@@ -105,6 +143,18 @@ pub struct Init {
 pub enum Config {
     #[structopt(template(SUBC_TEMPLATE))]
     Repo(config::Repo),
+    #[cfg(feature = "prefix")]
+    #[structopt(template(SUBC_TEMPLATE))]
+    Prefix(config::Prefix),
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(about = "Export or apply the installed package state of this machine")]
+pub enum State {
+    #[structopt(template(SUB_TEMPLATE))]
+    Export(state::Export),
+    #[structopt(template(SUB_TEMPLATE))]
+    Apply(state::Apply),
 }
 
 #[derive(Debug, StructOpt)]
@@ -112,6 +162,64 @@ pub enum Config {
 pub struct Status {
     #[structopt(help = "Packages to query status of")]
     pub packages: Vec<String>,
+    #[structopt(
+        short,
+        long,
+        conflicts_with = "packages",
+        help = "Query every installed package in all configured repositories"
+    )]
+    pub all: bool,
+    #[structopt(
+        long,
+        requires = "all",
+        help = "Only query packages from this repository"
+    )]
+    pub repo: Option<pahkat_types::repo::RepoUrl>,
+    #[structopt(long, help = "Print results as JSON")]
+    pub json: bool,
+    #[structopt(
+        short,
+        long,
+        help = "Also show installs that failed in the background updater"
+    )]
+    pub verbose: bool,
+    #[structopt(flatten)]
+    global_opts: super::GlobalOpts,
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(about = "Query status of the dependencies of a package")]
+pub struct DepsStatus {
+    #[structopt(help = "Package to query the dependencies of")]
+    pub package: String,
+    #[structopt(long, help = "Print results as JSON")]
+    pub json: bool,
+    #[structopt(flatten)]
+    global_opts: super::GlobalOpts,
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(about = "Show a package and where its latest release was built")]
+pub struct Show {
+    #[structopt(help = "Package to show")]
+    pub package: String,
+    #[structopt(long, help = "Print the package as JSON")]
+    pub json: bool,
+    #[structopt(flatten)]
+    global_opts: super::GlobalOpts,
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(about = "Print an update compliance report for this machine")]
+pub struct Report {
+    #[structopt(
+        short,
+        long,
+        default_value = "json",
+        possible_values = &["json"],
+        help = "Output format"
+    )]
+    pub format: String,
     #[structopt(flatten)]
     global_opts: super::GlobalOpts,
 }
@@ -146,6 +254,20 @@ impl Platform for Install {
     }
 }
 
+impl ConfigPath for Bundle {
+    #[inline]
+    fn config_path(&self) -> Option<&Path> {
+        self.global_opts.config_path.as_deref()
+    }
+}
+
+impl Platform for Bundle {
+    #[inline]
+    fn platform(&self) -> Option<&str> {
+        self.global_opts.platform.as_deref()
+    }
+}
+
 impl ConfigPath for Uninstall {
     #[inline]
     fn config_path(&self) -> Option<&Path> {
@@ -174,6 +296,60 @@ impl Platform for Status {
     }
 }
 
+impl ConfigPath for DepsStatus {
+    #[inline]
+    fn config_path(&self) -> Option<&Path> {
+        self.global_opts.config_path.as_deref()
+    }
+
+    #[cfg(feature = "prefix")]
+    #[inline]
+    fn prefix(&self) -> Option<&str> {
+        self.global_opts.prefix.as_deref()
+    }
+}
+
+impl Platform for DepsStatus {
+    #[inline]
+    fn platform(&self) -> Option<&str> {
+        self.global_opts.platform.as_deref()
+    }
+}
+
+impl ConfigPath for Show {
+    #[inline]
+    fn config_path(&self) -> Option<&Path> {
+        self.global_opts.config_path.as_deref()
+    }
+
+    #[cfg(feature = "prefix")]
+    #[inline]
+    fn prefix(&self) -> Option<&str> {
+        self.global_opts.prefix.as_deref()
+    }
+}
+
+impl Platform for Show {
+    #[inline]
+    fn platform(&self) -> Option<&str> {
+        self.global_opts.platform.as_deref()
+    }
+}
+
+impl ConfigPath for Report {
+    #[inline]
+    fn config_path(&self) -> Option<&Path> {
+        self.global_opts.config_path.as_deref()
+    }
+}
+
+impl Platform for Report {
+    #[inline]
+    fn platform(&self) -> Option<&str> {
+        self.global_opts.platform.as_deref()
+    }
+}
+
 impl ConfigPath for Init {
     #[inline]
     fn config_path(&self) -> Option<&Path> {
@@ -193,6 +369,46 @@ impl ConfigPath for Config {
     fn config_path(&self) -> Option<&Path> {
         match self {
             Config::Repo(x) => x.config_path(),
+            #[cfg(feature = "prefix")]
+            Config::Prefix(_) => None,
+        }
+    }
+
+    #[cfg(feature = "prefix")]
+    #[inline]
+    fn prefix(&self) -> Option<&str> {
+        match self {
+            Config::Repo(x) => x.prefix(),
+            Config::Prefix(_) => None,
+        }
+    }
+}
+
+impl ConfigPath for State {
+    #[inline]
+    fn config_path(&self) -> Option<&Path> {
+        match self {
+            State::Export(x) => x.config_path(),
+            State::Apply(x) => x.config_path(),
+        }
+    }
+
+    #[cfg(feature = "prefix")]
+    #[inline]
+    fn prefix(&self) -> Option<&str> {
+        match self {
+            State::Export(x) => x.prefix(),
+            State::Apply(x) => x.prefix(),
+        }
+    }
+}
+
+impl Platform for State {
+    #[inline]
+    fn platform(&self) -> Option<&str> {
+        match self {
+            State::Export(x) => x.platform(),
+            State::Apply(x) => x.platform(),
         }
     }
 }
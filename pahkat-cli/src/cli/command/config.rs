@@ -1,3 +1,5 @@
+#[cfg(feature = "prefix")]
+pub(crate) mod prefix;
 pub(crate) mod repo;
 
 use crate::cli::constants::*;
@@ -12,6 +14,12 @@ pub enum Repo {
     Remove(repo::Remove),
     #[structopt(template(SUBN_TEMPLATE))]
     List(repo::List),
+    #[structopt(template(SUB_TEMPLATE))]
+    Stats(repo::Stats),
+    #[structopt(template(SUB_TEMPLATE))]
+    Hold(repo::Hold),
+    #[structopt(template(SUB_TEMPLATE))]
+    Skip(repo::Skip),
 }
 
 impl crate::ConfigPath for Repo {
@@ -21,6 +29,35 @@ impl crate::ConfigPath for Repo {
             Repo::Add(x) => x.config_path(),
             Repo::Remove(x) => x.config_path(),
             Repo::List(x) => x.config_path(),
+            Repo::Stats(x) => x.config_path(),
+            Repo::Hold(x) => x.config_path(),
+            Repo::Skip(x) => x.config_path(),
+        }
+    }
+
+    #[cfg(feature = "prefix")]
+    #[inline]
+    fn prefix(&self) -> Option<&str> {
+        match self {
+            Repo::Add(x) => x.prefix(),
+            Repo::Remove(x) => x.prefix(),
+            Repo::List(x) => x.prefix(),
+            Repo::Stats(x) => x.prefix(),
+            Repo::Hold(x) => x.prefix(),
+            Repo::Skip(x) => x.prefix(),
         }
     }
 }
+
+#[cfg(feature = "prefix")]
+#[derive(Debug, StructOpt)]
+pub enum Prefix {
+    #[structopt(template(SUB_TEMPLATE))]
+    Add(prefix::Add),
+    #[structopt(template(SUB_TEMPLATE))]
+    Remove(prefix::Remove),
+    #[structopt(template(SUBN_TEMPLATE))]
+    List(prefix::List),
+    #[structopt(template(SUB_TEMPLATE))]
+    Use(prefix::Use),
+}
@@ -0,0 +1,30 @@
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(about = "Register a prefix under a name")]
+pub struct Add {
+    #[structopt(help = "Prefix name")]
+    pub name: String,
+
+    #[structopt(help = "Path to the prefix", parse(from_os_str))]
+    pub path: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(about = "Unregister a prefix, leaving its files in place")]
+pub struct Remove {
+    #[structopt(help = "Prefix name")]
+    pub name: String,
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(about = "List all registered prefixes")]
+pub struct List {}
+
+#[derive(Debug, StructOpt)]
+#[structopt(about = "Use a registered prefix when none is given")]
+pub struct Use {
+    #[structopt(help = "Prefix name")]
+    pub name: String,
+}
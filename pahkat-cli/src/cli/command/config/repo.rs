@@ -10,6 +10,13 @@ pub struct RepoArgs {
         parse(from_os_str)
     )]
     pub config_path: Option<PathBuf>,
+
+    #[cfg(feature = "prefix")]
+    #[structopt(
+        long,
+        help = "Name of a registered prefix [default: the default prefix]"
+    )]
+    pub prefix: Option<String>,
 }
 
 #[derive(Debug, StructOpt)]
@@ -21,6 +28,35 @@ pub struct Add {
     #[structopt(help = "Repository package channel")]
     pub channel: Option<String>,
 
+    #[structopt(
+        long = "trusted-key",
+        help = "Base64 ed25519 public key the repository index must be signed with"
+    )]
+    pub trusted_keys: Vec<String>,
+
+    #[structopt(
+        long,
+        env = "PAHKAT_REPO_AUTH_TOKEN",
+        hide_env_values = true,
+        help = "Bearer token sent with requests to the repository"
+    )]
+    pub auth_token: Option<String>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "PEM client certificate for repositories that require mutual TLS"
+    )]
+    pub client_certificate: Option<std::path::PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        requires = "client-certificate",
+        help = "PEM private key of the client certificate, if not in the same file"
+    )]
+    pub client_key: Option<std::path::PathBuf>,
+
     #[structopt(flatten)]
     args: RepoArgs,
 }
@@ -31,6 +67,27 @@ pub struct Remove {
     #[structopt(help = "Repository URL")]
     pub repo_url: pahkat_types::repo::RepoUrl,
 
+    #[structopt(
+        long,
+        help = "Uninstall the packages installed from the repository first"
+    )]
+    pub purge: bool,
+
+    #[structopt(
+        long,
+        requires = "purge",
+        help = "Only list the packages that would be uninstalled"
+    )]
+    pub dry_run: bool,
+
+    #[structopt(
+        short,
+        long,
+        requires = "purge",
+        help = "Uninstall without asking for confirmation"
+    )]
+    pub yes: bool,
+
     #[structopt(flatten)]
     args: RepoArgs,
 }
@@ -45,6 +102,58 @@ pub struct List {
         parse(from_os_str)
     )]
     pub config_path: Option<PathBuf>,
+
+    #[cfg(feature = "prefix")]
+    #[structopt(
+        long,
+        help = "Name of a registered prefix [default: the default prefix]"
+    )]
+    pub prefix: Option<String>,
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(about = "Show package counts, sizes and refresh times of repositories")]
+pub struct Stats {
+    #[structopt(help = "Repository URL [default: all configured repositories]")]
+    pub repo_url: Option<pahkat_types::repo::RepoUrl>,
+
+    #[structopt(long, help = "Print results as JSON")]
+    pub json: bool,
+
+    #[structopt(flatten)]
+    args: RepoArgs,
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(about = "Keep a package at its installed version")]
+pub struct Hold {
+    #[structopt(help = "Repository URL")]
+    pub repo_url: pahkat_types::repo::RepoUrl,
+
+    #[structopt(help = "Package identifier")]
+    pub package: String,
+
+    #[structopt(long, help = "Allow the package to be updated again")]
+    pub release: bool,
+
+    #[structopt(flatten)]
+    args: RepoArgs,
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(about = "Skip updating a package to a release")]
+pub struct Skip {
+    #[structopt(help = "Repository URL")]
+    pub repo_url: pahkat_types::repo::RepoUrl,
+
+    #[structopt(help = "Package identifier")]
+    pub package: String,
+
+    #[structopt(help = "Version to skip [default: stop skipping]")]
+    pub version: Option<String>,
+
+    #[structopt(flatten)]
+    args: RepoArgs,
 }
 
 impl crate::ConfigPath for Add {
@@ -52,6 +161,12 @@ impl crate::ConfigPath for Add {
     fn config_path(&self) -> Option<&std::path::Path> {
         self.args.config_path.as_ref().map(PathBuf::as_path)
     }
+
+    #[cfg(feature = "prefix")]
+    #[inline]
+    fn prefix(&self) -> Option<&str> {
+        self.args.prefix.as_deref()
+    }
 }
 
 impl crate::ConfigPath for Remove {
@@ -59,6 +174,12 @@ impl crate::ConfigPath for Remove {
     fn config_path(&self) -> Option<&std::path::Path> {
         self.args.config_path.as_ref().map(PathBuf::as_path)
     }
+
+    #[cfg(feature = "prefix")]
+    #[inline]
+    fn prefix(&self) -> Option<&str> {
+        self.args.prefix.as_deref()
+    }
 }
 
 impl crate::ConfigPath for List {
@@ -66,4 +187,49 @@ impl crate::ConfigPath for List {
     fn config_path(&self) -> Option<&std::path::Path> {
         self.config_path.as_ref().map(PathBuf::as_path)
     }
+
+    #[cfg(feature = "prefix")]
+    #[inline]
+    fn prefix(&self) -> Option<&str> {
+        self.prefix.as_deref()
+    }
+}
+
+impl crate::ConfigPath for Stats {
+    #[inline]
+    fn config_path(&self) -> Option<&std::path::Path> {
+        self.args.config_path.as_deref()
+    }
+
+    #[cfg(feature = "prefix")]
+    #[inline]
+    fn prefix(&self) -> Option<&str> {
+        self.args.prefix.as_deref()
+    }
+}
+
+impl crate::ConfigPath for Hold {
+    #[inline]
+    fn config_path(&self) -> Option<&std::path::Path> {
+        self.args.config_path.as_deref()
+    }
+
+    #[cfg(feature = "prefix")]
+    #[inline]
+    fn prefix(&self) -> Option<&str> {
+        self.args.prefix.as_deref()
+    }
+}
+
+impl crate::ConfigPath for Skip {
+    #[inline]
+    fn config_path(&self) -> Option<&std::path::Path> {
+        self.args.config_path.as_deref()
+    }
+
+    #[cfg(feature = "prefix")]
+    #[inline]
+    fn prefix(&self) -> Option<&str> {
+        self.args.prefix.as_deref()
+    }
 }
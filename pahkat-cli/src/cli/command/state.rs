@@ -0,0 +1,78 @@
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+use crate::{ConfigPath, Platform};
+
+#[derive(Debug, StructOpt)]
+#[structopt(about = "Write a manifest of the installed packages")]
+pub struct Export {
+    #[structopt(
+        short,
+        long = "output",
+        help = "Manifest path [default: standard output]",
+        parse(from_os_str)
+    )]
+    pub output_path: Option<PathBuf>,
+
+    #[structopt(flatten)]
+    global_opts: crate::cli::GlobalOpts,
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(about = "Install and update packages to match a manifest")]
+pub struct Apply {
+    #[structopt(help = "Manifest path", parse(from_os_str))]
+    pub manifest_path: PathBuf,
+
+    #[structopt(long, help = "Uninstall packages that are not in the manifest")]
+    pub prune: bool,
+
+    #[structopt(
+        long,
+        help = "Install releases even if they are marked as critically deprecated"
+    )]
+    pub allow_deprecated: bool,
+
+    #[structopt(flatten)]
+    global_opts: crate::cli::GlobalOpts,
+}
+
+impl ConfigPath for Export {
+    #[inline]
+    fn config_path(&self) -> Option<&Path> {
+        self.global_opts.config_path.as_deref()
+    }
+
+    #[cfg(feature = "prefix")]
+    #[inline]
+    fn prefix(&self) -> Option<&str> {
+        self.global_opts.prefix.as_deref()
+    }
+}
+
+impl Platform for Export {
+    #[inline]
+    fn platform(&self) -> Option<&str> {
+        self.global_opts.platform.as_deref()
+    }
+}
+
+impl ConfigPath for Apply {
+    #[inline]
+    fn config_path(&self) -> Option<&Path> {
+        self.global_opts.config_path.as_deref()
+    }
+
+    #[cfg(feature = "prefix")]
+    #[inline]
+    fn prefix(&self) -> Option<&str> {
+        self.global_opts.prefix.as_deref()
+    }
+}
+
+impl Platform for Apply {
+    #[inline]
+    fn platform(&self) -> Option<&str> {
+        self.global_opts.platform.as_deref()
+    }
+}
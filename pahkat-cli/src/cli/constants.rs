@@ -12,6 +12,13 @@ macro_rules! target {
     };
 }
 
+#[cfg(feature = "linux")]
+macro_rules! target {
+    () => {
+        "linux"
+    };
+}
+
 #[cfg(feature = "prefix")]
 macro_rules! target {
     () => {
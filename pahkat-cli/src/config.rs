@@ -9,7 +9,7 @@ use pahkat_client::{
 };
 
 use crate::Platform;
-use pahkat_client::config::RepoRecord;
+use pahkat_client::config::{ClientCertificate, RepoRecord};
 use pahkat_types::repo::RepoUrl;
 
 pub(crate) async fn config<'a>(
@@ -23,20 +23,206 @@ pub(crate) async fn config<'a>(
             crate::cli::command::config::Repo::Add(a) => {
                 let url = a.repo_url.to_owned();
                 let channel = a.channel.to_owned();
+                let trusted_keys = a.trusted_keys.to_owned();
+                let client_certificate =
+                    a.client_certificate
+                        .to_owned()
+                        .map(|certificate| ClientCertificate {
+                            certificate,
+                            key: a.client_key.to_owned(),
+                        });
 
                 let config = store.config();
                 let mut config = config.write().unwrap();
 
+                let secrets = config.settings().secret_store();
                 let repos = config.repos_mut();
-                repos.insert(url, RepoRecord { channel })?;
+                let existing = repos.get(&url).cloned().unwrap_or_default();
+                let mut record = RepoRecord {
+                    channel,
+                    trusted_keys,
+                    client_certificate,
+                    // Kept unless replaced, as the token itself cannot be shown again
+                    auth_token: existing.auth_token,
+                    held_packages: existing.held_packages,
+                    skipped_versions: existing.skipped_versions,
+                    ..Default::default()
+                };
+                if let Some(token) = a.auth_token.as_deref() {
+                    record.set_auth_token(&url, Some(token), &secrets)?;
+                }
+                repos.insert(url, record)?;
 
                 Ok(())
             }
             crate::cli::command::config::Repo::Remove(a) => {
-                let url = &a.repo_url;
+                if a.purge && !purge(Arc::clone(&store), a, target).await? {
+                    return Ok(());
+                }
+                if !store.remove_repo(&a.repo_url)? {
+                    println!("Repository {} was not configured", &a.repo_url);
+                }
                 Ok(())
             }
             crate::cli::command::config::Repo::List(a) => Ok(()),
+            crate::cli::command::config::Repo::Stats(a) => repo_stats(&*store, a),
+            crate::cli::command::config::Repo::Hold(a) => {
+                update_record(&*store, &a.repo_url, |record| {
+                    if a.release {
+                        record.held_packages.remove(&a.package);
+                    } else {
+                        record.held_packages.insert(a.package.clone());
+                    }
+                })
+            }
+            crate::cli::command::config::Repo::Skip(a) => {
+                update_record(&*store, &a.repo_url, |record| match a.version.as_ref() {
+                    Some(version) => {
+                        record
+                            .skipped_versions
+                            .insert(a.package.clone(), version.clone());
+                    }
+                    None => {
+                        record.skipped_versions.remove(&a.package);
+                    }
+                })
+            }
         },
+        #[cfg(feature = "prefix")]
+        crate::cli::command::Config::Prefix(_) => {
+            unreachable!("prefixes are managed before a store is opened")
+        }
     }
 }
+
+fn update_record(
+    store: &dyn PackageStore,
+    url: &RepoUrl,
+    f: impl FnOnce(&mut RepoRecord),
+) -> Result<(), anyhow::Error> {
+    let config = store.config();
+    let mut config = config.write().unwrap();
+    let repos = config.repos_mut();
+    let mut record = match repos.get(url) {
+        Some(v) => v.clone(),
+        None => anyhow::bail!("Repository not configured: {}", url),
+    };
+    f(&mut record);
+    repos.insert(url.clone(), record)?;
+    Ok(())
+}
+
+/// Uninstalls the packages installed from the repository being removed, after
+/// showing them. Returns whether the repository should then be removed.
+async fn purge(
+    store: Arc<dyn PackageStore>,
+    args: &crate::cli::command::config::repo::Remove,
+    target: InstallTarget,
+) -> Result<bool, anyhow::Error> {
+    use pahkat_client::transaction::TransactionEvent;
+
+    let keys = store.installed_from_repo(&args.repo_url, target);
+    if keys.is_empty() {
+        println!("No packages are installed from {}", &args.repo_url);
+        return Ok(!args.dry_run);
+    }
+
+    println!("Packages to uninstall:");
+    for key in keys.iter() {
+        println!("  {}", key);
+    }
+    if args.dry_run
+        || !(args.yes || confirm("Uninstall these packages and remove the repository?")?)
+    {
+        return Ok(false);
+    }
+
+    let actions = keys
+        .into_iter()
+        .map(|x| PackageAction::uninstall(x, target))
+        .collect();
+    let transaction = PackageTransaction::new(Arc::clone(&store), actions)?;
+    let (_canceler, mut events) = transaction.process();
+
+    let mut is_complete = false;
+    while let Some(event) = events.next().await {
+        match event {
+            TransactionEvent::Uninstalling(key) => println!("Uninstalling: {}", key),
+            TransactionEvent::Error(key, e) => {
+                anyhow::bail!("Could not uninstall {}, keeping the repository: {}", key, e)
+            }
+            TransactionEvent::RebootRequired(key) => println!("Restart required: {}", key),
+            TransactionEvent::Complete => is_complete = true,
+            _ => {}
+        }
+    }
+
+    Ok(is_complete)
+}
+
+fn confirm(prompt: &str) -> std::io::Result<bool> {
+    use std::io::Write;
+
+    print!("{} [y/N] ", prompt);
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+fn repo_stats(
+    store: &dyn PackageStore,
+    args: &crate::cli::command::config::repo::Stats,
+) -> Result<(), anyhow::Error> {
+    let urls = match args.repo_url.as_ref() {
+        Some(url) => vec![url.clone()],
+        None => {
+            let config = store.config();
+            let config = config.read().unwrap();
+            let mut urls = config.repos().keys().cloned().collect::<Vec<_>>();
+            urls.sort();
+            urls
+        }
+    };
+
+    let mut stats = vec![];
+    for url in urls {
+        match store.repo_statistics(&url) {
+            Some(v) => stats.push(v),
+            None => eprintln!("Repository {} is not loaded", &url),
+        }
+    }
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&stats)?);
+        return Ok(());
+    }
+
+    for x in stats {
+        use indicatif::HumanBytes;
+
+        println!("{}", x.url);
+        println!("  packages:     {}", x.package_count);
+        if !x.channels.is_empty() {
+            println!("  channels:     {}", x.channels.join(", "));
+        }
+        println!("  payload size: {}", HumanBytes(x.payload_size));
+        println!("  cache size:   {}", HumanBytes(x.cache_size));
+        match x.last_refresh {
+            Some(v) => println!("  refreshed:    {}", v.to_rfc3339()),
+            None => println!("  refreshed:    never"),
+        }
+        for failure in x.payload_failures.iter() {
+            println!(
+                "  failed:       {} ({} times, last {}: {} at {})",
+                failure.host,
+                failure.count,
+                failure.last_seen.to_rfc3339(),
+                failure.last_error,
+                failure.last_url
+            );
+        }
+    }
+
+    Ok(())
+}
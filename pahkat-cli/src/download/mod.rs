@@ -39,12 +39,12 @@ pub async fn download<'a>(
 
         while let Some(event) = download.next().await {
             match event {
-                DownloadEvent::Progress((current, total)) => {
-                    pb.set_length(total);
-                    pb.set_position(current);
+                DownloadEvent::Progress(x) => {
+                    pb.set_length(x.total);
+                    pb.set_position(x.current);
                 }
                 DownloadEvent::Complete(pkg_path) => {
-                    std::fs::copy(&pkg_path, output_path.join(pkg_path.file_name().unwrap()))?;
+                    copy_verified(&pkg_path, &output_path.join(pkg_path.file_name().unwrap()))?;
                     std::fs::remove_file(&pkg_path)?;
                     pb.finish();
                 }
@@ -54,3 +54,27 @@ pub async fn download<'a>(
     }
     Ok(())
 }
+
+/// Copies the file keeping its modification time, and checks that every byte
+/// made it to the destination.
+fn copy_verified(from: &Path, to: &Path) -> Result<(), anyhow::Error> {
+    let meta = std::fs::metadata(from)?;
+    let copied = std::fs::copy(from, to)?;
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(to)?
+        .sync_all()?;
+
+    let written = std::fs::metadata(to)?.len();
+    if copied != meta.len() || written != meta.len() {
+        anyhow::bail!(
+            "Copy of {} is incomplete: {} of {} bytes",
+            from.display(),
+            written,
+            meta.len()
+        );
+    }
+
+    filetime::set_file_mtime(to, filetime::FileTime::from_last_modification_time(&meta))?;
+    Ok(())
+}
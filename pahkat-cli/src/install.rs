@@ -6,17 +6,26 @@ use futures::stream::StreamExt;
 use crate::{cli::command::PackageSpec, Platform};
 use pahkat_client::{
     package_store::InstallTarget,
-    transaction::{PackageAction, PackageTransaction},
+    transaction::{observer::ExecObserver, PackageAction, PackageTransaction},
     DownloadEvent, PackageKey, PackageStore,
 };
 
 pub(crate) async fn install<'a>(
     store: Arc<dyn PackageStore>,
     packages: &'a Vec<PackageSpec>,
+    bundle_path: Option<&'a Path>,
     target: InstallTarget,
+    allow_deprecated: bool,
+    is_atomic: bool,
     args: &'a crate::Args,
 ) -> Result<(), anyhow::Error> {
-    let keys: Vec<PackageKey> = packages
+    // Without packages given, everything the bundle was exported for is installed
+    let bundled = match bundle_path {
+        Some(path) => store.import_bundle(path)?,
+        None => vec![],
+    };
+
+    let mut keys: Vec<PackageKey> = packages
         .iter()
         .map(|PackageSpec { id, version }| {
             let mut key: PackageKey = store
@@ -35,6 +44,9 @@ pub(crate) async fn install<'a>(
             Ok(key)
         })
         .collect::<anyhow::Result<Vec<_>>>()?;
+    if keys.is_empty() {
+        keys = bundled;
+    }
 
     // for key in keys.iter() {
     //     // let pb = indicatif::ProgressBar::new(0);
@@ -61,32 +73,120 @@ pub(crate) async fn install<'a>(
         keys.iter()
             .map(|x| PackageAction::install(x.clone(), target.clone()))
             .collect(),
-    )?;
+    )?
+    .with_atomic(is_atomic);
+    transaction
+        .observers()
+        .register(Arc::new(ExecObserver::new(store.config())));
+
+    check_deprecations(&transaction, allow_deprecated)?;
+
+    if bundle_path.is_none() {
+        return process(&store, transaction).await;
+    }
+
+    if let Some(record) = transaction
+        .actions()
+        .iter()
+        .find(|x| x.action.is_install() && !x.is_cached)
+    {
+        anyhow::bail!("{} is not in the bundle", record.action.id);
+    }
+    run(transaction).await
+}
 
+/// Warns about deprecated releases and refuses critically deprecated ones
+/// unless they are explicitly allowed. Also notes releases installed for an
+/// emulated architecture.
+pub(crate) fn check_deprecations(
+    transaction: &PackageTransaction,
+    allow_deprecated: bool,
+) -> Result<(), anyhow::Error> {
+    let language = system_language();
     for record in transaction.actions().iter() {
-        let id = record.action.id.clone();
-        let mut download = store.download(&record.action.id);
+        if let Some(arch) = record.emulated_arch() {
+            println!(
+                "Note: {} {} has no build for this machine; installing the {} build, which runs under emulation",
+                record.action.id.id, record.release.version, arch
+            );
+        }
+        if let Some(deprecation) = record.deprecation() {
+            println!(
+                "Warning: {} {} is deprecated: {}",
+                record.action.id.id,
+                record.release.version,
+                deprecation
+                    .message_for(&language)
+                    .unwrap_or("no reason given")
+            );
+        }
+    }
 
-        // TODO: handle cancel here
+    if !allow_deprecated {
+        if let Some(record) = transaction.critically_deprecated().first() {
+            anyhow::bail!(
+                "{} {} is critically deprecated; pass --allow-deprecated to install it anyway",
+                record.action.id.id,
+                record.release.version
+            );
+        }
+    }
 
-        println!("Downloading {}", id);
+    Ok(())
+}
 
-        while let Some(event) = download.next().await {
-            match event {
-                DownloadEvent::Error(e) => {
-                    println!("Error: {}", e);
-                    return Ok(());
-                }
-                DownloadEvent::Progress((current, total)) => {
-                    println!("Progress: {}/{}", current, total);
-                }
-                DownloadEvent::Complete(_) => {
-                    println!("Complete");
+/// The language of the user's locale, such as `nb` for `nb_NO.UTF-8`. Defaults to
+/// English when no locale is set.
+fn system_language() -> String {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|x| std::env::var(x).ok())
+        .find(|x| !x.is_empty())
+        .and_then(|x| {
+            x.split(['_', '.', '@'])
+                .next()
+                .filter(|x| !x.is_empty() && *x != "C" && *x != "POSIX")
+                .map(str::to_string)
+        })
+        .unwrap_or_else(|| "en".into())
+}
+
+/// Downloads the payloads of the transaction and then runs it.
+pub(crate) async fn process(
+    store: &Arc<dyn PackageStore>,
+    transaction: PackageTransaction,
+) -> Result<(), anyhow::Error> {
+    let concurrency = {
+        let config = store.config();
+        let config = config.read().unwrap();
+        config.settings().download_concurrency()
+    };
+    let mut downloads = transaction.download(concurrency);
+
+    // TODO: handle cancel here
+
+    while let Some((id, event)) = downloads.next().await {
+        match event {
+            DownloadEvent::Error(e) => {
+                anyhow::bail!("Could not download {}: {}", id, e);
+            }
+            DownloadEvent::Progress(x) => match x.percent {
+                Some(percent) => {
+                    println!("{}: Progress: {}/{} ({}%)", id, x.current, x.total, percent)
                 }
+                None => println!("{}: Progress: {}", id, x.current),
+            },
+            DownloadEvent::Complete(_) => {
+                println!("{}: Complete", id);
             }
         }
     }
 
+    run(transaction).await
+}
+
+/// Runs a transaction whose payloads have been downloaded.
+async fn run(transaction: PackageTransaction) -> Result<(), anyhow::Error> {
     let (canceler, mut tx) = transaction.process();
 
     while let Some(event) = tx.next().await {
@@ -106,8 +206,10 @@ pub(crate) async fn install<'a>(
                 println!("Progress: {} {}", id, msg);
             }
             TransactionEvent::Error(id, err) => {
-                println!("Error: {} {}", id, err);
-                return Ok(());
+                anyhow::bail!("Could not process {}: {}", id, err);
+            }
+            TransactionEvent::RebootRequired(id) => {
+                println!("Restart required: {}", id);
             }
             TransactionEvent::Complete => {
                 println!("Complete!");
@@ -1,7 +1,12 @@
+mod bundle;
 mod cli;
 mod config;
 mod download;
 mod install;
+#[cfg(feature = "prefix")]
+mod prefix;
+mod show;
+mod state;
 mod status;
 mod uninstall;
 
@@ -23,6 +28,29 @@ fn config_path(holder: &dyn ConfigPath) -> Result<PathBuf> {
         .with_context(|| "No default config path could be found")
 }
 
+/// Resolves the config path given on the command line, falling back to a
+/// registered prefix by name or to the default prefix.
+#[cfg(feature = "prefix")]
+fn resolve_config_path(holder: &dyn ConfigPath) -> Result<Option<PathBuf>> {
+    if let Some(path) = holder.config_path() {
+        return Ok(Some(path.to_owned()));
+    }
+
+    let prefixes = pahkat_client::config::Prefixes::load_default()?;
+    match holder.prefix() {
+        Some(name) => prefixes
+            .get(name)
+            .map(|x| Some(x.to_owned()))
+            .ok_or_else(|| anyhow::anyhow!("No prefix named {} is registered", name)),
+        None => Ok(prefixes.resolve(None).map(Path::to_owned)),
+    }
+}
+
+#[cfg(not(feature = "prefix"))]
+fn resolve_config_path(holder: &dyn ConfigPath) -> Result<Option<PathBuf>> {
+    Ok(holder.config_path().map(Path::to_owned))
+}
+
 #[inline(always)]
 #[cfg(feature = "windows")]
 async fn store(config_path: Option<&Path>) -> anyhow::Result<Arc<dyn PackageStore>> {
@@ -33,7 +61,7 @@ async fn store(config_path: Option<&Path>) -> anyhow::Result<Arc<dyn PackageStor
     let store = pahkat_client::WindowsPackageStore::new(config).await;
     let store = Arc::new(store);
 
-    if store.config().read().unwrap().repos().len() == 0 {
+    if store.config().read().unwrap().repos().is_empty() {
         println!("WARNING: There are no repositories in the given config.");
     }
 
@@ -47,7 +75,7 @@ async fn store(config_path: Option<&Path>) -> anyhow::Result<Arc<dyn PackageStor
     let store = pahkat_client::PrefixPackageStore::open(config_path).await?;
     let store = Arc::new(store);
 
-    if store.config().read().unwrap().repos().len() == 0 {
+    if store.config().read().unwrap().repos().is_empty() {
         println!("WARNING: There are no repositories in the given config.");
     }
 
@@ -61,7 +89,7 @@ async fn create_store(config_path: Option<&Path>) -> anyhow::Result<Arc<dyn Pack
     let store = pahkat_client::PrefixPackageStore::create(config_path).await?;
     let store = Arc::new(store);
 
-    if store.config().read().unwrap().repos().len() == 0 {
+    if store.config().read().unwrap().repos().is_empty() {
         println!("WARNING: There are no repositories in the given config.");
     }
 
@@ -78,7 +106,24 @@ async fn store(config_path: Option<&Path>) -> anyhow::Result<Arc<dyn PackageStor
     let store = pahkat_client::MacOSPackageStore::new(config).await;
     let store = Arc::new(store);
 
-    if store.config().read().unwrap().repos().len() == 0 {
+    if store.config().read().unwrap().repos().is_empty() {
+        println!("WARNING: There are no repositories in the given config.");
+    }
+
+    Ok(store)
+}
+
+#[inline(always)]
+#[cfg(feature = "linux")]
+async fn store(config_path: Option<&Path>) -> anyhow::Result<Arc<dyn PackageStore>> {
+    let (config, _errors) = match config_path {
+        Some(v) => pahkat_client::Config::load(&v, pahkat_client::Permission::ReadWrite),
+        None => (pahkat_client::Config::load_default()?, vec![]),
+    };
+    let store = pahkat_client::LinuxPackageStore::new(config).await?;
+    let store = Arc::new(store);
+
+    if store.config().read().unwrap().repos().is_empty() {
         println!("WARNING: There are no repositories in the given config.");
     }
 
@@ -88,17 +133,35 @@ async fn store(config_path: Option<&Path>) -> anyhow::Result<Arc<dyn PackageStor
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     env_logger::init();
+    pahkat_client::defaults::set_client_version(env!("CARGO_PKG_VERSION"))?;
 
     let args = Args::from_args();
 
+    // Managing the prefix registry does not need a store
+    #[cfg(feature = "prefix")]
+    if let cli::Args::Config(cli::command::Config::Prefix(a)) = &args {
+        return prefix::prefix(a);
+    }
+
+    let config_path = resolve_config_path(&args)?;
+    let config_path = config_path.as_deref();
+
     match &args {
         cli::Args::Init(a) => {
             // TODO: init should only be built for prefix builds.
             #[cfg(feature = "prefix")]
-            create_store(args.config_path()).await?;
+            {
+                create_store(config_path).await?;
+
+                // `init -c PATH --prefix NAME` registers the new prefix
+                if let (Some(path), Some(name)) = (args.config_path(), args.prefix()) {
+                    let mut prefixes = pahkat_client::config::Prefixes::load_default()?;
+                    prefixes.insert(name.to_string(), std::fs::canonicalize(path)?)?;
+                }
+            }
         }
         cli::Args::Download(a) => {
-            let store = store(args.config_path()).await?;
+            let store = store(config_path).await?;
             download::download(
                 store,
                 &a.packages,
@@ -110,19 +173,67 @@ async fn main() -> anyhow::Result<()> {
             .await?
         }
         cli::Args::Status(a) => {
-            let store = store(args.config_path()).await?;
-            status::status(&*store, &a.packages, Default::default())?
+            let store = store(config_path).await?;
+            if a.all {
+                status::status_all(
+                    &*store,
+                    a.repo.as_ref(),
+                    Default::default(),
+                    a.json,
+                    a.verbose,
+                )?
+            } else {
+                status::status(&*store, &a.packages, Default::default(), a.json, a.verbose)?
+            }
+        }
+        cli::Args::DepsStatus(a) => {
+            let store = store(config_path).await?;
+            status::deps_status(&*store, &a.package, Default::default(), a.json)?
+        }
+        cli::Args::Show(a) => {
+            let store = store(config_path).await?;
+            show::show(&*store, &a.package, a.json)?
+        }
+        cli::Args::Report(a) => {
+            let store = store(config_path).await?;
+            let report =
+                pahkat_client::report::ComplianceReport::generate(&*store, Default::default());
+            match &*a.format {
+                "json" => println!("{}", serde_json::to_string_pretty(&report)?),
+                format => anyhow::bail!("Unsupported report format: {}", format),
+            }
         }
         cli::Args::Uninstall(a) => {
-            let store = store(args.config_path()).await?;
+            let store = store(config_path).await?;
             uninstall::uninstall(&*store, &a.packages, Default::default())?
         }
         cli::Args::Install(a) => {
-            let store = store(args.config_path()).await?;
-            install::install(store, &a.packages, Default::default(), &args).await?
+            let store = store(config_path).await?;
+            install::install(
+                store,
+                &a.packages,
+                a.from_bundle.as_deref(),
+                Default::default(),
+                a.allow_deprecated,
+                a.atomic,
+                &args,
+            )
+            .await?
+        }
+        cli::Args::Bundle(a) => {
+            let store = store(config_path).await?;
+            bundle::bundle(store, &a.packages, &a.output_path).await?
+        }
+        cli::Args::State(cli::command::State::Export(a)) => {
+            let store = store(config_path).await?;
+            state::export(&*store, a.output_path.as_deref(), Default::default())?
+        }
+        cli::Args::State(cli::command::State::Apply(a)) => {
+            let store = store(config_path).await?;
+            state::apply(store, &a.manifest_path, a.prune, a.allow_deprecated).await?
         }
         cli::Args::Config(a) => {
-            let store = store(args.config_path()).await?;
+            let store = store(config_path).await?;
             config::config(store, a, Default::default(), &args).await?
         }
     }
@@ -0,0 +1,36 @@
+use pahkat_client::config::Prefixes;
+
+use crate::cli::command::config::Prefix;
+
+pub(crate) fn prefix(command: &Prefix) -> Result<(), anyhow::Error> {
+    let mut prefixes = Prefixes::load_default()?;
+
+    match command {
+        Prefix::Add(a) => {
+            let path = std::fs::canonicalize(&a.path)?;
+            prefixes.insert(a.name.to_string(), path)?;
+        }
+        Prefix::Remove(a) => {
+            if !prefixes.remove(&a.name)? {
+                println!("Prefix {} was not registered", &a.name);
+            }
+        }
+        Prefix::List(_) => {
+            for (name, path) in prefixes.iter() {
+                let marker = if prefixes.default_name() == Some(name) {
+                    "*"
+                } else {
+                    " "
+                };
+                println!("{} {}\t{}", marker, name, path.display());
+            }
+        }
+        Prefix::Use(a) => {
+            if !prefixes.set_default(&a.name)? {
+                anyhow::bail!("No prefix named {} is registered", &a.name);
+            }
+        }
+    }
+
+    Ok(())
+}
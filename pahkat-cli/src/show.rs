@@ -0,0 +1,82 @@
+use std::convert::TryFrom;
+
+use pahkat_client::{repo::resolve_release, PackageStore};
+use pahkat_types::package::{Descriptor, Provenance};
+use pahkat_types::LangTagMap;
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+struct ShowRecord {
+    id: String,
+    key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    channel: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    provenance: Option<Provenance>,
+}
+
+fn localized(map: &LangTagMap<String>) -> Option<String> {
+    map.get("en").or_else(|| map.values().next()).cloned()
+}
+
+pub fn show(store: &dyn PackageStore, id: &str, json: bool) -> Result<(), anyhow::Error> {
+    let (key, package) = store
+        .find_package_by_id(id)
+        .ok_or_else(|| anyhow::anyhow!("No package found with id: {}", id))?;
+    let descriptor = Descriptor::try_from(package)
+        .map_err(|_| anyhow::anyhow!("{} is not a concrete package", id))?;
+
+    let repos = store.repos();
+    let release = resolve_release(&key, &repos.read().unwrap()).map(|(release, _)| release);
+
+    let record = ShowRecord {
+        id: key.id.clone(),
+        key: key.to_string(),
+        name: localized(&descriptor.name),
+        description: localized(&descriptor.description),
+        version: release.as_ref().map(|x| x.version.to_string()),
+        channel: release.as_ref().and_then(|x| x.channel.clone()),
+        provenance: release.and_then(|x| x.provenance),
+    };
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&record)?);
+        return Ok(());
+    }
+
+    println!("{}", &record.key);
+    if let Some(name) = record.name.as_ref() {
+        println!("  Name: {}", name);
+    }
+    if let Some(description) = record.description.as_ref() {
+        println!("  Description: {}", description);
+    }
+    match (&record.version, &record.channel) {
+        (Some(version), Some(channel)) => println!("  Version: {} [{}]", version, channel),
+        (Some(version), None) => println!("  Version: {}", version),
+        _ => println!("  No release available for this platform"),
+    }
+
+    if let Some(provenance) = record.provenance.as_ref() {
+        if let Some(url) = provenance.source_url.as_ref() {
+            println!("  Source: {}", url);
+        }
+        if let Some(commit) = provenance.commit.as_ref() {
+            println!("  Commit: {}", commit);
+        }
+        if let Some(url) = provenance.ci_run_url.as_ref() {
+            println!("  CI run: {}", url);
+        }
+        if let Some(builder) = provenance.builder.as_ref() {
+            println!("  Built by: {}", builder);
+        }
+    }
+
+    Ok(())
+}
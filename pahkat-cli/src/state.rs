@@ -0,0 +1,145 @@
+use std::convert::TryFrom;
+use std::path::Path;
+use std::sync::Arc;
+
+use pahkat_client::{
+    package_store::InstallTarget,
+    repo::installed_version,
+    transaction::{observer::ExecObserver, PackageAction, PackageTransaction},
+    PackageKey, PackageStatus, PackageStore,
+};
+use serde::{Deserialize, Serialize};
+
+/// The installed packages of a machine, used to bring another machine to the same state.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct StateManifest {
+    pub packages: Vec<StateEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct StateEntry {
+    pub key: String,
+    /// Unknown for packages installed outside of Pahkat that are not up to date.
+    /// Without it, the latest release is installed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    pub target: InstallTarget,
+}
+
+pub(crate) fn export(
+    store: &dyn PackageStore,
+    output_path: Option<&Path>,
+    target: InstallTarget,
+) -> Result<(), anyhow::Error> {
+    let repos = store.repos();
+    let repos = repos.read().unwrap();
+
+    let mut packages = vec![];
+    for repo_url in repos.keys() {
+        for (id, status) in store.all_statuses(repo_url, target) {
+            let key = PackageKey::new_unchecked(repo_url.clone(), id.clone(), None);
+            let version = match status {
+                Ok(PackageStatus::UpToDate) | Ok(PackageStatus::RequiresUpdate) => {
+                    installed_version(store, &key, target, &repos).map(|x| x.to_string())
+                }
+                // Cannot be installed again on another machine
+                Ok(PackageStatus::NotInstalled) | Ok(PackageStatus::Unpublished) => continue,
+                Err(e) => {
+                    eprintln!("Warning: skipping {}: {}", &id, e);
+                    continue;
+                }
+            };
+
+            packages.push(StateEntry {
+                key: key.to_string(),
+                version,
+                target,
+            });
+        }
+    }
+    packages.sort_by(|a, b| a.key.cmp(&b.key));
+
+    let json = serde_json::to_string_pretty(&StateManifest { packages })?;
+    match output_path {
+        Some(path) => std::fs::write(path, json)?,
+        None => println!("{}", json),
+    }
+
+    Ok(())
+}
+
+pub(crate) async fn apply(
+    store: Arc<dyn PackageStore>,
+    manifest_path: &Path,
+    prune: bool,
+    allow_deprecated: bool,
+) -> Result<(), anyhow::Error> {
+    let manifest: StateManifest = serde_json::from_slice(&std::fs::read(manifest_path)?)?;
+
+    let mut actions = vec![];
+    let mut wanted = vec![];
+
+    {
+        let repos = store.repos();
+        let repos = repos.read().unwrap();
+
+        for entry in manifest.packages.iter() {
+            let mut key = PackageKey::try_from(&*entry.key)?;
+            if !repos.contains_key(&key.repository_url) {
+                anyhow::bail!(
+                    "Repository {} is not configured; add it with `config repo add`",
+                    &key.repository_url
+                );
+            }
+            wanted.push((key.clone().without_query_params(), entry.target));
+
+            if let Some(version) = entry.version.as_ref() {
+                key.query.version = Some(version.to_string());
+            }
+
+            match store.status(&key, entry.target) {
+                Ok(PackageStatus::UpToDate) => {}
+                Ok(_) => actions.push(PackageAction::install(key, entry.target)),
+                Err(e) => anyhow::bail!("Could not get status of {}: {}", &entry.key, e),
+            }
+        }
+
+        if prune {
+            let targets = wanted.iter().map(|(_, target)| *target);
+            let mut targets = targets.collect::<Vec<_>>();
+            targets.sort();
+            targets.dedup();
+
+            for repo_url in repos.keys() {
+                for target in targets.iter() {
+                    for (id, status) in store.all_statuses(repo_url, *target) {
+                        if !matches!(
+                            status,
+                            Ok(PackageStatus::UpToDate) | Ok(PackageStatus::RequiresUpdate)
+                        ) {
+                            continue;
+                        }
+
+                        let key = PackageKey::new_unchecked(repo_url.clone(), id, None);
+                        if !wanted.contains(&(key.clone(), *target)) {
+                            actions.push(PackageAction::uninstall(key, *target));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    if actions.is_empty() {
+        println!("Already up to date.");
+        return Ok(());
+    }
+
+    let transaction = PackageTransaction::new(Arc::clone(&store), actions)?;
+    transaction
+        .observers()
+        .register(Arc::new(ExecObserver::new(store.config())));
+
+    crate::install::check_deprecations(&transaction, allow_deprecated)?;
+    crate::install::process(&store, transaction).await
+}
@@ -1,30 +1,310 @@
-use std::path::Path;
+use pahkat_client::config::UpdateHold;
+use pahkat_client::failures::{self, FailurePolicy, InstallFailure};
+use pahkat_client::transaction::PackageStatusError;
+use pahkat_client::{
+    package_store::InstallTarget,
+    repo::{dependency_tree, resolve_release},
+    PackageKey, PackageStatus, PackageStore,
+};
+use pahkat_types::repo::RepoUrl;
+use serde::Serialize;
 
-use pahkat_client::{package_store::InstallTarget, PackageStore};
+#[derive(Debug, Serialize)]
+struct StatusRecord {
+    id: String,
+    /// Not set for packages that were not found in any repository.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    key: Option<String>,
+    status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    channel: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    latest_version: Option<String>,
+    update_available: bool,
+    /// Set if an update is available but the user held the package or skipped it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    update_hold: Option<UpdateHold>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    install_failure: Option<FailureRecord>,
+}
+
+#[derive(Debug, Serialize)]
+struct FailureRecord {
+    version: String,
+    count: u32,
+    last_error: String,
+    last_seen: String,
+    /// Not set if the release is no longer retried.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry_at: Option<String>,
+}
+
+/// Installs the background updater failed, shown with `--verbose`.
+struct Failures {
+    failures: Vec<InstallFailure>,
+    policy: FailurePolicy,
+}
+
+impl Failures {
+    fn load(store: &dyn PackageStore) -> Failures {
+        let config = store.config();
+        let config = config.read().unwrap();
+        Failures {
+            failures: failures::load(&failures::path(&config)),
+            policy: config.settings().install_failures(),
+        }
+    }
+
+    fn record(&self, key: &PackageKey) -> Option<FailureRecord> {
+        let failure = self
+            .failures
+            .iter()
+            .find(|x| x.key.repository_url == key.repository_url && x.key.id == key.id)?;
+        Some(FailureRecord {
+            version: failure.version.clone(),
+            count: failure.count,
+            last_error: failure.last_error.clone(),
+            last_seen: failure.last_seen.to_rfc3339(),
+            retry_at: self.policy.retry_at(failure).map(|x| x.to_rfc3339()),
+        })
+    }
+}
+
+impl StatusRecord {
+    fn new(
+        store: &dyn PackageStore,
+        key: &PackageKey,
+        status: Result<PackageStatus, PackageStatusError>,
+        failures: Option<&Failures>,
+    ) -> StatusRecord {
+        let repos = store.repos();
+        let repos = repos.read().unwrap();
+        let release = resolve_release(key, &repos).map(|(release, _)| release);
+        let update_hold = match (&status, &release) {
+            (Ok(PackageStatus::RequiresUpdate), Some(release)) => {
+                let config = store.config();
+                let config = config.read().unwrap();
+                config
+                    .repos()
+                    .get(&key.repository_url)
+                    .and_then(|x| x.update_hold(&key.id, &release.version.to_string()))
+            }
+            _ => None,
+        };
+
+        StatusRecord {
+            id: key.id.clone(),
+            key: Some(key.to_string()),
+            update_available: matches!(status, Ok(PackageStatus::RequiresUpdate))
+                && update_hold.is_none(),
+            update_hold,
+            status: match status {
+                Ok(x) => format!("{:?}", x),
+                Err(x) => format!("{:?}", x),
+            },
+            channel: release.as_ref().and_then(|x| x.channel.clone()),
+            latest_version: release.map(|x| x.version.to_string()),
+            install_failure: failures.and_then(|x| x.record(key)),
+        }
+    }
+
+    fn not_found(id: &str) -> StatusRecord {
+        StatusRecord {
+            id: id.to_string(),
+            key: None,
+            status: "NotFound".into(),
+            channel: None,
+            latest_version: None,
+            update_available: false,
+            update_hold: None,
+            install_failure: None,
+        }
+    }
+}
+
+fn print_records(records: &[StatusRecord], json: bool) -> Result<(), anyhow::Error> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(records)?);
+        return Ok(());
+    }
+
+    for record in records {
+        let key = match record.key.as_ref() {
+            Some(v) => v,
+            None => {
+                println!("{}: not found", &record.id);
+                continue;
+            }
+        };
+
+        match (&record.latest_version, &record.channel) {
+            (Some(version), Some(channel)) => println!(
+                "{}: {} (latest: {} [{}])",
+                key, &record.status, version, channel
+            ),
+            (Some(version), None) => {
+                println!("{}: {} (latest: {})", key, &record.status, version)
+            }
+            _ => println!("{}: {}", key, &record.status),
+        }
+
+        match record.update_hold {
+            Some(UpdateHold::Held) => println!("  Held at the installed version"),
+            Some(UpdateHold::Skipped) => println!("  This update was skipped"),
+            None => {}
+        }
+
+        if let Some(failure) = record.install_failure.as_ref() {
+            println!(
+                "  Installing {} failed {} times, last at {}: {}",
+                &failure.version, failure.count, &failure.last_seen, &failure.last_error
+            );
+            match failure.retry_at.as_ref() {
+                Some(at) => println!("  Next retry: {}", at),
+                None => println!("  Not retried until a newer release is published"),
+            }
+        }
+    }
+
+    Ok(())
+}
 
 pub fn status(
     store: &dyn PackageStore,
     packages: &Vec<String>,
     target: InstallTarget,
+    json: bool,
+    verbose: bool,
 ) -> Result<(), anyhow::Error> {
     if packages.is_empty() {
         println!("No packages specified.");
         return Ok(());
     }
 
+    let failures = verbose.then(|| Failures::load(store));
+    let mut records = vec![];
+
     for id in packages {
         let (package_key, _) = match store.find_package_by_id(id) {
             Some(v) => v,
             None => {
-                println!("{}: not found", &id);
+                records.push(StatusRecord::not_found(id));
                 continue;
             }
         };
-        match store.status(&package_key, target) {
-            Ok(x) => println!("{}: {:?}", &package_key, x),
-            Err(x) => println!("{}: {:?}", &package_key, x),
+        let status = store.status(&package_key, target);
+        records.push(StatusRecord::new(
+            store,
+            &package_key,
+            status,
+            failures.as_ref(),
+        ));
+    }
+
+    print_records(&records, json)
+}
+
+pub fn status_all(
+    store: &dyn PackageStore,
+    repo: Option<&RepoUrl>,
+    target: InstallTarget,
+    json: bool,
+    verbose: bool,
+) -> Result<(), anyhow::Error> {
+    let repo_urls = {
+        let repos = store.repos();
+        let repos = repos.read().unwrap();
+        let mut urls = repos
+            .keys()
+            .filter(|url| repo.map(|x| x == *url).unwrap_or(true))
+            .cloned()
+            .collect::<Vec<_>>();
+        urls.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        urls
+    };
+
+    if let Some(repo) = repo {
+        if repo_urls.is_empty() {
+            anyhow::bail!("Repository not configured: {}", repo);
         }
     }
 
+    let failures = verbose.then(|| Failures::load(store));
+    let mut records = vec![];
+
+    for repo_url in repo_urls {
+        for (id, status) in store.all_statuses(&repo_url, target) {
+            if let Ok(PackageStatus::NotInstalled) = status {
+                continue;
+            }
+            let key = PackageKey::new_unchecked(repo_url.clone(), id, None);
+            records.push(StatusRecord::new(store, &key, status, failures.as_ref()));
+        }
+    }
+
+    print_records(&records, json)
+}
+
+#[derive(Debug, Serialize)]
+struct DependencyRecord {
+    key: String,
+    status: String,
+    constraint: String,
+    depth: usize,
+    path: Vec<String>,
+}
+
+pub fn deps_status(
+    store: &dyn PackageStore,
+    id: &str,
+    target: InstallTarget,
+    json: bool,
+) -> Result<(), anyhow::Error> {
+    let (package_key, _) = store
+        .find_package_by_id(id)
+        .ok_or_else(|| anyhow::anyhow!("Could not find package for: `{}`", id))?;
+
+    let mut nodes = dependency_tree(store, &package_key, target)?;
+
+    // Ordering by path puts every dependency right below the package that pulled it in
+    let sort_key = |node: &pahkat_client::repo::DependencyNode| {
+        node.path
+            .iter()
+            .chain(std::iter::once(&node.key))
+            .map(|x| x.id.clone())
+            .collect::<Vec<_>>()
+    };
+    nodes.sort_by_key(sort_key);
+
+    let records = nodes
+        .into_iter()
+        .map(|node| DependencyRecord {
+            key: node.key.to_string(),
+            status: format!("{:?}", node.status),
+            constraint: node.constraint.to_string(),
+            depth: node.depth(),
+            path: node.path.iter().map(|x| x.to_string()).collect(),
+        })
+        .collect::<Vec<_>>();
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&records)?);
+        return Ok(());
+    }
+
+    println!("{}", &package_key);
+    if records.is_empty() {
+        println!("  (no dependencies)");
+    }
+    for record in records {
+        println!(
+            "{}{} ({}): {}",
+            "  ".repeat(record.depth),
+            &record.key,
+            &record.constraint,
+            &record.status
+        );
+    }
+
     Ok(())
 }
@@ -0,0 +1,208 @@
+//! Benchmarks for the paths that read the flatbuffer index, run against
+//! synthetic repositories in a prefix store.
+//!
+//! Run with `cargo bench --features prefix`.
+
+use std::sync::Arc;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use pahkat_client::repo::{LoadedRepositoryMeta, PackageQuery};
+use pahkat_client::types::package::{
+    Descriptor, DescriptorData, Package, Release, Version, VersionReq,
+};
+use pahkat_client::types::payload::{tarball, Payload, Target};
+use pahkat_client::types::repo::{Agent, Index, RepoUrl, RepositoryData};
+use pahkat_client::types::DependencyMap;
+use pahkat_client::{
+    InstallTarget, LoadedRepository, PackageAction, PackageKey, PackageStore, PackageTransaction,
+    PrefixPackageStore,
+};
+
+const SIZES: &[usize] = &[1000, 5000];
+const DEPTHS: &[usize] = &[1, 8, 32];
+const TAG: &str = "cat:bench";
+
+/// A package whose target depends on `dependency`, if given.
+fn package(id: &str, dependency: Option<&str>) -> Package {
+    let mut dependencies = DependencyMap::new();
+    if let Some(dependency) = dependency {
+        dependencies.insert(dependency.into(), VersionReq::STAR);
+    }
+
+    let target = Target::builder()
+        .platform(std::env::consts::OS.into())
+        .dependencies(dependencies)
+        .payload(Payload::TarballPackage(
+            tarball::Package::builder()
+                .url(format!("https://example.com/{}.txz", id).parse().unwrap())
+                .size(1)
+                .installed_size(1)
+                .build(),
+        ))
+        .build();
+
+    Package::Concrete(
+        Descriptor::builder()
+            .package(
+                DescriptorData::builder()
+                    .id(id.into())
+                    .tags(vec![TAG.into()])
+                    .build(),
+            )
+            .release(vec![Release::builder()
+                .version(Version::new("1.0.0").unwrap())
+                .target(vec![target])
+                .build()])
+            .build(),
+    )
+}
+
+/// The index of a repository with `size` packages, of which the first `depth`
+/// form a chain of dependencies.
+fn repository(name: &str, size: usize, depth: usize) -> (RepoUrl, String, Vec<u8>) {
+    let url: RepoUrl = format!("https://bench.example/{}/", name).parse().unwrap();
+
+    let packages = (0..size)
+        .map(|i| {
+            let id = format!("pkg-{}", i);
+            let dependency = format!("pkg-{}", i + 1);
+            package(&id, Some(&*dependency).filter(|_| i + 1 < depth))
+        })
+        .collect::<Vec<_>>();
+
+    let index = Index::builder()
+        .repository(RepositoryData::builder().url(url.clone()).build())
+        .agent(
+            Agent::builder()
+                .name("pahkat".into())
+                .version("bench".into())
+                .build(),
+        )
+        .build();
+
+    let info = toml::to_string(&index).unwrap();
+    let packages = pahkat_types::index_writer::encode_index(&packages).unwrap();
+    (url, info, packages)
+}
+
+fn load(info: &str, packages: &[u8]) -> LoadedRepository {
+    LoadedRepository::new(
+        toml::from_str(info).unwrap(),
+        packages.to_vec().into_boxed_slice(),
+        LoadedRepositoryMeta {
+            channel: None,
+            last_update: None,
+            prerelease_channels: Default::default(),
+        },
+    )
+    .unwrap()
+}
+
+fn store() -> (tempfile::TempDir, Arc<dyn PackageStore>) {
+    let dir = tempfile::tempdir().unwrap();
+    let store = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(PrefixPackageStore::create(dir.path()))
+        .unwrap();
+    (dir, Arc::new(store))
+}
+
+fn add_repository(store: &dyn PackageStore, url: &RepoUrl, info: &str, packages: &[u8]) {
+    store
+        .repos()
+        .write()
+        .unwrap()
+        .insert(url.clone(), load(info, packages));
+}
+
+fn index_load(c: &mut Criterion) {
+    let mut group = c.benchmark_group("index_load");
+
+    for &size in SIZES {
+        let (url, info, packages) = repository("load", size, 0);
+        let key = PackageKey::new_unchecked(url.clone(), format!("pkg-{}", size - 1), None);
+
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, _| {
+            b.iter(|| {
+                let mut repos = hashbrown::HashMap::new();
+                repos.insert(url.clone(), load(&info, &packages));
+                pahkat_client::repo::resolve_release(&key, &repos).unwrap()
+            })
+        });
+    }
+
+    group.finish();
+}
+
+fn all_statuses(c: &mut Criterion) {
+    let (_dir, store) = store();
+    let mut group = c.benchmark_group("all_statuses");
+
+    for &size in SIZES {
+        let (url, info, packages) = repository(&format!("statuses-{}", size), size, 0);
+        add_repository(&*store, &url, &info, &packages);
+
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, _| {
+            b.iter(|| store.all_statuses(&url, InstallTarget::System))
+        });
+    }
+
+    group.finish();
+}
+
+fn resolve_package_query(c: &mut Criterion) {
+    let mut group = c.benchmark_group("resolve_package_query");
+
+    for &size in SIZES {
+        // Queries cover every repository, so each size gets a store of its own.
+        let (_dir, store) = store();
+        let (url, info, packages) = repository("query", size, 0);
+        add_repository(&*store, &url, &info, &packages);
+
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, _| {
+            b.iter(|| {
+                let query = PackageQuery {
+                    keys: None,
+                    tags: Some(vec![TAG.into()]),
+                    channel: None,
+                    platform: None,
+                    arch: None,
+                };
+                store.resolve_package_query(query, &[InstallTarget::System])
+            })
+        });
+    }
+
+    group.finish();
+}
+
+fn dependency_resolution(c: &mut Criterion) {
+    let (_dir, store) = store();
+    let mut group = c.benchmark_group("dependency_resolution");
+
+    for &depth in DEPTHS {
+        let (url, info, packages) = repository(&format!("deps-{}", depth), 1000, depth);
+        add_repository(&*store, &url, &info, &packages);
+        let key = PackageKey::new_unchecked(url, "pkg-0".into(), None);
+
+        group.bench_with_input(BenchmarkId::from_parameter(depth), &depth, |b, _| {
+            b.iter(|| {
+                let action = PackageAction::install(key.clone(), InstallTarget::System);
+                PackageTransaction::new(Arc::clone(&store), vec![action]).unwrap()
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    index_load,
+    all_statuses,
+    resolve_package_query,
+    dependency_resolution
+);
+criterion_main!(benches);
@@ -5,9 +5,10 @@ fn main() -> Result<()> {
     let is_macos = env::var("CARGO_FEATURE_MACOS").ok().is_some();
     let is_windows = env::var("CARGO_FEATURE_WINDOWS").ok().is_some();
     let is_prefix = env::var("CARGO_FEATURE_PREFIX").ok().is_some();
+    let is_wasm = env::var("CARGO_FEATURE_WASM").ok().is_some();
 
-    if !is_macos && !is_windows && !is_prefix {
-        anyhow::bail!("Enable `macos`, `windows` or `prefix` feature.");
+    if !is_macos && !is_windows && !is_prefix && !is_wasm {
+        anyhow::bail!("Enable `macos`, `windows`, `prefix` or `wasm` feature.");
     }
 
     let output = String::from_utf8(
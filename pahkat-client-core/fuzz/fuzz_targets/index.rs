@@ -0,0 +1,35 @@
+//! Loads arbitrary bytes as `packages/index.bin` and decodes every package.
+//!
+//! Run with `cargo +nightly fuzz run index` from `pahkat-client-core`.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pahkat_client::repo::LoadedRepositoryMeta;
+use pahkat_client::types::repo::{Agent, Index, RepositoryData};
+use pahkat_client::LoadedRepository;
+
+fuzz_target!(|data: &[u8]| {
+    let info = Index::builder()
+        .repository(
+            RepositoryData::builder()
+                .url("https://fuzz.example/".parse().unwrap())
+                .build(),
+        )
+        .agent(
+            Agent::builder()
+                .name("pahkat".into())
+                .version("fuzz".into())
+                .build(),
+        )
+        .build();
+    let meta = LoadedRepositoryMeta {
+        channel: None,
+        last_update: None,
+        prerelease_channels: Default::default(),
+    };
+
+    if let Ok(repo) = LoadedRepository::new(info, data.into(), meta) {
+        let _ = repo.descriptors();
+    }
+});
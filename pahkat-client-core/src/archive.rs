@@ -0,0 +1,181 @@
+use std::path::{Component, Path, PathBuf};
+
+/// Reasons an archive is refused before anything is written to disk.
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum MaliciousArchive {
+    #[error("Entry `{}` has an absolute path", .0.display())]
+    AbsolutePath(PathBuf),
+
+    #[error("Entry `{}` contains `..`", .0.display())]
+    ParentDir(PathBuf),
+
+    #[error("Entry `{}` would be written outside of the package directory", .0.display())]
+    PathEscape(PathBuf),
+
+    #[error("Link `{}` points outside of the package directory", .0.display())]
+    LinkEscape(PathBuf),
+
+    #[error("Entry `{}` is {1} bytes, exceeding the limit of {2} bytes", .0.display())]
+    EntryTooLarge(PathBuf, u64, u64),
+
+    #[error("Archive expands to more than {0} bytes")]
+    TooLarge(u64),
+
+    #[error("Archive expands to more than {0} times its compressed size")]
+    CompressionRatio(u64),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub max_entry_size: u64,
+    pub max_total_size: u64,
+    /// Maximum ratio of extracted to compressed size. Only checked once the
+    /// extracted size passes `ratio_threshold`, as tiny archives compress poorly.
+    pub max_ratio: u64,
+    pub ratio_threshold: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_entry_size: 4 * 1024 * 1024 * 1024,
+            max_total_size: 16 * 1024 * 1024 * 1024,
+            max_ratio: 200,
+            ratio_threshold: 64 * 1024 * 1024,
+        }
+    }
+}
+
+/// Tracks the entries of a single archive as it is extracted.
+#[derive(Debug)]
+pub struct Sanitizer {
+    limits: Limits,
+    compressed_size: u64,
+    total_size: u64,
+}
+
+impl Sanitizer {
+    pub fn new(compressed_size: u64, limits: Limits) -> Sanitizer {
+        Sanitizer {
+            limits,
+            compressed_size,
+            total_size: 0,
+        }
+    }
+
+    /// Rejects absolute paths and any use of `..`. Leading `./` is allowed.
+    pub fn check_path(&self, path: &Path) -> Result<(), MaliciousArchive> {
+        for component in path.components() {
+            match component {
+                Component::Normal(_) | Component::CurDir => {}
+                Component::ParentDir => return Err(MaliciousArchive::ParentDir(path.into())),
+                Component::RootDir | Component::Prefix(_) => {
+                    return Err(MaliciousArchive::AbsolutePath(path.into()))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the path and declared size of an entry, and the running totals.
+    pub fn check_entry(&mut self, path: &Path, size: u64) -> Result<(), MaliciousArchive> {
+        self.check_path(path)?;
+
+        if size > self.limits.max_entry_size {
+            return Err(MaliciousArchive::EntryTooLarge(
+                path.into(),
+                size,
+                self.limits.max_entry_size,
+            ));
+        }
+
+        self.total_size = self.total_size.saturating_add(size);
+        if self.total_size > self.limits.max_total_size {
+            return Err(MaliciousArchive::TooLarge(self.limits.max_total_size));
+        }
+
+        if self.total_size > self.limits.ratio_threshold
+            && self.total_size / self.compressed_size.max(1) > self.limits.max_ratio
+        {
+            return Err(MaliciousArchive::CompressionRatio(self.limits.max_ratio));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hostile_paths() {
+        let sanitizer = Sanitizer::new(1, Limits::default());
+
+        for path in &[
+            "../evil",
+            "a/../../evil",
+            "a/b/../../../evil",
+            "./../evil",
+            "/etc/passwd",
+            "//etc/passwd",
+        ] {
+            assert!(
+                sanitizer.check_path(Path::new(path)).is_err(),
+                "{} was accepted",
+                path
+            );
+        }
+
+        for path in &["a", "./a/b", "a/./b", "a..b", "..a", "a/b.."] {
+            assert!(
+                sanitizer.check_path(Path::new(path)).is_ok(),
+                "{} was rejected",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn size_limits() {
+        let limits = Limits {
+            max_entry_size: 10,
+            max_total_size: 15,
+            max_ratio: 1000,
+            ratio_threshold: 0,
+        };
+
+        let mut sanitizer = Sanitizer::new(1, limits);
+        assert!(matches!(
+            sanitizer.check_entry(Path::new("a"), 11),
+            Err(MaliciousArchive::EntryTooLarge(..))
+        ));
+
+        let mut sanitizer = Sanitizer::new(1, limits);
+        sanitizer.check_entry(Path::new("a"), 10).unwrap();
+        assert!(matches!(
+            sanitizer.check_entry(Path::new("b"), 10),
+            Err(MaliciousArchive::TooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn compression_ratio() {
+        let limits = Limits {
+            max_ratio: 10,
+            ratio_threshold: 100,
+            ..Limits::default()
+        };
+
+        // Small archives are not held to the ratio
+        let mut sanitizer = Sanitizer::new(1, limits);
+        sanitizer.check_entry(Path::new("a"), 100).unwrap();
+
+        let mut sanitizer = Sanitizer::new(100, limits);
+        sanitizer.check_entry(Path::new("a"), 1000).unwrap();
+        assert!(matches!(
+            sanitizer.check_entry(Path::new("b"), 100),
+            Err(MaliciousArchive::CompressionRatio(_))
+        ));
+    }
+}
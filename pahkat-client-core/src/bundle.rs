@@ -0,0 +1,466 @@
+//! Offline bundles, for installing on machines without network access.
+//!
+//! A bundle is a tar archive holding the requested packages, everything they depend
+//! on and the indexes of their repositories:
+//!
+//! ```text
+//! bundle.toml
+//! repos/<sha256 of repository URL>/index.toml
+//! repos/<sha256 of repository URL>/packages/index.bin
+//! repos/<sha256 of repository URL>/manifest.toml{,.sig}, if signed
+//! payloads/<path in the package cache>
+//! ```
+//!
+//! The indexes are copied with their signed manifests, if any, and importing a bundle
+//! verifies them as if they were loaded from the repository. It then loads them into
+//! the store and places the payloads in the package cache, after which its packages
+//! install like any other cached download.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use futures::stream::StreamExt;
+use hashbrown::HashMap;
+use serde::{Deserialize, Serialize};
+use sha2::digest::Digest;
+use sha2::Sha256;
+
+use pahkat_types::repo::{Manifest as RepoManifest, RepoUrl};
+use pahkat_types::AsDownloadUrl;
+
+use crate::archive::{Limits, MaliciousArchive, Sanitizer};
+use crate::config::Config;
+use crate::repo::signature::signature_path;
+use crate::repo::{LoadedRepository, PayloadError, ReleaseQuery, RepoDownloadError};
+use crate::transaction::PackageDependencyStatusError;
+use crate::{DownloadError, DownloadEvent, InstallTarget, PackageKey, PackageStore};
+
+const MANIFEST: &str = "bundle.toml";
+const REPOS: &str = "repos";
+const PAYLOADS: &str = "payloads";
+
+#[derive(Debug, thiserror::Error)]
+pub enum BundleError {
+    #[error("Could not resolve dependencies")]
+    Dependencies(#[from] PackageDependencyStatusError),
+
+    #[error("Could not resolve payload for package key: `{0}`")]
+    Payload(PackageKey, #[source] PayloadError),
+
+    #[error("Could not download `{0}`")]
+    Download(PackageKey, #[source] DownloadError),
+
+    #[error("Could not write bundle to {}", .1.display())]
+    Write(#[source] std::io::Error, PathBuf),
+
+    #[error("Could not read bundle at {}", .1.display())]
+    Read(#[source] std::io::Error, PathBuf),
+
+    #[error("Bundle has no {}", MANIFEST)]
+    MissingManifest,
+
+    #[error("Invalid bundle manifest")]
+    Manifest(#[from] toml::de::Error),
+
+    #[error("Bundle has no valid index for {0}")]
+    Index(RepoUrl, #[source] RepoDownloadError),
+
+    #[error("Bundle contains a malicious entry")]
+    Malicious(#[from] MaliciousArchive),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    /// The packages the bundle was exported for, without their dependencies.
+    packages: Vec<PackageKey>,
+    repositories: Vec<RepoUrl>,
+}
+
+fn repo_dir(url: &RepoUrl) -> PathBuf {
+    let mut sha = Sha256::new();
+    sha.update(url.as_str().as_bytes());
+    Path::new(REPOS).join(format!("{:x}", sha.finalize()))
+}
+
+/// Where the verified index files of `url` are kept: the repository itself if it is
+/// local, and its index cache otherwise.
+fn index_dir(config: &Config, url: &RepoUrl) -> PathBuf {
+    match url.to_file_path() {
+        Ok(v) if url.is_local() => v,
+        _ => crate::repo::repo_cache_path(config, url),
+    }
+}
+
+/// Writes `keys`, the packages they depend on and the indexes of their repositories to
+/// a bundle at `path`, downloading any payloads that are not cached yet. Returns every
+/// package in the bundle.
+pub async fn export(
+    store: Arc<dyn PackageStore>,
+    keys: Vec<PackageKey>,
+    path: &Path,
+) -> Result<Vec<PackageKey>, BundleError> {
+    // Dependencies are included whether or not they are installed on this machine
+    let mut packages = keys.clone();
+    for key in keys.iter() {
+        let tree = crate::repo::dependency_tree(&*store, key, InstallTarget::System)?;
+        for node in tree {
+            if !packages.contains(&node.key) {
+                packages.push(node.key);
+            }
+        }
+    }
+
+    let (payloads, repos) = {
+        let config = store.config();
+        let config = config.read().unwrap();
+        let repos = store.repos();
+        let repos = repos.read().unwrap();
+
+        let mut payloads = vec![];
+        for key in packages.iter() {
+            let query = ReleaseQuery::new(key, &repos);
+            let (target, _, _) = crate::repo::resolve_payload(key, &query, &repos)
+                .map_err(|e| BundleError::Payload(key.clone(), e))?;
+            let is_cached = crate::repo::is_payload_cached(&config, &target.payload);
+            let path = crate::repo::download_file_path(&config, target.payload.as_download_url());
+            let name = path
+                .strip_prefix(config.settings().package_cache_dir())
+                .map(|x| Path::new(PAYLOADS).join(x))
+                .expect("downloads are in the package cache");
+            payloads.push((key.clone(), path, name, is_cached));
+        }
+
+        let mut urls = packages
+            .iter()
+            .map(|x| x.repository_url.clone())
+            .collect::<Vec<_>>();
+        urls.sort();
+        urls.dedup();
+        let repos = urls
+            .into_iter()
+            .filter_map(|url| {
+                let repo = repos.get(&url)?;
+                let dir = index_dir(&config, &url);
+                Some((url, repo.info.clone(), repo.packages.clone(), dir))
+            })
+            .collect::<Vec<_>>();
+
+        (payloads, repos)
+    };
+
+    for (key, _, _, is_cached) in payloads.iter() {
+        if *is_cached {
+            continue;
+        }
+
+        log::info!("Downloading {} for bundle", key);
+        let mut download = store.download(key);
+        while let Some(event) = download.next().await {
+            if let DownloadEvent::Error(e) = event {
+                return Err(BundleError::Download(key.clone(), e));
+            }
+        }
+    }
+
+    let manifest = Manifest {
+        packages: keys,
+        repositories: repos.iter().map(|x| x.0.clone()).collect(),
+    };
+
+    let write = |e| BundleError::Write(e, path.to_path_buf());
+    let file = File::create(path).map_err(write)?;
+    let mut builder = tar::Builder::new(file);
+
+    let manifest = toml::to_vec(&manifest).expect("manifest serializes");
+    append(&mut builder, Path::new(MANIFEST), &manifest).map_err(write)?;
+
+    for (url, info, index, source) in repos.iter() {
+        let dir = repo_dir(url);
+
+        // Copied as they are, as encoding them again would not match their manifest
+        if source.join("index.toml").is_file() {
+            let signature = signature_path(RepoManifest::PATH);
+            let signed = [RepoManifest::PATH, &*signature];
+            for path in RepoManifest::FILES.iter().chain(&signed) {
+                let file = source.join(path);
+                if file.is_file() {
+                    builder
+                        .append_path_with_name(&file, dir.join(path))
+                        .map_err(write)?;
+                }
+            }
+            continue;
+        }
+
+        // Legacy repositories are converted when loaded, and are never signed
+        let info = toml::to_vec(info).expect("index serializes");
+        append(&mut builder, &dir.join("index.toml"), &info).map_err(write)?;
+        append(&mut builder, &dir.join("packages").join("index.bin"), index).map_err(write)?;
+    }
+
+    for (key, path, name, _) in payloads.iter() {
+        log::debug!("Adding {} to bundle as {}", key, name.display());
+        builder.append_path_with_name(path, name).map_err(write)?;
+    }
+
+    builder
+        .into_inner()
+        .and_then(|x| x.sync_all())
+        .map_err(write)?;
+
+    Ok(packages)
+}
+
+fn append<W: std::io::Write>(
+    builder: &mut tar::Builder<W>,
+    path: &Path,
+    data: &[u8],
+) -> std::io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append_data(&mut header, path, data)
+}
+
+/// Loads the indexes of the bundle at `path` into `repos`, replacing any loaded from
+/// the network, and places its payloads in the package cache. Indexes must be signed
+/// by the keys trusted for their repositories, as when loaded from the network.
+/// Returns the packages the bundle was exported for.
+pub(crate) fn import(
+    config: &Config,
+    repos: &mut HashMap<RepoUrl, LoadedRepository>,
+    path: &Path,
+) -> Result<Vec<PackageKey>, BundleError> {
+    let read = |e| BundleError::Read(e, path.to_path_buf());
+    let file = File::open(path).map_err(read)?;
+    let len = file.metadata().map_err(read)?.len();
+    let mut archive = tar::Archive::new(file);
+    let mut sanitizer = Sanitizer::new(len, Limits::default());
+
+    let mut manifest = None;
+    let mut files = HashMap::new();
+
+    // Payloads are only unpacked once the indexes referring to them are verified
+    for entry in archive.entries().map_err(read)? {
+        let mut entry = entry.map_err(read)?;
+        let name = entry.path().map_err(read)?.into_owned();
+        sanitizer.check_entry(&name, entry.size())?;
+        if name.starts_with(PAYLOADS) {
+            continue;
+        }
+
+        let mut data = vec![];
+        entry.read_to_end(&mut data).map_err(read)?;
+        if name == Path::new(MANIFEST) {
+            manifest = Some(toml::from_slice::<Manifest>(&data)?);
+        } else {
+            files.insert(name, data);
+        }
+    }
+
+    let manifest = manifest.ok_or(BundleError::MissingManifest)?;
+
+    // Every index is verified before any is loaded
+    let mut loaded = vec![];
+    for url in manifest.repositories {
+        let dir = repo_dir(&url);
+        let record = config.repos().get(&url).cloned().unwrap_or_default();
+        let trust = crate::trust::repo_trust(config, &url, &record)
+            .map_err(|e| BundleError::Index(url.clone(), e.into()))?;
+
+        let read_file = |path: &str| {
+            files
+                .get(&dir.join(path))
+                .cloned()
+                .ok_or_else(|| std::io::ErrorKind::NotFound.into())
+        };
+        let repo = LoadedRepository::from_files(&url, read_file, None, trust.clone())
+            .map_err(|e| BundleError::Index(url.clone(), e))?;
+        loaded.push((url, repo, trust));
+    }
+
+    // The bundle is read again, so its entries are checked again
+    let cache_dir = config.settings().package_cache_dir();
+    let mut archive = tar::Archive::new(File::open(path).map_err(read)?);
+    let mut sanitizer = Sanitizer::new(len, Limits::default());
+    for entry in archive.entries().map_err(read)? {
+        let mut entry = entry.map_err(read)?;
+        let name = entry.path().map_err(read)?.into_owned();
+        sanitizer.check_entry(&name, entry.size())?;
+        let rest = match name.strip_prefix(PAYLOADS) {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+        if !entry.header().entry_type().is_file() {
+            log::warn!("Skipping {} in bundle, as it is not a file", name.display());
+            continue;
+        }
+        let dest = cache_dir.join(rest);
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent).map_err(read)?;
+        }
+        entry.unpack(&dest).map_err(read)?;
+    }
+
+    for (url, repo, trust) in loaded {
+        crate::trust::accept(config, &url, &trust, &repo);
+        log::info!("Loaded {} from bundle", &url);
+        repos.insert(url, repo);
+    }
+
+    Ok(manifest.packages)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use ed25519_dalek::{ExpandedSecretKey, PublicKey, SecretKey};
+    use pahkat_types::repo::{Agent, Index, RepositoryData};
+
+    use super::*;
+    use crate::config::{ConfigPath, Permission, RepoRecord};
+    use crate::package_store::mock::REPO;
+
+    fn keypair(seed: u8) -> (String, ExpandedSecretKey, PublicKey) {
+        let secret = SecretKey::from_bytes(&[seed; 32]).unwrap();
+        let public = PublicKey::from(&secret);
+        (
+            base64::encode(public.as_bytes()),
+            ExpandedSecretKey::from(&secret),
+            public,
+        )
+    }
+
+    /// A bundle of an empty repository advertising the key of `seed`, signed by that
+    /// key with `serial` unless `seed` is `None`. The index is tampered with after
+    /// signing if asked.
+    fn write_bundle(path: &Path, seed: Option<u8>, serial: u64, is_tampered: bool) {
+        let url: RepoUrl = REPO.parse().unwrap();
+        let keypair = seed.map(keypair);
+        let info = Index::builder()
+            .repository(
+                RepositoryData::builder()
+                    .url(url.clone())
+                    .signing_key(keypair.as_ref().map(|x| x.0.clone()))
+                    .build(),
+            )
+            .agent(
+                Agent::builder()
+                    .name("pahkat".into())
+                    .version("test".into())
+                    .build(),
+            )
+            .build();
+        let info = toml::to_vec(&info).unwrap();
+        let index = pahkat_types::index_writer::encode_index(&[]).unwrap();
+
+        let mut builder = tar::Builder::new(File::create(path).unwrap());
+        let manifest = Manifest {
+            packages: vec![],
+            repositories: vec![url.clone()],
+        };
+        let manifest = toml::to_vec(&manifest).unwrap();
+        append(&mut builder, Path::new(MANIFEST), &manifest).unwrap();
+
+        let dir = repo_dir(&url);
+        if let Some((_, secret, public)) = keypair.as_ref() {
+            let files = RepoManifest::FILES.iter().copied().zip([&*info, &*index]);
+            let manifest = toml::to_vec(&RepoManifest::new(url.clone(), serial, files)).unwrap();
+            let signature = base64::encode(secret.sign(&manifest, public).to_bytes());
+            append(&mut builder, &dir.join(RepoManifest::PATH), &manifest).unwrap();
+            let sig_path = dir.join(signature_path(RepoManifest::PATH));
+            append(&mut builder, &sig_path, signature.as_bytes()).unwrap();
+        }
+        for (name, data) in RepoManifest::FILES.iter().zip([info, index].iter()) {
+            let mut data = data.clone();
+            if is_tampered {
+                data.push(b'\n');
+            }
+            append(&mut builder, &dir.join(name), &data).unwrap();
+        }
+        append(
+            &mut builder,
+            &Path::new(PAYLOADS).join("payload"),
+            b"payload",
+        )
+        .unwrap();
+        builder.finish().unwrap();
+    }
+
+    #[test]
+    fn verifies_indexes_before_importing() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut config, errors) = Config::load(&dir.path().join("config"), Permission::ReadWrite);
+        assert!(errors.is_empty(), "{:?}", errors);
+        let cache_dir = ConfigPath::try_from(dir.path().join("cache")).unwrap();
+        config.settings_mut().set_cache_dir(cache_dir).unwrap();
+        let url: RepoUrl = REPO.parse().unwrap();
+        let bundle = dir.path().join("bundle.tar");
+        let payload = config.settings().package_cache_dir().join("payload");
+        let mut repos = HashMap::new();
+
+        let refuse =
+            |config: &Config, repos: &mut HashMap<_, _>| match import(config, repos, &bundle) {
+                Err(BundleError::Index(_, e)) => e,
+                other => panic!("{:?}", other),
+            };
+
+        // A repository with trusted keys must be signed by one of them
+        let (trusted_key, _, _) = keypair(1);
+        let record = RepoRecord {
+            trusted_keys: vec![trusted_key],
+            ..Default::default()
+        };
+        config.repos_mut().insert(url.clone(), record).unwrap();
+        write_bundle(&bundle, None, 1, false);
+        assert!(matches!(
+            refuse(&config, &mut repos),
+            RepoDownloadError::SignatureMissing(_)
+        ));
+        write_bundle(&bundle, Some(2), 1, false);
+        assert!(matches!(
+            refuse(&config, &mut repos),
+            RepoDownloadError::InvalidSignature(_)
+        ));
+        write_bundle(&bundle, Some(1), 1, true);
+        assert!(matches!(
+            refuse(&config, &mut repos),
+            RepoDownloadError::ManifestMismatch(_)
+        ));
+        assert!(repos.is_empty());
+        assert!(!payload.exists());
+
+        write_bundle(&bundle, Some(1), 2, false);
+        import(&config, &mut repos, &bundle).unwrap();
+        assert!(repos.contains_key(&url));
+        assert!(payload.exists());
+
+        // Bundles older than an index seen before are refused
+        write_bundle(&bundle, Some(1), 1, false);
+        assert!(matches!(
+            refuse(&config, &mut repos),
+            RepoDownloadError::Rollback(1, 2)
+        ));
+
+        // Otherwise the advertised key is pinned, as when loaded from the network
+        config.repos_mut().remove(&url).unwrap();
+        repos.clear();
+        write_bundle(&bundle, Some(2), 3, false);
+        import(&config, &mut repos, &bundle).unwrap();
+        let keys_path = crate::trust::path(&config);
+        assert_eq!(
+            crate::trust::pinned(&keys_path, &url).unwrap(),
+            Some(keypair(2).0)
+        );
+
+        write_bundle(&bundle, Some(3), 4, false);
+        assert!(matches!(
+            refuse(&config, &mut repos),
+            RepoDownloadError::KeyChanged(_)
+        ));
+    }
+}
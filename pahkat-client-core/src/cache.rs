@@ -0,0 +1,155 @@
+//! Keeps the package cache, where downloaded payloads are stored, within the
+//! limits of the settings.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::transaction::ResolvedAction;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CachePolicy {
+    /// Payloads are kept after they are installed, so that reinstalling or repairing
+    /// a package does not download it again.
+    #[default]
+    Keep,
+    /// Payloads are removed once the transaction that installed them has finished.
+    RemoveAfterInstall,
+}
+
+impl CachePolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CachePolicy::Keep => "keep",
+            CachePolicy::RemoveAfterInstall => "remove_after_install",
+        }
+    }
+}
+
+impl fmt::Display for CachePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for CachePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(CachePolicy::Keep),
+            "remove_after_install" => Ok(CachePolicy::RemoveAfterInstall),
+            _ => Err(format!(
+                "unknown policy `{}`, expected `keep` or `remove_after_install`",
+                s
+            )),
+        }
+    }
+}
+
+/// Applies the cache policy to the payloads of a finished transaction, then trims
+/// the cache to its size limit.
+pub(crate) fn after_transaction(config: &Config, actions: &[ResolvedAction]) {
+    let settings = config.settings();
+
+    if settings.cache_policy() == CachePolicy::RemoveAfterInstall {
+        for record in actions.iter().filter(|x| x.action.is_install()) {
+            let url = pahkat_types::AsDownloadUrl::as_download_url(&record.target.payload);
+            let path = crate::repo::download_file_path(config, url);
+            match std::fs::remove_file(&path) {
+                Ok(_) => log::debug!("Removed {} from the package cache", path.display()),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => log::warn!("Could not remove {}: {}", path.display(), e),
+            }
+        }
+    }
+
+    trim(&settings.package_cache_dir(), settings.cache_size_limit());
+}
+
+/// Removes the least recently modified files under `dir` until the rest take up
+/// at most `limit` bytes. A limit of zero keeps everything.
+pub(crate) fn trim(dir: &Path, limit: u64) {
+    if limit == 0 {
+        return;
+    }
+
+    let mut files = vec![];
+    collect_files(dir, &mut files);
+
+    let mut size = files.iter().map(|(_, len, _)| len).sum::<u64>();
+    if size <= limit {
+        return;
+    }
+
+    files.sort_by_key(|(_, _, modified)| *modified);
+
+    for (path, len, _) in files {
+        if size <= limit {
+            break;
+        }
+        match std::fs::remove_file(&path) {
+            Ok(_) => {
+                log::debug!("Removed {} from the package cache", path.display());
+                size -= len;
+            }
+            Err(e) => log::warn!("Could not remove {}: {}", path.display(), e),
+        }
+    }
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<(PathBuf, u64, SystemTime)>) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(v) => v,
+        Err(_) => return,
+    };
+
+    for entry in entries.filter_map(Result::ok) {
+        let meta = match entry.metadata() {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+
+        if meta.is_dir() {
+            collect_files(&entry.path(), files);
+        } else {
+            let modified = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            files.push((entry.path(), meta.len(), modified));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn trim_removes_oldest_files_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("ab").join("cd");
+        std::fs::create_dir_all(&nested).unwrap();
+
+        let now = SystemTime::now();
+        for (i, name) in ["old", "middle", "new"].iter().enumerate() {
+            let path = nested.join(name);
+            std::fs::write(&path, [0u8; 10]).unwrap();
+            let file = std::fs::File::options().write(true).open(&path).unwrap();
+            file.set_modified(now - Duration::from_secs(60 * (3 - i as u64)))
+                .unwrap();
+        }
+
+        trim(dir.path(), 0);
+        assert!(nested.join("old").exists());
+
+        trim(dir.path(), 15);
+        assert!(!nested.join("old").exists());
+        assert!(!nested.join("middle").exists());
+        assert!(nested.join("new").exists());
+    }
+}
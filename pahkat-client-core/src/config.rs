@@ -1,10 +1,16 @@
+mod migrate;
 pub(crate) mod path;
+#[cfg(feature = "prefix")]
+mod prefixes;
 mod repos;
 mod settings;
 
+pub use migrate::{AppliedMigration, CURRENT_VERSION};
 pub use path::ConfigPath;
-pub use repos::{RepoRecord, Repos, ReposData};
-pub use settings::{Settings, SettingsData};
+#[cfg(feature = "prefix")]
+pub use prefixes::{Prefixes, PrefixesData};
+pub use repos::{ClientCertificate, RepoRecord, Repos, ReposData, UpdateHold};
+pub use settings::{ExecHooks, ProgressRate, SettingError, SettingKey, Settings, SettingsData};
 
 use std::path::{Path, PathBuf};
 
@@ -23,6 +29,15 @@ pub enum Error {
     #[error("Error loading settings.toml file")]
     SettingsFile(#[source] FileError),
 
+    #[error("Error loading prefixes.toml file")]
+    PrefixesFile(#[source] FileError),
+
+    #[error("Error loading desired-state.toml file")]
+    DesiredStateFile(#[source] FileError),
+
+    #[error("Error migrating configuration from an older version")]
+    Migration(#[source] FileError),
+
     #[error("An error occurred managing app paths")]
     PathError(#[from] pathos::Error),
 }
@@ -41,6 +56,9 @@ pub enum FileError {
     #[error("Could not convert from TOML format: {1}")]
     FromToml(#[source] toml::de::Error, PathBuf),
 
+    #[error("Could not convert from JSON format: {1}")]
+    FromJson(#[source] serde_json::Error, PathBuf),
+
     #[error("Could not convert into TOML format: {1}")]
     ToToml(#[source] toml::ser::Error, PathBuf),
 
@@ -55,6 +73,7 @@ pub enum FileError {
 pub struct Config {
     repos: Repos,
     settings: Settings,
+    migrations: Vec<AppliedMigration>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -68,6 +87,7 @@ impl Config {
         Config {
             repos: Repos::read_only(),
             settings: Settings::read_only(),
+            migrations: vec![],
         }
     }
 
@@ -82,6 +102,26 @@ impl Config {
 
         let config_path = path.as_ref();
 
+        // Read only configs are used as they are, as the migrated files cannot be saved
+        let migrations = if permission == Permission::ReadWrite {
+            match migrate::migrate(config_path) {
+                Ok(v) => v,
+                Err(e) => {
+                    errors.push(Error::Migration(e));
+                    vec![]
+                }
+            }
+        } else {
+            vec![]
+        };
+        for migration in migrations.iter() {
+            log::info!(
+                "Applied config migration {}: {}",
+                migration.version,
+                migration.description
+            );
+        }
+
         let settings_path = config_path.join("settings.toml");
 
         let settings = match Settings::load(&settings_path, permission) {
@@ -120,7 +160,11 @@ impl Config {
             }
         };
 
-        let config = Config { repos, settings };
+        let config = Config {
+            repos,
+            settings,
+            migrations,
+        };
 
         log::trace!("Config loaded: {:#?}", &config);
 
@@ -128,7 +172,16 @@ impl Config {
     }
 
     pub fn new(settings: Settings, repos: Repos) -> Config {
-        Config { repos, settings }
+        Config {
+            repos,
+            settings,
+            migrations: vec![],
+        }
+    }
+
+    /// Migrations applied to the files of an older client when the config was loaded.
+    pub fn applied_migrations(&self) -> &[AppliedMigration] {
+        &self.migrations
     }
 
     pub fn repos(&self) -> &Repos {
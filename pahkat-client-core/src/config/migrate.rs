@@ -0,0 +1,262 @@
+//! Upgrades config directories written by older clients to the current layout.
+//!
+//! The layout version is kept as `config_version` in `settings.toml`. Migrations
+//! work on the raw TOML tables before they are deserialized, so they can handle keys
+//! and values the current structs would reject. The files a migration touches are
+//! copied to `<name>.v<version>.bak` first.
+
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use toml::value::{Table, Value};
+
+use super::path::ConfigPath;
+use super::FileError;
+
+pub const CURRENT_VERSION: u32 = 2;
+
+const SETTINGS_FILE: &str = "settings.toml";
+const REPOS_FILE: &str = "repos.toml";
+/// Written by the client before the TOML config, holding both repos and settings.
+const LEGACY_STORE_CONFIG_FILE: &str = "config.json";
+
+/// A migration that was applied while loading the config.
+#[derive(Debug, Clone, Serialize)]
+pub struct AppliedMigration {
+    pub version: u32,
+    pub description: &'static str,
+}
+
+struct Migration {
+    version: u32,
+    description: &'static str,
+    apply: fn(&mut Files) -> Result<(), FileError>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "Imported repositories and cache path from the legacy config.json",
+        apply: import_legacy_store_config,
+    },
+    Migration {
+        version: 2,
+        description: "Converted cache and temporary directory paths to file URLs",
+        apply: convert_paths_to_urls,
+    },
+];
+
+struct Files {
+    dir: PathBuf,
+    settings: Table,
+    repos: Table,
+}
+
+/// Brings the config at `dir` up to [`CURRENT_VERSION`], returning the migrations
+/// that were applied. A directory without any config is left alone.
+pub(crate) fn migrate(dir: &Path) -> Result<Vec<AppliedMigration>, FileError> {
+    let settings_path = dir.join(SETTINGS_FILE);
+    let repos_path = dir.join(REPOS_FILE);
+    let legacy_path = dir.join(LEGACY_STORE_CONFIG_FILE);
+
+    if !settings_path.exists() && !repos_path.exists() && !legacy_path.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut files = Files {
+        dir: dir.to_path_buf(),
+        settings: read_table(&settings_path)?,
+        repos: read_table(&repos_path)?,
+    };
+
+    let version = files
+        .settings
+        .get("config_version")
+        .and_then(Value::as_integer)
+        .unwrap_or(0) as u32;
+    if version >= CURRENT_VERSION {
+        return Ok(vec![]);
+    }
+
+    for path in [&settings_path, &repos_path, &legacy_path].iter() {
+        backup(path, version)?;
+    }
+
+    let mut applied = vec![];
+    for migration in MIGRATIONS.iter().filter(|x| x.version > version) {
+        log::info!(
+            "Migrating config to version {}: {}",
+            migration.version,
+            migration.description
+        );
+        (migration.apply)(&mut files)?;
+        applied.push(AppliedMigration {
+            version: migration.version,
+            description: migration.description,
+        });
+    }
+
+    files.settings.insert(
+        "config_version".into(),
+        Value::Integer(CURRENT_VERSION.into()),
+    );
+    write_table(&settings_path, &files.settings)?;
+    write_table(&repos_path, &files.repos)?;
+
+    Ok(applied)
+}
+
+fn read_table(path: &Path) -> Result<Table, FileError> {
+    let file = match std::fs::read_to_string(path) {
+        Ok(v) => v,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Table::new()),
+        Err(e) => return Err(FileError::Read(e, path.to_path_buf())),
+    };
+    toml::from_str(&file).map_err(|e| FileError::FromToml(e, path.to_path_buf()))
+}
+
+fn write_table(path: &Path, table: &Table) -> Result<(), FileError> {
+    let b = toml::to_vec(table).map_err(|e| FileError::ToToml(e, path.to_path_buf()))?;
+    std::fs::write(path, b).map_err(|e| FileError::Write(e, path.to_path_buf()))
+}
+
+/// Keeps the first backup of each version, should a migration be retried.
+fn backup(path: &Path, version: u32) -> Result<(), FileError> {
+    let name = match path.file_name() {
+        Some(v) => v.to_string_lossy(),
+        None => return Err(FileError::PathParent(path.to_path_buf())),
+    };
+    let backup_path = path.with_file_name(format!("{}.v{}.bak", name, version));
+    if !path.exists() || backup_path.exists() {
+        return Ok(());
+    }
+
+    std::fs::copy(path, &backup_path)
+        .map(|_| ())
+        .map_err(|e| FileError::Write(e, backup_path))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LegacyStoreConfig {
+    #[serde(default)]
+    repos: Vec<LegacyRepoRecord>,
+    #[serde(default, alias = "cacheBasePath")]
+    cache_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LegacyRepoRecord {
+    url: url::Url,
+    #[serde(default)]
+    channel: Option<String>,
+}
+
+/// Repositories already in `repos.toml` are kept as they are.
+fn import_legacy_store_config(files: &mut Files) -> Result<(), FileError> {
+    let path = files.dir.join(LEGACY_STORE_CONFIG_FILE);
+    let file = match std::fs::read_to_string(&path) {
+        Ok(v) => v,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(FileError::Read(e, path)),
+    };
+    let legacy: LegacyStoreConfig =
+        serde_json::from_str(&file).map_err(|e| FileError::FromJson(e, path))?;
+
+    for repo in legacy.repos {
+        let mut record = Table::new();
+        if let Some(channel) = repo.channel.filter(|x| !x.is_empty()) {
+            record.insert("channel".into(), Value::String(channel));
+        }
+        files
+            .repos
+            .entry(repo.url.to_string())
+            .or_insert(Value::Table(record));
+    }
+
+    if let Some(cache_path) = legacy.cache_path {
+        files
+            .settings
+            .entry("cache_dir".to_string())
+            .or_insert(Value::String(cache_path.to_string_lossy().into_owned()));
+    }
+
+    Ok(())
+}
+
+/// Older settings held plain paths, and the oldest named the cache `cache_base_path`.
+/// Paths that cannot be converted are removed so that the defaults are used.
+fn convert_paths_to_urls(files: &mut Files) -> Result<(), FileError> {
+    if let Some(value) = files.settings.remove("cache_base_path") {
+        files
+            .settings
+            .entry("cache_dir".to_string())
+            .or_insert(value);
+    }
+
+    for key in ["cache_dir", "tmp_dir"].iter() {
+        let path = match files.settings.get(*key).and_then(Value::as_str) {
+            Some(v) if !v.starts_with("file:") && !v.starts_with("container:") => PathBuf::from(v),
+            _ => continue,
+        };
+
+        match ConfigPath::try_from(path.clone()) {
+            Ok(v) => {
+                files
+                    .settings
+                    .insert(key.to_string(), Value::String(v.to_string()));
+            }
+            Err(_) => {
+                log::warn!("Dropping invalid `{}` setting: {}", key, path.display());
+                files.settings.remove(*key);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn imports_legacy_store_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = dir.path().join("cache");
+        std::fs::write(
+            dir.path().join(LEGACY_STORE_CONFIG_FILE),
+            serde_json::json!({
+                "repos": [{ "url": "https://pahkat.example/main/", "channel": "beta" }],
+                "cachePath": cache,
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        let applied = migrate(dir.path()).unwrap();
+        assert_eq!(
+            applied.iter().map(|x| x.version).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+
+        let repos = read_table(&dir.path().join(REPOS_FILE)).unwrap();
+        assert_eq!(
+            repos["https://pahkat.example/main/"]["channel"].as_str(),
+            Some("beta")
+        );
+        let settings = read_table(&dir.path().join(SETTINGS_FILE)).unwrap();
+        assert!(settings["cache_dir"].as_str().unwrap().starts_with("file:"));
+        assert!(dir.path().join("config.json.v0.bak").exists());
+
+        assert!(migrate(dir.path()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn leaves_empty_dir_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(migrate(dir.path()).unwrap().is_empty());
+        assert!(!dir.path().join(SETTINGS_FILE).exists());
+    }
+}
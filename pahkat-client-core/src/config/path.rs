@@ -32,6 +32,28 @@ impl ConfigPath {
     }
 }
 
+impl std::str::FromStr for ConfigPath {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.starts_with("file:") || value.starts_with("container:") {
+            let url = iref::IriBuf::new(value).map_err(|_| Error::InvalidUrl)?;
+            if value.starts_with("file:") {
+                url.to_path_buf().map_err(|_| Error::InvalidUrl)?;
+            }
+            Ok(ConfigPath(url))
+        } else {
+            Err(Error::InvalidScheme(value.to_string()))
+        }
+    }
+}
+
+impl fmt::Display for ConfigPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0.to_string())
+    }
+}
+
 impl TryFrom<PathBuf> for ConfigPath {
     type Error = ();
 
@@ -0,0 +1,123 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::FileError;
+
+/// Named prefixes registered in the user configuration, so a prefix can be
+/// selected by name instead of by path.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrefixesData {
+    /// The prefix used when none is given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+
+    // Tables have to come last in TOML
+    #[serde(default)]
+    pub prefixes: BTreeMap<String, PathBuf>,
+}
+
+impl PrefixesData {
+    fn load<P: AsRef<Path>>(path: P) -> Result<PrefixesData, FileError> {
+        let file = std::fs::read_to_string(&path)
+            .map_err(|e| FileError::Read(e, path.as_ref().to_path_buf()))?;
+        let file = toml::from_str(&file)
+            .map_err(|e| FileError::FromToml(e, path.as_ref().to_path_buf()))?;
+        Ok(file)
+    }
+
+    fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), FileError> {
+        let parent = path
+            .as_ref()
+            .parent()
+            .ok_or_else(|| FileError::PathParent(path.as_ref().to_path_buf()))?;
+        std::fs::create_dir_all(&parent)
+            .map_err(|e| FileError::CreateParentDir(e, parent.to_path_buf()))?;
+
+        let mut file =
+            File::create(&path).map_err(|e| FileError::Write(e, path.as_ref().to_path_buf()))?;
+        let b =
+            toml::to_vec(&self).map_err(|e| FileError::ToToml(e, path.as_ref().to_path_buf()))?;
+        file.write_all(&b)
+            .map_err(|e| FileError::Write(e, path.as_ref().to_path_buf()))?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Prefixes {
+    path: PathBuf,
+    data: PrefixesData,
+}
+
+impl Prefixes {
+    /// Loads `prefixes.toml` from the default configuration directory.
+    #[cfg(not(target_os = "android"))]
+    pub fn load_default() -> Result<Prefixes, super::Error> {
+        let path = crate::defaults::config_path()?.join("prefixes.toml");
+        Prefixes::load(path).map_err(super::Error::PrefixesFile)
+    }
+
+    /// A missing file is an empty registry; it is only created once a prefix is added.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Prefixes, FileError> {
+        let path = path.as_ref().to_path_buf();
+        let data = if path.exists() {
+            PrefixesData::load(&path)?
+        } else {
+            PrefixesData::default()
+        };
+        Ok(Prefixes { path, data })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Path> {
+        self.data.prefixes.get(name).map(PathBuf::as_path)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Path)> {
+        self.data
+            .prefixes
+            .iter()
+            .map(|(name, path)| (&**name, path.as_path()))
+    }
+
+    pub fn default_name(&self) -> Option<&str> {
+        self.data.default.as_deref()
+    }
+
+    /// The path of the named prefix, or of the default prefix if no name is given.
+    pub fn resolve(&self, name: Option<&str>) -> Option<&Path> {
+        name.or_else(|| self.default_name())
+            .and_then(|name| self.get(name))
+    }
+
+    pub fn insert(&mut self, name: String, path: PathBuf) -> Result<(), FileError> {
+        self.data.prefixes.insert(name, path);
+        self.data.save(&self.path)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<bool, FileError> {
+        let result = self.data.prefixes.remove(name).is_some();
+        if self.data.default.as_deref() == Some(name) {
+            self.data.default = None;
+        }
+        self.data.save(&self.path)?;
+        Ok(result)
+    }
+
+    /// Returns `false` if no prefix with that name is registered.
+    pub fn set_default(&mut self, name: &str) -> Result<bool, FileError> {
+        if !self.data.prefixes.contains_key(name) {
+            return Ok(false);
+        }
+        self.data.default = Some(name.to_string());
+        self.data.save(&self.path)?;
+        Ok(true)
+    }
+
+    pub fn data(&self) -> &PrefixesData {
+        &self.data
+    }
+}
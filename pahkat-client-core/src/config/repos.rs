@@ -1,3 +1,4 @@
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs::File;
 use std::io::Write;
 use std::path::{Path, PathBuf};
@@ -7,11 +8,109 @@ use serde::{Deserialize, Serialize};
 
 use super::FileError;
 use crate::config::Permission;
+use crate::secret::{SecretError, SecretHandle, SecretStore};
 use pahkat_types::repo::RepoUrl;
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 pub struct RepoRecord {
+    /// A single channel, whose releases are considered together with stable ones, or
+    /// a comma-separated list in order of preference, such as `beta, stable`.
     pub channel: Option<String>,
+    /// Packages opted into a pre-release channel, by package id, such as
+    /// `speller-sme = "beta"`. Whichever of their newest release in that channel
+    /// and their newest stable release is newer is installed.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub prerelease_channels: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_token: Option<SecretHandle>,
+    /// MSI properties for this deployment, such as license keys. These take
+    /// precedence over the properties declared by packages in this repository.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub msi_properties: BTreeMap<String, String>,
+    /// MSI transforms applied after those declared by the package.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub msi_transforms: Vec<String>,
+    /// Base64 ed25519 public keys. If any are given, the repository index must be
+    /// signed by one of them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trusted_keys: Vec<String>,
+    /// Sent with index and payload requests to repositories that require mutual TLS.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_certificate: Option<ClientCertificate>,
+    /// Packages kept at their installed version, by package id. Updates to them
+    /// are shown but not installed in the background.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub held_packages: BTreeSet<String>,
+    /// A release of each package, by package id, that the user chose not to update
+    /// to. Updates are offered again once a newer release is out.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub skipped_versions: BTreeMap<String, String>,
+}
+
+/// Why an available update to a package is not installed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateHold {
+    Held,
+    Skipped,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientCertificate {
+    /// PEM file with the certificate, followed by its chain and, without a `key`,
+    /// the private key.
+    pub certificate: PathBuf,
+    /// PEM file with the private key, if kept apart from the certificate.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<PathBuf>,
+}
+
+impl RepoRecord {
+    /// Whether updating package `id` to `version` is held back by the user.
+    pub fn update_hold(&self, id: &str, version: &str) -> Option<UpdateHold> {
+        if self.held_packages.contains(id) {
+            Some(UpdateHold::Held)
+        } else if self.skipped_versions.get(id).map(String::as_str) == Some(version) {
+            Some(UpdateHold::Skipped)
+        } else {
+            None
+        }
+    }
+
+    /// The bearer token for `url`, sent with index requests and with payload requests
+    /// to the same origin.
+    pub fn resolve_auth_token(
+        &self,
+        secrets: &dyn SecretStore,
+    ) -> Result<Option<String>, SecretError> {
+        self.auth_token.as_ref().map(|x| secrets.get(x)).transpose()
+    }
+
+    /// Keeps `token` in `secrets` and refers to it from this record, or deletes the
+    /// current token if `None`. The record itself still has to be saved.
+    pub fn set_auth_token(
+        &mut self,
+        url: &RepoUrl,
+        token: Option<&str>,
+        secrets: &dyn SecretStore,
+    ) -> Result<(), SecretError> {
+        match token {
+            Some(token) => {
+                let handle = self
+                    .auth_token
+                    .clone()
+                    .unwrap_or_else(|| SecretHandle::new(format!("repo-auth-token:{}", url)));
+                secrets.set(&handle, token)?;
+                self.auth_token = Some(handle);
+            }
+            None => {
+                if let Some(handle) = self.auth_token.take() {
+                    secrets.delete(&handle)?;
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -156,3 +255,30 @@ impl Repos {
         &self.data
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn held_and_skipped_updates() {
+        let mut record = RepoRecord::default();
+        assert_eq!(record.update_hold("speller", "2.0.0"), None);
+
+        record
+            .skipped_versions
+            .insert("speller".into(), "2.0.0".into());
+        assert_eq!(
+            record.update_hold("speller", "2.0.0"),
+            Some(UpdateHold::Skipped)
+        );
+        assert_eq!(record.update_hold("speller", "2.1.0"), None);
+
+        record.held_packages.insert("speller".into());
+        assert_eq!(
+            record.update_hold("speller", "2.1.0"),
+            Some(UpdateHold::Held)
+        );
+        assert_eq!(record.update_hold("keyboard", "2.1.0"), None);
+    }
+}
@@ -1,13 +1,22 @@
 use std::fs::File;
 use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use url::Url;
 
 use super::path::ConfigPath;
 use super::FileError;
+use crate::cache::CachePolicy;
 use crate::config::Permission;
 use crate::defaults;
+use crate::failures::FailurePolicy;
+use crate::power::PowerPolicy;
+use crate::priority::ProcessPriority;
+use crate::secret::DefaultSecretStore;
 
 #[inline(always)]
 fn cache_dir_default() -> ConfigPath {
@@ -19,6 +28,90 @@ fn tmp_dir_default() -> ConfigPath {
     defaults::tmp_dir().expect("tmp dir")
 }
 
+#[inline(always)]
+fn update_interval_default() -> u64 {
+    15 * 60
+}
+
+#[inline(always)]
+fn auto_update_default() -> bool {
+    true
+}
+
+#[inline(always)]
+fn background_priority_default() -> ProcessPriority {
+    ProcessPriority::Low
+}
+
+#[inline(always)]
+fn shutdown_grace_period_default() -> u64 {
+    30
+}
+
+#[inline(always)]
+fn progress_interval_default() -> u64 {
+    100
+}
+
+#[inline(always)]
+fn progress_step_default() -> u8 {
+    1
+}
+
+const MIN_UPDATE_INTERVAL: u64 = 60;
+const DEFAULT_CONCURRENT_DOWNLOADS: u8 = 3;
+
+/// Commands run at stages of a transaction, see `transaction::observer::ExecObserver`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ExecHooks {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_resolved: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_download_complete: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub before_install: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after_install: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_error: Option<PathBuf>,
+}
+
+impl ExecHooks {
+    pub fn is_empty(&self) -> bool {
+        self == &ExecHooks::default()
+    }
+}
+
+/// Limits how often download progress is reported. A progress event is only
+/// sent once `interval_ms` has passed and the download has advanced by at least
+/// `percent_step` percent since the last one; the final event is always sent.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ProgressRate {
+    #[serde(default = "progress_interval_default")]
+    pub interval_ms: u64,
+    #[serde(default = "progress_step_default")]
+    pub percent_step: u8,
+}
+
+impl ProgressRate {
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_ms)
+    }
+
+    pub fn is_default(&self) -> bool {
+        self == &ProgressRate::default()
+    }
+}
+
+impl Default for ProgressRate {
+    fn default() -> Self {
+        ProgressRate {
+            interval_ms: progress_interval_default(),
+            percent_step: progress_step_default(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SettingsData {
     #[serde(default = "cache_dir_default")]
@@ -27,8 +120,49 @@ pub struct SettingsData {
     pub tmp_dir: ConfigPath,
     #[serde(default)]
     pub max_concurrent_downloads: u8,
+    /// Bytes of payloads the package cache may hold before the least recently
+    /// downloaded are removed. Zero is no limit.
+    #[serde(default)]
+    pub cache_size_limit: u64,
+    #[serde(default)]
+    pub cache_policy: CachePolicy,
     #[serde(default)]
     pub skip_admin_verification: bool,
+    #[serde(default = "update_interval_default")]
+    pub update_interval: u64,
+    #[serde(default = "auto_update_default")]
+    pub auto_update: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<Url>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub report_url: Option<Url>,
+    /// Path of the Unix socket or named pipe the daemon listens on. Only read from
+    /// the settings file at startup, so it cannot be changed over RPC.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub socket_path: Option<PathBuf>,
+    /// Port of the HTTP gateway on localhost, if it should be served.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gateway_port: Option<u16>,
+    #[serde(default, skip_serializing_if = "ExecHooks::is_empty")]
+    pub hooks: ExecHooks,
+    #[serde(default, skip_serializing_if = "ProgressRate::is_default")]
+    pub progress: ProgressRate,
+    /// Priority of installers run by the background updater. Interactive installs
+    /// always run at normal priority.
+    #[serde(default = "background_priority_default")]
+    pub background_priority: ProcessPriority,
+    #[serde(default, skip_serializing_if = "PowerPolicy::is_default")]
+    pub power: PowerPolicy,
+    /// How the background updater retries packages that failed to install.
+    #[serde(default, skip_serializing_if = "FailurePolicy::is_default")]
+    pub install_failures: FailurePolicy,
+    /// Seconds the daemon waits for a running transaction when stopping, before
+    /// cancelling it.
+    #[serde(default = "shutdown_grace_period_default")]
+    pub shutdown_grace_period: u64,
+    /// Layout version of the config directory, see `config::migrate`.
+    #[serde(default)]
+    pub config_version: u32,
 }
 
 impl Default for SettingsData {
@@ -37,7 +171,22 @@ impl Default for SettingsData {
             cache_dir: cache_dir_default(),
             tmp_dir: tmp_dir_default(),
             max_concurrent_downloads: 0,
+            cache_size_limit: 0,
+            cache_policy: CachePolicy::default(),
             skip_admin_verification: false,
+            update_interval: update_interval_default(),
+            auto_update: auto_update_default(),
+            proxy: None,
+            report_url: None,
+            socket_path: None,
+            gateway_port: None,
+            hooks: ExecHooks::default(),
+            progress: ProgressRate::default(),
+            background_priority: background_priority_default(),
+            power: PowerPolicy::default(),
+            install_failures: FailurePolicy::default(),
+            shutdown_grace_period: shutdown_grace_period_default(),
+            config_version: super::CURRENT_VERSION,
         }
     }
 }
@@ -76,6 +225,79 @@ impl SettingsData {
     }
 }
 
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SettingKey {
+    CacheDir,
+    TmpDir,
+    MaxConcurrentDownloads,
+    CacheSizeLimit,
+    CachePolicy,
+    SkipAdminVerification,
+    UpdateInterval,
+    AutoUpdate,
+    Proxy,
+    ReportUrl,
+    GatewayPort,
+}
+
+impl SettingKey {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SettingKey::CacheDir => "cache_dir",
+            SettingKey::TmpDir => "tmp_dir",
+            SettingKey::MaxConcurrentDownloads => "max_concurrent_downloads",
+            SettingKey::CacheSizeLimit => "cache_size_limit",
+            SettingKey::CachePolicy => "cache_policy",
+            SettingKey::SkipAdminVerification => "skip_admin_verification",
+            SettingKey::UpdateInterval => "update_interval",
+            SettingKey::AutoUpdate => "auto_update",
+            SettingKey::Proxy => "proxy",
+            SettingKey::ReportUrl => "report_url",
+            SettingKey::GatewayPort => "gateway_port",
+        }
+    }
+}
+
+impl std::fmt::Display for SettingKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for SettingKey {
+    type Err = SettingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "cache_dir" => SettingKey::CacheDir,
+            "tmp_dir" => SettingKey::TmpDir,
+            "max_concurrent_downloads" => SettingKey::MaxConcurrentDownloads,
+            "cache_size_limit" => SettingKey::CacheSizeLimit,
+            "cache_policy" => SettingKey::CachePolicy,
+            "skip_admin_verification" => SettingKey::SkipAdminVerification,
+            "update_interval" => SettingKey::UpdateInterval,
+            "auto_update" => SettingKey::AutoUpdate,
+            "proxy" => SettingKey::Proxy,
+            "report_url" => SettingKey::ReportUrl,
+            "gateway_port" => SettingKey::GatewayPort,
+            _ => return Err(SettingError::UnknownKey(s.to_string())),
+        })
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SettingError {
+    #[error("Unknown setting: {0}")]
+    UnknownKey(String),
+
+    #[error("Invalid value for setting `{0}`: {1}")]
+    InvalidValue(SettingKey, String),
+
+    #[error("Could not save settings")]
+    File(#[from] FileError),
+}
+
 #[derive(Debug, Clone)]
 pub struct Settings {
     path: PathBuf,
@@ -166,6 +388,12 @@ impl Settings {
         self.cache_dir("packages").to_path_buf().unwrap()
     }
 
+    /// Keeps secrets such as auth tokens in the keyring, or in the `secrets` directory
+    /// of the config where no keyring can be reached.
+    pub fn secret_store(&self) -> DefaultSecretStore {
+        DefaultSecretStore::new(self.config_dir().join("secrets"))
+    }
+
     pub fn repo_cache_dir(&self) -> PathBuf {
         self.cache_dir("repos").to_path_buf().unwrap()
     }
@@ -178,10 +406,194 @@ impl Settings {
         self.data.max_concurrent_downloads
     }
 
+    /// How many payloads a transaction downloads at once. Zero in the settings means
+    /// the default.
+    pub fn download_concurrency(&self) -> usize {
+        match self.data.max_concurrent_downloads {
+            0 => DEFAULT_CONCURRENT_DOWNLOADS as usize,
+            x => x as usize,
+        }
+    }
+
+    pub fn cache_size_limit(&self) -> u64 {
+        self.data.cache_size_limit
+    }
+
+    pub fn cache_policy(&self) -> CachePolicy {
+        self.data.cache_policy
+    }
+
     pub fn skip_admin_verification(&self) -> bool {
         self.data.skip_admin_verification
     }
 
+    pub fn update_interval(&self) -> Duration {
+        Duration::from_secs(self.data.update_interval.max(MIN_UPDATE_INTERVAL))
+    }
+
+    pub fn auto_update(&self) -> bool {
+        self.data.auto_update
+    }
+
+    pub fn proxy(&self) -> Option<&Url> {
+        self.data.proxy.as_ref()
+    }
+
+    pub fn report_url(&self) -> Option<&Url> {
+        self.data.report_url.as_ref()
+    }
+
+    pub fn socket_path(&self) -> Option<&Path> {
+        self.data.socket_path.as_deref()
+    }
+
+    pub fn gateway_port(&self) -> Option<u16> {
+        self.data.gateway_port
+    }
+
+    pub fn hooks(&self) -> &ExecHooks {
+        &self.data.hooks
+    }
+
+    pub fn progress(&self) -> ProgressRate {
+        self.data.progress
+    }
+
+    pub fn background_priority(&self) -> ProcessPriority {
+        self.data.background_priority
+    }
+
+    pub fn power(&self) -> PowerPolicy {
+        self.data.power
+    }
+
+    pub fn install_failures(&self) -> FailurePolicy {
+        self.data.install_failures
+    }
+
+    pub fn shutdown_grace_period(&self) -> Duration {
+        Duration::from_secs(self.data.shutdown_grace_period)
+    }
+
+    /// Returns the string representation of a setting, as accepted by `set`. The
+    /// password of the proxy is left out, as any client may read settings.
+    pub fn get(&self, key: SettingKey) -> String {
+        match key {
+            SettingKey::CacheDir => self.data.cache_dir.to_string(),
+            SettingKey::TmpDir => self.data.tmp_dir.to_string(),
+            SettingKey::MaxConcurrentDownloads => self.data.max_concurrent_downloads.to_string(),
+            SettingKey::CacheSizeLimit => self.data.cache_size_limit.to_string(),
+            SettingKey::CachePolicy => self.data.cache_policy.to_string(),
+            SettingKey::SkipAdminVerification => self.data.skip_admin_verification.to_string(),
+            SettingKey::UpdateInterval => self.data.update_interval.to_string(),
+            SettingKey::AutoUpdate => self.data.auto_update.to_string(),
+            SettingKey::Proxy => self
+                .data
+                .proxy
+                .as_ref()
+                .map(|x| crate::proxy::redacted(x).to_string())
+                .unwrap_or_else(|| "".into()),
+            SettingKey::ReportUrl => self
+                .data
+                .report_url
+                .as_ref()
+                .map(|x| x.to_string())
+                .unwrap_or_else(|| "".into()),
+            SettingKey::GatewayPort => self
+                .data
+                .gateway_port
+                .map(|x| x.to_string())
+                .unwrap_or_else(|| "".into()),
+        }
+    }
+
+    /// Validates and sets a setting from its string representation.
+    pub fn set(&mut self, key: SettingKey, value: &str) -> Result<(), SettingError> {
+        let invalid =
+            |msg: &dyn std::fmt::Display| SettingError::InvalidValue(key, msg.to_string());
+
+        match key {
+            SettingKey::CacheDir => {
+                let path = ConfigPath::from_str(value).map_err(|e| invalid(&e))?;
+                self.set_cache_dir(path)?;
+            }
+            SettingKey::TmpDir => {
+                let path = ConfigPath::from_str(value).map_err(|e| invalid(&e))?;
+                self.set_tmp_dir(path)?;
+            }
+            SettingKey::MaxConcurrentDownloads => {
+                let count = value.parse::<u8>().map_err(|e| invalid(&e))?;
+                self.set_max_concurrent_downloads(count)?;
+            }
+            SettingKey::CacheSizeLimit => {
+                let limit = value.parse::<u64>().map_err(|e| invalid(&e))?;
+                self.set_cache_size_limit(limit)?;
+            }
+            SettingKey::CachePolicy => {
+                let policy = value.parse::<CachePolicy>().map_err(|e| invalid(&e))?;
+                self.set_cache_policy(policy)?;
+            }
+            SettingKey::SkipAdminVerification => {
+                let value = value.parse::<bool>().map_err(|e| invalid(&e))?;
+                self.set_skip_admin_verification(value)?;
+            }
+            SettingKey::UpdateInterval => {
+                let secs = value.parse::<u64>().map_err(|e| invalid(&e))?;
+                if secs < MIN_UPDATE_INTERVAL {
+                    return Err(invalid(&format_args!(
+                        "must be at least {} seconds",
+                        MIN_UPDATE_INTERVAL
+                    )));
+                }
+                self.set_update_interval(secs)?;
+            }
+            SettingKey::AutoUpdate => {
+                let value = value.parse::<bool>().map_err(|e| invalid(&e))?;
+                self.set_auto_update(value)?;
+            }
+            SettingKey::Proxy => {
+                let proxy = if value.is_empty() {
+                    None
+                } else {
+                    let url = Url::parse(value).map_err(|e| invalid(&e))?;
+                    match url.scheme() {
+                        "http" | "https" | "socks5" | "socks5h" => {}
+                        scheme => {
+                            return Err(invalid(&format_args!("unsupported scheme `{}`", scheme)))
+                        }
+                    }
+                    Some(url)
+                };
+                self.set_proxy(proxy)?;
+            }
+            SettingKey::ReportUrl => {
+                let url = if value.is_empty() {
+                    None
+                } else {
+                    let url = Url::parse(value).map_err(|e| invalid(&e))?;
+                    match url.scheme() {
+                        "http" | "https" => {}
+                        scheme => {
+                            return Err(invalid(&format_args!("unsupported scheme `{}`", scheme)))
+                        }
+                    }
+                    Some(url)
+                };
+                self.set_report_url(url)?;
+            }
+            SettingKey::GatewayPort => {
+                let port = if value.is_empty() {
+                    None
+                } else {
+                    Some(value.parse::<u16>().map_err(|e| invalid(&e))?)
+                };
+                self.set_gateway_port(port)?;
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn set_cache_dir(&mut self, cache_dir: ConfigPath) -> Result<(), FileError> {
         self.data.cache_dir = cache_dir;
 
@@ -211,4 +623,86 @@ impl Settings {
 
         Ok(())
     }
+
+    /// The package cache is trimmed to the new limit right away.
+    pub fn set_cache_size_limit(&mut self, limit: u64) -> Result<(), FileError> {
+        self.data.cache_size_limit = limit;
+        crate::cache::trim(&self.package_cache_dir(), limit);
+
+        if self.permission == Permission::ReadWrite {
+            return self.data.save(&self.path);
+        }
+
+        Ok(())
+    }
+
+    pub fn set_cache_policy(&mut self, policy: CachePolicy) -> Result<(), FileError> {
+        self.data.cache_policy = policy;
+
+        if self.permission == Permission::ReadWrite {
+            return self.data.save(&self.path);
+        }
+
+        Ok(())
+    }
+
+    pub fn set_skip_admin_verification(&mut self, value: bool) -> Result<(), FileError> {
+        self.data.skip_admin_verification = value;
+
+        if self.permission == Permission::ReadWrite {
+            return self.data.save(&self.path);
+        }
+
+        Ok(())
+    }
+
+    pub fn set_update_interval(&mut self, secs: u64) -> Result<(), FileError> {
+        self.data.update_interval = secs;
+
+        if self.permission == Permission::ReadWrite {
+            return self.data.save(&self.path);
+        }
+
+        Ok(())
+    }
+
+    pub fn set_auto_update(&mut self, value: bool) -> Result<(), FileError> {
+        self.data.auto_update = value;
+
+        if self.permission == Permission::ReadWrite {
+            return self.data.save(&self.path);
+        }
+
+        Ok(())
+    }
+
+    pub fn set_proxy(&mut self, proxy: Option<Url>) -> Result<(), FileError> {
+        self.data.proxy = proxy;
+
+        if self.permission == Permission::ReadWrite {
+            return self.data.save(&self.path);
+        }
+
+        Ok(())
+    }
+
+    pub fn set_report_url(&mut self, url: Option<Url>) -> Result<(), FileError> {
+        self.data.report_url = url;
+
+        if self.permission == Permission::ReadWrite {
+            return self.data.save(&self.path);
+        }
+
+        Ok(())
+    }
+
+    pub fn set_gateway_port(&mut self, port: Option<u16>) -> Result<(), FileError> {
+        self.data.gateway_port = port;
+
+        if self.permission == Permission::ReadWrite {
+            return self.data.save(&self.path);
+        }
+
+        Ok(())
+    }
 }
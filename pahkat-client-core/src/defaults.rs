@@ -49,6 +49,12 @@ pub fn config_path() -> Result<&'static Path, pathos::Error> {
     return user_dir!(|x| x.config_dir());
 }
 
+/// The machine-wide configuration directory, regardless of the current user.
+#[cfg(not(target_os = "android"))]
+pub fn system_config_path() -> Result<&'static Path, pathos::Error> {
+    sys_dir!(|x| x.config_dir())
+}
+
 #[inline(always)]
 #[cfg(not(target_os = "android"))]
 fn raw_cache_dir() -> Result<&'static Path, pathos::Error> {
@@ -146,6 +152,30 @@ pub fn uninstall_path() -> Result<PathBuf, pathos::Error> {
     Ok(pathos::user::app_data_dir(APP_PATH)?.join("uninstall"))
 }
 
+#[cfg(target_os = "macos")]
+pub(crate) fn user_receipts_path() -> Result<PathBuf, pathos::Error> {
+    Ok(pathos::user::app_data_dir(APP_PATH)?.join("receipts.toml"))
+}
+
+static CLIENT_VERSION: once_cell::sync::OnceCell<semver::Version> =
+    once_cell::sync::OnceCell::new();
+
+/// Sets the version of the application built on this library, such as the daemon
+/// or a GUI, which is what releases requiring a minimum Pahkat version are checked
+/// against. It cannot be changed once set or read.
+pub fn set_client_version(version: &str) -> Result<(), semver::Error> {
+    let version = semver::Version::parse(version)?;
+    if CLIENT_VERSION.set(version).is_err() {
+        log::warn!("Client version is already set to {}", client_version());
+    }
+    Ok(())
+}
+
+/// The version given to [`set_client_version`], or otherwise that of this library.
+pub fn client_version() -> &'static semver::Version {
+    CLIENT_VERSION.get_or_init(|| semver::Version::parse(env!("CARGO_PKG_VERSION")).unwrap())
+}
+
 macro_rules! platform {
     ($name:expr) => {{
         #[cfg(target_os = $name)]
@@ -163,6 +193,13 @@ pub(crate) const fn platform() -> &'static str {
     platform!("ios");
     platform!("android");
     platform!("linux");
+
+    // A browser is not a platform packages are built for, so the query has to
+    // name one. An empty platform matches no targets.
+    #[cfg(target_arch = "wasm32")]
+    {
+        return "";
+    }
 }
 
 macro_rules! arch {
@@ -185,22 +222,229 @@ pub(crate) const fn arch() -> Option<&'static str> {
     arch!("mips64");
     arch!("powerpc");
     arch!("powerpc64");
+
+    #[cfg(target_arch = "wasm32")]
+    {
+        return None;
+    }
+}
+
+/// The architecture of the machine, which differs from [`arch`] when this build runs
+/// under emulation, such as an x86_64 build on Windows ARM64.
+pub(crate) fn native_arch() -> Option<&'static str> {
+    static NATIVE_ARCH: Lazy<Option<&'static str>> = Lazy::new(|| {
+        #[cfg(windows)]
+        {
+            if let Some(arch) = windows_native_arch() {
+                return Some(arch);
+            }
+        }
+
+        #[cfg(target_os = "macos")]
+        {
+            if is_translated() {
+                return Some(pahkat_types::payload::arch::AARCH64);
+            }
+        }
+
+        arch()
+    });
+
+    *NATIVE_ARCH
+}
+
+/// Architectures to fall back to, in order, when a release has no target for the
+/// native architecture.
+pub(crate) fn emulated_arches(platform: &str) -> &'static [&'static str] {
+    use pahkat_types::payload::arch;
+
+    if platform == "macos" && !is_rosetta_available() {
+        return &[];
+    }
+
+    match native_arch() {
+        Some(native) => arch::emulated(platform, native, windows_build()),
+        None => &[],
+    }
+}
+
+/// The build number of the running Windows, such as 22631. Not known elsewhere,
+/// so Windows targets resolved on other platforms only fall back to what every
+/// build can emulate.
+pub(crate) fn windows_build() -> Option<u32> {
+    #[cfg(windows)]
+    {
+        use registry::{Data, Hive, Security};
+
+        static BUILD: Lazy<Option<u32>> = Lazy::new(|| {
+            let key = Hive::LocalMachine
+                .open(
+                    r"SOFTWARE\Microsoft\Windows NT\CurrentVersion",
+                    Security::Read,
+                )
+                .ok()?;
+            match key.value("CurrentBuildNumber") {
+                Ok(Data::String(v)) => v.to_string_lossy().trim().parse().ok(),
+                _ => None,
+            }
+        });
+        return *BUILD;
+    }
+
+    #[allow(unreachable_code)]
+    None
+}
+
+/// Whether x86_64 code can run, natively or through Rosetta.
+pub(crate) fn is_rosetta_available() -> bool {
+    #[cfg(target_os = "macos")]
+    {
+        static IS_AVAILABLE: Lazy<bool> = Lazy::new(|| {
+            native_arch() != Some(pahkat_types::payload::arch::AARCH64)
+                || Path::new("/Library/Apple/usr/share/rosetta/rosetta").exists()
+        });
+        return *IS_AVAILABLE;
+    }
+
+    #[allow(unreachable_code)]
+    false
+}
+
+/// Whether this x86_64 build is itself running under Rosetta.
+#[cfg(target_os = "macos")]
+fn is_translated() -> bool {
+    let name = std::ffi::CString::new("sysctl.proc_translated").unwrap();
+    let mut value: libc::c_int = 0;
+    let mut size = std::mem::size_of::<libc::c_int>();
+    let result = unsafe {
+        libc::sysctlbyname(
+            name.as_ptr(),
+            &mut value as *mut _ as *mut libc::c_void,
+            &mut size,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    result == 0 && value == 1
+}
+
+#[cfg(windows)]
+fn windows_native_arch() -> Option<&'static str> {
+    use pahkat_types::payload::arch;
+    use std::ffi::CString;
+    use winapi::shared::minwindef::BOOL;
+    use winapi::um::libloaderapi::{GetModuleHandleA, GetProcAddress};
+    use winapi::um::processthreadsapi::GetCurrentProcess;
+    use winapi::um::winnt::{
+        HANDLE, IMAGE_FILE_MACHINE_AMD64, IMAGE_FILE_MACHINE_ARM64, IMAGE_FILE_MACHINE_I386,
+    };
+
+    type IsWow64Process2 = unsafe extern "system" fn(HANDLE, *mut u16, *mut u16) -> BOOL;
+
+    // Only exists from Windows 10 1709, so it is looked up instead of linked against
+    let kernel32 = CString::new("kernel32.dll").unwrap();
+    let name = CString::new("IsWow64Process2").unwrap();
+    let native = unsafe {
+        let module = GetModuleHandleA(kernel32.as_ptr());
+        if module.is_null() {
+            return None;
+        }
+        let f = GetProcAddress(module, name.as_ptr());
+        if f.is_null() {
+            return None;
+        }
+        let f: IsWow64Process2 = std::mem::transmute(f);
+
+        let mut process = 0;
+        let mut native = 0;
+        if f(GetCurrentProcess(), &mut process, &mut native) == 0 {
+            return None;
+        }
+        native
+    };
+
+    match native {
+        IMAGE_FILE_MACHINE_ARM64 => Some(arch::AARCH64),
+        IMAGE_FILE_MACHINE_AMD64 => Some(arch::X86_64),
+        IMAGE_FILE_MACHINE_I386 => Some(arch::X86),
+        _ => None,
+    }
+}
+
+static MACHINE_ID: Lazy<String> = Lazy::new(|| {
+    #[cfg(windows)]
+    {
+        use registry::{Data, Hive, Security};
+
+        let guid = Hive::LocalMachine
+            .open(
+                r"SOFTWARE\Microsoft\Cryptography",
+                Security::Read | Security::Wow6464Key,
+            )
+            .ok()
+            .and_then(|key| match key.value("MachineGuid") {
+                Ok(Data::String(v)) => Some(v.to_string_lossy()),
+                _ => None,
+            });
+        if let Some(guid) = guid {
+            return guid;
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        for path in &["/etc/machine-id", "/var/lib/dbus/machine-id"] {
+            if let Ok(id) = std::fs::read_to_string(path) {
+                let id = id.trim();
+                if !id.is_empty() {
+                    return id.to_string();
+                }
+            }
+        }
+    }
+
+    whoami::devicename()
+});
+
+/// A stable identifier for this machine, used for bucketing staged rollouts.
+pub(crate) fn machine_id() -> &'static str {
+    &MACHINE_ID
 }
 
 #[inline(always)]
 pub(crate) fn payloads() -> &'static [&'static str] {
     #[cfg(all(feature = "windows", not(feature = "macos"), not(feature = "prefix")))]
     {
-        &["WindowsExecutable"]
+        &["WindowsExecutable", "WindowsMsix"]
     }
     #[cfg(all(not(feature = "windows"), feature = "macos", not(feature = "prefix")))]
     {
-        &["MacOSPackage"]
+        &["MacOSPackage", "MacOSAppBundle"]
     }
-    #[cfg(all(not(feature = "windows"), not(feature = "macos"), feature = "prefix"))]
+    #[cfg(all(
+        not(feature = "windows"),
+        not(feature = "macos"),
+        feature = "prefix",
+        not(all(target_os = "linux", feature = "linux"))
+    ))]
     {
         &["TarballPackage"]
     }
+    #[cfg(all(
+        not(feature = "windows"),
+        not(feature = "macos"),
+        target_os = "linux",
+        feature = "linux"
+    ))]
+    {
+        &[
+            "DebianPackage",
+            "RpmPackage",
+            "Flatpak",
+            "Snap",
+            "TarballPackage",
+        ]
+    }
 
     #[cfg(all(
         not(feature = "windows"),
@@ -0,0 +1,275 @@
+//! Building a payload from the payload of an earlier release and a patch.
+//!
+//! A delta is only used when it patches the installed release, the payload of that
+//! release is still in the download cache and the index gives the hash of the result.
+//! Anything else falls back to downloading the full payload.
+
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use pahkat_types::package::Descriptor;
+use pahkat_types::payload::delta::{Delta, DeltaFormat};
+use pahkat_types::payload::Target;
+use pahkat_types::PackageKey;
+use sha2::digest::Digest;
+use sha2::Sha256;
+
+use crate::package_store::InstallTarget;
+use crate::Config;
+
+#[derive(Debug, thiserror::Error)]
+pub enum DeltaError {
+    #[error("Could not read or write the patched payload")]
+    Io(#[from] std::io::Error),
+
+    #[error("Patched payload is {actual} bytes, expected {expected}")]
+    SizeMismatch { expected: u64, actual: u64 },
+
+    #[error("Patched payload is larger than the expected {0} bytes")]
+    TooLarge(u64),
+
+    #[error("Patched payload has SHA-256 {actual}, expected {expected}")]
+    HashMismatch { expected: String, actual: String },
+
+    #[error("Unsupported delta format")]
+    UnsupportedFormat,
+}
+
+/// The first delta of `target` from an installed release whose payload is in the
+/// cache, with the path of that payload.
+pub(crate) fn find(
+    config: &Config,
+    key: &PackageKey,
+    descriptor: &Descriptor,
+    target: &Target,
+) -> Option<(Delta, PathBuf)> {
+    use pahkat_types::AsDownloadUrl;
+
+    let installed = [InstallTarget::System, InstallTarget::User]
+        .iter()
+        .filter_map(|x| crate::repo::installed::load(config, key, *x))
+        .map(|x| x.release.version.to_string())
+        .collect::<Vec<_>>();
+
+    target.payload.deltas().iter().find_map(|delta| {
+        if delta.payload_sha256.is_none() || !installed.contains(&delta.from_version) {
+            return None;
+        }
+
+        let base = descriptor
+            .release
+            .iter()
+            .filter(|x| x.version.to_string() == delta.from_version)
+            .flat_map(|x| x.target.iter())
+            .find(|x| x.platform == target.platform && x.arch == target.arch)?;
+
+        if !crate::repo::is_payload_cached(config, &base.payload) {
+            return None;
+        }
+
+        let path = crate::repo::download_file_path(config, base.payload.as_download_url());
+        Some((delta.clone(), path))
+    })
+}
+
+/// Patches `base` with the file at `patch`, writing the result to `output` only if
+/// it has the size and hash of the full payload. The result is streamed to disk and
+/// patching stops as soon as it grows past `expected_size`.
+pub(crate) fn apply(
+    format: DeltaFormat,
+    base: &Path,
+    patch: &Path,
+    output: &Path,
+    expected_size: u64,
+    expected_sha256: &str,
+) -> Result<(), DeltaError> {
+    let base = std::fs::read(base)?;
+    let mut patch = io::BufReader::new(File::open(patch)?);
+
+    if let Some(parent) = output.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut tmp_path = output.as_os_str().to_owned();
+    tmp_path.push(".part");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let result = File::create(&tmp_path)
+        .map_err(DeltaError::from)
+        .and_then(|file| {
+            let mut patched = Patched {
+                file: BufWriter::new(file),
+                sha256: Sha256::new(),
+                len: 0,
+                limit: expected_size,
+            };
+            match format {
+                DeltaFormat::Bsdiff => bspatch(&base, &mut patch, &mut patched)?,
+                _ => return Err(DeltaError::UnsupportedFormat),
+            }
+            patched.file.flush()?;
+            patched.verify(expected_sha256)
+        })
+        .and_then(|_| Ok(std::fs::rename(&tmp_path, output)?));
+
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// The payload being written, hashed as it goes.
+struct Patched {
+    file: BufWriter<File>,
+    sha256: Sha256,
+    len: u64,
+    limit: u64,
+}
+
+impl Patched {
+    fn write(&mut self, buf: &[u8]) -> Result<(), DeltaError> {
+        self.len += buf.len() as u64;
+        if self.len > self.limit {
+            return Err(DeltaError::TooLarge(self.limit));
+        }
+        self.sha256.update(buf);
+        self.file.write_all(buf)?;
+        Ok(())
+    }
+
+    fn verify(self, expected_sha256: &str) -> Result<(), DeltaError> {
+        if self.len != self.limit {
+            return Err(DeltaError::SizeMismatch {
+                expected: self.limit,
+                actual: self.len,
+            });
+        }
+
+        let actual = format!("{:x}", self.sha256.finalize());
+        if !actual.eq_ignore_ascii_case(expected_sha256) {
+            return Err(DeltaError::HashMismatch {
+                expected: expected_sha256.to_string(),
+                actual,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Applies a patch as written by `bsdiff::diff`: blocks of a 24-byte header, bytes
+/// added to the old data, then bytes copied as is, after which the position in the
+/// old data moves by a signed offset.
+fn bspatch(old: &[u8], patch: &mut impl Read, output: &mut Patched) -> Result<(), DeltaError> {
+    let invalid = || DeltaError::Io(io::ErrorKind::InvalidData.into());
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut old_pos = 0usize;
+
+    loop {
+        let mut header = [0u8; 24];
+        match patch.read(&mut header[..1])? {
+            0 => return Ok(()),
+            _ => patch.read_exact(&mut header[1..])?,
+        }
+        let mix_len = u64::from_le_bytes(header[0..8].try_into().unwrap());
+        let copy_len = u64::from_le_bytes(header[8..16].try_into().unwrap());
+        let seek = u64::from_le_bytes(header[16..24].try_into().unwrap());
+
+        let mut remaining = mix_len;
+        while remaining > 0 {
+            let n = remaining.min(buf.len() as u64) as usize;
+            patch.read_exact(&mut buf[..n])?;
+            let old = old
+                .get(old_pos..)
+                .and_then(|x| x.get(..n))
+                .ok_or_else(invalid)?;
+            for (new, old) in buf[..n].iter_mut().zip(old) {
+                *new = new.wrapping_add(*old);
+            }
+            output.write(&buf[..n])?;
+            old_pos += n;
+            remaining -= n as u64;
+        }
+
+        let mut remaining = copy_len;
+        while remaining > 0 {
+            let n = remaining.min(buf.len() as u64) as usize;
+            patch.read_exact(&mut buf[..n])?;
+            output.write(&buf[..n])?;
+            remaining -= n as u64;
+        }
+
+        // The offset is stored as sign and magnitude
+        let magnitude = (seek & !(1 << 63)) as usize;
+        old_pos = if seek & (1 << 63) == 0 {
+            old_pos.checked_add(magnitude)
+        } else {
+            old_pos.checked_sub(magnitude)
+        }
+        .ok_or_else(invalid)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applies_bsdiff_patch() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = b"speller data, version one".repeat(100);
+        let new = b"speller data, version two".repeat(100);
+
+        let mut patch = vec![];
+        bsdiff::diff(&old, &new, &mut patch).unwrap();
+        std::fs::write(dir.path().join("old"), &old).unwrap();
+        std::fs::write(dir.path().join("patch"), &patch).unwrap();
+
+        let sha256 = format!("{:x}", Sha256::digest(&new));
+        let output = dir.path().join("out").join("new");
+        apply(
+            DeltaFormat::Bsdiff,
+            &dir.path().join("old"),
+            &dir.path().join("patch"),
+            &output,
+            new.len() as u64,
+            &sha256,
+        )
+        .unwrap();
+        assert_eq!(std::fs::read(&output).unwrap(), new);
+
+        let result = apply(
+            DeltaFormat::Bsdiff,
+            &dir.path().join("old"),
+            &dir.path().join("patch"),
+            &dir.path().join("wrong"),
+            1,
+            &sha256,
+        );
+        assert!(matches!(result, Err(DeltaError::TooLarge(1))));
+        assert!(!dir.path().join("wrong").exists());
+        assert!(!dir.path().join("wrong.part").exists());
+
+        let result = apply(
+            DeltaFormat::Bsdiff,
+            &dir.path().join("old"),
+            &dir.path().join("patch"),
+            &dir.path().join("wrong"),
+            new.len() as u64 + 1,
+            &sha256,
+        );
+        assert!(matches!(result, Err(DeltaError::SizeMismatch { .. })));
+        assert!(!dir.path().join("wrong").exists());
+
+        let result = apply(
+            DeltaFormat::Bsdiff,
+            &dir.path().join("old"),
+            &dir.path().join("patch"),
+            &dir.path().join("wrong"),
+            new.len() as u64,
+            &format!("{:x}", Sha256::digest(&old)),
+        );
+        assert!(matches!(result, Err(DeltaError::HashMismatch { .. })));
+        assert!(!dir.path().join("wrong").exists());
+    }
+}
@@ -0,0 +1,105 @@
+//! Packages that administrators require to be installed or absent on a machine.
+//!
+//! The desired state is read from `desired-state.toml` in the system configuration
+//! directory, so it cannot be changed by the users of the machine:
+//!
+//! ```toml
+//! installed = ["https://pahkat.example/main/packages/speller-sme?channel=beta"]
+//! absent = ["https://pahkat.example/main/packages/legacy-keyboard"]
+//! ```
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::FileError;
+use crate::package_store::{InstallTarget, PackageStore};
+use crate::transaction::{PackageAction, PackageStatus};
+use crate::PackageKey;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DesiredState {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub installed: Vec<PackageKey>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub absent: Vec<PackageKey>,
+}
+
+/// A difference between the desired state and what is installed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Drift {
+    Missing(PackageKey),
+    Outdated(PackageKey),
+    Present(PackageKey),
+}
+
+impl Drift {
+    pub fn key(&self) -> &PackageKey {
+        match self {
+            Drift::Missing(key) | Drift::Outdated(key) | Drift::Present(key) => key,
+        }
+    }
+
+    /// The action that corrects this drift.
+    pub fn action(&self, target: InstallTarget) -> PackageAction {
+        match self {
+            Drift::Missing(key) | Drift::Outdated(key) => {
+                PackageAction::install(key.clone(), target)
+            }
+            Drift::Present(key) => PackageAction::uninstall(key.clone(), target),
+        }
+    }
+}
+
+impl DesiredState {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<DesiredState, FileError> {
+        let file = std::fs::read_to_string(&path)
+            .map_err(|e| FileError::Read(e, path.as_ref().to_path_buf()))?;
+        let file = toml::from_str(&file)
+            .map_err(|e| FileError::FromToml(e, path.as_ref().to_path_buf()))?;
+        Ok(file)
+    }
+
+    /// Loads the desired state from the system configuration directory, if there is one.
+    #[cfg(not(target_os = "android"))]
+    pub fn load_system() -> Result<Option<DesiredState>, crate::config::Error> {
+        let path = crate::defaults::system_config_path()?.join("desired-state.toml");
+        if !path.exists() {
+            return Ok(None);
+        }
+        DesiredState::load(path)
+            .map(Some)
+            .map_err(crate::config::Error::DesiredStateFile)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.installed.is_empty() && self.absent.is_empty()
+    }
+
+    /// Compares the desired state against the store. Packages whose status
+    /// cannot be determined, such as those missing from every repository, are skipped.
+    pub fn drift(&self, store: &dyn PackageStore, target: InstallTarget) -> Vec<Drift> {
+        let mut drift = vec![];
+
+        for key in self.installed.iter() {
+            match store.status(key, target) {
+                Ok(PackageStatus::NotInstalled) => drift.push(Drift::Missing(key.clone())),
+                Ok(PackageStatus::RequiresUpdate) => drift.push(Drift::Outdated(key.clone())),
+                Ok(PackageStatus::UpToDate) | Ok(PackageStatus::Unpublished) => {}
+                Err(e) => log::warn!("Could not get status of desired package {}: {}", key, e),
+            }
+        }
+
+        for key in self.absent.iter() {
+            match store.status(key, target) {
+                Ok(PackageStatus::UpToDate)
+                | Ok(PackageStatus::RequiresUpdate)
+                | Ok(PackageStatus::Unpublished) => drift.push(Drift::Present(key.clone())),
+                Ok(PackageStatus::NotInstalled) => {}
+                Err(e) => log::warn!("Could not get status of absent package {}: {}", key, e),
+            }
+        }
+
+        drift
+    }
+}
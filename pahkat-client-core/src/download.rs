@@ -3,11 +3,12 @@ use std::io::{BufWriter, Write};
 use std::path::{Path, PathBuf};
 use std::thread::JoinHandle;
 
-use reqwest::header;
+use reqwest::{header, StatusCode};
 use url::Url;
 
+use crate::config::{ClientCertificate, ProgressRate};
 use crate::ext::PathExt;
-use crate::package_store::DownloadEvent;
+use crate::package_store::{DownloadEvent, DownloadProgress};
 
 pub trait Download {
     fn download<F>(
@@ -23,27 +24,83 @@ pub trait Download {
 pub(crate) struct DownloadManager {
     client: reqwest::Client,
     path: PathBuf,
+    progress: ProgressRate,
+    auth_token: Option<(url::Origin, String)>,
     // max_concurrent_downloads: u8,
 }
 
+/// Coalesces per-chunk progress into events at the configured rate.
+struct Throttle {
+    rate: ProgressRate,
+    last_sent: Option<std::time::Instant>,
+    last_percent: Option<u8>,
+}
+
+impl Throttle {
+    fn new(rate: ProgressRate) -> Throttle {
+        Throttle {
+            rate,
+            last_sent: None,
+            last_percent: None,
+        }
+    }
+
+    fn next(&mut self, current: u64, total: u64) -> Option<DownloadProgress> {
+        let progress = DownloadProgress::new(current, total);
+        let is_final = total > 0 && current >= total;
+
+        if !is_final {
+            if let Some(last_sent) = self.last_sent {
+                if last_sent.elapsed() < self.rate.interval() {
+                    return None;
+                }
+            }
+
+            if let (Some(percent), Some(last_percent)) = (progress.percent, self.last_percent) {
+                if percent < last_percent.saturating_add(self.rate.percent_step) {
+                    return None;
+                }
+            }
+        }
+
+        self.last_sent = Some(std::time::Instant::now());
+        self.last_percent = progress.percent;
+        Some(progress)
+    }
+}
+
 // type Stream<T> = Pin<
 //     Box<dyn futures::Stream<Item = std::result::Result<T, Status>> + Send + Sync + 'static>,
 // >;
 
 impl DownloadManager {
-    pub fn new(path: PathBuf, _max_concurrent_downloads: u8) -> DownloadManager {
-        let client = Self::client();
-
-        DownloadManager {
+    pub fn new(
+        path: PathBuf,
+        _max_concurrent_downloads: u8,
+        progress: ProgressRate,
+        proxy: Option<&Url>,
+        client_certificate: Option<&ClientCertificate>,
+    ) -> Result<DownloadManager, DownloadError> {
+        let builder =
+            crate::tls::apply_client_certificate(crate::tls::client_builder(), client_certificate)?;
+        let client = crate::proxy::apply(builder, proxy)
+            .and_then(|x| x.build())
+            .map_err(DownloadError::Proxy)?;
+
+        Ok(DownloadManager {
             client,
             path,
+            progress,
+            auth_token: None,
             // max_concurrent_downloads,
-        }
+        })
     }
 
-    #[inline]
-    fn client() -> reqwest::Client {
-        reqwest::Client::builder().build().unwrap()
+    /// Sends `token` with requests to `origin` only, as payloads of a private repository
+    /// may still be hosted elsewhere.
+    pub fn with_auth_token(mut self, origin: url::Origin, token: String) -> DownloadManager {
+        self.auth_token = Some((origin, token));
+        self
     }
 
     pub async fn download<P: AsRef<Path>>(
@@ -99,46 +156,43 @@ impl DownloadManager {
 
         let tmp_dest_path = cache_dir.join(filename);
 
-        // let mut file = fs::OpenOptions::new()
-        //     .append(true)
-        //     .write(true)
-        //     .open(&tmp_dest_path)
-        //     .or_else(|_| )
-        let file = fs::File::create(&tmp_dest_path).map_err(|e| {
-            log::error!("Open temp file failed: {:?}", &e);
-            DownloadError::TempFileOpenFailed(e, tmp_dest_path.to_path_buf())
-        })?;
-        // let meta = file.metadata().map_err(|e| {
-        //     log::error!("metadata error: {:?}", &e);
-        //     DownloadError::MetadataFailed(e, tmp_dest_path.to_path_buf())
-        // })?;
-
-        // let mut downloaded_bytes = meta.len();
-        // log::debug!("Downloaded bytes: {}", downloaded_bytes);
-
-        let client = &self.client;
-        let req = client.get(url.as_str());
-        // if downloaded_bytes > 0 {
-        //     req = req.header(header::RANGE, format!("bytes={}-", downloaded_bytes));
-        // }
+        // Payloads of a local repository are copied instead of requested
+        if url.scheme() == "file" {
+            let source = url.to_file_path().map_err(|_| DownloadError::InvalidUrl)?;
+            let len = {
+                let (source, tmp_dest_path) = (source.clone(), tmp_dest_path.clone());
+                tokio::task::spawn_blocking(move || fs::copy(&source, &tmp_dest_path))
+                    .await
+                    .unwrap()
+            }
+            .map_err(|e| DownloadError::CopyFailed(e, source, tmp_dest_path.clone()))?;
 
-        let req = req
-            .build()
-            .map_err(|e| DownloadError::ReqwestError(e, url.as_str().to_string()))?;
+            return Ok(Box::pin(async_stream::stream! {
+                yield DownloadEvent::Progress(DownloadProgress::new(len, len));
 
-        // Get URL headers
-        let (tx, rx) = tokio::sync::oneshot::channel();
-        tokio::spawn(async move {
-            let response = Self::client()
-                .execute(req)
-                .await
-                .and_then(|x| x.error_for_status());
-            tx.send(response).unwrap();
-        });
-        let mut res = rx
-            .await
-            .unwrap()
-            .map_err(|e| DownloadError::ReqwestError(e, url.as_str().to_string()))?;
+                let _ = fs::create_dir_all(&dest_path);
+                match persist(&tmp_dest_path, &dest_file_path, len) {
+                    Ok(_) => yield DownloadEvent::Complete(dest_file_path),
+                    Err(e) => yield DownloadEvent::Error(e),
+                }
+            }));
+        }
+
+        // A previous attempt may have left a partial file behind; ask for the rest of it,
+        // unless the file changed on the server since
+        let validator_path = validator_path(&tmp_dest_path);
+        let partial_len = fs::metadata(&tmp_dest_path).map(|x| x.len()).unwrap_or(0);
+        let resume = match fs::read_to_string(&validator_path) {
+            Ok(v) if partial_len > 0 => Some((partial_len, v)),
+            _ => None,
+        };
+        let mut res = self
+            .request(url, resume.as_ref().map(|(len, v)| (*len, v.as_str())))
+            .await?;
+        if resume.is_some() && res.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            log::debug!("Partial download no longer matches; restarting");
+            res = self.request(url, None).await?;
+        }
 
         // Get content length and send if exists
         let content_len = res
@@ -148,37 +202,55 @@ impl DownloadManager {
             .unwrap_or(0u64);
         log::debug!("Content length: {}", content_len);
 
-        // Check if range request was accepted!
-        // let is_partial = res.headers().get(header::CONTENT_RANGE).is_some();
-        // log::debug!("Is partial: {}", is_partial);
-
-        // let total_bytes = if !is_partial {
-        //     std::fs::remove_file(&tmp_dest_path).map_err(|e| {
-        //         log::error!("error removing temp file: {:?}", &e);
-        //         DownloadError::TempFileDeleteFailed(e, tmp_dest_path.to_path_buf())
-        //     })?;
-        //     file = fs::File::create(&tmp_dest_path)
-        //         .map_err(|e| {
-        //             log::error!("{:?}", &e);
-        //             DownloadError::TempFileOpenFailed(e, tmp_dest_path.to_path_buf())
-        //         })?;
-        //     content_len
-        // } else if content_len > 0 {
-        //     content_len + downloaded_bytes
-        // } else {
-        //     // If no content len, having downloaded bytes doesn't mean we have a known total...
-        //     0
-        // };
-
-        // log::debug!("Total bytes: {}", total_bytes);
-
-        let total_bytes = content_len;
-        let mut downloaded_bytes = 0;
-        let mut last_progress_event = std::time::Instant::now();
+        // Servers that ignore the range, or whose file changed, send the whole file again
+        let mut downloaded_bytes = match resume {
+            Some((len, _)) if res.status() == StatusCode::PARTIAL_CONTENT => len,
+            _ => 0,
+        };
+        let is_partial = downloaded_bytes > 0;
+        log::debug!("Is partial: {}", is_partial);
+
+        let file = if is_partial {
+            log::debug!("Resuming download at {} bytes", downloaded_bytes);
+            fs::OpenOptions::new().append(true).open(&tmp_dest_path)
+        } else {
+            // Kept for the next attempt, should this one be interrupted
+            match validator(res.headers()) {
+                Some(v) => {
+                    if let Err(e) = fs::write(&validator_path, v) {
+                        log::warn!("Could not keep validator of {}: {}", url, e);
+                    }
+                }
+                None => {
+                    let _ = fs::remove_file(&validator_path);
+                }
+            }
+            fs::File::create(&tmp_dest_path)
+        }
+        .map_err(|e| {
+            log::error!("Open temp file failed: {:?}", &e);
+            DownloadError::TempFileOpenFailed(e, tmp_dest_path.to_path_buf())
+        })?;
+
+        let total_bytes = total_len(
+            res.headers()
+                .get(header::CONTENT_RANGE)
+                .and_then(|x| x.to_str().ok()),
+            content_len,
+            downloaded_bytes,
+        );
+        log::debug!("Total bytes: {}", total_bytes);
+
+        let mut throttle = Throttle::new(self.progress);
 
         let url = url.to_owned();
         let stream = async_stream::stream! {
             let mut file = BufWriter::new(file);
+            if downloaded_bytes > 0 {
+                if let Some(progress) = throttle.next(downloaded_bytes, total_bytes) {
+                    yield DownloadEvent::Progress(progress);
+                }
+            }
             loop {
                 let chunk = res.chunk().await.map_err(|e| DownloadError::ReqwestError(e, url.as_str().to_string()));
                 match chunk {
@@ -194,50 +266,170 @@ impl DownloadManager {
                             });
                             match result {
                                 Ok(_) => {
-                                    // Send a progress event at most every 750ms
-                                    if downloaded_bytes == total_bytes || last_progress_event.elapsed().as_millis() >= 750 {
-                                        last_progress_event = std::time::Instant::now();
-                                        yield DownloadEvent::Progress((downloaded_bytes, total_bytes));
+                                    if let Some(progress) = throttle.next(downloaded_bytes, total_bytes) {
+                                        yield DownloadEvent::Progress(progress);
                                     }
                                 },
                                 Err(e) => {
                                     yield DownloadEvent::Error(e);
-                                    break;
+                                    return;
                                 }
                             }
                         }
                     }
                     Err(e) => {
                         yield DownloadEvent::Error(e);
-                        break;
+                        return;
                     }
                 }
             }
 
-            match file.flush() {
-                Err(e) => yield DownloadEvent::Error(DownloadError::FlushFailed(e, tmp_dest_path.to_path_buf())),
-                _ => {}
-            };
+            if total_bytes > 0 && downloaded_bytes != total_bytes {
+                yield DownloadEvent::Error(DownloadError::Incomplete(downloaded_bytes, total_bytes));
+                return;
+            }
 
-            drop(file);
+            // Make sure everything has hit the disk before the file is handed on
+            let result = file
+                .into_inner()
+                .map_err(|e| e.into_error())
+                .and_then(|file| file.sync_all());
+            if let Err(e) = result {
+                yield DownloadEvent::Error(DownloadError::FlushFailed(e, tmp_dest_path.to_path_buf()));
+                return;
+            }
 
             log::debug!("Moving {:?} to {:?}", &tmp_dest_path, &dest_path);
 
-            // If it's done, move the file!
-            let _ = fs::create_dir_all(dest_path);
-            match fs::copy(&tmp_dest_path, &dest_file_path) {
-                Err(e) => yield DownloadEvent::Error(DownloadError::CopyFailed(e, tmp_dest_path.to_path_buf(), dest_file_path.to_path_buf())),
-                _ => {}
-            };
-            match fs::remove_file(&tmp_dest_path) {
-                Err(e) => yield DownloadEvent::Error(DownloadError::RemoveFailed(e, tmp_dest_path.to_path_buf())),
-                _ => {}
-            };
+            let _ = fs::create_dir_all(&dest_path);
+            if let Err(e) = persist(&tmp_dest_path, &dest_file_path, downloaded_bytes) {
+                yield DownloadEvent::Error(e);
+                return;
+            }
+            let _ = fs::remove_file(&validator_path);
+
             yield DownloadEvent::Complete(dest_file_path);
         };
 
         Ok(Box::pin(stream))
     }
+
+    /// Requests `url` from byte `offset` onwards if `resume` is given, as long as the file
+    /// still matches its validator. A server that can't satisfy the range is not treated
+    /// as an error, so the caller can start over.
+    async fn request(
+        &self,
+        url: &Url,
+        resume: Option<(u64, &str)>,
+    ) -> Result<reqwest::Response, DownloadError> {
+        let mut req = self.client.get(url.as_str());
+        if let Some((origin, token)) = self.auth_token.as_ref() {
+            if url.origin() == *origin {
+                req = req.bearer_auth(token);
+            }
+        }
+        if let Some((offset, validator)) = resume {
+            req = req
+                .header(header::RANGE, format!("bytes={}-", offset))
+                .header(header::IF_RANGE, validator);
+        }
+
+        let req = req
+            .build()
+            .map_err(|e| DownloadError::ReqwestError(e, url.as_str().to_string()))?;
+
+        // Get URL headers
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let client = self.client.clone();
+        tokio::spawn(async move {
+            let response = client.execute(req).await;
+            tx.send(response).unwrap();
+        });
+        let res = rx
+            .await
+            .unwrap()
+            .map_err(|e| DownloadError::ReqwestError(e, url.as_str().to_string()))?;
+
+        if resume.is_some() && res.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            return Ok(res);
+        }
+
+        res.error_for_status()
+            .map_err(|e| DownloadError::ReqwestError(e, url.as_str().to_string()))
+    }
+}
+
+/// Where the validator of the partial download at `path` is kept.
+fn validator_path(path: &Path) -> PathBuf {
+    path.with_file_name(format!(
+        "{}.validator",
+        path.file_name().unwrap_or_default().to_string_lossy()
+    ))
+}
+
+/// The validator to resume a download with in `If-Range`: a strong `ETag`, or else
+/// `Last-Modified`. Weak entity tags cannot be used for ranges.
+fn validator(headers: &header::HeaderMap) -> Option<String> {
+    let get = |name| headers.get(name).and_then(|x| x.to_str().ok());
+    get(header::ETAG)
+        .filter(|x| !x.starts_with("W/"))
+        .or_else(|| get(header::LAST_MODIFIED))
+        .map(str::to_string)
+}
+
+/// The full size of a download resumed at `offset`, preferring the total given in a
+/// `Content-Range` of the form `bytes 100-999/1000`. Zero if unknown.
+fn total_len(content_range: Option<&str>, content_len: u64, offset: u64) -> u64 {
+    let from_range = content_range
+        .and_then(|x| x.rsplit('/').next())
+        .and_then(|x| x.trim().parse::<u64>().ok());
+
+    match from_range {
+        Some(total) => total,
+        None if content_len > 0 => content_len + offset,
+        None => 0,
+    }
+}
+
+/// Moves a fully written file into place so that readers only ever see the
+/// complete file. Falls back to copying to a temporary sibling first when the
+/// paths are on different filesystems.
+pub(crate) fn persist(from: &Path, to: &Path, expected_len: u64) -> Result<(), DownloadError> {
+    if fs::rename(from, to).is_err() {
+        let part_path = to.with_file_name(format!(
+            "{}.part",
+            to.file_name().unwrap_or_default().to_string_lossy()
+        ));
+
+        fs::copy(from, &part_path)
+            .and_then(|_| {
+                fs::OpenOptions::new()
+                    .write(true)
+                    .open(&part_path)?
+                    .sync_all()
+            })
+            .map_err(|e| DownloadError::CopyFailed(e, from.to_path_buf(), part_path.clone()))?;
+        fs::rename(&part_path, to)
+            .map_err(|e| DownloadError::CopyFailed(e, part_path.clone(), to.to_path_buf()))?;
+        fs::remove_file(from).map_err(|e| DownloadError::RemoveFailed(e, from.to_path_buf()))?;
+    }
+
+    // Persist the rename itself
+    #[cfg(unix)]
+    {
+        if let Some(parent) = to.parent() {
+            let _ = fs::File::open(parent).and_then(|x| x.sync_all());
+        }
+    }
+
+    let len = fs::metadata(to)
+        .map_err(|e| DownloadError::MetadataFailed(e, to.to_path_buf()))?
+        .len();
+    if len != expected_len {
+        return Err(DownloadError::Incomplete(len, expected_len));
+    }
+
+    Ok(())
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -257,6 +449,12 @@ pub enum DownloadError {
     #[error("An internal error occurred while attempting to download: {1}")]
     ReqwestError(#[source] reqwest::Error, String),
 
+    #[error("Could not use the configured proxy")]
+    Proxy(#[source] reqwest::Error),
+
+    #[error("Could not load the repository's client certificate")]
+    ClientCertificate(#[from] crate::tls::ClientCertificateError),
+
     #[error("Failed to get metadata for file at path: {}", .1.display())]
     MetadataFailed(#[source] std::io::Error, PathBuf),
 
@@ -280,4 +478,74 @@ pub enum DownloadError {
 
     #[error("Could not write data to file at path: {}", .1.display())]
     WriteFailed(#[source] std::io::Error, PathBuf),
+
+    #[error("Download incomplete: got {0} of {1} bytes")]
+    Incomplete(u64, u64),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn throttle_coalesces_by_percent() {
+        let mut throttle = Throttle::new(ProgressRate {
+            interval_ms: 0,
+            percent_step: 10,
+        });
+
+        let sent = (0..=1000u64)
+            .filter_map(|x| throttle.next(x, 1000))
+            .collect::<Vec<_>>();
+
+        assert_eq!(sent.len(), 11);
+        assert_eq!(sent[1].percent, Some(10));
+        assert_eq!(sent.last(), Some(&DownloadProgress::new(1000, 1000)));
+    }
+
+    #[test]
+    fn throttle_always_sends_final_event() {
+        let mut throttle = Throttle::new(ProgressRate {
+            interval_ms: 60_000,
+            percent_step: 1,
+        });
+
+        assert!(throttle.next(1, 100).is_some());
+        assert!(throttle.next(50, 100).is_none());
+        assert_eq!(throttle.next(100, 100).and_then(|x| x.percent), Some(100));
+    }
+
+    #[test]
+    fn total_len_of_resumed_download() {
+        assert_eq!(total_len(Some("bytes 100-999/1000"), 900, 100), 1000);
+        assert_eq!(total_len(Some("bytes 100-999/*"), 900, 100), 1000);
+        assert_eq!(total_len(None, 900, 100), 1000);
+        assert_eq!(total_len(None, 0, 100), 0);
+    }
+
+    #[test]
+    fn validator_prefers_strong_etag() {
+        let last_modified = "Wed, 21 Oct 2015 07:28:00 GMT";
+        let headers = |etag: Option<&'static str>, is_modified: bool| {
+            let mut headers = header::HeaderMap::new();
+            if let Some(etag) = etag {
+                headers.insert(header::ETAG, etag.parse().unwrap());
+            }
+            if is_modified {
+                headers.insert(header::LAST_MODIFIED, last_modified.parse().unwrap());
+            }
+            headers
+        };
+
+        assert_eq!(
+            validator(&headers(Some("\"abc\""), true)).as_deref(),
+            Some("\"abc\"")
+        );
+        assert_eq!(
+            validator(&headers(Some("W/\"abc\""), true)).as_deref(),
+            Some(last_modified)
+        );
+        assert_eq!(validator(&headers(Some("W/\"abc\""), false)), None);
+        assert_eq!(validator(&headers(None, false)), None);
+    }
 }
@@ -0,0 +1,371 @@
+//! Using pahkat from an application without running pahkat-rpc.
+//!
+//! [`EmbeddedPahkat`] opens the package store of the platform, keeps its
+//! repositories refreshed in the background and runs transactions, reporting
+//! what happens through callbacks instead of streams. Like the daemon, it never
+//! refreshes repositories while a transaction is running.
+//!
+//! Background work is spawned onto the Tokio runtime the methods are called
+//! from, and stops when the `EmbeddedPahkat` is dropped.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use futures::stream::StreamExt;
+use hashbrown::HashMap;
+use pahkat_types::repo::RepoUrl;
+use tokio::sync::{broadcast, Mutex};
+use tokio::task::JoinHandle;
+
+use crate::events::StoreEvent;
+use crate::repo::{PackageCandidateError, RepoDownloadError};
+use crate::transaction::observer::ExecObserver;
+use crate::transaction::TransactionEvent;
+use crate::{Config, DownloadEvent, PackageAction, PackageKey, PackageStore, PackageTransaction};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Error loading config")]
+    Config(#[from] crate::config::Error),
+
+    #[error("Could not open the package store")]
+    Store(#[source] Box<dyn std::error::Error + Send + Sync>),
+
+    #[error("No package store is available on this platform")]
+    Unsupported,
+
+    #[error("Could not resolve the packages of the transaction")]
+    Candidate(#[from] PackageCandidateError),
+
+    #[error("Downloading the payload of {0} failed")]
+    Download(PackageKey),
+
+    #[error("Processing {0} failed")]
+    Failed(PackageKey),
+}
+
+/// What happens while a transaction is run: first the downloads of its payloads,
+/// then the events of processing it.
+#[derive(Debug)]
+pub enum Event {
+    Download(PackageKey, DownloadEvent),
+    Transaction(TransactionEvent),
+}
+
+pub struct EmbeddedPahkat {
+    store: Arc<dyn PackageStore>,
+    current_transaction: Arc<Mutex<()>>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl EmbeddedPahkat {
+    /// Opens the system store of this platform, with the config at `config_path`
+    /// or the default one.
+    pub async fn open(config_path: Option<&Path>) -> Result<EmbeddedPahkat, Error> {
+        let config = match config_path {
+            Some(v) => {
+                let (config, errors) = Config::load(v, crate::Permission::ReadWrite);
+                for e in errors {
+                    log::warn!("Loading config at {}: {}", v.display(), e);
+                }
+                config
+            }
+            None => Config::load_default()?,
+        };
+
+        Ok(Self::with_store(platform_store(config).await?))
+    }
+
+    /// Opens the prefix at `prefix_path`, creating it if it does not exist.
+    #[cfg(feature = "prefix")]
+    pub async fn open_prefix<P: AsRef<Path>>(prefix_path: P) -> Result<EmbeddedPahkat, Error> {
+        let store = crate::PrefixPackageStore::open_or_create(prefix_path)
+            .await
+            .map_err(|e| Error::Store(Box::new(e)))?;
+        Ok(Self::with_store(Arc::new(store)))
+    }
+
+    pub fn with_store(store: Arc<dyn PackageStore>) -> EmbeddedPahkat {
+        if store.config().read().unwrap().repos().is_empty() {
+            log::warn!("There are no repositories in the given config.");
+        }
+
+        EmbeddedPahkat {
+            store,
+            current_transaction: Default::default(),
+            tasks: vec![],
+        }
+    }
+
+    pub fn store(&self) -> Arc<dyn PackageStore> {
+        Arc::clone(&self.store)
+    }
+
+    /// Refreshes the repositories now, waiting for a running transaction first.
+    pub async fn refresh(&self) -> Result<(), HashMap<RepoUrl, RepoDownloadError>> {
+        let _guard = self.current_transaction.lock().await;
+        self.store.force_refresh_repos().await
+    }
+
+    /// Refreshes the repositories every update interval of the settings, calling
+    /// `on_refresh` with the result of each refresh.
+    pub fn start_refresh<F>(&mut self, mut on_refresh: F)
+    where
+        F: FnMut(Result<(), HashMap<RepoUrl, RepoDownloadError>>) + Send + 'static,
+    {
+        let store = Arc::clone(&self.store);
+        let current_transaction = Arc::clone(&self.current_transaction);
+
+        self.tasks.push(tokio::spawn(async move {
+            loop {
+                // Read each time, as the interval may be changed in the meantime
+                let interval = store.config().read().unwrap().settings().update_interval();
+                tokio::time::sleep(interval).await;
+
+                let result = {
+                    let _guard = current_transaction.lock().await;
+                    store.refresh_repos().await
+                };
+                if let Err(errors) = &result {
+                    log::warn!("Refreshing repositories failed: {:?}", errors);
+                }
+                on_refresh(result);
+            }
+        }));
+    }
+
+    /// Calls `on_event` with each event published by the store, such as a needed
+    /// restart or changed repositories.
+    pub fn on_store_event<F>(&mut self, mut on_event: F)
+    where
+        F: FnMut(StoreEvent) + Send + 'static,
+    {
+        let mut events = crate::events::global().subscribe();
+
+        self.tasks.push(tokio::spawn(async move {
+            loop {
+                match events.recv().await {
+                    Ok(event) => on_event(event),
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        log::warn!("Missed {} store events", n);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                }
+            }
+        }));
+    }
+
+    /// Resolves `actions` into a transaction and runs it. See [`Self::run_transaction`].
+    pub async fn run<F>(&self, actions: Vec<PackageAction>, on_event: F) -> Result<(), Error>
+    where
+        F: FnMut(Event) + Send,
+    {
+        let transaction = PackageTransaction::new(Arc::clone(&self.store), actions)?;
+        self.run_transaction(transaction, on_event).await
+    }
+
+    /// Downloads the payloads of `transaction` and then processes it, calling
+    /// `on_event` along the way. Stops at the first failed download or action,
+    /// after its event has been passed to `on_event`.
+    pub async fn run_transaction<F>(
+        &self,
+        transaction: PackageTransaction,
+        on_event: F,
+    ) -> Result<(), Error>
+    where
+        F: FnMut(Event) + Send,
+    {
+        let _guard = self.current_transaction.lock().await;
+        crate::events::global().publish(StoreEvent::TransactionLocked);
+        let result = self.process_locked(transaction, on_event).await;
+        crate::events::global().publish(StoreEvent::TransactionUnlocked);
+        result
+    }
+
+    async fn process_locked<F>(
+        &self,
+        transaction: PackageTransaction,
+        mut on_event: F,
+    ) -> Result<(), Error>
+    where
+        F: FnMut(Event) + Send,
+    {
+        transaction
+            .observers()
+            .register(Arc::new(ExecObserver::new(self.store.config())));
+
+        let concurrency = self
+            .store
+            .config()
+            .read()
+            .unwrap()
+            .settings()
+            .download_concurrency();
+        let mut downloads = transaction.download(concurrency);
+        while let Some((key, event)) = downloads.next().await {
+            let is_failed = matches!(&event, DownloadEvent::Error(_));
+            on_event(Event::Download(key.clone(), event));
+            if is_failed {
+                return Err(Error::Download(key));
+            }
+        }
+
+        // Dropping the trigger would cancel the transaction
+        let (_canceler, mut events) = transaction.process();
+        while let Some(event) = events.next().await {
+            let failed = match &event {
+                TransactionEvent::Error(key, _) => Some(key.clone()),
+                _ => None,
+            };
+            on_event(Event::Transaction(event));
+            if let Some(key) = failed {
+                return Err(Error::Failed(key));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for EmbeddedPahkat {
+    fn drop(&mut self) {
+        for task in self.tasks.drain(..) {
+            task.abort();
+        }
+    }
+}
+
+#[cfg(all(windows, feature = "windows"))]
+async fn platform_store(config: Config) -> Result<Arc<dyn PackageStore>, Error> {
+    Ok(Arc::new(crate::WindowsPackageStore::new(config).await))
+}
+
+#[cfg(all(target_os = "macos", feature = "macos"))]
+async fn platform_store(config: Config) -> Result<Arc<dyn PackageStore>, Error> {
+    Ok(Arc::new(crate::MacOSPackageStore::new(config).await))
+}
+
+#[cfg(all(target_os = "linux", feature = "linux"))]
+async fn platform_store(config: Config) -> Result<Arc<dyn PackageStore>, Error> {
+    let store = crate::LinuxPackageStore::new(config)
+        .await
+        .map_err(|e| Error::Store(Box::new(e)))?;
+    Ok(Arc::new(store))
+}
+
+#[cfg(not(any(
+    all(windows, feature = "windows"),
+    all(target_os = "macos", feature = "macos"),
+    all(target_os = "linux", feature = "linux"),
+)))]
+async fn platform_store(_config: Config) -> Result<Arc<dyn PackageStore>, Error> {
+    Err(Error::Unsupported)
+}
+
+#[cfg(all(test, feature = "prefix"))]
+mod tests {
+    use pahkat_types::package::{Descriptor, DescriptorData, Package, Release, Version};
+    use pahkat_types::payload::{tarball, Payload, Target};
+
+    use super::*;
+    use crate::package_store::mock::{key, repository, REPO};
+    use crate::package_store::InstallTarget;
+
+    /// A package whose payload is an xz tarball with a single file, served from disk.
+    fn tarball_package(dir: &Path) -> Package {
+        let path = dir.join("example.txz");
+        let encoder = xz2::write::XzEncoder::new(std::fs::File::create(&path).unwrap(), 6);
+        let mut builder = tar::Builder::new(encoder);
+        let contents = b"example";
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "example.txt", &contents[..])
+            .unwrap();
+        builder.into_inner().unwrap().finish().unwrap();
+        let size = std::fs::metadata(&path).unwrap().len();
+
+        let target = Target::builder()
+            .platform(std::env::consts::OS.into())
+            .payload(Payload::TarballPackage(
+                tarball::Package::builder()
+                    .url(url::Url::from_file_path(&path).unwrap())
+                    .size(size)
+                    .installed_size(contents.len() as u64)
+                    .build(),
+            ))
+            .build();
+
+        Package::Concrete(
+            Descriptor::builder()
+                .package(DescriptorData::builder().id("example".into()).build())
+                .release(vec![Release::builder()
+                    .version(Version::new("1.0.0").unwrap())
+                    .target(vec![target])
+                    .build()])
+                .build(),
+        )
+    }
+
+    #[test]
+    fn runs_transactions_and_reports_store_events() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+
+        runtime.block_on(async {
+            let mut pahkat = EmbeddedPahkat::open_prefix(dir.path().join("prefix"))
+                .await
+                .unwrap();
+            let (sender, mut store_events) = tokio::sync::mpsc::unbounded_channel();
+            pahkat.on_store_event(move |event| {
+                let _ = sender.send(event);
+            });
+
+            pahkat.refresh().await.unwrap();
+            pahkat.store().repos().write().unwrap().insert(
+                REPO.parse().unwrap(),
+                repository(&[tarball_package(dir.path())]),
+            );
+
+            let mut events = vec![];
+            let action = PackageAction::install(key("example"), InstallTarget::System);
+            pahkat
+                .run(vec![action], |event| events.push(event))
+                .await
+                .unwrap();
+
+            assert!(matches!(
+                events.last(),
+                Some(Event::Transaction(TransactionEvent::Complete))
+            ));
+            let status = pahkat
+                .store()
+                .status(&key("example"), InstallTarget::System);
+            assert!(matches!(status, Ok(crate::PackageStatus::UpToDate)));
+
+            // Other tests publish on the same bus, so only the order of these is checked
+            let mut expected = vec![
+                StoreEvent::RepositoriesChanged,
+                StoreEvent::TransactionLocked,
+                StoreEvent::TransactionUnlocked,
+            ]
+            .into_iter()
+            .peekable();
+            while let Some(next) = expected.peek() {
+                let event =
+                    tokio::time::timeout(std::time::Duration::from_secs(10), store_events.recv())
+                        .await
+                        .unwrap()
+                        .unwrap();
+                if &event == next {
+                    expected.next();
+                }
+            }
+        });
+    }
+}
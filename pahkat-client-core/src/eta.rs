@@ -0,0 +1,150 @@
+//! Download speed and time remaining across the concurrent downloads of a
+//! transaction, so that frontends show the same steady estimate rather than each
+//! deriving their own from raw progress.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::PackageKey;
+
+/// Speed is averaged over this much of the most recent progress.
+const WINDOW: Duration = Duration::from_secs(5);
+
+/// No estimate is given until progress has been seen over at least this long.
+const MIN_SPAN: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Estimate {
+    pub bytes_per_second: Option<u64>,
+    /// Unknown while any download has no known total.
+    pub eta_seconds: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+pub struct Estimator {
+    /// Current and total bytes of each download.
+    downloads: HashMap<PackageKey, (u64, u64)>,
+    received: u64,
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl Estimator {
+    pub fn new() -> Estimator {
+        Default::default()
+    }
+
+    /// Counts a download that has not started yet towards the time remaining. A
+    /// payload that is already cached is expected with `current` equal to `total`,
+    /// so that reporting it does not count as bytes received.
+    pub fn expect(&mut self, key: &PackageKey, current: u64, total: u64) {
+        self.downloads.insert(key.clone(), (current, total));
+    }
+
+    /// Stops counting a download towards the time remaining, such as one that
+    /// completed without reporting its progress.
+    pub fn complete(&mut self, key: &PackageKey) {
+        if let Some((current, total)) = self.downloads.get_mut(key) {
+            *current = (*current).max(*total);
+        }
+    }
+
+    pub fn update(&mut self, key: &PackageKey, current: u64, total: u64) -> Estimate {
+        self.update_at(key, current, total, Instant::now())
+    }
+
+    fn update_at(&mut self, key: &PackageKey, current: u64, total: u64, now: Instant) -> Estimate {
+        // A download that restarted, such as after falling back to a mirror, is
+        // not progress until it passes where it was.
+        let previous = self.downloads.get(key).map(|x| x.0).unwrap_or(0);
+        self.downloads
+            .insert(key.clone(), (current.max(previous), total));
+        self.received += current.saturating_sub(previous);
+
+        self.samples.push_back((now, self.received));
+        while self.samples.len() > 2 && now.duration_since(self.samples[1].0) >= WINDOW {
+            self.samples.pop_front();
+        }
+
+        self.estimate(now)
+    }
+
+    fn estimate(&self, now: Instant) -> Estimate {
+        let (start, start_received) = match self.samples.front() {
+            Some(v) => *v,
+            None => return Estimate::default(),
+        };
+        let span = now.duration_since(start);
+        if span < MIN_SPAN {
+            return Estimate::default();
+        }
+
+        let bytes_per_second =
+            ((self.received - start_received) as f64 / span.as_secs_f64()).round() as u64;
+        let remaining = self
+            .downloads
+            .values()
+            .map(|&(current, total)| match total {
+                0 => None,
+                _ => Some(total.saturating_sub(current)),
+            })
+            .sum::<Option<u64>>();
+
+        let eta_seconds = match (remaining, bytes_per_second) {
+            (Some(0), _) => Some(0),
+            (_, 0) => None,
+            (Some(remaining), speed) => Some(remaining.div_ceil(speed)),
+            (None, _) => None,
+        };
+
+        Estimate {
+            bytes_per_second: Some(bytes_per_second),
+            eta_seconds,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pahkat_types::repo::RepoUrl;
+
+    use super::*;
+
+    fn key(id: &str) -> PackageKey {
+        let url = RepoUrl::new("https://pahkat.example/repo/".parse().unwrap()).unwrap();
+        PackageKey::new_unchecked(url, id.to_string(), None)
+    }
+
+    #[test]
+    fn combines_concurrent_downloads() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut estimator = Estimator::new();
+        estimator.expect(&key("speller"), 0, 3000);
+        estimator.expect(&key("keyboard"), 0, 1000);
+        estimator.expect(&key("cached"), 500, 500);
+
+        let estimate = estimator.update_at(&key("speller"), 0, 3000, at(0));
+        assert_eq!(estimate, Estimate::default());
+        estimator.update_at(&key("cached"), 500, 500, at(0));
+        estimator.update_at(&key("keyboard"), 500, 1000, at(500));
+
+        let estimate = estimator.update_at(&key("speller"), 500, 3000, at(1000));
+        assert_eq!(estimate.bytes_per_second, Some(1000));
+        assert_eq!(estimate.eta_seconds, Some(3));
+
+        let estimate = estimator.update_at(&key("keyboard"), 1000, 1000, at(2000));
+        assert_eq!(estimate.bytes_per_second, Some(750));
+        assert_eq!(estimate.eta_seconds, Some(4));
+    }
+
+    #[test]
+    fn unknown_total_has_no_eta() {
+        let start = Instant::now();
+        let mut estimator = Estimator::new();
+        estimator.update_at(&key("speller"), 0, 0, start);
+        let estimate =
+            estimator.update_at(&key("speller"), 2000, 0, start + Duration::from_secs(2));
+        assert_eq!(estimate.bytes_per_second, Some(1000));
+        assert_eq!(estimate.eta_seconds, None);
+    }
+}
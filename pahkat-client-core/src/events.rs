@@ -0,0 +1,86 @@
+//! Events raised by package stores and the services built around them.
+//!
+//! Producers publish to an [`EventBus`] instead of talking to their consumers, so
+//! RPC notifications, FFI callbacks and logs all see the same events.
+
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::power::DeferReason;
+use crate::PackageKey;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", content = "data", rename_all = "camelCase")]
+pub enum StoreEvent {
+    RebootRequired,
+    RepositoriesChanged,
+    /// The service publishing events is shutting down.
+    Stopping,
+    TransactionLocked,
+    TransactionUnlocked,
+    /// Packages that differ from the desired state.
+    StateDrift(Vec<PackageKey>),
+    /// Packages brought back to the desired state.
+    StateCorrected(Vec<PackageKey>),
+    /// Background updates are waiting for a better moment, such as being plugged in.
+    UpdatesDeferred(DeferReason),
+    /// Input sources installed by the system service, which only a process in a
+    /// user's session can enable for that user.
+    EnableInputSources(Vec<String>),
+}
+
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<StoreEvent>,
+}
+
+impl EventBus {
+    /// Subscribers that fall more than `capacity` events behind miss the oldest ones.
+    pub fn new(capacity: usize) -> EventBus {
+        let (sender, _) = broadcast::channel(capacity);
+        EventBus { sender }
+    }
+
+    /// Publishing without subscribers is not an error; the event is only logged.
+    pub fn publish(&self, event: StoreEvent) {
+        log::info!("Event: {:?}", &event);
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<StoreEvent> {
+        self.sender.subscribe()
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> EventBus {
+        EventBus::new(16)
+    }
+}
+
+static GLOBAL: Lazy<EventBus> = Lazy::new(EventBus::default);
+
+/// The bus shared by everything in this process.
+pub fn global() -> &'static EventBus {
+    &GLOBAL
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subscribers_receive_published_events() {
+        let bus = EventBus::new(4);
+        bus.publish(StoreEvent::RebootRequired);
+
+        let mut first = bus.subscribe();
+        let mut second = bus.subscribe();
+        bus.publish(StoreEvent::TransactionLocked);
+
+        assert_eq!(first.try_recv().unwrap(), StoreEvent::TransactionLocked);
+        assert_eq!(second.try_recv().unwrap(), StoreEvent::TransactionLocked);
+        assert!(first.try_recv().is_err());
+    }
+}
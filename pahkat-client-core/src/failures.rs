@@ -0,0 +1,234 @@
+//! Install failures of the background updater, kept per package so that a release
+//! that cannot be installed is retried with backoff rather than on every run, and
+//! given up on after repeated failures until a newer release is published.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::PackageKey;
+
+const FILE_NAME: &str = "install-failures.json";
+
+/// Retries are never further apart than this.
+const MAX_BACKOFF: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FailurePolicy {
+    /// Failures of the same release after which it is no longer retried. Zero
+    /// retries forever.
+    #[serde(default = "max_failures_default")]
+    pub max_failures: u32,
+    /// Seconds to wait after the first failure, doubling with each further failure.
+    #[serde(default = "backoff_default")]
+    pub backoff: u64,
+}
+
+#[inline(always)]
+fn max_failures_default() -> u32 {
+    5
+}
+
+#[inline(always)]
+fn backoff_default() -> u64 {
+    60 * 60
+}
+
+impl Default for FailurePolicy {
+    fn default() -> Self {
+        FailurePolicy {
+            max_failures: max_failures_default(),
+            backoff: backoff_default(),
+        }
+    }
+}
+
+impl FailurePolicy {
+    pub fn is_default(&self) -> bool {
+        self == &FailurePolicy::default()
+    }
+
+    /// When `failure` may be retried, or `None` if it has failed too often.
+    pub fn retry_at(&self, failure: &InstallFailure) -> Option<DateTime<Utc>> {
+        if self.max_failures > 0 && failure.count >= self.max_failures {
+            return None;
+        }
+
+        let shift = failure.count.saturating_sub(1).min(16);
+        let backoff = Duration::from_secs(self.backoff.saturating_mul(1 << shift)).min(MAX_BACKOFF);
+        Some(failure.last_seen + chrono::Duration::from_std(backoff).unwrap())
+    }
+
+    /// Whether installing `version` of `key` should be attempted at `now`. A release
+    /// other than the one that failed is always attempted.
+    pub fn should_attempt(
+        &self,
+        failures: &[InstallFailure],
+        key: &PackageKey,
+        version: &str,
+        now: DateTime<Utc>,
+    ) -> bool {
+        match find(failures, key) {
+            Some(failure) if failure.version == version => {
+                self.retry_at(failure).map(|x| now >= x).unwrap_or(false)
+            }
+            _ => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InstallFailure {
+    pub key: PackageKey,
+    /// The release that failed. Failures of earlier releases are forgotten.
+    pub version: String,
+    pub count: u32,
+    pub last_error: String,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+pub fn path(config: &Config) -> PathBuf {
+    config.settings().config_dir().join(FILE_NAME)
+}
+
+fn unqualified(key: &PackageKey) -> PackageKey {
+    PackageKey::new_unchecked(key.repository_url.clone(), key.id.clone(), None)
+}
+
+fn find<'a>(failures: &'a [InstallFailure], key: &PackageKey) -> Option<&'a InstallFailure> {
+    let key = unqualified(key);
+    failures.iter().find(|x| x.key == key)
+}
+
+/// Failures are ordered by package key.
+pub fn load(path: &Path) -> Vec<InstallFailure> {
+    std::fs::read(path)
+        .ok()
+        .and_then(|x| serde_json::from_slice(&x).ok())
+        .unwrap_or_default()
+}
+
+fn save(path: &Path, failures: &[InstallFailure]) {
+    let result = if failures.is_empty() {
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    } else {
+        path.parent()
+            .map(std::fs::create_dir_all)
+            .unwrap_or(Ok(()))
+            .and_then(|_| std::fs::write(path, serde_json::to_vec(failures)?))
+    };
+
+    if let Err(e) = result {
+        log::warn!(
+            "Could not save install failures to {}: {}",
+            path.display(),
+            e
+        );
+    }
+}
+
+/// Returns the updated failure.
+pub fn record_failure(
+    path: &Path,
+    key: &PackageKey,
+    version: &str,
+    error: &str,
+    now: DateTime<Utc>,
+) -> InstallFailure {
+    let key = unqualified(key);
+    let mut failures = load(path);
+    let index = match failures.binary_search_by(|x| x.key.to_string().cmp(&key.to_string())) {
+        Ok(v) => v,
+        Err(v) => {
+            failures.insert(
+                v,
+                InstallFailure {
+                    key,
+                    version: version.to_string(),
+                    count: 0,
+                    last_error: String::new(),
+                    first_seen: now,
+                    last_seen: now,
+                },
+            );
+            v
+        }
+    };
+
+    let failure = &mut failures[index];
+    if failure.version != version {
+        failure.version = version.to_string();
+        failure.count = 0;
+        failure.first_seen = now;
+    }
+    failure.count += 1;
+    failure.last_error = error.to_string();
+    failure.last_seen = now;
+
+    let failure = failure.clone();
+    save(path, &failures);
+    failure
+}
+
+/// Forgets the failures of `key`, after it was installed.
+pub fn record_success(path: &Path, key: &PackageKey) {
+    let key = unqualified(key);
+    let mut failures = load(path);
+    let len = failures.len();
+    failures.retain(|x| x.key != key);
+    if failures.len() != len {
+        save(path, &failures);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pahkat_types::repo::RepoUrl;
+
+    use super::*;
+
+    fn key(id: &str) -> PackageKey {
+        let url = RepoUrl::new("https://pahkat.example/repo/".parse().unwrap()).unwrap();
+        PackageKey::new_unchecked(url, id.to_string(), None)
+    }
+
+    #[test]
+    fn backs_off_then_gives_up_until_new_release() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(FILE_NAME);
+        let policy = FailurePolicy {
+            max_failures: 3,
+            backoff: 60,
+        };
+        let start = Utc::now();
+        let at = |secs| start + chrono::Duration::seconds(secs);
+
+        record_failure(&path, &key("speller"), "1.0.0", "exit code 1603", start);
+        let failures = load(&path);
+        assert!(!policy.should_attempt(&failures, &key("speller"), "1.0.0", at(59)));
+        assert!(policy.should_attempt(&failures, &key("speller"), "1.0.0", at(60)));
+        assert!(policy.should_attempt(&failures, &key("keyboard"), "1.0.0", start));
+
+        record_failure(&path, &key("speller"), "1.0.0", "exit code 1603", at(60));
+        let failures = load(&path);
+        assert!(!policy.should_attempt(&failures, &key("speller"), "1.0.0", at(179)));
+        assert!(policy.should_attempt(&failures, &key("speller"), "1.0.0", at(180)));
+
+        record_failure(&path, &key("speller"), "1.0.0", "exit code 1603", at(180));
+        let failures = load(&path);
+        assert_eq!(failures[0].count, 3);
+        assert!(!policy.should_attempt(&failures, &key("speller"), "1.0.0", at(100_000)));
+        assert!(policy.should_attempt(&failures, &key("speller"), "1.0.1", at(180)));
+
+        record_success(&path, &key("speller"));
+        assert!(load(&path).is_empty());
+        assert!(!path.exists());
+    }
+}
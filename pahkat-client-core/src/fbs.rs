@@ -1,17 +1,52 @@
 use std::convert::TryFrom;
 
 use crate::generated::pahkat as pahkat_fbs;
+use types::payload::windows::InstallerKind;
 use types::DependencyKey;
 
+/// A package in the index that cannot be decoded. Indexes come from the
+/// network, so this is reported rather than trusted.
+#[derive(Debug, thiserror::Error)]
+pub enum IndexError {
+    #[error("Malformed index: {0:?}")]
+    Malformed(fbs::Error),
+
+    #[error("Missing field `{0}`")]
+    MissingField(&'static str),
+
+    #[error("Invalid URL")]
+    Url(#[from] url::ParseError),
+
+    #[error("Invalid version")]
+    Version(#[from] pahkat_types::package::version::Error),
+
+    #[error("Invalid version requirement for dependency `{0}`")]
+    DependencyVersion(String, #[source] pahkat_types::package::version::Error),
+}
+
+impl From<fbs::Error> for IndexError {
+    fn from(e: fbs::Error) -> Self {
+        IndexError::Malformed(e)
+    }
+}
+
 pub(crate) trait DescriptorExt {
     fn name(&self) -> Option<Map<'_, &'_ str, &'_ str>>;
     fn description(&self) -> Option<Map<'_, &'_ str, &'_ str>>;
 }
 
+pub(crate) trait ReleaseExt {
+    fn deprecation_message(&self) -> Option<Map<'_, &'_ str, &'_ str>>;
+}
+
 pub(crate) trait TargetExt {
     fn dependencies(&self) -> Option<Map<'_, &'_ str, &'_ str>>;
 }
 
+pub(crate) trait WindowsExecutableExt {
+    fn msi_properties(&self) -> Option<Map<'_, &'_ str, &'_ str>>;
+}
+
 pub(crate) trait PackagesExt<B: AsRef<[u8]>> {
     fn packages(&self) -> Option<Map<'_, &'_ str, pahkat_fbs::Descriptor<&'_ [u8]>>>;
 }
@@ -38,6 +73,14 @@ impl<B: AsRef<[u8]>> DescriptorExt for pahkat_fbs::Descriptor<B> {
     }
 }
 
+impl<B: AsRef<[u8]>> ReleaseExt for pahkat_fbs::Release<B> {
+    fn deprecation_message(&self) -> Option<Map<'_, &'_ str, &'_ str>> {
+        let keys = self.deprecation_message_keys().ok()??;
+        let values = self.deprecation_message_values().ok()??;
+        Some(Map::new(keys, values))
+    }
+}
+
 impl<B: AsRef<[u8]>> TargetExt for pahkat_fbs::Target<B> {
     fn dependencies(&self) -> Option<Map<'_, &'_ str, &'_ str>> {
         let keys = self.dependencies_keys().ok()??;
@@ -46,53 +89,365 @@ impl<B: AsRef<[u8]>> TargetExt for pahkat_fbs::Target<B> {
     }
 }
 
+impl<B: AsRef<[u8]>> WindowsExecutableExt for pahkat_fbs::WindowsExecutable<B> {
+    fn msi_properties(&self) -> Option<Map<'_, &'_ str, &'_ str>> {
+        let keys = self.msi_properties_keys().ok()??;
+        let values = self.msi_properties_values().ok()??;
+        Some(Map::new(keys, values))
+    }
+}
+
+/// Actions this client does not understand are skipped rather than failing the
+/// whole package.
+fn build_actions(
+    actions: Option<fbs::Vector<'_, fbs::ForwardsUOffset<&'_ str>>>,
+) -> Vec<pahkat_types::payload::Action> {
+    actions
+        .map(|x| {
+            x.iter()
+                .filter_map(Result::ok)
+                .filter_map(|x| match x.parse() {
+                    Ok(v) => Some(v),
+                    Err(_) => {
+                        log::warn!("Skipping unknown payload action: {}", x);
+                        None
+                    }
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Mirrors that are not valid URLs are skipped rather than failing the whole payload.
+fn build_mirrors(mirrors: Option<fbs::Vector<'_, fbs::ForwardsUOffset<&'_ str>>>) -> Vec<url::Url> {
+    mirrors
+        .map(|x| {
+            x.iter()
+                .filter_map(Result::ok)
+                .filter_map(|x| match x.parse() {
+                    Ok(v) => Some(v),
+                    Err(_) => {
+                        log::warn!("Skipping invalid mirror URL: {}", x);
+                        None
+                    }
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Deltas that cannot be read are skipped, as the full payload can be used instead.
+fn build_deltas(
+    deltas: Option<fbs::Vector<'_, fbs::ForwardsUOffset<pahkat_fbs::Delta<&'_ [u8]>>>>,
+) -> Vec<pahkat_types::payload::delta::Delta> {
+    use pahkat_types::payload::delta::{Delta, DeltaFormat};
+
+    let build = |x: pahkat_fbs::Delta<&[u8]>| -> Result<Delta, IndexError> {
+        Ok(Delta::builder()
+            .from_version(x.from_version()?.to_string())
+            .url(x.url()?.parse::<url::Url>()?)
+            .size(x.size()?.ok_or(IndexError::MissingField("size"))?)
+            .format(match x.format()? {
+                None | Some(pahkat_fbs::DeltaFormat::Bsdiff) => DeltaFormat::Bsdiff,
+            })
+            .payload_sha256(x.payload_sha256()?.map(str::to_string))
+            .build())
+    };
+
+    deltas
+        .map(|x| {
+            x.iter()
+                .filter_map(Result::ok)
+                .filter_map(|x| match build(x) {
+                    Ok(v) => Some(v),
+                    Err(e) => {
+                        log::warn!("Skipping invalid delta: {}", e);
+                        None
+                    }
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn build_options(
+    options: Option<fbs::Vector<'_, fbs::ForwardsUOffset<pahkat_fbs::InstallOption<&'_ [u8]>>>>,
+) -> Vec<pahkat_types::payload::InstallOption> {
+    use pahkat_types::payload::InstallOption;
+
+    let build = |x: pahkat_fbs::InstallOption<&[u8]>| -> Result<InstallOption, IndexError> {
+        Ok(InstallOption::builder()
+            .id(x.id()?.to_string())
+            .values(
+                x.values()?
+                    .map(|x| {
+                        x.iter()
+                            .filter_map(Result::ok)
+                            .map(str::to_string)
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+            )
+            .default(x.default()?.map(str::to_string))
+            .build())
+    };
+
+    options
+        .map(|x| {
+            x.iter()
+                .filter_map(Result::ok)
+                .filter_map(|x| match build(x) {
+                    Ok(v) => Some(v),
+                    Err(e) => {
+                        log::warn!("Skipping invalid install option: {}", e);
+                        None
+                    }
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn build_provenance<B: AsRef<[u8]>>(
+    r: &pahkat_fbs::Release<B>,
+) -> Result<Option<pahkat_types::package::Provenance>, IndexError> {
+    let provenance = pahkat_types::package::Provenance::builder()
+        .source_url(r.provenance_source_url()?.and_then(|x| x.parse().ok()))
+        .commit(r.provenance_commit()?.map(str::to_string))
+        .ci_run_url(r.provenance_ci_run_url()?.and_then(|x| x.parse().ok()))
+        .builder(r.provenance_builder()?.map(str::to_string))
+        .build();
+
+    if provenance.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(provenance))
+}
+
+fn build_deprecation<B: AsRef<[u8]>>(
+    r: &pahkat_fbs::Release<B>,
+) -> Result<Option<pahkat_types::package::Deprecation>, IndexError> {
+    use pahkat_types::package::{Deprecation, DeprecationSeverity};
+
+    let severity = match r.deprecation_severity()?.unwrap_or(0) {
+        0 => return Ok(None),
+        v => DeprecationSeverity::from_u8(v),
+    };
+    let message = r
+        .deprecation_message()
+        .map(|x| {
+            x.iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Ok(Some(
+        Deprecation::builder()
+            .severity(severity)
+            .message(message)
+            .build(),
+    ))
+}
+
 fn build_target<B: AsRef<[u8]>>(
     t: &pahkat_fbs::Target<B>,
-) -> Result<pahkat_types::payload::Target, fbs::Error> {
+) -> Result<pahkat_types::payload::Target, IndexError> {
     let platform = t.platform()?.to_string();
     let arch = t.arch()?.map(str::to_string);
     let dependencies = t
         .dependencies()
         .map(|x| {
-            let mut out = std::collections::BTreeMap::new();
+            let mut out = pahkat_types::DependencyMap::new();
             for (k, v) in x.iter() {
-                out.insert(DependencyKey::from(k), v.to_string());
+                let req = v
+                    .parse()
+                    .map_err(|e| IndexError::DependencyVersion(k.to_string(), e))?;
+                out.insert(DependencyKey::from(k), req);
             }
-            out
+            Ok::<_, IndexError>(out)
         })
+        .transpose()?
         .unwrap_or_else(|| Default::default());
     let payload = match t.payload()? {
         pahkat_fbs::Payload::WindowsExecutable(x) => {
             pahkat_types::payload::Payload::WindowsExecutable(
                 pahkat_types::payload::windows::Executable::builder()
-                    .url(x.url()?.parse::<url::Url>().unwrap())
+                    .url(x.url()?.parse::<url::Url>()?)
+                    .mirrors(build_mirrors(x.mirrors()?))
+                    .deltas(build_deltas(x.deltas()?))
                     .product_code(x.product_code()?.to_string())
-                    .kind(match x.kind()?.unwrap() {
-                        pahkat_fbs::WindowsExecutableKind::NONE => None,
-                        x => Some(
-                            pahkat_fbs::enum_name_windows_executable_kind(x)
-                                .to_lowercase()
-                                .to_string(),
-                        ),
+                    .kind(match x.kind()? {
+                        None | Some(pahkat_fbs::WindowsExecutableKind::NONE) => None,
+                        Some(pahkat_fbs::WindowsExecutableKind::Msi) => Some(InstallerKind::Msi),
+                        Some(pahkat_fbs::WindowsExecutableKind::Inno) => {
+                            Some(InstallerKind::InnoSetup)
+                        }
+                        Some(pahkat_fbs::WindowsExecutableKind::Nsis) => Some(InstallerKind::Nsis),
+                        Some(pahkat_fbs::WindowsExecutableKind::InstallShield) => {
+                            Some(InstallerKind::InstallShield)
+                        }
+                        Some(pahkat_fbs::WindowsExecutableKind::Other) => x
+                            .kind_name()?
+                            .map(|name| InstallerKind::Other(name.to_string())),
                     })
-                    .size(x.size()?.unwrap())
-                    .installed_size(x.installed_size()?.unwrap())
+                    .size(x.size()?.ok_or(IndexError::MissingField("size"))?)
+                    .installed_size(
+                        x.installed_size()?
+                            .ok_or(IndexError::MissingField("installed_size"))?,
+                    )
+                    .publisher(x.publisher()?.map(str::to_string))
+                    .msi_properties(
+                        x.msi_properties()
+                            .map(|x| {
+                                x.iter()
+                                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                                    .collect()
+                            })
+                            .unwrap_or_default(),
+                    )
+                    .msi_transforms(
+                        x.msi_transforms()?
+                            .map(|x| {
+                                x.iter()
+                                    .filter_map(Result::ok)
+                                    .map(str::to_string)
+                                    .collect()
+                            })
+                            .unwrap_or_default(),
+                    )
+                    .actions(build_actions(x.actions()?))
+                    .uninstall({
+                        let registry_key = x.uninstall_registry_key()?.map(str::to_string);
+                        let quiet_command = x.uninstall_quiet_command()?.map(str::to_string);
+                        if registry_key.is_none() && quiet_command.is_none() {
+                            None
+                        } else {
+                            Some(
+                                pahkat_types::payload::windows::Uninstall::builder()
+                                    .registry_key(registry_key)
+                                    .quiet_command(quiet_command)
+                                    .build(),
+                            )
+                        }
+                    })
+                    .options(build_options(x.options()?))
                     .build(),
             )
         }
         pahkat_fbs::Payload::MacOSPackage(x) => pahkat_types::payload::Payload::MacOSPackage(
             pahkat_types::payload::macos::Package::builder()
-                .url(x.url()?.parse::<url::Url>().unwrap())
+                .url(x.url()?.parse::<url::Url>()?)
+                .mirrors(build_mirrors(x.mirrors()?))
+                .deltas(build_deltas(x.deltas()?))
                 .pkg_id(x.pkg_id()?.to_string())
-                .size(x.size()?.unwrap())
-                .installed_size(x.installed_size()?.unwrap())
+                .size(x.size()?.ok_or(IndexError::MissingField("size"))?)
+                .installed_size(
+                    x.installed_size()?
+                        .ok_or(IndexError::MissingField("installed_size"))?,
+                )
+                .team_id(x.team_id()?.map(str::to_string))
+                .choice_changes(x.choice_changes()?.map(str::to_string))
+                .user_choice_changes(x.user_choice_changes()?.map(str::to_string))
+                .choice_changes_sha256(x.choice_changes_sha256()?.map(str::to_string))
+                .user_choice_changes_sha256(x.user_choice_changes_sha256()?.map(str::to_string))
+                .actions(build_actions(x.actions()?))
+                .options(build_options(x.options()?))
                 .build(),
         ),
         pahkat_fbs::Payload::TarballPackage(x) => pahkat_types::payload::Payload::TarballPackage(
             pahkat_types::payload::tarball::Package::builder()
-                .url(x.url()?.parse::<url::Url>().unwrap())
-                .size(x.size()?.unwrap())
-                .installed_size(x.installed_size()?.unwrap())
+                .url(x.url()?.parse::<url::Url>()?)
+                .mirrors(build_mirrors(x.mirrors()?))
+                .deltas(build_deltas(x.deltas()?))
+                .size(x.size()?.ok_or(IndexError::MissingField("size"))?)
+                .installed_size(
+                    x.installed_size()?
+                        .ok_or(IndexError::MissingField("installed_size"))?,
+                )
+                .install_dir(x.install_dir()?.map(str::to_string))
+                .strip_components(x.strip_components()?.unwrap_or(0))
+                .options(build_options(x.options()?))
+                .build(),
+        ),
+        pahkat_fbs::Payload::DebianPackage(x) => pahkat_types::payload::Payload::DebianPackage(
+            pahkat_types::payload::debian::Package::builder()
+                .url(x.url()?.parse::<url::Url>()?)
+                .mirrors(build_mirrors(x.mirrors()?))
+                .deltas(build_deltas(x.deltas()?))
+                .package(x.package()?.to_string())
+                .size(x.size()?.ok_or(IndexError::MissingField("size"))?)
+                .installed_size(
+                    x.installed_size()?
+                        .ok_or(IndexError::MissingField("installed_size"))?,
+                )
+                .build(),
+        ),
+        pahkat_fbs::Payload::Flatpak(x) => pahkat_types::payload::Payload::Flatpak(
+            pahkat_types::payload::flatpak::Package::builder()
+                .url(x.url()?.parse::<url::Url>()?)
+                .remote(x.remote()?.to_string())
+                .flatpak_ref(x.flatpak_ref()?.to_string())
+                .size(x.size()?.ok_or(IndexError::MissingField("size"))?)
+                .installed_size(
+                    x.installed_size()?
+                        .ok_or(IndexError::MissingField("installed_size"))?,
+                )
+                .build(),
+        ),
+        pahkat_fbs::Payload::Snap(x) => pahkat_types::payload::Payload::Snap(
+            pahkat_types::payload::snap::Package::builder()
+                .url(x.url()?.parse::<url::Url>()?)
+                .name(x.name()?.to_string())
+                .channel(x.channel()?.map(str::to_string))
+                .classic(x.classic()?.unwrap_or(false))
+                .size(x.size()?.ok_or(IndexError::MissingField("size"))?)
+                .installed_size(
+                    x.installed_size()?
+                        .ok_or(IndexError::MissingField("installed_size"))?,
+                )
+                .build(),
+        ),
+        pahkat_fbs::Payload::WindowsMsix(x) => pahkat_types::payload::Payload::WindowsMsix(
+            pahkat_types::payload::windows::Msix::builder()
+                .url(x.url()?.parse::<url::Url>()?)
+                .mirrors(build_mirrors(x.mirrors()?))
+                .deltas(build_deltas(x.deltas()?))
+                .package_family_name(x.package_family_name()?.to_string())
+                .size(x.size()?.ok_or(IndexError::MissingField("size"))?)
+                .installed_size(
+                    x.installed_size()?
+                        .ok_or(IndexError::MissingField("installed_size"))?,
+                )
+                .actions(build_actions(x.actions()?))
+                .build(),
+        ),
+        pahkat_fbs::Payload::MacOSAppBundle(x) => pahkat_types::payload::Payload::MacOSAppBundle(
+            pahkat_types::payload::macos::AppBundle::builder()
+                .url(x.url()?.parse::<url::Url>()?)
+                .mirrors(build_mirrors(x.mirrors()?))
+                .deltas(build_deltas(x.deltas()?))
+                .bundle_id(x.bundle_id()?.to_string())
+                .app_name(x.app_name()?.to_string())
+                .size(x.size()?.ok_or(IndexError::MissingField("size"))?)
+                .installed_size(
+                    x.installed_size()?
+                        .ok_or(IndexError::MissingField("installed_size"))?,
+                )
+                .actions(build_actions(x.actions()?))
+                .build(),
+        ),
+        pahkat_fbs::Payload::RpmPackage(x) => pahkat_types::payload::Payload::RpmPackage(
+            pahkat_types::payload::rpm::Package::builder()
+                .url(x.url()?.parse::<url::Url>()?)
+                .mirrors(build_mirrors(x.mirrors()?))
+                .deltas(build_deltas(x.deltas()?))
+                .package(x.package()?.to_string())
+                .size(x.size()?.ok_or(IndexError::MissingField("size"))?)
+                .installed_size(
+                    x.installed_size()?
+                        .ok_or(IndexError::MissingField("installed_size"))?,
+                )
                 .build(),
         ),
     };
@@ -106,7 +461,7 @@ fn build_target<B: AsRef<[u8]>>(
 }
 
 impl<'a> TryFrom<&'a pahkat_fbs::Descriptor<&'a [u8]>> for pahkat_types::package::Descriptor {
-    type Error = fbs::Error;
+    type Error = IndexError;
 
     fn try_from(pkg: &'a pahkat_fbs::Descriptor<&'a [u8]>) -> Result<Self, Self::Error> {
         use std::collections::BTreeMap;
@@ -146,25 +501,31 @@ impl<'a> TryFrom<&'a pahkat_fbs::Descriptor<&'a [u8]>> for pahkat_types::package
             )
             .release(
                 pkg.release()?
-                    .unwrap()
+                    .ok_or(IndexError::MissingField("release"))?
                     .iter()
                     .filter_map(Result::ok)
                     .map(|x| {
                         let release = pahkat_types::package::Release::builder()
-                            .version(
-                                pahkat_types::package::version::Version::new(x.version()?).unwrap(),
-                            )
+                            .version(pahkat_types::package::version::Version::new(x.version()?)?)
                             .channel(x.channel()?.map(|x| x.to_string()))
+                            .available_from(x.available_from()?.map(|x| x.to_string()))
+                            .min_client_version(x.min_client_version()?.map(|x| x.to_string()))
+                            .rollout(match x.rollout()?.unwrap_or(100) {
+                                100 => None,
+                                v => Some(v),
+                            })
+                            .deprecation(build_deprecation(&x)?)
+                            .provenance(build_provenance(&x)?)
                             .target(
                                 x.target()?
-                                    .unwrap()
+                                    .ok_or(IndexError::MissingField("target"))?
                                     .iter()
                                     .filter_map(Result::ok)
                                     .map(|t| build_target(&t))
                                     .collect::<Result<Vec<_>, _>>()?,
                             )
                             .build();
-                        Ok(release)
+                        Ok::<_, IndexError>(release)
                     })
                     .collect::<Result<Vec<_>, _>>()?,
             )
@@ -250,7 +611,7 @@ where
             .iter()
             .filter_map(Result::ok)
             .position(|x| x == key)
-            .map(|i| self.values.get(i).unwrap())
+            .and_then(|i| self.values.get(i).ok())
     }
 
     #[inline]
@@ -265,7 +626,7 @@ where
         if index >= self.len {
             None
         } else {
-            Some(self.keys.get(index).unwrap())
+            self.keys.get(index).ok()
         }
     }
 
@@ -277,7 +638,60 @@ where
         if index >= self.len {
             None
         } else {
-            Some(self.values.get(index).unwrap())
+            self.values.get(index).ok()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use proptest::prelude::*;
+    use types::package::{Descriptor, Package};
+    use types::payload::Payload;
+
+    use super::PackagesExt;
+    use crate::generated::pahkat as pahkat_fbs;
+
+    /// Clears what the index does not hold, or what is not read back from it.
+    fn indexed(mut descriptor: Descriptor) -> Descriptor {
+        for release in descriptor.release.iter_mut() {
+            release.authors.clear();
+            release.license = None;
+            release.license_url = None;
+
+            for target in release.target.iter_mut() {
+                match &mut target.payload {
+                    Payload::WindowsExecutable(x) => {
+                        x.args = None;
+                        x.uninstall_args = None;
+                        x.requires_reboot.clear();
+                    }
+                    Payload::MacOSPackage(x) => {
+                        x.targets.clear();
+                        x.requires_reboot.clear();
+                    }
+                    _ => {}
+                }
+            }
+        }
+        descriptor
+    }
+
+    proptest! {
+        #[test]
+        fn descriptor_index_round_trip(descriptor in types::strategy::descriptor()) {
+            let id = descriptor.package.id.clone();
+            let index = pahkat_types::index_writer::encode_index(&[
+                Package::Concrete(descriptor.clone()),
+            ])
+            .unwrap();
+
+            let packages = pahkat_fbs::Packages::get_root(&*index).unwrap();
+            let packages = packages.packages().unwrap();
+            let pkg = packages.get(&id).unwrap();
+            prop_assert_eq!(Descriptor::try_from(&pkg).unwrap(), indexed(descriptor));
         }
     }
 }
@@ -0,0 +1,108 @@
+//! Transactions made with `pahkat_ios_transaction_new` are driven with
+//! `pahkat_prefix_transaction_actions`, `pahkat_prefix_transaction_download` and
+//! `pahkat_prefix_transaction_process`.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use crate::package_store::ios::IosPackageStore;
+use crate::package_store::PackageStore;
+use crate::transaction::{status_to_i8, PackageAction, PackageTransaction};
+use crate::{Config, PackageKey};
+
+use super::{block_on, BoxError, JsonMarshaler, PackageKeyMarshaler};
+
+#[cffi::marshal(return_marshaler = "cffi::ArcMarshaler::<IosPackageStore>")]
+pub extern "C" fn pahkat_ios_package_store_open(
+    #[marshal(cffi::PathBufMarshaler)] container_path: PathBuf,
+) -> Result<Arc<IosPackageStore>, Box<dyn Error>> {
+    block_on(IosPackageStore::open_or_create(container_path))
+        .map(|x| Arc::new(x))
+        .box_err()
+}
+
+#[cffi::marshal]
+pub extern "C" fn pahkat_ios_package_store_status(
+    #[marshal(cffi::ArcRefMarshaler::<IosPackageStore>)] handle: Arc<IosPackageStore>,
+    #[marshal(PackageKeyMarshaler::<'_>)] package_key: PackageKey,
+) -> i8 {
+    status_to_i8(handle.status(&package_key, Default::default()))
+}
+
+#[cffi::marshal(return_marshaler = "JsonMarshaler")]
+pub extern "C" fn pahkat_ios_package_store_all_statuses(
+    #[marshal(cffi::ArcRefMarshaler::<IosPackageStore>)] handle: Arc<IosPackageStore>,
+    #[marshal(cffi::UrlMarshaler)] repo_url: url::Url,
+) -> BTreeMap<String, i8> {
+    let repo_url = match pahkat_types::repo::RepoUrl::new(repo_url) {
+        Ok(v) => v,
+        Err(_) => return Default::default(),
+    };
+    handle
+        .all_statuses(&repo_url, Default::default())
+        .into_iter()
+        .map(|(id, result)| (id, status_to_i8(result)))
+        .collect()
+}
+
+/// Moves a payload downloaded by the app into the package cache.
+#[cffi::marshal(return_marshaler = "cffi::PathBufMarshaler")]
+pub extern "C" fn pahkat_ios_package_store_import(
+    #[marshal(cffi::ArcRefMarshaler::<IosPackageStore>)] handle: Arc<IosPackageStore>,
+    #[marshal(PackageKeyMarshaler::<'_>)] package_key: PackageKey,
+    #[marshal(cffi::PathBufMarshaler)] installer_path: PathBuf,
+) -> Result<PathBuf, Box<dyn Error>> {
+    handle.import(&package_key, &installer_path).box_err()
+}
+
+#[cffi::marshal(return_marshaler = "cffi::UrlMarshaler")]
+pub extern "C" fn pahkat_ios_package_store_download_url(
+    #[marshal(cffi::ArcRefMarshaler::<IosPackageStore>)] handle: Arc<IosPackageStore>,
+    #[marshal(PackageKeyMarshaler::<'_>)] package_key: PackageKey,
+) -> Result<url::Url, Box<dyn Error>> {
+    handle
+        .download_url(&package_key)
+        .map_err(crate::download::DownloadError::Payload)
+        .box_err()
+}
+
+#[cffi::marshal(return_marshaler = "JsonMarshaler")]
+pub extern "C" fn pahkat_ios_package_store_find_package_by_key(
+    #[marshal(cffi::ArcRefMarshaler::<IosPackageStore>)] handle: Arc<IosPackageStore>,
+    #[marshal(PackageKeyMarshaler::<'_>)] package_key: PackageKey,
+) -> Option<pahkat_types::package::Package> {
+    handle.find_package_by_key(&package_key)
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Some repositories could not be updated.")]
+struct RefreshRepoError;
+
+#[cffi::marshal(return_marshaler = "cffi::UnitMarshaler")]
+pub extern "C" fn pahkat_ios_package_store_refresh_repos(
+    #[marshal(cffi::ArcRefMarshaler::<IosPackageStore>)] handle: Arc<IosPackageStore>,
+) -> Result<(), Box<dyn Error>> {
+    block_on(handle.refresh_repos())
+        .map_err(|_| RefreshRepoError)
+        .box_err()
+}
+
+#[cffi::marshal(return_marshaler = "cffi::ArcMarshaler::<RwLock<Config>>")]
+pub extern "C" fn pahkat_ios_package_store_config(
+    #[marshal(cffi::ArcRefMarshaler::<IosPackageStore>)] handle: Arc<IosPackageStore>,
+) -> Arc<RwLock<Config>> {
+    handle.config()
+}
+
+#[cffi::marshal(return_marshaler = "cffi::BoxMarshaler::<PackageTransaction>")]
+pub extern "C" fn pahkat_ios_transaction_new(
+    #[marshal(cffi::ArcRefMarshaler::<IosPackageStore>)] handle: Arc<IosPackageStore>,
+    #[marshal(cffi::StrMarshaler::<'_>)] actions: &str,
+) -> Result<Box<PackageTransaction>, Box<dyn Error>> {
+    let actions: Vec<PackageAction> = serde_json::from_str(actions)?;
+    PackageTransaction::new(handle as _, actions)
+        .map(|x| Box::new(x))
+        .map_err(|e| e.into())
+}
@@ -1,3 +1,6 @@
+#[cfg(all(target_os = "ios", feature = "ios"))]
+pub mod ios;
+
 #[cfg(all(target_os = "macos", feature = "macos"))]
 pub mod macos;
 
@@ -44,6 +47,50 @@ pub extern "C" fn pahkat_set_logging_callback(
         .box_err()
 }
 
+/// Sets the version of the application, which releases requiring a minimum Pahkat
+/// version are checked against. Call it before loading any repositories.
+#[cffi::marshal(return_marshaler = "cffi::UnitMarshaler")]
+pub extern "C" fn pahkat_set_client_version(
+    #[marshal(cffi::StrMarshaler::<'_>)] version: &str,
+) -> Result<(), Box<dyn Error>> {
+    crate::defaults::set_client_version(version).box_err()
+}
+
+type EventCallback = extern "C" fn(*const libc::c_char);
+
+static EVENT_CALLBACK: Lazy<Mutex<Option<EventCallback>>> = Lazy::new(Default::default);
+static EVENT_THREAD: std::sync::Once = std::sync::Once::new();
+
+/// Calls `callback` with each event published on the global event bus, as JSON.
+/// Setting another callback replaces the previous one, and null stops the calls.
+#[no_mangle]
+pub extern "C" fn pahkat_set_event_callback(callback: Option<EventCallback>) {
+    *EVENT_CALLBACK.lock().unwrap() = callback;
+
+    EVENT_THREAD.call_once(|| {
+        let mut events = crate::events::global().subscribe();
+
+        std::thread::spawn(move || loop {
+            let event = match events.blocking_recv() {
+                Ok(v) => v,
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                    ::log::warn!("Event callback missed {} events", n);
+                    continue;
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => return,
+            };
+
+            // Copied out so that the callback may replace itself
+            let callback = *EVENT_CALLBACK.lock().unwrap();
+            if let Some(callback) = callback {
+                let json = serde_json::to_string(&event).unwrap_or_default();
+                let json = CString::new(json).unwrap_or_else(|_| CString::default());
+                callback(json.as_ptr());
+            }
+        });
+    });
+}
+
 #[cffi::marshal(return_marshaler = "JsonMarshaler")]
 pub extern "C" fn pahkat_config_repos_get(
     #[marshal(cffi::ArcRefMarshaler::<RwLock<Config>>)] handle: Arc<RwLock<Config>>,
@@ -60,6 +107,31 @@ pub extern "C" fn pahkat_config_repos_set(
     handle.write().unwrap().repos_mut().set(repos).box_err()
 }
 
+/// Keeps `token` in the system keyring for the configured repository `repo_url`.
+/// An empty token deletes the current one.
+#[cffi::marshal(return_marshaler = "cffi::UnitMarshaler")]
+pub extern "C" fn pahkat_config_repos_set_auth_token(
+    #[marshal(cffi::ArcRefMarshaler::<RwLock<Config>>)] handle: Arc<RwLock<Config>>,
+    #[marshal(cffi::UrlMarshaler)] repo_url: Url,
+    #[marshal(cffi::StrMarshaler::<'_>)] token: &str,
+) -> Result<(), Box<dyn Error>> {
+    let repo_url = pahkat_types::repo::RepoUrl::new(repo_url)?;
+    let mut config = handle.write().unwrap();
+    let secrets = config.settings().secret_store();
+    let repos = config.repos_mut();
+
+    let mut record = match repos.get(&repo_url) {
+        Some(v) => v.clone(),
+        None => return Err(format!("Repository {} is not configured", repo_url).into()),
+    };
+    let token = match token {
+        "" => None,
+        token => Some(token),
+    };
+    record.set_auth_token(&repo_url, token, &secrets)?;
+    repos.insert(repo_url, record).box_err()
+}
+
 #[cffi::marshal(return_marshaler = "cffi::PathBufMarshaler")]
 pub extern "C" fn pahkat_config_settings_config_dir(
     #[marshal(cffi::ArcRefMarshaler::<RwLock<Config>>)] handle: Arc<RwLock<Config>>,
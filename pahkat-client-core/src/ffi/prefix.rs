@@ -105,8 +105,8 @@ pub extern "C" fn pahkat_prefix_package_store_download(
             DownloadEvent::Error(e) => {
                 return Err(e).box_err();
             }
-            DownloadEvent::Progress((current, total)) => {
-                progress(package_key_str.as_ptr(), current, total);
+            DownloadEvent::Progress(x) => {
+                progress(package_key_str.as_ptr(), x.current, x.total);
             }
             DownloadEvent::Complete(path_buf) => {
                 path = Some(path_buf);
@@ -215,6 +215,50 @@ pub extern "C" fn pahkat_prefix_transaction_actions(
     handle.actions().to_vec()
 }
 
+/// Downloads the payloads of the transaction, calling `progress_callback` with the
+/// key, current and total bytes of a download, and the speed and seconds remaining
+/// of all of them. Zero stands for an unknown total, speed or time remaining.
+#[cffi::marshal(return_marshaler = "cffi::UnitMarshaler")]
+pub extern "C" fn pahkat_prefix_transaction_download(
+    #[marshal(cffi::BoxRefMarshaler::<PackageTransaction>)] handle: &PackageTransaction,
+    tag: u32,
+    progress_callback: extern "C" fn(u32, cffi::Slice<u8>, u64, u64, u64, u64) -> u8,
+) -> Result<(), Box<dyn Error>> {
+    use crate::package_store::DownloadEvent;
+
+    let concurrency = handle
+        .store()
+        .config()
+        .read()
+        .unwrap()
+        .settings()
+        .download_concurrency();
+    let mut stream = handle.download(concurrency);
+
+    while let Some((key, event)) = block_on(stream.next()) {
+        match event {
+            DownloadEvent::Error(e) => return Err(e).box_err(),
+            DownloadEvent::Progress(x) => {
+                let k = PackageKeyMarshaler::to_foreign(&key).unwrap();
+                let is_continuing = progress_callback(
+                    tag,
+                    k,
+                    x.current,
+                    x.total,
+                    x.bytes_per_second.unwrap_or(0),
+                    x.eta_seconds.unwrap_or(0),
+                );
+                if is_continuing == 0 {
+                    return Err(DownloadError::UserCancelled).box_err();
+                }
+            }
+            DownloadEvent::Complete(_) => {}
+        }
+    }
+
+    Ok(())
+}
+
 #[cffi::marshal(return_marshaler = "cffi::UnitMarshaler")]
 pub extern "C" fn pahkat_prefix_transaction_process(
     #[marshal(cffi::BoxRefMarshaler::<PackageTransaction>)] handle: &PackageTransaction,
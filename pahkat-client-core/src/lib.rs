@@ -7,23 +7,59 @@ pub extern crate pahkat_types as types;
 #[cfg(feature = "ffi")]
 pub mod ffi;
 
+pub mod archive;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod bundle;
+pub mod cache;
 pub mod config;
 pub mod defaults;
+pub mod desired;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod embedded;
+pub mod eta;
+pub mod events;
+pub mod failures;
 pub mod package_store;
+pub mod power;
+pub mod priority;
 pub mod repo;
+pub mod report;
+#[cfg(feature = "schema")]
+pub mod schema;
+pub mod secret;
+pub mod throttle;
 pub mod transaction;
+pub mod trust;
+
+#[cfg(all(target_arch = "wasm32", feature = "wasm"))]
+pub mod wasm;
 
 mod cmp;
+mod delta;
 mod download;
 mod ext;
 mod fbs;
+mod proxy;
+mod tls;
 
 pub use self::config::{Config, Permission};
-pub use self::download::Download;
-pub use self::package_store::{DownloadEvent, InstallTarget, PackageStore};
+pub use self::download::{Download, DownloadError};
+#[cfg(not(target_arch = "wasm32"))]
+pub use self::embedded::EmbeddedPahkat;
+pub use self::package_store::{AsyncPackageStore, DownloadEvent, InstallTarget, PackageStore};
 pub use self::repo::{LoadedRepository, PackageKey};
+#[cfg(all(feature = "test-roots", not(target_arch = "wasm32")))]
+pub use self::tls::add_root_certificate;
+#[cfg(not(target_arch = "wasm32"))]
+pub use self::tls::ClientCertificateError;
 pub use self::transaction::{PackageAction, PackageActionType, PackageStatus, PackageTransaction};
 
+#[cfg(all(target_os = "ios", feature = "ios"))]
+pub use package_store::ios::IosPackageStore;
+
+#[cfg(all(target_os = "linux", feature = "linux"))]
+pub use package_store::linux::LinuxPackageStore;
+
 #[cfg(all(target_os = "macos", feature = "macos"))]
 pub use package_store::macos::MacOSPackageStore;
 
@@ -0,0 +1,169 @@
+//! Tarball installs into the container of an iOS app.
+//!
+//! Apps cannot start other processes, so only tarball payloads are supported, and are
+//! extracted in-process the same way as in a prefix. Downloads that should survive the
+//! app being suspended are made by the app with a background `URLSession`: it asks for
+//! [`IosPackageStore::download_url`] and hands the finished file over with
+//! [`PackageStore::import`].
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use hashbrown::HashMap;
+use pahkat_types::package::Package;
+use pahkat_types::repo::RepoUrl;
+use pahkat_types::AsDownloadUrl;
+use url::Url;
+
+use super::prefix::{Error, PrefixPackageStore};
+use super::{
+    DownloadEvent, ImportError, InstallTarget, LocalizedStrings, PackageStore, SharedRepoErrors,
+    SharedRepos, SharedStoreConfig,
+};
+use crate::repo::{PackageQuery, PayloadError, RepoDownloadError};
+use crate::transaction::{install::InstallError, uninstall::UninstallError};
+use crate::transaction::{
+    PackageDependencyStatusError, PackageStatus, PackageStatusError, ResolvedPackageQuery,
+};
+use crate::PackageKey;
+
+pub struct IosPackageStore {
+    inner: PrefixPackageStore,
+}
+
+impl IosPackageStore {
+    /// Opens the store kept in `Library/Application Support/Pahkat` of the app
+    /// container at `container_path`, creating it on first use.
+    pub async fn open_or_create<P: AsRef<Path>>(
+        container_path: P,
+    ) -> Result<IosPackageStore, Error> {
+        let path = Self::store_path(container_path.as_ref());
+        let inner = PrefixPackageStore::open_or_create(path).await?;
+        Ok(IosPackageStore { inner })
+    }
+
+    fn store_path(container_path: &Path) -> PathBuf {
+        container_path
+            .join("Library")
+            .join("Application Support")
+            .join("Pahkat")
+    }
+
+    /// The URL of the payload that installing `key` needs, for downloading with a
+    /// background `URLSession`.
+    pub fn download_url(&self, key: &PackageKey) -> Result<Url, PayloadError> {
+        let repos = self.inner.repos();
+        let repos = repos.read().unwrap();
+        let query = crate::repo::ReleaseQuery::new(key, &*repos);
+        let (target, _, _) = crate::repo::resolve_payload(key, &query, &*repos)?;
+        Ok(target.payload.as_download_url().clone())
+    }
+}
+
+impl PackageStore for IosPackageStore {
+    fn repos(&self) -> SharedRepos {
+        self.inner.repos()
+    }
+
+    fn errors(&self) -> SharedRepoErrors {
+        self.inner.errors()
+    }
+
+    fn config(&self) -> SharedStoreConfig {
+        self.inner.config()
+    }
+
+    fn import(&self, key: &PackageKey, installer_path: &Path) -> Result<PathBuf, ImportError> {
+        self.inner.import(key, installer_path)
+    }
+
+    fn download(
+        &self,
+        key: &PackageKey,
+    ) -> std::pin::Pin<Box<dyn futures::stream::Stream<Item = DownloadEvent> + Sync + Send + 'static>>
+    {
+        self.inner.download(key)
+    }
+
+    fn install(
+        &self,
+        key: &PackageKey,
+        target: InstallTarget,
+    ) -> Result<PackageStatus, InstallError> {
+        self.inner.install(key, target)
+    }
+
+    fn install_with_options(
+        &self,
+        key: &PackageKey,
+        target: InstallTarget,
+        options: &BTreeMap<String, String>,
+    ) -> Result<PackageStatus, InstallError> {
+        self.inner.install_with_options(key, target, options)
+    }
+
+    fn uninstall(
+        &self,
+        key: &PackageKey,
+        target: InstallTarget,
+    ) -> Result<PackageStatus, UninstallError> {
+        self.inner.uninstall(key, target)
+    }
+
+    fn status(
+        &self,
+        key: &PackageKey,
+        target: InstallTarget,
+    ) -> Result<PackageStatus, PackageStatusError> {
+        self.inner.status(key, target)
+    }
+
+    fn dependency_status(
+        &self,
+        key: &PackageKey,
+        target: InstallTarget,
+    ) -> Result<Vec<(PackageKey, PackageStatus)>, PackageDependencyStatusError> {
+        self.inner.dependency_status(key, target)
+    }
+
+    fn all_statuses(
+        &self,
+        repo_url: &RepoUrl,
+        target: InstallTarget,
+    ) -> BTreeMap<String, Result<PackageStatus, PackageStatusError>> {
+        self.inner.all_statuses(repo_url, target)
+    }
+
+    fn find_package_by_key(&self, key: &PackageKey) -> Option<Package> {
+        self.inner.find_package_by_key(key)
+    }
+
+    fn find_package_by_id(&self, package_id: &str) -> Option<(PackageKey, Package)> {
+        self.inner.find_package_by_id(package_id)
+    }
+
+    fn refresh_repos(
+        &self,
+    ) -> crate::package_store::Future<Result<(), HashMap<RepoUrl, RepoDownloadError>>> {
+        self.inner.refresh_repos()
+    }
+
+    fn clear_cache(&self) {
+        self.inner.clear_cache()
+    }
+
+    fn strings(
+        &self,
+        language: String,
+    ) -> crate::package_store::Future<HashMap<RepoUrl, LocalizedStrings>> {
+        self.inner.strings(language)
+    }
+
+    fn resolve_package_query(
+        &self,
+        query: PackageQuery,
+        install_target: &[InstallTarget],
+    ) -> ResolvedPackageQuery {
+        self.inner.resolve_package_query(query, install_target)
+    }
+}
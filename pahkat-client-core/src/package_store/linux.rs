@@ -0,0 +1,942 @@
+//! System-wide installation of tarball payloads on Linux.
+//!
+//! Payloads are extracted into a root such as `/usr/local` or `/opt`, and the files
+//! of each package are tracked in a database kept with the config, using the same
+//! schema as the prefix store.
+//!
+//! Debian packages are installed with apt instead, which also resolves their
+//! dependencies, and dpkg is asked for their status. RPM packages are installed
+//! and queried with rpm, and Flatpak refs with flatpak, either for the user or
+//! system wide depending on the install target. Snaps are installed system wide
+//! by snapd, from the snap channel matching the channel of the release.
+
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, read_dir, remove_dir, remove_file, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Output;
+use std::sync::{Arc, RwLock};
+
+use hashbrown::HashMap;
+use pahkat_types::package::Package;
+use pahkat_types::payload::Payload;
+use pahkat_types::repo::RepoUrl;
+use r2d2_sqlite::SqliteConnectionManager;
+use xz2::bufread::XzDecoder;
+
+use super::prefix::{extract, PackageDbRecord, PrefixPackageStore, SQL_INIT};
+use super::{
+    ImportError, InstallTarget, LocalizedStrings, PackageStore, SharedRepoErrors, SharedRepos,
+    SharedStoreConfig,
+};
+use crate::archive::{Limits as ArchiveLimits, Sanitizer};
+use crate::ext::DependencyKeyExt;
+use crate::repo::{PackageCandidateError, PackageQuery, RepoDownloadError};
+use crate::transaction::{install::InstallError, uninstall::UninstallError};
+use crate::transaction::{
+    PackageDependencyStatusError, PackageStatus, PackageStatusError, ResolvedPackageQuery,
+};
+use crate::{cmp, Config, PackageActionType, PackageKey};
+
+pub const DEFAULT_ROOT: &str = "/usr/local";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Could not create install root {}", .1.display())]
+    CreateRoot(#[source] std::io::Error, PathBuf),
+
+    #[error("Error connecting to database")]
+    DatabaseConnection(#[from] r2d2::Error),
+
+    #[error("Error processing SQL query")]
+    Database(#[from] rusqlite::Error),
+}
+
+pub struct LinuxPackageStore {
+    pool: r2d2::Pool<SqliteConnectionManager>,
+    root: PathBuf,
+    repos: SharedRepos,
+    errors: SharedRepoErrors,
+    config: SharedStoreConfig,
+}
+
+impl LinuxPackageStore {
+    /// Installs into [`DEFAULT_ROOT`].
+    pub async fn new(config: Config) -> Result<LinuxPackageStore, Error> {
+        Self::with_root(config, DEFAULT_ROOT).await
+    }
+
+    pub async fn with_root<P: AsRef<Path>>(
+        config: Config,
+        root: P,
+    ) -> Result<LinuxPackageStore, Error> {
+        let root = root.as_ref();
+        create_dir_all(root).map_err(|e| Error::CreateRoot(e, root.to_path_buf()))?;
+        let root = root
+            .canonicalize()
+            .map_err(|e| Error::CreateRoot(e, root.to_path_buf()))?;
+
+        let db_file_path = config.settings().config_dir().join("packages.sqlite");
+        let is_new = !db_file_path.exists();
+        log::debug!("Package database: {:?}", &db_file_path);
+
+        let manager = SqliteConnectionManager::file(&db_file_path);
+        let pool = PrefixPackageStore::make_pool(manager)?;
+        if is_new {
+            pool.get()?.execute_batch(SQL_INIT)?;
+        }
+
+        let store = LinuxPackageStore {
+            pool,
+            root,
+            repos: Default::default(),
+            errors: Default::default(),
+            config: Arc::new(RwLock::new(config)),
+        };
+
+        // We ignore failures here.
+        let _ = store.refresh_repos().await;
+
+        Ok(store)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn cached_payload(&self, url: &url::Url) -> Result<PathBuf, InstallError> {
+        let pkg_path = crate::repo::download_file_path(&*self.config.read().unwrap(), url);
+        if !pkg_path.exists() {
+            log::error!("Package path doesn't exist: {:?}", &pkg_path);
+            return Err(InstallError::PackageNotInCache);
+        }
+        Ok(pkg_path)
+    }
+
+    /// The payload installed for `key` if it is left to a system package manager,
+    /// which is either a Debian or an RPM package, a Flatpak or a snap.
+    fn system_package(&self, key: &PackageKey, install_target: InstallTarget) -> Option<Payload> {
+        let repos = self.repos.read().unwrap();
+        let query = crate::repo::ReleaseQuery::new(key, &*repos).and_payloads(vec![
+            "DebianPackage",
+            "RpmPackage",
+            "Flatpak",
+            "Snap",
+        ]);
+        let config = self.config.read().unwrap();
+        let (target, _, _, _) =
+            crate::repo::resolve_installed_payload(&*config, key, install_target, &query, &*repos)
+                .ok()?;
+        match target.payload {
+            x @ Payload::DebianPackage(_)
+            | x @ Payload::RpmPackage(_)
+            | x @ Payload::Flatpak(_)
+            | x @ Payload::Snap(_) => Some(x),
+            _ => None,
+        }
+    }
+}
+
+/// The install target is ignored except for Flatpaks, as every other package is
+/// installed under the root or by the system package manager.
+impl PackageStore for LinuxPackageStore {
+    fn repos(&self) -> SharedRepos {
+        Arc::clone(&self.repos)
+    }
+
+    fn errors(&self) -> SharedRepoErrors {
+        Arc::clone(&self.errors)
+    }
+
+    fn config(&self) -> SharedStoreConfig {
+        Arc::clone(&self.config)
+    }
+
+    fn import(&self, key: &PackageKey, installer_path: &Path) -> Result<PathBuf, ImportError> {
+        let repos = self.repos.read().unwrap();
+        let query = crate::repo::ReleaseQuery::new(key, &*repos);
+        crate::repo::import(&self.config, key, &query, &*repos, installer_path)
+    }
+
+    fn download(
+        &self,
+        key: &PackageKey,
+    ) -> std::pin::Pin<
+        Box<
+            dyn futures::stream::Stream<Item = crate::package_store::DownloadEvent>
+                + Sync
+                + Send
+                + 'static,
+        >,
+    > {
+        let repos = self.repos.read().unwrap();
+        let query = crate::repo::ReleaseQuery::new(key, &*repos);
+        crate::repo::download(&self.config, key, &query, &*repos)
+    }
+
+    fn install(
+        &self,
+        key: &PackageKey,
+        install_target: InstallTarget,
+    ) -> Result<PackageStatus, InstallError> {
+        self.install_with_options(key, install_target, &BTreeMap::new())
+    }
+
+    fn install_with_options(
+        &self,
+        key: &PackageKey,
+        install_target: InstallTarget,
+        options: &BTreeMap<String, String>,
+    ) -> Result<PackageStatus, InstallError> {
+        let repos = self.repos.read().unwrap();
+        let query = crate::repo::ReleaseQuery::new(key, &*repos);
+
+        let (target, release, _) =
+            crate::repo::resolve_payload(key, &query, &*repos).map_err(InstallError::Payload)?;
+        let installer = match target.payload {
+            Payload::TarballPackage(v) => v,
+            Payload::DebianPackage(v) => {
+                let pkg_path = self.cached_payload(&v.url)?;
+                log::debug!("Installing {} with apt: {:?}", &key, &pkg_path);
+                dpkg::install(&pkg_path).map_err(InstallError::InstallerFailure)?;
+                return Ok(PackageStatus::UpToDate);
+            }
+            Payload::RpmPackage(v) => {
+                let pkg_path = self.cached_payload(&v.url)?;
+                log::debug!("Installing {} with rpm: {:?}", &key, &pkg_path);
+                rpm::install(&pkg_path).map_err(InstallError::InstallerFailure)?;
+                return Ok(PackageStatus::UpToDate);
+            }
+            Payload::Flatpak(v) => {
+                log::debug!("Installing {} with flatpak: {}", &key, &v.flatpak_ref);
+                flatpak::install(&v, install_target).map_err(InstallError::InstallerFailure)?;
+                return Ok(PackageStatus::UpToDate);
+            }
+            Payload::Snap(v) => {
+                let channel = v.channel_for(release.channel.as_deref());
+                log::debug!("Installing {} with snap: {} ({})", &key, &v.name, &channel);
+                snap::install(&v, &channel).map_err(InstallError::InstallerFailure)?;
+                return Ok(PackageStatus::UpToDate);
+            }
+            _ => return Err(InstallError::WrongPayloadType),
+        };
+        let options = pahkat_types::payload::resolve_install_options(&installer.options, options)?;
+        let pkg_path = self.cached_payload(&installer.url)?;
+        log::debug!("Installing {} into {:?}: {:?}", &key, &self.root, &pkg_path);
+
+        let file = File::open(&pkg_path).map_err(|e| InstallError::ExtractFailed(e.into()))?;
+        let len = file
+            .metadata()
+            .map_err(|e| InstallError::ExtractFailed(e.into()))?
+            .len();
+        let mut sanitizer = Sanitizer::new(len, ArchiveLimits::default());
+        let reader = XzDecoder::new(std::io::BufReader::new(file));
+        let mut tar_file = tar::Archive::new(reader);
+
+        let install_dir = installer
+            .install_dir()?
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+
+        let files = extract::unpack(
+            &mut tar_file,
+            &mut sanitizer,
+            &self.root,
+            &install_dir,
+            &installer,
+            &options,
+        )?;
+
+        let dependencies = target
+            .dependencies
+            .keys()
+            .filter_map(|x| x.to_package_key(&key.repository_url).ok())
+            .map(|x| x.to_string())
+            .collect();
+
+        let record = PackageDbRecord {
+            id: 0,
+            url: key.clone().without_query_params().to_string(),
+            version: release.version.to_string(),
+            files,
+            dependencies,
+        };
+
+        let mut conn = self.pool.get().unwrap();
+        record.save(&mut conn).unwrap();
+
+        Ok(PackageStatus::UpToDate)
+    }
+
+    fn uninstall(
+        &self,
+        key: &PackageKey,
+        target: InstallTarget,
+    ) -> Result<PackageStatus, UninstallError> {
+        let mut conn = self.pool.get().unwrap();
+        let record = match PackageDbRecord::find_by_id(&mut conn, &key) {
+            Some(v) => v,
+            None => {
+                let result = match self.system_package(key, target) {
+                    Some(Payload::DebianPackage(p)) => {
+                        log::debug!("Removing {} with apt: {}", &key, &p.package);
+                        dpkg::remove(&p.package)
+                    }
+                    Some(Payload::RpmPackage(p)) => {
+                        log::debug!("Removing {} with rpm: {}", &key, &p.package);
+                        rpm::erase(&p.package)
+                    }
+                    Some(Payload::Flatpak(p)) => {
+                        log::debug!("Removing {} with flatpak: {}", &key, &p.flatpak_ref);
+                        flatpak::uninstall(&p.flatpak_ref, target)
+                    }
+                    Some(Payload::Snap(p)) => {
+                        log::debug!("Removing {} with snap: {}", &key, &p.name);
+                        snap::remove(&p.name)
+                    }
+                    _ => return Err(UninstallError::NotInstalled),
+                };
+                result.map_err(UninstallError::UninstallerFailure)?;
+                return Ok(PackageStatus::NotInstalled);
+            }
+        };
+
+        // Files also installed by another package are left for that package
+        let files = record
+            .files
+            .iter()
+            .filter(|x| !record.is_shared(&mut conn, x))
+            .map(|x| self.root.join(x))
+            .collect::<Vec<_>>();
+
+        for file in files.iter() {
+            // Links are removed themselves rather than what they point to
+            match std::fs::symlink_metadata(file) {
+                Ok(meta) if !meta.is_dir() => {
+                    if let Err(e) = remove_file(file) {
+                        log::warn!("Could not remove {:?}: {}", file, e);
+                    }
+                }
+                _ => continue,
+            }
+        }
+
+        // Deepest first, so that directories emptied by removing their children go too
+        let mut dirs = files.iter().filter(|x| x.is_dir()).collect::<Vec<_>>();
+        dirs.sort_by_key(|x| std::cmp::Reverse(x.components().count()));
+        for dir in dirs {
+            let is_empty = read_dir(dir)
+                .map(|mut x| x.next().is_none())
+                .unwrap_or(false);
+            if is_empty && dir != &self.root {
+                let _ = remove_dir(dir);
+            }
+        }
+
+        record.delete(&mut conn).unwrap();
+
+        Ok(PackageStatus::NotInstalled)
+    }
+
+    fn status(
+        &self,
+        key: &PackageKey,
+        install_target: InstallTarget,
+    ) -> Result<PackageStatus, PackageStatusError> {
+        let mut conn = self.pool.get().unwrap();
+        let record = PackageDbRecord::find_by_id(&mut conn, &key);
+
+        let repos = self.repos.read().unwrap();
+        let query = crate::repo::ReleaseQuery::new(key, &*repos).and_payloads(vec![
+            "TarballPackage",
+            "DebianPackage",
+            "RpmPackage",
+            "Flatpak",
+            "Snap",
+        ]);
+
+        let config = self.config.read().unwrap();
+        let (target, release, _, is_published) = match crate::repo::resolve_installed_payload(
+            &*config,
+            key,
+            install_target,
+            &query,
+            &*repos,
+        ) {
+            Ok(v) => v,
+            Err(_) if record.is_none() => return Ok(PackageStatus::NotInstalled),
+            Err(e) => return Err(PackageStatusError::Payload(e)),
+        };
+
+        let status = match (target.payload, record) {
+            (Payload::TarballPackage(_), None) => Ok(PackageStatus::NotInstalled),
+            (Payload::TarballPackage(_), Some(record)) => {
+                cmp::cmp(&record.version, &release.version)
+            }
+            (Payload::DebianPackage(p), _) => match dpkg::installed_version(&p.package) {
+                Ok(Some(version)) => cmp::cmp(
+                    pahkat_types::payload::debian::upstream_version(&version),
+                    &release.version,
+                ),
+                Ok(None) => Ok(PackageStatus::NotInstalled),
+                Err(e) => {
+                    log::error!("dpkg-query: {:?}", e);
+                    Ok(PackageStatus::NotInstalled)
+                }
+            },
+            (Payload::RpmPackage(p), _) => match rpm::installed_version(&p.package) {
+                Ok(Some(version)) => cmp::cmp(&version, &release.version),
+                Ok(None) => Ok(PackageStatus::NotInstalled),
+                Err(e) => {
+                    log::error!("rpm: {:?}", e);
+                    Ok(PackageStatus::NotInstalled)
+                }
+            },
+            (Payload::Flatpak(p), _) => match flatpak::info(&p.flatpak_ref, install_target) {
+                Ok(Some(flatpak::Info {
+                    version: Some(version),
+                })) => cmp::cmp(&version, &release.version),
+                // Without a version in its metadata, what is installed is taken to be current
+                Ok(Some(_)) => Ok(PackageStatus::UpToDate),
+                Ok(None) => Ok(PackageStatus::NotInstalled),
+                Err(e) => {
+                    log::error!("flatpak: {:?}", e);
+                    Ok(PackageStatus::NotInstalled)
+                }
+            },
+            (Payload::Snap(p), _) => match snap::installed_version(&p.name) {
+                Ok(Some(version)) => cmp::cmp(&version, &release.version),
+                Ok(None) => Ok(PackageStatus::NotInstalled),
+                Err(e) => {
+                    log::error!("snap: {:?}", e);
+                    Ok(PackageStatus::NotInstalled)
+                }
+            },
+            _ => return Err(PackageStatusError::WrongPayloadType),
+        };
+
+        crate::repo::published_status(is_published, status)
+    }
+
+    fn dependency_status(
+        &self,
+        key: &PackageKey,
+        target: InstallTarget,
+    ) -> Result<Vec<(PackageKey, PackageStatus)>, PackageDependencyStatusError> {
+        crate::repo::resolve_package_set(self, &[(PackageActionType::Install, key.clone(), target)])
+            .map(|dep| {
+                dep.into_iter()
+                    .map(|dep| (dep.package_key, dep.status))
+                    .collect()
+            })
+            .map_err(|err| match err {
+                PackageCandidateError::Status(p, PackageStatusError::Payload(e)) => {
+                    PackageDependencyStatusError::Payload(p, e)
+                }
+                PackageCandidateError::Status(p, PackageStatusError::WrongPayloadType) => {
+                    PackageDependencyStatusError::WrongPayloadType(p)
+                }
+                PackageCandidateError::Status(p, PackageStatusError::ParsingVersion) => {
+                    PackageDependencyStatusError::ParsingVersion(p)
+                }
+                PackageCandidateError::Status(p, PackageStatusError::Cancelled(e)) => {
+                    PackageDependencyStatusError::Cancelled(p, e)
+                }
+                PackageCandidateError::Payload(p, e) => PackageDependencyStatusError::Payload(p, e),
+                PackageCandidateError::UnresolvedId(id)
+                | PackageCandidateError::VirtualUnavailable(id) => {
+                    PackageDependencyStatusError::PackageNotFound(id)
+                }
+                PackageCandidateError::UninstallConflict(_)
+                | PackageCandidateError::InvalidOption(..)
+                | PackageCandidateError::TargetConflict(..) => unreachable!(),
+                PackageCandidateError::ClientUpdateRequired(p, v) => {
+                    PackageDependencyStatusError::ClientUpdateRequired(p, v)
+                }
+                PackageCandidateError::UnsatisfiedDependency(p, req, v) => {
+                    PackageDependencyStatusError::UnsatisfiedDependency(p, req, v)
+                }
+            })
+    }
+
+    fn all_statuses(
+        &self,
+        repo_url: &RepoUrl,
+        target: InstallTarget,
+    ) -> BTreeMap<String, Result<PackageStatus, PackageStatusError>> {
+        crate::repo::all_statuses(self, repo_url, target)
+    }
+
+    fn find_package_by_key(&self, key: &PackageKey) -> Option<Package> {
+        let repos = self.repos.read().unwrap();
+        crate::repo::find_package_by_key(key, &*repos)
+    }
+
+    fn find_package_by_id(&self, package_id: &str) -> Option<(PackageKey, Package)> {
+        let repos = self.repos.read().unwrap();
+        crate::repo::find_package_by_id(self, package_id, &*repos)
+    }
+
+    fn refresh_repos(
+        &self,
+    ) -> crate::package_store::Future<Result<(), HashMap<RepoUrl, RepoDownloadError>>> {
+        let config = self.config().read().unwrap().clone();
+        let requested = config.repos().keys().cloned().collect::<Vec<_>>();
+        let shared_config = self.config();
+        let repos = self.repos();
+        Box::pin(async move {
+            let (mut result, mut errors) = crate::repo::refresh_repos(config).await;
+            crate::repo::discard_removed_repos(
+                &*shared_config.read().unwrap(),
+                &requested,
+                &mut result,
+                &mut errors,
+            );
+            *repos.write().unwrap() = result;
+            crate::events::global().publish(crate::events::StoreEvent::RepositoriesChanged);
+            if errors.is_empty() {
+                Ok(())
+            } else {
+                Err(errors)
+            }
+        })
+    }
+
+    fn clear_cache(&self) {
+        crate::repo::clear_cache(&self.config)
+    }
+
+    fn strings(
+        &self,
+        language: String,
+    ) -> crate::package_store::Future<HashMap<RepoUrl, LocalizedStrings>> {
+        let repos = self.repos.read().unwrap();
+        let urls = repos.keys().cloned().collect::<Vec<_>>();
+        let proxy = self.config.read().unwrap().settings().proxy().cloned();
+
+        Box::pin(crate::repo::strings(urls, language, proxy))
+    }
+
+    fn resolve_package_query(
+        &self,
+        query: PackageQuery,
+        install_target: &[InstallTarget],
+    ) -> ResolvedPackageQuery {
+        let repos = self.repos();
+        let repos = repos.read().unwrap();
+        crate::repo::resolve_package_query(
+            |key, target| self.status(key, target).ok(),
+            &query,
+            install_target,
+            &*repos,
+        )
+    }
+}
+
+fn check(
+    output: io::Result<Output>,
+    program: &str,
+) -> Result<Output, crate::transaction::install::ProcessError> {
+    use crate::transaction::install::ProcessError;
+
+    let output = output.map_err(|e| {
+        log::error!("{}: {:?}", program, &e);
+        ProcessError::Io(e)
+    })?;
+    if !output.status.success() {
+        log::error!("{}: {:?}", program, &output);
+        return Err(ProcessError::Unknown(output));
+    }
+    Ok(output)
+}
+
+mod dpkg {
+    use std::io;
+    use std::path::Path;
+
+    use pahkat_types::payload::debian;
+
+    use super::check;
+    use crate::transaction::install::ProcessError;
+
+    /// Names come from the index, and must not be taken as options by apt.
+    fn check_name(name: &str) -> Result<(), ProcessError> {
+        if debian::is_valid_name(name) {
+            return Ok(());
+        }
+        Err(ProcessError::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid Debian package name: {:?}", name),
+        )))
+    }
+
+    pub(super) fn install(path: &Path) -> Result<(), ProcessError> {
+        // apt only takes the argument as a file rather than a package name if it has a `/`
+        let path = path.canonicalize()?;
+        let output = crate::priority::command("apt-get")
+            .env("DEBIAN_FRONTEND", "noninteractive")
+            .args(&["install", "-y", "--no-install-recommends"])
+            .arg(&path)
+            .output();
+        check(output, "apt-get").map(|_| ())
+    }
+
+    pub(super) fn remove(name: &str) -> Result<(), ProcessError> {
+        check_name(name)?;
+        let output = crate::priority::command("apt-get")
+            .env("DEBIAN_FRONTEND", "noninteractive")
+            .args(&["remove", "-y", name])
+            .output();
+        check(output, "apt-get").map(|_| ())
+    }
+
+    /// The version of `name` as dpkg has it, or `None` if it is not installed. A
+    /// package that was removed but whose config files were kept is not installed.
+    pub(super) fn installed_version(name: &str) -> Result<Option<String>, ProcessError> {
+        check_name(name)?;
+        let output = std::process::Command::new("dpkg-query")
+            .args(&["-W", "-f", "${db:Status-Status}\t${Version}", name])
+            .output();
+
+        match output {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Ok(v) if v.status.code() == Some(1) => return Ok(None),
+            _ => {}
+        }
+
+        let output = check(output, "dpkg-query")?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        Ok(parse_status(&stdout))
+    }
+
+    fn parse_status(stdout: &str) -> Option<String> {
+        match stdout.trim().split_once('\t') {
+            Some(("installed", version)) if !version.is_empty() => Some(version.to_string()),
+            _ => None,
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn status() {
+            assert_eq!(
+                parse_status("installed\t1:2.0.1-1\n"),
+                Some("1:2.0.1-1".to_string())
+            );
+            assert_eq!(parse_status("config-files\t2.0.1-1"), None);
+            assert_eq!(parse_status("not-installed\t"), None);
+        }
+    }
+}
+
+mod rpm {
+    use std::io;
+    use std::path::Path;
+
+    use pahkat_types::payload::rpm;
+
+    use super::check;
+    use crate::transaction::install::ProcessError;
+
+    /// Names come from the index, and must not be taken as options by rpm.
+    fn check_name(name: &str) -> Result<(), ProcessError> {
+        if rpm::is_valid_name(name) {
+            return Ok(());
+        }
+        Err(ProcessError::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid RPM package name: {:?}", name),
+        )))
+    }
+
+    /// Installs or upgrades to the package at `path`. Reinstalling the same version
+    /// is not an error.
+    pub(super) fn install(path: &Path) -> Result<(), ProcessError> {
+        let path = path.canonicalize()?;
+        let output = crate::priority::command("rpm")
+            .args(&["-U", "--replacepkgs"])
+            .arg(&path)
+            .output();
+        check(output, "rpm").map(|_| ())
+    }
+
+    pub(super) fn erase(name: &str) -> Result<(), ProcessError> {
+        check_name(name)?;
+        let output = crate::priority::command("rpm").args(&["-e", name]).output();
+        check(output, "rpm").map(|_| ())
+    }
+
+    /// The version of `name` as rpm has it, or `None` if it is not installed.
+    pub(super) fn installed_version(name: &str) -> Result<Option<String>, ProcessError> {
+        check_name(name)?;
+        let output = std::process::Command::new("rpm")
+            .args(&["-q", "--queryformat", "%{VERSION}\\n", name])
+            .output();
+
+        match output {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Ok(v) if v.status.code() == Some(1) => return Ok(None),
+            _ => {}
+        }
+
+        let output = check(output, "rpm")?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        Ok(parse_version(&stdout))
+    }
+
+    /// Several versions of a package can be installed side by side, in which case
+    /// the last, most recently installed one is taken. RPM versions cannot contain
+    /// `-`, so a prerelease is packaged with `~` in its place, which is turned back.
+    fn parse_version(stdout: &str) -> Option<String> {
+        stdout
+            .lines()
+            .map(str::trim)
+            .filter(|x| !x.is_empty())
+            .last()
+            .map(|x| x.replace('~', "-"))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn version() {
+            assert_eq!(parse_version("2.0.1\n"), Some("2.0.1".to_string()));
+            assert_eq!(
+                parse_version("1.0.0~beta.1\n"),
+                Some("1.0.0-beta.1".to_string())
+            );
+            assert_eq!(
+                parse_version("5.14.0\n5.15.2\n"),
+                Some("5.15.2".to_string())
+            );
+            assert_eq!(parse_version(""), None);
+        }
+    }
+}
+
+mod flatpak {
+    use std::io;
+
+    use pahkat_types::payload::flatpak;
+
+    use super::check;
+    use crate::package_store::InstallTarget;
+    use crate::transaction::install::ProcessError;
+
+    #[derive(Debug, PartialEq, Eq)]
+    pub(super) struct Info {
+        pub version: Option<String>,
+    }
+
+    fn installation(target: InstallTarget) -> &'static str {
+        match target {
+            InstallTarget::System => "--system",
+            InstallTarget::User => "--user",
+        }
+    }
+
+    /// Names come from the index, and must not be taken as options by flatpak.
+    fn check_arg(is_valid: bool, kind: &str, value: &str) -> Result<(), ProcessError> {
+        if is_valid {
+            return Ok(());
+        }
+        Err(ProcessError::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid flatpak {}: {:?}", kind, value),
+        )))
+    }
+
+    /// Adds the remote if it is not configured yet, then installs the ref or updates
+    /// it if it already is.
+    pub(super) fn install(
+        payload: &flatpak::Package,
+        target: InstallTarget,
+    ) -> Result<(), ProcessError> {
+        check_arg(
+            flatpak::is_valid_remote(&payload.remote),
+            "remote",
+            &payload.remote,
+        )?;
+        check_arg(
+            flatpak::is_valid_ref(&payload.flatpak_ref),
+            "ref",
+            &payload.flatpak_ref,
+        )?;
+
+        let output = crate::priority::command("flatpak")
+            .args(&["remote-add", installation(target), "--if-not-exists"])
+            .args(&[&payload.remote, payload.url.as_str()])
+            .output();
+        check(output, "flatpak").map(|_| ())?;
+
+        let output = crate::priority::command("flatpak")
+            .args(&[
+                "install",
+                installation(target),
+                "--noninteractive",
+                "--or-update",
+                "-y",
+            ])
+            .args(&[&payload.remote, &payload.flatpak_ref])
+            .output();
+        check(output, "flatpak").map(|_| ())
+    }
+
+    pub(super) fn uninstall(flatpak_ref: &str, target: InstallTarget) -> Result<(), ProcessError> {
+        check_arg(flatpak::is_valid_ref(flatpak_ref), "ref", flatpak_ref)?;
+        let output = crate::priority::command("flatpak")
+            .args(&[
+                "uninstall",
+                installation(target),
+                "--noninteractive",
+                "-y",
+                flatpak_ref,
+            ])
+            .output();
+        check(output, "flatpak").map(|_| ())
+    }
+
+    /// What is installed of `flatpak_ref` in the installation for `target`, or `None`
+    /// if it is not installed there.
+    pub(super) fn info(
+        flatpak_ref: &str,
+        target: InstallTarget,
+    ) -> Result<Option<Info>, ProcessError> {
+        check_arg(flatpak::is_valid_ref(flatpak_ref), "ref", flatpak_ref)?;
+        // The labels of the output are translated
+        let output = std::process::Command::new("flatpak")
+            .env("LC_ALL", "C")
+            .args(&["info", installation(target), flatpak_ref])
+            .output();
+
+        match output {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Ok(v) if v.status.code() == Some(1) => return Ok(None),
+            _ => {}
+        }
+
+        let output = check(output, "flatpak")?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        Ok(Some(parse_info(&stdout)))
+    }
+
+    fn parse_info(stdout: &str) -> Info {
+        let version = stdout
+            .lines()
+            .filter_map(|x| x.trim().strip_prefix("Version:"))
+            .map(|x| x.trim().to_string())
+            .find(|x| !x.is_empty());
+        Info { version }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn info() {
+            let stdout = "\nDivvun Manager - Language tools\n\n          ID: org.divvun.Manager\n         Ref: app/org.divvun.Manager/x86_64/stable\n     Version: 2.1.0\n      Origin: divvun\n";
+            assert_eq!(parse_info(stdout).version, Some("2.1.0".to_string()));
+            assert_eq!(
+                parse_info("          ID: org.divvun.Manager\n").version,
+                None
+            );
+        }
+    }
+}
+
+mod snap {
+    use std::io;
+
+    use pahkat_types::payload::snap;
+
+    use super::check;
+    use crate::transaction::install::ProcessError;
+
+    /// Both the name and the channel end up as arguments to snap.
+    fn check_arg(is_valid: bool, kind: &str, value: &str) -> Result<(), ProcessError> {
+        if is_valid {
+            return Ok(());
+        }
+        Err(ProcessError::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid snap {}: {:?}", kind, value),
+        )))
+    }
+
+    /// Installs the snap from `channel`, or refreshes it to track `channel` if it
+    /// is already installed.
+    pub(super) fn install(payload: &snap::Package, channel: &str) -> Result<(), ProcessError> {
+        check_arg(snap::is_valid_name(&payload.name), "name", &payload.name)?;
+        check_arg(snap::is_valid_channel(channel), "channel", channel)?;
+
+        let command = match installed_version(&payload.name)? {
+            Some(_) => "refresh",
+            None => "install",
+        };
+        let mut args = vec![command, &payload.name, "--channel", channel];
+        if payload.classic {
+            args.push("--classic");
+        }
+
+        let output = crate::priority::command("snap").args(&args).output();
+        check(output, "snap").map(|_| ())
+    }
+
+    pub(super) fn remove(name: &str) -> Result<(), ProcessError> {
+        check_arg(snap::is_valid_name(name), "name", name)?;
+        let output = crate::priority::command("snap")
+            .args(&["remove", name])
+            .output();
+        check(output, "snap").map(|_| ())
+    }
+
+    /// The version of the installed snap, or `None` if it is not installed.
+    pub(super) fn installed_version(name: &str) -> Result<Option<String>, ProcessError> {
+        check_arg(snap::is_valid_name(name), "name", name)?;
+        let output = std::process::Command::new("snap")
+            .env("LC_ALL", "C")
+            .args(&["list", name])
+            .output();
+
+        match output {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Ok(v) if v.status.code() == Some(1) => return Ok(None),
+            _ => {}
+        }
+
+        let output = check(output, "snap")?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        Ok(parse_list(&stdout, name))
+    }
+
+    fn parse_list(stdout: &str, name: &str) -> Option<String> {
+        stdout
+            .lines()
+            .skip(1)
+            .map(|x| x.split_whitespace().collect::<Vec<_>>())
+            .find(|x| x.first() == Some(&name))
+            .and_then(|x| x.get(1).map(|x| x.to_string()))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn list() {
+            let stdout = "Name            Version  Rev  Tracking       Publisher  Notes\n\
+                          divvun-manager  2.1.0    42   latest/stable  divvun     -\n";
+            assert_eq!(
+                parse_list(stdout, "divvun-manager"),
+                Some("2.1.0".to_string())
+            );
+            assert_eq!(parse_list(stdout, "divvun"), None);
+        }
+    }
+}
@@ -1,3 +1,7 @@
+mod actions;
+mod app_bundle;
+mod receipts;
+
 use std::collections::BTreeMap;
 use std::fmt::Display;
 use std::fs::{remove_dir, remove_file};
@@ -24,6 +28,8 @@ use crate::transaction::{
 };
 use crate::{cmp, Config, PackageActionType, PackageKey};
 
+pub use self::actions::enable_input_sources;
+
 #[cfg(target_os = "macos")]
 #[inline(always)]
 pub fn global_uninstall_path() -> PathBuf {
@@ -75,6 +81,15 @@ impl PackageStore for MacOSPackageStore {
         &self,
         key: &PackageKey,
         install_target: InstallTarget,
+    ) -> Result<PackageStatus, InstallError> {
+        self.install_with_options(key, install_target, &BTreeMap::new())
+    }
+
+    fn install_with_options(
+        &self,
+        key: &PackageKey,
+        install_target: InstallTarget,
+        options: &BTreeMap<String, String>,
     ) -> Result<PackageStatus, InstallError> {
         let repos = self.repos.read().unwrap();
         let query = crate::repo::ReleaseQuery::new(key, &*repos);
@@ -83,8 +98,25 @@ impl PackageStore for MacOSPackageStore {
             crate::repo::resolve_payload(key, &query, &*repos).map_err(InstallError::Payload)?;
         let installer = match target.payload {
             pahkat_types::payload::Payload::MacOSPackage(v) => v,
+            pahkat_types::payload::Payload::MacOSAppBundle(v) => {
+                let zip_path =
+                    crate::repo::download_file_path(&self.config.read().unwrap(), &v.url);
+                log::debug!("Installing {}: {:?}", &key, &zip_path);
+                if !zip_path.exists() {
+                    log::error!("Package path doesn't exist: {:?}", &zip_path);
+                    return Err(InstallError::PackageNotInCache);
+                }
+
+                app_bundle::install(&zip_path, &v, install_target)?;
+                actions::run(&v.actions);
+
+                return Ok(self
+                    .status_impl(&descriptor, &release, install_target)
+                    .unwrap());
+            }
             _ => return Err(InstallError::WrongPayloadType),
         };
+        let options = pahkat_types::payload::resolve_install_options(&installer.options, options)?;
         let pkg_path =
             crate::repo::download_file_path(&*self.config.read().unwrap(), &installer.url);
         log::debug!("Installing {}: {:?}", &key, &pkg_path);
@@ -94,13 +126,66 @@ impl PackageStore for MacOSPackageStore {
             return Err(InstallError::PackageNotInCache);
         }
 
-        install_macos_package(&pkg_path, install_target).map_err(InstallError::InstallerFailure)?;
+        if let Some(team_id) = installer.team_id.as_ref() {
+            verify_pkg_signature(&pkg_path, team_id).map_err(InstallError::InvalidSignature)?;
+        }
+
+        let choice_target = match install_target {
+            InstallTarget::System => pahkat_types::payload::macos::InstallTarget::System,
+            InstallTarget::User => pahkat_types::payload::macos::InstallTarget::User,
+        };
+        let choice_changes = match installer.choice_changes_for(choice_target) {
+            Some((value, sha256)) => Some(
+                choice_changes_path(&self.config.read().unwrap(), &pkg_path, value, sha256)
+                    .map_err(|e| InstallError::InstallerFailure(ProcessError::Io(e)))?,
+            ),
+            None => None,
+        };
+        let choice_changes = if options.is_empty() {
+            choice_changes
+        } else {
+            Some(
+                choice_changes_with_options(&pkg_path, choice_changes.as_deref(), &options)
+                    .map_err(|e| InstallError::InstallerFailure(ProcessError::Io(e)))?,
+            )
+        };
+
+        install_macos_package(&pkg_path, install_target, choice_changes.as_deref())
+            .map_err(InstallError::InstallerFailure)?;
+        actions::run(&installer.actions);
+
+        if let InstallTarget::User = install_target {
+            let paths = get_package_info(&installer.pkg_id, install_target)
+                .map(|x| x.paths())
+                .unwrap_or_default();
+            receipts::record(
+                &installer.pkg_id,
+                receipts::Receipt {
+                    version: release.version.to_string(),
+                    paths,
+                },
+            );
+        }
 
         Ok(self
             .status_impl(&descriptor, &release, install_target)
             .unwrap())
     }
 
+    fn preflight(
+        &self,
+        key: &PackageKey,
+        options: &BTreeMap<String, String>,
+    ) -> Result<(), InstallError> {
+        let (payload, pkg_path) = super::preflight_payload(self, key, options)?;
+        if let pahkat_types::payload::Payload::MacOSPackage(v) = payload {
+            if let Some(team_id) = v.team_id.as_ref() {
+                verify_pkg_signature(&pkg_path, team_id).map_err(InstallError::InvalidSignature)?;
+            }
+        }
+        Ok(())
+    }
+
     fn uninstall(
         &self,
         key: &PackageKey,
@@ -109,15 +194,24 @@ impl PackageStore for MacOSPackageStore {
         let repos = self.repos.read().unwrap();
         let query = crate::repo::ReleaseQuery::new(key, &*repos);
 
-        let (target, release, descriptor) =
-            crate::repo::resolve_payload(key, &query, &*repos).map_err(UninstallError::Payload)?;
-        let installer = match target.payload {
-            pahkat_types::payload::Payload::MacOSPackage(v) => v,
+        let (target, release, descriptor, _) = crate::repo::resolve_installed_payload(
+            &self.config.read().unwrap(),
+            key,
+            install_target,
+            &query,
+            &repos,
+        )
+        .map_err(UninstallError::Payload)?;
+        match target.payload {
+            pahkat_types::payload::Payload::MacOSPackage(v) => {
+                uninstall_macos_package(&v.pkg_id, install_target)
+            }
+            pahkat_types::payload::Payload::MacOSAppBundle(v) => {
+                app_bundle::uninstall(&v, install_target)
+            }
             _ => return Err(UninstallError::WrongPayloadType),
-        };
-
-        uninstall_macos_package(&installer.pkg_id, install_target)
-            .map_err(UninstallError::UninstallerFailure)?;
+        }
+        .map_err(UninstallError::UninstallerFailure)?;
 
         Ok(self
             .status_impl(&descriptor, &release, install_target)
@@ -154,14 +248,24 @@ impl PackageStore for MacOSPackageStore {
         let repos = self.repos.read().unwrap();
         let query = crate::repo::ReleaseQuery::new(key, &*repos);
 
-        let (target, release, descriptor) = crate::repo::resolve_payload(key, &query, &*repos)
-            .map_err(PackageStatusError::Payload)?;
-        let installer = match target.payload {
-            pahkat_types::payload::Payload::MacOSPackage(v) => v,
+        let (target, release, descriptor, is_published) = crate::repo::resolve_installed_payload(
+            &self.config.read().unwrap(),
+            key,
+            install_target,
+            &query,
+            &repos,
+        )
+        .map_err(PackageStatusError::Payload)?;
+        match target.payload {
+            pahkat_types::payload::Payload::MacOSPackage(_)
+            | pahkat_types::payload::Payload::MacOSAppBundle(_) => {}
             _ => return Err(PackageStatusError::WrongPayloadType),
         };
 
-        self.status_impl(&descriptor, &release, install_target)
+        crate::repo::published_status(
+            is_published,
+            self.status_impl(&descriptor, &release, install_target),
+        )
     }
 
     fn dependency_status(
@@ -169,33 +273,41 @@ impl PackageStore for MacOSPackageStore {
         key: &PackageKey,
         target: InstallTarget,
     ) -> Result<Vec<(PackageKey, PackageStatus)>, PackageDependencyStatusError> {
-        crate::repo::resolve_package_set(
-            self,
-            &[(PackageActionType::Install, key.clone())],
-            &[target],
-        )
-        .map(|dep| {
-            dep.into_iter()
-                .map(|dep| (dep.package_key, dep.status))
-                .collect()
-        })
-        .map_err(|err| match err {
-            PackageCandidateError::Status(p, PackageStatusError::Payload(e)) => {
-                PackageDependencyStatusError::Payload(p, e)
-            }
-            PackageCandidateError::Status(p, PackageStatusError::WrongPayloadType) => {
-                PackageDependencyStatusError::WrongPayloadType(p)
-            }
-            PackageCandidateError::Status(p, PackageStatusError::ParsingVersion) => {
-                PackageDependencyStatusError::ParsingVersion(p)
-            }
+        crate::repo::resolve_package_set(self, &[(PackageActionType::Install, key.clone(), target)])
+            .map(|dep| {
+                dep.into_iter()
+                    .map(|dep| (dep.package_key, dep.status))
+                    .collect()
+            })
+            .map_err(|err| match err {
+                PackageCandidateError::Status(p, PackageStatusError::Payload(e)) => {
+                    PackageDependencyStatusError::Payload(p, e)
+                }
+                PackageCandidateError::Status(p, PackageStatusError::WrongPayloadType) => {
+                    PackageDependencyStatusError::WrongPayloadType(p)
+                }
+                PackageCandidateError::Status(p, PackageStatusError::ParsingVersion) => {
+                    PackageDependencyStatusError::ParsingVersion(p)
+                }
+                PackageCandidateError::Status(p, PackageStatusError::Cancelled(e)) => {
+                    PackageDependencyStatusError::Cancelled(p, e)
+                }
 
-            PackageCandidateError::Payload(p, e) => PackageDependencyStatusError::Payload(p, e),
-            PackageCandidateError::UnresolvedId(id) => {
-                PackageDependencyStatusError::PackageNotFound(id)
-            }
-            PackageCandidateError::UninstallConflict(_) => unreachable!(),
-        })
+                PackageCandidateError::Payload(p, e) => PackageDependencyStatusError::Payload(p, e),
+                PackageCandidateError::UnresolvedId(id)
+                | PackageCandidateError::VirtualUnavailable(id) => {
+                    PackageDependencyStatusError::PackageNotFound(id)
+                }
+                PackageCandidateError::UninstallConflict(_)
+                | PackageCandidateError::InvalidOption(..)
+                | PackageCandidateError::TargetConflict(..) => unreachable!(),
+                PackageCandidateError::ClientUpdateRequired(p, v) => {
+                    PackageDependencyStatusError::ClientUpdateRequired(p, v)
+                }
+                PackageCandidateError::UnsatisfiedDependency(p, req, v) => {
+                    PackageDependencyStatusError::UnsatisfiedDependency(p, req, v)
+                }
+            })
     }
 
     fn all_statuses(
@@ -220,10 +332,19 @@ impl PackageStore for MacOSPackageStore {
         &self,
     ) -> crate::package_store::Future<Result<(), HashMap<RepoUrl, RepoDownloadError>>> {
         let config = self.config().read().unwrap().clone();
+        let requested = config.repos().keys().cloned().collect::<Vec<_>>();
+        let shared_config = self.config();
         let repos = self.repos();
         Box::pin(async move {
-            let (result, errors) = crate::repo::refresh_repos(config).await;
+            let (mut result, mut errors) = crate::repo::refresh_repos(config).await;
+            crate::repo::discard_removed_repos(
+                &shared_config.read().unwrap(),
+                &requested,
+                &mut result,
+                &mut errors,
+            );
             *repos.write().unwrap() = result;
+            crate::events::global().publish(crate::events::StoreEvent::RepositoriesChanged);
             if errors.is_empty() {
                 Ok(())
             } else {
@@ -242,8 +363,9 @@ impl PackageStore for MacOSPackageStore {
     ) -> crate::package_store::Future<HashMap<RepoUrl, LocalizedStrings>> {
         let repos = self.repos.read().unwrap();
         let urls = repos.keys().cloned().collect::<Vec<_>>();
+        let proxy = self.config.read().unwrap().settings().proxy().cloned();
 
-        Box::pin(crate::repo::strings(urls, language))
+        Box::pin(crate::repo::strings(urls, language, proxy))
     }
 
     fn resolve_package_query(
@@ -253,7 +375,12 @@ impl PackageStore for MacOSPackageStore {
     ) -> ResolvedPackageQuery {
         let repos = self.repos();
         let repos = repos.read().unwrap();
-        crate::repo::resolve_package_query(self, &query, install_target, &*repos)
+        crate::repo::resolve_package_query(
+            |key, target| self.status(key, target).ok(),
+            &query,
+            install_target,
+            &repos,
+        )
     }
 }
 
@@ -277,6 +404,27 @@ impl MacOSPackageStore {
         release: &pahkat_types::package::Release,
         target: InstallTarget,
     ) -> Result<PackageStatus, PackageStatusError> {
+        // App bundles are found by their bundle in the applications directory
+        let app_version = descriptor
+            .release
+            .iter()
+            .flat_map(|release| release.target.iter())
+            .find_map(|x| match &x.payload {
+                pahkat_types::payload::Payload::MacOSAppBundle(v) => {
+                    app_bundle::installed_version(v, target)
+                }
+                _ => None,
+            });
+        if let Some(version) = app_version {
+            return match self::cmp::cmp(&version, &release.version) {
+                Ok(v) => Ok(v),
+                Err(e) => {
+                    log::error!("Invalid version: {:?}, assuming not installed", e);
+                    Ok(PackageStatus::NotInstalled)
+                }
+            };
+        }
+
         // Map over all targets to find pkg_ids
         let pkg_ids: Vec<&str> = descriptor.release.iter().fold(vec![], |acc, release| {
             release.target.iter().fold(acc, |mut acc, target| {
@@ -307,20 +455,27 @@ impl MacOSPackageStore {
                 }
             });
 
-        let pkg_info = match pkg_info {
-            Some(v) => v,
-            None => return Ok(PackageStatus::NotInstalled),
+        let pkg_info = match (pkg_info, target) {
+            (Some(v), _) => v,
+            (None, InstallTarget::User) => {
+                let receipt = match receipts::find(&pkg_ids) {
+                    Some(v) => v,
+                    None => return Ok(PackageStatus::NotInstalled),
+                };
+                return Ok(self::cmp::cmp(&receipt.version, &release.version)
+                    .unwrap_or(PackageStatus::NotInstalled));
+            }
+            (None, InstallTarget::System) => return Ok(PackageStatus::NotInstalled),
         };
 
-        let real_version =
-            detailed_package_version(&pkg_info.pkgid, target).unwrap_or_else(|e| {
-                log::warn!(
-                    "Couldn't get real version number from info.plist {}: {:?}",
-                    pkg_info.pkgid,
-                    e
-                );
-                pkg_info.pkg_version
-            });
+        let real_version = detailed_package_version(&pkg_info.pkgid, target).unwrap_or_else(|e| {
+            log::warn!(
+                "Couldn't get real version number from info.plist {}: {:?}",
+                pkg_info.pkgid,
+                e
+            );
+            pkg_info.pkg_version
+        });
 
         let status = match self::cmp::cmp(&real_version, &release.version) {
             Ok(v) => v,
@@ -443,16 +598,145 @@ fn detailed_package_version(
     Ok(data.version)
 }
 
-fn install_macos_package(pkg_path: &Path, target: InstallTarget) -> Result<(), ProcessError> {
+fn verify_pkg_signature(pkg_path: &Path, team_id: &str) -> Result<(), String> {
+    let output = Command::new("spctl")
+        .args(&["--assess", "--type", "install", "-vv"])
+        .arg(pkg_path)
+        .output()
+        .map_err(|e| format!("could not run spctl: {}", e))?;
+
+    // spctl writes its assessment to stderr
+    let assessment = String::from_utf8_lossy(&output.stderr);
+    log::debug!("spctl assessment: {}", &assessment);
+
+    if !output.status.success() {
+        return Err(format!("{} was rejected by Gatekeeper", pkg_path.display()));
+    }
+
+    check_team_id(&assessment, team_id).map_err(|e| format!("{}: {}", pkg_path.display(), e))
+}
+
+/// Checks the signing origin of an `spctl` assessment, such as
+/// `origin=Developer ID Installer: Example Ltd (ABCDE12345)`.
+fn check_team_id(assessment: &str, team_id: &str) -> Result<(), String> {
+    let origin = assessment
+        .lines()
+        .find_map(|x| x.trim().strip_prefix("origin="))
+        .ok_or_else(|| "no signing origin found".to_string())?;
+
+    if origin.ends_with(&format!("({})", team_id)) {
+        Ok(())
+    } else {
+        Err(format!(
+            "expected team id `{}`, found `{}`",
+            team_id, origin
+        ))
+    }
+}
+
+/// Resolves choice changes to a file on disk, either the downloaded copy of a
+/// remote file, which must match `sha256`, or the inline XML written next to the
+/// package.
+fn choice_changes_path(
+    config: &Config,
+    pkg_path: &Path,
+    value: &str,
+    sha256: Option<&str>,
+) -> io::Result<PathBuf> {
+    use pahkat_types::payload::macos::{choice_changes_url, is_inline_choice_changes};
+    use sha2::{Digest, Sha256};
+
+    if let Some(url) = choice_changes_url(value) {
+        let expected = sha256.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("No checksum for choice changes: {}", url),
+            )
+        })?;
+        let path = crate::repo::download_file_path(config, &url);
+        if !path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Choice changes not downloaded: {}", url),
+            ));
+        }
+        let actual = format!("{:x}", Sha256::digest(&std::fs::read(&path)?));
+        if !actual.eq_ignore_ascii_case(expected) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Checksum mismatch for choice changes: {}", url),
+            ));
+        }
+        return Ok(path);
+    }
+
+    if !is_inline_choice_changes(value) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Choice changes must be an https URL or inline XML",
+        ));
+    }
+
+    let path = pkg_path.with_extension("choices.xml");
+    std::fs::write(&path, value)?;
+    Ok(path)
+}
+
+/// Appends a change selecting or deselecting each chosen option to the choice
+/// changes of the package, if any, and writes them next to the package.
+fn choice_changes_with_options(
+    pkg_path: &Path,
+    choice_changes: Option<&Path>,
+    options: &BTreeMap<String, String>,
+) -> io::Result<PathBuf> {
+    let mut changes = match choice_changes {
+        Some(path) => match plist::Value::from_file(path) {
+            Ok(plist::Value::Array(x)) => x,
+            Ok(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Choice changes are not an array: {}", path.display()),
+                ))
+            }
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        },
+        None => vec![],
+    };
+
+    for (id, value) in options {
+        let mut change = plist::Dictionary::new();
+        change.insert("choiceIdentifier".into(), id.clone().into());
+        change.insert("choiceAttribute".into(), "selected".into());
+        let selected: i64 = if value == "1" { 1 } else { 0 };
+        change.insert("attributeSetting".into(), selected.into());
+        changes.push(plist::Value::Dictionary(change));
+    }
+
+    let path = pkg_path.with_extension("options.xml");
+    plist::Value::Array(changes)
+        .to_file_xml(&path)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    Ok(path)
+}
+
+fn install_macos_package(
+    pkg_path: &Path,
+    target: InstallTarget,
+    choice_changes: Option<&Path>,
+) -> Result<(), ProcessError> {
     let target_str = match target {
         InstallTarget::User => "CurrentUserHomeDirectory",
         InstallTarget::System => "LocalSystem",
     };
 
-    let args = &["-pkg", &pkg_path.to_str().unwrap(), "-target", target_str];
+    let mut args = vec!["-pkg", pkg_path.to_str().unwrap(), "-target", target_str];
+    if let Some(path) = choice_changes {
+        args.push("-applyChoiceChangesXML");
+        args.push(path.to_str().unwrap());
+    }
     log::debug!("Running command: 'installer {}'", args.join(" "));
 
-    let res = Command::new("installer").args(args).output();
+    let res = crate::priority::command("installer").args(args).output();
     let output = match res {
         Ok(v) => v,
         Err(e) => {
@@ -479,7 +763,7 @@ fn run_script(name: &str, bundle_id: &str, target: InstallTarget) -> Result<(),
         return Ok(());
     }
 
-    let res = Command::new(&script_path).output();
+    let res = crate::priority::command(&script_path).output();
     let output = match res {
         Ok(v) => v,
         Err(e) => {
@@ -504,14 +788,22 @@ fn run_post_uninstall_script(bundle_id: &str, target: InstallTarget) -> Result<(
 }
 
 fn uninstall_macos_package(bundle_id: &str, target: InstallTarget) -> Result<(), ProcessError> {
-    let package_info = get_package_info(bundle_id, target)?;
+    // User installs pkgutil does not find are removed using their receipt
+    let (paths, is_known) = match (get_package_info(bundle_id, target), target) {
+        (Ok(info), _) => (info.paths(), true),
+        (Err(ProcessError::NotFound), InstallTarget::User) => match receipts::find(&[bundle_id]) {
+            Some(receipt) if !receipt.paths.is_empty() => (receipt.paths, false),
+            _ => return Err(ProcessError::NotFound),
+        },
+        (Err(e), _) => return Err(e),
+    };
 
     run_pre_uninstall_script(bundle_id, target)?;
 
     let mut errors = vec![];
     let mut directories = vec![];
 
-    for path in package_info.paths() {
+    for path in paths {
         let meta = match path.symlink_metadata() {
             Ok(v) => v,
             Err(err) => {
@@ -558,7 +850,12 @@ fn uninstall_macos_package(bundle_id: &str, target: InstallTarget) -> Result<(),
 
     log::error!("{:?}", errors);
 
-    forget_pkg_id(bundle_id, target)?;
+    if is_known {
+        forget_pkg_id(bundle_id, target)?;
+    }
+    if let InstallTarget::User = target {
+        receipts::forget(bundle_id);
+    }
 
     run_post_uninstall_script(bundle_id, target)?;
 
@@ -588,3 +885,77 @@ fn forget_pkg_id(bundle_id: &str, target: InstallTarget) -> Result<(), ProcessEr
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use pahkat_types::package::{Descriptor, DescriptorData, Release, Version};
+    use pahkat_types::payload::{arch, macos::Package, Payload, Target};
+
+    use super::check_team_id;
+    use crate::repo::ReleaseQuery;
+
+    #[test]
+    fn team_id_from_spctl_assessment() {
+        let assessment = "speller.pkg: accepted\n\
+                          source=Notarized Developer ID\n\
+                          origin=Developer ID Installer: Example Ltd (ABCDE12345)\n";
+        assert!(check_team_id(assessment, "ABCDE12345").is_ok());
+        assert!(check_team_id(assessment, "ABCDE1234").is_err());
+        assert!(check_team_id(assessment, "Example Ltd").is_err());
+        assert!(check_team_id("speller.pkg: accepted\n", "ABCDE12345").is_err());
+    }
+
+    fn resolved_arch(native: &str, emulated: &[&'static str], arches: &[&str]) -> Option<String> {
+        let target = |arch: &str| {
+            Target::builder()
+                .platform("macos".into())
+                .arch(Some(arch.to_string()))
+                .payload(Payload::MacOSPackage(
+                    Package::builder()
+                        .url(format!("https://example.com/{}.pkg", arch).parse().unwrap())
+                        .pkg_id("com.example.speller".into())
+                        .size(1)
+                        .installed_size(1)
+                        .build(),
+                ))
+                .build()
+        };
+        let descriptor = Descriptor::builder()
+            .package(DescriptorData::builder().id("speller".into()).build())
+            .release(vec![Release::builder()
+                .version(Version::new("1.0.0").unwrap())
+                .target(arches.iter().map(|x| target(x)).collect())
+                .build()])
+            .build();
+
+        let query = ReleaseQuery {
+            platform: "macos",
+            arch: Some(native),
+            emulated_arches: emulated.to_vec(),
+            ..Default::default()
+        };
+        let arch = query
+            .iter(&descriptor)
+            .next()
+            .map(|x| x.target.arch.clone().unwrap());
+        arch
+    }
+
+    #[test]
+    fn apple_silicon_prefers_universal_over_emulated() {
+        let rosetta = arch::emulated("macos", arch::AARCH64, None);
+        assert_eq!(
+            resolved_arch(arch::AARCH64, rosetta, &["x86_64", "universal"]).as_deref(),
+            Some("universal")
+        );
+        assert_eq!(
+            resolved_arch(arch::AARCH64, rosetta, &["x86_64"]).as_deref(),
+            Some("x86_64")
+        );
+    }
+
+    #[test]
+    fn x86_64_is_not_offered_without_rosetta() {
+        assert_eq!(resolved_arch(arch::AARCH64, &[], &["x86_64"]), None);
+    }
+}
@@ -0,0 +1,171 @@
+use std::ffi::c_void;
+use std::process::Command;
+
+use pahkat_types::payload::{Action, ActionKind};
+
+use crate::events::StoreEvent;
+
+type CFIndex = isize;
+type CFTypeRef = *const c_void;
+type CFStringRef = *const c_void;
+type CFArrayRef = *const c_void;
+type CFDictionaryRef = *const c_void;
+type OSStatus = i32;
+
+const K_CF_STRING_ENCODING_UTF8: u32 = 0x0800_0100;
+
+#[repr(C)]
+struct CFDictionaryCallBacks {
+    _private: [u8; 0],
+}
+
+#[link(name = "CoreFoundation", kind = "framework")]
+extern "C" {
+    static kCFTypeDictionaryKeyCallBacks: CFDictionaryCallBacks;
+    static kCFTypeDictionaryValueCallBacks: CFDictionaryCallBacks;
+
+    fn CFStringCreateWithBytes(
+        alloc: CFTypeRef,
+        bytes: *const u8,
+        num_bytes: CFIndex,
+        encoding: u32,
+        is_external_representation: u8,
+    ) -> CFStringRef;
+    fn CFDictionaryCreate(
+        alloc: CFTypeRef,
+        keys: *const CFTypeRef,
+        values: *const CFTypeRef,
+        num_values: CFIndex,
+        key_callbacks: *const CFDictionaryCallBacks,
+        value_callbacks: *const CFDictionaryCallBacks,
+    ) -> CFDictionaryRef;
+    fn CFArrayGetCount(array: CFArrayRef) -> CFIndex;
+    fn CFArrayGetValueAtIndex(array: CFArrayRef, index: CFIndex) -> CFTypeRef;
+    fn CFRelease(cf: CFTypeRef);
+}
+
+#[link(name = "Carbon", kind = "framework")]
+extern "C" {
+    static kTISPropertyInputSourceID: CFStringRef;
+
+    fn TISCreateInputSourceList(
+        properties: CFDictionaryRef,
+        include_all_installed: u8,
+    ) -> CFArrayRef;
+    fn TISEnableInputSource(source: CFTypeRef) -> OSStatus;
+}
+
+/// Runs the post-install actions of a payload. Failures are logged, as the
+/// package itself was installed successfully.
+pub(super) fn run(actions: &[Action]) {
+    let mut forwarded = vec![];
+
+    for action in actions.iter() {
+        log::debug!("Running action: {}", action);
+        let result = match action.kind {
+            ActionKind::EnableInputSource if !is_user_session() => {
+                log::info!("Leaving {} for user sessions to enable", &action.id);
+                forwarded.push(action.id.clone());
+                Ok(())
+            }
+            ActionKind::EnableInputSource => enable_input_source(&action.id),
+            ActionKind::RegisterSpellService => register_spell_service(),
+        };
+
+        if let Err(e) = result {
+            log::warn!("Action {} failed: {}", action, e);
+        }
+    }
+
+    if !forwarded.is_empty() {
+        crate::events::global().publish(StoreEvent::EnableInputSources(forwarded));
+    }
+}
+
+/// Enables input sources the system service left for user sessions, as told by
+/// `StoreEvent::EnableInputSources`. Does nothing outside of a user session.
+pub fn enable_input_sources(ids: &[String]) {
+    if !is_user_session() {
+        return;
+    }
+
+    for id in ids.iter() {
+        if let Err(e) = enable_input_source(id) {
+            log::warn!("Could not enable input source {}: {}", id, e);
+        }
+    }
+}
+
+/// Input sources are enabled per user, and the daemon runs as root outside of
+/// any user's session, where the Text Input Sources API has no effect.
+fn is_user_session() -> bool {
+    unsafe { libc::geteuid() != 0 }
+}
+
+/// Enables every installed input source with the given ID for the session the
+/// process runs in.
+fn enable_input_source(id: &str) -> Result<(), String> {
+    unsafe {
+        let value = CFStringCreateWithBytes(
+            std::ptr::null(),
+            id.as_ptr(),
+            id.len() as CFIndex,
+            K_CF_STRING_ENCODING_UTF8,
+            0,
+        );
+        if value.is_null() {
+            return Err("Invalid input source ID".into());
+        }
+
+        let keys = [kTISPropertyInputSourceID];
+        let values = [value];
+        let properties = CFDictionaryCreate(
+            std::ptr::null(),
+            keys.as_ptr(),
+            values.as_ptr(),
+            1,
+            &kCFTypeDictionaryKeyCallBacks,
+            &kCFTypeDictionaryValueCallBacks,
+        );
+        CFRelease(value);
+
+        let sources = TISCreateInputSourceList(properties, 1);
+        CFRelease(properties);
+
+        if sources.is_null() {
+            return Err(format!("No input source found with ID `{}`", id));
+        }
+
+        let count = CFArrayGetCount(sources);
+        let mut result = if count == 0 {
+            Err(format!("No input source found with ID `{}`", id))
+        } else {
+            Ok(())
+        };
+
+        for i in 0..count {
+            let status = TISEnableInputSource(CFArrayGetValueAtIndex(sources, i));
+            if status != 0 {
+                result = Err(format!("TISEnableInputSource returned {}", status));
+            }
+        }
+
+        CFRelease(sources);
+        result
+    }
+}
+
+/// Spell services are found through the services registry, which is otherwise
+/// only rebuilt at login.
+fn register_spell_service() -> Result<(), String> {
+    let status = Command::new("/System/Library/CoreServices/pbs")
+        .arg("-update")
+        .status()
+        .map_err(|e| e.to_string())?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("pbs exited with {}", status))
+    }
+}
@@ -0,0 +1,189 @@
+//! Zipped `.app` bundles, installed the way users drag them into Applications.
+//! Archives are unpacked with `ditto`, which keeps the symlinks, extended
+//! attributes and code signatures of the bundle intact. As `ditto` cannot be told
+//! to refuse anything, the unpacked tree is checked before it is moved into place.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::Deserialize;
+
+use pahkat_types::payload::macos;
+
+use crate::archive::{Limits, MaliciousArchive, Sanitizer};
+use crate::package_store::InstallTarget;
+use crate::transaction::install::{InstallError, ProcessError};
+
+#[derive(Deserialize)]
+struct InfoPlist {
+    #[serde(rename = "CFBundleIdentifier")]
+    bundle_id: String,
+    #[serde(rename = "CFBundleShortVersionString")]
+    version: String,
+}
+
+fn applications_dir(target: InstallTarget) -> PathBuf {
+    match target {
+        InstallTarget::System => PathBuf::from("/Applications"),
+        InstallTarget::User => pathos::user::home_dir().unwrap().join("Applications"),
+    }
+}
+
+fn bundle_path(app_name: &str, target: InstallTarget) -> Result<PathBuf, ProcessError> {
+    if !macos::is_valid_app_name(app_name) {
+        log::error!("Refusing invalid app bundle name: {:?}", app_name);
+        return Err(ProcessError::NotFound);
+    }
+    Ok(applications_dir(target).join(app_name))
+}
+
+fn ditto(args: &[&Path]) -> Result<(), ProcessError> {
+    let output = Command::new("ditto").args(args).output().map_err(|e| {
+        log::error!("ditto: {:?}", &e);
+        ProcessError::Io(e)
+    })?;
+    if !output.status.success() {
+        log::error!("ditto: {:?}", &output);
+        return Err(ProcessError::Unknown(output));
+    }
+    Ok(())
+}
+
+fn info_plist(path: &Path) -> Option<InfoPlist> {
+    let path = path.join("Contents").join("Info.plist");
+    match plist::from_file(&path) {
+        Ok(v) => Some(v),
+        Err(e) => {
+            log::warn!("Could not read {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Unpacks the archive next to it, then moves the bundle into place, replacing
+/// the installed one, if any.
+pub(super) fn install(
+    zip_path: &Path,
+    payload: &macos::AppBundle,
+    target: InstallTarget,
+) -> Result<(), InstallError> {
+    let dest = bundle_path(&payload.app_name, target)?;
+    let staging = zip_path.with_extension("unpacked");
+    if staging.exists() {
+        std::fs::remove_dir_all(&staging).map_err(ProcessError::Io)?;
+    }
+
+    ditto(&[Path::new("-x"), Path::new("-k"), zip_path, &staging])?;
+    let result = check_unpacked(zip_path, &staging).and_then(|_| {
+        move_bundle(&staging.join(&payload.app_name), &dest, &payload.bundle_id)
+            .map_err(InstallError::InstallerFailure)
+    });
+    if let Err(e) = std::fs::remove_dir_all(&staging) {
+        log::warn!("Could not remove {}: {}", staging.display(), e);
+    }
+    result
+}
+
+/// Applies the checks made to tarballs while they are extracted to the tree `ditto`
+/// unpacked, and refuses symlinks that lead out of it.
+fn check_unpacked(zip_path: &Path, staging: &Path) -> Result<(), InstallError> {
+    let io = |e| InstallError::InstallerFailure(ProcessError::Io(e));
+    let malicious = |path: &Path| {
+        InstallError::MaliciousArchive(MaliciousArchive::LinkEscape(path.to_path_buf()))
+    };
+
+    let compressed_size = std::fs::metadata(zip_path).map_err(io)?.len();
+    let mut sanitizer = Sanitizer::new(compressed_size, Limits::default());
+    let root = staging.canonicalize().map_err(io)?;
+    let mut dirs = vec![root.clone()];
+
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(&dir).map_err(io)? {
+            let path = entry.map_err(io)?.path();
+            let relative = path.strip_prefix(&root).unwrap_or(&path).to_path_buf();
+            let meta = std::fs::symlink_metadata(&path).map_err(io)?;
+            sanitizer
+                .check_entry(&relative, meta.len())
+                .map_err(InstallError::MaliciousArchive)?;
+
+            if meta.file_type().is_symlink() {
+                if std::fs::read_link(&path).map_err(io)?.is_absolute() {
+                    return Err(malicious(&relative));
+                }
+                match path.canonicalize() {
+                    Ok(v) if v.starts_with(&root) => {}
+                    _ => return Err(malicious(&relative)),
+                }
+            } else if meta.is_dir() {
+                dirs.push(path);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn move_bundle(src: &Path, dest: &Path, bundle_id: &str) -> Result<(), ProcessError> {
+    match info_plist(src) {
+        Some(info) if info.bundle_id == bundle_id => {}
+        Some(info) => {
+            log::error!("Expected bundle {}, found {}", bundle_id, info.bundle_id);
+            return Err(ProcessError::NotFound);
+        }
+        None => return Err(ProcessError::NotFound),
+    }
+
+    if let Some(parent) = dest.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if dest.exists() {
+        std::fs::remove_dir_all(dest)?;
+    }
+
+    // The cache may be on another volume than the applications directory
+    match std::fs::rename(src, dest) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            log::debug!("Could not move {}, copying: {}", src.display(), e);
+            ditto(&[src, dest])
+        }
+    }
+}
+
+/// Removes the bundle, as long as it is still the one that was installed.
+pub(super) fn uninstall(
+    payload: &macos::AppBundle,
+    target: InstallTarget,
+) -> Result<(), ProcessError> {
+    let path = bundle_path(&payload.app_name, target)?;
+    match info_plist(&path) {
+        Some(info) if info.bundle_id == payload.bundle_id => {}
+        _ => return Err(ProcessError::NotFound),
+    }
+    std::fs::remove_dir_all(&path)?;
+    Ok(())
+}
+
+/// The `CFBundleShortVersionString` of the installed bundle, or `None` if it is
+/// not installed.
+pub(super) fn installed_version(
+    payload: &macos::AppBundle,
+    target: InstallTarget,
+) -> Option<String> {
+    let path = bundle_path(&payload.app_name, target).ok()?;
+    if !path.exists() {
+        return None;
+    }
+
+    let info = info_plist(&path)?;
+    if info.bundle_id != payload.bundle_id {
+        log::warn!(
+            "{} is {}, not {}",
+            path.display(),
+            info.bundle_id,
+            payload.bundle_id
+        );
+        return None;
+    }
+    Some(info.version)
+}
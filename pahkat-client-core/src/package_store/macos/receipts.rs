@@ -0,0 +1,162 @@
+//! Receipts of packages installed for the current user.
+//!
+//! Not every version of pkgutil finds packages installed into the home directory,
+//! so user installs are also recorded in Application Support. A receipt is trusted
+//! for as long as the files it lists are there, and dropped once they are all gone.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(super) struct Receipt {
+    pub version: String,
+    /// Installed files, if pkgutil knew of them at install time
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub paths: Vec<PathBuf>,
+}
+
+impl Receipt {
+    /// Directories may be shared with other packages, so only files count. Without
+    /// any known paths, the package is taken to still be installed.
+    fn is_present(&self) -> bool {
+        self.paths.is_empty()
+            || self.paths.iter().any(|x| match x.symlink_metadata() {
+                Ok(meta) => !meta.is_dir(),
+                Err(_) => false,
+            })
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Receipts {
+    #[serde(default)]
+    packages: BTreeMap<String, Receipt>,
+}
+
+impl Receipts {
+    fn load(path: &Path) -> Receipts {
+        let data = match std::fs::read_to_string(path) {
+            Ok(v) => v,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Receipts::default(),
+            Err(e) => {
+                log::warn!("Could not read {}: {}", path.display(), e);
+                return Receipts::default();
+            }
+        };
+
+        toml::from_str(&data).unwrap_or_else(|e| {
+            log::warn!("Ignoring invalid receipts in {}: {}", path.display(), e);
+            Receipts::default()
+        })
+    }
+
+    fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let data =
+            toml::to_string(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let tmp_path = path.with_extension("toml.tmp");
+        std::fs::write(&tmp_path, data)?;
+        std::fs::rename(&tmp_path, path)
+    }
+
+    /// The receipt of the first of `pkg_ids` that is still present. Receipts of
+    /// packages whose files are gone are dropped, returning whether any were.
+    fn reconcile(&mut self, pkg_ids: &[&str]) -> (Option<Receipt>, bool) {
+        let mut is_changed = false;
+        for pkg_id in pkg_ids {
+            match self.packages.get(*pkg_id) {
+                Some(receipt) if receipt.is_present() => {
+                    return (Some(receipt.clone()), is_changed)
+                }
+                Some(_) => {
+                    log::info!("Files of {} are gone, dropping its receipt", pkg_id);
+                    self.packages.remove(*pkg_id);
+                    is_changed = true;
+                }
+                None => {}
+            }
+        }
+        (None, is_changed)
+    }
+}
+
+fn update<T>(f: impl FnOnce(&mut Receipts) -> (T, bool)) -> Option<T> {
+    let path = match crate::defaults::user_receipts_path() {
+        Ok(v) => v,
+        Err(e) => {
+            log::error!("No path for user receipts: {:?}", e);
+            return None;
+        }
+    };
+
+    let mut receipts = Receipts::load(&path);
+    let (value, is_changed) = f(&mut receipts);
+    if is_changed {
+        if let Err(e) = receipts.save(&path) {
+            log::error!("Could not save {}: {}", path.display(), e);
+        }
+    }
+    Some(value)
+}
+
+pub(super) fn record(pkg_id: &str, receipt: Receipt) {
+    update(|x| {
+        x.packages.insert(pkg_id.to_string(), receipt);
+        ((), true)
+    });
+}
+
+pub(super) fn forget(pkg_id: &str) {
+    update(|x| {
+        let is_changed = x.packages.remove(pkg_id).is_some();
+        ((), is_changed)
+    });
+}
+
+/// The receipt of the first of `pkg_ids` still installed for the user.
+pub(super) fn find(pkg_ids: &[&str]) -> Option<Receipt> {
+    update(|x| x.reconcile(pkg_ids)).flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn receipts_of_removed_files_are_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let installed = dir.path().join("Speller.bundle");
+        std::fs::write(&installed, "").unwrap();
+
+        let mut receipts = Receipts::default();
+        receipts.packages.insert(
+            "no.uit.speller".into(),
+            Receipt {
+                version: "1.0.0".into(),
+                paths: vec![installed.clone()],
+            },
+        );
+        receipts.packages.insert(
+            "no.uit.unknown".into(),
+            Receipt {
+                version: "2.0.0".into(),
+                paths: vec![],
+            },
+        );
+
+        let (receipt, is_changed) = receipts.reconcile(&["no.uit.speller"]);
+        assert_eq!(receipt.map(|x| x.version), Some("1.0.0".to_string()));
+        assert!(!is_changed);
+
+        std::fs::remove_file(&installed).unwrap();
+        let (receipt, is_changed) = receipts.reconcile(&["no.uit.speller", "no.uit.unknown"]);
+        assert_eq!(receipt.map(|x| x.version), Some("2.0.0".to_string()));
+        assert!(is_changed);
+        assert!(!receipts.packages.contains_key("no.uit.speller"));
+    }
+}
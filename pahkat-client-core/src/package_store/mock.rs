@@ -0,0 +1,263 @@
+//! A store that keeps package statuses in memory, for testing transactions and
+//! dependency resolution without running installers.
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+
+use hashbrown::HashMap;
+use pahkat_types::package::{Descriptor, DescriptorData, Package, Release, Version};
+use pahkat_types::payload::{tarball, Payload, Target};
+use pahkat_types::repo::{Agent, Index, RepoUrl, RepositoryData};
+use pahkat_types::DependencyMap;
+
+use super::{
+    DownloadEvent, Future, LocalizedStrings, PackageStore, SharedRepoErrors, SharedRepos,
+    SharedStoreConfig, Stream,
+};
+use crate::repo::{LoadedRepositoryMeta, PackageQuery, RepoDownloadError};
+use crate::transaction::{install::InstallError, uninstall::UninstallError};
+use crate::transaction::{
+    PackageDependencyStatusError, PackageStatus, PackageStatusError, ResolvedPackageQuery,
+};
+use crate::{Config, LoadedRepository, PackageKey, Permission};
+
+pub(crate) const REPO: &str = "https://pahkat.example/repo/";
+
+/// A package with a single release for the running platform.
+pub(crate) fn package(id: &str, version: &str, dependencies: &[(&str, &str)]) -> Package {
+    let dependencies = dependencies
+        .iter()
+        .map(|(id, req)| ((*id).into(), req.parse().unwrap()))
+        .collect::<DependencyMap>();
+
+    let target = Target::builder()
+        .platform(std::env::consts::OS.into())
+        .dependencies(dependencies)
+        .payload(Payload::TarballPackage(
+            tarball::Package::builder()
+                .url(
+                    format!("https://pahkat.example/{}.txz", id)
+                        .parse()
+                        .unwrap(),
+                )
+                .size(1)
+                .installed_size(1)
+                .build(),
+        ))
+        .build();
+
+    Package::Concrete(
+        Descriptor::builder()
+            .package(DescriptorData::builder().id(id.into()).build())
+            .release(vec![Release::builder()
+                .version(Version::new(version).unwrap())
+                .target(vec![target])
+                .build()])
+            .build(),
+    )
+}
+
+/// The repository at [`REPO`], with `packages` in its index.
+pub(crate) fn repository(packages: &[Package]) -> LoadedRepository {
+    let info = Index::builder()
+        .repository(RepositoryData::builder().url(REPO.parse().unwrap()).build())
+        .agent(
+            Agent::builder()
+                .name("pahkat".into())
+                .version("test".into())
+                .build(),
+        )
+        .build();
+    let packages = pahkat_types::index_writer::encode_index(packages).unwrap();
+    LoadedRepository::new(
+        info,
+        packages.into_boxed_slice(),
+        LoadedRepositoryMeta {
+            channel: None,
+            last_update: None,
+            prerelease_channels: Default::default(),
+        },
+    )
+    .unwrap()
+}
+
+pub(crate) fn key(id: &str) -> PackageKey {
+    PackageKey::new_unchecked(REPO.parse().unwrap(), id.into(), None)
+}
+
+/// Installs and uninstalls only change the recorded statuses. Operations on the
+/// packages in the `failing_*` sets fail, and every operation is logged in `calls`.
+pub(crate) struct MockStore {
+    _dir: tempfile::TempDir,
+    repos: SharedRepos,
+    errors: SharedRepoErrors,
+    config: SharedStoreConfig,
+    statuses: Mutex<HashMap<String, PackageStatus>>,
+    pub(crate) failing_preflight: Mutex<HashSet<String>>,
+    pub(crate) failing_install: Mutex<HashSet<String>>,
+    pub(crate) calls: Mutex<Vec<String>>,
+}
+
+impl MockStore {
+    pub(crate) fn new(packages: &[Package]) -> MockStore {
+        let dir = tempfile::tempdir().unwrap();
+        let (config, errors) = Config::load(dir.path(), Permission::ReadWrite);
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        let mut repos = HashMap::new();
+        repos.insert(REPO.parse().unwrap(), repository(packages));
+
+        MockStore {
+            _dir: dir,
+            repos: Arc::new(RwLock::new(repos)),
+            errors: Default::default(),
+            config: Arc::new(RwLock::new(config)),
+            statuses: Default::default(),
+            failing_preflight: Default::default(),
+            failing_install: Default::default(),
+            calls: Default::default(),
+        }
+    }
+
+    pub(crate) fn set_status(&self, id: &str, status: PackageStatus) {
+        self.statuses.lock().unwrap().insert(id.into(), status);
+    }
+
+    pub(crate) fn calls(&self) -> Vec<String> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    fn call(&self, name: &str, key: &PackageKey) {
+        self.calls
+            .lock()
+            .unwrap()
+            .push(format!("{} {}", name, key.id));
+    }
+}
+
+impl PackageStore for MockStore {
+    fn repos(&self) -> SharedRepos {
+        Arc::clone(&self.repos)
+    }
+
+    fn errors(&self) -> SharedRepoErrors {
+        Arc::clone(&self.errors)
+    }
+
+    fn config(&self) -> SharedStoreConfig {
+        Arc::clone(&self.config)
+    }
+
+    fn download(&self, _key: &PackageKey) -> Stream<DownloadEvent> {
+        Box::pin(futures::stream::empty())
+    }
+
+    fn import(
+        &self,
+        _key: &PackageKey,
+        installer_path: &Path,
+    ) -> Result<PathBuf, super::ImportError> {
+        Ok(installer_path.to_path_buf())
+    }
+
+    fn install(
+        &self,
+        key: &PackageKey,
+        _target: super::InstallTarget,
+    ) -> Result<PackageStatus, InstallError> {
+        self.call("install", key);
+        if self.failing_install.lock().unwrap().contains(&key.id) {
+            return Err(InstallError::PackageNotInCache);
+        }
+        self.set_status(&key.id, PackageStatus::UpToDate);
+        Ok(PackageStatus::UpToDate)
+    }
+
+    fn preflight(
+        &self,
+        key: &PackageKey,
+        _options: &BTreeMap<String, String>,
+    ) -> Result<(), InstallError> {
+        self.call("preflight", key);
+        if self.failing_preflight.lock().unwrap().contains(&key.id) {
+            return Err(InstallError::PackageNotInCache);
+        }
+        Ok(())
+    }
+
+    fn uninstall(
+        &self,
+        key: &PackageKey,
+        _target: super::InstallTarget,
+    ) -> Result<PackageStatus, UninstallError> {
+        self.call("uninstall", key);
+        self.set_status(&key.id, PackageStatus::NotInstalled);
+        Ok(PackageStatus::NotInstalled)
+    }
+
+    fn status(
+        &self,
+        key: &PackageKey,
+        _target: super::InstallTarget,
+    ) -> Result<PackageStatus, PackageStatusError> {
+        Ok(self
+            .statuses
+            .lock()
+            .unwrap()
+            .get(&key.id)
+            .copied()
+            .unwrap_or(PackageStatus::NotInstalled))
+    }
+
+    fn dependency_status(
+        &self,
+        key: &PackageKey,
+        target: super::InstallTarget,
+    ) -> Result<Vec<(PackageKey, PackageStatus)>, PackageDependencyStatusError> {
+        crate::repo::dependency_tree(self, key, target)
+            .map(|nodes| nodes.into_iter().map(|x| (x.key, x.status)).collect())
+    }
+
+    fn all_statuses(
+        &self,
+        repo_url: &RepoUrl,
+        target: super::InstallTarget,
+    ) -> BTreeMap<String, Result<PackageStatus, PackageStatusError>> {
+        crate::repo::all_statuses(self, repo_url, target)
+    }
+
+    fn find_package_by_id(&self, package_id: &str) -> Option<(PackageKey, Package)> {
+        let repos = self.repos.read().unwrap();
+        crate::repo::find_package_by_id(self, package_id, &repos)
+    }
+
+    fn find_package_by_key(&self, key: &PackageKey) -> Option<Package> {
+        let repos = self.repos.read().unwrap();
+        crate::repo::find_package_by_key(key, &repos)
+    }
+
+    fn refresh_repos(&self) -> Future<Result<(), HashMap<RepoUrl, RepoDownloadError>>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn clear_cache(&self) {}
+
+    fn strings(&self, _language: String) -> Future<HashMap<RepoUrl, LocalizedStrings>> {
+        Box::pin(async { HashMap::new() })
+    }
+
+    fn resolve_package_query(
+        &self,
+        query: PackageQuery,
+        install_target: &[super::InstallTarget],
+    ) -> ResolvedPackageQuery {
+        let repos = self.repos.read().unwrap();
+        crate::repo::resolve_package_query(
+            |key, target| self.status(key, target).ok(),
+            &query,
+            install_target,
+            &repos,
+        )
+    }
+}
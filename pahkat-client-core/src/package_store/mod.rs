@@ -1,5 +1,11 @@
+#[cfg(all(target_os = "ios", feature = "ios"))]
+pub mod ios;
+#[cfg(all(target_os = "linux", feature = "linux"))]
+pub mod linux;
 #[cfg(all(target_os = "macos", feature = "macos"))]
 pub mod macos;
+#[cfg(test)]
+pub(crate) mod mock;
 #[cfg(feature = "prefix")]
 pub mod prefix;
 #[cfg(all(windows, feature = "windows"))]
@@ -16,7 +22,7 @@ use pahkat_types::package::Package;
 use serde::{Deserialize, Serialize};
 use url::Url;
 
-use crate::config::Config;
+use crate::config::{Config, FileError};
 use crate::repo::{PackageQuery, RepoDownloadError};
 use crate::transaction::{install::InstallError, uninstall::UninstallError};
 use crate::transaction::{
@@ -39,8 +45,17 @@ pub enum ImportError {
 
     #[error("Invalid payload type")]
     InvalidPayloadType,
+
+    #[error("Import did not complete")]
+    Cancelled(#[from] BlockingError),
 }
 
+/// A store operation that was run on the blocking pool never completed, because
+/// the runtime shut down before it ran.
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("Blocking store task did not complete: {0}")]
+pub struct BlockingError(String);
+
 #[derive(Debug)]
 pub enum ProgressEvent<P: Debug, C: Debug, E: Debug> {
     Progress(P),
@@ -48,11 +63,49 @@ pub enum ProgressEvent<P: Debug, C: Debug, E: Debug> {
     Error(E),
 }
 
-pub type DownloadEvent = ProgressEvent<(u64, u64), PathBuf, crate::download::DownloadError>;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DownloadProgress {
+    pub current: u64,
+    /// Zero if the server did not send a content length.
+    pub total: u64,
+    /// Whole percent downloaded, if the total is known.
+    pub percent: Option<u8>,
+    /// Across all downloads of the transaction, when downloaded as part of one.
+    pub bytes_per_second: Option<u64>,
+    pub eta_seconds: Option<u64>,
+}
+
+impl DownloadProgress {
+    pub fn new(current: u64, total: u64) -> DownloadProgress {
+        let percent = match total {
+            0 => None,
+            _ => Some((current.min(total) * 100 / total) as u8),
+        };
+
+        DownloadProgress {
+            current,
+            total,
+            percent,
+            bytes_per_second: None,
+            eta_seconds: None,
+        }
+    }
+
+    pub fn with_estimate(self, estimate: crate::eta::Estimate) -> DownloadProgress {
+        DownloadProgress {
+            bytes_per_second: estimate.bytes_per_second,
+            eta_seconds: estimate.eta_seconds,
+            ..self
+        }
+    }
+}
+
+pub type DownloadEvent = ProgressEvent<DownloadProgress, PathBuf, crate::download::DownloadError>;
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[serde(rename_all = "lowercase")]
 #[non_exhaustive]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum InstallTarget {
     System,
     User,
@@ -65,6 +118,15 @@ impl InstallTarget {
             InstallTarget::User => 1,
         }
     }
+
+    /// Targets where an installed package satisfies a dependency of a package
+    /// installed for this target. System packages cannot rely on per-user installs.
+    pub fn dependency_targets(&self) -> &'static [InstallTarget] {
+        match self {
+            InstallTarget::System => &[InstallTarget::System],
+            InstallTarget::User => &[InstallTarget::User, InstallTarget::System],
+        }
+    }
 }
 
 impl From<u8> for InstallTarget {
@@ -93,6 +155,9 @@ impl Default for InstallTarget {
 pub type Stream<T> = Pin<Box<dyn futures::stream::Stream<Item = T> + Send + Sync + 'static>>;
 pub type Future<T> = Pin<Box<dyn std::future::Future<Output = T> + Send + Sync + 'static>>;
 
+/// Statuses of the packages of a repository, by package id.
+pub type PackageStatuses = BTreeMap<String, Result<PackageStatus, PackageStatusError>>;
+
 pub trait PackageStore: Send + Sync {
     fn repos(&self) -> SharedRepos;
     fn errors(&self) -> SharedRepoErrors;
@@ -108,6 +173,31 @@ pub trait PackageStore: Send + Sync {
         target: InstallTarget,
     ) -> Result<PackageStatus, InstallError>;
 
+    /// Installs with the install options chosen for `key`. Stores whose payloads
+    /// have no options ignore them.
+    fn install_with_options(
+        &self,
+        key: &PackageKey,
+        target: InstallTarget,
+        options: &BTreeMap<String, String>,
+    ) -> Result<PackageStatus, InstallError> {
+        if !options.is_empty() {
+            log::warn!("Ignoring install options of {}: {:?}", key, options);
+        }
+        self.install(key, target)
+    }
+
+    /// Checks that `key` could be installed now without changing anything: its
+    /// payload is downloaded and the install `options` apply to it. Stores that
+    /// verify signatures when installing also verify them here.
+    fn preflight(
+        &self,
+        key: &PackageKey,
+        options: &BTreeMap<String, String>,
+    ) -> Result<(), InstallError> {
+        preflight_payload(self, key, options).map(|_| ())
+    }
+
     fn uninstall(
         &self,
         key: &PackageKey,
@@ -136,9 +226,171 @@ pub trait PackageStore: Send + Sync {
 
     fn find_package_by_key(&self, key: &PackageKey) -> Option<Package>;
 
+    /// Whether the installer or uninstaller last run for this package asked for a
+    /// restart, for example an MSI exiting with 3010. The request is cleared.
+    fn take_reboot_request(&self, _key: &PackageKey) -> bool {
+        false
+    }
+
+    /// Statistics of a loaded repository, computed without network requests.
+    fn repo_statistics(&self, url: &RepoUrl) -> Option<crate::repo::RepoStatistics> {
+        let config = self.config();
+        let config = config.read().unwrap();
+        let repos = self.repos();
+        let repos = repos.read().unwrap();
+        crate::repo::RepoStatistics::new(&config, url, &repos)
+    }
+
+    /// Returns the member keys if the key refers to a package set.
+    fn set_members(&self, key: &PackageKey) -> Option<Vec<PackageKey>> {
+        let repos = self.repos();
+        let repos = repos.read().unwrap();
+        crate::repo::set_members(key, &repos)
+    }
+
+    /// Status of a package set, if the key refers to one.
+    ///
+    /// A set that gained members since it was installed requires an update.
+    fn set_status(
+        &self,
+        key: &PackageKey,
+        target: InstallTarget,
+    ) -> Option<Result<PackageStatus, PackageStatusError>> {
+        let members = self.set_members(key)?;
+        let mut installed = 0;
+        let mut requires_update = false;
+
+        for member in members.iter() {
+            match self.status(member, target) {
+                Ok(PackageStatus::NotInstalled) => {}
+                Ok(PackageStatus::UpToDate) | Ok(PackageStatus::Unpublished) => installed += 1,
+                Ok(PackageStatus::RequiresUpdate) => {
+                    installed += 1;
+                    requires_update = true;
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        Some(Ok(if installed == 0 {
+            PackageStatus::NotInstalled
+        } else if requires_update || installed < members.len() {
+            PackageStatus::RequiresUpdate
+        } else {
+            PackageStatus::UpToDate
+        }))
+    }
+
+    /// Installed packages that their repository no longer publishes. These can still
+    /// be uninstalled using the release recorded when they were installed.
+    fn unpublished_packages(&self, target: InstallTarget) -> Vec<PackageKey> {
+        let records = crate::repo::installed::list(&self.config().read().unwrap());
+        records
+            .into_iter()
+            .filter(|x| x.install_target == target)
+            .filter(|x| self.find_package_by_key(&x.key).is_none())
+            .filter(|x| matches!(self.status(&x.key, target), Ok(PackageStatus::Unpublished)))
+            .map(|x| x.key)
+            .collect()
+    }
+
+    /// Installed packages that came from the repository at `url`, including those it
+    /// no longer publishes. Used to clean up before removing the repository.
+    fn installed_from_repo(&self, url: &RepoUrl, target: InstallTarget) -> Vec<PackageKey> {
+        let is_installed = |status: &Result<PackageStatus, PackageStatusError>| matches!(status, Ok(x) if *x != PackageStatus::NotInstalled);
+
+        let mut keys = self
+            .all_statuses(url, target)
+            .into_iter()
+            .filter(|(_, status)| is_installed(status))
+            .map(|(id, _)| PackageKey::new_unchecked(url.clone(), id, None))
+            .collect::<Vec<_>>();
+
+        let records = crate::repo::installed::list(&self.config().read().unwrap());
+        for record in records {
+            if &record.key.repository_url != url
+                || record.install_target != target
+                || keys.iter().any(|x| x.id == record.key.id)
+            {
+                continue;
+            }
+            if is_installed(&self.status(&record.key, target)) {
+                keys.push(record.key);
+            }
+        }
+
+        keys.sort_by(|a, b| a.id.cmp(&b.id));
+        keys
+    }
+
+    /// Loads an offline bundle written by [`crate::bundle::export`], so that its packages
+    /// install without network access. Returns the packages it was exported for.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn import_bundle(&self, path: &Path) -> Result<Vec<PackageKey>, crate::bundle::BundleError> {
+        let config = self.config();
+        let repos = self.repos();
+        let config = config.read().unwrap();
+        let mut repos = repos.write().unwrap();
+        crate::bundle::import(&config, &mut repos, path)
+    }
+
     #[must_use]
     fn refresh_repos(&self) -> Future<Result<(), HashMap<RepoUrl, RepoDownloadError>>>;
 
+    /// Reloads a single repository and the repositories it links to, leaving the
+    /// other loaded repositories as they are.
+    #[must_use]
+    fn refresh_repo(
+        &self,
+        url: &RepoUrl,
+    ) -> Future<Result<(), HashMap<RepoUrl, RepoDownloadError>>> {
+        let config = self.config().read().unwrap().clone();
+        let shared_config = self.config();
+        let repos = self.repos();
+        let url = url.clone();
+        Box::pin(async move {
+            let (mut result, mut errors) = crate::repo::load_repos(config, vec![url.clone()]).await;
+            crate::repo::discard_removed_repos(
+                &shared_config.read().unwrap(),
+                std::slice::from_ref(&url),
+                &mut result,
+                &mut errors,
+            );
+            {
+                let mut repos = repos.write().unwrap();
+                repos.remove(&url);
+                repos.extend(result);
+            }
+            crate::events::global().publish(crate::events::StoreEvent::RepositoriesChanged);
+            if errors.is_empty() {
+                Ok(())
+            } else {
+                Err(errors)
+            }
+        })
+    }
+
+    /// Removes a repository from the config along with its loaded index, last error
+    /// and cached files. Returns whether the repository was configured.
+    fn remove_repo(&self, url: &RepoUrl) -> Result<bool, FileError> {
+        let config = self.config();
+        let is_removed = config.write().unwrap().repos_mut().remove(url)?;
+
+        let downloads = crate::repo::repo_download_dirs(
+            &config.read().unwrap(),
+            url,
+            &self.repos().read().unwrap(),
+        );
+        self.repos().write().unwrap().remove(url);
+        self.errors().write().unwrap().remove(url);
+
+        if let Err(e) = crate::repo::purge_repo_cache(&config.read().unwrap(), url, &downloads) {
+            log::warn!("Could not remove cache for {}: {:?}", url, e);
+        }
+
+        Ok(is_removed)
+    }
+
     #[must_use]
     fn force_refresh_repos(&self) -> Future<Result<(), HashMap<RepoUrl, RepoDownloadError>>> {
         self.clear_cache();
@@ -156,3 +408,143 @@ pub trait PackageStore: Send + Sync {
         install_target: &[InstallTarget],
     ) -> ResolvedPackageQuery;
 }
+
+/// The payload of `key` and the path it was downloaded to, if it is downloaded
+/// and the install `options` apply to it.
+pub(crate) fn preflight_payload<S: PackageStore + ?Sized>(
+    store: &S,
+    key: &PackageKey,
+    options: &BTreeMap<String, String>,
+) -> Result<(pahkat_types::payload::Payload, PathBuf), InstallError> {
+    let repos = store.repos();
+    let repos = repos.read().unwrap();
+    let query = crate::repo::ReleaseQuery::new(key, &repos);
+    let (target, _, _) = crate::repo::resolve_payload(key, &query, &repos)?;
+    pahkat_types::payload::resolve_install_options(target.payload.install_options(), options)?;
+
+    let config = store.config();
+    let config = config.read().unwrap();
+    let path = crate::repo::download_file_path(&config, target.payload.url());
+
+    // Flatpak and snapd fetch what they install themselves
+    let is_fetched_later = matches!(
+        target.payload,
+        pahkat_types::payload::Payload::Flatpak(_) | pahkat_types::payload::Payload::Snap(_)
+    );
+    if !is_fetched_later && !crate::repo::is_payload_cached(&config, &target.payload) {
+        log::error!("Package path doesn't exist: {:?}", &path);
+        return Err(InstallError::PackageNotInCache);
+    }
+
+    Ok((target.payload, path))
+}
+
+/// Async entry points for a shared store.
+///
+/// Installers, uninstallers and status checks run external processes and query
+/// the OS, so they are run on the blocking pool instead of stalling the executor.
+pub trait AsyncPackageStore {
+    /// Runs `f` against the store on the blocking pool. The task is started right
+    /// away, whether or not the returned future is polled.
+    fn blocking<T, F>(&self, f: F) -> Future<Result<T, BlockingError>>
+    where
+        T: Send + 'static,
+        F: FnOnce(&dyn PackageStore) -> T + Send + 'static;
+
+    /// Like `blocking`, for operations that fail with an error of their own.
+    fn try_blocking<T, E, F>(&self, f: F) -> Future<Result<T, E>>
+    where
+        T: Send + 'static,
+        E: From<BlockingError> + Send + 'static,
+        F: FnOnce(&dyn PackageStore) -> Result<T, E> + Send + 'static,
+    {
+        let task = self.blocking(f);
+        Box::pin(async move { task.await? })
+    }
+
+    fn install_async(
+        &self,
+        key: &PackageKey,
+        target: InstallTarget,
+    ) -> Future<Result<PackageStatus, InstallError>> {
+        let key = key.clone();
+        self.try_blocking(move |store| store.install(&key, target))
+    }
+
+    fn uninstall_async(
+        &self,
+        key: &PackageKey,
+        target: InstallTarget,
+    ) -> Future<Result<PackageStatus, UninstallError>> {
+        let key = key.clone();
+        self.try_blocking(move |store| store.uninstall(&key, target))
+    }
+
+    /// Status of a package, or of a package set if the key refers to one.
+    fn status_async(
+        &self,
+        key: &PackageKey,
+        target: InstallTarget,
+    ) -> Future<Result<PackageStatus, PackageStatusError>> {
+        let key = key.clone();
+        self.try_blocking(move |store| match store.set_status(&key, target) {
+            Some(status) => status,
+            None => store.status(&key, target),
+        })
+    }
+
+    fn dependency_status_async(
+        &self,
+        key: &PackageKey,
+        target: InstallTarget,
+    ) -> Future<Result<Vec<(PackageKey, PackageStatus)>, PackageDependencyStatusError>> {
+        let task = {
+            let key = key.clone();
+            self.blocking(move |store| store.dependency_status(&key, target))
+        };
+        let key = key.clone();
+        Box::pin(async move {
+            task.await
+                .map_err(|e| PackageDependencyStatusError::Cancelled(key, e))?
+        })
+    }
+
+    fn all_statuses_async(
+        &self,
+        repo_url: &RepoUrl,
+        target: InstallTarget,
+    ) -> Future<Result<PackageStatuses, BlockingError>> {
+        let repo_url = repo_url.clone();
+        self.blocking(move |store| store.all_statuses(&repo_url, target))
+    }
+
+    fn import_async(
+        &self,
+        key: &PackageKey,
+        installer_path: &Path,
+    ) -> Future<Result<PathBuf, ImportError>> {
+        let key = key.clone();
+        let installer_path = installer_path.to_path_buf();
+        self.try_blocking(move |store| store.import(&key, &installer_path))
+    }
+}
+
+impl AsyncPackageStore for Arc<dyn PackageStore> {
+    fn blocking<T, F>(&self, f: F) -> Future<Result<T, BlockingError>>
+    where
+        T: Send + 'static,
+        F: FnOnce(&dyn PackageStore) -> T + Send + 'static,
+    {
+        let store = Arc::clone(self);
+        // Spawned before the future is returned, so that the future only holds the
+        // join handle and `f` need not be `Sync`.
+        let task = tokio::task::spawn_blocking(move || f(&*store));
+        Box::pin(async move {
+            match task.await {
+                Ok(value) => Ok(value),
+                Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+                Err(e) => Err(BlockingError(e.to_string())),
+            }
+        })
+    }
+}
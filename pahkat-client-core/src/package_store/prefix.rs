@@ -12,6 +12,7 @@ use r2d2_sqlite::SqliteConnectionManager;
 use xz2::bufread::XzDecoder;
 
 use super::InstallTarget;
+use crate::archive::{Limits as ArchiveLimits, Sanitizer};
 use crate::repo::RepoDownloadError;
 use crate::transaction::{
     install::InstallError, uninstall::UninstallError, PackageDependencyError,
@@ -36,7 +37,11 @@ use crate::{
 
 // type Result<T> = std::result::Result<T, Error>;
 
-const SQL_INIT: &str = include_str!("prefix/prefix_init.sql");
+pub(super) mod extract;
+
+pub use extract::ExtractError;
+
+pub(super) const SQL_INIT: &str = include_str!("prefix/prefix_init.sql");
 
 pub struct PrefixPackageStore {
     pool: r2d2::Pool<SqliteConnectionManager>,
@@ -138,7 +143,7 @@ impl PrefixPackageStore {
     }
 
     #[inline(always)]
-    fn make_pool(
+    pub(super) fn make_pool(
         manager: SqliteConnectionManager,
     ) -> Result<r2d2::Pool<SqliteConnectionManager>, r2d2::Error> {
         r2d2::Pool::builder()
@@ -216,6 +221,15 @@ impl PackageStore for PrefixPackageStore {
         &self,
         key: &PackageKey,
         target: InstallTarget,
+    ) -> Result<PackageStatus, InstallError> {
+        self.install_with_options(key, target, &BTreeMap::new())
+    }
+
+    fn install_with_options(
+        &self,
+        key: &PackageKey,
+        _target: InstallTarget,
+        options: &BTreeMap<String, String>,
     ) -> Result<PackageStatus, InstallError> {
         log::trace!("In prefix install");
 
@@ -229,6 +243,7 @@ impl PackageStore for PrefixPackageStore {
             pahkat_types::payload::Payload::TarballPackage(v) => v,
             _ => return Err(InstallError::WrongPayloadType),
         };
+        let options = pahkat_types::payload::resolve_install_options(&installer.options, options)?;
         let pkg_path =
             crate::repo::download_file_path(&*self.config.read().unwrap(), &installer.url);
         log::debug!("Installing {}: {:?}", &key, &pkg_path);
@@ -239,32 +254,28 @@ impl PackageStore for PrefixPackageStore {
         }
 
         let file = File::open(&pkg_path).unwrap();
+        let mut sanitizer =
+            Sanitizer::new(file.metadata().unwrap().len(), ArchiveLimits::default());
         let reader = XzDecoder::new(std::io::BufReader::new(file));
 
         let mut tar_file = tar::Archive::new(reader);
-        let mut files = vec![];
 
         let pkg_path = self.package_dir(&package.package.id);
-        create_dir_all(&pkg_path).unwrap(); // map_err(InstallError::CreateDirFailed)?;
+        let install_dir = installer
+            .install_dir()?
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
 
         log::debug!("Prefix: {:?}", &self.prefix);
 
-        for entry in tar_file.entries().unwrap() {
-            let mut entry = entry.unwrap();
-            let unpack_res;
-            {
-                unpack_res = entry.unpack_in(&pkg_path).unwrap(); //.context(UnpackFailed)?;
-            }
-
-            if unpack_res {
-                let entry_path = entry.header().path().unwrap();
-                log::debug!("entry path: {:?}", &entry_path);
-                let entry_path = entry_path.to_str().unwrap().to_string();
-                files.push(entry_path);
-            } else {
-                continue;
-            }
-        }
+        let files = extract::unpack(
+            &mut tar_file,
+            &mut sanitizer,
+            &pkg_path,
+            &install_dir,
+            &installer,
+            &options,
+        )?;
 
         let deps = &target.dependencies;
         let dependencies: Vec<String> = deps
@@ -301,17 +312,11 @@ impl PackageStore for PrefixPackageStore {
 
         let pkg_path = self.package_dir(&key.id);
         for file in &record.files {
-            let file = match pkg_path.join(file).canonicalize() {
-                Ok(v) => v,
-                Err(_) => continue,
-            };
-
-            if file.is_dir() {
-                continue;
-            }
-
-            if file.exists() {
-                remove_file(file).unwrap();
+            // Links are removed themselves rather than what they point to
+            let file = pkg_path.join(file);
+            match std::fs::symlink_metadata(&file) {
+                Ok(meta) if !meta.is_dir() => remove_file(file).unwrap(),
+                _ => continue,
             }
         }
 
@@ -339,7 +344,7 @@ impl PackageStore for PrefixPackageStore {
     fn status(
         &self,
         key: &PackageKey,
-        _target: InstallTarget,
+        install_target: InstallTarget,
     ) -> Result<PackageStatus, PackageStatusError> {
         let mut conn = self.pool.get().unwrap();
         let record = match PackageDbRecord::find_by_id(&mut conn, &key) {
@@ -352,15 +357,19 @@ impl PackageStore for PrefixPackageStore {
             crate::repo::ReleaseQuery::new(key, &*repos).and_payloads(vec!["TarballPackage"]);
         log::debug!("query: {:?}", &query);
 
-        let (target, release, package) = crate::repo::resolve_payload(key, &query, &*repos)
-            .map_err(PackageStatusError::Payload)?;
+        let config = self.config.read().unwrap();
+        let (target, release, package, is_published) =
+            crate::repo::resolve_installed_payload(&config, key, install_target, &query, &repos)
+                .map_err(PackageStatusError::Payload)?;
         let _installer = match target.payload {
             pahkat_types::payload::Payload::TarballPackage(v) => v,
             _ => return Err(PackageStatusError::WrongPayloadType),
         };
 
-        let config = self.config.read().unwrap();
-        let status = self::cmp::cmp(&record.version, &release.version);
+        let status = crate::repo::published_status(
+            is_published,
+            self::cmp::cmp(&record.version, &release.version),
+        );
 
         log::debug!("Status: {:?}", &status);
         status
@@ -371,33 +380,41 @@ impl PackageStore for PrefixPackageStore {
         key: &PackageKey,
         target: InstallTarget,
     ) -> Result<Vec<(PackageKey, PackageStatus)>, PackageDependencyStatusError> {
-        crate::repo::resolve_package_set(
-            self,
-            &[(PackageActionType::Install, key.clone())],
-            &[target],
-        )
-        .map(|dep| {
-            dep.into_iter()
-                .map(|dep| (dep.package_key, dep.status))
-                .collect()
-        })
-        .map_err(|err| match err {
-            PackageCandidateError::Status(p, PackageStatusError::Payload(e)) => {
-                PackageDependencyStatusError::Payload(p, e)
-            }
-            PackageCandidateError::Status(p, PackageStatusError::WrongPayloadType) => {
-                PackageDependencyStatusError::WrongPayloadType(p)
-            }
-            PackageCandidateError::Status(p, PackageStatusError::ParsingVersion) => {
-                PackageDependencyStatusError::ParsingVersion(p)
-            }
-
-            PackageCandidateError::Payload(p, e) => PackageDependencyStatusError::Payload(p, e),
-            PackageCandidateError::UnresolvedId(id) => {
-                PackageDependencyStatusError::PackageNotFound(id)
-            }
-            PackageCandidateError::UninstallConflict(_) => unreachable!(),
-        })
+        crate::repo::resolve_package_set(self, &[(PackageActionType::Install, key.clone(), target)])
+            .map(|dep| {
+                dep.into_iter()
+                    .map(|dep| (dep.package_key, dep.status))
+                    .collect()
+            })
+            .map_err(|err| match err {
+                PackageCandidateError::Status(p, PackageStatusError::Payload(e)) => {
+                    PackageDependencyStatusError::Payload(p, e)
+                }
+                PackageCandidateError::Status(p, PackageStatusError::WrongPayloadType) => {
+                    PackageDependencyStatusError::WrongPayloadType(p)
+                }
+                PackageCandidateError::Status(p, PackageStatusError::ParsingVersion) => {
+                    PackageDependencyStatusError::ParsingVersion(p)
+                }
+                PackageCandidateError::Status(p, PackageStatusError::Cancelled(e)) => {
+                    PackageDependencyStatusError::Cancelled(p, e)
+                }
+
+                PackageCandidateError::Payload(p, e) => PackageDependencyStatusError::Payload(p, e),
+                PackageCandidateError::UnresolvedId(id)
+                | PackageCandidateError::VirtualUnavailable(id) => {
+                    PackageDependencyStatusError::PackageNotFound(id)
+                }
+                PackageCandidateError::UninstallConflict(_)
+                | PackageCandidateError::InvalidOption(..)
+                | PackageCandidateError::TargetConflict(..) => unreachable!(),
+                PackageCandidateError::ClientUpdateRequired(p, v) => {
+                    PackageDependencyStatusError::ClientUpdateRequired(p, v)
+                }
+                PackageCandidateError::UnsatisfiedDependency(p, req, v) => {
+                    PackageDependencyStatusError::UnsatisfiedDependency(p, req, v)
+                }
+            })
     }
 
     fn all_statuses(
@@ -422,12 +439,21 @@ impl PackageStore for PrefixPackageStore {
         &self,
     ) -> crate::package_store::Future<Result<(), HashMap<RepoUrl, RepoDownloadError>>> {
         let config = self.config().read().unwrap().clone();
+        let requested = config.repos().keys().cloned().collect::<Vec<_>>();
+        let shared_config = self.config();
         let repos = self.repos();
         Box::pin(async move {
             log::trace!("Calling into refresh repos");
-            let (result, errors) = crate::repo::refresh_repos(config).await;
+            let (mut result, mut errors) = crate::repo::refresh_repos(config).await;
+            crate::repo::discard_removed_repos(
+                &shared_config.read().unwrap(),
+                &requested,
+                &mut result,
+                &mut errors,
+            );
             log::trace!("Finished refresh repos: {:?}", &errors);
             *repos.write().unwrap() = result;
+            crate::events::global().publish(crate::events::StoreEvent::RepositoriesChanged);
             if errors.is_empty() {
                 Ok(())
             } else {
@@ -447,8 +473,9 @@ impl PackageStore for PrefixPackageStore {
     {
         let repos = self.repos.read().unwrap();
         let urls = repos.keys().cloned().collect::<Vec<_>>();
+        let proxy = self.config.read().unwrap().settings().proxy().cloned();
 
-        Box::pin(crate::repo::strings(urls, language))
+        Box::pin(crate::repo::strings(urls, language, proxy))
     }
 
     fn resolve_package_query(
@@ -458,17 +485,22 @@ impl PackageStore for PrefixPackageStore {
     ) -> ResolvedPackageQuery {
         let repos = self.repos();
         let repos = repos.read().unwrap();
-        crate::repo::resolve_package_query(self, &query, install_target, &*repos)
+        crate::repo::resolve_package_query(
+            |key, target| self.status(key, target).ok(),
+            &query,
+            install_target,
+            &repos,
+        )
     }
 }
 
 #[derive(Debug)]
-struct PackageDbRecord {
-    id: i64,
-    url: String,
-    version: String,
-    files: Vec<String>,
-    dependencies: Vec<String>,
+pub(super) struct PackageDbRecord {
+    pub(super) id: i64,
+    pub(super) url: String,
+    pub(super) version: String,
+    pub(super) files: Vec<String>,
+    pub(super) dependencies: Vec<String>,
 }
 
 struct PackageDbConnection<'a>(&'a mut rusqlite::Connection);
@@ -504,15 +536,24 @@ impl<'a> PackageDbConnection<'a> {
         res
     }
 
-    fn version(&self, url: &str) -> Option<String> {
-        match self.0.query_row(
-            "SELECT version FROM packages WHERE url = ? LIMIT 1",
-            &[&url],
-            |row| row.get(0),
-        ) {
-            Ok(v) => v,
-            Err(_) => return None,
-        }
+    fn id_and_version(&self, url: &str) -> Option<(i64, String)> {
+        self.0
+            .query_row(
+                "SELECT id, version FROM packages WHERE url = ? LIMIT 1",
+                &[&url],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .ok()
+    }
+
+    fn other_owners(&self, id: i64, file_path: &str) -> i64 {
+        self.0
+            .query_row(
+                "SELECT COUNT(*) FROM packages_files WHERE file_path = ? AND package_id != ?",
+                rusqlite::params![file_path, id],
+                |row| row.get(0),
+            )
+            .unwrap_or(0)
     }
 
     fn replace_pkg(&mut self, pkg: &PackageDbRecord) -> rusqlite::Result<()> {
@@ -581,7 +622,7 @@ impl<'a> PackageDbConnection<'a> {
     fn remove_pkg(&mut self, pkg: &PackageDbRecord) -> rusqlite::Result<()> {
         let tx = self.0.transaction().unwrap();
 
-        tx.execute("DELETE FROM packages WHERE id = ?", &[&pkg.id])?;
+        // Rows referring to the package go first, as bundled SQLite enforces foreign keys
         tx.execute(
             "DELETE FROM packages_dependencies WHERE package_id = ?",
             &[&pkg.id],
@@ -590,6 +631,7 @@ impl<'a> PackageDbConnection<'a> {
             "DELETE FROM packages_files WHERE package_id = ?",
             &[&pkg.id],
         )?;
+        tx.execute("DELETE FROM packages WHERE id = ?", &[&pkg.id])?;
 
         tx.commit()
     }
@@ -603,7 +645,7 @@ impl PackageDbRecord {
         let conn = PackageDbConnection(conn);
         let url = key.clone().without_query_params().to_string();
 
-        let version = match conn.version(&url) {
+        let (id, version) = match conn.id_and_version(&url) {
             Some(v) => v,
             None => return None,
         };
@@ -612,7 +654,7 @@ impl PackageDbRecord {
         let dependencies = conn.dependencies(&url);
 
         Some(PackageDbRecord {
-            id: 0,
+            id,
             url,
             version,
             files,
@@ -620,6 +662,11 @@ impl PackageDbRecord {
         })
     }
 
+    /// Whether another installed package also installed `file_path`.
+    pub fn is_shared(&self, conn: &mut rusqlite::Connection, file_path: &str) -> bool {
+        PackageDbConnection(conn).other_owners(self.id, file_path) > 0
+    }
+
     pub fn save(&self, conn: &mut rusqlite::Connection) -> rusqlite::Result<()> {
         log::trace!("Saving package record: {:?}", &self);
         PackageDbConnection(conn).replace_pkg(self)
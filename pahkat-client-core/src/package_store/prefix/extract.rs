@@ -0,0 +1,451 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+
+use pahkat_types::payload::tarball;
+use tar::EntryType;
+
+use crate::archive::{MaliciousArchive, Sanitizer};
+use crate::transaction::install::InstallError;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ExtractError {
+    #[error("IO error")]
+    Io(#[from] io::Error),
+
+    #[error(transparent)]
+    Malicious(#[from] MaliciousArchive),
+}
+
+impl From<ExtractError> for InstallError {
+    fn from(e: ExtractError) -> Self {
+        match e {
+            ExtractError::Malicious(e) => InstallError::MaliciousArchive(e),
+            e => InstallError::ExtractFailed(e),
+        }
+    }
+}
+
+/// Unpacks a tarball payload into `pkg_path`, returning the extracted paths
+/// relative to it. Entries of options that were not chosen are skipped.
+///
+/// Permission bits are kept (except setuid, setgid and sticky), and symlinks and
+/// hardlinks are recreated as long as they resolve to a location inside `pkg_path`.
+/// Hardlinks to symlinks are refused.
+pub(crate) fn unpack<R: Read>(
+    archive: &mut tar::Archive<R>,
+    sanitizer: &mut Sanitizer,
+    pkg_path: &Path,
+    install_dir: &Path,
+    installer: &tarball::Package,
+    options: &BTreeMap<String, String>,
+) -> Result<Vec<String>, ExtractError> {
+    fs::create_dir_all(pkg_path.join(install_dir))?;
+    let root = pkg_path.canonicalize()?;
+
+    archive.set_preserve_permissions(false);
+    archive.set_preserve_mtime(true);
+
+    let mut files = vec![];
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let entry_path = entry.path()?.into_owned();
+        log::debug!("entry path: {:?}", &entry_path);
+
+        sanitizer.check_entry(&entry_path, entry.header().size()?)?;
+
+        let relative_path = match installer.strip_path(&entry_path) {
+            Some(v) if installer.is_selected(&v, options) => install_dir.join(v),
+            _ => continue,
+        };
+
+        // Resolve the parent for real, so that a symlink extracted earlier cannot
+        // be used to write outside of the package directory.
+        let dest_path = root.join(&relative_path);
+        let parent = dest_path.parent().unwrap();
+        fs::create_dir_all(parent)?;
+        let parent = parent.canonicalize()?;
+        if !parent.starts_with(&root) {
+            return Err(MaliciousArchive::PathEscape(entry_path).into());
+        }
+        let dest_path = parent.join(dest_path.file_name().unwrap());
+
+        match entry.header().entry_type() {
+            EntryType::Symlink => {
+                let target = link_name(&entry, &entry_path)?;
+                if !symlink_stays_within(&root, &parent, &target) {
+                    return Err(MaliciousArchive::LinkEscape(entry_path).into());
+                }
+                remove_existing(&dest_path)?;
+                symlink(&target, &dest_path)?;
+            }
+            EntryType::Link => {
+                let target = link_name(&entry, &entry_path)?;
+                let source = match sanitizer.check_path(&target) {
+                    Ok(_) => installer.strip_path(&target),
+                    Err(_) => None,
+                };
+                let source = match source {
+                    Some(v) => root.join(install_dir).join(v),
+                    None => return Err(MaliciousArchive::LinkEscape(entry_path).into()),
+                };
+                let source_parent = source.parent().unwrap().canonicalize()?;
+                if !source_parent.starts_with(&root) {
+                    return Err(MaliciousArchive::LinkEscape(entry_path).into());
+                }
+                // Linking to a symlink follows it on some platforms, so only
+                // regular files inside the package directory are linked to.
+                let source = source_parent.join(source.file_name().unwrap());
+                if fs::symlink_metadata(&source)?.file_type().is_symlink()
+                    || !source.canonicalize()?.starts_with(&root)
+                {
+                    return Err(MaliciousArchive::LinkEscape(entry_path).into());
+                }
+                remove_existing(&dest_path)?;
+                fs::hard_link(source, &dest_path)?;
+            }
+            EntryType::Regular | EntryType::Continuous | EntryType::Directory => {
+                remove_existing(&dest_path)?;
+                entry.unpack(&dest_path)?;
+            }
+            kind => {
+                log::warn!("Skipping unsupported entry {:?} ({:?})", &entry_path, kind);
+                continue;
+            }
+        }
+
+        files.push(relative_path.to_string_lossy().to_string());
+    }
+
+    Ok(files)
+}
+
+fn link_name<R: Read>(
+    entry: &tar::Entry<'_, R>,
+    entry_path: &Path,
+) -> Result<PathBuf, ExtractError> {
+    match entry.link_name()? {
+        Some(v) => Ok(v.into_owned()),
+        None => Err(MaliciousArchive::LinkEscape(entry_path.to_path_buf()).into()),
+    }
+}
+
+/// Only relative targets are allowed, and `..` may only appear at the start so
+/// that it can be resolved against the real parent directory.
+fn symlink_stays_within(root: &Path, parent: &Path, target: &Path) -> bool {
+    let mut resolved = parent.to_path_buf();
+    let mut seen_normal = false;
+
+    for component in target.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if !seen_normal => {
+                if !resolved.pop() || !resolved.starts_with(root) {
+                    return false;
+                }
+            }
+            Component::Normal(x) => {
+                seen_normal = true;
+                resolved.push(x);
+            }
+            _ => return false,
+        }
+    }
+
+    resolved.starts_with(root)
+}
+
+fn remove_existing(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if !meta.is_dir() => fs::remove_file(path),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, dest: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, dest)
+}
+
+#[cfg(windows)]
+fn symlink(target: &Path, dest: &Path) -> io::Result<()> {
+    let is_dir = dest
+        .parent()
+        .map(|x| x.join(target).is_dir())
+        .unwrap_or(false);
+    if is_dir {
+        std::os::windows::fs::symlink_dir(target, dest)
+    } else {
+        std::os::windows::fs::symlink_file(target, dest)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::archive::Limits;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn installer() -> tarball::Package {
+        tarball::Package::builder()
+            .url("https://example.com/a.txz".parse().unwrap())
+            .size(1)
+            .installed_size(1)
+            .build()
+    }
+
+    /// Writes an entry without the path checks done by `tar::Builder`.
+    fn raw_entry(
+        builder: &mut tar::Builder<Vec<u8>>,
+        kind: EntryType,
+        path: &str,
+        link: Option<&str>,
+        mode: u32,
+        data: &[u8],
+    ) {
+        let mut header = tar::Header::new_old();
+        header.set_entry_type(kind);
+        header.set_size(data.len() as u64);
+        header.set_mode(mode);
+        {
+            let old = header.as_old_mut();
+            old.name[..path.len()].copy_from_slice(path.as_bytes());
+            if let Some(link) = link {
+                old.linkname[..link.len()].copy_from_slice(link.as_bytes());
+            }
+        }
+        header.set_cksum();
+        builder.append(&header, data).unwrap();
+    }
+
+    fn extract_with(
+        compressed_size: u64,
+        limits: Limits,
+        entries: impl FnOnce(&mut tar::Builder<Vec<u8>>),
+    ) -> (tempfile::TempDir, Result<Vec<String>, ExtractError>) {
+        let dir = tempfile::tempdir().unwrap();
+        let result = unpack_into(&dir.path().join("pkg"), compressed_size, limits, entries);
+        (dir, result)
+    }
+
+    fn unpack_into(
+        pkg_path: &Path,
+        compressed_size: u64,
+        limits: Limits,
+        entries: impl FnOnce(&mut tar::Builder<Vec<u8>>),
+    ) -> Result<Vec<String>, ExtractError> {
+        let mut builder = tar::Builder::new(vec![]);
+        entries(&mut builder);
+        let data = builder.into_inner().unwrap();
+
+        let mut archive = tar::Archive::new(&*data);
+        let mut sanitizer = Sanitizer::new(compressed_size, limits);
+        unpack(
+            &mut archive,
+            &mut sanitizer,
+            pkg_path,
+            Path::new(""),
+            &installer(),
+            &BTreeMap::new(),
+        )
+    }
+
+    fn extract(
+        entries: impl FnOnce(&mut tar::Builder<Vec<u8>>),
+    ) -> (tempfile::TempDir, Result<Vec<String>, ExtractError>) {
+        extract_with(u64::MAX, Limits::default(), entries)
+    }
+
+    #[test]
+    fn preserves_links_and_permissions() {
+        let (dir, result) = extract(|b| {
+            raw_entry(b, EntryType::Directory, "bin/", None, 0o755, b"");
+            raw_entry(b, EntryType::Regular, "bin/tool", None, 0o755, b"#!/bin/sh");
+            raw_entry(b, EntryType::Symlink, "tool", Some("bin/tool"), 0o777, b"");
+            raw_entry(
+                b,
+                EntryType::Link,
+                "bin/tool2",
+                Some("bin/tool"),
+                0o755,
+                b"",
+            );
+        });
+        result.unwrap();
+
+        let pkg_path = dir.path().join("pkg");
+        let mode = fs::metadata(pkg_path.join("bin/tool"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o755);
+        assert_eq!(
+            fs::read_link(pkg_path.join("tool")).unwrap(),
+            Path::new("bin/tool")
+        );
+        assert_eq!(fs::read(pkg_path.join("bin/tool2")).unwrap(), b"#!/bin/sh");
+    }
+
+    #[test]
+    fn rejects_parent_dir_paths() {
+        let (dir, result) = extract(|b| {
+            raw_entry(b, EntryType::Regular, "../evil", None, 0o644, b"evil");
+        });
+        assert!(matches!(
+            result,
+            Err(ExtractError::Malicious(MaliciousArchive::ParentDir(_)))
+        ));
+        assert!(!dir.path().join("evil").exists());
+    }
+
+    #[test]
+    fn rejects_absolute_symlinks() {
+        let (_dir, result) = extract(|b| {
+            raw_entry(
+                b,
+                EntryType::Symlink,
+                "passwd",
+                Some("/etc/passwd"),
+                0o777,
+                b"",
+            );
+        });
+        assert!(matches!(
+            result,
+            Err(ExtractError::Malicious(MaliciousArchive::LinkEscape(_)))
+        ));
+    }
+
+    #[test]
+    fn rejects_relative_symlink_escapes() {
+        let (_dir, result) = extract(|b| {
+            raw_entry(b, EntryType::Symlink, "up", Some("../outside"), 0o777, b"");
+        });
+        assert!(matches!(
+            result,
+            Err(ExtractError::Malicious(MaliciousArchive::LinkEscape(_)))
+        ));
+
+        let (_dir, result) = extract(|b| {
+            raw_entry(b, EntryType::Symlink, "d", Some("."), 0o777, b"");
+            raw_entry(
+                b,
+                EntryType::Symlink,
+                "d/e/f/up",
+                Some("../../.."),
+                0o777,
+                b"",
+            );
+        });
+        assert!(matches!(
+            result,
+            Err(ExtractError::Malicious(MaliciousArchive::LinkEscape(_)))
+        ));
+    }
+
+    #[test]
+    fn rejects_hardlinks_outside() {
+        let (_dir, result) = extract(|b| {
+            raw_entry(
+                b,
+                EntryType::Link,
+                "passwd",
+                Some("/etc/passwd"),
+                0o644,
+                b"",
+            );
+        });
+        assert!(matches!(
+            result,
+            Err(ExtractError::Malicious(MaliciousArchive::LinkEscape(_)))
+        ));
+    }
+
+    #[test]
+    fn rejects_hardlinks_to_symlinks() {
+        let hardlink_to_etc = |b: &mut tar::Builder<Vec<u8>>| {
+            raw_entry(b, EntryType::Symlink, "etc", Some("/etc"), 0o777, b"");
+            raw_entry(b, EntryType::Link, "etc2", Some("etc"), 0o644, b"");
+        };
+
+        let (dir, result) = extract(hardlink_to_etc);
+        assert!(matches!(
+            result,
+            Err(ExtractError::Malicious(MaliciousArchive::LinkEscape(_)))
+        ));
+        assert!(fs::symlink_metadata(dir.path().join("pkg/etc2")).is_err());
+
+        // A symlink left in the package directory is not linked to either
+        let dir = tempfile::tempdir().unwrap();
+        let pkg_path = dir.path().join("pkg");
+        fs::create_dir_all(&pkg_path).unwrap();
+        std::os::unix::fs::symlink("/etc", pkg_path.join("etc")).unwrap();
+        let result = unpack_into(&pkg_path, u64::MAX, Limits::default(), |b| {
+            raw_entry(b, EntryType::Link, "etc2", Some("etc"), 0o644, b"");
+        });
+        assert!(matches!(
+            result,
+            Err(ExtractError::Malicious(MaliciousArchive::LinkEscape(_)))
+        ));
+        assert!(fs::symlink_metadata(pkg_path.join("etc2")).is_err());
+
+        let (dir, result) = extract(|b| {
+            raw_entry(b, EntryType::Regular, "tool", None, 0o755, b"#!/bin/sh");
+            raw_entry(b, EntryType::Symlink, "alias", Some("tool"), 0o777, b"");
+            raw_entry(b, EntryType::Link, "alias2", Some("alias"), 0o755, b"");
+        });
+        assert!(matches!(
+            result,
+            Err(ExtractError::Malicious(MaliciousArchive::LinkEscape(_)))
+        ));
+        assert!(fs::symlink_metadata(dir.path().join("pkg/alias2")).is_err());
+    }
+
+    #[test]
+    fn rejects_absolute_paths() {
+        let (_dir, result) = extract(|b| {
+            raw_entry(b, EntryType::Regular, "/tmp/evil", None, 0o644, b"evil");
+        });
+        assert!(matches!(
+            result,
+            Err(ExtractError::Malicious(MaliciousArchive::AbsolutePath(_)))
+        ));
+    }
+
+    #[test]
+    fn rejects_oversized_entries() {
+        let limits = Limits {
+            max_entry_size: 4,
+            ..Limits::default()
+        };
+        let (dir, result) = extract_with(u64::MAX, limits, |b| {
+            raw_entry(b, EntryType::Regular, "big", None, 0o644, b"too big");
+        });
+        assert!(matches!(
+            result,
+            Err(ExtractError::Malicious(MaliciousArchive::EntryTooLarge(..)))
+        ));
+        assert!(!dir.path().join("pkg/big").exists());
+    }
+
+    #[test]
+    fn rejects_compression_bombs() {
+        let limits = Limits {
+            max_ratio: 10,
+            ratio_threshold: 1024,
+            ..Limits::default()
+        };
+        let zeroes = vec![0u8; 64 * 1024];
+        let (_dir, result) = extract_with(1024, limits, |b| {
+            raw_entry(b, EntryType::Regular, "zeroes", None, 0o644, &zeroes);
+        });
+        assert!(matches!(
+            result,
+            Err(ExtractError::Malicious(MaliciousArchive::CompressionRatio(
+                _
+            )))
+        ));
+    }
+}
@@ -1,11 +1,13 @@
+mod actions;
+mod msix;
 mod sys;
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashSet};
 use std::convert::{TryFrom, TryInto};
 use std::ffi::OsString;
 use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 
 use hashbrown::HashMap;
 use registry::{Data, Hive, RegKey, Security};
@@ -22,22 +24,30 @@ use crate::Config;
 use crate::{repo::PayloadError, LoadedRepository, PackageActionType, PackageKey, PackageStore};
 use pahkat_types::{
     package::{Descriptor, Package},
-    payload::windows,
+    payload::windows::{self, InstallerKind},
     repo::RepoUrl,
 };
 
 const UNINSTALL_PATH: &'static str = r"Software\Microsoft\Windows\CurrentVersion\Uninstall";
 const DISPLAY_VERSION: &'static str = "DisplayVersion";
+const UNINSTALL_STRING: &'static str = "UninstallString";
 const QUIET_UNINSTALL_STRING: &'static str = "QuietUninstallString";
 
+// Installers report success that only takes effect after a restart with these.
+const ERROR_SUCCESS_REBOOT_INITIATED: i32 = 1641;
+const ERROR_SUCCESS_REBOOT_REQUIRED: i32 = 3010;
+
 use super::LocalizedStrings;
 use super::{SharedRepoErrors, SharedRepos, SharedStoreConfig};
 
+pub use self::actions::enable_input_sources;
+
 #[derive(Debug)]
 pub struct WindowsPackageStore {
     repos: SharedRepos,
     errors: SharedRepoErrors,
     config: SharedStoreConfig,
+    reboot_requests: Mutex<HashSet<PackageKey>>,
 }
 
 impl PackageStore for WindowsPackageStore {
@@ -73,6 +83,15 @@ impl PackageStore for WindowsPackageStore {
         &self,
         key: &PackageKey,
         install_target: InstallTarget,
+    ) -> Result<PackageStatus, InstallError> {
+        self.install_with_options(key, install_target, &BTreeMap::new())
+    }
+
+    fn install_with_options(
+        &self,
+        key: &PackageKey,
+        install_target: InstallTarget,
+        options: &BTreeMap<String, String>,
     ) -> Result<PackageStatus, InstallError> {
         let repos = self.repos.read().unwrap();
         let query = crate::repo::ReleaseQuery::new(key, &*repos);
@@ -81,8 +100,25 @@ impl PackageStore for WindowsPackageStore {
             crate::repo::resolve_payload(key, &query, &*repos).map_err(InstallError::Payload)?;
         let installer = match target.payload {
             pahkat_types::payload::Payload::WindowsExecutable(v) => v,
+            pahkat_types::payload::Payload::WindowsMsix(v) => {
+                let pkg_path =
+                    crate::repo::download_file_path(&self.config.read().unwrap(), &v.url);
+                log::debug!("Installing {}: {:?}", &key, &pkg_path);
+                if !pkg_path.exists() {
+                    log::error!("Package path doesn't exist: {:?}", &pkg_path);
+                    return Err(InstallError::PackageNotInCache);
+                }
+
+                msix::install(&pkg_path, install_target).map_err(InstallError::InstallerFailure)?;
+                actions::run(&v.actions);
+
+                return Ok(self
+                    .status_impl(key, &descriptor, &release.version, install_target)
+                    .unwrap());
+            }
             _ => return Err(InstallError::WrongPayloadType),
         };
+        let options = pahkat_types::payload::resolve_install_options(&installer.options, options)?;
         let pkg_path =
             crate::repo::download_file_path(&*self.config.read().unwrap(), &installer.url);
         log::debug!("Installing {}: {:?}", &key, &pkg_path);
@@ -92,37 +128,45 @@ impl PackageStore for WindowsPackageStore {
             return Err(InstallError::PackageNotInCache);
         }
 
-        let mut args: Vec<OsString> = match (&installer.kind, &installer.args) {
+        if let Some(publisher) = installer.publisher.as_ref() {
+            verify_authenticode(&pkg_path, publisher).map_err(InstallError::InvalidSignature)?;
+        }
+
+        let kind = installer
+            .kind
+            .clone()
+            .or_else(|| detect_installer_kind(&pkg_path));
+
+        let mut args: Vec<OsString> = match (&kind, &installer.args) {
             (_, &Some(ref v)) => sys::args(&v).map(|x| x.clone()).collect(),
-            (&Some(ref type_), &None) => {
+            (&Some(InstallerKind::Msi), &None) => {
                 let mut arg_str = OsString::new();
-                // TODO: generic parameter extensions for windows based on install target
-                match type_.as_ref() {
-                    "inno" => {
-                        arg_str.push("\"");
-                        arg_str.push(&pkg_path);
-                        arg_str.push("\" /VERYSILENT /SP- /SUPPRESSMSGBOXES /NORESTART");
-                        // TODO: add user-mode installation?
-                    }
-                    "msi" => {
-                        arg_str.push("msiexec /i \"");
-                        arg_str.push(&pkg_path);
-                        arg_str.push("\" /qn /norestart");
-                    }
-                    "nsis" => {
-                        arg_str.push("\"");
-                        arg_str.push(&pkg_path);
-                        arg_str.push("\" /S");
-                        // if target == InstallTarget::User {
-                        //     arg_str.push(" /CurrentUser")
-                        // }
-                    }
-                    kind => {
-                        log::warn!("Unknown kind: {:?}", &kind);
-                    }
-                };
+                arg_str.push("msiexec /i \"");
+                arg_str.push(&pkg_path);
+                arg_str.push("\" /qn /norestart");
+
+                let config = self.config.read().unwrap();
+                let record = config.repos().get(&key.repository_url);
+                arg_str.push(msi_deployment_args(&installer, record, &options)?);
                 sys::args(&arg_str.as_os_str()).collect()
             }
+            // TODO: generic parameter extensions for windows based on install target
+            (&Some(ref kind), &None) => match kind.silent_install_args() {
+                Some(silent_args) => {
+                    let mut arg_str = OsString::new();
+                    arg_str.push("\"");
+                    arg_str.push(&pkg_path);
+                    arg_str.push("\" ");
+                    arg_str.push(silent_args);
+                    sys::args(&arg_str.as_os_str()).collect()
+                }
+                None => {
+                    return Err(InstallError::Payload(PayloadError::CriteriaUnmet(format!(
+                        "No silent arguments known for installer kind {}",
+                        kind
+                    ))))
+                }
+            },
             _ => sys::args(&OsString::from(pkg_path)).collect(),
         };
         log::debug!("{:?}", &args);
@@ -131,7 +175,7 @@ impl PackageStore for WindowsPackageStore {
 
         // log::debug!("Cmd line: {:?} {:?}", &pkg_path, &args);
 
-        let res = Command::new(&prog).args(&args).output();
+        let res = crate::priority::command(&prog).args(&args).output();
 
         let output = match res {
             Ok(v) => v,
@@ -141,18 +185,37 @@ impl PackageStore for WindowsPackageStore {
             }
         };
 
-        if !output.status.success() {
+        if self.record_reboot_request(key, &output.status) {
+            log::info!("Installer for {} requested a restart", &key);
+        } else if !output.status.success() {
             log::error!("{:?}", output);
             return Err(InstallError::InstallerFailure(ProcessError::Unknown(
                 output,
             )));
         }
 
+        actions::run(&installer.actions);
+
         Ok(self
             .status_impl(key, &descriptor, &release.version, install_target)
             .unwrap())
     }
 
+    fn preflight(
+        &self,
+        key: &PackageKey,
+        options: &BTreeMap<String, String>,
+    ) -> Result<(), InstallError> {
+        let (payload, pkg_path) = super::preflight_payload(self, key, options)?;
+        if let pahkat_types::payload::Payload::WindowsExecutable(v) = payload {
+            if let Some(publisher) = v.publisher.as_ref() {
+                verify_authenticode(&pkg_path, publisher)
+                    .map_err(InstallError::InvalidSignature)?;
+            }
+        }
+        Ok(())
+    }
+
     fn uninstall(
         &self,
         key: &PackageKey,
@@ -161,10 +224,24 @@ impl PackageStore for WindowsPackageStore {
         let repos = self.repos.read().unwrap();
         let query = crate::repo::ReleaseQuery::new(key, &*repos);
 
-        let (target, release, descriptor) =
-            crate::repo::resolve_payload(key, &query, &*repos).map_err(UninstallError::Payload)?;
+        let (target, release, descriptor, _) = crate::repo::resolve_installed_payload(
+            &self.config.read().unwrap(),
+            key,
+            install_target,
+            &query,
+            &repos,
+        )
+        .map_err(UninstallError::Payload)?;
         let installer = match target.payload {
             pahkat_types::payload::Payload::WindowsExecutable(v) => v,
+            pahkat_types::payload::Payload::WindowsMsix(v) => {
+                msix::uninstall(&v.package_family_name, install_target)
+                    .map_err(UninstallError::UninstallerFailure)?;
+
+                return Ok(self
+                    .status_impl(key, &descriptor, &release.version, install_target)
+                    .unwrap());
+            }
             _ => return Err(UninstallError::WrongPayloadType),
         };
 
@@ -173,6 +250,38 @@ impl PackageStore for WindowsPackageStore {
             None => return Err(UninstallError::NotInstalled),
         };
 
+        // A declared command replaces both the registered uninstaller and its arguments
+        let declared = installer
+            .uninstall
+            .as_ref()
+            .filter(|x| x.quiet_command.is_some());
+        if let Some(uninstall) = declared {
+            let uninstall_string = match regkey.value(UNINSTALL_STRING) {
+                Ok(Data::String(v)) => Some(v.to_string_lossy()),
+                _ => None,
+            };
+            let command = uninstall
+                .command(&installer.product_code, uninstall_string.as_deref())
+                .ok_or_else(|| {
+                    UninstallError::Payload(PayloadError::CriteriaUnmet(
+                        "Uninstall command needs an UninstallString that is not registered.".into(),
+                    ))
+                })?;
+
+            let mut args: Vec<OsString> = sys::args(&command).collect();
+            if args.is_empty() {
+                return Err(UninstallError::Payload(PayloadError::CriteriaUnmet(
+                    "Uninstall command is empty.".into(),
+                )));
+            }
+            let prog = args.remove(0);
+            self.run_uninstaller(key, &prog, &args)?;
+
+            return Ok(self
+                .status_impl(key, &descriptor, &release.version, install_target)
+                .unwrap());
+        }
+
         let uninst_string: String = match regkey
             .value(QUIET_UNINSTALL_STRING)
             .or_else(|_| regkey.value(QUIET_UNINSTALL_STRING))
@@ -194,18 +303,27 @@ impl PackageStore for WindowsPackageStore {
         let prog = raw_args[0].clone();
         raw_args.remove(0);
 
-        let args: Vec<OsString> = match (&installer.kind, &installer.uninstall_args) {
+        // The installer is only still around to detect its kind if it is cached
+        let kind = installer.kind.clone().or_else(|| {
+            let config = self.config.read().unwrap();
+            detect_installer_kind(&crate::repo::download_file_path(&config, &installer.url))
+        });
+
+        let args: Vec<OsString> = match (&kind, &installer.uninstall_args) {
             (_, &Some(ref v)) => sys::args(&v).map(|x| x.clone()).collect(),
-            (&Some(ref type_), &None) => {
-                let arg_str = match type_.as_ref() {
-                    "inno" => "/VERYSILENT /SP- /SUPPRESSMSGBOXES /NORESTART".to_owned(),
-                    "msi" => format!("/x \"{}\" /qn /norestart", &installer.product_code),
-                    "nsis" => "/S".to_owned(),
-                    _ => {
-                        return Err(UninstallError::Payload(PayloadError::CriteriaUnmet(
-                            "Invalid type specified for package installer.".into(),
-                        )))
+            (&Some(ref kind), &None) => {
+                let arg_str = match kind {
+                    InstallerKind::Msi => {
+                        format!("/x \"{}\" /qn /norestart", &installer.product_code)
                     }
+                    kind => match kind.silent_uninstall_args() {
+                        Some(v) => v.to_owned(),
+                        None => {
+                            return Err(UninstallError::Payload(PayloadError::CriteriaUnmet(
+                                "Invalid type specified for package installer.".into(),
+                            )))
+                        }
+                    },
                 };
                 sys::args(&arg_str).collect()
             }
@@ -216,28 +334,17 @@ impl PackageStore for WindowsPackageStore {
             }
         };
 
-        let res = Command::new(&prog).args(&args).output();
-
-        let output = match res {
-            Ok(v) => v,
-            Err(e) => {
-                log::error!("{:?}", e);
-                return Err(UninstallError::UninstallerFailure(ProcessError::Io(e)));
-            }
-        };
-
-        if !output.status.success() {
-            log::error!("{:?}", output);
-            return Err(UninstallError::UninstallerFailure(ProcessError::Unknown(
-                output,
-            )));
-        }
+        self.run_uninstaller(key, &prog, &args)?;
 
         Ok(self
             .status_impl(key, &descriptor, &release.version, install_target)
             .unwrap())
     }
 
+    fn take_reboot_request(&self, key: &PackageKey) -> bool {
+        self.reboot_requests.lock().unwrap().remove(key)
+    }
+
     fn status(
         &self,
         key: &PackageKey,
@@ -248,14 +355,24 @@ impl PackageStore for WindowsPackageStore {
         let repos = self.repos.read().unwrap();
         let query = crate::repo::ReleaseQuery::new(key, &*repos);
 
-        let (target, release, descriptor) = crate::repo::resolve_payload(key, &query, &*repos)
-            .map_err(PackageStatusError::Payload)?;
-        let installer = match target.payload {
-            pahkat_types::payload::Payload::WindowsExecutable(v) => v,
+        let (target, release, descriptor, is_published) = crate::repo::resolve_installed_payload(
+            &self.config.read().unwrap(),
+            key,
+            install_target,
+            &query,
+            &repos,
+        )
+        .map_err(PackageStatusError::Payload)?;
+        match target.payload {
+            pahkat_types::payload::Payload::WindowsExecutable(_)
+            | pahkat_types::payload::Payload::WindowsMsix(_) => {}
             _ => return Err(PackageStatusError::WrongPayloadType),
         };
 
-        self.status_impl(key, &descriptor, &release.version, install_target)
+        crate::repo::published_status(
+            is_published,
+            self.status_impl(key, &descriptor, &release.version, install_target),
+        )
     }
 
     fn dependency_status(
@@ -263,33 +380,41 @@ impl PackageStore for WindowsPackageStore {
         key: &PackageKey,
         target: InstallTarget,
     ) -> Result<Vec<(PackageKey, PackageStatus)>, PackageDependencyStatusError> {
-        crate::repo::resolve_package_set(
-            self,
-            &[(PackageActionType::Install, key.clone())],
-            &[target],
-        )
-        .map(|dep| {
-            dep.into_iter()
-                .map(|dep| (dep.package_key, dep.status))
-                .collect()
-        })
-        .map_err(|err| match err {
-            PackageCandidateError::Status(p, PackageStatusError::Payload(e)) => {
-                PackageDependencyStatusError::Payload(p, e)
-            }
-            PackageCandidateError::Status(p, PackageStatusError::WrongPayloadType) => {
-                PackageDependencyStatusError::WrongPayloadType(p)
-            }
-            PackageCandidateError::Status(p, PackageStatusError::ParsingVersion) => {
-                PackageDependencyStatusError::ParsingVersion(p)
-            }
-
-            PackageCandidateError::Payload(p, e) => PackageDependencyStatusError::Payload(p, e),
-            PackageCandidateError::UnresolvedId(id) => {
-                PackageDependencyStatusError::PackageNotFound(id)
-            }
-            PackageCandidateError::UninstallConflict(_) => unreachable!(),
-        })
+        crate::repo::resolve_package_set(self, &[(PackageActionType::Install, key.clone(), target)])
+            .map(|dep| {
+                dep.into_iter()
+                    .map(|dep| (dep.package_key, dep.status))
+                    .collect()
+            })
+            .map_err(|err| match err {
+                PackageCandidateError::Status(p, PackageStatusError::Payload(e)) => {
+                    PackageDependencyStatusError::Payload(p, e)
+                }
+                PackageCandidateError::Status(p, PackageStatusError::WrongPayloadType) => {
+                    PackageDependencyStatusError::WrongPayloadType(p)
+                }
+                PackageCandidateError::Status(p, PackageStatusError::ParsingVersion) => {
+                    PackageDependencyStatusError::ParsingVersion(p)
+                }
+                PackageCandidateError::Status(p, PackageStatusError::Cancelled(e)) => {
+                    PackageDependencyStatusError::Cancelled(p, e)
+                }
+
+                PackageCandidateError::Payload(p, e) => PackageDependencyStatusError::Payload(p, e),
+                PackageCandidateError::UnresolvedId(id)
+                | PackageCandidateError::VirtualUnavailable(id) => {
+                    PackageDependencyStatusError::PackageNotFound(id)
+                }
+                PackageCandidateError::UninstallConflict(_)
+                | PackageCandidateError::InvalidOption(..)
+                | PackageCandidateError::TargetConflict(..) => unreachable!(),
+                PackageCandidateError::ClientUpdateRequired(p, v) => {
+                    PackageDependencyStatusError::ClientUpdateRequired(p, v)
+                }
+                PackageCandidateError::UnsatisfiedDependency(p, req, v) => {
+                    PackageDependencyStatusError::UnsatisfiedDependency(p, req, v)
+                }
+            })
     }
 
     fn find_package_by_key(&self, key: &PackageKey) -> Option<Package> {
@@ -306,10 +431,19 @@ impl PackageStore for WindowsPackageStore {
         &self,
     ) -> crate::package_store::Future<Result<(), HashMap<RepoUrl, RepoDownloadError>>> {
         let config = self.config().read().unwrap().clone();
+        let requested = config.repos().keys().cloned().collect::<Vec<_>>();
+        let shared_config = self.config();
         let repos = self.repos();
         Box::pin(async move {
-            let (result, errors) = crate::repo::refresh_repos(config).await;
+            let (mut result, mut errors) = crate::repo::refresh_repos(config).await;
+            crate::repo::discard_removed_repos(
+                &shared_config.read().unwrap(),
+                &requested,
+                &mut result,
+                &mut errors,
+            );
             *repos.write().unwrap() = result;
+            crate::events::global().publish(crate::events::StoreEvent::RepositoriesChanged);
             if errors.is_empty() {
                 Ok(())
             } else {
@@ -342,8 +476,9 @@ impl PackageStore for WindowsPackageStore {
     ) -> crate::package_store::Future<HashMap<RepoUrl, LocalizedStrings>> {
         let repos = self.repos.read().unwrap();
         let urls = repos.keys().cloned().collect::<Vec<_>>();
+        let proxy = self.config.read().unwrap().settings().proxy().cloned();
 
-        Box::pin(crate::repo::strings(urls, language))
+        Box::pin(crate::repo::strings(urls, language, proxy))
     }
 
     fn resolve_package_query(
@@ -353,16 +488,62 @@ impl PackageStore for WindowsPackageStore {
     ) -> ResolvedPackageQuery {
         let repos = self.repos();
         let repos = repos.read().unwrap();
-        crate::repo::resolve_package_query(self, &query, install_target, &*repos)
+        crate::repo::resolve_package_query(
+            |key, target| self.status(key, target).ok(),
+            &query,
+            install_target,
+            &repos,
+        )
     }
 }
 
 impl WindowsPackageStore {
+    /// Records a restart request for the package if the exit code is one of the
+    /// "success, but reboot" codes.
+    fn record_reboot_request(&self, key: &PackageKey, status: &std::process::ExitStatus) -> bool {
+        match status.code() {
+            Some(ERROR_SUCCESS_REBOOT_REQUIRED) | Some(ERROR_SUCCESS_REBOOT_INITIATED) => {
+                self.reboot_requests.lock().unwrap().insert(key.clone());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn run_uninstaller(
+        &self,
+        key: &PackageKey,
+        prog: &OsString,
+        args: &[OsString],
+    ) -> Result<(), UninstallError> {
+        let res = crate::priority::command(prog).args(args).output();
+
+        let output = match res {
+            Ok(v) => v,
+            Err(e) => {
+                log::error!("{:?}", e);
+                return Err(UninstallError::UninstallerFailure(ProcessError::Io(e)));
+            }
+        };
+
+        if self.record_reboot_request(key, &output.status) {
+            log::info!("Uninstaller for {} requested a restart", &key);
+        } else if !output.status.success() {
+            log::error!("{:?}", output);
+            return Err(UninstallError::UninstallerFailure(ProcessError::Unknown(
+                output,
+            )));
+        }
+
+        Ok(())
+    }
+
     pub async fn new(config: Config) -> WindowsPackageStore {
         let store = WindowsPackageStore {
             repos: Default::default(),
             errors: Default::default(),
             config: Arc::new(RwLock::new(config)),
+            reboot_requests: Default::default(),
         };
 
         // We ignore errors here.
@@ -380,11 +561,29 @@ impl WindowsPackageStore {
         key: &PackageKey,
         package: &Descriptor,
         version: &pahkat_types::package::Version,
-        _target: InstallTarget,
+        target: InstallTarget,
     ) -> Result<PackageStatus, PackageStatusError> {
         let repos = self.repos.read().unwrap();
         let mut query = crate::repo::ReleaseQuery::new(key, &*repos);
 
+        // MSIX packages are found by their family name rather than in the registry
+        let family_names = query
+            .iter(package)
+            .filter_map(|x| match x.target.payload {
+                pahkat_types::payload::Payload::WindowsMsix(ref v) => {
+                    Some(v.package_family_name.clone())
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if let Some(installed) = family_names
+            .iter()
+            .find_map(|x| msix::installed_version(x, target))
+        {
+            log::trace!("MSIX version: {}", &installed);
+            return crate::cmp::cmp(&installed, &version);
+        }
+
         let (response, inst_key) = match query
             .iter(package)
             .filter_map(|x| match x.target.payload {
@@ -412,17 +611,230 @@ impl WindowsPackageStore {
 }
 
 #[inline(always)]
+/// Installers are recognised by markers near the start of the file.
+fn detect_installer_kind(path: &Path) -> Option<InstallerKind> {
+    use std::io::Read;
+
+    let mut bytes = vec![];
+    std::fs::File::open(path)
+        .ok()?
+        .take(8 * 1024 * 1024)
+        .read_to_end(&mut bytes)
+        .ok()?;
+
+    let kind = InstallerKind::detect(&bytes);
+    log::debug!("Detected installer kind of {:?}: {:?}", path, &kind);
+    kind
+}
+
 fn uninstall_regkey(installer: &windows::Executable) -> Option<RegKey> {
+    let name = installer
+        .uninstall
+        .as_ref()
+        .and_then(|x| x.registry_key.as_deref())
+        .unwrap_or(&*installer.product_code);
+
     Hive::LocalMachine
         .open(
-            vec![UNINSTALL_PATH, &*installer.product_code].join(r"\"),
+            vec![UNINSTALL_PATH, name].join(r"\"),
             Security::Read | Security::Wow6464Key,
         )
         .or_else(|_| {
             Hive::LocalMachine.open(
-                vec![UNINSTALL_PATH, &*installer.product_code].join(r"\"),
+                vec![UNINSTALL_PATH, name].join(r"\"),
                 Security::Read | Security::Wow6432Key,
             )
         })
         .ok()
 }
+
+/// Builds the `TRANSFORMS=` and public property arguments for msiexec. Repo
+/// config properties override those declared by the package, and install options
+/// chosen for this install override both.
+fn msi_deployment_args(
+    installer: &windows::Executable,
+    record: Option<&crate::config::RepoRecord>,
+    options: &BTreeMap<String, String>,
+) -> Result<String, InstallError> {
+    let mut properties = installer.msi_properties.clone();
+    let mut transforms = installer.msi_transforms.clone();
+
+    if let Some(record) = record {
+        properties.extend(
+            record
+                .msi_properties
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone())),
+        );
+        transforms.extend(record.msi_transforms.iter().cloned());
+    }
+    properties.extend(options.iter().map(|(k, v)| (k.clone(), v.clone())));
+
+    // Repository configs and descriptors are not trusted to be quoted correctly
+    if let Some(key) = properties
+        .keys()
+        .find(|x| !windows::is_msi_property_name(x))
+    {
+        return Err(InstallError::InvalidMsiProperty(key.clone()));
+    }
+    if let Some(transform) = transforms
+        .iter()
+        .find(|x| !windows::is_valid_msi_transform(x))
+    {
+        return Err(InstallError::InvalidMsiTransform(transform.clone()));
+    }
+
+    let mut out = String::new();
+    if !transforms.is_empty() {
+        out.push_str(&format!(" TRANSFORMS=\"{}\"", transforms.join(";")));
+    }
+    for (key, value) in properties {
+        out.push_str(&format!(" {}=\"{}\"", key, value.replace('"', "\"\"")));
+    }
+    Ok(out)
+}
+
+fn verify_authenticode(pkg_path: &Path, publisher: &str) -> Result<(), String> {
+    // The path is passed through the environment to sidestep PowerShell quoting rules.
+    let output = Command::new("powershell.exe")
+        .args(&[
+            "-NoProfile",
+            "-NonInteractive",
+            "-Command",
+            "$s = Get-AuthenticodeSignature -LiteralPath $env:PAHKAT_VERIFY_PATH; \
+             Write-Output $s.Status; Write-Output $s.SignerCertificate.Subject",
+        ])
+        .env("PAHKAT_VERIFY_PATH", pkg_path)
+        .output()
+        .map_err(|e| format!("could not run Get-AuthenticodeSignature: {}", e))?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut lines = stdout.lines().map(str::trim);
+    let status = lines.next().unwrap_or("");
+    let subject = lines.next().unwrap_or("");
+
+    log::debug!("Authenticode status: {:?}, subject: {:?}", status, subject);
+
+    check_signer(status, subject, publisher).map_err(|e| format!("{}: {}", pkg_path.display(), e))
+}
+
+/// Checks the status and signer subject reported by `Get-AuthenticodeSignature`, such as
+/// `CN="Example, Ltd", O="Example, Ltd", C=NO`.
+fn check_signer(status: &str, subject: &str, publisher: &str) -> Result<(), String> {
+    if status != "Valid" {
+        return Err(format!("signature status is {}", status));
+    }
+
+    // Values containing commas are quoted, so the subject cannot simply be split on them
+    let start = if subject.starts_with("CN=") {
+        Some(3)
+    } else {
+        subject.find(", CN=").map(|i| i + 5)
+    };
+    let common_name = start
+        .map(|i| &subject[i..])
+        .map(|x| match x.strip_prefix('"') {
+            Some(quoted) => quoted.split('"').next().unwrap_or(""),
+            None => x.split(", ").next().unwrap_or(""),
+        });
+
+    match common_name {
+        Some(cn) if cn == publisher => Ok(()),
+        Some(cn) => Err(format!(
+            "expected publisher `{}`, found `{}`",
+            publisher, cn
+        )),
+        None => Err("no signer found".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pahkat_types::package::{Descriptor, DescriptorData, Release, Version};
+    use pahkat_types::payload::{arch, windows::Executable, Payload, Target};
+
+    use super::check_signer;
+    use crate::repo::ReleaseQuery;
+
+    #[test]
+    fn signer_common_name() {
+        let subject = "CN=Example Ltd, O=Example Ltd, L=Oslo, C=NO";
+        assert!(check_signer("Valid", subject, "Example Ltd").is_ok());
+        assert!(check_signer("Valid", subject, "Example").is_err());
+        assert!(check_signer("HashMismatch", subject, "Example Ltd").is_err());
+        assert!(check_signer("NotSigned", "", "Example Ltd").is_err());
+        assert!(check_signer("Valid", "O=Example Ltd", "Example Ltd").is_err());
+
+        let subject = "CN=\"Example, Ltd\", O=\"Example, Ltd\", C=NO";
+        assert!(check_signer("Valid", subject, "Example, Ltd").is_ok());
+        assert!(check_signer("Valid", "O=Example, CN=Example Ltd", "Example Ltd").is_ok());
+    }
+
+    fn descriptor(arches: &[&str]) -> Descriptor {
+        let target = |arch: &str| {
+            Target::builder()
+                .platform("windows".into())
+                .arch(Some(arch.to_string()))
+                .payload(Payload::WindowsExecutable(
+                    Executable::builder()
+                        .url(format!("https://example.com/{}.exe", arch).parse().unwrap())
+                        .product_code("{a88c2543-9c04-4fc4-b2bd-bed6daff4341}".into())
+                        .size(1)
+                        .installed_size(1)
+                        .build(),
+                ))
+                .build()
+        };
+
+        Descriptor::builder()
+            .package(DescriptorData::builder().id("speller".into()).build())
+            .release(vec![Release::builder()
+                .version(Version::new("1.0.0").unwrap())
+                .target(arches.iter().map(|x| target(x)).collect())
+                .build()])
+            .build()
+    }
+
+    fn resolved_arch(native: &str, arches: &[&str]) -> Option<String> {
+        let query = ReleaseQuery {
+            platform: "windows",
+            arch: Some(native),
+            emulated_arches: arch::emulated(
+                "windows",
+                native,
+                Some(arch::WINDOWS_X86_64_EMULATION_BUILD),
+            )
+            .to_vec(),
+            ..Default::default()
+        };
+        let descriptor = descriptor(arches);
+        let arch = query
+            .iter(&descriptor)
+            .next()
+            .map(|x| x.target.arch.clone().unwrap());
+        arch
+    }
+
+    #[test]
+    fn arm64_prefers_native_target() {
+        assert_eq!(
+            resolved_arch("aarch64", &["x86", "x86_64", "arm64"]).as_deref(),
+            Some("arm64")
+        );
+    }
+
+    #[test]
+    fn arm64_falls_back_to_emulated_targets_in_order() {
+        assert_eq!(
+            resolved_arch("aarch64", &["x86", "x86_64"]).as_deref(),
+            Some("x86_64")
+        );
+        assert_eq!(resolved_arch("aarch64", &["x86"]).as_deref(), Some("x86"));
+    }
+
+    #[test]
+    fn x86_64_runs_x86_under_wow64() {
+        assert_eq!(resolved_arch("x86_64", &["x86"]).as_deref(), Some("x86"));
+        assert_eq!(resolved_arch("x86", &["x86_64"]), None);
+    }
+}
@@ -0,0 +1,134 @@
+use std::ffi::OsStr;
+use std::os::windows::ffi::OsStrExt;
+
+use pahkat_types::payload::{Action, ActionKind};
+use winapi::shared::minwindef::{BOOL, DWORD};
+use winapi::um::handleapi::CloseHandle;
+use winapi::um::libloaderapi::{FreeLibrary, GetProcAddress, LoadLibraryW};
+use winapi::um::processthreadsapi::{GetCurrentProcess, OpenProcessToken};
+use winapi::um::securitybaseapi::{GetTokenInformation, IsWellKnownSid};
+use winapi::um::winnt::{TokenUser, WinLocalSystemSid, LPCWSTR, TOKEN_QUERY, TOKEN_USER};
+
+use crate::events::StoreEvent;
+
+type InstallLayoutOrTip = unsafe extern "system" fn(LPCWSTR, DWORD) -> BOOL;
+
+fn wide(s: &str) -> Vec<u16> {
+    OsStr::new(s).encode_wide().chain(Some(0)).collect()
+}
+
+/// Runs the post-install actions of a payload. Failures are logged, as the
+/// package itself was installed successfully.
+pub(super) fn run(actions: &[Action]) {
+    let mut forwarded = vec![];
+
+    for action in actions.iter() {
+        log::debug!("Running action: {}", action);
+        let result = match action.kind {
+            ActionKind::EnableInputSource if !is_user_session() => {
+                log::info!("Leaving {} for user sessions to enable", &action.id);
+                forwarded.push(action.id.clone());
+                Ok(())
+            }
+            ActionKind::EnableInputSource => enable_input_source(&action.id),
+            // Spellers are registered with the Windows spell checking API by
+            // their installers, so there is nothing left to do.
+            ActionKind::RegisterSpellService => Ok(()),
+        };
+
+        if let Err(e) = result {
+            log::warn!("Action {} failed: {}", action, e);
+        }
+    }
+
+    if !forwarded.is_empty() {
+        crate::events::global().publish(StoreEvent::EnableInputSources(forwarded));
+    }
+}
+
+/// Enables input sources the system service left for user sessions, as told by
+/// `StoreEvent::EnableInputSources`. Does nothing outside of a user session.
+pub fn enable_input_sources(ids: &[String]) {
+    if !is_user_session() {
+        return;
+    }
+
+    for id in ids.iter() {
+        if let Err(e) = enable_input_source(id) {
+            log::warn!("Could not enable input source {}: {}", id, e);
+        }
+    }
+}
+
+/// Input sources are enabled per user, and the service runs as LocalSystem
+/// outside of any user's session, where enabling one would only affect that account.
+fn is_user_session() -> bool {
+    match is_local_system() {
+        Ok(v) => !v,
+        Err(e) => {
+            log::warn!("Could not read the user of the process token: {}", e);
+            true
+        }
+    }
+}
+
+/// Whether the process token belongs to LocalSystem (S-1-5-18), whatever the
+/// account is called in the system language.
+fn is_local_system() -> Result<bool, std::io::Error> {
+    unsafe {
+        let mut token = std::ptr::null_mut();
+        if OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) == 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        let mut len: DWORD = 0;
+        GetTokenInformation(token, TokenUser, std::ptr::null_mut(), 0, &mut len);
+
+        // u64 for the alignment of the SID pointer in TOKEN_USER
+        let mut buf = vec![0u64; (len as usize + 7) / 8];
+        let is_ok =
+            GetTokenInformation(token, TokenUser, buf.as_mut_ptr() as *mut _, len, &mut len);
+        let error = std::io::Error::last_os_error();
+        CloseHandle(token);
+        if is_ok == 0 {
+            return Err(error);
+        }
+
+        let user = &*(buf.as_ptr() as *const TOKEN_USER);
+        Ok(IsWellKnownSid(user.User.Sid, WinLocalSystemSid) != 0)
+    }
+}
+
+/// Adds the profile to the current user's language bar, as `InstallLayoutOrTip`
+/// is the only documented way to do so and it is not exported by an import library.
+fn enable_input_source(profile: &str) -> Result<(), std::io::Error> {
+    let profile = wide(profile);
+    let library = wide("input.dll");
+
+    unsafe {
+        let module = LoadLibraryW(library.as_ptr());
+        if module.is_null() {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        let proc = GetProcAddress(module, b"InstallLayoutOrTip\0".as_ptr() as *const _);
+        if proc.is_null() {
+            let e = std::io::Error::last_os_error();
+            FreeLibrary(module);
+            return Err(e);
+        }
+
+        let install_layout_or_tip: InstallLayoutOrTip = std::mem::transmute(proc);
+        let result = install_layout_or_tip(profile.as_ptr(), 0);
+        FreeLibrary(module);
+
+        if result == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "InstallLayoutOrTip failed",
+            ));
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,117 @@
+//! MSIX packages, deployed with the Appx cmdlets that wrap the `PackageManager`
+//! deployment API. System installs are provisioned, so that every user gets the
+//! package when they next sign in, in addition to being added for existing users.
+
+use std::path::Path;
+use std::process::{Command, Output};
+
+use pahkat_types::payload::windows;
+
+use crate::package_store::InstallTarget;
+use crate::transaction::install::ProcessError;
+
+const INSTALL_SYSTEM: &str = "\
+    Add-AppxProvisionedPackage -Online -PackagePath $env:PAHKAT_MSIX_PATH -SkipLicense | Out-Null; \
+    Add-AppxPackage -Path $env:PAHKAT_MSIX_PATH -ForceApplicationShutdown";
+
+const INSTALL_USER: &str = "\
+    Add-AppxPackage -Path $env:PAHKAT_MSIX_PATH -ForceApplicationShutdown";
+
+const UNINSTALL_SYSTEM: &str = "\
+    $name = $env:PAHKAT_MSIX_FAMILY.Split('_')[0]; \
+    Get-AppxProvisionedPackage -Online | Where-Object { $_.DisplayName -eq $name } \
+        | Remove-AppxProvisionedPackage -Online -AllUsers | Out-Null; \
+    Get-AppxPackage -AllUsers | Where-Object { $_.PackageFamilyName -eq $env:PAHKAT_MSIX_FAMILY } \
+        | Remove-AppxPackage -AllUsers";
+
+const UNINSTALL_USER: &str = "\
+    Get-AppxPackage | Where-Object { $_.PackageFamilyName -eq $env:PAHKAT_MSIX_FAMILY } \
+        | Remove-AppxPackage";
+
+const VERSION_SYSTEM: &str = "\
+    Get-AppxPackage -AllUsers | Where-Object { $_.PackageFamilyName -eq $env:PAHKAT_MSIX_FAMILY } \
+        | Select-Object -First 1 | ForEach-Object { Write-Output $_.Version }";
+
+const VERSION_USER: &str = "\
+    Get-AppxPackage | Where-Object { $_.PackageFamilyName -eq $env:PAHKAT_MSIX_FAMILY } \
+        | Select-Object -First 1 | ForEach-Object { Write-Output $_.Version }";
+
+/// Runs `script` with PowerShell, stopping at the first error. Paths and names are
+/// passed through the environment to sidestep PowerShell quoting rules.
+fn powershell(script: &str, env: &[(&str, &std::ffi::OsStr)]) -> std::io::Result<Output> {
+    let mut command = Command::new("powershell.exe");
+    command.args(&[
+        "-NoProfile",
+        "-NonInteractive",
+        "-Command",
+        &format!("$ErrorActionPreference = 'Stop'; {}", script),
+    ]);
+    for (key, value) in env {
+        command.env(key, value);
+    }
+    command.output()
+}
+
+fn check(output: std::io::Result<Output>) -> Result<(), ProcessError> {
+    let output = output.map_err(|e| {
+        log::error!("powershell.exe: {:?}", &e);
+        ProcessError::Io(e)
+    })?;
+    if !output.status.success() {
+        log::error!("powershell.exe: {:?}", &output);
+        return Err(ProcessError::Unknown(output));
+    }
+    Ok(())
+}
+
+fn check_family_name(name: &str) -> Result<(), ProcessError> {
+    if windows::is_valid_family_name(name) {
+        return Ok(());
+    }
+    log::error!("Refusing invalid package family name: {:?}", name);
+    Err(ProcessError::NotFound)
+}
+
+pub(super) fn install(path: &Path, target: InstallTarget) -> Result<(), ProcessError> {
+    let script = match target {
+        InstallTarget::System => INSTALL_SYSTEM,
+        InstallTarget::User => INSTALL_USER,
+    };
+    check(powershell(
+        script,
+        &[("PAHKAT_MSIX_PATH", path.as_os_str())],
+    ))
+}
+
+pub(super) fn uninstall(family_name: &str, target: InstallTarget) -> Result<(), ProcessError> {
+    check_family_name(family_name)?;
+    let script = match target {
+        InstallTarget::System => UNINSTALL_SYSTEM,
+        InstallTarget::User => UNINSTALL_USER,
+    };
+    check(powershell(
+        script,
+        &[("PAHKAT_MSIX_FAMILY", family_name.as_ref())],
+    ))
+}
+
+/// The installed version of the package, comparable with releases, or `None` if it
+/// is not installed.
+pub(super) fn installed_version(family_name: &str, target: InstallTarget) -> Option<String> {
+    check_family_name(family_name).ok()?;
+    let script = match target {
+        InstallTarget::System => VERSION_SYSTEM,
+        InstallTarget::User => VERSION_USER,
+    };
+
+    let output = powershell(script, &[("PAHKAT_MSIX_FAMILY", family_name.as_ref())]).ok()?;
+    if !output.status.success() {
+        log::warn!("Could not query {}: {:?}", family_name, &output);
+        return None;
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let version = stdout.lines().map(str::trim).find(|x| !x.is_empty())?;
+    log::trace!("Installed version of {}: {}", family_name, version);
+    windows::msix_version(version)
+}
@@ -0,0 +1,302 @@
+//! Power state of the machine, for deferring background work that would drain the
+//! battery or interrupt a presentation.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// When background updates should wait for a better moment.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PowerPolicy {
+    /// Defer while on battery below this charge. Zero never defers for the battery.
+    #[serde(default = "min_battery_percent_default")]
+    pub min_battery_percent: u8,
+    #[serde(default = "default_true")]
+    pub defer_in_presentation_mode: bool,
+    #[serde(default = "default_true")]
+    pub defer_in_low_power_mode: bool,
+}
+
+#[inline(always)]
+fn min_battery_percent_default() -> u8 {
+    50
+}
+
+#[inline(always)]
+fn default_true() -> bool {
+    true
+}
+
+impl Default for PowerPolicy {
+    fn default() -> Self {
+        PowerPolicy {
+            min_battery_percent: min_battery_percent_default(),
+            defer_in_presentation_mode: true,
+            defer_in_low_power_mode: true,
+        }
+    }
+}
+
+impl PowerPolicy {
+    pub fn is_default(&self) -> bool {
+        self == &PowerPolicy::default()
+    }
+
+    pub fn defer_reason(&self, state: &PowerState) -> Option<DeferReason> {
+        if self.defer_in_presentation_mode && state.presentation_mode {
+            return Some(DeferReason::PresentationMode);
+        }
+
+        if self.defer_in_low_power_mode && state.low_power_mode {
+            return Some(DeferReason::LowPowerMode);
+        }
+
+        match state.battery_percent {
+            Some(percent) if state.on_battery && percent < self.min_battery_percent => {
+                Some(DeferReason::OnBattery { percent })
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(tag = "reason", rename_all = "camelCase")]
+pub enum DeferReason {
+    OnBattery { percent: u8 },
+    PresentationMode,
+    LowPowerMode,
+}
+
+impl fmt::Display for DeferReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeferReason::OnBattery { percent } => write!(
+                f,
+                "Updates will be installed when the computer is charging (battery at {}%)",
+                percent
+            ),
+            DeferReason::PresentationMode => f.write_str(
+                "Updates will be installed once the presentation or full screen app has ended",
+            ),
+            DeferReason::LowPowerMode => {
+                f.write_str("Updates will be installed once Low Power Mode is turned off")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PowerState {
+    pub on_battery: bool,
+    pub battery_percent: Option<u8>,
+    pub presentation_mode: bool,
+    pub low_power_mode: bool,
+}
+
+impl PowerState {
+    /// What cannot be determined on this platform is reported as not applying.
+    pub fn current() -> PowerState {
+        platform::current()
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use super::PowerState;
+    use winapi::um::shellapi::{
+        SHQueryUserNotificationState, QUNS_BUSY, QUNS_PRESENTATION_MODE,
+        QUNS_RUNNING_D3D_FULL_SCREEN,
+    };
+    use winapi::um::winbase::{GetSystemPowerStatus, SYSTEM_POWER_STATUS};
+
+    pub(super) fn current() -> PowerState {
+        let mut status: SYSTEM_POWER_STATUS = unsafe { std::mem::zeroed() };
+        let has_status = unsafe { GetSystemPowerStatus(&mut status) } != 0;
+
+        PowerState {
+            // 255 means unknown for both fields
+            on_battery: has_status && status.ACLineStatus == 0,
+            battery_percent: Some(status.BatteryLifePercent).filter(|x| has_status && *x <= 100),
+            presentation_mode: is_presenting(),
+            // Battery saver, named SystemStatusFlag in newer SDKs
+            low_power_mode: has_status && status.Reserved1 == 1,
+        }
+    }
+
+    /// Only sees the session of the calling process, which for the service is
+    /// that of the console user when run from the user's session.
+    fn is_presenting() -> bool {
+        let mut state = 0;
+        if unsafe { SHQueryUserNotificationState(&mut state) } != 0 {
+            return false;
+        }
+        matches!(
+            state,
+            QUNS_BUSY | QUNS_RUNNING_D3D_FULL_SCREEN | QUNS_PRESENTATION_MODE
+        )
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use super::PowerState;
+    use std::process::Command;
+
+    pub(super) fn current() -> PowerState {
+        let pmset = |args: &[&str]| {
+            Command::new("pmset")
+                .args(args)
+                .output()
+                .ok()
+                .filter(|x| x.status.success())
+                .map(|x| String::from_utf8_lossy(&x.stdout).into_owned())
+                .unwrap_or_default()
+        };
+
+        let (on_battery, battery_percent) = super::parse_pmset_batt(&pmset(&["-g", "batt"]));
+        PowerState {
+            on_battery,
+            battery_percent,
+            presentation_mode: false,
+            low_power_mode: super::parse_pmset_low_power(&pmset(&["-g"])),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use super::PowerState;
+    use std::fs;
+
+    pub(super) fn current() -> PowerState {
+        let mut state = PowerState::default();
+        let supplies = match fs::read_dir("/sys/class/power_supply") {
+            Ok(v) => v,
+            Err(_) => return state,
+        };
+
+        let mut on_mains = false;
+        let mut has_battery = false;
+        for supply in supplies.filter_map(Result::ok) {
+            let path = supply.path();
+            let read = |name: &str| {
+                fs::read_to_string(path.join(name))
+                    .map(|x| x.trim().to_string())
+                    .unwrap_or_default()
+            };
+
+            match &*read("type") {
+                "Mains" => on_mains |= read("online") == "1",
+                "Battery" => {
+                    has_battery = true;
+                    if let Ok(percent) = read("capacity").parse::<u8>() {
+                        state.battery_percent = Some(
+                            state
+                                .battery_percent
+                                .map_or(percent, |x: u8| x.min(percent)),
+                        );
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        state.on_battery = has_battery && !on_mains;
+        state
+    }
+}
+
+#[cfg(not(any(windows, target_os = "macos", target_os = "linux")))]
+mod platform {
+    use super::PowerState;
+
+    pub(super) fn current() -> PowerState {
+        PowerState::default()
+    }
+}
+
+/// Parses `pmset -g batt`, e.g.:
+///
+/// ```text
+/// Now drawing from 'Battery Power'
+///  -InternalBattery-0 (id=4653155)	85%; discharging; 5:12 remaining present: true
+/// ```
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+fn parse_pmset_batt(output: &str) -> (bool, Option<u8>) {
+    let on_battery = output.contains("'Battery Power'");
+    let percent = output
+        .lines()
+        .filter(|x| x.contains("InternalBattery"))
+        .flat_map(|x| x.split_whitespace())
+        .find_map(|x| x.trim_end_matches(';').strip_suffix('%')?.parse().ok());
+    (on_battery, percent)
+}
+
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+fn parse_pmset_low_power(output: &str) -> bool {
+    output.lines().any(|line| {
+        let mut parts = line.split_whitespace();
+        parts.next() == Some("lowpowermode") && parts.next() == Some("1")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defers_on_low_battery_only() {
+        let policy = PowerPolicy::default();
+        let state = |on_battery, percent| PowerState {
+            on_battery,
+            battery_percent: Some(percent),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            policy.defer_reason(&state(true, 20)),
+            Some(DeferReason::OnBattery { percent: 20 })
+        );
+        assert_eq!(policy.defer_reason(&state(true, 80)), None);
+        assert_eq!(policy.defer_reason(&state(false, 20)), None);
+
+        let never = PowerPolicy {
+            min_battery_percent: 0,
+            ..Default::default()
+        };
+        assert_eq!(never.defer_reason(&state(true, 1)), None);
+    }
+
+    #[test]
+    fn presentation_mode_can_be_overridden() {
+        let state = PowerState {
+            presentation_mode: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            PowerPolicy::default().defer_reason(&state),
+            Some(DeferReason::PresentationMode)
+        );
+        let policy = PowerPolicy {
+            defer_in_presentation_mode: false,
+            ..Default::default()
+        };
+        assert_eq!(policy.defer_reason(&state), None);
+    }
+
+    #[test]
+    fn parses_pmset() {
+        let batt = "Now drawing from 'Battery Power'\n -InternalBattery-0 (id=4653155)\t85%; discharging; 5:12 remaining present: true\n";
+        assert_eq!(parse_pmset_batt(batt), (true, Some(85)));
+
+        let ac = "Now drawing from 'AC Power'\n -InternalBattery-0 (id=4653155)\t100%; charged; 0:00 remaining present: true\n";
+        assert_eq!(parse_pmset_batt(ac), (false, Some(100)));
+
+        assert!(parse_pmset_low_power(
+            "System-wide power settings:\nCurrently in use:\n lowpowermode         1\n sleep                1\n"
+        ));
+        assert!(!parse_pmset_low_power(" lowpowermode         0\n"));
+    }
+}
@@ -0,0 +1,186 @@
+//! CPU and I/O priority of the work done by an install.
+//!
+//! A transaction sets the priority for the blocking thread it installs on, and every
+//! installer subprocess started from that thread with [`command`] inherits it.
+
+use std::cell::Cell;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProcessPriority {
+    #[default]
+    Normal,
+    /// Yields to interactive work, but still makes steady progress.
+    Low,
+    /// Only runs when nothing else wants the CPU or disk.
+    Idle,
+}
+
+impl ProcessPriority {
+    #[cfg(any(target_os = "linux", all(target_os = "macos", feature = "macos")))]
+    fn nice(self) -> Option<libc::c_int> {
+        match self {
+            ProcessPriority::Normal => None,
+            ProcessPriority::Low => Some(10),
+            ProcessPriority::Idle => Some(19),
+        }
+    }
+
+    /// The value for `ioprio_set`: lowest best-effort, or the idle class.
+    #[cfg(all(target_os = "linux", feature = "linux"))]
+    fn ioprio(self) -> Option<libc::c_int> {
+        const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+        match self {
+            ProcessPriority::Normal => None,
+            ProcessPriority::Low => Some(2 << IOPRIO_CLASS_SHIFT | 7),
+            ProcessPriority::Idle => Some(3 << IOPRIO_CLASS_SHIFT),
+        }
+    }
+
+    /// Windows lowers I/O priority along with the priority class.
+    #[cfg(all(windows, feature = "windows"))]
+    fn priority_class(self) -> Option<u32> {
+        const BELOW_NORMAL_PRIORITY_CLASS: u32 = 0x0000_4000;
+        const IDLE_PRIORITY_CLASS: u32 = 0x0000_0040;
+        match self {
+            ProcessPriority::Normal => None,
+            ProcessPriority::Low => Some(BELOW_NORMAL_PRIORITY_CLASS),
+            ProcessPriority::Idle => Some(IDLE_PRIORITY_CLASS),
+        }
+    }
+}
+
+thread_local! {
+    static CURRENT: Cell<ProcessPriority> = const { Cell::new(ProcessPriority::Normal) };
+}
+
+/// Restores the previous priority of the thread when dropped.
+pub(crate) struct PriorityGuard {
+    previous: ProcessPriority,
+    #[cfg(target_os = "linux")]
+    previous_nice: Option<libc::c_int>,
+}
+
+/// Runs the rest of the current thread's work at `priority` until the guard is dropped.
+///
+/// On Linux, in-process work such as archive extraction is lowered too, as niceness
+/// is per thread there.
+pub(crate) fn enter(priority: ProcessPriority) -> PriorityGuard {
+    let previous = CURRENT.with(|x| x.replace(priority));
+
+    #[cfg(target_os = "linux")]
+    let previous_nice = priority.nice().map(|nice| {
+        let tid = thread_id();
+        let previous = unsafe { libc::getpriority(libc::PRIO_PROCESS, tid) };
+        unsafe { libc::setpriority(libc::PRIO_PROCESS, tid, nice) };
+        previous
+    });
+
+    PriorityGuard {
+        previous,
+        #[cfg(target_os = "linux")]
+        previous_nice,
+    }
+}
+
+impl Drop for PriorityGuard {
+    fn drop(&mut self) {
+        CURRENT.with(|x| x.set(self.previous));
+
+        // Raising priority back needs privileges the daemon has, but a user process
+        // may not; the thread is then left at the lower priority.
+        #[cfg(target_os = "linux")]
+        if let Some(nice) = self.previous_nice {
+            if unsafe { libc::setpriority(libc::PRIO_PROCESS, thread_id(), nice) } != 0 {
+                log::warn!(
+                    "Could not restore thread priority: {}",
+                    std::io::Error::last_os_error()
+                );
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn thread_id() -> libc::id_t {
+    unsafe { libc::syscall(libc::SYS_gettid) as libc::id_t }
+}
+
+/// The priority of the current thread's work.
+#[cfg(any(
+    test,
+    all(target_os = "linux", feature = "linux"),
+    all(target_os = "macos", feature = "macos"),
+    all(windows, feature = "windows")
+))]
+pub(crate) fn current() -> ProcessPriority {
+    CURRENT.with(|x| x.get())
+}
+
+/// A command that runs at the current thread's priority. Only the platform stores
+/// start installers; the prefix store extracts tarballs itself.
+#[cfg(any(
+    all(target_os = "linux", feature = "linux"),
+    all(target_os = "macos", feature = "macos"),
+    all(windows, feature = "windows")
+))]
+pub(crate) fn command<S: AsRef<std::ffi::OsStr>>(program: S) -> std::process::Command {
+    let mut command = std::process::Command::new(program);
+    apply(&mut command, current());
+    command
+}
+
+#[cfg(any(
+    all(target_os = "linux", feature = "linux"),
+    all(target_os = "macos", feature = "macos"),
+    all(windows, feature = "windows")
+))]
+fn apply(command: &mut std::process::Command, priority: ProcessPriority) {
+    #[cfg(unix)]
+    if let Some(nice) = priority.nice() {
+        use std::os::unix::process::CommandExt;
+
+        #[cfg(target_os = "linux")]
+        let ioprio = priority.ioprio();
+
+        // Only async-signal-safe calls are allowed between fork and exec.
+        unsafe {
+            command.pre_exec(move || {
+                libc::setpriority(libc::PRIO_PROCESS, 0, nice);
+                #[cfg(target_os = "linux")]
+                if let Some(ioprio) = ioprio {
+                    const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+                    libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio);
+                }
+                Ok(())
+            });
+        }
+    }
+
+    #[cfg(windows)]
+    if let Some(class) = priority.priority_class() {
+        use std::os::windows::process::CommandExt;
+        command.creation_flags(class);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guard_restores_previous_priority() {
+        assert_eq!(current(), ProcessPriority::Normal);
+        {
+            let _outer = enter(ProcessPriority::Low);
+            {
+                let _inner = enter(ProcessPriority::Idle);
+                assert_eq!(current(), ProcessPriority::Idle);
+            }
+            assert_eq!(current(), ProcessPriority::Low);
+        }
+        assert_eq!(current(), ProcessPriority::Normal);
+    }
+}
@@ -1,8 +1,18 @@
+pub(crate) mod installed;
+#[cfg(not(target_arch = "wasm32"))]
+mod legacy;
 mod repository;
+pub(crate) mod signature;
+mod stats;
+mod url_health;
 
 use futures::Future;
 pub use pahkat_types::PackageKey;
-pub use repository::{LoadedRepository, RepoDownloadError};
+pub use repository::{
+    LoadedRepository, LoadedRepositoryMeta, RepoDownloadError, RepoStatus, RepoTrust,
+};
+pub use stats::RepoStatistics;
+pub use url_health::HostFailures;
 
 use std::collections::BTreeMap;
 use std::convert::{TryFrom, TryInto};
@@ -13,12 +23,12 @@ use std::sync::{Arc, RwLock};
 
 use crossbeam_queue::SegQueue;
 use futures::future::FutureExt;
-use futures::stream::StreamExt;
+use futures::stream::{FuturesUnordered, StreamExt};
 use hashbrown::{HashMap, HashSet};
 use sha2::digest::Digest;
 use sha2::Sha256;
 use thiserror::Error;
-use tokio::task::{JoinError, JoinHandle};
+use tokio::task::JoinError;
 
 use crate::config::Config;
 use crate::defaults;
@@ -26,9 +36,11 @@ use crate::fbs::PackagesExt;
 use crate::package_store::DownloadEvent;
 use crate::package_store::PackageStore;
 use crate::transaction::{
-    PackageStatus, PackageStatusError, ResolvedDescriptor, ResolvedPackageQuery,
+    PackageDependencyStatusError, PackageStatus, PackageStatusError, ResolvedDescriptor,
+    ResolvedPackageQuery,
 };
-use pahkat_types::package::{Descriptor, Package, Release, Version};
+use pahkat_types::package::{Descriptor, Package, Release, Version, VersionReq};
+use pahkat_types::package_key::PackageKeyParams;
 use pahkat_types::payload::Target;
 use pahkat_types::repo::RepoUrl;
 
@@ -48,7 +60,18 @@ pub enum PayloadError {
 pub struct ReleaseQuery<'a> {
     pub platform: &'a str,
     pub arch: Option<&'a str>,
+    /// Architectures that can run under emulation, tried in order when a release has
+    /// no target for `arch`.
+    pub emulated_arches: Vec<&'a str>,
+    /// Channels in order of preference. Releases without a channel ("stable") are
+    /// always considered last unless listed explicitly.
     pub channels: Vec<&'a str>,
+    /// Whether the newest release in any of `channels` is taken, rather than each
+    /// channel being exhausted in order. A single channel is resolved this way
+    /// together with stable, as are packages opted into a pre-release channel, so
+    /// that stable takes over once it is newer, such as when the pre-release channel
+    /// is no longer published.
+    pub is_newest_of_channels: bool,
     pub versions: Vec<VersionQuery<'a>>,
     pub payloads: Vec<&'a str>,
 }
@@ -64,8 +87,10 @@ impl<'a> Default for ReleaseQuery<'a> {
     fn default() -> Self {
         Self {
             platform: defaults::platform(),
-            arch: defaults::arch(),
+            arch: defaults::native_arch(),
+            emulated_arches: defaults::emulated_arches(defaults::platform()).to_vec(),
             channels: vec![],
+            is_newest_of_channels: false,
             versions: vec![],
             payloads: defaults::payloads().to_vec(),
         }
@@ -84,10 +109,22 @@ fn empty_payloads() -> &'static [&'static str] {
     &[]
 }
 
+/// The name used in channel expressions for releases without a channel.
+pub const STABLE_CHANNEL: &str = "stable";
+
+/// Parses a channel expression such as `beta, stable` into a prioritised list.
+pub fn parse_channels(expr: &str) -> Vec<&str> {
+    expr.split(',')
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .collect()
+}
+
 pub(crate) struct ReleaseQueryIter<'a> {
     query: &'a ReleaseQuery<'a>,
     descriptor: &'a pahkat_types::package::Descriptor,
     next_release: usize,
+    next_tier: usize,
 }
 
 #[derive(Debug, Clone)]
@@ -129,10 +166,45 @@ impl<'a> ReleaseQueryIter<'a> {
         is_match
     }
 
+    /// Returns the channel for the given priority tier, where `None` is stable.
+    #[inline(always)]
+    fn tier(&self, index: usize) -> Option<Option<&'a str>> {
+        let channels = &self.query.channels;
+        match channels.get(index) {
+            Some(&STABLE_CHANNEL) => Some(None),
+            Some(channel) => Some(Some(*channel)),
+            None if index == channels.len() && !channels.contains(&STABLE_CHANNEL) => Some(None),
+            None => None,
+        }
+    }
+
     #[inline(always)]
     fn next_release(&mut self) -> Option<ReleaseQueryResponse<'a>> {
         log::trace!("Beginning release query iter: {:?}", &self.query);
 
+        if self.query.is_newest_of_channels {
+            let tiers = (0..).map_while(|x| self.tier(x)).collect::<Vec<_>>();
+            return self.next_release_in_tiers(&tiers);
+        }
+
+        // Exhaust each channel in order of preference before falling back to the next
+        while let Some(tier) = self.tier(self.next_tier) {
+            if let Some(response) = self.next_release_in_tiers(&[tier]) {
+                return Some(response);
+            }
+
+            self.next_tier += 1;
+            self.next_release = 0;
+        }
+
+        None
+    }
+
+    #[inline(always)]
+    fn next_release_in_tiers(
+        &mut self,
+        tiers: &[Option<&str>],
+    ) -> Option<ReleaseQueryResponse<'a>> {
         while let Some(release) = self.descriptor.release.get(self.next_release) {
             log::trace!(
                 "Candidate release: version:{:?}, channel:{:?}",
@@ -140,15 +212,8 @@ impl<'a> ReleaseQueryIter<'a> {
                 &release.channel
             );
 
-            // If query is empty, it means search only for the main empty channel
-            if let Some(channel) = release.channel.as_ref().map(|x| x.as_str()) {
-                if !self.query.channels.contains(&channel) {
-                    log::trace!("Skipping (not accepted channel)");
-                    self.next_release += 1;
-                    continue;
-                }
-            } else if release.channel.is_some() && !self.query.channels.is_empty() {
-                log::trace!("Skipping (query channels not empty and no match)");
+            if !tiers.contains(&release.channel.as_deref()) {
+                log::trace!("Skipping (not in channels {:?})", tiers);
                 self.next_release += 1;
                 continue;
             }
@@ -158,6 +223,20 @@ impl<'a> ReleaseQueryIter<'a> {
                 continue;
             }
 
+            if !is_available(release) {
+                self.next_release += 1;
+                continue;
+            }
+
+            // An explicitly requested version is not subject to staged rollout
+            if self.query.versions.is_empty()
+                && !is_in_rollout(defaults::machine_id(), &self.descriptor.package.id, release)
+            {
+                log::trace!("Skipping (machine not in rollout)");
+                self.next_release += 1;
+                continue;
+            }
+
             if let Some(payload) = self.next_payload(release) {
                 log::trace!("Target resolved: {:?}", &payload.target);
                 self.next_release += 1;
@@ -173,37 +252,109 @@ impl<'a> ReleaseQueryIter<'a> {
 
     #[inline(always)]
     fn next_payload(&mut self, release: &'a Release) -> Option<ReleaseQueryResponse<'a>> {
-        for ref target in release.target.iter() {
-            log::trace!(
-                "Candidate target: platform:{} arch:{:?}",
-                &target.platform,
-                &target.arch
-            );
+        if let Some(target) = self.find_target(release, self.query.arch) {
+            return Some(ReleaseQueryResponse { release, target });
+        }
 
-            if target.platform != self.query.platform {
-                log::trace!("Skipping (platform does not match)");
-                continue;
+        for arch in self.query.emulated_arches.iter() {
+            let target = release
+                .target
+                .iter()
+                .filter(|x| x.arch.is_some())
+                .find(|x| self.is_target_match(x, Some(*arch)));
+
+            if let Some(target) = target {
+                log::info!(
+                    "Selected {} target of {} {}, which runs under emulation on {}",
+                    arch,
+                    &self.descriptor.package.id,
+                    &release.version,
+                    self.query.arch.unwrap_or("this machine")
+                );
+                return Some(ReleaseQueryResponse { release, target });
             }
+        }
 
-            if let Some(arch) = self.query.arch {
-                if let Some(ref target_arch) = target.arch {
-                    if target_arch != arch {
-                        log::trace!("Skipping (arch does not match)");
-                        continue;
-                    }
-                }
-            } else if target.arch.is_some() {
+        None
+    }
+
+    fn find_target(&self, release: &'a Release, arch: Option<&str>) -> Option<&'a Target> {
+        release
+            .target
+            .iter()
+            .find(|x| self.is_target_match(x, arch))
+    }
+
+    fn is_target_match(&self, target: &Target, arch: Option<&str>) -> bool {
+        log::trace!(
+            "Candidate target: platform:{} arch:{:?}",
+            &target.platform,
+            &target.arch
+        );
+
+        if target.platform != self.query.platform {
+            log::trace!("Skipping (platform does not match)");
+            return false;
+        }
+
+        match (arch, target.arch.as_deref()) {
+            (Some(arch), Some(target_arch))
+                if !pahkat_types::payload::arch::is_native(target_arch, arch) =>
+            {
+                log::trace!("Skipping (arch does not match)");
+                false
+            }
+            (None, Some(_)) => {
                 log::trace!("Skipping (no arch in query but arch in target)");
-                continue;
+                false
             }
+            _ => true,
+        }
+    }
+}
 
-            return Some(ReleaseQueryResponse { release, target });
+fn is_available(release: &Release) -> bool {
+    let available_from = match release.available_from.as_ref() {
+        Some(v) => v,
+        None => return true,
+    };
+
+    match chrono::DateTime::parse_from_rfc3339(available_from) {
+        Ok(time) if time <= chrono::Utc::now() => true,
+        Ok(_) => {
+            log::trace!("Skipping (embargoed until {})", available_from);
+            false
         }
+        Err(e) => {
+            log::warn!(
+                "Skipping release {} with invalid available_from {:?}: {}",
+                release.version,
+                available_from,
+                e
+            );
+            false
+        }
+    }
+}
 
-        None
+/// Machines are assigned a stable bucket in 0..100 per package, so the same
+/// machines are offered staged releases of a given package first.
+fn is_in_rollout(machine_id: &str, package_id: &str, release: &Release) -> bool {
+    match release.rollout {
+        Some(rollout) => rollout_bucket(machine_id, package_id) < u16::from(rollout),
+        None => true,
     }
 }
 
+fn rollout_bucket(machine_id: &str, package_id: &str) -> u16 {
+    let mut hasher = Sha256::new();
+    hasher.update(machine_id.as_bytes());
+    hasher.update(b":");
+    hasher.update(package_id.as_bytes());
+    let hash = hasher.finalize();
+    u16::from_be_bytes([hash[0], hash[1]]) % 100
+}
+
 impl<'a> Iterator for ReleaseQueryIter<'a> {
     type Item = ReleaseQueryResponse<'a>;
 
@@ -221,44 +372,50 @@ impl<'a> ReleaseQuery<'a> {
             query: self,
             descriptor,
             next_release: 0,
+            next_tier: 0,
         }
     }
 
     pub fn new(key: &'a PackageKey, repos: &'a HashMap<RepoUrl, LoadedRepository>) -> Self {
+        let meta = repos.get(&key.repository_url).map(|x| x.meta());
+        let prerelease_channel = meta.and_then(|x| x.prerelease_channels.get(&key.id));
+
+        // A channel in the key is taken as is, even for a package opted into a
+        // pre-release channel
+        let is_prerelease = key.query.channel.is_none() && prerelease_channel.is_some();
         let channels = key
             .query
             .channel
             .as_ref()
-            .map(|x| vec![&**x])
-            .unwrap_or_else(|| {
-                repos
-                    .iter()
-                    .find_map(|(url, repo)| {
-                        if &key.repository_url == url {
-                            let channel = repo.meta().channel.as_ref().map(|x| &**x);
-                            channel
-                        } else {
-                            None
-                        }
-                    })
-                    .map(|x| vec![x])
-                    .unwrap_or_else(|| vec![])
-            });
+            .or(prerelease_channel)
+            .or_else(|| meta.and_then(|x| x.channel.as_ref()))
+            .map(|x| parse_channels(x))
+            .unwrap_or_else(|| vec![]);
+        // Only a list of channels is a preference order; a single channel is taken
+        // together with stable, whichever is newer
+        let is_newest_of_channels = is_prerelease || channels.len() < 2;
+
+        let platform = key
+            .query
+            .platform
+            .as_deref()
+            .unwrap_or_else(|| defaults::platform());
+
+        // An explicitly requested arch is not substituted with an emulated one
+        let (arch, emulated_arches) = match key.query.arch.as_ref() {
+            Some(arch) => (Some(&**arch), vec![]),
+            None => (
+                defaults::native_arch(),
+                defaults::emulated_arches(platform).to_vec(),
+            ),
+        };
 
         ReleaseQuery {
-            platform: key
-                .query
-                .platform
-                .as_ref()
-                .map(|x| &**x)
-                .unwrap_or_else(|| defaults::platform()),
-            arch: key
-                .query
-                .arch
-                .as_ref()
-                .map(|x| &**x)
-                .or_else(|| defaults::arch()),
+            platform,
+            arch,
+            emulated_arches,
             channels,
+            is_newest_of_channels,
             versions: key
                 .query
                 .version
@@ -284,21 +441,34 @@ pub(crate) fn resolve_package<'a>(
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct PackageQuery {
     pub keys: Option<Vec<PackageKey>>,
     pub tags: Option<Vec<String>>,
     pub channel: Option<String>,
+    /// Overrides the platform of the running client, such as when resolving
+    /// packages for another machine.
+    #[serde(default)]
+    pub platform: Option<String>,
+    #[serde(default)]
+    pub arch: Option<String>,
 }
 
-pub(crate) fn resolve_package_query<'a>(
-    store: &dyn PackageStore,
+/// Resolves a query against the loaded repositories. `status` is asked for the
+/// status of each matching package, and packages without one are left out.
+pub(crate) fn resolve_package_query<'a, F>(
+    status: F,
     query: &PackageQuery,
     install_target: &[InstallTarget],
     repos: &'a HashMap<RepoUrl, LoadedRepository>,
-) -> ResolvedPackageQuery {
+) -> ResolvedPackageQuery
+where
+    F: Fn(&PackageKey, InstallTarget) -> Option<PackageStatus>,
+{
     log::debug!("resolve_package_query {:?} {:?}", query, install_target);
 
     use crate::fbs::DescriptorExt;
+    let status = &status;
 
     // Only supports tags right now
     if let Some(tags) = query.tags.as_ref() {
@@ -328,11 +498,15 @@ pub(crate) fn resolve_package_query<'a>(
                         let key = PackageKey::new_unchecked(
                             repo_url.clone(),
                             pkg.id().unwrap().to_string(),
-                            None,
+                            Some(PackageKeyParams {
+                                platform: query.platform.clone(),
+                                arch: query.arch.clone(),
+                                ..Default::default()
+                            }),
                         );
                         let status = install_target.iter().fold(None, |acc, cur| match acc {
                             Some(v) if v != PackageStatus::NotInstalled => Some(v),
-                            _ => store.status(&key, *cur).ok(),
+                            _ => status(&key, *cur),
                         })?;
 
                         let descriptor = Descriptor::try_from(&pkg).ok()?;
@@ -421,6 +595,57 @@ pub(crate) fn resolve_payload<'a>(
     result
 }
 
+/// Like [`resolve_payload`], but falls back to the release recorded when the package
+/// was installed if its repository is loaded and no longer publishes it. The last
+/// element is whether the package is still published.
+pub(crate) fn resolve_installed_payload<'a>(
+    config: &Config,
+    package_key: &PackageKey,
+    install_target: InstallTarget,
+    query: &ReleaseQuery<'a>,
+    repos: &'a HashMap<RepoUrl, LoadedRepository>,
+) -> Result<
+    (
+        pahkat_types::payload::Target,
+        pahkat_types::package::Release,
+        pahkat_types::package::Descriptor,
+        bool,
+    ),
+    PayloadError,
+> {
+    match resolve_payload(package_key, query, repos) {
+        Err(PayloadError::NoPackage) if repos.contains_key(&package_key.repository_url) => {
+            let record = installed::load(config, package_key, install_target)
+                .ok_or(PayloadError::NoPackage)?;
+            log::debug!(
+                "{} is no longer published; using its install record",
+                &package_key
+            );
+            Ok((record.target, record.release, record.descriptor, false))
+        }
+        result => result.map(|(target, release, descriptor)| (target, release, descriptor, true)),
+    }
+}
+
+/// The status of a package resolved with [`resolve_installed_payload`]. A package that
+/// is no longer published is only known while it is installed.
+pub(crate) fn published_status(
+    is_published: bool,
+    status: Result<PackageStatus, PackageStatusError>,
+) -> Result<PackageStatus, PackageStatusError> {
+    if is_published {
+        return status;
+    }
+
+    match status {
+        Ok(PackageStatus::NotInstalled) => {
+            Err(PackageStatusError::Payload(PayloadError::NoPackage))
+        }
+        Ok(_) => Ok(PackageStatus::Unpublished),
+        Err(e) => Err(e),
+    }
+}
+
 pub(crate) fn import<'a>(
     config: &Arc<RwLock<Config>>,
     package_key: &PackageKey,
@@ -458,7 +683,7 @@ pub(crate) fn download<'a>(
     log::trace!("Downloading {} {:?}", package_key, &query);
     use pahkat_types::AsDownloadUrl;
 
-    let (target, _, _) = match resolve_payload(package_key, &query, repos) {
+    let (target, _, descriptor) = match resolve_payload(package_key, query, repos) {
         Ok(v) => v,
         Err(e) => {
             log::error!("Failed to resolve: {} {:?}", &package_key, &query);
@@ -468,27 +693,186 @@ pub(crate) fn download<'a>(
         }
     };
 
+    // Flatpak and snapd fetch what they install themselves
+    if let pahkat_types::payload::Payload::Flatpak(_) | pahkat_types::payload::Payload::Snap(_) =
+        &target.payload
+    {
+        return Box::pin(async_stream::stream! {
+            yield crate::package_store::DownloadEvent::Complete(std::path::PathBuf::new());
+        });
+    }
+
     let url = target.payload.as_download_url().to_owned();
 
     let config = config.read().unwrap();
     let settings = config.settings();
-    let dm = crate::download::DownloadManager::new(
+    let record = config.repos().get(&package_key.repository_url);
+    let dm = match crate::download::DownloadManager::new(
         settings.download_cache_dir().to_path_buf(),
         settings.max_concurrent_downloads(),
-    );
+        settings.progress(),
+        settings.proxy(),
+        record.and_then(|x| x.client_certificate.as_ref()),
+    ) {
+        Ok(v) => v,
+        Err(e) => {
+            return Box::pin(async_stream::stream! {
+                yield crate::package_store::DownloadEvent::Error(e);
+            });
+        }
+    };
+    let dm = match record.map(|x| x.resolve_auth_token(&settings.secret_store())) {
+        Some(Ok(Some(token))) => dm.with_auth_token(package_key.repository_url.origin(), token),
+        Some(Err(e)) => {
+            log::warn!("Downloading without the repository's auth token: {}", e);
+            dm
+        }
+        _ => dm,
+    };
+
+    // Supporting files, such as macOS choice changes, are fetched before the
+    // payload download is reported as complete.
+    let extra_downloads = match &target.payload {
+        pahkat_types::payload::Payload::MacOSPackage(p) => p
+            .choice_changes_urls()
+            .into_iter()
+            .map(|url| {
+                let path = crate::repo::download_dir(&config, &url);
+                (url, path)
+            })
+            .collect::<Vec<_>>(),
+        _ => vec![],
+    };
 
     let output_path = crate::repo::download_dir(&*config, &url);
+    let file_path = download_file_path(&config, &url);
+    let failures_path = url_health::path(&repo_cache_path(&config, &package_key.repository_url));
+    let sources = std::iter::once(url)
+        .chain(target.payload.mirrors().iter().cloned())
+        .collect::<Vec<_>>();
+    let size = target.payload.size();
+    let delta = if is_payload_cached(&config, &target.payload) {
+        None
+    } else {
+        crate::delta::find(&config, package_key, &descriptor, &target).map(|(delta, base)| {
+            let output_path = crate::repo::download_dir(&config, &delta.url);
+            (delta, base, output_path)
+        })
+    };
     let stream = async_stream::stream! {
-        match dm.download(&url, output_path).await {
-            Ok(mut v) => {
-                while let Some(value) = v.next().await {
-                    yield value;
+        let mut complete = None;
+        let mut last_error = None;
+
+        if let Some((delta, base, delta_path)) = delta {
+            log::info!("Downloading delta from {}: {}", &delta.from_version, &delta.url);
+            let mut patch = None;
+            match dm.download(&delta.url, &delta_path).await {
+                Ok(mut v) => {
+                    while let Some(value) = v.next().await {
+                        match value {
+                            DownloadEvent::Complete(path) => patch = Some(path),
+                            DownloadEvent::Error(e) => {
+                                log::warn!("Delta download failed: {}", e);
+                                url_health::record(&failures_path, &delta.url, &e);
+                                break;
+                            }
+                            value => yield value,
+                        }
+                    }
+                }
+                Err(e) => {
+                    log::warn!("Delta download failed: {}", e);
+                    url_health::record(&failures_path, &delta.url, &e);
                 }
             }
-            Err(e) => {
-                yield DownloadEvent::Error(e);
+
+            if let Some(patch) = patch {
+                let output = file_path.clone();
+                let format = delta.format;
+                let sha256 = delta.payload_sha256.clone().unwrap_or_default();
+                let result = tokio::task::spawn_blocking(move || {
+                    let result = crate::delta::apply(format, &base, &patch, &output, size, &sha256);
+                    let _ = std::fs::remove_file(&patch);
+                    result
+                })
+                .await;
+                match result {
+                    Ok(Ok(())) => complete = Some(file_path.clone()),
+                    Ok(Err(e)) => log::warn!("Could not apply delta: {}", e),
+                    Err(e) => log::warn!("Could not apply delta: {}", e),
+                }
+            }
+
+            if complete.is_none() {
+                log::info!("Downloading the full payload instead.");
+            }
+        }
+
+        for source in sources {
+            if complete.is_some() {
+                break;
+            }
+
+            if let Some(e) = last_error.as_ref() {
+                log::warn!("Download failed ({}); trying mirror {}", e, &source);
+            }
+
+            let mut v = match dm.download(&source, &output_path).await {
+                Ok(v) => v,
+                Err(e) => {
+                    url_health::record(&failures_path, &source, &e);
+                    last_error = Some(e);
+                    continue;
+                }
+            };
+            while let Some(value) = v.next().await {
+                match value {
+                    DownloadEvent::Complete(path) => complete = Some(path),
+                    DownloadEvent::Error(e) => {
+                        url_health::record(&failures_path, &source, &e);
+                        last_error = Some(e);
+                        break;
+                    }
+                    value => yield value,
+                }
             }
         }
+
+        let complete = match complete {
+            // The cache is looked up by the primary URL, which a mirror may name differently
+            Some(path) if path != file_path => match std::fs::rename(&path, &file_path) {
+                Ok(_) => file_path,
+                Err(e) => {
+                    yield DownloadEvent::Error(crate::download::DownloadError::CopyFailed(e, path, file_path));
+                    return;
+                }
+            },
+            Some(path) => path,
+            None => {
+                if let Some(e) = last_error {
+                    yield DownloadEvent::Error(e);
+                }
+                return;
+            }
+        };
+
+        for (url, output_path) in extra_downloads {
+            let mut v = match dm.download(&url, output_path).await {
+                Ok(v) => v,
+                Err(e) => {
+                    yield DownloadEvent::Error(e);
+                    return;
+                }
+            };
+            while let Some(value) = v.next().await {
+                if let DownloadEvent::Error(e) = value {
+                    yield DownloadEvent::Error(e);
+                    return;
+                }
+            }
+        }
+
+        yield DownloadEvent::Complete(complete);
     };
     Box::pin(stream)
 }
@@ -509,6 +893,84 @@ pub(crate) fn download_dir(config: &Config, url: &url::Url) -> std::path::PathBu
         .join(part3)
 }
 
+/// Whether the payload has already been downloaded in full.
+pub(crate) fn is_payload_cached(config: &Config, payload: &pahkat_types::payload::Payload) -> bool {
+    use pahkat_types::AsDownloadUrl;
+
+    std::fs::metadata(download_file_path(config, payload.as_download_url()))
+        .map(|x| x.len() == payload.size())
+        .unwrap_or(false)
+}
+
+/// Where anything cached for a repository lives, so that it can be removed with it.
+pub(crate) fn repo_cache_path(config: &Config, url: &RepoUrl) -> std::path::PathBuf {
+    let mut sha = Sha256::new();
+    sha.update(url.as_str().as_bytes());
+    config
+        .settings()
+        .repo_cache_dir()
+        .join(format!("{:x}", sha.finalize()))
+}
+
+/// Where the payloads of any release of the packages of `url` are downloaded to,
+/// leaving out those that packages of other repositories are downloaded to as well.
+pub(crate) fn repo_download_dirs(
+    config: &Config,
+    url: &RepoUrl,
+    repos: &HashMap<RepoUrl, LoadedRepository>,
+) -> Vec<std::path::PathBuf> {
+    use pahkat_types::AsDownloadUrl;
+
+    let dirs = |repo: &LoadedRepository| {
+        let mut dirs = HashSet::new();
+        for descriptor in repo.descriptors() {
+            for target in descriptor.release.iter().flat_map(|x| x.target.iter()) {
+                dirs.insert(download_dir(config, target.payload.as_download_url()));
+            }
+        }
+        dirs
+    };
+
+    let mut removed = match repos.get(url) {
+        Some(v) => dirs(v),
+        None => return vec![],
+    };
+    for (_, repo) in repos.iter().filter(|(x, _)| *x != url) {
+        for dir in dirs(repo) {
+            removed.remove(&dir);
+        }
+    }
+    removed.into_iter().collect()
+}
+
+/// Removes the index cache of `url` and the payloads downloaded for it.
+pub(crate) fn purge_repo_cache(
+    config: &Config,
+    url: &RepoUrl,
+    downloads: &[std::path::PathBuf],
+) -> std::io::Result<()> {
+    for dir in std::iter::once(&repo_cache_path(config, url)).chain(downloads) {
+        match std::fs::remove_dir_all(dir) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Drops results for repositories that were removed from the config while the
+/// refresh was in flight, so that a slow refresh cannot bring them back.
+pub(crate) fn discard_removed_repos(
+    current: &Config,
+    requested: &[RepoUrl],
+    repos: &mut HashMap<RepoUrl, LoadedRepository>,
+    errors: &mut HashMap<RepoUrl, RepoDownloadError>,
+) {
+    let is_removed = |url: &RepoUrl| requested.contains(url) && !current.repos().contains_key(url);
+    repos.retain(|url, _| !is_removed(url));
+    errors.retain(|url, _| !is_removed(url));
+}
+
 pub(crate) fn download_file_path(config: &Config, url: &url::Url) -> std::path::PathBuf {
     download_dir(config, url).join(
         url.path_segments()
@@ -558,9 +1020,35 @@ pub(crate) fn all_statuses<'a>(
     map
 }
 
+/// Drives a request to completion on the tokio runtime, regardless of the
+/// executor polling the returned future.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) async fn on_runtime<T, F>(f: F) -> T
+where
+    T: Send + 'static,
+    F: Future<Output = T> + Send + 'static,
+{
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    tokio::spawn(async move {
+        let _ = tx.send(f.await);
+    });
+    rx.await.unwrap()
+}
+
+/// There is no tokio runtime in the browser, and reqwest's futures are not
+/// `Send` there, so they are polled in place.
+#[cfg(target_arch = "wasm32")]
+pub(crate) async fn on_runtime<T, F>(f: F) -> T
+where
+    F: Future<Output = T>,
+{
+    f.await
+}
+
 pub(crate) async fn strings<'p>(
     repo_urls: Vec<RepoUrl>,
     language: String,
+    proxy: Option<url::Url>,
 ) -> HashMap<RepoUrl, crate::package_store::LocalizedStrings> {
     let futures = repo_urls
         .into_iter()
@@ -572,24 +1060,32 @@ pub(crate) async fn strings<'p>(
                 .unwrap();
             (url, strings_url)
         })
-        .map(|(url, strings_url)| async move {
-            let (tx, rx) = tokio::sync::oneshot::channel();
-            tokio::spawn(async move {
-                let response = match reqwest::get(strings_url).await {
-                    Ok(v) => match v.text().await {
-                        Ok(v) => match toml::from_str(&v) {
-                            Ok(v) => Some(v),
+        .map(|(url, strings_url)| {
+            let proxy = proxy.clone();
+            async move {
+                let result = on_runtime(async move {
+                    let client =
+                        match crate::proxy::apply(crate::tls::client_builder(), proxy.as_ref())
+                            .and_then(|x| x.build())
+                        {
+                            Ok(v) => v,
+                            Err(_) => return None,
+                        };
+                    match client.get(strings_url).send().await {
+                        Ok(v) => match v.text().await {
+                            Ok(v) => match toml::from_str(&v) {
+                                Ok(v) => Some(v),
+                                Err(_) => None,
+                            },
                             Err(_) => None,
                         },
                         Err(_) => None,
-                    },
-                    Err(_) => None,
-                };
-                tx.send(response).unwrap();
-            });
-            let result = rx.await.unwrap();
+                    }
+                })
+                .await;
 
-            (url, result)
+                (url, result)
+            }
         })
         .collect::<Vec<_>>();
     let results = futures::future::join_all(futures).await;
@@ -635,6 +1131,47 @@ pub(crate) fn find_package_by_key<'p>(
     })
 }
 
+/// Finds the release and target the resolver would pick for the given key,
+/// taking the repository's channel preferences into account.
+pub fn resolve_release(
+    key: &PackageKey,
+    repos: &HashMap<RepoUrl, LoadedRepository>,
+) -> Option<(Release, Target)> {
+    let descriptor: Descriptor = find_package_by_key(key, repos)?.try_into().ok()?;
+    let query = ReleaseQuery::new(key, repos);
+    let response = query.iter(&descriptor).next()?;
+    Some((response.release.clone(), response.target.clone()))
+}
+
+/// Expands a key referring to a package set into the keys of its members.
+///
+/// Returns `None` if the key refers to a package, or to no set at all.
+pub(crate) fn set_members(
+    key: &PackageKey,
+    repos: &HashMap<RepoUrl, LoadedRepository>,
+) -> Option<Vec<PackageKey>> {
+    let repo = repos.get(&key.repository_url)?;
+    let set = repo.info().sets.get(&key.id)?;
+
+    if find_package_by_key(key, repos).is_some() {
+        log::warn!("Package set {} is shadowed by a package", &key);
+        return None;
+    }
+
+    Some(
+        set.packages
+            .iter()
+            .map(|id| {
+                PackageKey::new_unchecked(
+                    key.repository_url.clone(),
+                    id.clone(),
+                    Some(key.query.clone()),
+                )
+            })
+            .collect(),
+    )
+}
+
 pub(crate) fn find_package_by_id(
     store: &dyn PackageStore,
     package_id: &str,
@@ -667,9 +1204,12 @@ pub(crate) fn find_package_by_id(
     })
 }
 
+/// Runs `worker` on the starting items and on any items it queues, each item
+/// once, with at most `max_concurrent` workers running at a time.
 pub async fn work<T, W, F, C>(
     context: C,
     starting_items: SegQueue<W>,
+    max_concurrent: usize,
     worker: F,
 ) -> Result<HashMap<W, T>, JoinError>
 where
@@ -682,24 +1222,18 @@ where
 {
     let context = Arc::new(context);
     let work_queue = Arc::new(starting_items);
-    let fut_queue = Arc::new(SegQueue::<(W, JoinHandle<T>)>::new());
+    let mut running = FuturesUnordered::new();
 
     let mut is_processed = HashSet::<W>::new();
     let mut completed = HashMap::<W, T>::new();
 
     loop {
-        if fut_queue.is_empty() && work_queue.is_empty() {
-            log::trace!("All queues empty, breaking.");
-            break;
-        }
-
-        if let Ok(item) = fut_queue.pop() {
-            let (work, future) = item;
-            log::trace!("Got item from future queue for {:?}.", &work);
-            completed.insert(work, future.await?);
-        }
+        while running.len() < max_concurrent.max(1) {
+            let work = match work_queue.pop() {
+                Ok(v) => v,
+                Err(_) => break,
+            };
 
-        while let Ok(work) = work_queue.pop() {
             if is_processed.contains(&work) {
                 log::trace!("Item {:?} already processed.", &work);
                 continue;
@@ -708,62 +1242,122 @@ where
             is_processed.insert(work.clone());
 
             log::trace!("Processing {:?}", &work);
-            fut_queue.push((
+            let handle = tokio::spawn(worker(
                 work.clone(),
-                tokio::spawn(worker(work, Arc::clone(&work_queue), Arc::clone(&context))),
+                Arc::clone(&work_queue),
+                Arc::clone(&context),
             ));
+            running.push(handle.map(move |result| (work, result)));
+        }
+
+        // Items queued by a worker are picked up as soon as it completes, rather
+        // than after every worker started before it.
+        match running.next().await {
+            Some((work, result)) => {
+                log::trace!("Completed {:?}.", &work);
+                completed.insert(work, result?);
+            }
+            None => {
+                log::trace!("All queues empty, breaking.");
+                break;
+            }
         }
     }
 
     Ok(completed)
 }
 
+/// Repositories fetched and parsed at once while refreshing.
+const MAX_CONCURRENT_REFRESHES: usize = 8;
+
+/// A broken repository fails the same way on every refresh, so only some of
+/// its errors are logged. They are still returned with every refresh.
+static REFRESH_ERRORS: once_cell::sync::Lazy<crate::throttle::ErrorThrottle> =
+    once_cell::sync::Lazy::new(Default::default);
+
 pub(crate) async fn refresh_repos(
     config: Config,
 ) -> (
     HashMap<RepoUrl, LoadedRepository>,
     HashMap<RepoUrl, RepoDownloadError>,
+) {
+    let repo_keys = config.repos().keys().cloned().collect::<Vec<_>>();
+    load_repos(config, repo_keys).await
+}
+
+/// Loads the given repositories and any repositories they link to.
+pub(crate) async fn load_repos(
+    config: Config,
+    repo_keys: Vec<RepoUrl>,
+) -> (
+    HashMap<RepoUrl, LoadedRepository>,
+    HashMap<RepoUrl, RepoDownloadError>,
 ) {
     let config = Arc::new(config);
 
     log::debug!("Refreshing repos...");
 
     let repo_data = {
-        let repo_keys = config
-            .repos()
-            .keys()
+        let repo_keys = repo_keys
+            .into_iter()
             .fold(crossbeam_queue::SegQueue::new(), |acc, cur| {
-                acc.push(cur.clone());
+                acc.push(cur);
                 acc
             });
 
         if repo_keys.is_empty() {
             Default::default()
         } else {
-            work(config, repo_keys, |url, queue, config| {
-                Box::pin(async move {
-                    log::trace!("Downloading repo at {:?}…", &url);
-
-                    let cache_dir = config.settings().repo_cache_dir();
-                    let channel = config.repos().get(&url).and_then(|r| r.channel.clone());
-
-                    match LoadedRepository::from_cache_or_url(url, channel, cache_dir).await {
-                        Ok(repo) => {
-                            for url in repo.info().repository.linked_repositories.iter() {
-                                log::trace!("Queuing linked repo: {:?}", &url);
-                                queue.push(url.clone());
-                                // recurse_repo(url.clone(), Arc::clone(&repos), Arc::clone(&config)).await?;
+            work(
+                config,
+                repo_keys,
+                MAX_CONCURRENT_REFRESHES,
+                |url, queue, config| {
+                    Box::pin(async move {
+                        log::trace!("Downloading repo at {:?}…", &url);
+
+                        let cache_dir = repo_cache_path(&config, &url);
+                        let proxy = config.settings().proxy().cloned();
+                        let record = config.repos().get(&url).cloned().unwrap_or_default();
+
+                        let auth_token =
+                            record.resolve_auth_token(&config.settings().secret_store())?;
+
+                        let source = url.to_string();
+                        let trust = crate::trust::repo_trust(&config, &url, &record)?;
+                        let prerelease_channels = record.prerelease_channels;
+                        match LoadedRepository::from_cache_or_url(
+                            url.clone(),
+                            record.channel,
+                            auth_token,
+                            trust.clone(),
+                            proxy,
+                            record.client_certificate,
+                            cache_dir,
+                        )
+                        .await
+                        {
+                            Ok(mut repo) => {
+                                REFRESH_ERRORS.success(&source);
+                                repo.meta.prerelease_channels = prerelease_channels;
+                                crate::trust::accept(&config, &url, &trust, &repo);
+
+                                for url in repo.info().repository.linked_repositories.iter() {
+                                    log::trace!("Queuing linked repo: {:?}", &url);
+                                    queue.push(url.clone());
+                                    // recurse_repo(url.clone(), Arc::clone(&repos), Arc::clone(&config)).await?;
+                                }
+
+                                Ok(repo)
+                            }
+                            Err(e) => {
+                                REFRESH_ERRORS.error(&source, format!("{:?}", e));
+                                Err(e)
                             }
-
-                            Ok(repo)
-                        }
-                        Err(e) => {
-                            log::error!("{:?}", e);
-                            Err(e)
                         }
-                    }
-                })
-            })
+                    })
+                },
+            )
             .await
             .unwrap()
         }
@@ -801,6 +1395,9 @@ pub struct PackageCandidate {
     pub descriptor: Descriptor,
     pub release: Release,
     pub target: Target,
+    /// The target this action is performed for. Dependencies inherit it from the
+    /// package that requires them.
+    pub install_target: InstallTarget,
     pub status: PackageStatus,
     pub is_reboot_required: bool,
 }
@@ -836,38 +1433,89 @@ pub enum PackageCandidateError {
 
     #[error("Attempting to uninstall package required by installation set: `{0}`")]
     UninstallConflict(PackageKey),
+
+    #[error("Package `{0}` requires Pahkat {1} or newer. Please update Pahkat first.")]
+    ClientUpdateRequired(PackageKey, String),
+
+    #[error("Required `{0}` is not available on this system")]
+    VirtualUnavailable(String),
+
+    #[error("Invalid install options for `{0}`")]
+    InvalidOption(
+        PackageKey,
+        #[source] pahkat_types::payload::InstallOptionError,
+    ),
+
+    #[error("Dependency `{0}` {1} is not available, only {2}")]
+    UnsatisfiedDependency(PackageKey, pahkat_types::package::VersionReq, Version),
+
+    #[error("`{0}` was requested for the current user, but `{1}` needs it installed system-wide")]
+    TargetConflict(PackageKey, String),
+}
+
+/// The status of a virtual dependency, which the system provides instead of a package.
+pub(crate) fn virtual_dependency_status(id: &str) -> Option<PackageStatus> {
+    let is_available = match id {
+        pahkat_types::synth::macos::ROSETTA => defaults::is_rosetta_available(),
+        _ => return None,
+    };
+
+    Some(if is_available {
+        PackageStatus::UpToDate
+    } else {
+        PackageStatus::NotInstalled
+    })
+}
+
+fn is_client_version_supported(release: &Release) -> bool {
+    let required = match release.min_client_version.as_ref() {
+        Some(v) => v,
+        None => return true,
+    };
+
+    match semver::Version::parse(required) {
+        Ok(required) => defaults::client_version() >= &required,
+        Err(e) => {
+            log::warn!("Invalid min_client_version {:?}: {}", required, e);
+            false
+        }
+    }
 }
 
 use crate::{ext::DependencyKeyExt, package_store::InstallTarget, PackageActionType};
 use types::DependencyKey;
 
+/// Resolves the release of a candidate. Installs take the highest release matching
+/// `constraint`, which only dependencies narrow down from `*`.
 fn resolve_package_candidate(
     store: &dyn PackageStore,
-    candidate: &(PackageActionType, PackageKey),
-    install_target: &[InstallTarget],
+    candidate: &(PackageActionType, PackageKey, InstallTarget),
+    constraint: &VersionReq,
     repos: &HashMap<RepoUrl, LoadedRepository>,
 ) -> Result<PackageCandidate, PackageCandidateError> {
     let package_key = &candidate.1;
-    let query = crate::repo::ReleaseQuery::new(package_key, &repos);
+    let install_target = candidate.2;
+    let query = crate::repo::ReleaseQuery::new(package_key, repos);
 
     match candidate.0 {
         PackageActionType::Install => {
-            let status = install_target
-                .iter()
-                .fold(None, |acc, cur| match acc {
-                    Some(Ok(v)) if v != PackageStatus::NotInstalled => Some(Ok(v)),
-                    _ => Some(
-                        store
-                            .status(&package_key, *cur)
-                            .map_err(|e| PackageCandidateError::Status(package_key.to_owned(), e)),
-                    ),
-                })
-                .unwrap_or_else(|| {
-                    Err(PackageCandidateError::UnresolvedId(package_key.to_string()))
-                })?;
+            let status = store
+                .status(package_key, install_target)
+                .map_err(|e| PackageCandidateError::Status(package_key.to_owned(), e))?;
 
-            let (target, release, descriptor) = resolve_payload(package_key, &query, &*repos)
-                .map_err(|e| PackageCandidateError::Payload(package_key.to_owned(), e))?;
+            let (target, release, descriptor) = if constraint.is_star() {
+                resolve_payload(package_key, &query, repos)
+                    .map_err(|e| PackageCandidateError::Payload(package_key.to_owned(), e))?
+            } else {
+                resolve_matching_payload(package_key, &query, repos, constraint)?
+            };
+
+            if !is_client_version_supported(&release) {
+                return Err(PackageCandidateError::ClientUpdateRequired(
+                    package_key.to_owned(),
+                    release.min_client_version.clone().unwrap_or_default(),
+                ));
+            }
 
             use pahkat_types::payload::Payload;
 
@@ -906,27 +1554,26 @@ fn resolve_package_candidate(
                 descriptor,
                 release,
                 target,
+                install_target,
                 status,
                 is_reboot_required,
             })
         }
         PackageActionType::Uninstall => {
-            let status = install_target
-                .iter()
-                .fold(None, |acc, cur| match acc {
-                    Some(Ok(v)) if v != PackageStatus::NotInstalled => Some(Ok(v)),
-                    _ => Some(
-                        store
-                            .status(&package_key, *cur)
-                            .map_err(|e| PackageCandidateError::Status(package_key.to_owned(), e)),
-                    ),
-                })
-                .unwrap_or_else(|| {
-                    Err(PackageCandidateError::UnresolvedId(package_key.to_string()))
-                })?;
-
-            let (target, release, descriptor) = resolve_payload(package_key, &query, &*repos)
-                .map_err(|e| PackageCandidateError::Payload(package_key.to_owned(), e))?;
+            let status = store
+                .status(package_key, install_target)
+                .map_err(|e| PackageCandidateError::Status(package_key.to_owned(), e))?;
+
+            // Packages no longer published are uninstalled with their install record
+            let config = store.config();
+            let (target, release, descriptor, _) = resolve_installed_payload(
+                &config.read().unwrap(),
+                package_key,
+                install_target,
+                &query,
+                repos,
+            )
+            .map_err(|e| PackageCandidateError::Payload(package_key.to_owned(), e))?;
 
             use pahkat_types::payload::Payload;
 
@@ -935,19 +1582,15 @@ fn resolve_package_candidate(
                 Payload::MacOSPackage(pkg) => {
                     use pahkat_types::payload::macos::RebootSpec;
                     match status {
-                        PackageStatus::NotInstalled => {
-                            pkg.requires_reboot.contains(&RebootSpec::Uninstall)
-                        }
-                        _ => false,
+                        PackageStatus::NotInstalled => false,
+                        _ => pkg.requires_reboot.contains(&RebootSpec::Uninstall),
                     }
                 }
                 Payload::WindowsExecutable(pkg) => {
                     use pahkat_types::payload::windows::RebootSpec;
                     match status {
-                        PackageStatus::NotInstalled => {
-                            pkg.requires_reboot.contains(&RebootSpec::Uninstall)
-                        }
-                        _ => false,
+                        PackageStatus::NotInstalled => false,
+                        _ => pkg.requires_reboot.contains(&RebootSpec::Uninstall),
                     }
                 }
                 _ => false,
@@ -959,6 +1602,7 @@ fn resolve_package_candidate(
                 descriptor,
                 release,
                 target,
+                install_target,
                 status,
                 is_reboot_required,
             })
@@ -969,15 +1613,28 @@ fn resolve_package_candidate(
 fn recurse_package_set(
     store: &dyn PackageStore,
     package_candidate: &PackageCandidate,
-    install_target: &[InstallTarget],
     repos: &HashMap<RepoUrl, LoadedRepository>,
+    requested: &[(PackageActionType, PackageKey, InstallTarget)],
     set: &mut HashMap<PackageKey, PackageCandidate>,
 ) -> Result<(), PackageCandidateError> {
+    let install_target = package_candidate.install_target;
+
     package_candidate
         .target
         .dependencies
-        .keys()
-        .try_fold((), |_, key| {
+        .iter()
+        .try_fold((), |_, (key, constraint)| {
+            if let DependencyKey::Local(id) = key {
+                if let Some(status) = virtual_dependency_status(id) {
+                    if status != PackageStatus::UpToDate
+                        && package_candidate.action == PackageActionType::Install
+                    {
+                        return Err(PackageCandidateError::VirtualUnavailable(id.to_string()));
+                    }
+                    return Ok(());
+                }
+            }
+
             let key = match key {
                 DependencyKey::Remote(key) => PackageKey::try_from(key)
                     .map_err(|_| PackageCandidateError::UnresolvedId(key.to_string()))?,
@@ -989,25 +1646,263 @@ fn recurse_package_set(
 
             // FIXME: this uninstall thing here is a workaround to make uninstall work at all.
             // No dependency cleanup will occur.
-            if set.contains_key(&key) || package_candidate.action == PackageActionType::Uninstall {
+            if package_candidate.action == PackageActionType::Uninstall {
+                return Ok(());
+            }
+
+            let check_version = |candidate: &PackageCandidate| {
+                if candidate.action == PackageActionType::Install
+                    && !constraint.matches(&candidate.release.version)
+                {
+                    return Err(PackageCandidateError::UnsatisfiedDependency(
+                        candidate.package_key.clone(),
+                        constraint.clone(),
+                        candidate.release.version.clone(),
+                    ));
+                }
+                Ok(())
+            };
+
+            // A dependency already in the set is only revisited when a System package
+            // needs what was so far only going to be installed for the user.
+            match set.get(&key) {
+                // Packages the user asked for are never moved to another target.
+                Some(x)
+                    if x.install_target == InstallTarget::User
+                        && install_target == InstallTarget::System
+                        && requested.iter().any(|r| r.1 == key) =>
+                {
+                    return Err(PackageCandidateError::TargetConflict(
+                        key,
+                        package_candidate.package_key.to_string(),
+                    ));
+                }
+                Some(x)
+                    if x.action == PackageActionType::Install
+                        && x.install_target == InstallTarget::User
+                        && install_target == InstallTarget::System => {}
+                Some(x) => return check_version(x),
+                None => {}
+            }
+
+            let installed = installed_version(store, &key, install_target, repos);
+            if matches!(&installed, Some(v) if constraint.matches(v)) {
+                log::debug!("Dependency {} is already installed", &key);
+                return Ok(());
+            }
+
+            let is_satisfied_elsewhere = install_target
+                .dependency_targets()
+                .iter()
+                .filter(|x| **x != install_target)
+                .any(|x| {
+                    matches!(
+                        installed_version(store, &key, *x, repos),
+                        Some(v) if constraint.matches(&v)
+                    )
+                });
+            if is_satisfied_elsewhere {
+                log::debug!(
+                    "Dependency {} is already installed for another target",
+                    &key
+                );
                 return Ok(());
             }
 
             let candidate = resolve_package_candidate(
                 store,
-                &(PackageActionType::Install, key.to_owned()),
-                install_target,
+                &(PackageActionType::Install, key.to_owned(), install_target),
+                constraint,
                 repos,
             )?;
+
+            // Nothing would be installed for an up to date package, as that would be
+            // a downgrade
+            if let (PackageStatus::UpToDate, Some(version)) = (candidate.status, installed) {
+                return Err(PackageCandidateError::UnsatisfiedDependency(
+                    key,
+                    constraint.clone(),
+                    version,
+                ));
+            }
+
             set.insert(key, candidate);
             Ok(())
         })
 }
 
+/// The version of `key` installed for `target`, as recorded when it was installed.
+/// Packages installed some other way are taken to be at the newest release if up
+/// to date, and at an unknown version otherwise.
+pub fn installed_version(
+    store: &dyn PackageStore,
+    key: &PackageKey,
+    target: InstallTarget,
+    repos: &HashMap<RepoUrl, LoadedRepository>,
+) -> Option<Version> {
+    let status = store.status(key, target).ok()?;
+    if status == PackageStatus::NotInstalled {
+        return None;
+    }
+
+    let config = store.config();
+    let record = installed::load(&config.read().unwrap(), key, target);
+    match (record, status) {
+        (Some(record), _) => Some(record.release.version),
+        (None, PackageStatus::UpToDate) => {
+            let query = ReleaseQuery::new(key, repos);
+            resolve_payload(key, &query, repos)
+                .ok()
+                .map(|(_, release, _)| release.version)
+        }
+        (None, _) => None,
+    }
+}
+
+/// Like [`resolve_payload`], but takes the highest release matching `constraint`
+/// rather than the newest one.
+fn resolve_matching_payload(
+    package_key: &PackageKey,
+    query: &ReleaseQuery<'_>,
+    repos: &HashMap<RepoUrl, LoadedRepository>,
+    constraint: &VersionReq,
+) -> Result<
+    (
+        pahkat_types::payload::Target,
+        pahkat_types::package::Release,
+        pahkat_types::package::Descriptor,
+    ),
+    PackageCandidateError,
+> {
+    let descriptor = resolve_package(package_key, repos)
+        .map_err(|e| PackageCandidateError::Payload(package_key.to_owned(), e))?;
+
+    let mut newest: Option<&Version> = None;
+    let mut best: Option<ReleaseQueryResponse<'_>> = None;
+    for x in query.iter(&descriptor) {
+        newest.get_or_insert(&x.release.version);
+        if !constraint.matches(&x.release.version) {
+            continue;
+        }
+        if best
+            .as_ref()
+            .is_none_or(|b| x.release.version > b.release.version)
+        {
+            best = Some(x);
+        }
+    }
+
+    match (best, newest) {
+        (Some(x), _) => Ok((x.target.clone(), x.release.clone(), descriptor.clone())),
+        (None, Some(version)) => Err(PackageCandidateError::UnsatisfiedDependency(
+            package_key.to_owned(),
+            constraint.clone(),
+            version.clone(),
+        )),
+        (None, None) => Err(PackageCandidateError::Payload(
+            package_key.to_owned(),
+            PayloadError::NoPayloadFound,
+        )),
+    }
+}
+
+/// A dependency found while walking the dependency graph of a package.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DependencyNode {
+    pub key: PackageKey,
+    pub status: PackageStatus,
+    /// The version requirement declared by the package that pulled this one in.
+    pub constraint: pahkat_types::package::VersionReq,
+    /// The packages from the requested one down to the one that pulled this one in.
+    pub path: Vec<PackageKey>,
+}
+
+impl DependencyNode {
+    /// Direct dependencies have a depth of 1.
+    pub fn depth(&self) -> usize {
+        self.path.len()
+    }
+}
+
+/// Walks the dependencies of a package, including transitive ones, breadth first.
+///
+/// Each dependency is listed once, as reached by its shortest path.
+pub fn dependency_tree(
+    store: &dyn PackageStore,
+    key: &PackageKey,
+    target: InstallTarget,
+) -> Result<Vec<DependencyNode>, PackageDependencyStatusError> {
+    let repos = store.repos();
+    let repos = repos.read().unwrap();
+
+    let mut nodes = vec![];
+    let mut seen = HashSet::new();
+    let mut queue = std::collections::VecDeque::new();
+    seen.insert(key.clone());
+    queue.push_back((key.clone(), vec![]));
+
+    while let Some((parent, mut path)) = queue.pop_front() {
+        let (_, parent_target) = resolve_release(&parent, &repos)
+            .ok_or_else(|| PackageDependencyStatusError::PackageNotFound(parent.to_string()))?;
+        path.push(parent);
+
+        for (dependency, constraint) in parent_target.dependencies.iter() {
+            // Virtual dependencies are not packages, so have no key to report
+            if let DependencyKey::Local(id) = dependency {
+                if virtual_dependency_status(id).is_some() {
+                    continue;
+                }
+            }
+
+            let key = match dependency {
+                DependencyKey::Remote(url) => PackageKey::try_from(url)
+                    .map_err(|_| PackageDependencyStatusError::PackageNotFound(url.to_string()))?,
+                DependencyKey::Local(id) => store
+                    .find_package_by_id(id)
+                    .map(|x| x.0)
+                    .ok_or_else(|| PackageDependencyStatusError::PackageNotFound(id.to_string()))?,
+            };
+
+            if !seen.insert(key.clone()) {
+                continue;
+            }
+
+            let status = store.status(&key, target).map_err(|e| match e {
+                PackageStatusError::Payload(e) => {
+                    PackageDependencyStatusError::Payload(key.clone(), e)
+                }
+                PackageStatusError::WrongPayloadType => {
+                    PackageDependencyStatusError::WrongPayloadType(key.clone())
+                }
+                PackageStatusError::ParsingVersion => {
+                    PackageDependencyStatusError::ParsingVersion(key.clone())
+                }
+                PackageStatusError::Cancelled(e) => {
+                    PackageDependencyStatusError::Cancelled(key.clone(), e)
+                }
+            })?;
+
+            nodes.push(DependencyNode {
+                key: key.clone(),
+                status,
+                constraint: constraint.clone(),
+                path: path.clone(),
+            });
+            queue.push_back((key, path.clone()));
+        }
+    }
+
+    Ok(nodes)
+}
+
+/// Resolves the requested actions and the dependencies of any installs.
+///
+/// Dependencies are installed for the same target as the package requiring them.
+/// A System package can only depend on System packages, while a User package's
+/// dependency is also satisfied by a System install.
 pub(crate) fn resolve_package_set(
     store: &dyn PackageStore,
-    candidates: &[(PackageActionType, PackageKey)],
-    install_target: &[InstallTarget],
+    candidates: &[(PackageActionType, PackageKey, InstallTarget)],
 ) -> Result<Vec<PackageCandidate>, PackageCandidateError> {
     let repos = store.repos();
     let repos = repos.read().unwrap();
@@ -1016,7 +1911,7 @@ pub(crate) fn resolve_package_set(
     let mut candidate_set = candidates
         .iter()
         .map(|key| {
-            resolve_package_candidate(store, &key, install_target, &*repos)
+            resolve_package_candidate(store, key, &VersionReq::STAR, &repos)
                 .map(|v| (key.1.to_owned(), v))
         })
         .collect::<Result<HashMap<_, _>, _>>()?;
@@ -1028,13 +1923,7 @@ pub(crate) fn resolve_package_set(
     values.iter().try_fold((), |_, candidate| {
         log::trace!("Recursing packages for candidate: {:?}", candidate);
 
-        recurse_package_set(
-            store,
-            candidate,
-            install_target,
-            &*repos,
-            &mut candidate_set,
-        )
+        recurse_package_set(store, candidate, &repos, candidates, &mut candidate_set)
     })?;
 
     // Take our candidate set and resolve it down to a mutation set
@@ -1074,3 +1963,360 @@ pub(crate) fn resolve_package_set(
 
     Ok(output_mutation_set)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn staged(rollout: Option<u8>) -> Release {
+        Release::builder()
+            .version(pahkat_types::package::Version::new("1.0.0").unwrap())
+            .rollout(rollout)
+            .build()
+    }
+
+    #[test]
+    fn only_channel_lists_are_tiered() {
+        let url = RepoUrl::new("https://pahkat.example/repo/".parse().unwrap()).unwrap();
+        let key = |channel: Option<&str>| {
+            let query = PackageKeyParams {
+                channel: channel.map(str::to_string),
+                ..Default::default()
+            };
+            PackageKey::new_unchecked(url.clone(), "speller".into(), Some(query))
+        };
+        let repos = HashMap::new();
+
+        for channel in [None, Some("beta")] {
+            let key = key(channel);
+            assert!(ReleaseQuery::new(&key, &repos).is_newest_of_channels);
+        }
+        for channel in ["beta, stable", "nightly, beta"] {
+            let key = key(Some(channel));
+            assert!(!ReleaseQuery::new(&key, &repos).is_newest_of_channels);
+        }
+    }
+
+    #[test]
+    fn rollout_bucket_is_stable_per_machine_and_package() {
+        let machines = (0..1000)
+            .map(|i| format!("machine-{}", i))
+            .collect::<Vec<_>>();
+
+        for machine in machines.iter() {
+            let bucket = rollout_bucket(machine, "speller");
+            assert!(bucket < 100);
+            assert_eq!(rollout_bucket(machine, "speller"), bucket);
+        }
+
+        // Machines are not offered staged releases of every package first
+        assert!(machines
+            .iter()
+            .any(|x| rollout_bucket(x, "speller") != rollout_bucket(x, "keyboard")));
+
+        // About a tenth of the machines are in the first ten buckets
+        let first = machines
+            .iter()
+            .filter(|x| rollout_bucket(x, "speller") < 10)
+            .count();
+        assert!((50..150).contains(&first), "{}", first);
+    }
+
+    #[test]
+    fn rollout_percentage_boundaries() {
+        let machines = (0..1000)
+            .map(|i| format!("machine-{}", i))
+            .collect::<Vec<_>>();
+
+        for machine in machines.iter() {
+            let bucket = rollout_bucket(machine, "speller") as u8;
+            assert!(is_in_rollout(machine, "speller", &staged(None)));
+            assert!(is_in_rollout(machine, "speller", &staged(Some(100))));
+            assert!(!is_in_rollout(machine, "speller", &staged(Some(0))));
+            assert!(!is_in_rollout(machine, "speller", &staged(Some(bucket))));
+            assert!(is_in_rollout(machine, "speller", &staged(Some(bucket + 1))));
+        }
+    }
+
+    #[derive(Default)]
+    struct Running {
+        current: AtomicUsize,
+        max: AtomicUsize,
+    }
+
+    #[test]
+    fn work_is_bounded_and_follows_queued_items() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+
+        let items = SegQueue::new();
+        for i in 0..10u32 {
+            items.push(i);
+        }
+
+        let completed = runtime
+            .block_on(work(
+                Running::default(),
+                items,
+                3,
+                |item, queue, running| {
+                    Box::pin(async move {
+                        let current = running.current.fetch_add(1, Ordering::SeqCst) + 1;
+                        running.max.fetch_max(current, Ordering::SeqCst);
+                        for _ in 0..5 {
+                            tokio::task::yield_now().await;
+                        }
+                        if item < 10 {
+                            queue.push(item + 10);
+                        }
+                        running.current.fetch_sub(1, Ordering::SeqCst);
+                        running.max.load(Ordering::SeqCst)
+                    })
+                },
+            ))
+            .unwrap();
+
+        assert_eq!(completed.len(), 20);
+        assert!(completed.values().all(|max| *max <= 3));
+    }
+
+    #[test]
+    fn prerelease_falls_back_to_newer_stable() {
+        use pahkat_types::package::{Descriptor, DescriptorData, Release, Version};
+        use pahkat_types::payload::{tarball, Payload, Target};
+
+        let release = |version: &str, channel: Option<&str>| {
+            let url = format!("https://pahkat.example/speller-{}.txz", version);
+            Release::builder()
+                .version(Version::new(version).unwrap())
+                .channel(channel.map(str::to_string))
+                .target(vec![Target::builder()
+                    .platform("linux".into())
+                    .payload(Payload::TarballPackage(
+                        tarball::Package::builder()
+                            .url(url.parse().unwrap())
+                            .size(1)
+                            .installed_size(1)
+                            .build(),
+                    ))
+                    .build()])
+                .build()
+        };
+        let descriptor = |releases| {
+            Descriptor::builder()
+                .package(DescriptorData::builder().id("speller".into()).build())
+                .release(releases)
+                .build()
+        };
+        let resolved = |query: &ReleaseQuery<'_>, descriptor: &Descriptor| {
+            query
+                .iter(descriptor)
+                .next()
+                .map(|x| x.release.version.to_string())
+        };
+
+        let mut query = ReleaseQuery {
+            platform: "linux",
+            arch: None,
+            emulated_arches: vec![],
+            channels: vec!["beta"],
+            ..Default::default()
+        };
+
+        let stale_beta = descriptor(vec![
+            release("1.1.0", None),
+            release("1.1.0-beta.2", Some("beta")),
+        ]);
+        assert_eq!(resolved(&query, &stale_beta).unwrap(), "1.1.0-beta.2");
+        query.is_newest_of_channels = true;
+        assert_eq!(resolved(&query, &stale_beta).unwrap(), "1.1.0");
+
+        let current_beta = descriptor(vec![
+            release("1.2.0-beta.1", Some("beta")),
+            release("1.1.0", None),
+        ]);
+        assert_eq!(resolved(&query, &current_beta).unwrap(), "1.2.0-beta.1");
+    }
+
+    #[test]
+    fn min_client_version_is_checked_against_the_client() {
+        let requiring = |version: Option<&str>| {
+            let mut release = staged(None);
+            release.min_client_version = version.map(str::to_string);
+            is_client_version_supported(&release)
+        };
+
+        assert!(requiring(None));
+        assert!(requiring(Some("0.1.0")));
+        assert!(requiring(Some(&defaults::client_version().to_string())));
+        assert!(!requiring(Some("999.0.0")));
+        assert!(!requiring(Some("not a version")));
+    }
+
+    #[test]
+    fn removing_a_repo_purges_its_downloads() {
+        use crate::package_store::mock::{package, MockStore, REPO};
+        use pahkat_types::AsDownloadUrl;
+
+        let store = MockStore::new(&[package("a", "1.0.0", &[])]);
+        let url: RepoUrl = REPO.parse().unwrap();
+        let payload = {
+            let config = store.config();
+            let config = config.read().unwrap();
+            let repos = store.repos();
+            let repos = repos.read().unwrap();
+            let descriptor = repos[&url].descriptors().remove(0);
+            let payload = &descriptor.release[0].target[0].payload;
+            download_file_path(&config, payload.as_download_url())
+        };
+        std::fs::create_dir_all(payload.parent().unwrap()).unwrap();
+        std::fs::write(&payload, b"payload").unwrap();
+
+        store.remove_repo(&url).unwrap();
+        assert!(!payload.exists());
+        assert!(store.repos().read().unwrap().is_empty());
+    }
+
+    #[test]
+    fn dependency_version_requirements_are_enforced() {
+        use crate::package_store::mock::{key, package, MockStore};
+
+        let resolve = |requirement: &str| {
+            let store = MockStore::new(&[
+                package("a", "1.0.0", &[("b", requirement)]),
+                package("b", "1.0.0", &[]),
+            ]);
+            let candidates = [(PackageActionType::Install, key("a"), InstallTarget::System)];
+            resolve_package_set(&store, &candidates)
+        };
+
+        assert_eq!(resolve(">=1").unwrap().len(), 2);
+        assert!(matches!(
+            resolve(">=2"),
+            Err(PackageCandidateError::UnsatisfiedDependency(..))
+        ));
+    }
+
+    #[test]
+    fn dependencies_follow_the_install_target() {
+        use crate::package_store::mock::{key, package, MockStore};
+
+        let store = MockStore::new(&[
+            package("a", "1.0.0", &[("b", ">=1")]),
+            package("b", "1.0.0", &[]),
+            package("c", "1.0.0", &[("b", ">=1")]),
+        ]);
+
+        // A user install depends on packages for the user.
+        let set = resolve_package_set(
+            &store,
+            &[(PackageActionType::Install, key("a"), InstallTarget::User)],
+        )
+        .unwrap();
+        assert!(set.iter().all(|x| x.install_target == InstallTarget::User));
+
+        // A system package moves a dependency that was only pulled in for the user.
+        let set = resolve_package_set(
+            &store,
+            &[
+                (PackageActionType::Install, key("a"), InstallTarget::User),
+                (PackageActionType::Install, key("c"), InstallTarget::System),
+            ],
+        )
+        .unwrap();
+        let b = set.iter().find(|x| x.package_key == key("b")).unwrap();
+        assert_eq!(b.install_target, InstallTarget::System);
+
+        // ...but not one the user explicitly asked to install for themselves.
+        let result = resolve_package_set(
+            &store,
+            &[
+                (PackageActionType::Install, key("b"), InstallTarget::User),
+                (PackageActionType::Install, key("c"), InstallTarget::System),
+            ],
+        );
+        assert!(matches!(
+            result,
+            Err(PackageCandidateError::TargetConflict(b, c)) if b == key("b") && c == key("c").to_string()
+        ));
+    }
+
+    #[test]
+    fn user_installs_use_dependencies_installed_for_the_system() {
+        use crate::package_store::mock::{key, package, MockStore};
+
+        let store = MockStore::new(&[
+            package("a", "1.0.0", &[("b", ">=1")]),
+            package("b", "1.0.0", &[]),
+        ]);
+        store.set_status("b", PackageStatus::UpToDate);
+
+        let set = resolve_package_set(
+            &store,
+            &[(PackageActionType::Install, key("a"), InstallTarget::User)],
+        )
+        .unwrap();
+        assert_eq!(set.len(), 1);
+        assert_eq!(set[0].package_key, key("a"));
+    }
+
+    #[test]
+    fn dependencies_use_the_highest_matching_release() {
+        use crate::package_store::mock::{key, package, MockStore};
+
+        let mut b = package("b", "2.0.0", &[]);
+        if let Package::Concrete(descriptor) = &mut b {
+            for version in ["1.2.0", "1.5.0"] {
+                let mut release = descriptor.release[0].clone();
+                release.version = Version::new(version).unwrap();
+                descriptor.release.push(release);
+            }
+        }
+        let store = MockStore::new(&[package("a", "1.0.0", &[("b", "<2")]), b]);
+
+        let set = resolve_package_set(
+            &store,
+            &[(PackageActionType::Install, key("a"), InstallTarget::System)],
+        )
+        .unwrap();
+        let b = set.iter().find(|x| x.package_key == key("b")).unwrap();
+        assert_eq!(b.release.version, Version::new("1.5.0").unwrap());
+
+        // An installed release that matches is kept, even if a newer one exists.
+        let b = Descriptor::try_from(store.find_package_by_key(&key("b")).unwrap()).unwrap();
+        installed::save(
+            &store.config().read().unwrap(),
+            &key("b"),
+            InstallTarget::System,
+            &b,
+            &b.release[1],
+            &b.release[1].target[0],
+        );
+        store.set_status("b", PackageStatus::RequiresUpdate);
+        let set = resolve_package_set(
+            &store,
+            &[(PackageActionType::Install, key("a"), InstallTarget::System)],
+        )
+        .unwrap();
+        assert_eq!(set.len(), 1);
+
+        // An up to date install that does not match would need a downgrade.
+        installed::remove(
+            &store.config().read().unwrap(),
+            &key("b"),
+            InstallTarget::System,
+        );
+        store.set_status("b", PackageStatus::UpToDate);
+        let result = resolve_package_set(
+            &store,
+            &[(PackageActionType::Install, key("a"), InstallTarget::System)],
+        );
+        assert!(matches!(
+            result,
+            Err(PackageCandidateError::UnsatisfiedDependency(b, _, v))
+                if b == key("b") && v == Version::new("2.0.0").unwrap()
+        ));
+    }
+}
@@ -0,0 +1,96 @@
+//! The resolved release of each package installed through a transaction, kept so that
+//! a package can still be found and uninstalled after its repository stops publishing
+//! it.
+
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use sha2::digest::Digest;
+use sha2::Sha256;
+
+use pahkat_types::package::{Descriptor, Release};
+use pahkat_types::payload::Target;
+use pahkat_types::PackageKey;
+
+use crate::config::Config;
+use crate::package_store::InstallTarget;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct InstallRecord {
+    pub key: PackageKey,
+    pub install_target: InstallTarget,
+    pub descriptor: Descriptor,
+    pub release: Release,
+    pub target: Target,
+}
+
+/// Kept with the config rather than the cache, as clearing the cache must not lose it.
+fn dir(config: &Config) -> PathBuf {
+    config.settings().config_dir().join("installed")
+}
+
+/// Records are kept per package, whatever the channel or version it was installed with.
+fn unqualified(key: &PackageKey) -> PackageKey {
+    PackageKey::new_unchecked(key.repository_url.clone(), key.id.clone(), None)
+}
+
+fn path(config: &Config, key: &PackageKey, install_target: InstallTarget) -> PathBuf {
+    let mut sha = Sha256::new();
+    sha.update(unqualified(key).to_string().as_bytes());
+    sha.update(&[install_target.to_u8()]);
+    dir(config).join(format!("{:x}.json", sha.finalize()))
+}
+
+pub(crate) fn save(
+    config: &Config,
+    key: &PackageKey,
+    install_target: InstallTarget,
+    descriptor: &Descriptor,
+    release: &Release,
+    target: &Target,
+) {
+    let record = InstallRecord {
+        key: unqualified(key),
+        install_target,
+        descriptor: descriptor.clone(),
+        release: release.clone(),
+        target: target.clone(),
+    };
+
+    let path = path(config, key, install_target);
+    let result = std::fs::create_dir_all(dir(config))
+        .and_then(|_| std::fs::write(&path, serde_json::to_vec(&record)?));
+    if let Err(e) = result {
+        log::warn!("Could not record installation of {}: {}", key, e);
+    }
+}
+
+pub(crate) fn remove(config: &Config, key: &PackageKey, install_target: InstallTarget) {
+    match std::fs::remove_file(path(config, key, install_target)) {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => log::warn!("Could not remove installation record of {}: {}", key, e),
+    }
+}
+
+pub(crate) fn load(
+    config: &Config,
+    key: &PackageKey,
+    install_target: InstallTarget,
+) -> Option<InstallRecord> {
+    let data = std::fs::read(path(config, key, install_target)).ok()?;
+    serde_json::from_slice(&data).ok()
+}
+
+pub(crate) fn list(config: &Config) -> Vec<InstallRecord> {
+    let entries = match std::fs::read_dir(dir(config)) {
+        Ok(v) => v,
+        Err(_) => return vec![],
+    };
+
+    entries
+        .filter_map(Result::ok)
+        .filter_map(|x| std::fs::read(x.path()).ok())
+        .filter_map(|x| serde_json::from_slice(&x).ok())
+        .collect()
+}
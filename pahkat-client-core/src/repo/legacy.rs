@@ -0,0 +1,113 @@
+//! Reading repositories still in the JSON-LD layout of pahkat 1, with `index.json` and
+//! `packages/index.json` in place of `index.toml` and `packages/index.bin`.
+//!
+//! The conversion is shared with `pahkat-repomgr repo migrate-legacy`, which moves such
+//! repositories to the current layout for good.
+
+use pahkat_types::index_writer::{encode_index, EncodeError};
+use pahkat_types::legacy;
+use pahkat_types::package::Package;
+use pahkat_types::repo::{Index, RepoUrl};
+
+#[derive(Debug, thiserror::Error)]
+pub enum LegacyError {
+    #[error("Error reading legacy JSON index")]
+    Read(#[from] legacy::Error),
+
+    #[error("Could not convert legacy packages into a package index")]
+    Encode(#[from] EncodeError),
+}
+
+/// Converts the contents of `index.json` and `packages/index.json` into the repository
+/// index and the contents of `packages/index.bin`.
+///
+/// The `base` of the legacy index is ignored in favour of `url`, as it is the URL the
+/// repository is known by locally.
+pub(crate) fn convert(
+    url: &RepoUrl,
+    index: &[u8],
+    packages: &[u8],
+) -> Result<(Index, Box<[u8]>), LegacyError> {
+    let info = legacy::read_index(index, Some(url))?;
+    let packages = legacy::read_packages(packages)?
+        .into_iter()
+        .map(Package::Concrete)
+        .collect::<Vec<_>>();
+
+    let packages = encode_index(&packages)?.into_boxed_slice();
+
+    Ok((info, packages))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_legacy_index() {
+        let url: RepoUrl = "https://pahkat.example/main/".parse().unwrap();
+        let index = serde_json::json!({
+            "@type": "Repository",
+            "base": "https://pahkat.example/main/",
+            "name": { "en": "Main" },
+            "channels": ["stable", "beta"],
+            "agent": { "name": "pahkat", "version": "0.6.0" },
+        });
+        let packages = serde_json::json!({
+            "@type": "Packages",
+            "packages": {
+                "speller-sme": {
+                    "@type": "Package",
+                    "id": "speller-sme",
+                    "name": { "en": "Northern Sami speller" },
+                    "version": "1.2.0",
+                    "category": "spellers",
+                    "languages": ["sme"],
+                    "platform": { "windows": ">= 8.1" },
+                    "installer": {
+                        "@type": "WindowsInstaller",
+                        "url": "https://pahkat.example/speller-sme.exe",
+                        "productCode": "{C3A1F6B8-1234-4F4E-9C38-1E6B2C0D4B11}",
+                        "type": "inno",
+                        "requiresReboot": true,
+                        "size": 1000,
+                        "installedSize": 4000,
+                    },
+                },
+                "no-installer": {
+                    "@type": "Package",
+                    "id": "no-installer",
+                    "version": "1.0.0",
+                },
+            },
+        });
+
+        let (info, packages) = convert(
+            &url,
+            index.to_string().as_bytes(),
+            packages.to_string().as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(info.repository.url, url);
+        assert_eq!(info.repository.channels, vec!["stable", "beta"]);
+
+        let repo = super::super::LoadedRepository::new(
+            info,
+            packages,
+            super::super::LoadedRepositoryMeta {
+                channel: None,
+                last_update: None,
+                prerelease_channels: Default::default(),
+            },
+        )
+        .unwrap();
+        let descriptors = repo.descriptors();
+        assert_eq!(descriptors.len(), 1);
+        assert_eq!(descriptors[0].package.id, "speller-sme");
+        assert_eq!(
+            descriptors[0].package.tags,
+            vec!["category:spellers", "language:sme"]
+        );
+        assert_eq!(descriptors[0].release[0].target[0].platform, "windows");
+    }
+}
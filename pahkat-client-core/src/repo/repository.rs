@@ -1,9 +1,16 @@
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 
 use serde::{Deserialize, Serialize};
+use url::Url;
 
+use crate::config::ClientCertificate;
+use crate::fbs::PackagesExt;
 use crate::generated::pahkat as pahkat_fbs;
-use pahkat_types::{repo::RepoUrl, PackageKey};
+use pahkat_types::repo::{Manifest, RepoUrl};
+use pahkat_types::{package::Descriptor, PackageKey};
+
+use super::signature::{signature_path, verify_file, verify_manifest};
 
 #[derive(Debug, thiserror::Error)]
 pub enum RepoDownloadError {
@@ -13,15 +20,100 @@ pub enum RepoDownloadError {
     #[error("Error parsing TOML index")]
     TomlError(#[from] toml::de::Error),
 
+    #[error("Invalid package index")]
+    InvalidIndex,
+
     #[error("I/O error")]
     IoError(#[from] std::io::Error),
+
+    #[error("Could not decompress {0}")]
+    Decompress(String, #[source] std::io::Error),
+
+    #[error("{0} decompresses to more than {1} bytes")]
+    IndexTooLarge(String, u64),
+
+    #[error("Could not retrieve repository credentials")]
+    SecretError(#[from] crate::secret::SecretError),
+
+    #[error("Repository does not provide a signature for {0}")]
+    SignatureMissing(String),
+
+    #[error("Signature of {0} does not match any trusted key")]
+    InvalidSignature(String),
+
+    #[error("{0} does not match the signed manifest of the repository")]
+    ManifestMismatch(String),
+
+    #[error("Signed manifest is for another repository, {0}")]
+    ManifestUrl(String),
+
+    #[error("Repository serves an older index (serial {0}) than one seen before (serial {1})")]
+    Rollback(u64, u64),
+
+    #[error("Trusted key is not a base64 ed25519 public key: {0}")]
+    InvalidTrustedKey(String),
+
+    #[error(
+        "Signing key of the repository changed to {}; accept it once verified",
+        .0.as_deref().unwrap_or("none")
+    )]
+    KeyChanged(Option<String>),
+
+    #[error("Could not load pinned signing keys")]
+    Trust(#[from] crate::trust::TrustError),
+
+    #[error("Not a local path: {0}")]
+    LocalPath(String),
+
+    #[error("Could not load client certificate")]
+    ClientCertificate(#[from] crate::tls::ClientCertificateError),
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[error("Could not load legacy JSON repository")]
+    Legacy(#[from] super::legacy::LegacyError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RepoStatus {
+    Loaded,
+    Failed,
+    /// The repository advertises another signing key than the one first seen, and is
+    /// not loaded until the new key is accepted.
+    KeyChanged,
+}
+
+impl RepoStatus {
+    pub fn of(error: Option<&RepoDownloadError>) -> RepoStatus {
+        match error {
+            None => RepoStatus::Loaded,
+            Some(RepoDownloadError::KeyChanged(_)) => RepoStatus::KeyChanged,
+            Some(_) => RepoStatus::Failed,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct LoadedRepositoryMeta {
     pub channel: Option<String>,
     // pub hash_id: String,
-    // TODO: last update
+    #[serde(default)]
+    pub last_update: Option<chrono::DateTime<chrono::Utc>>,
+    /// Pre-release channels of individual packages, by package id, from the
+    /// repository's config record.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub prerelease_channels: BTreeMap<String, String>,
+}
+
+/// What the indexes of a repository are verified against.
+#[derive(Debug, Clone, Default)]
+pub struct RepoTrust {
+    /// If any, the manifest must be signed by one of these keys.
+    pub keys: Vec<String>,
+    /// Otherwise by the key the repository advertises, which must be this key if one
+    /// was seen for the repository before.
+    pub pinned_key: Option<String>,
+    /// The newest manifest serial seen for the repository. Older manifests are refused.
+    pub serial: Option<u64>,
 }
 
 #[derive(Debug, Clone)]
@@ -29,21 +121,99 @@ pub struct LoadedRepository {
     pub info: pahkat_types::repo::Index,
     pub packages: Box<[u8]>,
     pub meta: LoadedRepositoryMeta,
+    serial: Option<u64>,
 }
 
 impl LoadedRepository {
+    /// Fails if `packages` is not a package index.
+    pub fn new(
+        info: pahkat_types::repo::Index,
+        packages: Box<[u8]>,
+        meta: LoadedRepositoryMeta,
+    ) -> Result<LoadedRepository, RepoDownloadError> {
+        if pahkat_fbs::Packages::get_root(&*packages).is_err() {
+            return Err(RepoDownloadError::InvalidIndex);
+        }
+
+        Ok(LoadedRepository {
+            info,
+            packages,
+            meta,
+            serial: None,
+        })
+    }
+
+    /// Like [`LoadedRepository::from_url`], but keeps the index files of the repository
+    /// in `cache_dir` once verified. The cached index is loaded instead, and verified
+    /// again, when the repository cannot be reached.
     pub async fn from_cache_or_url(
         url: RepoUrl,
         channel: Option<String>,
+        auth_token: Option<String>,
+        trust: RepoTrust,
+        proxy: Option<Url>,
+        client_certificate: Option<ClientCertificate>,
         cache_dir: PathBuf,
     ) -> Result<LoadedRepository, RepoDownloadError> {
-        Self::from_url(url, channel).await
+        let result = Self::fetch(
+            url.clone(),
+            channel.clone(),
+            auth_token,
+            trust.clone(),
+            proxy,
+            client_certificate,
+        )
+        .await;
+
+        match result {
+            Ok((repo, files)) => {
+                #[cfg(not(target_arch = "wasm32"))]
+                if let Err(e) = write_cache(&cache_dir, &files) {
+                    log::warn!("Could not cache index of {}: {}", &url, e);
+                }
+                #[cfg(target_arch = "wasm32")]
+                let _ = (cache_dir, files);
+                Ok(repo)
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            Err(RepoDownloadError::ReqwestError(e)) if cache_dir.join("index.toml").exists() => {
+                log::warn!("Could not reach {}, loading cached index: {}", &url, e);
+                let mut repo = Self::from_dir(&url, &cache_dir, channel, trust)?;
+                repo.meta.last_update = std::fs::metadata(cache_dir.join("index.toml"))
+                    .and_then(|x| x.modified())
+                    .ok()
+                    .map(chrono::DateTime::from);
+                Ok(repo)
+            }
+            Err(e) => Err(e),
+        }
     }
 
-    async fn from_url(
+    /// Signed repositories are verified against `trust`. Unsigned repositories load as
+    /// long as no keys are configured or pinned for them.
+    pub async fn from_url(
         url: RepoUrl,
         channel: Option<String>,
+        auth_token: Option<String>,
+        trust: RepoTrust,
+        proxy: Option<Url>,
+        client_certificate: Option<ClientCertificate>,
     ) -> Result<LoadedRepository, RepoDownloadError> {
+        Self::fetch(url, channel, auth_token, trust, proxy, client_certificate)
+            .await
+            .map(|(repo, _)| repo)
+    }
+
+    /// Also returns the files the repository was loaded from, for caching. Legacy
+    /// repositories return none.
+    async fn fetch(
+        url: RepoUrl,
+        channel: Option<String>,
+        auth_token: Option<String>,
+        trust: RepoTrust,
+        proxy: Option<Url>,
+        client_certificate: Option<ClientCertificate>,
+    ) -> Result<(LoadedRepository, Vec<IndexFile>), RepoDownloadError> {
         const USER_AGENT: &str = concat!(
             "pahkat-client/",
             env!("GIT_VERSION"),
@@ -51,53 +221,225 @@ impl LoadedRepository {
             env!("CARGO_TARGET_TRIPLE"),
             ")"
         );
-        let (tx, rx) = tokio::sync::oneshot::channel();
-
-        tokio::spawn(async move {
-            let result = async move {
-                let client = reqwest::Client::builder()
-                    .user_agent(USER_AGENT)
-                    .referer(false)
-                    .redirect(reqwest::redirect::Policy::none())
-                    .build()?;
-
-                log::trace!("Loading repo: {} channel:{:?}", &url, &channel);
-
-                let info = client
-                    .get(&format!("{}/index.toml", url))
-                    .send()
-                    .await?
-                    .text()
-                    .await?;
-                let info: pahkat_types::repo::Index = toml::from_str(&info)?;
-
-                let packages = client
-                    .get(&format!("{}/packages/index.bin", url))
-                    .send()
-                    .await?
-                    .bytes()
-                    .await?
-                    .to_vec()
-                    .into_boxed_slice();
-
-                let repo = LoadedRepository {
-                    info,
-                    packages,
-                    meta: LoadedRepositoryMeta {
-                        channel,
-                        // hash_id: "".into(),
-                    },
-                };
-
-                log::trace!("Loaded.");
-                Ok(repo)
+        #[cfg(not(target_arch = "wasm32"))]
+        if url.is_local() {
+            return Self::from_path(url, channel, trust).map(|repo| (repo, vec![]));
+        }
+
+        super::on_runtime(async move {
+            let client = client(USER_AGENT, proxy.as_ref(), client_certificate.as_ref())?;
+
+            log::trace!("Loading repo: {} channel:{:?}", &url, &channel);
+
+            let get = |path: &str| {
+                let req = client.get(&format!("{}/{}", url, path));
+                #[cfg(not(target_arch = "wasm32"))]
+                let req = req.header(reqwest::header::ACCEPT_ENCODING, ACCEPT_ENCODING);
+                match auth_token.as_ref() {
+                    Some(token) => req.bearer_auth(token),
+                    None => req,
+                }
+            };
+
+            let get_signed = |path: String| {
+                let response = get(&path).send();
+                async move {
+                    let response = response.await?;
+                    if !response.status().is_success() {
+                        return Err(RepoDownloadError::SignatureMissing(path));
+                    }
+                    body(&path, response).await
+                }
+            };
+
+            let response = get("index.toml").send().await?;
+
+            #[cfg(not(target_arch = "wasm32"))]
+            if response.status() == reqwest::StatusCode::NOT_FOUND {
+                let legacy = get("index.json").send().await?;
+                if legacy.status().is_success() {
+                    let keys = super::signature::trusted_keys(
+                        &trust.keys,
+                        trust.pinned_key.as_deref(),
+                        None,
+                    )?;
+                    if !keys.is_empty() {
+                        return Err(RepoDownloadError::SignatureMissing("index.json".into()));
+                    }
+                    log::warn!(
+                        "Repo {} uses the legacy JSON format and should be migrated with pahkat-repomgr",
+                        &url
+                    );
+
+                    let index = legacy.bytes().await?;
+                    let packages = get("packages/index.json")
+                        .send()
+                        .await?
+                        .error_for_status()?
+                        .bytes()
+                        .await?;
+                    let (info, packages) = super::legacy::convert(&url, &index, &packages)?;
+                    let repo = LoadedRepository::new(
+                        info,
+                        packages,
+                        LoadedRepositoryMeta {
+                            channel,
+                            last_update: Some(chrono::Utc::now()),
+                            prerelease_channels: Default::default(),
+                        },
+                    )?;
+                    return Ok((repo, vec![]));
+                }
             }
-            .await;
 
-            tx.send(result).unwrap();
-        });
+            // The index is read before the manifest is checked, as it advertises the
+            // key to check it with when none is configured
+            let data = body("index.toml", response).await?;
+            let info: pahkat_types::repo::Index = toml::from_slice(&data)?;
+            let keys = super::signature::trusted_keys(
+                &trust.keys,
+                trust.pinned_key.as_deref(),
+                info.repository.signing_key.as_deref(),
+            )?;
+            let signed = if keys.is_empty() {
+                None
+            } else {
+                let manifest = get_signed(Manifest::PATH.into()).await?;
+                let signature = get_signed(signature_path(Manifest::PATH)).await?;
+                let signature = String::from_utf8(signature)
+                    .map_err(|_| RepoDownloadError::InvalidSignature(Manifest::PATH.into()))?;
+                let parsed = verify_manifest(&keys, &url, &manifest, &signature, trust.serial)?;
+                Some((parsed, manifest, signature))
+            };
+            let manifest = signed.as_ref().map(|x| &x.0);
+            verify_file(manifest, "index.toml", &data)?;
+
+            let packages = body("packages/index.bin", get("packages/index.bin").send().await?)
+                .await?
+                .into_boxed_slice();
+            verify_file(manifest, "packages/index.bin", &packages)?;
+
+            let serial = manifest.map(|x| x.serial);
+            let (manifest, signature) = match signed {
+                Some((_, manifest, signature)) => (Some(manifest), Some(signature)),
+                None => (None, None),
+            };
+            let files = vec![
+                IndexFile::new("index.toml", Some(data)),
+                IndexFile::new("packages/index.bin", Some(packages.to_vec())),
+                IndexFile::new(Manifest::PATH, manifest),
+                IndexFile::signature(Manifest::PATH, signature),
+            ];
+
+            log::debug!(
+                "Repo {} indexed by {} {}",
+                &url,
+                &info.agent.name,
+                &info.agent.version
+            );
+
+            let mut repo = LoadedRepository::new(
+                info,
+                packages,
+                LoadedRepositoryMeta {
+                    channel,
+                    // hash_id: "".into(),
+                    last_update: Some(chrono::Utc::now()),
+                    prerelease_channels: Default::default(),
+                },
+            )?;
+            repo.serial = serial;
+
+            log::trace!("Loaded.");
+            Ok((repo, files))
+        })
+        .await
+    }
+
+    /// Loads a repository from a `file` URL, such as one just built with pahkat-repomgr.
+    /// The legacy JSON format is not supported here.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn from_path(
+        url: RepoUrl,
+        channel: Option<String>,
+        trust: RepoTrust,
+    ) -> Result<LoadedRepository, RepoDownloadError> {
+        let root = url
+            .to_file_path()
+            .map_err(|_| RepoDownloadError::LocalPath(url.to_string()))?;
+        Self::from_dir(&url, &root, channel, trust)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn from_dir(
+        url: &RepoUrl,
+        root: &Path,
+        channel: Option<String>,
+        trust: RepoTrust,
+    ) -> Result<LoadedRepository, RepoDownloadError> {
+        log::trace!("Loading repo from {}", root.display());
+        Self::from_files(url, |path| std::fs::read(root.join(path)), channel, trust)
+    }
+
+    /// Loads the repository at `url` from index files read by `read`, given their paths
+    /// relative to the root of the repository, verifying them like
+    /// [`LoadedRepository::from_url`].
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn from_files<F>(
+        url: &RepoUrl,
+        read: F,
+        channel: Option<String>,
+        trust: RepoTrust,
+    ) -> Result<LoadedRepository, RepoDownloadError>
+    where
+        F: Fn(&str) -> std::io::Result<Vec<u8>>,
+    {
+        let data = read("index.toml")?;
+        let info: pahkat_types::repo::Index = toml::from_slice(&data)?;
+        let keys = super::signature::trusted_keys(
+            &trust.keys,
+            trust.pinned_key.as_deref(),
+            info.repository.signing_key.as_deref(),
+        )?;
+        let manifest = if keys.is_empty() {
+            None
+        } else {
+            let missing = || RepoDownloadError::SignatureMissing(Manifest::PATH.into());
+            let manifest = read(Manifest::PATH).map_err(|_| missing())?;
+            let signature = read(&signature_path(Manifest::PATH))
+                .ok()
+                .and_then(|x| String::from_utf8(x).ok())
+                .ok_or_else(missing)?;
+            // A repository opened from a local path is signed for where it is published
+            let url = if url.is_local() {
+                &info.repository.url
+            } else {
+                url
+            };
+            Some(verify_manifest(
+                &keys,
+                url,
+                &manifest,
+                &signature,
+                trust.serial,
+            )?)
+        };
+        verify_file(manifest.as_ref(), "index.toml", &data)?;
+
+        let packages = read("packages/index.bin")?.into_boxed_slice();
+        verify_file(manifest.as_ref(), "packages/index.bin", &packages)?;
 
-        rx.await.unwrap()
+        let mut repo = LoadedRepository::new(
+            info,
+            packages,
+            LoadedRepositoryMeta {
+                channel,
+                last_update: Some(chrono::Utc::now()),
+                prerelease_channels: Default::default(),
+            },
+        )?;
+        repo.serial = manifest.map(|x| x.serial);
+        Ok(repo)
     }
 
     pub fn info(&self) -> &pahkat_types::repo::Index {
@@ -108,10 +450,43 @@ impl LoadedRepository {
         pahkat_fbs::Packages::get_root(&*self.packages).expect("packages must always exist")
     }
 
+    /// Decodes every package in the index. Packages that cannot be decoded are
+    /// logged and skipped.
+    pub fn descriptors(&self) -> Vec<Descriptor> {
+        use std::convert::TryFrom;
+
+        let packages = self.packages();
+        let packages = match packages.packages() {
+            Some(v) => v,
+            None => return vec![],
+        };
+
+        packages
+            .iter()
+            .filter_map(|(id, pkg)| match Descriptor::try_from(&pkg) {
+                Ok(v) => Some(v),
+                Err(e) => {
+                    log::warn!(
+                        "Skipping package `{}` in {}: {}",
+                        id,
+                        self.info.repository.url,
+                        e
+                    );
+                    None
+                }
+            })
+            .collect()
+    }
+
     pub fn meta(&self) -> &LoadedRepositoryMeta {
         &self.meta
     }
 
+    /// The serial of the verified manifest, if the repository is signed.
+    pub fn serial(&self) -> Option<u64> {
+        self.serial
+    }
+
     pub fn package_key(&self, descriptor: &pahkat_types::package::Descriptor) -> PackageKey {
         PackageKey::new_unchecked(
             self.info.repository.url.to_owned(),
@@ -120,3 +495,215 @@ impl LoadedRepository {
         )
     }
 }
+
+/// A file of a repository index, relative to its root. Files without `data` are not
+/// served by the repository, and are removed from the cache.
+pub(crate) struct IndexFile {
+    path: String,
+    data: Option<Vec<u8>>,
+}
+
+impl IndexFile {
+    fn new(path: &str, data: Option<Vec<u8>>) -> IndexFile {
+        IndexFile {
+            path: path.to_string(),
+            data,
+        }
+    }
+
+    fn signature(path: &str, signature: Option<String>) -> IndexFile {
+        IndexFile::new(&signature_path(path), signature.map(String::into_bytes))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write_cache(cache_dir: &Path, files: &[IndexFile]) -> std::io::Result<()> {
+    if files.is_empty() {
+        return Ok(());
+    }
+
+    for file in files {
+        let path = cache_dir.join(&file.path);
+        match &file.data {
+            Some(data) => {
+                std::fs::create_dir_all(path.parent().unwrap_or(cache_dir))?;
+                std::fs::write(&path, data)?;
+            }
+            None => match std::fs::remove_file(&path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            },
+        }
+    }
+
+    Ok(())
+}
+
+/// Index files of large repositories are several megabytes, so compressed responses
+/// are accepted. reqwest only decodes gzip by itself.
+#[cfg(not(target_arch = "wasm32"))]
+const ACCEPT_ENCODING: &str = "zstd, gzip";
+
+/// Stops a small compressed response from decompressing into an unbounded buffer.
+#[cfg(not(target_arch = "wasm32"))]
+const MAX_INDEX_SIZE: u64 = 256 * 1024 * 1024;
+
+#[cfg(not(target_arch = "wasm32"))]
+async fn body(path: &str, response: reqwest::Response) -> Result<Vec<u8>, RepoDownloadError> {
+    let is_zstd = response
+        .headers()
+        .get(reqwest::header::CONTENT_ENCODING)
+        .map(|x| x.as_bytes().eq_ignore_ascii_case(b"zstd"))
+        .unwrap_or(false);
+    let bytes = response.bytes().await?;
+
+    if is_zstd {
+        decompress(path, &bytes, MAX_INDEX_SIZE)
+    } else {
+        Ok(bytes.to_vec())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn decompress(path: &str, bytes: &[u8], limit: u64) -> Result<Vec<u8>, RepoDownloadError> {
+    use std::io::Read;
+
+    let decoder = zstd::stream::Decoder::new(bytes)
+        .map_err(|e| RepoDownloadError::Decompress(path.into(), e))?;
+    let mut data = vec![];
+    // One byte past the limit tells an index of exactly that size from a larger one
+    decoder
+        .take(limit + 1)
+        .read_to_end(&mut data)
+        .map_err(|e| RepoDownloadError::Decompress(path.into(), e))?;
+
+    if data.len() as u64 > limit {
+        return Err(RepoDownloadError::IndexTooLarge(path.into(), limit));
+    }
+    Ok(data)
+}
+
+// The browser negotiates and decodes compressed responses.
+#[cfg(target_arch = "wasm32")]
+async fn body(_path: &str, response: reqwest::Response) -> Result<Vec<u8>, RepoDownloadError> {
+    Ok(response.bytes().await?.to_vec())
+}
+
+/// Features of this client a repository may want to know about, sent with every refresh.
+const CAPABILITIES: &[&str] = &["package-sets", "signed-manifest"];
+
+/// Lets repository maintainers tell which clients are fetching their indexes.
+#[cfg(not(target_arch = "wasm32"))]
+fn agent_headers() -> reqwest::header::HeaderMap {
+    use reqwest::header::{HeaderMap, HeaderValue};
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "Pahkat-Agent-Name",
+        HeaderValue::from_static(env!("CARGO_PKG_NAME")),
+    );
+    headers.insert(
+        "Pahkat-Agent-Version",
+        HeaderValue::from_static(env!("GIT_VERSION")),
+    );
+    headers.insert(
+        "Pahkat-Agent-OS",
+        HeaderValue::from_static(std::env::consts::OS),
+    );
+    headers.insert(
+        "Pahkat-Agent-Capabilities",
+        HeaderValue::from_str(&CAPABILITIES.join(",")).expect("capabilities are valid header text"),
+    );
+    headers
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn client(
+    user_agent: &str,
+    proxy: Option<&Url>,
+    client_certificate: Option<&ClientCertificate>,
+) -> Result<reqwest::Client, RepoDownloadError> {
+    let builder =
+        crate::tls::apply_client_certificate(crate::tls::client_builder(), client_certificate)?;
+    Ok(crate::proxy::apply(builder, proxy)?
+        .user_agent(user_agent)
+        .default_headers(agent_headers())
+        .referer(false)
+        .redirect(reqwest::redirect::Policy::none())
+        .build()?)
+}
+
+// The browser owns the user agent, referer and redirect handling.
+#[cfg(target_arch = "wasm32")]
+fn client(
+    _user_agent: &str,
+    _proxy: Option<&Url>,
+    _client_certificate: Option<&ClientCertificate>,
+) -> Result<reqwest::Client, RepoDownloadError> {
+    Ok(reqwest::Client::builder().build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INDEX: &str = r#"
+[repository]
+url = "https://127.0.0.1:9/repo/"
+
+[agent]
+name = "pahkat"
+version = "2.3.0"
+"#;
+
+    #[test]
+    fn loads_cached_index_when_unreachable() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        let url: RepoUrl = "https://127.0.0.1:9/repo/".parse().unwrap();
+        let load = |cache_dir: PathBuf| {
+            runtime.block_on(LoadedRepository::from_cache_or_url(
+                url.clone(),
+                None,
+                None,
+                Default::default(),
+                None,
+                None,
+                cache_dir,
+            ))
+        };
+
+        assert!(matches!(
+            load(cache.path().to_path_buf()),
+            Err(RepoDownloadError::ReqwestError(_))
+        ));
+
+        let packages = pahkat_types::index_writer::encode_index(&[]).unwrap();
+        write_cache(
+            cache.path(),
+            &[
+                IndexFile::new("index.toml", Some(INDEX.as_bytes().to_vec())),
+                IndexFile::new("packages/index.bin", Some(packages)),
+                IndexFile::signature(Manifest::PATH, None),
+            ],
+        )
+        .unwrap();
+
+        let repo = load(cache.path().to_path_buf()).unwrap();
+        assert_eq!(repo.info().repository.url, url);
+        assert!(repo.meta().last_update.is_some());
+    }
+
+    #[test]
+    fn limits_decompressed_index_size() {
+        let bytes = zstd::stream::encode_all(&[0u8; 1024][..], 0).unwrap();
+        assert_eq!(decompress("index.toml", &bytes, 1024).unwrap().len(), 1024);
+        assert!(matches!(
+            decompress("index.toml", &bytes, 1023),
+            Err(RepoDownloadError::IndexTooLarge(_, 1023))
+        ));
+    }
+}
@@ -0,0 +1,233 @@
+//! Verification of signed repository indexes.
+//!
+//! A signed repository serves a `manifest.toml` listing the hashes of its index files
+//! and a serial, and `manifest.toml.sig` next to it, holding a base64 ed25519
+//! signature of the manifest's bytes.
+
+use std::convert::TryFrom;
+
+use ed25519_dalek::{PublicKey, Signature, Verifier};
+use pahkat_types::repo::{Manifest, RepoUrl};
+
+use super::RepoDownloadError;
+
+/// The path of the signature of the file at `path`.
+pub(crate) fn signature_path(path: &str) -> String {
+    format!("{}.sig", path)
+}
+
+/// The keys the indexes of a repository must be signed by, if any. Keys configured
+/// for the repository are used as they are. Otherwise the key the index advertises
+/// is trusted if it is the key `pinned` on first use, or if none was pinned yet.
+pub(crate) fn trusted_keys(
+    configured: &[String],
+    pinned: Option<&str>,
+    advertised: Option<&str>,
+) -> Result<Vec<String>, RepoDownloadError> {
+    if !configured.is_empty() {
+        return Ok(configured.to_vec());
+    }
+
+    match (pinned, advertised) {
+        (Some(pinned), Some(advertised)) if pinned.trim() == advertised.trim() => {
+            Ok(vec![pinned.to_string()])
+        }
+        (Some(_), advertised) => Err(RepoDownloadError::KeyChanged(
+            advertised.map(str::to_string),
+        )),
+        (None, Some(advertised)) => Ok(vec![advertised.to_string()]),
+        (None, None) => Ok(vec![]),
+    }
+}
+
+/// Whether `key` is a base64 encoded ed25519 public key.
+pub(crate) fn is_public_key(key: &str) -> bool {
+    base64::decode(key.trim())
+        .ok()
+        .and_then(|x| PublicKey::from_bytes(&x).ok())
+        .is_some()
+}
+
+/// Succeeds if `signature` is a signature of `data` by any of the trusted keys.
+pub(crate) fn verify(
+    trusted_keys: &[String],
+    path: &str,
+    data: &[u8],
+    signature: &str,
+) -> Result<(), RepoDownloadError> {
+    let signature = base64::decode(signature.trim())
+        .ok()
+        .and_then(|x| Signature::try_from(&*x).ok())
+        .ok_or_else(|| RepoDownloadError::InvalidSignature(path.to_string()))?;
+
+    for key in trusted_keys {
+        let key = base64::decode(key.trim())
+            .ok()
+            .and_then(|x| PublicKey::from_bytes(&x).ok())
+            .ok_or_else(|| RepoDownloadError::InvalidTrustedKey(key.to_string()))?;
+
+        if key.verify(data, &signature).is_ok() {
+            return Ok(());
+        }
+    }
+
+    Err(RepoDownloadError::InvalidSignature(path.to_string()))
+}
+
+/// Verifies the signature of the manifest in `data`, that it was signed for the
+/// repository at `url`, and that it is not older than the manifest with `serial`, if
+/// one was seen before.
+pub(crate) fn verify_manifest(
+    trusted_keys: &[String],
+    url: &RepoUrl,
+    data: &[u8],
+    signature: &str,
+    serial: Option<u64>,
+) -> Result<Manifest, RepoDownloadError> {
+    verify(trusted_keys, Manifest::PATH, data, signature)?;
+    let manifest: Manifest = toml::from_slice(data)?;
+    if &manifest.url != url {
+        return Err(RepoDownloadError::ManifestUrl(manifest.url.to_string()));
+    }
+
+    match serial {
+        Some(serial) if manifest.serial < serial => {
+            Err(RepoDownloadError::Rollback(manifest.serial, serial))
+        }
+        _ => Ok(manifest),
+    }
+}
+
+/// Succeeds if `data` is the file at `path` listed in `manifest`, or if the repository
+/// is not signed.
+pub(crate) fn verify_file(
+    manifest: Option<&Manifest>,
+    path: &str,
+    data: &[u8],
+) -> Result<(), RepoDownloadError> {
+    match manifest {
+        Some(manifest) if !manifest.contains(path, data) => {
+            Err(RepoDownloadError::ManifestMismatch(path.to_string()))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::{ExpandedSecretKey, SecretKey};
+
+    use super::*;
+
+    fn keypair(seed: u8) -> (String, ExpandedSecretKey, PublicKey) {
+        let secret = SecretKey::from_bytes(&[seed; 32]).unwrap();
+        let public = PublicKey::from(&secret);
+        (
+            base64::encode(public.as_bytes()),
+            ExpandedSecretKey::from(&secret),
+            public,
+        )
+    }
+
+    fn sign(secret: &ExpandedSecretKey, public: &PublicKey, data: &[u8]) -> String {
+        base64::encode(secret.sign(data, public).to_bytes())
+    }
+
+    #[test]
+    fn accepts_any_trusted_key() {
+        let (old_key, _, _) = keypair(1);
+        let (new_key, secret, public) = keypair(2);
+        let signature = sign(&secret, &public, b"index");
+
+        assert!(verify(&[old_key, new_key], "index.toml", b"index", &signature).is_ok());
+    }
+
+    #[test]
+    fn trusts_first_key_until_it_changes() {
+        let (configured, _, _) = keypair(1);
+        let (first, _, _) = keypair(2);
+        let (second, _, _) = keypair(3);
+
+        assert_eq!(trusted_keys(&[], None, None).unwrap(), Vec::<String>::new());
+        assert_eq!(
+            trusted_keys(&[], None, Some(&first)).unwrap(),
+            vec![first.clone()]
+        );
+        assert_eq!(
+            trusted_keys(&[], Some(&first), Some(&first)).unwrap(),
+            vec![first.clone()]
+        );
+        assert!(matches!(
+            trusted_keys(&[], Some(&first), Some(&second)),
+            Err(RepoDownloadError::KeyChanged(Some(x))) if x == second
+        ));
+        assert!(matches!(
+            trusted_keys(&[], Some(&first), None),
+            Err(RepoDownloadError::KeyChanged(None))
+        ));
+        assert_eq!(
+            trusted_keys(&[configured.clone()], Some(&first), Some(&second)).unwrap(),
+            vec![configured]
+        );
+    }
+
+    #[test]
+    fn rejects_tampered_data_and_untrusted_keys() {
+        let (key, secret, public) = keypair(1);
+        let (other_key, _, _) = keypair(2);
+        let signature = sign(&secret, &public, b"index");
+
+        assert!(matches!(
+            verify(&[key], "index.toml", b"tampered", &signature),
+            Err(RepoDownloadError::InvalidSignature(_))
+        ));
+        assert!(matches!(
+            verify(&[other_key], "index.toml", b"index", &signature),
+            Err(RepoDownloadError::InvalidSignature(_))
+        ));
+        assert!(matches!(
+            verify(&["not a key".into()], "index.toml", b"index", &signature),
+            Err(RepoDownloadError::InvalidTrustedKey(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_older_manifests_and_unlisted_files() {
+        let (key, secret, public) = keypair(1);
+        let url: RepoUrl = "https://pahkat.example/repo/".parse().unwrap();
+        let manifest = Manifest::new(url.clone(), 5, [("index.toml", &b"index"[..])]);
+        let data = toml::to_vec(&manifest).unwrap();
+        let signature = sign(&secret, &public, &data);
+        let keys = [key];
+
+        for serial in [None, Some(4), Some(5)] {
+            assert_eq!(
+                verify_manifest(&keys, &url, &data, &signature, serial).unwrap(),
+                manifest
+            );
+        }
+        assert!(matches!(
+            verify_manifest(&keys, &url, &data, &signature, Some(6)),
+            Err(RepoDownloadError::Rollback(5, 6))
+        ));
+
+        // The same key may sign several repositories
+        let other: RepoUrl = "https://pahkat.example/other/".parse().unwrap();
+        assert!(matches!(
+            verify_manifest(&keys, &other, &data, &signature, None),
+            Err(RepoDownloadError::ManifestUrl(x)) if x == url.to_string()
+        ));
+
+        assert!(verify_file(Some(&manifest), "index.toml", b"index").is_ok());
+        assert!(verify_file(None, "index.toml", b"tampered").is_ok());
+        for (path, data) in [
+            ("index.toml", &b"tampered"[..]),
+            ("packages/index.bin", b""),
+        ] {
+            assert!(matches!(
+                verify_file(Some(&manifest), path, data),
+                Err(RepoDownloadError::ManifestMismatch(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,112 @@
+//! Figures about a loaded repository, taken from its index and the local caches
+//! without any network requests.
+
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use hashbrown::{HashMap, HashSet};
+use serde::Serialize;
+
+use pahkat_types::repo::RepoUrl;
+use pahkat_types::AsDownloadUrl;
+
+use super::{LoadedRepository, ReleaseQuery};
+use crate::config::Config;
+
+#[derive(Debug, Clone, Serialize)]
+#[non_exhaustive]
+pub struct RepoStatistics {
+    pub url: String,
+    pub package_count: usize,
+    /// Channels the repository declares or has releases in.
+    pub channels: Vec<String>,
+    /// Download size of the release of every package that would be installed on
+    /// this platform.
+    pub payload_size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_refresh: Option<DateTime<Utc>>,
+    /// Payloads of any release of the repository's packages in the download cache,
+    /// along with the cached index.
+    pub cache_size: u64,
+    /// Payload downloads that failed, per host.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub payload_failures: Vec<super::HostFailures>,
+}
+
+impl RepoStatistics {
+    pub(crate) fn new(
+        config: &Config,
+        url: &RepoUrl,
+        repos: &HashMap<RepoUrl, LoadedRepository>,
+    ) -> Option<RepoStatistics> {
+        let repo = repos.get(url)?;
+        let descriptors = repo.descriptors();
+
+        let mut channels = repo
+            .info()
+            .repository
+            .channels
+            .iter()
+            .cloned()
+            .collect::<BTreeSet<_>>();
+        let mut payload_size = 0;
+        let mut cached_payloads = HashSet::new();
+
+        for descriptor in descriptors.iter() {
+            let key = repo.package_key(descriptor);
+            let query = ReleaseQuery::new(&key, repos);
+            if let Some(x) = query.iter(descriptor).next() {
+                payload_size += x.target.payload.size();
+            }
+
+            for release in descriptor.release.iter() {
+                if let Some(channel) = release.channel.as_ref() {
+                    channels.insert(channel.clone());
+                }
+                for target in release.target.iter() {
+                    cached_payloads.insert(super::download_file_path(
+                        config,
+                        target.payload.as_download_url(),
+                    ));
+                }
+            }
+        }
+
+        let cache_size = cached_payloads
+            .iter()
+            .filter_map(|x| std::fs::metadata(x).ok())
+            .map(|x| x.len())
+            .sum::<u64>()
+            + dir_size(&super::repo_cache_path(config, url));
+        let payload_failures = super::url_health::load(&super::url_health::path(
+            &super::repo_cache_path(config, url),
+        ));
+
+        Some(RepoStatistics {
+            url: url.to_string(),
+            package_count: descriptors.len(),
+            channels: channels.into_iter().collect(),
+            payload_size,
+            last_refresh: repo.meta().last_update,
+            cache_size,
+            payload_failures,
+        })
+    }
+}
+
+fn dir_size(path: &Path) -> u64 {
+    let entries = match std::fs::read_dir(path) {
+        Ok(v) => v,
+        Err(_) => return 0,
+    };
+
+    entries
+        .filter_map(Result::ok)
+        .map(|entry| match entry.metadata() {
+            Ok(x) if x.is_dir() => dir_size(&entry.path()),
+            Ok(x) => x.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
@@ -0,0 +1,129 @@
+//! Payload URL failures seen while downloading, kept with the repository's cache and
+//! aggregated per host, so that dead artifacts show up in the repository's statistics.
+
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::download::DownloadError;
+
+const FILE_NAME: &str = "payload-failures.json";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HostFailures {
+    /// The host that answered, after following any redirects.
+    pub host: String,
+    pub count: u64,
+    pub last_url: String,
+    /// Such as `HTTP 404 Not Found`, or the TLS or connection error.
+    pub last_error: String,
+    pub last_seen: DateTime<Utc>,
+}
+
+pub(crate) fn path(repo_cache_path: &Path) -> PathBuf {
+    repo_cache_path.join(FILE_NAME)
+}
+
+/// Failures are ordered by host.
+pub(crate) fn load(path: &Path) -> Vec<HostFailures> {
+    std::fs::read(path)
+        .ok()
+        .and_then(|x| serde_json::from_slice(&x).ok())
+        .unwrap_or_default()
+}
+
+/// Records `error` if it was caused by the server or the connection to it, rather
+/// than by this client, such as a failure to write the file.
+pub(crate) fn record(path: &Path, url: &Url, error: &DownloadError) {
+    let (url, message) = match error {
+        DownloadError::ReqwestError(e, _) => (e.url().unwrap_or(url), describe(e)),
+        DownloadError::Incomplete(..) => (url, error.to_string()),
+        _ => return,
+    };
+    let host = url.host_str().unwrap_or_default().to_string();
+
+    let mut failures = load(path);
+    let index = match failures.binary_search_by(|x| x.host.cmp(&host)) {
+        Ok(v) => v,
+        Err(v) => {
+            failures.insert(
+                v,
+                HostFailures {
+                    host,
+                    count: 0,
+                    last_url: String::new(),
+                    last_error: String::new(),
+                    last_seen: Utc::now(),
+                },
+            );
+            v
+        }
+    };
+
+    let entry = &mut failures[index];
+    entry.count += 1;
+    entry.last_url = url.to_string();
+    entry.last_error = message;
+    entry.last_seen = Utc::now();
+
+    let result = path
+        .parent()
+        .map(std::fs::create_dir_all)
+        .unwrap_or(Ok(()))
+        .and_then(|_| std::fs::write(path, serde_json::to_vec(&failures)?));
+    if let Err(e) = result {
+        log::warn!(
+            "Could not record payload failure in {}: {}",
+            path.display(),
+            e
+        );
+    }
+}
+
+fn describe(error: &reqwest::Error) -> String {
+    if let Some(status) = error.status() {
+        return format!("HTTP {}", status);
+    }
+
+    // TLS and connection errors are only described by the innermost source
+    let mut message = error.to_string();
+    let mut source = std::error::Error::source(error);
+    while let Some(e) = source {
+        message.push_str(": ");
+        message.push_str(&e.to_string());
+        source = e.source();
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aggregates_per_host() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = path(dir.path());
+        let url = Url::parse("https://cdn.example/a.exe").unwrap();
+
+        record(&path, &url, &DownloadError::Incomplete(1, 2));
+        record(&path, &url, &DownloadError::Incomplete(1, 3));
+        record(&path, &url, &DownloadError::InvalidUrl);
+        record(
+            &path,
+            &Url::parse("https://alt.example/a.exe").unwrap(),
+            &DownloadError::Incomplete(1, 2),
+        );
+
+        let failures = load(&path);
+        assert_eq!(
+            failures
+                .iter()
+                .map(|x| (&*x.host, x.count))
+                .collect::<Vec<_>>(),
+            vec![("alt.example", 1), ("cdn.example", 2)]
+        );
+    }
+}
@@ -0,0 +1,162 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use url::Url;
+
+use crate::package_store::{InstallTarget, PackageStore};
+use crate::transaction::PackageStatus;
+use crate::types::repo::Agent;
+use crate::PackageKey;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SubmitError {
+    #[error("Could not serialize report")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Could not submit report")]
+    Http(#[from] reqwest::Error),
+}
+
+/// A snapshot of the update compliance of this machine, for fleet dashboards.
+#[derive(Debug, Clone, Serialize)]
+#[non_exhaustive]
+pub struct ComplianceReport {
+    pub generated_at: DateTime<Utc>,
+    /// Not set if the hostname could not be determined.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    pub client_version: String,
+    pub repos: Vec<RepoReport>,
+    pub packages: Vec<PackageReport>,
+    /// Only known when the report is generated by the daemon.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub requires_reboot: Option<bool>,
+    /// Only known when the report is generated by the daemon.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_update_run: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[non_exhaustive]
+pub struct RepoReport {
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_refresh: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_age_secs: Option<i64>,
+    /// The tool that generated the repository's index, once it has been loaded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub agent: Option<Agent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[non_exhaustive]
+pub struct PackageReport {
+    pub key: String,
+    pub status: String,
+    pub update_available: bool,
+}
+
+impl ComplianceReport {
+    /// Collects repository and package state from the store. Only installed
+    /// packages are listed.
+    pub fn generate(store: &dyn PackageStore, target: InstallTarget) -> ComplianceReport {
+        let now = Utc::now();
+
+        let mut repos = {
+            let loaded = store.repos();
+            let loaded = loaded.read().unwrap();
+            let errors = store.errors();
+            let errors = errors.read().unwrap();
+            let config = store.config();
+            let config = config.read().unwrap();
+
+            config
+                .repos()
+                .iter()
+                .map(|(url, record)| {
+                    let repo = loaded.get(url);
+                    let last_refresh = repo.and_then(|x| x.meta().last_update);
+                    RepoReport {
+                        url: url.to_string(),
+                        channel: record.channel.clone(),
+                        last_refresh,
+                        refresh_age_secs: last_refresh.map(|x| (now - x).num_seconds()),
+                        agent: repo.map(|x| x.info.agent.clone()),
+                        error: errors.get(url).map(|e| e.to_string()),
+                    }
+                })
+                .collect::<Vec<_>>()
+        };
+        repos.sort_by(|a, b| a.url.cmp(&b.url));
+
+        let repo_urls = {
+            let loaded = store.repos();
+            let loaded = loaded.read().unwrap();
+            loaded.keys().cloned().collect::<Vec<_>>()
+        };
+
+        let mut packages = vec![];
+        for repo_url in repo_urls {
+            for (id, status) in store.all_statuses(&repo_url, target) {
+                if let Ok(PackageStatus::NotInstalled) = status {
+                    continue;
+                }
+
+                let key = PackageKey::new_unchecked(repo_url.clone(), id, None);
+                packages.push(PackageReport {
+                    key: key.to_string(),
+                    update_available: matches!(status, Ok(PackageStatus::RequiresUpdate)),
+                    status: match status {
+                        Ok(x) => format!("{:?}", x),
+                        Err(e) => e.to_string(),
+                    },
+                });
+            }
+        }
+        packages.sort_by(|a, b| a.key.cmp(&b.key));
+
+        ComplianceReport {
+            generated_at: now,
+            hostname: match whoami::fallible::hostname() {
+                Ok(v) => Some(v),
+                Err(e) => {
+                    log::warn!("Could not determine hostname: {}", e);
+                    None
+                }
+            },
+            client_version: crate::defaults::client_version().to_string(),
+            repos,
+            packages,
+            requires_reboot: None,
+            last_update_run: None,
+        }
+    }
+
+    pub fn with_daemon_state(
+        mut self,
+        requires_reboot: bool,
+        last_update_run: Option<DateTime<Utc>>,
+    ) -> ComplianceReport {
+        self.requires_reboot = Some(requires_reboot);
+        self.last_update_run = last_update_run;
+        self
+    }
+
+    /// POSTs the report as JSON to the given URL, through `proxy` if one is configured.
+    pub async fn submit(&self, url: &Url, proxy: Option<&Url>) -> Result<(), SubmitError> {
+        let body = serde_json::to_vec(self)?;
+        crate::proxy::apply(crate::tls::client_builder(), proxy)
+            .and_then(|x| x.build())?
+            .post(url.clone())
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
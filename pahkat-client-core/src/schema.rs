@@ -0,0 +1,47 @@
+//! JSON Schema for the JSON that frontends exchange with Pahkat, such as the query
+//! taken and the result returned by `resolve_package_query`, so that they can check
+//! their integration against it.
+
+use std::collections::BTreeMap;
+
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::schema::RootSchema;
+use schemars::JsonSchema;
+
+use crate::repo::PackageQuery;
+use crate::transaction::{PackageAction, ResolvedPackageQuery};
+
+/// The schema of `T`, in the draft 7 form most validators support.
+pub fn schema_for<T: JsonSchema>() -> RootSchema {
+    SchemaGenerator::new(SchemaSettings::draft07()).into_root_schema_for::<T>()
+}
+
+/// The schemas of the JSON documents defined by this crate, by name.
+pub fn schemas() -> BTreeMap<&'static str, RootSchema> {
+    let mut map = BTreeMap::new();
+    map.insert("PackageQuery", schema_for::<PackageQuery>());
+    map.insert("ResolvedPackageQuery", schema_for::<ResolvedPackageQuery>());
+    map.insert("PackageAction", schema_for::<PackageAction>());
+    map
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolved_query_defines_payloads() {
+        let schema = serde_json::to_value(schema_for::<ResolvedPackageQuery>()).unwrap();
+        let definitions = schema["definitions"].as_object().unwrap();
+        let payloads = definitions["Payload"]["oneOf"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|x| x["properties"]["type"]["enum"][0].as_str().unwrap())
+            .collect::<Vec<_>>();
+        for name in ["TarballPackage", "DebianPackage", "RpmPackage"] {
+            assert!(payloads.contains(&name), "{} is not defined", name);
+        }
+        assert_eq!(definitions["PackageKey"]["format"], "uri");
+    }
+}
@@ -0,0 +1,269 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// A reference to a secret held by the platform credential store.
+///
+/// Only the handle is written to the TOML config; the secret itself is kept in
+/// Windows Credential Manager, the macOS Keychain or the Secret Service on Linux,
+/// or in a file only its owner can read where none of those can be reached.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct SecretHandle(String);
+
+impl SecretHandle {
+    pub fn new<S: Into<String>>(name: S) -> SecretHandle {
+        SecretHandle(name.into())
+    }
+
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for SecretHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SecretError {
+    #[error("No secret found for handle `{0}`")]
+    NotFound(SecretHandle),
+
+    #[error("Secret storage is not supported on this platform")]
+    Unsupported,
+
+    #[error("Secret storage backend failed: {0}")]
+    Backend(String),
+}
+
+pub trait SecretStore: Send + Sync {
+    fn get(&self, handle: &SecretHandle) -> Result<String, SecretError>;
+    fn set(&self, handle: &SecretHandle, secret: &str) -> Result<(), SecretError>;
+    fn delete(&self, handle: &SecretHandle) -> Result<(), SecretError>;
+}
+
+/// Secret store backed by the operating system keyring.
+#[derive(Debug, Clone)]
+pub struct KeyringSecretStore {
+    service: String,
+}
+
+impl KeyringSecretStore {
+    pub fn new<S: Into<String>>(service: S) -> KeyringSecretStore {
+        KeyringSecretStore {
+            service: service.into(),
+        }
+    }
+}
+
+impl Default for KeyringSecretStore {
+    fn default() -> Self {
+        KeyringSecretStore::new("pahkat")
+    }
+}
+
+#[cfg(any(windows, target_os = "macos", target_os = "linux"))]
+impl KeyringSecretStore {
+    fn entry(&self, handle: &SecretHandle) -> Result<keyring::Entry, SecretError> {
+        keyring::Entry::new(&self.service, handle.name())
+            .map_err(|e| SecretError::Backend(e.to_string()))
+    }
+}
+
+#[cfg(any(windows, target_os = "macos", target_os = "linux"))]
+impl SecretStore for KeyringSecretStore {
+    fn get(&self, handle: &SecretHandle) -> Result<String, SecretError> {
+        match self.entry(handle)?.get_password() {
+            Ok(v) => Ok(v),
+            Err(keyring::Error::NoEntry) => Err(SecretError::NotFound(handle.clone())),
+            Err(e) => Err(SecretError::Backend(e.to_string())),
+        }
+    }
+
+    fn set(&self, handle: &SecretHandle, secret: &str) -> Result<(), SecretError> {
+        self.entry(handle)?
+            .set_password(secret)
+            .map_err(|e| SecretError::Backend(e.to_string()))
+    }
+
+    fn delete(&self, handle: &SecretHandle) -> Result<(), SecretError> {
+        match self.entry(handle)?.delete_password() {
+            Ok(_) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(SecretError::Backend(e.to_string())),
+        }
+    }
+}
+
+#[cfg(not(any(windows, target_os = "macos", target_os = "linux")))]
+impl SecretStore for KeyringSecretStore {
+    fn get(&self, _handle: &SecretHandle) -> Result<String, SecretError> {
+        Err(SecretError::Unsupported)
+    }
+
+    fn set(&self, _handle: &SecretHandle, _secret: &str) -> Result<(), SecretError> {
+        Err(SecretError::Unsupported)
+    }
+
+    fn delete(&self, _handle: &SecretHandle) -> Result<(), SecretError> {
+        Err(SecretError::Unsupported)
+    }
+}
+
+/// Secret store keeping each secret in its own file, readable only by the owner.
+///
+/// Daemons running as system services often cannot reach a keyring, such as on
+/// Linux where the Secret Service belongs to a user session.
+#[derive(Debug, Clone)]
+pub struct FileSecretStore {
+    dir: PathBuf,
+}
+
+impl FileSecretStore {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> FileSecretStore {
+        FileSecretStore { dir: dir.into() }
+    }
+
+    /// Handles hold URLs, so files are named by the hash of the handle.
+    fn path(&self, handle: &SecretHandle) -> PathBuf {
+        let mut sha = Sha256::new();
+        sha.update(handle.name().as_bytes());
+        self.dir.join(format!("{:x}", sha.finalize()))
+    }
+}
+
+fn backend_error(e: std::io::Error, path: &Path) -> SecretError {
+    SecretError::Backend(format!("{}: {}", path.display(), e))
+}
+
+impl SecretStore for FileSecretStore {
+    fn get(&self, handle: &SecretHandle) -> Result<String, SecretError> {
+        let path = self.path(handle);
+        match std::fs::read_to_string(&path) {
+            Ok(v) => Ok(v),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                Err(SecretError::NotFound(handle.clone()))
+            }
+            Err(e) => Err(backend_error(e, &path)),
+        }
+    }
+
+    fn set(&self, handle: &SecretHandle, secret: &str) -> Result<(), SecretError> {
+        use std::io::Write;
+
+        let mut builder = std::fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        builder
+            .create(&self.dir)
+            .map_err(|e| backend_error(e, &self.dir))?;
+
+        let path = self.path(handle);
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&path).map_err(|e| backend_error(e, &path))?;
+
+        // The mode only applies to new files
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(std::fs::Permissions::from_mode(0o600))
+                .map_err(|e| backend_error(e, &path))?;
+        }
+
+        file.write_all(secret.as_bytes())
+            .map_err(|e| backend_error(e, &path))
+    }
+
+    fn delete(&self, handle: &SecretHandle) -> Result<(), SecretError> {
+        let path = self.path(handle);
+        match std::fs::remove_file(&path) {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(backend_error(e, &path)),
+        }
+    }
+}
+
+/// The operating system keyring, falling back to files where it cannot be reached.
+#[derive(Debug, Clone)]
+pub struct DefaultSecretStore {
+    keyring: KeyringSecretStore,
+    files: FileSecretStore,
+}
+
+impl DefaultSecretStore {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> DefaultSecretStore {
+        DefaultSecretStore {
+            keyring: KeyringSecretStore::default(),
+            files: FileSecretStore::new(dir),
+        }
+    }
+}
+
+impl SecretStore for DefaultSecretStore {
+    fn get(&self, handle: &SecretHandle) -> Result<String, SecretError> {
+        match self.keyring.get(handle) {
+            Ok(v) => Ok(v),
+            Err(SecretError::NotFound(_)) => self.files.get(handle),
+            Err(e) => {
+                log::debug!("Reading {} from a file: {}", handle, e);
+                self.files.get(handle)
+            }
+        }
+    }
+
+    fn set(&self, handle: &SecretHandle, secret: &str) -> Result<(), SecretError> {
+        match self.keyring.set(handle, secret) {
+            // A copy from when the keyring could not be reached is stale now
+            Ok(_) => self.files.delete(handle),
+            Err(e) => {
+                log::warn!("Storing {} in a file: {}", handle, e);
+                self.files.set(handle, secret)
+            }
+        }
+    }
+
+    fn delete(&self, handle: &SecretHandle) -> Result<(), SecretError> {
+        if let Err(e) = self.keyring.delete(handle) {
+            log::debug!("Could not delete {} from the keyring: {}", handle, e);
+        }
+        self.files.delete(handle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_store_is_owner_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileSecretStore::new(dir.path().join("secrets"));
+        let handle = SecretHandle::new("repo-auth-token:https://pahkat.example/main/");
+
+        assert!(matches!(store.get(&handle), Err(SecretError::NotFound(_))));
+        store.set(&handle, "hunter2").unwrap();
+        assert_eq!(store.get(&handle).unwrap(), "hunter2");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(store.path(&handle))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        store.delete(&handle).unwrap();
+        assert!(matches!(store.get(&handle), Err(SecretError::NotFound(_))));
+    }
+}
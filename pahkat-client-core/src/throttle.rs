@@ -0,0 +1,120 @@
+//! Suppression of identical errors repeated by background work, such as a
+//! repository that fails on every refresh.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::Mutex;
+
+/// Logs an error the first time it occurs for a source, then only after 2, 4, 8…
+/// occurrences. Suppressed repeats are summarised once the error changes or the
+/// source recovers.
+#[derive(Debug, Default)]
+pub struct ErrorThrottle {
+    seen: Mutex<HashMap<String, Repeats>>,
+}
+
+#[derive(Debug)]
+struct Repeats {
+    message: String,
+    count: u64,
+    suppressed: u64,
+}
+
+impl ErrorThrottle {
+    pub fn error(&self, source: &str, message: impl Display) {
+        for line in self.record(source, message.to_string()) {
+            log::error!("{}", line);
+        }
+    }
+
+    pub fn success(&self, source: &str) {
+        if let Some(line) = self.clear(source) {
+            log::info!("{}", line);
+        }
+    }
+
+    fn record(&self, source: &str, message: String) -> Vec<String> {
+        let mut seen = self.seen.lock().unwrap();
+        let mut lines = vec![];
+
+        let is_repeat = seen
+            .get(source)
+            .map(|x| x.message == message)
+            .unwrap_or(false);
+
+        if is_repeat {
+            let repeats = seen.get_mut(source).unwrap();
+            repeats.count += 1;
+            if repeats.count.is_power_of_two() {
+                lines.push(format!(
+                    "{}: {} (occurred {} times)",
+                    source, message, repeats.count
+                ));
+                repeats.suppressed = 0;
+            } else {
+                repeats.suppressed += 1;
+            }
+            return lines;
+        }
+
+        if let Some(previous) = seen.remove(source) {
+            if previous.suppressed > 0 {
+                lines.push(summary(source, previous.suppressed));
+            }
+        }
+        lines.push(format!("{}: {}", source, message));
+        seen.insert(
+            source.to_string(),
+            Repeats {
+                message,
+                count: 1,
+                suppressed: 0,
+            },
+        );
+        lines
+    }
+
+    fn clear(&self, source: &str) -> Option<String> {
+        let previous = self.seen.lock().unwrap().remove(source)?;
+        Some(match previous.suppressed {
+            0 => format!("{}: recovered", source),
+            n => format!("{}, then recovered", summary(source, n)),
+        })
+    }
+}
+
+fn summary(source: &str, suppressed: u64) -> String {
+    format!("{}: last error repeated {} more times", source, suppressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suppresses_repeats_exponentially() {
+        let throttle = ErrorThrottle::default();
+        let logged = (0..12)
+            .map(|_| throttle.record("repo", "offline".into()).len())
+            .collect::<Vec<_>>();
+        assert_eq!(logged, [1, 1, 0, 1, 0, 0, 0, 1, 0, 0, 0, 0]);
+        assert_eq!(
+            throttle.clear("repo").unwrap(),
+            "repo: last error repeated 4 more times, then recovered"
+        );
+        assert_eq!(throttle.clear("repo"), None);
+    }
+
+    #[test]
+    fn new_error_summarises_previous() {
+        let throttle = ErrorThrottle::default();
+        for _ in 0..3 {
+            throttle.record("repo", "offline".into());
+        }
+        assert_eq!(
+            throttle.record("repo", "bad index".into()),
+            ["repo: last error repeated 1 more times", "repo: bad index"]
+        );
+        assert_eq!(throttle.record("other", "offline".into()).len(), 1);
+    }
+}
@@ -0,0 +1,81 @@
+//! Certificates trusted in addition to the built-in roots.
+
+#[cfg(all(feature = "test-roots", not(target_arch = "wasm32")))]
+static ROOT_CERTIFICATES: once_cell::sync::Lazy<std::sync::RwLock<Vec<reqwest::Certificate>>> =
+    once_cell::sync::Lazy::new(Default::default);
+
+/// Trusts a DER-encoded certificate for loading repositories and downloading
+/// payloads, so tests can serve repositories with a self-signed certificate.
+/// Only clients created after this call are affected, so call it before
+/// creating a package store.
+#[cfg(all(feature = "test-roots", not(target_arch = "wasm32")))]
+pub fn add_root_certificate(der: &[u8]) -> Result<(), reqwest::Error> {
+    let certificate = reqwest::Certificate::from_der(der)?;
+    ROOT_CERTIFICATES.write().unwrap().push(certificate);
+    Ok(())
+}
+
+#[cfg(all(feature = "test-roots", not(target_arch = "wasm32")))]
+pub(crate) fn client_builder() -> reqwest::ClientBuilder {
+    ROOT_CERTIFICATES
+        .read()
+        .unwrap()
+        .iter()
+        .fold(reqwest::Client::builder(), |builder, x| {
+            builder.add_root_certificate(x.clone())
+        })
+}
+
+#[cfg(not(all(feature = "test-roots", not(target_arch = "wasm32"))))]
+pub(crate) fn client_builder() -> reqwest::ClientBuilder {
+    reqwest::Client::builder()
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ClientCertificateError {
+    #[error("Could not read client certificate or key at path: {}", .1.display())]
+    Read(#[source] std::io::Error, std::path::PathBuf),
+
+    #[error("Client certificate or key is not valid PEM")]
+    Invalid(#[source] reqwest::Error),
+}
+
+/// Presents `certificate` to servers that ask for one, for repositories that require
+/// mutual TLS.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn apply_client_certificate(
+    builder: reqwest::ClientBuilder,
+    certificate: Option<&crate::config::ClientCertificate>,
+) -> Result<reqwest::ClientBuilder, ClientCertificateError> {
+    let certificate = match certificate {
+        Some(v) => v,
+        None => return Ok(builder),
+    };
+
+    let read = |path: &std::path::Path| {
+        std::fs::read(path).map_err(|e| ClientCertificateError::Read(e, path.to_path_buf()))
+    };
+
+    // The certificate and key are given to reqwest as a single PEM bundle.
+    let mut pem = read(&certificate.certificate)?;
+    if let Some(key) = certificate.key.as_deref() {
+        pem.push(b'\n');
+        pem.extend(read(key)?);
+    }
+
+    log::debug!(
+        "Using client certificate: {}",
+        certificate.certificate.display()
+    );
+    let identity = reqwest::Identity::from_pem(&pem).map_err(ClientCertificateError::Invalid)?;
+    Ok(builder.identity(identity))
+}
+
+// The browser owns client certificate selection.
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn apply_client_certificate(
+    builder: reqwest::ClientBuilder,
+    _certificate: Option<&crate::config::ClientCertificate>,
+) -> Result<reqwest::ClientBuilder, ClientCertificateError> {
+    Ok(builder)
+}
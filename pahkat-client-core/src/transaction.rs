@@ -1,21 +1,29 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use std::fmt;
 use std::sync::Arc;
 
 use serde::{Deserialize, Serialize};
 use url::Url;
 
-use crate::package_store::PackageStore;
+use crate::package_store::{AsyncPackageStore, DownloadEvent, PackageStore};
+use crate::priority::ProcessPriority;
 use pahkat_types::PackageKey;
 
 pub mod install;
+pub mod observer;
 pub mod uninstall;
 
+use self::observer::{Observers, TransactionObserver};
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum PackageStatus {
     NotInstalled,
     UpToDate,
     RequiresUpdate,
+    /// Installed, but no longer published by its repository. It can still be
+    /// uninstalled.
+    Unpublished,
 }
 
 use crate::repo::PayloadError;
@@ -26,6 +34,7 @@ pub fn status_to_i8(result: Result<PackageStatus, PackageStatusError>) -> i8 {
             PackageStatus::NotInstalled => 0,
             PackageStatus::UpToDate => 1,
             PackageStatus::RequiresUpdate => 2,
+            PackageStatus::Unpublished => 3,
         },
         Err(error) => match error {
             PackageStatusError::Payload(e) => match e {
@@ -35,6 +44,7 @@ pub fn status_to_i8(result: Result<PackageStatus, PackageStatusError>) -> i8 {
             },
             PackageStatusError::WrongPayloadType => -3,
             PackageStatusError::ParsingVersion => -4,
+            PackageStatusError::Cancelled(_) => -6,
         },
     }
 }
@@ -48,6 +58,7 @@ impl fmt::Display for PackageStatus {
                 PackageStatus::NotInstalled => "Not installed",
                 PackageStatus::UpToDate => "Up to date",
                 PackageStatus::RequiresUpdate => "Requires update",
+                PackageStatus::Unpublished => "No longer published",
             }
         )
     }
@@ -57,11 +68,15 @@ use crate::package_store::InstallTarget;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct PackageAction {
     pub id: PackageKey,
     pub action: PackageActionType,
     #[serde(default)]
     pub target: InstallTarget,
+    /// Install options chosen by the user, by id, from those declared by the payload
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub options: BTreeMap<String, String>,
 }
 
 impl fmt::Display for PackageAction {
@@ -70,6 +85,7 @@ impl fmt::Display for PackageAction {
             .field("id", &self.id.to_string())
             .field("action", &self.action)
             .field("target", &self.target)
+            .field("options", &self.options)
             .finish()
     }
 }
@@ -80,6 +96,7 @@ impl PackageAction {
             id,
             action: PackageActionType::Install,
             target,
+            options: BTreeMap::new(),
         }
     }
 
@@ -88,9 +105,15 @@ impl PackageAction {
             id,
             action: PackageActionType::Uninstall,
             target,
+            options: BTreeMap::new(),
         }
     }
 
+    pub fn with_options(mut self, options: BTreeMap<String, String>) -> PackageAction {
+        self.options = options;
+        self
+    }
+
     #[inline]
     pub fn is_install(&self) -> bool {
         self.action == PackageActionType::Install
@@ -112,6 +135,9 @@ pub enum PackageStatusError {
 
     #[error("Error parsing version")]
     ParsingVersion,
+
+    #[error("Status check did not complete")]
+    Cancelled(#[from] crate::package_store::BlockingError),
 }
 
 #[derive(Debug, thiserror::Error, Clone)]
@@ -127,6 +153,19 @@ pub enum PackageDependencyStatusError {
 
     #[error("Package not found: {0}")]
     PackageNotFound(String),
+
+    #[error("Package `{0}` requires Pahkat {1} or newer")]
+    ClientUpdateRequired(PackageKey, String),
+
+    #[error("Dependency `{0}` {1} is not available, only {2}")]
+    UnsatisfiedDependency(
+        PackageKey,
+        pahkat_types::package::VersionReq,
+        pahkat_types::package::Version,
+    ),
+
+    #[error("Status check did not complete")]
+    Cancelled(PackageKey, #[source] crate::package_store::BlockingError),
 }
 
 impl PackageDependencyStatusError {
@@ -136,6 +175,9 @@ impl PackageDependencyStatusError {
             PackageDependencyStatusError::WrongPayloadType(p) => p.to_string(),
             PackageDependencyStatusError::ParsingVersion(p) => p.to_string(),
             PackageDependencyStatusError::PackageNotFound(p) => p.clone(),
+            PackageDependencyStatusError::ClientUpdateRequired(p, _) => p.to_string(),
+            PackageDependencyStatusError::UnsatisfiedDependency(p, _, _) => p.to_string(),
+            PackageDependencyStatusError::Cancelled(p, _) => p.to_string(),
         }
     }
 }
@@ -172,6 +214,7 @@ pub enum PackageTransactionError {
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum PackageActionType {
     Install,
     Uninstall,
@@ -226,11 +269,13 @@ pub enum TransactionEvent {
     Uninstalling(PackageKey),
     Progress(PackageKey, String),
     Error(PackageKey, TransactionError),
+    /// The action for this package finished, but it only takes effect after a restart.
+    RebootRequired(PackageKey),
     Complete,
 }
 
 use pahkat_types::{
-    package::{Descriptor, Release},
+    package::{Deprecation, DeprecationSeverity, Descriptor, Release},
     payload::Target,
 };
 
@@ -240,6 +285,13 @@ pub struct ResolvedAction {
     pub descriptor: Descriptor,
     pub release: Release,
     pub target: Target,
+    /// Whether the payload declares that this action needs a restart. Installers
+    /// may still ask for one when they run.
+    #[serde(default)]
+    pub is_reboot_required: bool,
+    /// Whether the payload was already downloaded when the transaction was created.
+    #[serde(default)]
+    pub is_cached: bool,
 }
 
 impl std::fmt::Display for ResolvedAction {
@@ -252,16 +304,45 @@ impl std::fmt::Display for ResolvedAction {
     }
 }
 
+impl ResolvedAction {
+    /// The deprecation notice of the release being installed, if any.
+    pub fn deprecation(&self) -> Option<&Deprecation> {
+        if self.action.is_install() {
+            self.release.deprecation.as_ref()
+        } else {
+            None
+        }
+    }
+
+    /// The architecture of the target being installed when the release has no target
+    /// native to this machine, so that it runs under emulation.
+    pub fn emulated_arch(&self) -> Option<&str> {
+        let native = crate::defaults::native_arch()?;
+        let arch = self.target.arch.as_deref()?;
+        if !self.action.is_install()
+            || self.target.platform != crate::defaults::platform()
+            || pahkat_types::payload::arch::is_native(arch, native)
+        {
+            return None;
+        }
+        Some(arch)
+    }
+}
+
 pub struct PackageTransaction {
     store: Arc<dyn PackageStore>,
     actions: Arc<Vec<ResolvedAction>>,
     is_reboot_required: bool,
+    observers: Observers,
+    priority: ProcessPriority,
+    is_atomic: bool,
 }
 
 use crate::repo::PackageCandidateError;
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 #[serde(rename_all = "camelCase")]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct ResolvedRelease {
     pub version: pahkat_types::package::Version,
 
@@ -277,10 +358,14 @@ pub struct ResolvedRelease {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub license_url: Option<Url>,
 
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deprecation: Option<Deprecation>,
+
     pub target: pahkat_types::payload::Target,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct ResolvedDescriptor {
     pub key: PackageKey,
     pub status: PackageStatus,
@@ -299,12 +384,14 @@ impl ResolvedRelease {
             authors: release.authors,
             license: release.license,
             license_url: release.license_url,
+            deprecation: release.deprecation,
             target,
         }
     }
 }
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 #[serde(rename_all = "camelCase")]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct ResolvedPackageQuery {
     pub descriptors: Vec<ResolvedDescriptor>,
     pub size: u64,
@@ -319,48 +406,87 @@ impl PackageTransaction {
     ) -> Result<PackageTransaction, PackageCandidateError> {
         log::debug!("New transaction with actions: {:#?}", &actions);
 
+        // Package sets are expanded into their members. Uninstalling a set only
+        // touches the members that are actually installed.
+        let actions = actions
+            .into_iter()
+            .flat_map(|action| match store.set_members(&action.id) {
+                Some(members) => members
+                    .into_iter()
+                    .filter(|id| {
+                        action.is_install()
+                            || !matches!(
+                                store.status(id, action.target),
+                                Ok(PackageStatus::NotInstalled)
+                            )
+                    })
+                    .map(|id| PackageAction {
+                        id,
+                        action: action.action,
+                        target: action.target,
+                        options: action.options.clone(),
+                    })
+                    .collect::<Vec<_>>(),
+                None => vec![action],
+            })
+            .collect::<Vec<_>>();
+
         let repos = store.repos();
         let repos = repos.read().unwrap();
 
         // // Get mutation set (for install and uninstall actions)
-        let install_target = actions
-            .iter()
-            .map(|a| a.target)
-            .collect::<std::collections::HashSet<_>>()
-            .into_iter()
-            .collect::<Vec<_>>();
         let candidate_keys = actions
             .iter()
-            .map(|a| (a.action, a.id.clone()))
+            .map(|a| (a.action, a.id.clone(), a.target))
             .collect::<Vec<_>>();
-        let mutation_set =
-            crate::repo::resolve_package_set(&*store, &*candidate_keys, &*install_target)?;
+        let mutation_set = crate::repo::resolve_package_set(&*store, &candidate_keys)?;
 
         let is_reboot_required = mutation_set.iter().any(|x| x.is_reboot_required);
 
+        let config = store.config();
+        let config = config.read().unwrap();
+
         // Create a list of resolved actions to be processed.
         let new_actions = mutation_set
             .into_iter()
             .map(|candidate| {
-                let key = candidate.package_key;
-                let action = candidate.action;
-
-                ResolvedAction {
+                // Options only apply to the packages they were chosen for, not to the
+                // dependencies installed along with them
+                let options = actions
+                    .iter()
+                    .find(|x| {
+                        x.is_install()
+                            && candidate.action == PackageActionType::Install
+                            && x.id.repository_url == candidate.package_key.repository_url
+                            && x.id.id == candidate.package_key.id
+                    })
+                    .map(|x| x.options.clone())
+                    .unwrap_or_default();
+                pahkat_types::payload::resolve_install_options(
+                    candidate.target.payload.install_options(),
+                    &options,
+                )
+                .map_err(|e| {
+                    PackageCandidateError::InvalidOption(candidate.package_key.clone(), e)
+                })?;
+
+                Ok(ResolvedAction {
+                    is_cached: candidate.action == PackageActionType::Install
+                        && crate::repo::is_payload_cached(&config, &candidate.target.payload),
                     descriptor: candidate.descriptor,
                     release: candidate.release,
                     target: candidate.target,
-                    action: actions
-                        .iter()
-                        .find(|x| &x.id == &key)
-                        .cloned()
-                        .unwrap_or_else(|| PackageAction {
-                            id: key,
-                            action,
-                            target: InstallTarget::System,
-                        }),
-                }
+                    is_reboot_required: candidate.is_reboot_required,
+                    action: PackageAction {
+                        id: candidate.package_key,
+                        action: candidate.action,
+                        target: candidate.install_target,
+                        options,
+                    },
+                })
             })
-            .collect::<Vec<_>>();
+            .collect::<Result<Vec<_>, PackageCandidateError>>()?;
+        drop(config);
 
         // Check for uninstall actions that contradict this set
         // for action in actions
@@ -384,9 +510,118 @@ impl PackageTransaction {
             store,
             actions: Arc::new(new_actions),
             is_reboot_required,
+            observers: Observers::default(),
+            priority: ProcessPriority::Normal,
+            is_atomic: false,
         })
     }
 
+    /// Runs installers at `priority`, such as when nobody is waiting for the transaction.
+    pub fn with_priority(mut self, priority: ProcessPriority) -> PackageTransaction {
+        self.priority = priority;
+        self
+    }
+
+    /// Applies every action or none of them. Nothing is applied until every
+    /// install passes its pre-flight checks, and if an action still fails, the
+    /// packages this transaction newly installed are uninstalled again. Installs
+    /// run before uninstalls, as updates and uninstalls cannot be undone.
+    pub fn with_atomic(mut self, is_atomic: bool) -> PackageTransaction {
+        self.is_atomic = is_atomic;
+        if is_atomic {
+            let mut actions = (*self.actions).clone();
+            actions.sort_by_key(|x| !x.action.is_install());
+            self.actions = Arc::new(actions);
+        }
+        self
+    }
+
+    pub fn is_atomic(&self) -> bool {
+        self.is_atomic
+    }
+
+    /// Observers notified as this transaction is processed.
+    pub fn observers(&self) -> &Observers {
+        &self.observers
+    }
+
+    /// Notifies the observers that the payload for `key` has been downloaded.
+    pub async fn download_complete(&self, key: &PackageKey, path: &std::path::Path) {
+        let key = key.clone();
+        let path = path.to_path_buf();
+        observe(&self.store, &self.observers, move |x| {
+            x.on_download_complete(&key, &path)
+        })
+        .await;
+    }
+
+    /// Downloads the payloads of the install actions, up to `concurrency` at a time.
+    /// Events of the downloads are interleaved; observers are told of each completed
+    /// download before its `Complete` event is yielded. Progress carries the speed
+    /// and time remaining of the downloads as a whole.
+    pub fn download(
+        &self,
+        concurrency: usize,
+    ) -> crate::package_store::Stream<(PackageKey, DownloadEvent)> {
+        use futures::stream::StreamExt;
+
+        let mut estimator = crate::eta::Estimator::new();
+        for record in self.actions.iter().filter(|x| x.action.is_install()) {
+            let size = record.target.payload.size();
+            let current = if record.is_cached { size } else { 0 };
+            estimator.expect(&record.action.id, current, size);
+        }
+
+        let downloads = self
+            .actions
+            .iter()
+            .filter(|x| x.action.is_install())
+            .map(|record| {
+                let key = record.action.id.clone();
+                self.store
+                    .download(&key)
+                    .map(move |event| (key.clone(), event))
+            })
+            .collect::<Vec<_>>();
+
+        let store = Arc::clone(&self.store);
+        let observers = self.observers.clone();
+        let stream = futures::stream::iter(downloads)
+            .flatten_unordered(concurrency.max(1))
+            .map(move |(key, event)| match event {
+                DownloadEvent::Progress(x) => {
+                    let estimate = estimator.update(&key, x.current, x.total);
+                    (key, DownloadEvent::Progress(x.with_estimate(estimate)))
+                }
+                DownloadEvent::Complete(path) => {
+                    estimator.complete(&key);
+                    (key, DownloadEvent::Complete(path))
+                }
+                event => (key, event),
+            })
+            .then(move |(key, event)| {
+                let store = Arc::clone(&store);
+                let observers = observers.clone();
+                async move {
+                    if let DownloadEvent::Complete(path) = &event {
+                        let key = key.clone();
+                        let path = path.clone();
+                        observe(&store, &observers, move |x| {
+                            x.on_download_complete(&key, &path)
+                        })
+                        .await;
+                    }
+                    (key, event)
+                }
+            });
+
+        Box::pin(stream)
+    }
+
+    pub(crate) fn store(&self) -> &Arc<dyn PackageStore> {
+        &self.store
+    }
+
     pub fn actions(&self) -> Arc<Vec<ResolvedAction>> {
         Arc::clone(&self.actions)
     }
@@ -395,59 +630,372 @@ impl PackageTransaction {
         self.is_reboot_required
     }
 
+    /// Actions installing a critically deprecated release. Frontends should
+    /// only process the transaction if the user explicitly allowed these.
+    pub fn critically_deprecated(&self) -> Vec<&ResolvedAction> {
+        self.actions
+            .iter()
+            .filter(|x| {
+                x.deprecation()
+                    .map(|x| x.severity == DeprecationSeverity::Critical)
+                    .unwrap_or(false)
+            })
+            .collect()
+    }
+
+    /// Hash of the resolved actions, independent of the order they were requested
+    /// in. Two transactions with the same fingerprint would do the same work.
+    pub fn fingerprint(&self) -> u64 {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+
+        let mut actions = self
+            .actions
+            .iter()
+            .map(|x| {
+                (
+                    x.action.id.to_string(),
+                    x.action.action.to_u8(),
+                    x.action.target,
+                    x.release.version.to_string(),
+                )
+            })
+            .collect::<Vec<_>>();
+        actions.sort();
+
+        let mut hasher = DefaultHasher::new();
+        actions.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Runs the actions in order. Cancelling with the returned trigger ends the stream
+    /// before the next action, as a running installer cannot be stopped safely.
     pub fn process(
         &self,
     ) -> (
         stream_cancel::Trigger,
         crate::package_store::Stream<TransactionEvent>,
     ) {
+        use futures::future::FutureExt;
+
         log::debug!("beginning transaction process");
 
-        let (canceler, valve) = stream_cancel::Valve::new();
+        let (canceler, tripwire) = stream_cancel::Tripwire::new();
 
         let store = Arc::clone(&self.store);
         let actions: Arc<Vec<ResolvedAction>> = Arc::clone(&self.actions);
+        let observers = self.observers.clone();
+        let priority = self.priority;
+        let is_atomic = self.is_atomic;
 
         let stream = async_stream::stream! {
+            {
+                let actions = Arc::clone(&actions);
+                observe(&store, &observers, move |x| x.on_resolved(&actions)).await;
+            }
+
+            if is_atomic {
+                for record in actions.iter().filter(|x| x.action.is_install()) {
+                    let key = record.action.id.clone();
+                    let options = record.action.options.clone();
+                    let result = store.try_blocking(move |store| store.preflight(&key, &options)).await;
+                    if let Err(e) = result {
+                        log::error!("Pre-flight of {} failed: {:?}", &record.action.id, &e);
+                        let observed = record.clone();
+                        let error = observe(&store, &observers, move |x| {
+                            let error = TransactionError::Install(e);
+                            x.on_error(&observed, &error);
+                            error
+                        }).await;
+                        if let Some(error) = error {
+                            yield TransactionEvent::Error(record.action.id.clone(), error);
+                        }
+                        return;
+                    }
+                }
+            }
+
+            // Packages this transaction installed that were not installed before,
+            // which are uninstalled again if an atomic transaction fails
+            let mut fresh_installs: Vec<&ResolvedAction> = vec![];
+            let mut is_reboot_published = false;
+
             for record in actions.iter() {
                 let action = &record.action;
+                if tripwire.clone().now_or_never() == Some(true) {
+                    log::info!("Transaction cancelled before {}", &action);
+                    return;
+                }
                 log::debug!("processing action: {}", &action);
 
-                match action.action {
+                let is_fresh_install = is_atomic && action.is_install() && {
+                    let (key, target) = (action.id.clone(), action.target);
+                    let status = store.try_blocking(move |store| store.status(&key, target)).await;
+                    matches!(status, Ok(PackageStatus::NotInstalled))
+                };
+
+                {
+                    let record = record.clone();
+                    observe(&store, &observers, move |x| x.before_install(&record)).await;
+                }
+
+                let error = match action.action {
                     PackageActionType::Install => {
                         log::debug!("Going to yield now.");
                         yield TransactionEvent::Installing(action.id.clone());
 
                         log::debug!("Going to install now.");
-                        match store.install(&action.id, action.target) {
+                        let (key, target) = (action.id.clone(), action.target);
+                        let options = action.options.clone();
+                        let result = store.try_blocking(move |store| {
+                            let _priority = crate::priority::enter(priority);
+                            store.install_with_options(&key, target, &options)
+                        }).await;
+                        match result {
                             Ok(_) => {
                                 log::trace!("We came out the other side.");
+                                if is_fresh_install {
+                                    fresh_installs.push(record);
+                                }
+                                crate::repo::installed::save(
+                                    &*store.config().read().unwrap(),
+                                    &action.id,
+                                    action.target,
+                                    &record.descriptor,
+                                    &record.release,
+                                    &record.target,
+                                );
+                                None
                             }
-                            Err(e) => {
-                                log::error!("{:?}", &e);
-                                yield TransactionEvent::Error(action.id.clone(), TransactionError::Install(e));
-                                return;
-                            }
-                        };
+                            Err(e) => Some(TransactionError::Install(e)),
+                        }
                     }
                     PackageActionType::Uninstall => {
                         yield TransactionEvent::Uninstalling(action.id.clone());
 
-                        match store.uninstall(&action.id, action.target) {
-                            Ok(_) => {}
-                            Err(e) => {
-                                log::error!("{:?}", &e);
-                                yield TransactionEvent::Error(action.id.clone(), TransactionError::Uninstall(e));
-                                return;
+                        let (key, target) = (action.id.clone(), action.target);
+                        let result = store.try_blocking(move |store| {
+                            let _priority = crate::priority::enter(priority);
+                            store.uninstall(&key, target)
+                        }).await;
+                        match result {
+                            Ok(_) => {
+                                crate::repo::installed::remove(
+                                    &*store.config().read().unwrap(),
+                                    &action.id,
+                                    action.target,
+                                );
+                                None
+                            }
+                            Err(e) => Some(TransactionError::Uninstall(e)),
+                        }
+                    }
+                };
+
+                let observed = record.clone();
+                match error {
+                    Some(error) => {
+                        log::error!("{:?}", &error);
+
+                        for record in fresh_installs.iter().rev() {
+                            let action = &record.action;
+                            log::info!("Rolling back install of {}", &action.id);
+                            yield TransactionEvent::Uninstalling(action.id.clone());
+
+                            let (key, target) = (action.id.clone(), action.target);
+                            let result = store.try_blocking(move |store| {
+                                let _priority = crate::priority::enter(priority);
+                                store.uninstall(&key, target)
+                            }).await;
+                            match result {
+                                Ok(_) => crate::repo::installed::remove(
+                                    &*store.config().read().unwrap(),
+                                    &action.id,
+                                    action.target,
+                                ),
+                                Err(e) => log::error!("Could not roll back {}: {:?}", &action.id, e),
                             }
-                        };
+                        }
+
+                        let error = observe(&store, &observers, move |x| {
+                            x.on_error(&observed, &error);
+                            error
+                        }).await;
+                        if let Some(error) = error {
+                            yield TransactionEvent::Error(action.id.clone(), error);
+                        }
+                        return;
+                    }
+                    None => {
+                        observe(&store, &observers, move |x| x.after_install(&observed)).await;
                     }
                 }
+
+                // Always take the request so that it does not leak into a later transaction.
+                let is_reboot_requested = store.take_reboot_request(&action.id);
+                if record.is_reboot_required || is_reboot_requested {
+                    // Published once, as soon as it is known, since a later action may fail
+                    if !is_reboot_published {
+                        crate::events::global().publish(crate::events::StoreEvent::RebootRequired);
+                        is_reboot_published = true;
+                    }
+                    yield TransactionEvent::RebootRequired(action.id.clone());
+                }
+            }
+
+            {
+                let actions = Arc::clone(&actions);
+                let result = store.blocking(move |store| {
+                    crate::cache::after_transaction(&store.config().read().unwrap(), &actions)
+                }).await;
+                if let Err(e) = result {
+                    log::error!("Could not clean up the package cache: {}", e);
+                }
             }
 
             yield TransactionEvent::Complete;
         };
 
-        (canceler, Box::pin(valve.wrap(stream)))
+        (canceler, Box::pin(stream))
+    }
+}
+
+/// Runs an observer callback on the blocking pool, as hooks may run processes.
+/// Returns `None` if the runtime shut down before the callback ran.
+async fn observe<T, F>(store: &Arc<dyn PackageStore>, observers: &Observers, f: F) -> Option<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&Observers) -> T + Send + 'static,
+{
+    let observers = observers.clone();
+    match store.blocking(move |_| f(&observers)).await {
+        Ok(value) => Some(value),
+        Err(e) => {
+            log::error!("Could not run transaction observers: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::stream::StreamExt;
+
+    use super::*;
+    use crate::package_store::mock::{key, package, MockStore};
+
+    #[test]
+    fn cancelling_finishes_the_running_action() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let store = Arc::new(MockStore::new(&[
+            package("a", "1.0.0", &[]),
+            package("b", "1.0.0", &[]),
+        ]));
+        let actions = vec![
+            PackageAction::install(key("a"), InstallTarget::System),
+            PackageAction::install(key("b"), InstallTarget::System),
+        ];
+        let transaction = PackageTransaction::new(store.clone(), actions).unwrap();
+
+        let events = runtime.block_on(async {
+            let (trigger, mut stream) = transaction.process();
+            let first = stream.next().await;
+            trigger.cancel();
+            (first, stream.collect::<Vec<_>>().await)
+        });
+
+        assert!(matches!(events.0, Some(TransactionEvent::Installing(_))));
+        assert!(events.1.is_empty());
+        assert_eq!(store.calls().len(), 1);
+    }
+
+    fn run_atomic(store: &Arc<MockStore>, actions: Vec<PackageAction>) -> Vec<TransactionEvent> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let transaction = PackageTransaction::new(store.clone(), actions)
+            .unwrap()
+            .with_atomic(true);
+        runtime.block_on(async {
+            let (_trigger, stream) = transaction.process();
+            stream.collect::<Vec<_>>().await
+        })
+    }
+
+    #[test]
+    fn atomic_preflight_failure_changes_nothing() {
+        let store = Arc::new(MockStore::new(&[
+            package("a", "1.0.0", &[]),
+            package("b", "1.0.0", &[]),
+        ]));
+        store.failing_preflight.lock().unwrap().insert("b".into());
+
+        let events = run_atomic(
+            &store,
+            vec![
+                PackageAction::install(key("a"), InstallTarget::System),
+                PackageAction::install(key("b"), InstallTarget::System),
+            ],
+        );
+
+        assert!(matches!(events.last(), Some(TransactionEvent::Error(..))));
+        assert!(store.calls().iter().all(|x| x.starts_with("preflight")));
+    }
+
+    #[test]
+    fn atomic_failure_rolls_back_fresh_installs() {
+        let store = Arc::new(MockStore::new(&[
+            package("a", "1.0.0", &[]),
+            package("b", "1.0.0", &[("a", "*")]),
+        ]));
+        store.failing_install.lock().unwrap().insert("b".into());
+
+        let events = run_atomic(
+            &store,
+            vec![PackageAction::install(key("b"), InstallTarget::System)],
+        );
+
+        assert!(matches!(events.last(), Some(TransactionEvent::Error(..))));
+        assert_eq!(
+            store.calls(),
+            [
+                "preflight a",
+                "preflight b",
+                "install a",
+                "install b",
+                "uninstall a"
+            ]
+        );
+        assert_eq!(
+            store.status(&key("a"), InstallTarget::System).unwrap(),
+            PackageStatus::NotInstalled
+        );
+    }
+
+    #[test]
+    fn atomic_installs_run_before_uninstalls() {
+        let store = Arc::new(MockStore::new(&[
+            package("a", "1.0.0", &[]),
+            package("old", "1.0.0", &[]),
+        ]));
+        store.set_status("old", PackageStatus::UpToDate);
+        store.failing_install.lock().unwrap().insert("a".into());
+
+        let events = run_atomic(
+            &store,
+            vec![
+                PackageAction::uninstall(key("old"), InstallTarget::System),
+                PackageAction::install(key("a"), InstallTarget::System),
+            ],
+        );
+
+        assert!(matches!(events.last(), Some(TransactionEvent::Error(..))));
+        assert!(!store.calls().contains(&"uninstall old".to_string()));
+        assert_eq!(
+            store.status(&key("old"), InstallTarget::System).unwrap(),
+            PackageStatus::UpToDate
+        );
     }
 }
@@ -13,6 +13,31 @@ pub enum InstallError {
 
     #[error("Installation process failed")]
     InstallerFailure(#[from] ProcessError),
+
+    #[error("Installer signature could not be verified: {0}")]
+    InvalidSignature(String),
+
+    #[error("Invalid install directory")]
+    InvalidInstallDir(#[from] pahkat_types::payload::tarball::InvalidInstallDir),
+
+    #[error("Invalid install option")]
+    InvalidOption(#[from] pahkat_types::payload::InstallOptionError),
+
+    #[error("Invalid MSI property name: {0}")]
+    InvalidMsiProperty(String),
+
+    #[error("Invalid MSI transform: {0}")]
+    InvalidMsiTransform(String),
+
+    #[error("Refusing to extract malicious archive: {0}")]
+    MaliciousArchive(#[source] crate::archive::MaliciousArchive),
+
+    #[error("Installation did not complete")]
+    Cancelled(#[from] crate::package_store::BlockingError),
+
+    #[cfg(feature = "prefix")]
+    #[error("Failed to extract package")]
+    ExtractFailed(#[source] crate::package_store::prefix::ExtractError),
 }
 
 #[derive(thiserror::Error, Debug)]
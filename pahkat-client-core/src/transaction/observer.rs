@@ -0,0 +1,182 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, RwLock};
+
+use pahkat_types::PackageKey;
+
+use super::{PackageActionType, ResolvedAction, TransactionError};
+use crate::config::ExecHooks;
+use crate::package_store::{InstallTarget, SharedStoreConfig};
+
+/// Callbacks for the stages of a transaction.
+///
+/// Methods are called from the blocking pool, so they may block. The install
+/// callbacks are also called for uninstall actions; check `action.action`.
+pub trait TransactionObserver: Send + Sync {
+    /// Called once, when the transaction starts processing its resolved actions.
+    fn on_resolved(&self, _actions: &[ResolvedAction]) {}
+
+    fn on_download_complete(&self, _key: &PackageKey, _path: &Path) {}
+
+    fn before_install(&self, _action: &ResolvedAction) {}
+
+    fn after_install(&self, _action: &ResolvedAction) {}
+
+    fn on_error(&self, _action: &ResolvedAction, _error: &TransactionError) {}
+}
+
+/// A set of observers that are notified in the order they were registered.
+#[derive(Clone, Default)]
+pub struct Observers(Arc<RwLock<Vec<Arc<dyn TransactionObserver>>>>);
+
+impl Observers {
+    pub fn register(&self, observer: Arc<dyn TransactionObserver>) {
+        self.0.write().unwrap().push(observer);
+    }
+
+    fn each(&self, f: impl Fn(&dyn TransactionObserver)) {
+        for observer in self.0.read().unwrap().iter() {
+            f(&**observer);
+        }
+    }
+}
+
+impl std::fmt::Debug for Observers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Observers")
+            .field(&self.0.read().unwrap().len())
+            .finish()
+    }
+}
+
+impl TransactionObserver for Observers {
+    fn on_resolved(&self, actions: &[ResolvedAction]) {
+        self.each(|x| x.on_resolved(actions));
+    }
+
+    fn on_download_complete(&self, key: &PackageKey, path: &Path) {
+        self.each(|x| x.on_download_complete(key, path));
+    }
+
+    fn before_install(&self, action: &ResolvedAction) {
+        self.each(|x| x.before_install(action));
+    }
+
+    fn after_install(&self, action: &ResolvedAction) {
+        self.each(|x| x.after_install(action));
+    }
+
+    fn on_error(&self, action: &ResolvedAction, error: &TransactionError) {
+        self.each(|x| x.on_error(action, error));
+    }
+}
+
+/// Runs the commands configured in the `[hooks]` table of the settings.
+///
+/// Details of the event are passed in `PAHKAT_*` environment variables. A hook
+/// that fails is logged and does not affect the transaction.
+pub struct ExecObserver {
+    config: SharedStoreConfig,
+}
+
+impl ExecObserver {
+    pub fn new(config: SharedStoreConfig) -> ExecObserver {
+        ExecObserver { config }
+    }
+
+    fn run<F>(&self, event: &str, hook: F, vars: &[(&str, String)])
+    where
+        F: FnOnce(&ExecHooks) -> Option<&PathBuf>,
+    {
+        let hook = {
+            let config = self.config.read().unwrap();
+            match hook(config.settings().hooks()) {
+                Some(v) => v.clone(),
+                None => return,
+            }
+        };
+
+        log::debug!("Running {} hook: {:?}", event, &hook);
+        let result = Command::new(&hook)
+            .env("PAHKAT_HOOK", event)
+            .envs(vars.iter().map(|(k, v)| (*k, v)))
+            .status();
+
+        match result {
+            Ok(status) if status.success() => {}
+            Ok(status) => log::warn!("{} hook {:?} exited with {}", event, &hook, status),
+            Err(e) => log::error!("Could not run {} hook {:?}: {}", event, &hook, e),
+        }
+    }
+}
+
+fn action_vars(action: &ResolvedAction) -> Vec<(&'static str, String)> {
+    vec![
+        ("PAHKAT_PACKAGE", action.action.id.to_string()),
+        (
+            "PAHKAT_ACTION",
+            match action.action.action {
+                PackageActionType::Install => "install",
+                PackageActionType::Uninstall => "uninstall",
+            }
+            .to_string(),
+        ),
+        (
+            "PAHKAT_TARGET",
+            match action.action.target {
+                InstallTarget::System => "system",
+                InstallTarget::User => "user",
+            }
+            .to_string(),
+        ),
+        ("PAHKAT_VERSION", action.release.version.to_string()),
+    ]
+}
+
+impl TransactionObserver for ExecObserver {
+    fn on_resolved(&self, actions: &[ResolvedAction]) {
+        let packages = actions
+            .iter()
+            .map(|x| x.action.id.to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+        self.run(
+            "on_resolved",
+            |x| x.on_resolved.as_ref(),
+            &[("PAHKAT_PACKAGES", packages)],
+        );
+    }
+
+    fn on_download_complete(&self, key: &PackageKey, path: &Path) {
+        self.run(
+            "on_download_complete",
+            |x| x.on_download_complete.as_ref(),
+            &[
+                ("PAHKAT_PACKAGE", key.to_string()),
+                ("PAHKAT_PATH", path.display().to_string()),
+            ],
+        );
+    }
+
+    fn before_install(&self, action: &ResolvedAction) {
+        self.run(
+            "before_install",
+            |x| x.before_install.as_ref(),
+            &action_vars(action),
+        );
+    }
+
+    fn after_install(&self, action: &ResolvedAction) {
+        self.run(
+            "after_install",
+            |x| x.after_install.as_ref(),
+            &action_vars(action),
+        );
+    }
+
+    fn on_error(&self, action: &ResolvedAction, error: &TransactionError) {
+        let mut vars = action_vars(action);
+        vars.push(("PAHKAT_ERROR", error.to_string()));
+        self.run("on_error", |x| x.on_error.as_ref(), &vars);
+    }
+}
@@ -16,4 +16,7 @@ pub enum UninstallError {
 
     #[error("The package is not installed")]
     NotInstalled,
+
+    #[error("Uninstallation did not complete")]
+    Cancelled(#[from] crate::package_store::BlockingError),
 }
@@ -0,0 +1,213 @@
+//! Signing keys seen for repositories that have no trusted keys configured. The key
+//! a repository advertises is pinned the first time it is loaded, and a repository
+//! advertising another key is refused until the new key is accepted, after it has
+//! been verified out of band.
+//!
+//! The serial of the newest manifest seen for each signed repository is kept as well,
+//! so that an older index signed by the same key is refused.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
+use pahkat_types::repo::RepoUrl;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::config::{Config, RepoRecord};
+use crate::repo::{LoadedRepository, RepoTrust};
+
+const FILE_NAME: &str = "repo-keys.json";
+const SERIALS_FILE_NAME: &str = "repo-serials.json";
+
+/// Held while the file is read, changed and written back.
+static LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+#[derive(Debug, thiserror::Error)]
+pub enum TrustError {
+    #[error("Could not read or write repository keys")]
+    Io(#[from] std::io::Error),
+
+    #[error("Repository keys are not valid JSON")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Not a base64 ed25519 public key: {0}")]
+    InvalidKey(String),
+}
+
+pub fn path(config: &Config) -> PathBuf {
+    config.settings().config_dir().join(FILE_NAME)
+}
+
+pub fn serials_path(config: &Config) -> PathBuf {
+    config.settings().config_dir().join(SERIALS_FILE_NAME)
+}
+
+/// Pinned keys or serials by repository URL. A missing file pins nothing, but a file
+/// that cannot be read is an error rather than a reason to pin keys anew.
+pub fn load<V: DeserializeOwned>(path: &Path) -> Result<BTreeMap<String, V>, TrustError> {
+    match std::fs::read(path) {
+        Ok(v) => Ok(serde_json::from_slice(&v)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Replaces the file in one step, so that it is never left partially written.
+fn save<V: Serialize>(path: &Path, keys: &BTreeMap<String, V>) -> Result<(), TrustError> {
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    std::fs::create_dir_all(dir)?;
+
+    let tmp_path = path.with_extension("json.tmp");
+    std::fs::write(&tmp_path, serde_json::to_vec_pretty(keys)?)?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+pub fn pinned(path: &Path, url: &RepoUrl) -> Result<Option<String>, TrustError> {
+    let _guard = LOCK.lock().unwrap();
+    Ok(load(path)?.remove(url.as_str()))
+}
+
+/// Fails unless `key` is a base64 ed25519 public key.
+pub fn check_key(key: &str) -> Result<(), TrustError> {
+    match crate::repo::signature::is_public_key(key) {
+        true => Ok(()),
+        false => Err(TrustError::InvalidKey(key.trim().to_string())),
+    }
+}
+
+/// Pins `key` for `url`, replacing any key pinned before.
+pub fn pin(path: &Path, url: &RepoUrl, key: &str) -> Result<(), TrustError> {
+    let key = key.trim();
+    check_key(key)?;
+
+    let _guard = LOCK.lock().unwrap();
+    let mut keys = load(path)?;
+    keys.insert(url.to_string(), key.to_string());
+    save(path, &keys)
+}
+
+/// Forgets the key of `url`, so that whichever key it advertises next is pinned.
+pub fn forget(path: &Path, url: &RepoUrl) -> Result<(), TrustError> {
+    let _guard = LOCK.lock().unwrap();
+    let mut keys = load::<String>(path)?;
+    if keys.remove(url.as_str()).is_some() {
+        save(path, &keys)?;
+    }
+    Ok(())
+}
+
+pub fn serial(path: &Path, url: &RepoUrl) -> Result<Option<u64>, TrustError> {
+    let _guard = LOCK.lock().unwrap();
+    Ok(load(path)?.remove(url.as_str()))
+}
+
+/// Records `serial` as the newest seen for `url`, unless a newer one was seen before.
+pub fn record_serial(path: &Path, url: &RepoUrl, serial: u64) -> Result<(), TrustError> {
+    let _guard = LOCK.lock().unwrap();
+    let mut serials = load::<u64>(path)?;
+    let newest = serials.entry(url.to_string()).or_insert(serial);
+    if *newest > serial {
+        return Ok(());
+    }
+    *newest = serial;
+    save(path, &serials)
+}
+
+/// What the indexes of `url`, configured by `record`, are verified against.
+pub fn repo_trust(
+    config: &Config,
+    url: &RepoUrl,
+    record: &RepoRecord,
+) -> Result<RepoTrust, TrustError> {
+    Ok(RepoTrust {
+        keys: record.trusted_keys.clone(),
+        pinned_key: pinned(&path(config), url)?,
+        serial: serial(&serials_path(config), url)?,
+    })
+}
+
+/// Pins the key of `repo` if it was verified by the key it advertises on first use,
+/// and records the serial of its manifest. Failures are only logged, as the repository
+/// was verified either way.
+pub fn accept(config: &Config, url: &RepoUrl, trust: &RepoTrust, repo: &LoadedRepository) {
+    if trust.keys.is_empty() && trust.pinned_key.is_none() {
+        if let Some(key) = repo.info().repository.signing_key.as_deref() {
+            log::info!("Pinning signing key of {}", url);
+            if let Err(e) = pin(&path(config), url, key) {
+                log::warn!("Could not pin signing key of {}: {}", url, e);
+            }
+        }
+    }
+
+    if let Some(serial) = repo.serial() {
+        if let Err(e) = record_serial(&serials_path(config), url, serial) {
+            log::warn!("Could not record manifest serial of {}: {}", url, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(seed: u8) -> String {
+        let secret = ed25519_dalek::SecretKey::from_bytes(&[seed; 32]).unwrap();
+        base64::encode(ed25519_dalek::PublicKey::from(&secret).as_bytes())
+    }
+
+    #[test]
+    fn pins_per_repository() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(FILE_NAME);
+        let main = RepoUrl::new("https://pahkat.example/main/".parse().unwrap()).unwrap();
+        let nightly = RepoUrl::new("https://pahkat.example/nightly/".parse().unwrap()).unwrap();
+        let (first, second, other) = (key(1), key(2), key(3));
+
+        assert_eq!(pinned(&path, &main).unwrap(), None);
+        pin(&path, &main, &format!("{}\n", first)).unwrap();
+        pin(&path, &nightly, &other).unwrap();
+        assert_eq!(pinned(&path, &main).unwrap(), Some(first));
+
+        pin(&path, &main, &second).unwrap();
+        assert_eq!(pinned(&path, &main).unwrap(), Some(second));
+
+        forget(&path, &main).unwrap();
+        assert_eq!(pinned(&path, &main).unwrap(), None);
+        assert_eq!(pinned(&path, &nightly).unwrap(), Some(other));
+    }
+
+    #[test]
+    fn keeps_newest_serial() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(SERIALS_FILE_NAME);
+        let url = RepoUrl::new("https://pahkat.example/main/".parse().unwrap()).unwrap();
+
+        assert_eq!(serial(&path, &url).unwrap(), None);
+        record_serial(&path, &url, 2).unwrap();
+        record_serial(&path, &url, 1).unwrap();
+        assert_eq!(serial(&path, &url).unwrap(), Some(2));
+        record_serial(&path, &url, 3).unwrap();
+        assert_eq!(serial(&path, &url).unwrap(), Some(3));
+    }
+
+    #[test]
+    fn refuses_invalid_keys_and_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(FILE_NAME);
+        let url = RepoUrl::new("https://pahkat.example/main/".parse().unwrap()).unwrap();
+
+        for key in ["", "not base64", "c2hvcnQ="] {
+            assert!(matches!(
+                pin(&path, &url, key),
+                Err(TrustError::InvalidKey(_))
+            ));
+        }
+
+        std::fs::write(&path, b"{ truncated").unwrap();
+        assert!(matches!(pinned(&path, &url), Err(TrustError::Json(_))));
+        assert!(pin(&path, &url, &key(1)).is_err());
+    }
+}
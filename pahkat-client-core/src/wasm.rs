@@ -0,0 +1,101 @@
+//! Read-only bindings for browsing repositories from a web page.
+//!
+//! There is no package store in the browser, so every package is reported as
+//! not installed, and the platform (and arch) to resolve releases for must be
+//! given in the query or package key.
+
+use std::convert::TryFrom;
+
+use hashbrown::HashMap;
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+use crate::package_store::InstallTarget;
+use crate::repo::{LoadedRepository, PackageKey, PackageQuery};
+use crate::transaction::{PackageStatus, ResolvedRelease};
+use pahkat_types::repo::RepoUrl;
+
+fn to_js<T: Serialize>(value: &T) -> Result<JsValue, JsValue> {
+    value
+        .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+        .map_err(Into::into)
+}
+
+fn error(e: impl std::fmt::Display) -> JsValue {
+    JsValue::from_str(&e.to_string())
+}
+
+#[wasm_bindgen]
+pub struct Repositories {
+    repos: HashMap<RepoUrl, LoadedRepository>,
+}
+
+#[wasm_bindgen]
+impl Repositories {
+    /// Loads the index of each repository URL in `urls`, an array of strings.
+    /// Fails if any of them cannot be loaded.
+    pub async fn load(urls: JsValue, channel: Option<String>) -> Result<Repositories, JsValue> {
+        let urls: Vec<RepoUrl> = serde_wasm_bindgen::from_value(urls)?;
+        let mut repos = HashMap::new();
+
+        for url in urls {
+            let repo = LoadedRepository::from_url(
+                url.clone(),
+                channel.clone(),
+                None,
+                Default::default(),
+                None,
+                None,
+            )
+            .await
+            .map_err(error)?;
+            repos.insert(url, repo);
+        }
+
+        Ok(Repositories { repos })
+    }
+
+    #[wasm_bindgen(js_name = resolvePackageQuery)]
+    pub fn resolve_package_query(&self, query: JsValue) -> Result<JsValue, JsValue> {
+        let query: PackageQuery = serde_wasm_bindgen::from_value(query)?;
+        let resolved = crate::repo::resolve_package_query(
+            |_, _| Some(PackageStatus::NotInstalled),
+            &query,
+            &[InstallTarget::System],
+            &self.repos,
+        );
+        to_js(&resolved)
+    }
+
+    /// Returns the package for a key, or `undefined` if it is not found.
+    pub fn package(&self, key: &str) -> Result<JsValue, JsValue> {
+        let key = PackageKey::try_from(key).map_err(error)?;
+        match crate::repo::find_package_by_key(&key, &self.repos) {
+            Some(package) => to_js(&package),
+            None => Ok(JsValue::UNDEFINED),
+        }
+    }
+
+    /// Returns the release and target that would be installed for a key, or
+    /// `undefined` if there is none.
+    pub fn release(&self, key: &str) -> Result<JsValue, JsValue> {
+        let key = PackageKey::try_from(key).map_err(error)?;
+        match crate::repo::resolve_release(&key, &self.repos) {
+            Some((release, target)) => to_js(&ResolvedRelease::new(release, target)),
+            None => Ok(JsValue::UNDEFINED),
+        }
+    }
+
+    #[wasm_bindgen(js_name = repoUrls)]
+    pub fn repo_urls(&self) -> Result<JsValue, JsValue> {
+        to_js(&self.repos.keys().collect::<Vec<_>>())
+    }
+}
+
+/// Fetches the localised tag and channel names of each repository, keyed by
+/// repository URL. Repositories without strings for `language` are left out.
+#[wasm_bindgen]
+pub async fn strings(urls: JsValue, language: String) -> Result<JsValue, JsValue> {
+    let urls: Vec<RepoUrl> = serde_wasm_bindgen::from_value(urls)?;
+    to_js(&crate::repo::strings(urls, language, None).await)
+}
@@ -53,6 +53,189 @@ impl RepoIndexCommand {
     }
 }
 
+struct IndexProgress;
+
+impl repo::indexing::Progress for IndexProgress {
+    fn indexed(&self, current: usize, total: usize, id: &str) {
+        eprintln!("[{}/{}] {}", current, total, id);
+    }
+
+    fn skipped(&self, path: &std::path::Path, error: &anyhow::Error) {
+        eprintln!("Skipping {}: {}", path.display(), error);
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct RepoLintCommand {
+    #[structopt(parse(from_os_str))]
+    repo_path: Option<PathBuf>,
+}
+
+impl RepoLintCommand {
+    fn to_partial<'a>(&'a self) -> repo::validate::PartialRequest<'a> {
+        repo::validate::PartialRequest::builder()
+            .path(self.repo_path.as_deref())
+            .build()
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct RepoSignCommand {
+    /// File holding a base64 ed25519 secret key, such as one from `openssl rand -base64 32`
+    #[structopt(short, long, parse(from_os_str))]
+    key: PathBuf,
+
+    #[structopt(parse(from_os_str))]
+    repo_path: Option<PathBuf>,
+}
+
+impl RepoSignCommand {
+    fn to_partial<'a>(&'a self) -> repo::sign::PartialRequest<'a> {
+        repo::sign::PartialRequest::builder()
+            .path(self.repo_path.as_deref())
+            .key_path(&self.key)
+            .build()
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct RepoRotateKeysCommand {
+    /// File holding the key the repository is currently signed with
+    #[structopt(short, long, parse(from_os_str))]
+    key: PathBuf,
+
+    /// File to write the new key to, which must not exist yet
+    #[structopt(short, long, parse(from_os_str))]
+    new_key: PathBuf,
+
+    #[structopt(parse(from_os_str))]
+    repo_path: Option<PathBuf>,
+}
+
+impl RepoRotateKeysCommand {
+    fn to_partial<'a>(&'a self) -> repo::rotate_keys::PartialRequest<'a> {
+        repo::rotate_keys::PartialRequest::builder()
+            .path(self.repo_path.as_deref())
+            .key_path(&self.key)
+            .new_key_path(&self.new_key)
+            .build()
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct RepoChannelsCommand {
+    /// Channels offered besides the stable one; none if omitted and not prompted
+    #[structopt(short, long)]
+    channels: Option<Vec<String>>,
+
+    #[structopt(short, long)]
+    default_channel: Option<String>,
+
+    #[structopt(parse(from_os_str))]
+    repo_path: Option<PathBuf>,
+}
+
+impl RepoChannelsCommand {
+    fn to_partial<'a>(&'a self) -> repo::channels::PartialRequest<'a> {
+        repo::channels::PartialRequest::builder()
+            .path(self.repo_path.as_deref())
+            .channels(self.channels.as_deref())
+            .default_channel(self.default_channel.as_deref())
+            .build()
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct RepoAgentCommand {
+    #[structopt(short, long)]
+    name: Option<String>,
+
+    #[structopt(short, long)]
+    version: Option<String>,
+
+    #[structopt(short = "u", long, parse(try_from_str = Url::parse))]
+    url: Option<Url>,
+
+    #[structopt(parse(from_os_str))]
+    repo_path: Option<PathBuf>,
+}
+
+impl RepoAgentCommand {
+    fn to_partial<'a>(&'a self) -> repo::agent::PartialRequest<'a> {
+        repo::agent::PartialRequest::builder()
+            .path(self.repo_path.as_deref())
+            .name(self.name.as_deref())
+            .version(self.version.as_deref())
+            .url(self.url.as_ref())
+            .build()
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct RepoListCommand {
+    #[structopt(parse(from_os_str))]
+    repo_path: Option<PathBuf>,
+}
+
+impl RepoListCommand {
+    fn to_partial<'a>(&'a self) -> repo::list::PartialRequest<'a> {
+        repo::list::PartialRequest::builder()
+            .path(self.repo_path.as_deref())
+            .build()
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct RepoHealthCommand {
+    #[structopt(parse(from_os_str))]
+    repo_path: Option<PathBuf>,
+}
+
+impl RepoHealthCommand {
+    fn to_partial<'a>(&'a self) -> repo::health::PartialRequest<'a> {
+        repo::health::PartialRequest::builder()
+            .path(self.repo_path.as_deref())
+            .build()
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct RepoCheckUrlsCommand {
+    /// Also print the URLs that resolved
+    #[structopt(short, long)]
+    verbose: bool,
+
+    #[structopt(parse(from_os_str))]
+    repo_path: Option<PathBuf>,
+}
+
+impl RepoCheckUrlsCommand {
+    fn to_partial<'a>(&'a self) -> repo::check_urls::PartialRequest<'a> {
+        repo::check_urls::PartialRequest::builder()
+            .path(self.repo_path.as_deref())
+            .build()
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct RepoMigrateLegacyCommand {
+    /// URL of the repository; taken from the `base` of `index.json` if omitted
+    #[structopt(short = "u", long, parse(try_from_str = Url::parse))]
+    url: Option<Url>,
+
+    #[structopt(parse(from_os_str))]
+    repo_path: Option<PathBuf>,
+}
+
+impl RepoMigrateLegacyCommand {
+    fn to_partial<'a>(&'a self) -> repo::legacy::PartialRequest<'a> {
+        repo::legacy::PartialRequest::builder()
+            .path(self.repo_path.as_deref())
+            .url(self.url.as_ref())
+            .build()
+    }
+}
+
 #[derive(Debug, StructOpt)]
 struct PackageInitCommand {
     id: Option<String>,
@@ -103,6 +286,21 @@ struct PackageUpdateCommand {
 
     #[structopt(short, long)]
     url: Option<url::Url>,
+
+    /// RFC 3339 timestamp before which clients will not see this release
+    #[structopt(long)]
+    available_from: Option<String>,
+
+    /// Percentage of machines that are offered this release
+    #[structopt(long)]
+    rollout: Option<u8>,
+
+    /// Oldest Pahkat client version able to install this release
+    #[structopt(long)]
+    min_client_version: Option<String>,
+
+    #[structopt(flatten)]
+    provenance: pahkat_types::package::Provenance,
 }
 
 impl PackageUpdateCommand {
@@ -115,6 +313,10 @@ impl PackageUpdateCommand {
             .repo_path(self.repo_path.as_ref().map(|x| &**x))
             .channel(self.channel.as_ref().map(|x| &**x))
             .url(self.url.as_ref())
+            .available_from(self.available_from.as_deref())
+            .rollout(self.rollout)
+            .min_client_version(self.min_client_version.as_deref())
+            .provenance(Some(&self.provenance).filter(|x| !x.is_empty()))
             .build()
     }
 }
@@ -123,6 +325,19 @@ impl PackageUpdateCommand {
 enum RepoCommand {
     Init(RepoInitCommand),
     Index(RepoIndexCommand),
+    Lint(RepoLintCommand),
+    /// Signs the index files, which must be done again after any change to the repository
+    Sign(RepoSignCommand),
+    /// Signs the repository with a newly generated key in place of the current one
+    RotateKeys(RepoRotateKeysCommand),
+    Channels(RepoChannelsCommand),
+    Agent(RepoAgentCommand),
+    List(RepoListCommand),
+    Health(RepoHealthCommand),
+    /// Requests every payload URL and mirror, reporting the ones that fail
+    CheckUrls(RepoCheckUrlsCommand),
+    /// Converts a pahkat 1 JSON repository to the current layout
+    MigrateLegacy(RepoMigrateLegacyCommand),
 }
 
 #[derive(Debug, StructOpt)]
@@ -193,7 +408,91 @@ fn main() -> anyhow::Result<()> {
             }
             RepoCommand::Index(index) => {
                 let req = repo::indexing::Request::new_from_user_input(index.to_partial())?;
-                repo::indexing::index(req)?;
+                repo::indexing::index_with_progress(req, &IndexProgress)?;
+            }
+            RepoCommand::Lint(lint) => {
+                let req = repo::validate::Request::new_from_user_input(lint.to_partial())?;
+                let issues = repo::validate::validate(req)?;
+                for issue in issues.iter() {
+                    eprintln!("{}", issue);
+                }
+                if !issues.is_empty() {
+                    anyhow::bail!("{} issue(s) found", issues.len());
+                }
+            }
+            RepoCommand::Sign(sign) => {
+                let req = repo::sign::Request::new_from_user_input(sign.to_partial())?;
+                let manifest = repo::sign::sign(req)?;
+                eprintln!("Signed index with serial {}", manifest.serial);
+            }
+            RepoCommand::RotateKeys(rotate) => {
+                let req = repo::rotate_keys::Request::new_from_user_input(rotate.to_partial())?;
+                let rotation = repo::rotate_keys::rotate_keys(req)?;
+                eprintln!(
+                    "Signed index with serial {} using new key {}",
+                    rotation.manifest.serial, rotation.public_key
+                );
+                eprintln!("Clients that trust the old key must be given the new one");
+            }
+            RepoCommand::Channels(channels) => {
+                let req = repo::channels::Request::new_from_user_input(channels.to_partial())?;
+                repo::channels::set_channels(req)?;
+            }
+            RepoCommand::Agent(agent) => {
+                let req = repo::agent::Request::new_from_user_input(agent.to_partial())?;
+                repo::agent::set_agent(req)?;
+            }
+            RepoCommand::List(list) => {
+                let req = repo::list::Request::new_from_user_input(list.to_partial())?;
+                for entry in repo::list::list(req)? {
+                    println!("{}", entry);
+                }
+            }
+            RepoCommand::Health(health) => {
+                let req = repo::health::Request::new_from_user_input(health.to_partial())?;
+                let health = repo::health::health(req)?;
+                print!("{}", health);
+                if !health.is_healthy() {
+                    anyhow::bail!("Repository index is out of date or inconsistent");
+                }
+            }
+            RepoCommand::CheckUrls(check) => {
+                let req = repo::check_urls::Request::new_from_user_input(check.to_partial())?;
+                let checks = repo::check_urls::check_urls(req)?;
+
+                let mut failures = std::collections::BTreeMap::<&str, usize>::new();
+                for x in checks.iter() {
+                    if !x.is_ok() {
+                        *failures.entry(x.host()).or_default() += 1;
+                    }
+                    if check.verbose || !x.is_ok() {
+                        println!("{}", x);
+                    }
+                }
+
+                let redirected = checks.iter().filter(|x| !x.redirects.is_empty()).count();
+                println!(
+                    "Checked {} URLs: {} redirected, {} failed",
+                    checks.len(),
+                    redirected,
+                    failures.values().sum::<usize>()
+                );
+                for (host, count) in failures.iter() {
+                    println!("  {}: {} failed", host, count);
+                }
+                if !failures.is_empty() {
+                    anyhow::bail!("Some payload URLs could not be resolved");
+                }
+            }
+            RepoCommand::MigrateLegacy(migrate) => {
+                let req = repo::legacy::Request::new_from_user_input(migrate.to_partial())?;
+                let migration = repo::legacy::migrate(req)?;
+                for id in migration.packages.iter() {
+                    println!("Migrated package: {}", id);
+                }
+                for id in migration.virtuals.iter() {
+                    println!("Migrated virtual: {}", id);
+                }
             }
         },
         Command::Package(package) => match package {
@@ -1,6 +1,9 @@
 pub mod nuke;
 pub mod package;
 pub mod repo;
+pub mod repository;
+
+pub use repository::Repository;
 
 pub(crate) mod fbs {
     fbs_build::include_fbs!("index");
@@ -17,6 +17,10 @@ pub struct Request<'a> {
     pub version: Cow<'a, Version>,
     pub target: Cow<'a, pahkat_types::payload::Target>,
     pub url: Option<Cow<'a, url::Url>>,
+    pub available_from: Option<Cow<'a, str>>,
+    pub rollout: Option<u8>,
+    pub min_client_version: Option<Cow<'a, str>>,
+    pub provenance: Option<Cow<'a, pahkat_types::package::Provenance>>,
 }
 
 #[non_exhaustive]
@@ -42,6 +46,14 @@ pub struct PartialRequest<'a> {
     pub payload_path: Option<&'a Path>,
     #[builder(default)]
     pub url: Option<&'a url::Url>,
+    #[builder(default)]
+    pub available_from: Option<&'a str>,
+    #[builder(default)]
+    pub rollout: Option<u8>,
+    #[builder(default)]
+    pub min_client_version: Option<&'a str>,
+    #[builder(default)]
+    pub provenance: Option<&'a pahkat_types::package::Provenance>,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -181,6 +193,10 @@ impl<'a> crate::Request for Request<'a> {
             version,
             target: Cow::Owned(target),
             url: partial.url.map(|x| Cow::Borrowed(x)),
+            available_from: partial.available_from.map(|x| Cow::Borrowed(x)),
+            rollout: partial.rollout,
+            min_client_version: partial.min_client_version.map(Cow::Borrowed),
+            provenance: partial.provenance.map(|x| Cow::Borrowed(x)),
         })
     }
 }
@@ -204,12 +220,30 @@ pub enum Error {
 
     #[error("Could not find repository at provided path")]
     NoRepo(#[from] FindRepoError),
+
+    #[error("Available from time `{0}` is not an RFC 3339 timestamp")]
+    InvalidAvailableFrom(String, #[source] chrono::ParseError),
+
+    #[error("Minimum client version `{0}` is not a semantic version")]
+    InvalidMinClientVersion(String, #[source] semver::Error),
 }
 
 pub fn update<'a>(request: Request<'a>) -> Result<(), Error> {
     use std::ops::Deref;
     log::debug!("{:?}", request);
 
+    // Clients skip releases they cannot parse this for, so refuse to write it
+    if let Some(available_from) = request.available_from.as_ref() {
+        chrono::DateTime::parse_from_rfc3339(available_from)
+            .map_err(|e| Error::InvalidAvailableFrom(available_from.to_string(), e))?;
+    }
+
+    // Clients refuse releases whose minimum version they cannot parse
+    if let Some(version) = request.min_client_version.as_ref() {
+        semver::Version::parse(version)
+            .map_err(|e| Error::InvalidMinClientVersion(version.to_string(), e))?;
+    }
+
     let pkg_dir = find_repo(&request.repo_path)?
         .join("packages")
         .join(&*request.id);
@@ -261,6 +295,26 @@ pub fn update<'a>(request: Request<'a>) -> Result<(), Error> {
         }
     };
 
+    if let Some(available_from) = request.available_from.as_ref() {
+        log::info!("Setting available from to {}", &available_from);
+        release.available_from = Some(available_from.to_string());
+    }
+
+    if let Some(rollout) = request.rollout {
+        log::info!("Setting rollout to {}%", rollout);
+        release.rollout = if rollout >= 100 { None } else { Some(rollout) };
+    }
+
+    if let Some(version) = request.min_client_version.as_ref() {
+        log::info!("Setting minimum client version to {}", &version);
+        release.min_client_version = Some(version.to_string());
+    }
+
+    if let Some(provenance) = request.provenance.as_ref() {
+        log::info!("Setting provenance to {:?}", &provenance);
+        release.provenance = Some(provenance.deref().clone());
+    }
+
     // Check if a target exists that meets this criteria
     let target = match release
         .target
@@ -0,0 +1,61 @@
+use std::borrow::Cow;
+use std::path::Path;
+
+use pahkat_types::repo::Agent;
+use typed_builder::TypedBuilder;
+use url::Url;
+
+use crate::repository::{Error, Repository};
+
+/// Records the tool that manages the repository in its index.
+pub fn set_agent(request: Request<'_>) -> Result<(), Error> {
+    let mut repo = Repository::open(&request.path)?;
+    repo.set_agent(request.agent);
+    repo.commit()
+}
+
+#[non_exhaustive]
+#[derive(Debug, Clone, TypedBuilder)]
+pub struct Request<'a> {
+    pub path: Cow<'a, Path>,
+    pub agent: Agent,
+}
+
+/// Fields left unset are taken from this version of repomgr.
+#[non_exhaustive]
+#[derive(Debug, Clone, Default, TypedBuilder)]
+pub struct PartialRequest<'a> {
+    #[builder(default)]
+    pub path: Option<&'a Path>,
+    #[builder(default)]
+    pub name: Option<&'a str>,
+    #[builder(default)]
+    pub version: Option<&'a str>,
+    #[builder(default)]
+    pub url: Option<&'a Url>,
+}
+
+impl<'a> crate::Request for Request<'a> {
+    type Error = std::convert::Infallible;
+    type Partial = PartialRequest<'a>;
+
+    fn new_from_user_input(partial: Self::Partial) -> Result<Self, Self::Error> {
+        let path = partial
+            .path
+            .map(Cow::Borrowed)
+            .unwrap_or_else(|| Cow::Owned(std::env::current_dir().unwrap()));
+
+        let mut agent = super::init::create_agent();
+        if let Some(name) = partial.name {
+            agent.name = name.to_string();
+        }
+        if let Some(version) = partial.version {
+            agent.version = version.to_string();
+        }
+        if let Some(url) = partial.url {
+            agent.url = Some(url.clone());
+        }
+
+        Ok(Request { path, agent })
+    }
+}
@@ -0,0 +1,88 @@
+use std::borrow::Cow;
+use std::path::Path;
+
+use typed_builder::TypedBuilder;
+
+use crate::repository::{Error, Repository};
+
+/// Replaces the channels a repository offers, and which one clients use by default.
+pub fn set_channels(request: Request<'_>) -> Result<(), Error> {
+    let mut repo = Repository::open(&request.path)?;
+    repo.set_channels(
+        request.channels.into_owned(),
+        request.default_channel.map(Cow::into_owned),
+    );
+    repo.commit()
+}
+
+#[non_exhaustive]
+#[derive(Debug, Clone, TypedBuilder)]
+pub struct Request<'a> {
+    pub path: Cow<'a, Path>,
+    pub channels: Cow<'a, [String]>,
+    #[builder(default)]
+    pub default_channel: Option<Cow<'a, str>>,
+}
+
+#[non_exhaustive]
+#[derive(Debug, Clone, Default, TypedBuilder)]
+pub struct PartialRequest<'a> {
+    #[builder(default)]
+    pub path: Option<&'a Path>,
+    #[builder(default)]
+    pub channels: Option<&'a [String]>,
+    #[builder(default)]
+    pub default_channel: Option<&'a str>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RequestError {
+    #[error("Invalid input")]
+    InvalidInput,
+
+    #[error("Default channel `{0}` is not one of the repository's channels")]
+    UnknownDefaultChannel(String),
+}
+
+impl<'a> crate::Request for Request<'a> {
+    type Error = RequestError;
+    type Partial = PartialRequest<'a>;
+
+    fn new_from_user_input(partial: Self::Partial) -> Result<Self, Self::Error> {
+        use dialoguer::Input;
+
+        let path = partial
+            .path
+            .map(Cow::Borrowed)
+            .unwrap_or_else(|| Cow::Owned(std::env::current_dir().unwrap()));
+
+        let channels = match partial.channels {
+            Some(channels) => Cow::Borrowed(channels),
+            None => Cow::Owned(
+                Input::<String>::new()
+                    .with_prompt("Channels (comma-separated)")
+                    .allow_empty(true)
+                    .interact()
+                    .map_err(|_| RequestError::InvalidInput)?
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|x| !x.is_empty())
+                    .map(str::to_string)
+                    .collect::<Vec<_>>(),
+            ),
+        };
+
+        let default_channel = partial.default_channel.map(Cow::Borrowed);
+        if let Some(channel) = default_channel.as_ref() {
+            if !channels.iter().any(|x| x == channel) {
+                return Err(RequestError::UnknownDefaultChannel(channel.to_string()));
+            }
+        }
+
+        Ok(Request {
+            path,
+            channels,
+            default_channel,
+        })
+    }
+}
@@ -0,0 +1,207 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use pahkat_types::package::Version;
+use reqwest::blocking::{Client, Response};
+use reqwest::{header, StatusCode};
+use typed_builder::TypedBuilder;
+use url::Url;
+
+use crate::repository::Repository;
+
+/// Redirects followed before a URL is reported as broken.
+const MAX_REDIRECTS: usize = 10;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Could not open repository")]
+    Repository(#[from] crate::repository::Error),
+
+    #[error("Could not create HTTP client")]
+    Client(#[source] reqwest::Error),
+}
+
+/// The outcome of requesting one payload URL or mirror of a release.
+#[derive(Debug, Clone)]
+pub struct UrlCheck {
+    pub package: String,
+    pub version: Version,
+    pub platform: String,
+    pub url: Url,
+    /// Each URL redirected to, ending with the one that answered.
+    pub redirects: Vec<Url>,
+    /// Why the URL is considered broken, such as `HTTP 404 Not Found`.
+    pub error: Option<String>,
+}
+
+impl UrlCheck {
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none()
+    }
+
+    /// The host that answered, after any redirects.
+    pub fn host(&self) -> &str {
+        self.redirects
+            .last()
+            .unwrap_or(&self.url)
+            .host_str()
+            .unwrap_or_default()
+    }
+}
+
+impl fmt::Display for UrlCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} ({}): {}",
+            self.package, self.version, self.platform, self.url
+        )?;
+        if let Some(url) = self.redirects.last() {
+            write!(f, " -> {} ({} redirects)", url, self.redirects.len())?;
+        }
+        match self.error.as_ref() {
+            Some(e) => write!(f, ": {}", e),
+            None => write!(f, ": OK"),
+        }
+    }
+}
+
+/// Requests the payload URL and mirrors of every release in the repository. Each
+/// distinct URL is only requested once.
+pub fn check_urls(request: Request<'_>) -> Result<Vec<UrlCheck>, Error> {
+    let repo = Repository::open(&request.path)?;
+    let client = Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .user_agent(concat!("pahkat-repomgr/", env!("CARGO_PKG_VERSION")))
+        .build()
+        .map_err(Error::Client)?;
+
+    let mut results = HashMap::<Url, (Vec<Url>, Option<String>)>::new();
+    let mut checks = vec![];
+
+    for descriptor in repo.packages() {
+        for release in descriptor.release.iter() {
+            for target in release.target.iter() {
+                let urls =
+                    std::iter::once(target.payload.url()).chain(target.payload.mirrors().iter());
+
+                for url in urls {
+                    let (redirects, error) = results
+                        .entry(url.clone())
+                        .or_insert_with(|| {
+                            log::debug!("Checking {}", url);
+                            check(&client, url)
+                        })
+                        .clone();
+
+                    checks.push(UrlCheck {
+                        package: descriptor.package.id.clone(),
+                        version: release.version.clone(),
+                        platform: target.platform.clone(),
+                        url: url.clone(),
+                        redirects,
+                        error,
+                    });
+                }
+            }
+        }
+    }
+
+    Ok(checks)
+}
+
+fn check(client: &Client, url: &Url) -> (Vec<Url>, Option<String>) {
+    let mut redirects = vec![];
+    let mut current = url.clone();
+
+    loop {
+        let response = match send(client, &current) {
+            Ok(v) => v,
+            Err(e) => return (redirects, Some(describe(&e))),
+        };
+        let status = response.status();
+
+        if status.is_success() {
+            return (redirects, None);
+        }
+
+        if !status.is_redirection() {
+            return (redirects, Some(format!("HTTP {}", status)));
+        }
+
+        if redirects.len() >= MAX_REDIRECTS {
+            return (redirects, Some("Too many redirects".into()));
+        }
+
+        let location = response
+            .headers()
+            .get(header::LOCATION)
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| current.join(x).ok());
+        match location {
+            Some(v) => {
+                redirects.push(v.clone());
+                current = v;
+            }
+            None => {
+                return (
+                    redirects,
+                    Some(format!("HTTP {} without a valid Location", status)),
+                )
+            }
+        }
+    }
+}
+
+/// Some hosts refuse `HEAD`, so the first byte is requested instead.
+fn send(client: &Client, url: &Url) -> Result<Response, reqwest::Error> {
+    let response = client.head(url.as_str()).send()?;
+    match response.status() {
+        StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED => client
+            .get(url.as_str())
+            .header(header::RANGE, "bytes=0-0")
+            .send(),
+        _ => Ok(response),
+    }
+}
+
+/// TLS and connection errors are only described by the innermost source.
+fn describe(error: &reqwest::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = std::error::Error::source(error);
+    while let Some(e) = source {
+        message.push_str(": ");
+        message.push_str(&e.to_string());
+        source = e.source();
+    }
+    message
+}
+
+#[non_exhaustive]
+#[derive(Debug, Clone, TypedBuilder)]
+pub struct Request<'a> {
+    pub path: Cow<'a, Path>,
+}
+
+#[non_exhaustive]
+#[derive(Debug, Clone, Default, TypedBuilder)]
+pub struct PartialRequest<'a> {
+    #[builder(default)]
+    pub path: Option<&'a Path>,
+}
+
+impl<'a> crate::Request for Request<'a> {
+    type Error = std::convert::Infallible;
+    type Partial = PartialRequest<'a>;
+
+    fn new_from_user_input(partial: Self::Partial) -> Result<Self, Self::Error> {
+        Ok(Request {
+            path: partial
+                .path
+                .map(Cow::Borrowed)
+                .unwrap_or_else(|| Cow::Owned(std::env::current_dir().unwrap())),
+        })
+    }
+}
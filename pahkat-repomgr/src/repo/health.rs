@@ -0,0 +1,161 @@
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use typed_builder::TypedBuilder;
+
+use crate::repository::{Error, Repository};
+
+/// Whether the generated package index still reflects the descriptors on disk.
+///
+/// A repository is left in an inconsistent state when indexing or a commit
+/// fails halfway, which otherwise goes unnoticed until clients miss updates.
+#[derive(Debug, Clone, Default)]
+pub struct Health {
+    /// Modification time of `packages/index.bin`
+    pub indexed_at: Option<SystemTime>,
+    /// Why `packages/index.bin` could not be read, if it could not
+    pub index_error: Option<String>,
+    /// Packages with a descriptor that are missing from the index
+    pub unindexed: Vec<String>,
+    /// Packages in the index without a descriptor
+    pub orphaned: Vec<String>,
+    /// Packages whose descriptor changed after the index was generated
+    pub changed: Vec<String>,
+    /// Files staged by a commit that did not finish
+    pub staged: Vec<PathBuf>,
+}
+
+impl Health {
+    pub fn is_healthy(&self) -> bool {
+        self.index_error.is_none()
+            && self.unindexed.is_empty()
+            && self.orphaned.is_empty()
+            && self.changed.is_empty()
+            && self.staged.is_empty()
+    }
+}
+
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.indexed_at.and_then(|x| x.elapsed().ok()) {
+            Some(age) => writeln!(f, "Indexed {} seconds ago", age.as_secs())?,
+            None => writeln!(f, "Never indexed")?,
+        }
+        if let Some(e) = self.index_error.as_ref() {
+            writeln!(f, "Index is unreadable: {}", e)?;
+        }
+        for id in self.unindexed.iter() {
+            writeln!(f, "Not indexed: {}", id)?;
+        }
+        for id in self.orphaned.iter() {
+            writeln!(f, "Indexed without descriptor: {}", id)?;
+        }
+        for id in self.changed.iter() {
+            writeln!(f, "Changed since indexing: {}", id)?;
+        }
+        for path in self.staged.iter() {
+            writeln!(f, "Uncommitted: {}", path.display())?;
+        }
+        Ok(())
+    }
+}
+
+pub fn health(request: Request<'_>) -> Result<Health, Error> {
+    let repo = Repository::open(&request.path)?;
+    let packages_path = repo.path().join("packages");
+    let index_path = packages_path.join("index.bin");
+
+    let mut health = Health {
+        indexed_at: fs::metadata(&index_path).and_then(|x| x.modified()).ok(),
+        ..Default::default()
+    };
+
+    let descriptors = repo
+        .packages()
+        .map(|x| x.package.id.clone())
+        .collect::<BTreeSet<_>>();
+
+    match read_indexed_ids(&index_path) {
+        Ok(indexed) => {
+            health.unindexed = descriptors.difference(&indexed).cloned().collect();
+            health.orphaned = indexed.difference(&descriptors).cloned().collect();
+        }
+        Err(e) => health.index_error = Some(e),
+    }
+
+    if let Some(indexed_at) = health.indexed_at {
+        health.changed = descriptors
+            .iter()
+            .filter(|id| {
+                fs::metadata(packages_path.join(id).join("index.toml"))
+                    .and_then(|x| x.modified())
+                    .map(|x| x > indexed_at)
+                    .unwrap_or(false)
+            })
+            .cloned()
+            .collect();
+    }
+
+    health.staged = staged_files(repo.path());
+    for id in descriptors.iter() {
+        health.staged.extend(staged_files(&packages_path.join(id)));
+    }
+
+    Ok(health)
+}
+
+fn read_indexed_ids(path: &Path) -> Result<BTreeSet<String>, String> {
+    let data = fs::read(path).map_err(|e| e.to_string())?;
+    let packages =
+        crate::fbs::pahkat::Packages::get_root(&*data).map_err(|e| format!("{:?}", e))?;
+    let keys = match packages.packages_keys().map_err(|e| format!("{:?}", e))? {
+        Some(v) => v,
+        None => return Ok(BTreeSet::new()),
+    };
+
+    keys.iter()
+        .map(|x| x.map(str::to_string).map_err(|e| format!("{:?}", e)))
+        .collect()
+}
+
+fn staged_files(dir: &Path) -> Vec<PathBuf> {
+    fs::read_dir(dir)
+        .map(|x| {
+            x.filter_map(Result::ok)
+                .map(|x| x.path())
+                .filter(|x| x.to_string_lossy().ends_with(".toml.tmp"))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[non_exhaustive]
+#[derive(Debug, Clone, TypedBuilder)]
+pub struct Request<'a> {
+    pub path: Cow<'a, Path>,
+}
+
+#[non_exhaustive]
+#[derive(Debug, Clone, Default, TypedBuilder)]
+pub struct PartialRequest<'a> {
+    #[builder(default)]
+    pub path: Option<&'a Path>,
+}
+
+impl<'a> crate::Request for Request<'a> {
+    type Error = std::convert::Infallible;
+    type Partial = PartialRequest<'a>;
+
+    fn new_from_user_input(partial: Self::Partial) -> Result<Self, Self::Error> {
+        Ok(Request {
+            path: partial
+                .path
+                .map(Cow::Borrowed)
+                .unwrap_or_else(|| Cow::Owned(std::env::current_dir().unwrap())),
+        })
+    }
+}
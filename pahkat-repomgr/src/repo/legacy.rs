@@ -0,0 +1,242 @@
+//! Reading and migrating repositories in the JSON-LD layout of pahkat 1, with
+//! `index.json`, `packages/index.json` and `virtuals/index.json`.
+//!
+//! Legacy packages had a single installer, which becomes the only release of the
+//! migrated descriptor. Virtual packages become synthetic descriptors, written to
+//! `virtuals/` as the package index cannot hold them yet.
+
+use std::borrow::Cow;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use typed_builder::TypedBuilder;
+use url::Url;
+
+use pahkat_types::legacy;
+pub use pahkat_types::legacy::{read_index, read_packages, read_virtuals};
+use pahkat_types::repo::RepoUrl;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Failed to read file `{0}`")]
+    ReadFailed(PathBuf, #[source] io::Error),
+
+    #[error("Failed to parse legacy JSON file `{0}`")]
+    ReadJson(PathBuf, #[source] serde_json::Error),
+
+    #[error("Legacy index has no valid `base` URL; provide one with --url")]
+    MissingUrl,
+
+    #[error("Repository is already migrated, as `{0}` exists")]
+    AlreadyMigrated(PathBuf),
+
+    #[error("Failed to create directory `{0}`")]
+    DirCreateFailed(PathBuf, #[source] io::Error),
+
+    #[error("Failed to write file `{0}`")]
+    WriteFailed(PathBuf, #[source] io::Error),
+
+    #[error("Failed to serialize TOML for `{0}`")]
+    SerializeToml(PathBuf, #[source] toml::ser::Error),
+
+    #[error("Failed to build index: {0}")]
+    Index(anyhow::Error),
+}
+
+/// What was written by [`migrate`].
+#[derive(Debug, Clone, Default)]
+pub struct Migration {
+    pub packages: Vec<String>,
+    pub virtuals: Vec<String>,
+}
+
+/// Writes `index.toml`, a descriptor for each legacy package and virtual and then the
+/// package index. The legacy files are left in place.
+pub fn migrate(request: Request<'_>) -> Result<Migration, Error> {
+    let path = &*request.path;
+    let index_path = path.join("index.toml");
+    if index_path.exists() {
+        return Err(Error::AlreadyMigrated(index_path));
+    }
+
+    let index = read_index(&read(&path.join("index.json"))?, request.url.as_deref())
+        .map_err(|e| with_path(e, path.join("index.json")))?;
+
+    let packages_path = path.join("packages").join("index.json");
+    let packages =
+        read_packages(&read(&packages_path)?).map_err(|e| with_path(e, packages_path))?;
+
+    let virtuals_path = path.join("virtuals").join("index.json");
+    let virtuals = if virtuals_path.exists() {
+        read_virtuals(&read(&virtuals_path)?).map_err(|e| with_path(e, virtuals_path))?
+    } else {
+        vec![]
+    };
+
+    let mut migration = Migration::default();
+    for descriptor in packages.iter() {
+        let id = &descriptor.package.id;
+        write_toml(
+            &path.join("packages").join(id).join("index.toml"),
+            descriptor,
+        )?;
+        migration.packages.push(id.clone());
+    }
+    for descriptor in virtuals.iter() {
+        let id = &descriptor.synthetic.id;
+        write_toml(
+            &path.join("virtuals").join(id).join("index.toml"),
+            descriptor,
+        )?;
+        migration.virtuals.push(id.clone());
+    }
+    write_toml(&index_path, &index)?;
+
+    let request = super::indexing::Request::builder()
+        .path(Cow::Borrowed(path))
+        .build();
+    super::indexing::index(request).map_err(Error::Index)?;
+
+    Ok(migration)
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, Error> {
+    fs::read(path).map_err(|e| Error::ReadFailed(path.to_path_buf(), e))
+}
+
+fn with_path(error: legacy::Error, path: PathBuf) -> Error {
+    match error {
+        legacy::Error::Json(e) => Error::ReadJson(path, e),
+        legacy::Error::MissingUrl => Error::MissingUrl,
+    }
+}
+
+fn write_toml<T: Serialize>(path: &Path, value: &T) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| Error::DirCreateFailed(parent.to_path_buf(), e))?;
+    }
+    let data =
+        toml::to_string_pretty(value).map_err(|e| Error::SerializeToml(path.to_path_buf(), e))?;
+    fs::write(path, data).map_err(|e| Error::WriteFailed(path.to_path_buf(), e))
+}
+
+#[non_exhaustive]
+#[derive(Debug, Clone, TypedBuilder)]
+pub struct Request<'a> {
+    pub path: Cow<'a, Path>,
+    #[builder(default)]
+    pub url: Option<Cow<'a, RepoUrl>>,
+}
+
+#[non_exhaustive]
+#[derive(Debug, Clone, Default, TypedBuilder)]
+pub struct PartialRequest<'a> {
+    #[builder(default)]
+    pub path: Option<&'a Path>,
+    #[builder(default)]
+    pub url: Option<&'a Url>,
+}
+
+impl<'a> crate::Request for Request<'a> {
+    type Error = pahkat_types::repo::RepoUrlError;
+    type Partial = PartialRequest<'a>;
+
+    fn new_from_user_input(partial: Self::Partial) -> Result<Self, Self::Error> {
+        let path = partial
+            .path
+            .map(Cow::Borrowed)
+            .unwrap_or_else(|| Cow::Owned(std::env::current_dir().unwrap()));
+        let url = match partial.url {
+            Some(url) => Some(Cow::Owned(RepoUrl::new(url.clone())?)),
+            None => None,
+        };
+
+        Ok(Request { path, url })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pahkat_types::payload::{self, Payload};
+
+    #[test]
+    fn migrates_legacy_repository() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path();
+        fs::create_dir_all(path.join("packages")).unwrap();
+        fs::create_dir_all(path.join("virtuals")).unwrap();
+
+        let index = serde_json::json!({
+            "@type": "Repository",
+            "base": "https://pahkat.example/main/",
+            "name": { "en": "Main" },
+            "channels": ["stable", "beta"],
+            "agent": { "name": "pahkat", "version": "0.6.0" },
+        });
+        let packages = serde_json::json!({
+            "@type": "Packages",
+            "packages": {
+                "keyboard-sme": {
+                    "@type": "Package",
+                    "id": "keyboard-sme",
+                    "version": "2.0.1",
+                    "category": "keyboards",
+                    "languages": ["sme"],
+                    "installer": {
+                        "@type": "MacOSInstaller",
+                        "url": "https://pahkat.example/keyboard-sme.pkg",
+                        "pkgId": "no.uit.giella.keyboards.sme",
+                        "targets": ["system", "user"],
+                        "requiresReboot": true,
+                        "size": 1000,
+                        "installedSize": 4000,
+                    },
+                },
+            },
+        });
+        let virtuals = serde_json::json!({
+            "@type": "Virtuals",
+            "virtuals": {
+                "msoffice": {
+                    "@type": "Virtual",
+                    "id": "msoffice",
+                    "version": "16.0",
+                    "target": {
+                        "registryKey": {
+                            "path": "HKEY_LOCAL_MACHINE\\SOFTWARE\\Microsoft\\Office\\16.0",
+                            "name": "Path",
+                        },
+                    },
+                },
+            },
+        });
+        fs::write(path.join("index.json"), index.to_string()).unwrap();
+        fs::write(path.join("packages/index.json"), packages.to_string()).unwrap();
+        fs::write(path.join("virtuals/index.json"), virtuals.to_string()).unwrap();
+
+        let migration = migrate(Request::builder().path(Cow::Borrowed(path)).build()).unwrap();
+        assert_eq!(migration.packages, vec!["keyboard-sme"]);
+        assert_eq!(migration.virtuals, vec!["msoffice"]);
+        assert!(path.join("packages/index.bin").exists());
+
+        let repo = crate::Repository::open(path).unwrap();
+        assert_eq!(repo.index().repository.channels, vec!["stable", "beta"]);
+        let descriptor = repo.package("keyboard-sme").unwrap();
+        match &descriptor.release[0].target[0].payload {
+            Payload::MacOSPackage(x) => {
+                assert_eq!(x.pkg_id, "no.uit.giella.keyboards.sme");
+                assert_eq!(x.targets.len(), 2);
+                assert!(x
+                    .requires_reboot
+                    .contains(&payload::macos::RebootSpec::Install));
+            }
+            x => panic!("Unexpected payload {:?}", x),
+        }
+
+        let result = migrate(Request::builder().path(Cow::Borrowed(path)).build());
+        assert!(matches!(result, Err(Error::AlreadyMigrated(_))));
+    }
+}
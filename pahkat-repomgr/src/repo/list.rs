@@ -0,0 +1,76 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::path::Path;
+
+use pahkat_types::package::Version;
+use typed_builder::TypedBuilder;
+
+use crate::repository::{Error, Repository};
+
+/// A package in the repository and the releases it has, newest first.
+#[derive(Debug, Clone)]
+pub struct PackageEntry {
+    pub id: String,
+    pub releases: Vec<(Version, Option<String>)>,
+}
+
+impl fmt::Display for PackageEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.id)?;
+        for (i, (version, channel)) in self.releases.iter().enumerate() {
+            let sep = if i == 0 { ": " } else { ", " };
+            match channel {
+                Some(channel) => write!(f, "{}{} ({})", sep, version, channel)?,
+                None => write!(f, "{}{}", sep, version)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn list(request: Request<'_>) -> Result<Vec<PackageEntry>, Error> {
+    let repo = Repository::open(&request.path)?;
+
+    Ok(repo
+        .packages()
+        .map(|descriptor| {
+            let mut releases = descriptor.release.iter().collect::<Vec<_>>();
+            releases.sort_by(|a, b| b.cmp(a));
+
+            PackageEntry {
+                id: descriptor.package.id.clone(),
+                releases: releases
+                    .into_iter()
+                    .map(|x| (x.version.clone(), x.channel.clone()))
+                    .collect(),
+            }
+        })
+        .collect())
+}
+
+#[non_exhaustive]
+#[derive(Debug, Clone, TypedBuilder)]
+pub struct Request<'a> {
+    pub path: Cow<'a, Path>,
+}
+
+#[non_exhaustive]
+#[derive(Debug, Clone, Default, TypedBuilder)]
+pub struct PartialRequest<'a> {
+    #[builder(default)]
+    pub path: Option<&'a Path>,
+}
+
+impl<'a> crate::Request for Request<'a> {
+    type Error = std::convert::Infallible;
+    type Partial = PartialRequest<'a>;
+
+    fn new_from_user_input(partial: Self::Partial) -> Result<Self, Self::Error> {
+        Ok(Request {
+            path: partial
+                .path
+                .map(Cow::Borrowed)
+                .unwrap_or_else(|| Cow::Owned(std::env::current_dir().unwrap())),
+        })
+    }
+}
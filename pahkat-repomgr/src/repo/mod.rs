@@ -1,3 +1,11 @@
+pub mod agent;
+pub mod channels;
+pub mod check_urls;
+pub mod health;
 pub mod indexing;
 pub mod init;
+pub mod legacy;
+pub mod list;
+pub mod rotate_keys;
+pub mod sign;
 pub mod validate;
@@ -0,0 +1,184 @@
+//! Replaces the signing key of a repository with a newly generated one and signs the
+//! repository with it. Clients that pinned the old key refuse the new one until it is
+//! accepted, so the new public key needs to be distributed to them.
+
+use std::borrow::Cow;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use ed25519_dalek::Keypair;
+use pahkat_types::repo::Manifest;
+use typed_builder::TypedBuilder;
+
+use super::sign;
+use crate::repository::Repository;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Signing key `{0}` is not the key the repository is signed with")]
+    KeyMismatch(PathBuf),
+
+    #[error("Failed to write signing key `{0}`")]
+    WriteKey(PathBuf, #[source] io::Error),
+
+    #[error(transparent)]
+    Sign(#[from] sign::Error),
+
+    #[error(transparent)]
+    Repository(#[from] crate::repository::Error),
+}
+
+/// The public key now advertised and the manifest signed with it.
+#[derive(Debug, Clone)]
+pub struct Rotation {
+    pub public_key: String,
+    pub manifest: Manifest,
+}
+
+/// Writes a new key to `new_key_path`, which must not exist yet, and signs the
+/// repository with it. The current key is only needed to prove the repository is
+/// ours; it is left in place.
+pub fn rotate_keys(request: Request<'_>) -> Result<Rotation, Error> {
+    let current = sign::read_keypair(&request.key_path)?;
+    let repo = Repository::open(&request.path)?;
+    let advertised = repo.index().repository.signing_key.as_deref();
+    if advertised != Some(&*base64::encode(current.public.as_bytes())) {
+        return Err(Error::KeyMismatch(request.key_path.to_path_buf()));
+    }
+
+    let keypair = Keypair::generate(&mut rand::rngs::OsRng);
+    write_key(&request.new_key_path, &keypair)?;
+    log::info!(
+        "Wrote new signing key to {}",
+        request.new_key_path.display()
+    );
+
+    let manifest = sign::sign(
+        sign::Request::builder()
+            .path(request.path.clone())
+            .key_path(request.new_key_path.clone())
+            .build(),
+    )?;
+
+    Ok(Rotation {
+        public_key: base64::encode(keypair.public.as_bytes()),
+        manifest,
+    })
+}
+
+fn write_key(path: &Path, keypair: &Keypair) -> Result<(), Error> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(base64::encode(keypair.secret.as_bytes()).as_bytes()))
+        .map_err(|e| Error::WriteKey(path.to_path_buf(), e))
+}
+
+#[non_exhaustive]
+#[derive(Debug, Clone, TypedBuilder)]
+pub struct Request<'a> {
+    pub path: Cow<'a, Path>,
+    pub key_path: Cow<'a, Path>,
+    pub new_key_path: Cow<'a, Path>,
+}
+
+#[non_exhaustive]
+#[derive(Debug, Clone, TypedBuilder)]
+pub struct PartialRequest<'a> {
+    #[builder(default)]
+    pub path: Option<&'a Path>,
+    pub key_path: &'a Path,
+    pub new_key_path: &'a Path,
+}
+
+impl<'a> crate::Request for Request<'a> {
+    type Error = std::convert::Infallible;
+    type Partial = PartialRequest<'a>;
+
+    fn new_from_user_input(partial: Self::Partial) -> Result<Self, Self::Error> {
+        let path = partial
+            .path
+            .map(Cow::Borrowed)
+            .unwrap_or_else(|| Cow::Owned(std::env::current_dir().unwrap()));
+
+        Ok(Request {
+            path,
+            key_path: Cow::Borrowed(partial.key_path),
+            new_key_path: Cow::Borrowed(partial.new_key_path),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INDEX: &str = r#"
+[repository]
+url = "https://pahkat.example/repo/"
+
+[agent]
+name = "pahkat"
+version = "2.3.0"
+"#;
+
+    #[test]
+    fn rotates_to_a_new_key_only_with_the_current_one() {
+        let dir = tempfile::tempdir().unwrap();
+        let key_path = dir.path().join("signing.key");
+        let new_key_path = dir.path().join("new.key");
+        fs::write(&key_path, base64::encode([7u8; 32])).unwrap();
+        let repo_path = dir.path().join("repo");
+        fs::create_dir_all(&repo_path).unwrap();
+        fs::write(repo_path.join("index.toml"), INDEX).unwrap();
+
+        let request = |key_path: &Path| {
+            Request::builder()
+                .path(Cow::Borrowed(&*repo_path))
+                .key_path(Cow::Owned(key_path.to_path_buf()))
+                .new_key_path(Cow::Borrowed(&*new_key_path))
+                .build()
+        };
+
+        // Not signed yet, so there is no key to prove ownership with
+        assert!(matches!(
+            rotate_keys(request(&key_path)),
+            Err(Error::KeyMismatch(_))
+        ));
+
+        sign::sign(
+            sign::Request::builder()
+                .path(Cow::Borrowed(&*repo_path))
+                .key_path(Cow::Borrowed(&*key_path))
+                .build(),
+        )
+        .unwrap();
+        let rotation = rotate_keys(request(&key_path)).unwrap();
+
+        let new_key = sign::read_keypair(&new_key_path).unwrap();
+        assert_eq!(
+            rotation.public_key,
+            base64::encode(new_key.public.as_bytes())
+        );
+        let repo = Repository::open(&repo_path).unwrap();
+        assert_eq!(
+            repo.index().repository.signing_key.as_deref(),
+            Some(&*rotation.public_key)
+        );
+
+        // The old key no longer matches, and an existing key file is never replaced
+        assert!(matches!(
+            rotate_keys(request(&key_path)),
+            Err(Error::KeyMismatch(_))
+        ));
+        assert!(matches!(
+            rotate_keys(request(&new_key_path)),
+            Err(Error::WriteKey(..))
+        ));
+    }
+}
@@ -0,0 +1,193 @@
+//! Signs a repository for clients that verify its indexes, by writing a `manifest.toml`
+//! of its index files and `manifest.toml.sig`, the base64 ed25519 signature of the
+//! manifest. Any change to the repository needs it to be signed again.
+
+use std::borrow::Cow;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signer};
+use pahkat_types::repo::Manifest;
+use typed_builder::TypedBuilder;
+
+use crate::repository::Repository;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Failed to read signing key `{0}`")]
+    ReadKey(PathBuf, #[source] io::Error),
+
+    #[error("Signing key `{0}` is not a base64 ed25519 secret key")]
+    InvalidKey(PathBuf),
+
+    #[error("Failed to read file `{0}`; is the repository indexed?")]
+    ReadFailed(PathBuf, #[source] io::Error),
+
+    #[error("Failed to write file `{0}`")]
+    WriteFailed(PathBuf, #[source] io::Error),
+
+    #[error(transparent)]
+    Repository(#[from] crate::repository::Error),
+}
+
+/// Advertises the public key of the signing key in `index.toml` if it is not yet, and
+/// signs the index files. Returns the manifest written.
+pub fn sign(request: Request<'_>) -> Result<Manifest, Error> {
+    let path = &*request.path;
+    let keypair = read_keypair(&request.key_path)?;
+    let public_key = base64::encode(keypair.public.as_bytes());
+
+    let mut repo = Repository::open(path)?;
+    if repo.index().repository.signing_key.as_deref() != Some(&*public_key) {
+        log::info!("Advertising signing key {}", &public_key);
+        repo.set_signing_key(Some(public_key));
+        repo.commit()?;
+    }
+
+    let mut files = vec![];
+    for file in Manifest::FILES {
+        let file_path = path.join(file);
+        let data = fs::read(&file_path).map_err(|e| Error::ReadFailed(file_path, e))?;
+        files.push((*file, data));
+    }
+    let manifest = Manifest::new(
+        repo.index().repository.url.clone(),
+        next_serial(path),
+        files.iter().map(|(file, data)| (*file, &**data)),
+    );
+
+    let data = toml::to_vec(&manifest).expect("manifest serializes");
+    let signature = base64::encode(keypair.sign(&data).to_bytes());
+    write(&path.join(Manifest::PATH), &data)?;
+    write(
+        &path.join(format!("{}.sig", Manifest::PATH)),
+        signature.as_bytes(),
+    )?;
+
+    Ok(manifest)
+}
+
+pub(crate) fn read_keypair(path: &Path) -> Result<Keypair, Error> {
+    let key = fs::read_to_string(path).map_err(|e| Error::ReadKey(path.to_path_buf(), e))?;
+    let secret = base64::decode(key.trim())
+        .ok()
+        .and_then(|x| SecretKey::from_bytes(&x).ok())
+        .ok_or_else(|| Error::InvalidKey(path.to_path_buf()))?;
+    let public = PublicKey::from(&secret);
+    Ok(Keypair { secret, public })
+}
+
+/// The current time, so that serials keep increasing if the manifest is lost, unless
+/// the current manifest was signed later than that.
+fn next_serial(path: &Path) -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0);
+    let current = fs::read(path.join(Manifest::PATH))
+        .ok()
+        .and_then(|x| toml::from_slice::<Manifest>(&x).ok())
+        .map(|x| x.serial);
+
+    match current {
+        Some(serial) if serial >= now => serial + 1,
+        _ => now,
+    }
+}
+
+/// Written next to `path` first, so that clients never read a partially written file.
+fn write(path: &Path, data: &[u8]) -> Result<(), Error> {
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, data)
+        .and_then(|_| fs::rename(&tmp_path, path))
+        .map_err(|e| Error::WriteFailed(path.to_path_buf(), e))
+}
+
+#[non_exhaustive]
+#[derive(Debug, Clone, TypedBuilder)]
+pub struct Request<'a> {
+    pub path: Cow<'a, Path>,
+    pub key_path: Cow<'a, Path>,
+}
+
+#[non_exhaustive]
+#[derive(Debug, Clone, TypedBuilder)]
+pub struct PartialRequest<'a> {
+    #[builder(default)]
+    pub path: Option<&'a Path>,
+    pub key_path: &'a Path,
+}
+
+impl<'a> crate::Request for Request<'a> {
+    type Error = std::convert::Infallible;
+    type Partial = PartialRequest<'a>;
+
+    fn new_from_user_input(partial: Self::Partial) -> Result<Self, Self::Error> {
+        let path = partial
+            .path
+            .map(Cow::Borrowed)
+            .unwrap_or_else(|| Cow::Owned(std::env::current_dir().unwrap()));
+
+        Ok(Request {
+            path,
+            key_path: Cow::Borrowed(partial.key_path),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::{Signature, Verifier};
+
+    use super::*;
+
+    const INDEX: &str = r#"
+[repository]
+url = "https://pahkat.example/repo/"
+
+[agent]
+name = "pahkat"
+version = "2.3.0"
+"#;
+
+    #[test]
+    fn signs_index_files_with_increasing_serials() {
+        let dir = tempfile::tempdir().unwrap();
+        let key_path = dir.path().join("signing.key");
+        fs::write(&key_path, base64::encode([7u8; 32])).unwrap();
+        let repo_path = dir.path().join("repo");
+        fs::create_dir_all(&repo_path).unwrap();
+        fs::write(repo_path.join("index.toml"), INDEX).unwrap();
+        let sign = || {
+            let request = Request::builder()
+                .path(Cow::Borrowed(&*repo_path))
+                .key_path(Cow::Borrowed(&*key_path))
+                .build();
+            sign(request).unwrap()
+        };
+
+        let first = sign();
+        let second = sign();
+        assert!(second.serial > first.serial);
+
+        let public = read_keypair(&key_path).unwrap().public;
+        let repo = Repository::open(&repo_path).unwrap();
+        assert_eq!(
+            repo.index().repository.signing_key,
+            Some(base64::encode(public.as_bytes()))
+        );
+
+        let data = fs::read(repo_path.join(Manifest::PATH)).unwrap();
+        let signature = fs::read_to_string(repo_path.join("manifest.toml.sig")).unwrap();
+        let signature = Signature::from_bytes(&base64::decode(signature).unwrap()).unwrap();
+        assert!(public.verify(&data, &signature).is_ok());
+
+        let manifest: Manifest = toml::from_slice(&data).unwrap();
+        assert_eq!(manifest, second);
+        for file in Manifest::FILES {
+            assert!(manifest.contains(file, &fs::read(repo_path.join(file)).unwrap()));
+        }
+    }
+}
@@ -1 +1,433 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::path::Path;
 
+use pahkat_types::package::{Release, Version};
+use pahkat_types::payload::{
+    debian, flatpak, macos, rpm, snap, windows, windows::InstallerKind, InstallOption, Payload,
+};
+use typed_builder::TypedBuilder;
+
+use crate::repository::{Error, Repository};
+
+/// A problem found in a package descriptor that would not be caught when indexing.
+#[derive(Debug, Clone)]
+pub struct Issue {
+    pub package: String,
+    pub version: Version,
+    /// The platform of the target with the problem, or `None` for the release itself.
+    pub platform: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.platform.as_ref() {
+            Some(platform) => write!(
+                f,
+                "{} {} ({}): {}",
+                self.package, self.version, platform, self.message
+            ),
+            None => write!(f, "{} {}: {}", self.package, self.version, self.message),
+        }
+    }
+}
+
+pub fn validate(request: Request<'_>) -> Result<Vec<Issue>, Error> {
+    let repo = Repository::open(&request.path)?;
+    let mut issues = vec![];
+
+    for descriptor in repo.packages() {
+        for release in descriptor.release.iter() {
+            issues.extend(lint_release(release).into_iter().map(|message| Issue {
+                package: descriptor.package.id.clone(),
+                version: release.version.clone(),
+                platform: None,
+                message,
+            }));
+
+            for target in release.target.iter() {
+                let mut messages = match &target.payload {
+                    Payload::WindowsExecutable(p) => lint_windows_executable(p),
+                    Payload::WindowsMsix(p) => lint_windows_msix(p),
+                    Payload::MacOSPackage(p) => lint_macos_package(p),
+                    Payload::MacOSAppBundle(p) => lint_macos_app_bundle(p),
+                    Payload::DebianPackage(p) => lint_debian_package(p),
+                    Payload::RpmPackage(p) => lint_rpm_package(p),
+                    Payload::Flatpak(p) => lint_flatpak(p),
+                    Payload::Snap(p) => lint_snap(p),
+                    _ => vec![],
+                };
+                messages.extend(lint_install_options(target.payload.install_options()));
+
+                issues.extend(messages.into_iter().map(|message| Issue {
+                    package: descriptor.package.id.clone(),
+                    version: release.version.clone(),
+                    platform: Some(target.platform.clone()),
+                    message,
+                }));
+            }
+        }
+    }
+
+    Ok(issues)
+}
+
+fn lint_release(release: &Release) -> Vec<String> {
+    let mut messages = vec![];
+
+    if let Some(available_from) = release.available_from.as_ref() {
+        if let Err(e) = chrono::DateTime::parse_from_rfc3339(available_from) {
+            messages.push(format!(
+                "`available_from` of `{}` is not an RFC 3339 timestamp: {}",
+                available_from, e
+            ));
+        }
+    }
+
+    if let Some(version) = release.min_client_version.as_ref() {
+        if let Err(e) = semver::Version::parse(version) {
+            messages.push(format!(
+                "`min_client_version` of `{}` is not a semantic version: {}",
+                version, e
+            ));
+        }
+    }
+
+    messages
+}
+
+fn lint_windows_executable(payload: &windows::Executable) -> Vec<String> {
+    let mut messages = vec![];
+
+    if let Some(InstallerKind::Other(name)) = payload.kind.as_ref() {
+        if payload.args.is_none() {
+            messages.push(format!(
+                "Unknown installer kind `{}` cannot be installed silently without `args`",
+                name
+            ));
+        }
+        if payload.uninstall_args.is_none() {
+            messages.push(format!(
+                "Unknown installer kind `{}` cannot be uninstalled silently without `uninstall_args`",
+                name
+            ));
+        }
+    }
+
+    let quiet_command = payload
+        .uninstall
+        .as_ref()
+        .and_then(|x| x.quiet_command.as_ref());
+    if quiet_command.map(|x| x.trim().is_empty()).unwrap_or(false) {
+        messages.push("Uninstall `quiet_command` must not be empty".into());
+    }
+
+    // Options of MSI installers are passed as public properties
+    for option in payload.options.iter() {
+        if payload.kind == Some(InstallerKind::Msi) && !windows::is_msi_property_name(&option.id) {
+            messages.push(format!(
+                "Install option `{}` is not a public MSI property name",
+                option.id
+            ));
+        }
+    }
+
+    if payload.msi_properties.is_empty() && payload.msi_transforms.is_empty() {
+        return messages;
+    }
+
+    if payload.kind != Some(InstallerKind::Msi) {
+        messages.push("MSI properties and transforms are only applied to `msi` installers".into());
+    } else if payload.args.is_some() {
+        messages.push("MSI properties and transforms are ignored when `args` is set".into());
+    }
+
+    for key in payload.msi_properties.keys() {
+        if !windows::is_msi_property_name(key) {
+            messages.push(format!(
+                "`{}` is not a public MSI property name (uppercase letters, digits, `_` and `.`)",
+                key
+            ));
+        }
+    }
+
+    for transform in payload.msi_transforms.iter() {
+        if !windows::is_valid_msi_transform(transform) {
+            messages.push(format!(
+                "Transform `{}` must not be empty or contain `;` or `\"`",
+                transform
+            ));
+        } else if !transform.to_lowercase().ends_with(".mst") {
+            messages.push(format!("Transform `{}` is not an .mst file", transform));
+        }
+    }
+
+    messages
+}
+
+fn lint_install_options(options: &[InstallOption]) -> Vec<String> {
+    let mut messages = vec![];
+
+    for (i, option) in options.iter().enumerate() {
+        if options[..i].iter().any(|x| x.id == option.id) {
+            messages.push(format!(
+                "Install option `{}` is declared more than once",
+                option.id
+            ));
+        }
+        match option.default.as_ref() {
+            Some(value) if !option.values.is_empty() && !option.values.contains(value) => {
+                messages.push(format!(
+                    "Default `{}` of install option `{}` is not one of its values",
+                    value, option.id
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    messages
+}
+
+fn lint_macos_package(payload: &macos::Package) -> Vec<String> {
+    let choice_changes = [
+        (&payload.choice_changes, &payload.choice_changes_sha256),
+        (
+            &payload.user_choice_changes,
+            &payload.user_choice_changes_sha256,
+        ),
+    ];
+
+    let mut messages = vec![];
+    for (value, sha256) in choice_changes {
+        let value = match value {
+            Some(v) => v,
+            None => continue,
+        };
+        if macos::choice_changes_url(value).is_some() {
+            if sha256.is_none() {
+                messages.push(format!(
+                    "Choice changes from {} need a SHA-256 checksum",
+                    value
+                ));
+            }
+        } else if !macos::is_inline_choice_changes(value) {
+            messages.push("Choice changes must be an https URL or an inline XML plist".into());
+        }
+    }
+    messages
+}
+
+fn lint_macos_app_bundle(payload: &macos::AppBundle) -> Vec<String> {
+    if macos::is_valid_app_name(&payload.app_name) {
+        vec![]
+    } else {
+        vec![format!("`{}` is not an .app bundle name", payload.app_name)]
+    }
+}
+
+fn lint_debian_package(payload: &debian::Package) -> Vec<String> {
+    if debian::is_valid_name(&payload.package) {
+        vec![]
+    } else {
+        vec![format!(
+            "`{}` is not a valid Debian package name",
+            payload.package
+        )]
+    }
+}
+
+fn lint_windows_msix(payload: &windows::Msix) -> Vec<String> {
+    if windows::is_valid_family_name(&payload.package_family_name) {
+        vec![]
+    } else {
+        vec![format!(
+            "`{}` is not a valid MSIX package family name",
+            payload.package_family_name
+        )]
+    }
+}
+
+fn lint_rpm_package(payload: &rpm::Package) -> Vec<String> {
+    if rpm::is_valid_name(&payload.package) {
+        vec![]
+    } else {
+        vec![format!(
+            "`{}` is not a valid RPM package name",
+            payload.package
+        )]
+    }
+}
+
+fn lint_flatpak(payload: &flatpak::Package) -> Vec<String> {
+    let mut messages = vec![];
+    if !flatpak::is_valid_remote(&payload.remote) {
+        messages.push(format!(
+            "`{}` is not a valid flatpak remote name",
+            payload.remote
+        ));
+    }
+    if !flatpak::is_valid_ref(&payload.flatpak_ref) {
+        messages.push(format!(
+            "`{}` is not a valid flatpak ref",
+            payload.flatpak_ref
+        ));
+    }
+    messages
+}
+
+fn lint_snap(payload: &snap::Package) -> Vec<String> {
+    let mut messages = vec![];
+    if !snap::is_valid_name(&payload.name) {
+        messages.push(format!("`{}` is not a valid snap name", payload.name));
+    }
+    match payload.channel.as_ref() {
+        Some(channel) if !snap::is_valid_channel(channel) => {
+            messages.push(format!("`{}` is not a valid snap channel", channel));
+        }
+        _ => {}
+    }
+    messages
+}
+
+#[non_exhaustive]
+#[derive(Debug, Clone, TypedBuilder)]
+pub struct Request<'a> {
+    pub path: Cow<'a, Path>,
+}
+
+#[non_exhaustive]
+#[derive(Debug, Clone, Default, TypedBuilder)]
+pub struct PartialRequest<'a> {
+    #[builder(default)]
+    pub path: Option<&'a Path>,
+}
+
+impl<'a> crate::Request for Request<'a> {
+    type Error = std::convert::Infallible;
+    type Partial = PartialRequest<'a>;
+
+    fn new_from_user_input(partial: Self::Partial) -> Result<Self, Self::Error> {
+        Ok(Request {
+            path: partial
+                .path
+                .map(Cow::Borrowed)
+                .unwrap_or_else(|| Cow::Owned(std::env::current_dir().unwrap())),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msi(kind: Option<&str>) -> windows::Executable {
+        let mut payload = windows::Executable::builder()
+            .url("https://example.com/a.msi".parse().unwrap())
+            .product_code("{A}".into())
+            .size(1)
+            .installed_size(1)
+            .kind(kind.map(|x| x.parse().unwrap()))
+            .build();
+        payload
+            .msi_properties
+            .insert("LICENSEKEY".into(), "1234".into());
+        payload.msi_transforms.push(":sv.mst".into());
+        payload
+    }
+
+    #[test]
+    fn available_from_must_be_rfc3339() {
+        let release = |available_from: &str| {
+            Release::builder()
+                .version(Version::new("1.0.0").unwrap())
+                .available_from(Some(available_from.to_string()))
+                .build()
+        };
+        assert!(lint_release(&release("2024-03-01T12:00:00Z")).is_empty());
+        assert!(lint_release(&release("2024-03-01T12:00:00+01:00")).is_empty());
+        assert_eq!(lint_release(&release("2024-03-01")).len(), 1);
+        assert_eq!(lint_release(&release("next tuesday")).len(), 1);
+    }
+
+    #[test]
+    fn min_client_version_must_be_semver() {
+        let release = |version: &str| {
+            Release::builder()
+                .version(Version::new("1.0.0").unwrap())
+                .min_client_version(Some(version.to_string()))
+                .build()
+        };
+        assert!(lint_release(&release("2.3.0")).is_empty());
+        assert_eq!(lint_release(&release("2.3")).len(), 1);
+    }
+
+    #[test]
+    fn valid_msi_options() {
+        assert!(lint_windows_executable(&msi(Some("msi"))).is_empty());
+    }
+
+    #[test]
+    fn msi_options_on_other_kinds() {
+        assert_eq!(lint_windows_executable(&msi(Some("nsis"))).len(), 1);
+    }
+
+    #[test]
+    fn unknown_kind_requires_args() {
+        let mut payload = msi(Some("wix-burn"));
+        payload.msi_properties.clear();
+        payload.msi_transforms.clear();
+        assert_eq!(lint_windows_executable(&payload).len(), 2);
+
+        payload.args = Some("/quiet".into());
+        payload.uninstall_args = Some("/uninstall /quiet".into());
+        assert!(lint_windows_executable(&payload).is_empty());
+    }
+
+    #[test]
+    fn empty_uninstall_command() {
+        let mut payload = msi(Some("msi"));
+        payload.uninstall = Some(
+            windows::Uninstall::builder()
+                .quiet_command(Some(" ".into()))
+                .build(),
+        );
+        assert_eq!(lint_windows_executable(&payload).len(), 1);
+    }
+
+    #[test]
+    fn invalid_property_names_and_transforms() {
+        let mut payload = msi(Some("msi"));
+        payload
+            .msi_properties
+            .insert("licenseKey".into(), "".into());
+        payload.msi_transforms.push("a.mst;b.mst".into());
+        payload.msi_transforms.push("sv.msi".into());
+        assert_eq!(lint_windows_executable(&payload).len(), 3);
+    }
+
+    #[test]
+    fn choice_changes_need_https_and_a_checksum() {
+        let payload = |choice_changes: &str, sha256: Option<&str>| {
+            macos::Package::builder()
+                .url("https://pahkat.example/speller.pkg".parse().unwrap())
+                .pkg_id("no.divvun.speller".into())
+                .size(1)
+                .installed_size(1)
+                .choice_changes(Some(choice_changes.into()))
+                .choice_changes_sha256(sha256.map(str::to_string))
+                .build()
+        };
+        let url = "https://pahkat.example/choices.xml";
+        let sha256 = Some("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+
+        assert!(lint_macos_package(&payload("<plist/>", None)).is_empty());
+        assert!(lint_macos_package(&payload(url, sha256)).is_empty());
+        assert_eq!(lint_macos_package(&payload(url, None)).len(), 1);
+        assert_eq!(
+            lint_macos_package(&payload("http://pahkat.example/choices.xml", sha256)).len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,497 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use pahkat_types::package::{Descriptor, Release, Version};
+use pahkat_types::repo::{Agent, Index, PackageSet};
+use serde::Serialize;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Failed to read file `{0}`")]
+    ReadFailed(PathBuf, #[source] io::Error),
+
+    #[error("Failed to read TOML file `{0}`")]
+    ReadToml(PathBuf, #[source] toml::de::Error),
+
+    #[error("Failed to create directory `{0}`")]
+    DirCreateFailed(PathBuf, #[source] io::Error),
+
+    #[error("Failed to write file `{0}`")]
+    WriteFailed(PathBuf, #[source] io::Error),
+
+    #[error("Failed to serialize TOML for `{0}`")]
+    SerializeToml(PathBuf, #[source] toml::ser::Error),
+
+    #[error("Package `{0}` already exists")]
+    PackageExists(String),
+
+    #[error("Package `{0}` not found")]
+    PackageNotFound(String),
+
+    #[error("Release `{1}` of package `{0}` not found")]
+    ReleaseNotFound(String, Version),
+
+    #[error("Failed to build index: {0}")]
+    Index(anyhow::Error),
+}
+
+/// An open repository whose changes are held in memory until `commit` is called.
+///
+/// All package descriptors are loaded on `open`. Modifications only touch the
+/// in-memory copies; `commit` writes every changed file to a temporary sibling
+/// first and only renames them into place once all of them were written, then
+/// regenerates the package index. The replaced files are kept until the index is
+/// written, so that a failure at any step leaves the repository as it was.
+#[derive(Debug)]
+pub struct Repository {
+    path: PathBuf,
+    index: Index,
+    packages: BTreeMap<String, Descriptor>,
+    index_changed: bool,
+    changed: BTreeSet<String>,
+}
+
+impl Repository {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Repository, Error> {
+        let path = path.as_ref().to_path_buf();
+        let index: Index = read_toml(&path.join("index.toml"))?;
+
+        let packages_path = path.join("packages");
+        let mut packages = BTreeMap::new();
+
+        let entries = match fs::read_dir(&packages_path) {
+            Ok(v) => v.filter_map(Result::ok).collect::<Vec<_>>(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => vec![],
+            Err(e) => return Err(Error::ReadFailed(packages_path, e)),
+        };
+
+        for entry in entries {
+            if !entry.file_type().map(|x| x.is_dir()).unwrap_or(false) {
+                continue;
+            }
+
+            let pkg_path = entry.path().join("index.toml");
+            if !pkg_path.exists() {
+                continue;
+            }
+
+            let descriptor: Descriptor = read_toml(&pkg_path)?;
+            packages.insert(descriptor.package.id.clone(), descriptor);
+        }
+
+        Ok(Repository {
+            path,
+            index,
+            packages,
+            index_changed: false,
+            changed: BTreeSet::new(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn index(&self) -> &Index {
+        &self.index
+    }
+
+    pub fn packages(&self) -> impl Iterator<Item = &Descriptor> {
+        self.packages.values()
+    }
+
+    pub fn package(&self, id: &str) -> Option<&Descriptor> {
+        self.packages.get(id)
+    }
+
+    pub fn add_package(&mut self, descriptor: Descriptor) -> Result<(), Error> {
+        let id = descriptor.package.id.clone();
+        if self.packages.contains_key(&id) {
+            return Err(Error::PackageExists(id));
+        }
+
+        self.packages.insert(id.clone(), descriptor);
+        self.changed.insert(id);
+        Ok(())
+    }
+
+    /// Adds the release to the package, or merges its targets into an existing
+    /// release with the same version and channel. Targets are replaced per platform.
+    pub fn update_package(&mut self, id: &str, release: Release) -> Result<(), Error> {
+        let descriptor = self
+            .packages
+            .get_mut(id)
+            .ok_or_else(|| Error::PackageNotFound(id.to_string()))?;
+
+        match descriptor
+            .release
+            .iter_mut()
+            .find(|x| x.version == release.version && x.channel == release.channel)
+        {
+            Some(existing) => {
+                for target in release.target {
+                    match existing
+                        .target
+                        .iter_mut()
+                        .find(|x| x.platform == target.platform && x.arch == target.arch)
+                    {
+                        Some(t) => *t = target,
+                        None => existing.target.insert(0, target),
+                    }
+                }
+            }
+            None => {
+                // Insert new releases at front
+                descriptor.release.insert(0, release);
+            }
+        }
+
+        self.changed.insert(id.to_string());
+        Ok(())
+    }
+
+    /// Removes the release with the given version and channel from the package.
+    pub fn yank_release(
+        &mut self,
+        id: &str,
+        version: &Version,
+        channel: Option<&str>,
+    ) -> Result<(), Error> {
+        let descriptor = self
+            .packages
+            .get_mut(id)
+            .ok_or_else(|| Error::PackageNotFound(id.to_string()))?;
+
+        let len = descriptor.release.len();
+        descriptor
+            .release
+            .retain(|x| !(&x.version == version && x.channel.as_deref() == channel));
+
+        if descriptor.release.len() == len {
+            return Err(Error::ReleaseNotFound(id.to_string(), version.clone()));
+        }
+
+        self.changed.insert(id.to_string());
+        Ok(())
+    }
+
+    pub fn set_channels(&mut self, channels: Vec<String>, default_channel: Option<String>) {
+        self.index.repository.channels = channels;
+        self.index.repository.default_channel = default_channel;
+        self.index_changed = true;
+    }
+
+    pub fn set_agent(&mut self, agent: Agent) {
+        self.index.agent = agent;
+        self.index_changed = true;
+    }
+
+    pub fn set_signing_key(&mut self, key: Option<String>) {
+        self.index.repository.signing_key = key;
+        self.index_changed = true;
+    }
+
+    /// Adds or replaces the package set with the given id.
+    pub fn set_package_set(&mut self, id: &str, set: PackageSet) -> Result<(), Error> {
+        if let Some(missing) = set
+            .packages
+            .iter()
+            .find(|x| !self.packages.contains_key(*x))
+        {
+            return Err(Error::PackageNotFound(missing.to_string()));
+        }
+
+        self.index.sets.insert(id.to_string(), set);
+        self.index_changed = true;
+        Ok(())
+    }
+
+    pub fn remove_package_set(&mut self, id: &str) -> Option<PackageSet> {
+        let set = self.index.sets.remove(id)?;
+        self.index_changed = true;
+        Some(set)
+    }
+
+    /// Writes all pending changes to disk and regenerates the package index.
+    ///
+    /// If any file fails to be written or moved into place, or the index cannot be
+    /// regenerated, nothing in the repository is replaced.
+    pub fn commit(&mut self) -> Result<(), Error> {
+        self.commit_with(
+            |from, to| fs::rename(from, to),
+            |path| {
+                let request = crate::repo::indexing::Request::builder()
+                    .path(Cow::Borrowed(path))
+                    .build();
+                crate::repo::indexing::index(request)
+            },
+        )
+    }
+
+    fn commit_with(
+        &mut self,
+        rename: impl Fn(&Path, &Path) -> io::Result<()>,
+        index: impl FnOnce(&Path) -> anyhow::Result<()>,
+    ) -> Result<(), Error> {
+        let mut staged = vec![];
+
+        let result = self
+            .stage(&mut staged)
+            .and_then(|_| replace_all(&staged, &rename));
+        let mut replaced = match result {
+            Ok(v) => v,
+            Err(e) => {
+                for (tmp_path, _) in staged {
+                    let _ = fs::remove_file(tmp_path);
+                }
+                return Err(e);
+            }
+        };
+
+        let packages_path = self.path.join("packages");
+        let result = GENERATED_FILES
+            .iter()
+            .try_for_each(|name| replaced.set_aside(&packages_path.join(name), &rename))
+            .and_then(|_| index(&self.path).map_err(Error::Index));
+        if let Err(e) = result {
+            replaced.restore();
+            return Err(e);
+        }
+        replaced.finish();
+
+        self.index_changed = false;
+        self.changed.clear();
+        Ok(())
+    }
+
+    fn stage(&self, staged: &mut Vec<(PathBuf, PathBuf)>) -> Result<(), Error> {
+        if self.index_changed {
+            let path = self.path.join("index.toml");
+            staged.push(write_staged(&path, &self.index)?);
+        }
+
+        for id in self.changed.iter() {
+            let descriptor = &self.packages[id];
+            let pkg_dir = self.path.join("packages").join(id);
+            fs::create_dir_all(&pkg_dir).map_err(|e| Error::DirCreateFailed(pkg_dir.clone(), e))?;
+            staged.push(write_staged(&pkg_dir.join("index.toml"), descriptor)?);
+        }
+
+        Ok(())
+    }
+}
+
+/// Files in `packages` written by indexing, which are replaced along with the
+/// descriptors.
+const GENERATED_FILES: &[&str] = &["index.bin", "index.bin.gz", "index.bin.zst"];
+
+/// Paths written by a commit, along with the files they replaced, if any.
+struct Replaced(Vec<(PathBuf, Option<PathBuf>)>);
+
+impl Replaced {
+    /// Moves the file at `path` aside, so that whatever is written there next is
+    /// undone by `restore`.
+    fn set_aside(
+        &mut self,
+        path: &Path,
+        rename: impl Fn(&Path, &Path) -> io::Result<()>,
+    ) -> Result<(), Error> {
+        let backup = if path.exists() {
+            let mut backup = path.as_os_str().to_owned();
+            backup.push(".bak");
+            let backup = PathBuf::from(backup);
+            rename(path, &backup).map_err(|e| Error::WriteFailed(path.to_path_buf(), e))?;
+            Some(backup)
+        } else {
+            None
+        };
+        self.0.push((path.to_path_buf(), backup));
+        Ok(())
+    }
+
+    /// Puts every replaced file back, and removes the files that were new.
+    fn restore(self) {
+        for (path, backup) in self.0.into_iter().rev() {
+            let _ = match backup {
+                Some(backup) => fs::rename(backup, path),
+                None => fs::remove_file(path),
+            };
+        }
+    }
+
+    fn finish(self) {
+        for backup in self.0.into_iter().filter_map(|(_, backup)| backup) {
+            let _ = fs::remove_file(backup);
+        }
+    }
+}
+
+/// Moves the staged files into place. If any rename fails, every replaced file is
+/// put back.
+fn replace_all(
+    staged: &[(PathBuf, PathBuf)],
+    rename: impl Fn(&Path, &Path) -> io::Result<()>,
+) -> Result<Replaced, Error> {
+    let mut replaced = Replaced(vec![]);
+
+    let result = staged.iter().try_for_each(|(tmp_path, path)| {
+        replaced.set_aside(path, &rename)?;
+        rename(tmp_path, path).map_err(|e| Error::WriteFailed(path.clone(), e))
+    });
+
+    match result {
+        Ok(()) => Ok(replaced),
+        Err(e) => {
+            replaced.restore();
+            Err(e)
+        }
+    }
+}
+
+fn read_toml<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T, Error> {
+    let file = fs::read_to_string(path).map_err(|e| Error::ReadFailed(path.to_path_buf(), e))?;
+    toml::from_str(&file).map_err(|e| Error::ReadToml(path.to_path_buf(), e))
+}
+
+fn write_staged<T: Serialize>(path: &Path, value: &T) -> Result<(PathBuf, PathBuf), Error> {
+    let data =
+        toml::to_string_pretty(value).map_err(|e| Error::SerializeToml(path.to_path_buf(), e))?;
+    let tmp_path = path.with_extension("toml.tmp");
+    fs::write(&tmp_path, data).map_err(|e| Error::WriteFailed(tmp_path.clone(), e))?;
+    Ok((tmp_path, path.to_path_buf()))
+}
+
+#[cfg(test)]
+mod tests {
+    use pahkat_types::package::DescriptorData;
+
+    use super::*;
+
+    const INDEX: &str = r#"
+[repository]
+url = "https://pahkat.example/repo/"
+
+[agent]
+name = "pahkat"
+version = "2.3.0"
+"#;
+
+    fn descriptor(id: &str) -> Descriptor {
+        Descriptor::builder()
+            .package(DescriptorData::builder().id(id.into()).build())
+            .build()
+    }
+
+    fn read(path: &Path) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn failed_rename_restores_replaced_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("index.toml"), INDEX).unwrap();
+
+        let mut repo = Repository::open(dir.path()).unwrap();
+        repo.add_package(descriptor("a")).unwrap();
+        repo.add_package(descriptor("b")).unwrap();
+        repo.commit().unwrap();
+
+        let a = dir.path().join("packages").join("a").join("index.toml");
+        let b = dir.path().join("packages").join("b").join("index.toml");
+        let (index, old_a, old_b) = (read(&dir.path().join("index.toml")), read(&a), read(&b));
+
+        let mut repo = Repository::open(dir.path()).unwrap();
+        repo.set_channels(vec!["beta".into()], None);
+        repo.add_package(descriptor("c")).unwrap();
+        let release = Release::builder()
+            .version(Version::new("1.0.0").unwrap())
+            .build();
+        repo.update_package("a", release.clone()).unwrap();
+        repo.update_package("b", release).unwrap();
+
+        // Fail moving the last staged file into place, after the others were moved
+        let result = repo.commit_with(
+            |from, to| {
+                if from == b.with_extension("toml.tmp") {
+                    return Err(io::Error::new(io::ErrorKind::Other, "injected"));
+                }
+                fs::rename(from, to)
+            },
+            |_| panic!("indexed after a failed rename"),
+        );
+        assert!(matches!(result, Err(Error::WriteFailed(..))));
+
+        assert_eq!(read(&dir.path().join("index.toml")), index);
+        assert_eq!(read(&a), old_a);
+        assert_eq!(read(&b), old_b);
+        assert!(!dir
+            .path()
+            .join("packages")
+            .join("c")
+            .join("index.toml")
+            .exists());
+
+        let leftovers = walk(dir.path())
+            .into_iter()
+            .filter(|x| x.extension().map_or(false, |x| x == "tmp" || x == "bak"))
+            .collect::<Vec<_>>();
+        assert!(leftovers.is_empty(), "{:?}", leftovers);
+    }
+
+    #[test]
+    fn failed_indexing_restores_replaced_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("index.toml"), INDEX).unwrap();
+
+        let mut repo = Repository::open(dir.path()).unwrap();
+        repo.add_package(descriptor("a")).unwrap();
+        repo.commit().unwrap();
+
+        let a = dir.path().join("packages").join("a").join("index.toml");
+        let index_bin = dir.path().join("packages").join("index.bin");
+        let (old_a, old_index_bin) = (read(&a), fs::read(&index_bin).unwrap());
+
+        let mut repo = Repository::open(dir.path()).unwrap();
+        let release = Release::builder()
+            .version(Version::new("1.0.0").unwrap())
+            .build();
+        repo.update_package("a", release).unwrap();
+        repo.add_package(descriptor("b")).unwrap();
+
+        // Fail after part of the index was written
+        let result = repo.commit_with(
+            |from, to| fs::rename(from, to),
+            |path| {
+                fs::write(path.join("packages").join("index.bin"), b"partial")?;
+                Err(anyhow::anyhow!("injected"))
+            },
+        );
+        assert!(matches!(result, Err(Error::Index(..))));
+
+        assert_eq!(read(&a), old_a);
+        assert_eq!(fs::read(&index_bin).unwrap(), old_index_bin);
+        assert!(!dir
+            .path()
+            .join("packages")
+            .join("b")
+            .join("index.toml")
+            .exists());
+
+        let leftovers = walk(dir.path())
+            .into_iter()
+            .filter(|x| x.extension().map_or(false, |x| x == "tmp" || x == "bak"))
+            .collect::<Vec<_>>();
+        assert!(leftovers.is_empty(), "{:?}", leftovers);
+    }
+
+    fn walk(path: &Path) -> Vec<PathBuf> {
+        fs::read_dir(path)
+            .unwrap()
+            .map(|x| x.unwrap().path())
+            .flat_map(|x| if x.is_dir() { walk(&x) } else { vec![x] })
+            .collect()
+    }
+}
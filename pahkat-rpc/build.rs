@@ -1,15 +1,21 @@
 fn main() {
-    tonic_build::compile_protos("proto/pahkat.proto").unwrap();
+    let out_dir = std::path::PathBuf::from(std::env::var("OUT_DIR").unwrap());
 
-    let gen_path = std::path::Path::new(&std::env::var("OUT_DIR").unwrap()).join("pahkat.rs");
+    tonic_build::configure()
+        .file_descriptor_set_path(out_dir.join("pahkat_descriptor.bin"))
+        .compile(&["proto/pahkat.proto"], &["proto"])
+        .unwrap();
+
+    let gen_path = out_dir.join("pahkat.rs");
     let data = std::fs::read_to_string(&gen_path).unwrap();
+    // Messages default missing fields, so JSON clients keep working as fields are added
     let data = data.replace(
-        "::prost::Message)",
-        "::prost::Message, ::serde::Serialize, ::serde::Deserialize)",
+        "::prost::Message)]",
+        "::prost::Message, ::serde::Serialize, ::serde::Deserialize, ::schemars::JsonSchema)] #[serde(default)]",
     );
     let data = data.replace(
         "::prost::Oneof)",
-        "::prost::Oneof, ::serde::Serialize, ::serde::Deserialize)",
+        "::prost::Oneof, ::serde::Serialize, ::serde::Deserialize, ::schemars::JsonSchema)",
     );
     let data = data.replace(
         "pub enum Value {",
@@ -1,12 +1,27 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+struct Args {
+    /// Unix socket or named pipe to listen on (defaults to $PAHKAT_SOCKET, the
+    /// `socket_path` setting, then a path derived from the store kind)
+    #[structopt(long, parse(from_os_str))]
+    socket: Option<PathBuf>,
+
+    /// Write the encoded descriptor set of the service to this path and exit
+    #[structopt(long, parse(from_os_str))]
+    write_descriptor_set: Option<PathBuf>,
+}
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let path = if cfg!(windows) {
-        format!("//./pipe/pahkat")
-    } else {
-        format!("/tmp/pahkat")
-    };
+    let args = Args::from_args();
+
+    if let Some(path) = args.write_descriptor_set {
+        std::fs::write(path, pahkat_rpc::FILE_DESCRIPTOR_SET)?;
+        return Ok(());
+    }
 
     match pahkat_rpc::server::setup_logger("service") {
         Ok(_) => log::debug!("Logging started."),
@@ -19,7 +34,7 @@ async fn main() -> anyhow::Result<()> {
     }
 
     pahkat_rpc::start(
-        Path::new(&path),
+        args.socket.as_deref(),
         config_path(),
         tokio::sync::mpsc::unbounded_channel().1,
     )
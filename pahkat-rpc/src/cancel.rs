@@ -0,0 +1,74 @@
+//! The canceler of the transaction holding the transaction lock.
+//!
+//! Stopping the daemon waits for the running transaction to finish, which for a long
+//! list of packages can take long enough for the service manager to give up on it. The
+//! holder of the lock registers its canceler here, so that shutdown can stop the
+//! transaction after its grace period.
+
+use std::sync::{Arc, Mutex};
+
+use stream_cancel::Trigger;
+
+#[derive(Clone, Default)]
+pub(crate) struct Canceler(Arc<Mutex<Option<Trigger>>>);
+
+impl Canceler {
+    /// Replaces the canceler of a transaction that has since finished.
+    pub(crate) fn set(&self, trigger: Trigger) {
+        let old = self.0.lock().unwrap().replace(trigger);
+        if let Some(old) = old {
+            old.disable();
+        }
+    }
+
+    /// Forgets the current canceler without cancelling its transaction.
+    pub(crate) fn clear(&self) {
+        if let Some(trigger) = self.0.lock().unwrap().take() {
+            trigger.disable();
+        }
+    }
+
+    /// Cancels the current transaction once its running action, such as an installer,
+    /// has finished. Returns whether there was a transaction to cancel.
+    pub(crate) fn cancel(&self) -> bool {
+        match self.0.lock().unwrap().take() {
+            Some(trigger) => {
+                trigger.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::stream::StreamExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn cancel_ends_the_registered_stream() {
+        let canceler = Canceler::default();
+        assert!(!canceler.cancel());
+
+        let (trigger, valve) = stream_cancel::Valve::new();
+        let mut stream = valve.wrap(futures::stream::pending::<()>());
+        canceler.set(trigger);
+
+        assert!(canceler.cancel());
+        assert_eq!(stream.next().await, None);
+    }
+
+    #[tokio::test]
+    async fn clear_leaves_the_stream_running() {
+        let canceler = Canceler::default();
+        let (trigger, valve) = stream_cancel::Valve::new();
+        let mut stream = valve.wrap(futures::stream::iter(vec![1, 2]));
+        canceler.set(trigger);
+
+        canceler.clear();
+        assert_eq!(stream.next().await, Some(1));
+        assert_eq!(stream.next().await, Some(2));
+    }
+}
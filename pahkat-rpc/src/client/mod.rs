@@ -1,6 +1,9 @@
+mod progress;
+
 use futures::stream::{self, TryStreamExt};
 
 use std::convert::TryFrom;
+use std::path::PathBuf;
 
 use structopt::StructOpt;
 
@@ -14,7 +17,7 @@ use tonic::{
 };
 use tower::service_fn;
 
-use crate::pb;
+use crate::{ipc, pb};
 
 #[derive(Debug, StructOpt)]
 struct StatusCommand {
@@ -22,13 +25,46 @@ struct StatusCommand {
     target: String,
 }
 
+#[derive(Debug, StructOpt)]
+struct UnpublishedCommand {
+    /// `system` or `user`
+    #[structopt(default_value = "system")]
+    target: String,
+}
+
 // #[derive(Debug, StructOpt)]
 // struct RepoIndexesCommand {}
 
 #[derive(Debug, StructOpt)]
 struct ProcessTransactionCommand {
-    // package-id::action[::target]
+    // package-id::action[::target[::KEY=VALUE,...]]
     actions: Vec<String>,
+
+    /// Print each event as a line of JSON instead of rendering progress
+    #[structopt(long)]
+    json: bool,
+
+    /// Install releases even if they are critically deprecated
+    #[structopt(long)]
+    allow_deprecated: bool,
+
+    /// Apply every action or none of them
+    #[structopt(long)]
+    atomic: bool,
+
+    /// When to run the transaction: now, idle or shutdown
+    #[structopt(long, default_value = "now", parse(try_from_str = parse_schedule))]
+    schedule: pb::transaction_request::Schedule,
+}
+
+fn parse_schedule(value: &str) -> Result<pb::transaction_request::Schedule, String> {
+    use pb::transaction_request::Schedule;
+    match value {
+        "now" => Ok(Schedule::Immediate),
+        "idle" => Ok(Schedule::OnIdle),
+        "shutdown" => Ok(Schedule::AtShutdown),
+        other => Err(format!("unknown schedule: {}", other)),
+    }
 }
 
 // #[derive(Debug, StructOpt)]
@@ -44,20 +80,150 @@ struct ProcessTransactionCommand {
 #[derive(Debug, StructOpt)]
 struct SetRepoCommand {
     repo_url: String,
+
+    /// Channel to follow; leave out to keep the current channel
+    #[structopt(short, long)]
+    channel: Option<String>,
+
+    /// Bearer token sent with requests to the repository
+    #[structopt(long, env = "PAHKAT_REPO_AUTH_TOKEN", hide_env_values = true)]
+    auth_token: Option<String>,
+
+    /// Remove the repository's bearer token
+    #[structopt(long, conflicts_with = "auth-token")]
+    clear_auth_token: bool,
+
+    /// Base64 ed25519 key the repository must be signed by; replaces the current keys
+    #[structopt(long = "trusted-key")]
+    trusted_keys: Vec<String>,
+
+    /// Trust the key the repository advertises on first use instead of set keys
+    #[structopt(long, conflicts_with = "trusted-key")]
+    clear_trusted_keys: bool,
+}
+
+#[derive(Debug, StructOpt)]
+struct RemoveRepoCommand {
+    repo_url: String,
+
+    /// Uninstall the packages installed from the repository first
+    #[structopt(long)]
+    purge: bool,
+
+    /// Only list the packages that would be uninstalled
+    #[structopt(long, requires = "purge")]
+    dry_run: bool,
+
+    /// Uninstall without asking for confirmation
+    #[structopt(short, long, requires = "purge")]
+    yes: bool,
+
+    /// `system` or `user`
+    #[structopt(long, default_value = "system")]
+    target: String,
+}
+
+#[derive(Debug, StructOpt)]
+struct RepoStatsCommand {
+    /// Leave out for every configured repository
+    repo_url: Option<String>,
+}
+
+#[derive(Debug, StructOpt)]
+struct PrereleaseCommand {
+    package_key: String,
+    /// Such as `beta`; leave out to return the package to stable
+    channel: Option<String>,
+}
+
+#[derive(Debug, StructOpt)]
+struct AcceptKeyCommand {
+    repo_url: String,
+    /// The new base64 signing key, verified with the repository's maintainers
+    key: String,
+}
+
+#[derive(Debug, StructOpt)]
+struct SchemaCommand {
+    /// Such as `ResolvedPackageQuery`; leave out to list the available schemas
+    name: Option<String>,
 }
 
 #[derive(Debug, StructOpt)]
 enum Command {
     // Install(InstallCommand),
     Status(StatusCommand),
+    /// Installed packages that their repository no longer publishes
+    Unpublished(UnpublishedCommand),
+    /// Packages the background updater failed to install
+    Failures,
     // RepoIndexes(RepoIndexesCommand),
     ProcessTransaction(ProcessTransactionCommand),
     // Strings(StringsCommand),
     SetRepo(SetRepoCommand),
+    /// Opts a package into a pre-release channel, or out of it
+    Prerelease(PrereleaseCommand),
+    RemoveRepo(RemoveRepoCommand),
+    GetRepos,
+    /// Accepts a changed signing key of a repository
+    AcceptKey(AcceptKeyCommand),
+    /// Package counts, sizes and refresh times of repositories
+    RepoStats(RepoStatsCommand),
+    Refresh,
+    /// JSON Schema of the JSON exchanged with the service
+    Schema(SchemaCommand),
+}
+
+fn confirm(prompt: &str) -> std::io::Result<bool> {
+    use std::io::Write;
+
+    print!("{} [y/N] ", prompt);
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+fn print_repos(
+    records: &std::collections::HashMap<String, pb::RepoRecord>,
+    errors: &std::collections::HashMap<String, String>,
+) {
+    let mut urls = records.keys().collect::<Vec<_>>();
+    urls.sort();
+
+    if urls.is_empty() {
+        println!("No repositories configured.");
+    }
+
+    for url in urls {
+        match records[url].channel.as_str() {
+            "" => println!("{}", url),
+            channel => println!("{} (channel: {})", url, channel),
+        }
+        if records[url].has_auth_token {
+            println!("  auth token: set");
+        }
+        let mut prerelease_channels = records[url].prerelease_channels.iter().collect::<Vec<_>>();
+        prerelease_channels.sort();
+        for (id, channel) in prerelease_channels {
+            println!("  {}: {}", id, channel);
+        }
+        if let Some(error) = errors.get(url) {
+            println!("  error: {}", error);
+        }
+    }
 }
 
 #[derive(Debug, StructOpt)]
 struct Args {
+    /// Unix socket or named pipe of the daemon to connect to
+    #[structopt(long, parse(from_os_str))]
+    socket: Option<PathBuf>,
+
+    /// Connect to the `system` or `user` session daemon when no socket is given
+    #[structopt(long)]
+    session: Option<ipc::Session>,
+
     #[structopt(subcommand)]
     command: Command,
 }
@@ -71,15 +237,16 @@ use std::sync::Arc;
 use tokio::sync::RwLock;
 
 #[cfg(windows)]
-async fn new_client() -> anyhow::Result<PahkatClient> {
+async fn new_client(path: PathBuf) -> anyhow::Result<PahkatClient> {
     use tokio::net::windows::named_pipe::ClientOptions;
 
     let channel = Endpoint::try_from("file://tmp/pahkat")?
-        .connect_with_connector(service_fn(|_: Uri| async move {
-            let path = format!("//./pipe/pahkat");
-
-            let pipe = ClientOptions::new().open(path)?;
-            Ok::<_, std::io::Error>(pipe)
+        .connect_with_connector(service_fn(move |_: Uri| {
+            let path = path.clone();
+            async move {
+                let pipe = ClientOptions::new().open(path)?;
+                Ok::<_, std::io::Error>(pipe)
+            }
         }))
         .await?;
 
@@ -88,12 +255,14 @@ async fn new_client() -> anyhow::Result<PahkatClient> {
 }
 
 #[cfg(unix)]
-async fn new_client() -> anyhow::Result<PahkatClient> {
+async fn new_client(path: PathBuf) -> anyhow::Result<PahkatClient> {
     let channel = Endpoint::try_from("file://tmp/pahkat.sock")?
-        .connect_with_connector(service_fn(move |_: Uri| async move {
-            let path = format!("/tmp/pahkat.sock");
-            let uds = UnixStream::connect(path).await?;
-            Ok::<_, std::io::Error>(uds)
+        .connect_with_connector(service_fn(move |_: Uri| {
+            let path = path.clone();
+            async move {
+                let uds = UnixStream::connect(path).await?;
+                Ok::<_, std::io::Error>(uds)
+            }
         }))
         .await?;
 
@@ -103,7 +272,13 @@ async fn new_client() -> anyhow::Result<PahkatClient> {
 
 pub async fn run() -> anyhow::Result<()> {
     let args = Args::from_args();
-    let mut client = new_client().await?;
+    let session = args.session.unwrap_or_else(ipc::Session::current);
+    let path = ipc::socket_path(
+        args.socket.as_deref(),
+        session.configured_path().as_deref(),
+        session,
+    );
+    let mut client = new_client(path).await?;
 
     match args.command {
         Command::Status(command) => {
@@ -115,16 +290,217 @@ pub async fn run() -> anyhow::Result<()> {
             let response = client.status(request).await?;
             println!("{:#?}", response);
         }
-        Command::SetRepo(command) => {
+        Command::Unpublished(command) => {
+            let request = Request::new(pb::UnpublishedPackagesRequest {
+                target: if command.target == "user" { 1 } else { 0 },
+            });
+
+            let result = client.unpublished_packages(request).await?.into_inner();
+            if result.package_keys.is_empty() {
+                println!("No installed packages are unpublished.");
+            }
+            for key in result.package_keys {
+                println!("{}", key);
+            }
+        }
+        Command::Failures => {
+            let request = Request::new(pb::InstallFailuresRequest {});
+
+            let result = client.install_failures(request).await?.into_inner();
+            if result.failures.is_empty() {
+                println!("No failed installs.");
+            }
+            for failure in result.failures {
+                println!(
+                    "{} {}: failed {} times since {}",
+                    failure.package_key, failure.version, failure.count, failure.first_seen
+                );
+                println!("  Last error: {}", failure.last_error);
+                if failure.retry_at.is_empty() {
+                    println!("  Not retried until a newer release is published");
+                } else {
+                    println!("  Next retry: {}", failure.retry_at);
+                }
+            }
+        }
+        Command::Schema(command) => {
+            let request = Request::new(pb::JsonSchemaRequest {
+                name: command.name.clone().unwrap_or_default(),
+            });
+
+            let result = client.json_schema(request).await?.into_inner();
+            match command.name {
+                Some(name) => println!("{}", result.schemas[&name]),
+                None => {
+                    let mut names = result.schemas.keys().collect::<Vec<_>>();
+                    names.sort();
+                    for name in names {
+                        println!("{}", name);
+                    }
+                }
+            }
+        }
+        Command::Prerelease(command) => {
+            let request = Request::new(pb::SetPrereleaseChannelRequest {
+                package_key: command.package_key.clone(),
+                channel: command.channel.clone().unwrap_or_default(),
+            });
+
+            let result = client.set_prerelease_channel(request).await?.into_inner();
+            match command.channel {
+                Some(channel) => println!("{} follows {}", command.package_key, channel),
+                None => println!("{} follows stable", command.package_key),
+            }
+            if result.status == 2 {
+                println!("An update is available.");
+            }
+        }
+        Command::SetRepo(mut command) => {
+            let is_token_set = command.auth_token.is_some() || command.clear_auth_token;
+            let is_keys_set = !command.trusted_keys.is_empty() || command.clear_trusted_keys;
+            let is_set = command.channel.is_some() || is_token_set || is_keys_set;
+
+            // Settings replace the channel, so the current one is sent along with the
+            // other settings
+            if command.channel.is_none() && (is_token_set || is_keys_set) {
+                let request = Request::new(pb::GetRepoRecordsRequest {});
+                let result = client.get_repo_records(request).await?.into_inner();
+                command.channel = result
+                    .records
+                    .get(&command.repo_url)
+                    .map(|x| x.channel.clone());
+            }
+
             let request = Request::new(pb::SetRepoRequest {
-                url: command.repo_url,
-                settings: None,
+                url: command.repo_url.clone(),
+                settings: Some(pb::RepoRecord {
+                    channel: command.channel.unwrap_or_default(),
+                    auth_token: command.auth_token.unwrap_or_default(),
+                    clear_auth_token: command.clear_auth_token,
+                    trusted_keys: command.trusted_keys,
+                    clear_trusted_keys: command.clear_trusted_keys,
+                    ..Default::default()
+                })
+                .filter(|_| is_set),
             });
 
             let response = client.set_repo(request).await?;
             let result = response.into_inner();
 
-            println!("Response: {:?}", &result);
+            match pb::set_repo_response::Change::from_i32(result.change) {
+                Some(pb::set_repo_response::Change::Created) => {
+                    println!("Added {}", command.repo_url)
+                }
+                Some(pb::set_repo_response::Change::Updated) => {
+                    println!("Updated {}", command.repo_url)
+                }
+                _ => println!("{} is unchanged", command.repo_url),
+            }
+            print_repos(&result.records, &result.errors);
+        }
+        Command::RemoveRepo(command) => {
+            let target = if command.target == "user" { 1 } else { 0 };
+
+            if command.purge && !command.yes {
+                let request = Request::new(pb::RemoveRepoRequest {
+                    url: command.repo_url.clone(),
+                    purge: true,
+                    dry_run: true,
+                    target,
+                });
+                let preview = client.remove_repo(request).await?.into_inner();
+
+                if preview.purged_packages.is_empty() {
+                    println!("No packages are installed from {}", command.repo_url);
+                } else {
+                    println!("Packages to uninstall:");
+                    for key in preview.purged_packages.iter() {
+                        println!("  {}", key);
+                    }
+                }
+                if command.dry_run || !confirm("Remove the repository?")? {
+                    return Ok(());
+                }
+            }
+
+            let request = Request::new(pb::RemoveRepoRequest {
+                url: command.repo_url.clone(),
+                purge: command.purge,
+                dry_run: false,
+                target,
+            });
+
+            let response = client.remove_repo(request).await?;
+            let result = response.into_inner();
+
+            for key in result.purged_packages.iter() {
+                println!("Uninstalled {}", key);
+            }
+
+            if result.was_present {
+                println!("Removed {}", command.repo_url);
+            } else {
+                println!("{} was not configured", command.repo_url);
+            }
+            print_repos(&result.records, &result.errors);
+        }
+        Command::GetRepos => {
+            let request = Request::new(pb::GetRepoRecordsRequest {});
+            let result = client.get_repo_records(request).await?.into_inner();
+            print_repos(&result.records, &result.errors);
+
+            let key_changed = pb::RepoStatus::KeyChanged as i32;
+            if result.statuses.values().any(|x| *x == key_changed) {
+                println!();
+                println!("A signing key changed. Verify the new key with the repository's");
+                println!("maintainers, then accept it with `accept-key <repo-url> <key>`.");
+            }
+        }
+        Command::AcceptKey(command) => {
+            let request = Request::new(pb::AcceptRepoKeyRequest {
+                url: command.repo_url.clone(),
+                key: command.key,
+            });
+            let result = client.accept_repo_key(request).await?.into_inner();
+            print_repos(&result.records, &result.errors);
+        }
+        Command::RepoStats(command) => {
+            let request = Request::new(pb::GetRepoStatisticsRequest {
+                url: command.repo_url.unwrap_or_default(),
+            });
+            let result = client.get_repo_statistics(request).await?.into_inner();
+
+            for x in result.repos {
+                println!("{}", x.url);
+                println!("  packages:     {}", x.package_count);
+                if !x.channels.is_empty() {
+                    println!("  channels:     {}", x.channels.join(", "));
+                }
+                println!("  payload size: {} bytes", x.payload_size);
+                println!("  cache size:   {} bytes", x.cache_size);
+                match x.last_refresh.as_str() {
+                    "" => println!("  refreshed:    never"),
+                    v => println!("  refreshed:    {}", v),
+                }
+                for failure in x.payload_failures {
+                    println!(
+                        "  failed:       {} ({} times, last {}: {} at {})",
+                        failure.host,
+                        failure.count,
+                        failure.last_seen,
+                        failure.last_error,
+                        failure.last_url
+                    );
+                }
+            }
+        }
+        Command::Refresh => {
+            client.refresh(Request::new(pb::RefreshRequest {})).await?;
+            println!("Repositories refreshed.");
+
+            let request = Request::new(pb::GetRepoRecordsRequest {});
+            let result = client.get_repo_records(request).await?.into_inner();
+            print_repos(&result.records, &result.errors);
         }
         // Command::RepoIndexes(_) => {
         //     let request = Request::new(pb::RepositoryIndexesRequest {});
@@ -154,23 +530,39 @@ pub async fn run() -> anyhow::Result<()> {
                     } else {
                         1
                     };
-                    pb::PackageAction { id, action, target }
+                    let options = s
+                        .next()
+                        .into_iter()
+                        .flat_map(|x| x.split(','))
+                        .filter_map(|x| x.split_once('='))
+                        .map(|(k, v)| (k.to_string(), v.to_string()))
+                        .collect();
+                    pb::PackageAction {
+                        id,
+                        action,
+                        target,
+                        options,
+                    }
                 })
                 .collect::<Vec<_>>();
 
             let req = stream::iter(vec![pb::TransactionRequest {
                 value: Some(pb::transaction_request::Value::Transaction(
-                    pb::transaction_request::Transaction { actions },
+                    pb::transaction_request::Transaction {
+                        actions,
+                        allow_deprecated: command.allow_deprecated,
+                        schedule: command.schedule as i32,
+                        atomic: command.atomic,
+                    },
                 )),
             }]);
 
             let request = Request::new(req);
             let stream = client.process_transaction(request).await?;
 
-            let mut stream = stream.into_inner();
-
-            while let Ok(Some(message)) = stream.message().await {
-                println!("{:?}", message);
+            let exit_code = progress::render(stream.into_inner(), command.json).await?;
+            if exit_code != 0 {
+                std::process::exit(exit_code);
             }
         } // Command::Strings(StringsCommand { language }) => {
           //     let request = Request::new(pb::StringsRequest { language });
@@ -244,7 +636,9 @@ where
 
 #[cffi::marshal(return_marshaler = "cffi::ArcMarshaler::<RwLock<PahkatClient>>")]
 pub extern "C" fn pahkat_rpc_new() -> Result<Arc<RwLock<PahkatClient>>, Box<dyn Error>> {
-    let client = block_on(new_client())?;
+    let session = ipc::Session::current();
+    let path = ipc::socket_path(None, session.configured_path().as_deref(), session);
+    let client = block_on(new_client(path))?;
     Ok(Arc::new(RwLock::new(client)))
 }
 
@@ -363,6 +757,7 @@ pub extern "C" fn pahkat_rpc_remove_repo(
 ) -> Result<pb::RemoveRepoResponse, Box<dyn Error>> {
     let request = Request::new(pb::RemoveRepoRequest {
         url: repo_url.to_string(),
+        ..Default::default()
     });
 
     block_on(async move {
@@ -387,6 +782,10 @@ pub extern "C" fn pahkat_rpc_notifications(
         };
 
         while let Ok(Some(message)) = stream.message().await {
+            if message.value == pb::notification_response::ValueType::EnableInputSources as i32 {
+                enable_input_sources(&message.input_sources);
+            }
+
             unsafe {
                 (callback)(message.value as i32);
             };
@@ -394,6 +793,20 @@ pub extern "C" fn pahkat_rpc_notifications(
     });
 }
 
+/// The service cannot enable input sources for users, so the clients of their
+/// sessions do it when told about them.
+fn enable_input_sources(ids: &[String]) {
+    #[cfg(all(target_os = "macos", feature = "macos"))]
+    pahkat_client::package_store::macos::enable_input_sources(ids);
+    #[cfg(all(windows, feature = "windows"))]
+    pahkat_client::package_store::windows::enable_input_sources(ids);
+    #[cfg(not(any(
+        all(target_os = "macos", feature = "macos"),
+        all(windows, feature = "windows")
+    )))]
+    log::debug!("Not enabling input sources on this platform: {:?}", ids);
+}
+
 #[cffi::marshal(return_marshaler = "JsonMarshaler")]
 pub extern "C" fn pahkat_rpc_strings(
     #[marshal(cffi::ArcRefMarshaler::<RwLock<PahkatClient>>)] client: Arc<RwLock<PahkatClient>>,
@@ -466,9 +879,16 @@ pub extern "C" fn pahkat_rpc_process_transaction(
         }
     });
 
+    // Frontends are told about critically deprecated releases through a
+    // ReleaseDeprecated response instead.
     tx.send(pb::TransactionRequest {
         value: Some(pb::transaction_request::Value::Transaction(
-            pb::transaction_request::Transaction { actions },
+            pb::transaction_request::Transaction {
+                actions,
+                allow_deprecated: false,
+                schedule: pb::transaction_request::Schedule::Immediate as i32,
+                atomic: false,
+            },
         )),
     })?;
 
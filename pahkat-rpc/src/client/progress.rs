@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+
+use indicatif::{ProgressBar, ProgressStyle};
+
+use crate::pb;
+use pb::transaction_response::Value;
+
+/// Exit codes for the ways a transaction stream can end.
+const EXIT_TRANSACTION_ERROR: i32 = 1;
+const EXIT_VERIFICATION_FAILED: i32 = 2;
+const EXIT_CLIENT_UPDATE_REQUIRED: i32 = 3;
+const EXIT_MALICIOUS_ARCHIVE: i32 = 4;
+const EXIT_RELEASE_DEPRECATED: i32 = 5;
+
+/// Prints the events of a transaction as they arrive, either for humans or as one
+/// JSON object per line. Returns the exit code for the process.
+pub(crate) async fn render(
+    mut stream: tonic::Streaming<pb::TransactionResponse>,
+    json: bool,
+) -> anyhow::Result<i32> {
+    let mut bars = HashMap::new();
+
+    loop {
+        let message = match stream.message().await {
+            Ok(Some(v)) => v,
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("Error: {}", e.message());
+                return Ok(EXIT_TRANSACTION_ERROR);
+            }
+        };
+
+        if json {
+            println!("{}", serde_json::to_string(&message)?);
+        }
+
+        let value = match message.value {
+            Some(v) => v,
+            None => continue,
+        };
+
+        let exit_code = match &value {
+            Value::TransactionComplete(_) => Some(0),
+            Value::TransactionError(_) => Some(EXIT_TRANSACTION_ERROR),
+            Value::VerificationFailed(_) => Some(EXIT_VERIFICATION_FAILED),
+            Value::ClientUpdateRequired(_) => Some(EXIT_CLIENT_UPDATE_REQUIRED),
+            Value::MaliciousArchive(_) => Some(EXIT_MALICIOUS_ARCHIVE),
+            Value::ReleaseDeprecated(_) => Some(EXIT_RELEASE_DEPRECATED),
+            Value::TransactionScheduled(_) => Some(0),
+            _ => None,
+        };
+
+        if !json {
+            print_event(value, &mut bars);
+        }
+
+        if let Some(code) = exit_code {
+            return Ok(code);
+        }
+    }
+
+    if !json {
+        eprintln!("Error: the transaction ended unexpectedly");
+    }
+    Ok(EXIT_TRANSACTION_ERROR)
+}
+
+/// English if available, as the CLI is not localised.
+fn localized(message: &HashMap<String, String>) -> &str {
+    message
+        .get("en")
+        .or_else(|| message.values().next())
+        .map(|x| x.as_str())
+        .unwrap_or("no reason given")
+}
+
+fn print_event(value: Value, bars: &mut HashMap<String, ProgressBar>) {
+    match value {
+        Value::TransactionQueued(_) => {
+            println!("Waiting for another transaction to finish…");
+        }
+        Value::TransactionStarted(x) => {
+            println!("Transaction started:");
+            for action in x.actions.iter() {
+                let (id, verb) = match action.action.as_ref() {
+                    Some(a) if a.action == 1 => (a.id.as_str(), "uninstall"),
+                    Some(a) => (a.id.as_str(), "install"),
+                    None => continue,
+                };
+                println!(
+                    " - {} {} {} ({})",
+                    verb,
+                    id,
+                    action.version,
+                    indicatif::HumanBytes(action.size)
+                );
+                if let Some(deprecation) = action.deprecation.as_ref() {
+                    println!("   deprecated: {}", localized(&deprecation.message));
+                }
+            }
+            if x.is_reboot_required {
+                println!("A restart will be required.");
+            }
+        }
+        Value::DownloadProgress(x) => {
+            let bar = bars.entry(x.package_id.clone()).or_insert_with(|| {
+                let bar = ProgressBar::new(x.total);
+                bar.set_style(
+                    ProgressStyle::default_bar()
+                        .template("{spinner:.green} {prefix} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})")
+                        .expect("Invalid progress bar template!"),
+                );
+                bar.set_prefix(x.package_id.clone());
+                bar
+            });
+            bar.set_length(x.total);
+            bar.set_position(x.current);
+        }
+        Value::DownloadComplete(x) => match bars.remove(&x.package_id) {
+            Some(bar) => bar.finish(),
+            None => println!("Downloaded {}", x.package_id),
+        },
+        Value::InstallStarted(x) => {
+            println!("Installing {}…", x.package_id);
+        }
+        Value::UninstallStarted(x) => {
+            println!("Uninstalling {}…", x.package_id);
+        }
+        Value::TransactionProgress(x) => {
+            println!("{}: {}", x.package_id, x.message);
+        }
+        Value::TransactionComplete(x) => {
+            println!("Transaction complete.");
+            let mut ids = x
+                .reboot_required
+                .iter()
+                .filter(|(_, v)| **v)
+                .map(|(k, _)| k.as_str())
+                .collect::<Vec<_>>();
+            if !ids.is_empty() {
+                ids.sort();
+                println!("A restart is required to finish: {}", ids.join(", "));
+            }
+        }
+        Value::TransactionError(x) => {
+            for bar in bars.values() {
+                bar.abandon();
+            }
+            if x.package_id.is_empty() {
+                eprintln!("Error: {}", x.error);
+            } else {
+                eprintln!("Error: {}: {}", x.package_id, x.error);
+            }
+        }
+        Value::VerificationFailed(_) => {
+            eprintln!("Error: administrator rights are required for system installs");
+        }
+        Value::ClientUpdateRequired(x) => {
+            eprintln!(
+                "Error: {} requires Pahkat {} or newer",
+                x.package_id, x.required_version
+            );
+        }
+        Value::MaliciousArchive(x) => {
+            eprintln!("Error: refusing to install {}: {}", x.package_id, x.reason);
+        }
+        Value::ReleaseDeprecated(x) => {
+            eprintln!(
+                "Error: {} is critically deprecated: {}",
+                x.package_id,
+                localized(&x.message)
+            );
+            eprintln!("Pass --allow-deprecated to install it anyway.");
+        }
+        Value::TransactionScheduled(x) => {
+            use pb::transaction_request::Schedule;
+            match Schedule::from_i32(x.schedule) {
+                Some(Schedule::OnIdle) => {
+                    println!("Transaction scheduled for when the computer is idle.")
+                }
+                Some(Schedule::AtShutdown) => {
+                    println!("Transaction scheduled for the next shutdown or log off.")
+                }
+                _ => println!("Transaction scheduled."),
+            }
+        }
+    }
+}
@@ -0,0 +1,313 @@
+//! HTTP+JSON gateway for tools that cannot speak gRPC over the daemon's socket.
+//!
+//! Requests are handled by the same service as the socket, but are never treated
+//! as coming from an administrator, so they cannot change settings, or install for
+//! the whole system on Windows. The gateway only binds to localhost, and turns
+//! away requests made by web pages.
+
+use std::convert::{Infallible, TryFrom};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::str::FromStr;
+
+use axum::extract::{Path, Query, State};
+use axum::http::{header, Request as HttpRequest, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post, put};
+use axum::{Json, Router};
+use futures::stream::{Stream, StreamExt};
+use pahkat_client::config::SettingKey;
+use serde::Deserialize;
+use tonic::Request;
+
+use crate::pb::pahkat_server::Pahkat;
+use crate::{pb, Rpc};
+
+struct Error(tonic::Status);
+
+impl From<tonic::Status> for Error {
+    fn from(status: tonic::Status) -> Self {
+        Error(status)
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        use tonic::Code;
+
+        let code = match self.0.code() {
+            Code::InvalidArgument | Code::FailedPrecondition => StatusCode::BAD_REQUEST,
+            Code::NotFound => StatusCode::NOT_FOUND,
+            Code::PermissionDenied | Code::Unauthenticated => StatusCode::FORBIDDEN,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        let body = serde_json::json!({ "error": self.0.message() });
+        (code, Json(body)).into_response()
+    }
+}
+
+type Result<T> = std::result::Result<Json<T>, Error>;
+
+#[derive(Debug, Deserialize)]
+struct StatusQuery {
+    package_id: String,
+    #[serde(default)]
+    target: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct SettingValue {
+    value: String,
+}
+
+/// Sends each message as the data of an event. Errors are sent as `error` events.
+fn sse_stream<T, S>(stream: S) -> Sse<impl Stream<Item = std::result::Result<Event, Infallible>>>
+where
+    T: serde::Serialize,
+    S: Stream<Item = std::result::Result<T, tonic::Status>> + Send + 'static,
+{
+    let events = stream.map(|message| {
+        let event = match message {
+            Ok(message) => Event::default().json_data(message),
+            Err(status) => Event::default()
+                .event("error")
+                .json_data(serde_json::json!({ "error": status.message() })),
+        };
+        Ok(event.unwrap_or_else(|_| Event::default().event("error")))
+    });
+
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+fn setting_key(name: &str) -> std::result::Result<i32, Error> {
+    let key =
+        SettingKey::from_str(name).map_err(|e| Error(tonic::Status::not_found(e.to_string())))?;
+    Ok(pb::SettingKey::try_from(key).map_err(Error)? as i32)
+}
+
+async fn notifications(State(rpc): State<Rpc>) -> std::result::Result<impl IntoResponse, Error> {
+    let stream = rpc
+        .notifications(Request::new(pb::NotificationsRequest {}))
+        .await?
+        .into_inner();
+    Ok(sse_stream(stream))
+}
+
+async fn refresh(State(rpc): State<Rpc>) -> Result<pb::RefreshResponse> {
+    let response = rpc.refresh(Request::new(pb::RefreshRequest {})).await?;
+    Ok(Json(response.into_inner()))
+}
+
+async fn status(
+    State(rpc): State<Rpc>,
+    Query(query): Query<StatusQuery>,
+) -> Result<pb::StatusResponse> {
+    let request = pb::StatusRequest {
+        package_id: query.package_id,
+        target: query.target,
+    };
+    let response = rpc.status(Request::new(request)).await?;
+    Ok(Json(response.into_inner()))
+}
+
+async fn dependency_status(
+    State(rpc): State<Rpc>,
+    Query(query): Query<StatusQuery>,
+) -> Result<pb::DependencyStatusResponse> {
+    let request = pb::StatusRequest {
+        package_id: query.package_id,
+        target: query.target,
+    };
+    let response = rpc.dependency_status(Request::new(request)).await?;
+    Ok(Json(response.into_inner()))
+}
+
+async fn repository_indexes(State(rpc): State<Rpc>) -> Result<pb::RepositoryIndexesResponse> {
+    let response = rpc
+        .repository_indexes(Request::new(pb::RepositoryIndexesRequest {}))
+        .await?;
+    Ok(Json(response.into_inner()))
+}
+
+async fn strings(
+    State(rpc): State<Rpc>,
+    Path(language): Path<String>,
+) -> Result<pb::StringsResponse> {
+    let response = rpc
+        .strings(Request::new(pb::StringsRequest { language }))
+        .await?;
+    Ok(Json(response.into_inner()))
+}
+
+/// The body is a package query, and is answered with the resolved query as is.
+async fn resolve_package_query(
+    State(rpc): State<Rpc>,
+    json: String,
+) -> std::result::Result<impl IntoResponse, Error> {
+    let response = rpc
+        .resolve_package_query(Request::new(pb::JsonRequest { json }))
+        .await?;
+    Ok((
+        [(header::CONTENT_TYPE, "application/json")],
+        response.into_inner().json,
+    ))
+}
+
+/// The JSON Schema document itself, rather than wrapped in a response message.
+async fn json_schema(
+    State(rpc): State<Rpc>,
+    Path(name): Path<String>,
+) -> std::result::Result<impl IntoResponse, Error> {
+    let response = rpc
+        .json_schema(Request::new(pb::JsonSchemaRequest { name: name.clone() }))
+        .await?;
+    let schema = response
+        .into_inner()
+        .schemas
+        .remove(&name)
+        .unwrap_or_default();
+    Ok(([(header::CONTENT_TYPE, "application/schema+json")], schema))
+}
+
+async fn process_transaction(
+    State(rpc): State<Rpc>,
+    Json(transaction): Json<pb::transaction_request::Transaction>,
+) -> impl IntoResponse {
+    let request = pb::TransactionRequest {
+        value: Some(pb::transaction_request::Value::Transaction(transaction)),
+    };
+    sse_stream(rpc.transaction(futures::stream::iter(vec![Ok(request)]), false))
+}
+
+async fn get_repo_records(State(rpc): State<Rpc>) -> Result<pb::GetRepoRecordsResponse> {
+    let response = rpc
+        .get_repo_records(Request::new(pb::GetRepoRecordsRequest {}))
+        .await?;
+    Ok(Json(response.into_inner()))
+}
+
+async fn set_repo(
+    State(rpc): State<Rpc>,
+    Json(request): Json<pb::SetRepoRequest>,
+) -> Result<pb::SetRepoResponse> {
+    let response = rpc.set_repo(Request::new(request)).await?;
+    Ok(Json(response.into_inner()))
+}
+
+async fn set_prerelease_channel(
+    State(rpc): State<Rpc>,
+    Json(request): Json<pb::SetPrereleaseChannelRequest>,
+) -> Result<pb::SetPrereleaseChannelResponse> {
+    let response = rpc.set_prerelease_channel(Request::new(request)).await?;
+    Ok(Json(response.into_inner()))
+}
+
+async fn accept_repo_key(
+    State(rpc): State<Rpc>,
+    Json(request): Json<pb::AcceptRepoKeyRequest>,
+) -> Result<pb::AcceptRepoKeyResponse> {
+    let response = rpc.accept_repo_key(Request::new(request)).await?;
+    Ok(Json(response.into_inner()))
+}
+
+async fn remove_repo(
+    State(rpc): State<Rpc>,
+    Json(request): Json<pb::RemoveRepoRequest>,
+) -> Result<pb::RemoveRepoResponse> {
+    let response = rpc.remove_repo(Request::new(request)).await?;
+    Ok(Json(response.into_inner()))
+}
+
+async fn get_repo_statistics(State(rpc): State<Rpc>) -> Result<pb::GetRepoStatisticsResponse> {
+    let response = rpc
+        .get_repo_statistics(Request::new(pb::GetRepoStatisticsRequest::default()))
+        .await?;
+    Ok(Json(response.into_inner()))
+}
+
+async fn get_setting(
+    State(rpc): State<Rpc>,
+    Path(key): Path<String>,
+) -> Result<pb::GetSettingResponse> {
+    let key = setting_key(&key)?;
+    let response = rpc
+        .get_setting(Request::new(pb::GetSettingRequest { key }))
+        .await?;
+    Ok(Json(response.into_inner()))
+}
+
+async fn set_setting(
+    State(rpc): State<Rpc>,
+    Path(key): Path<String>,
+    Json(body): Json<SettingValue>,
+) -> Result<pb::SetSettingResponse> {
+    let key = setting_key(&key)?;
+    let response = rpc
+        .set_setting(Request::new(pb::SetSettingRequest {
+            key,
+            value: body.value,
+        }))
+        .await?;
+    Ok(Json(response.into_inner()))
+}
+
+/// Browsers send `Origin` with cross-origin requests, and a `Host` other than
+/// localhost points to a DNS rebinding attempt.
+async fn local_only<B>(request: HttpRequest<B>, next: Next<B>) -> Response {
+    let headers = request.headers();
+    let is_local_host = headers
+        .get(header::HOST)
+        .and_then(|x| x.to_str().ok())
+        .map(|x| x.rsplitn(2, ':').last().unwrap_or(x))
+        .map(|x| x == "localhost" || x == "127.0.0.1")
+        .unwrap_or(false);
+
+    if headers.contains_key(header::ORIGIN) || !is_local_host {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    next.run(request).await
+}
+
+fn router(rpc: Rpc) -> Router {
+    Router::new()
+        .route("/v1/notifications", get(notifications))
+        .route("/v1/refresh", post(refresh))
+        .route("/v1/status", get(status))
+        .route("/v1/dependency-status", get(dependency_status))
+        .route("/v1/repository-indexes", get(repository_indexes))
+        .route("/v1/strings/:language", get(strings))
+        .route("/v1/resolve-package-query", post(resolve_package_query))
+        .route("/v1/schemas/:name", get(json_schema))
+        .route("/v1/transactions", post(process_transaction))
+        .route(
+            "/v1/repos",
+            get(get_repo_records).put(set_repo).delete(remove_repo),
+        )
+        .route("/v1/repos/statistics", get(get_repo_statistics))
+        .route("/v1/repos/keys", put(accept_repo_key))
+        .route("/v1/prerelease-channels", put(set_prerelease_channel))
+        .route("/v1/settings/:key", get(get_setting).put(set_setting))
+        .layer(middleware::from_fn(local_only))
+        .with_state(rpc)
+}
+
+/// Serves the gateway on `127.0.0.1:port` until the daemon stops.
+pub(crate) async fn serve(port: u16, rpc: Rpc) {
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    log::info!("Starting HTTP gateway on {}", addr);
+
+    let server = match axum::Server::try_bind(&addr) {
+        Ok(v) => v,
+        Err(e) => {
+            log::error!("Could not bind HTTP gateway to {}: {}", addr, e);
+            return;
+        }
+    };
+
+    if let Err(e) = server.serve(router(rpc).into_make_service()).await {
+        log::error!("HTTP gateway stopped: {}", e);
+    }
+}
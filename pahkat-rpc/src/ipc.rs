@@ -0,0 +1,106 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Overrides the socket path (or pipe name on Windows) for both the daemon and clients.
+pub const SOCKET_PATH_ENV: &str = "PAHKAT_SOCKET";
+
+/// Which daemon an endpoint belongs to, so that a system daemon and a daemon
+/// running in a user session can coexist on the same machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Session {
+    System,
+    User,
+}
+
+impl Session {
+    /// The session of the store this crate was built for. Prefix stores are owned
+    /// by a user, everything else is managed system-wide.
+    pub fn current() -> Session {
+        if cfg!(feature = "prefix") {
+            Session::User
+        } else {
+            Session::System
+        }
+    }
+
+    #[cfg(windows)]
+    pub fn default_path(self) -> PathBuf {
+        match self {
+            Session::System => PathBuf::from("//./pipe/pahkat"),
+            Session::User => PathBuf::from(format!("//./pipe/pahkat-{}", user_name())),
+        }
+    }
+
+    /// Both defaults are in directories only their owner can write to, unlike `/tmp`.
+    #[cfg(unix)]
+    pub fn default_path(self) -> PathBuf {
+        match self {
+            Session::System => PathBuf::from("/var/run/pahkat.sock"),
+            Session::User => match std::env::var_os("XDG_RUNTIME_DIR") {
+                Some(dir) => Path::new(&dir).join("pahkat.sock"),
+                None => pahkat_client::defaults::config_path()
+                    .map(|x| x.join("pahkat.sock"))
+                    .unwrap_or_else(|_| PathBuf::from("pahkat.sock")),
+            },
+        }
+    }
+
+    /// The `socket_path` setting of the daemon of this session, so that clients find
+    /// a daemon configured to listen elsewhere.
+    pub fn configured_path(self) -> Option<PathBuf> {
+        use pahkat_client::{config::Settings, defaults, Permission};
+
+        let config_dir = match self {
+            Session::System => defaults::system_config_path(),
+            Session::User => defaults::config_path(),
+        }
+        .ok()?;
+        let settings =
+            Settings::load(config_dir.join("settings.toml"), Permission::ReadOnly).ok()?;
+        settings.socket_path().map(Path::to_path_buf)
+    }
+}
+
+impl FromStr for Session {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "system" => Ok(Session::System),
+            "user" => Ok(Session::User),
+            _ => Err(format!(
+                "Unknown session `{}`, expected `system` or `user`",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(windows)]
+fn user_name() -> String {
+    std::env::var("USERNAME")
+        .ok()
+        .filter(|x| !x.is_empty())
+        .unwrap_or_else(|| "user".into())
+}
+
+/// Picks the endpoint from, in order: an explicit path (such as a command line
+/// flag), `PAHKAT_SOCKET`, the `socket_path` setting and the session default.
+pub fn socket_path(
+    explicit: Option<&Path>,
+    configured: Option<&Path>,
+    session: Session,
+) -> PathBuf {
+    if let Some(path) = explicit {
+        return path.to_path_buf();
+    }
+
+    if let Some(path) = std::env::var_os(SOCKET_PATH_ENV).filter(|x| !x.is_empty()) {
+        return PathBuf::from(path);
+    }
+
+    match configured {
+        Some(path) => path.to_path_buf(),
+        None => session.default_path(),
+    }
+}
@@ -1,13 +1,24 @@
 #![recursion_limit = "1024"]
 
+mod cancel;
 pub mod client;
+#[cfg(feature = "gateway")]
+mod gateway;
+pub mod ipc;
+mod schedule;
 pub mod server;
+mod updater;
 
 use futures::stream::StreamExt;
 
 use pahkat_client::{
-    config::RepoRecord, package_store::InstallTarget, PackageAction, PackageActionType, PackageKey,
-    PackageStatus, PackageStore, PackageTransaction,
+    config::{RepoRecord, SettingKey},
+    events::{EventBus, StoreEvent},
+    package_store::InstallTarget,
+    repo::{PackageCandidateError, RepoStatus},
+    transaction::observer::{ExecObserver, Observers, TransactionObserver},
+    AsyncPackageStore, PackageAction, PackageActionType, PackageKey, PackageStatus, PackageStore,
+    PackageTransaction,
 };
 use std::collections::HashMap;
 use std::convert::{TryFrom, TryInto};
@@ -17,7 +28,6 @@ use std::pin::Pin;
 use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
 
-use std::time::Duration;
 use task_collection::{GlobalTokioSpawner, TaskCollection};
 
 #[cfg(unix)]
@@ -31,26 +41,150 @@ use tonic::{Request, Response, Status};
 
 use url::Url;
 
-mod pb {
+/// Messages and the client of the daemon's gRPC service.
+pub mod pb {
     tonic::include_proto!("pahkat");
+
+    pub(crate) const FILE_DESCRIPTOR_SET: &[u8] =
+        tonic::include_file_descriptor_set!("pahkat_descriptor");
+}
+
+/// The encoded `FileDescriptorSet` of the Pahkat service, for generating bindings
+/// without a copy of the `.proto` file. The daemon also serves it over gRPC
+/// reflection.
+pub const FILE_DESCRIPTOR_SET: &[u8] = pb::FILE_DESCRIPTOR_SET;
+
+fn reflection_service() -> anyhow::Result<
+    tonic_reflection::server::ServerReflectionServer<
+        impl tonic_reflection::server::ServerReflection,
+    >,
+> {
+    Ok(tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(pb::FILE_DESCRIPTOR_SET)
+        .build()?)
+}
+
+static OBSERVERS: once_cell::sync::Lazy<Observers> = once_cell::sync::Lazy::new(Default::default);
+
+/// Registers an observer for every transaction processed by the daemon. Call this
+/// before `start` to be notified of transactions from the first request on.
+pub fn register_observer(observer: Arc<dyn TransactionObserver>) {
+    OBSERVERS.register(observer);
+}
+
+fn package_status(status: PackageStatus) -> i32 {
+    match status {
+        PackageStatus::NotInstalled => 0,
+        PackageStatus::UpToDate => 1,
+        PackageStatus::RequiresUpdate => 2,
+        PackageStatus::Unpublished => 3,
+    }
+}
+
+fn repo_status_to_i32(status: RepoStatus) -> i32 {
+    match status {
+        RepoStatus::Loaded => pb::RepoStatus::Loaded as i32,
+        RepoStatus::Failed => pb::RepoStatus::Failed as i32,
+        RepoStatus::KeyChanged => pb::RepoStatus::KeyChanged as i32,
+    }
+}
+
+fn observed(transaction: PackageTransaction) -> PackageTransaction {
+    transaction
+        .observers()
+        .register(Arc::new(OBSERVERS.clone()));
+    transaction
 }
 
 impl From<RepoRecord> for pb::RepoRecord {
     fn from(repo: RepoRecord) -> pb::RepoRecord {
         pb::RepoRecord {
+            has_auth_token: repo.auth_token.is_some(),
             channel: repo.channel.unwrap_or_else(|| "".into()),
+            prerelease_channels: repo.prerelease_channels.into_iter().collect(),
+            trusted_keys: repo.trusted_keys,
+            ..Default::default()
         }
     }
 }
 
-impl From<pb::PackageAction> for PackageAction {
-    fn from(input: pb::PackageAction) -> PackageAction {
+impl From<pb::SettingKey> for SettingKey {
+    fn from(key: pb::SettingKey) -> SettingKey {
+        match key {
+            pb::SettingKey::CacheDir => SettingKey::CacheDir,
+            pb::SettingKey::TmpDir => SettingKey::TmpDir,
+            pb::SettingKey::MaxConcurrentDownloads => SettingKey::MaxConcurrentDownloads,
+            pb::SettingKey::SkipAdminVerification => SettingKey::SkipAdminVerification,
+            pb::SettingKey::UpdateInterval => SettingKey::UpdateInterval,
+            pb::SettingKey::AutoUpdate => SettingKey::AutoUpdate,
+            pb::SettingKey::Proxy => SettingKey::Proxy,
+            pb::SettingKey::ReportUrl => SettingKey::ReportUrl,
+            pb::SettingKey::GatewayPort => SettingKey::GatewayPort,
+            pb::SettingKey::CacheSizeLimit => SettingKey::CacheSizeLimit,
+            pb::SettingKey::CachePolicy => SettingKey::CachePolicy,
+        }
+    }
+}
+
+/// Fails for settings that are not exposed over RPC.
+impl TryFrom<SettingKey> for pb::SettingKey {
+    type Error = Status;
+
+    fn try_from(key: SettingKey) -> std::result::Result<pb::SettingKey, Status> {
+        Ok(match key {
+            SettingKey::CacheDir => pb::SettingKey::CacheDir,
+            SettingKey::TmpDir => pb::SettingKey::TmpDir,
+            SettingKey::MaxConcurrentDownloads => pb::SettingKey::MaxConcurrentDownloads,
+            SettingKey::SkipAdminVerification => pb::SettingKey::SkipAdminVerification,
+            SettingKey::UpdateInterval => pb::SettingKey::UpdateInterval,
+            SettingKey::AutoUpdate => pb::SettingKey::AutoUpdate,
+            SettingKey::Proxy => pb::SettingKey::Proxy,
+            SettingKey::ReportUrl => pb::SettingKey::ReportUrl,
+            SettingKey::GatewayPort => pb::SettingKey::GatewayPort,
+            SettingKey::CacheSizeLimit => pb::SettingKey::CacheSizeLimit,
+            SettingKey::CachePolicy => pb::SettingKey::CachePolicy,
+            key => {
+                return Err(Status::invalid_argument(format!(
+                    "Setting `{}` is not available over RPC",
+                    key.as_str()
+                )))
+            }
+        })
+    }
+}
+
+/// The schemas of the client library, along with those of the transaction messages
+/// as the FFI passes them: the actions taken by `pahkat_rpc_process_transaction` and
+/// the events given to its callback.
+fn json_schemas() -> std::collections::BTreeMap<&'static str, schemars::schema::RootSchema> {
+    use pahkat_client::schema::{schema_for, schemas};
+
+    let mut schemas = schemas();
+    schemas.insert("TransactionActions", schema_for::<Vec<pb::PackageAction>>());
+    schemas.insert("TransactionEvent", schema_for::<pb::transaction_response::Value>());
+    schemas
+}
+
+fn setting_key(value: i32) -> std::result::Result<SettingKey, Status> {
+    pb::SettingKey::from_i32(value)
+        .map(SettingKey::from)
+        .ok_or_else(|| Status::invalid_argument(format!("Unknown setting key: {}", value)))
+}
+
+impl TryFrom<pb::PackageAction> for PackageAction {
+    type Error = Status;
+
+    fn try_from(input: pb::PackageAction) -> std::result::Result<PackageAction, Status> {
         log::trace!("pb PackageAction to PackageAction: {:?}", &input);
-        PackageAction {
-            id: PackageKey::try_from(&*input.id).unwrap(),
+        let id = PackageKey::try_from(&*input.id).map_err(|e| {
+            Status::failed_precondition(format!("Invalid package key `{}`: {}", input.id, e))
+        })?;
+        Ok(PackageAction {
+            id,
             action: PackageActionType::from_u8(input.action as u8),
             target: InstallTarget::from(input.target as u8),
-        }
+            options: input.options.into_iter().collect(),
+        })
     }
 }
 
@@ -60,16 +194,29 @@ impl From<PackageAction> for pb::PackageAction {
             id: input.id.to_string(),
             action: input.action.to_u8() as u32,
             target: input.target.to_u8() as u32,
+            options: input.options.into_iter().collect(),
         }
     }
 }
 
 impl From<pahkat_client::transaction::ResolvedAction> for pb::ResolvedAction {
     fn from(record: pahkat_client::transaction::ResolvedAction) -> Self {
+        let deprecation = record.deprecation().map(|x| pb::Deprecation {
+            severity: x.severity.to_u8() as u32,
+            message: x.message.clone().into_iter().collect(),
+        });
+        let payload = &record.target.payload;
         pb::ResolvedAction {
+            size: payload.size(),
+            installed_size: payload.installed_size(),
+            host: payload.url().host_str().unwrap_or_default().to_string(),
+            channel: record.release.channel.clone().unwrap_or_default(),
+            is_cached: record.is_cached,
             action: Some(record.action.into()),
             name: record.descriptor.name.into_iter().collect(),
             version: record.release.version.to_string(),
+            is_reboot_required: record.is_reboot_required,
+            deprecation,
         }
     }
 }
@@ -121,6 +268,7 @@ impl From<pahkat_client::repo::LoadedRepository> for pb::LoadedRepository {
                     .iter()
                     .map(|x| x.to_string())
                     .collect(),
+                signing_key: value.info.repository.signing_key.unwrap_or_default(),
             }),
             meta: Some(pb::loaded_repository::Meta {
                 channel: value.meta.clone().channel.unwrap_or_else(|| "".into()),
@@ -130,24 +278,94 @@ impl From<pahkat_client::repo::LoadedRepository> for pb::LoadedRepository {
     }
 }
 
-#[derive(Debug, Clone)]
-enum Notification {
-    RebootRequired,
-    RepositoriesChanged,
-    RpcStopping,
-    TransactionLocked,
-    TransactionUnlocked,
+fn notification_response(event: &StoreEvent) -> pb::NotificationResponse {
+    use pb::notification_response::ValueType;
+
+    let (value, package_keys) = match event {
+        StoreEvent::RebootRequired => (ValueType::RebootRequired, &[][..]),
+        StoreEvent::RepositoriesChanged => (ValueType::RepositoriesChanged, &[][..]),
+        StoreEvent::Stopping => (ValueType::RpcStopping, &[][..]),
+        StoreEvent::TransactionLocked => (ValueType::TransactionLocked, &[][..]),
+        StoreEvent::TransactionUnlocked => (ValueType::TransactionUnlocked, &[][..]),
+        StoreEvent::StateDrift(keys) => (ValueType::StateDrift, &keys[..]),
+        StoreEvent::StateCorrected(keys) => (ValueType::StateCorrected, &keys[..]),
+        StoreEvent::UpdatesDeferred(_) => (ValueType::UpdatesDeferred, &[][..]),
+        StoreEvent::EnableInputSources(_) => (ValueType::EnableInputSources, &[][..]),
+    };
+
+    let message = match event {
+        StoreEvent::UpdatesDeferred(reason) => reason.to_string(),
+        _ => String::new(),
+    };
+
+    let input_sources = match event {
+        StoreEvent::EnableInputSources(ids) => ids.clone(),
+        _ => vec![],
+    };
+
+    pb::NotificationResponse {
+        value: value as i32,
+        package_keys: package_keys.iter().map(|x| x.to_string()).collect(),
+        message,
+        input_sources,
+    }
 }
 
 type Result<T> = std::result::Result<Response<T>, Status>;
 type Stream<T> =
     Pin<Box<dyn futures::Stream<Item = std::result::Result<T, Status>> + Send + Sync + 'static>>;
 
+type TransactionResult = std::result::Result<pb::TransactionResponse, Status>;
+
+/// A transaction that is queued or running, which identical requests can attach to.
+/// Errors are kept too, so attached clients learn that the transaction failed.
+struct InFlightTransaction {
+    history: Vec<TransactionResult>,
+    events: broadcast::Sender<TransactionResult>,
+}
+
+type InFlight = Arc<std::sync::Mutex<HashMap<u64, InFlightTransaction>>>;
+
+#[derive(Clone)]
 struct Rpc {
     store: Arc<dyn PackageStore>,
-    notifications: broadcast::Sender<Notification>,
+    notifications: EventBus,
     current_transaction: Arc<tokio::sync::Mutex<()>>,
-    requires_reboot: AtomicBool,
+    canceler: cancel::Canceler,
+    in_flight: InFlight,
+    requires_reboot: Arc<AtomicBool>,
+    scheduler: Arc<schedule::Scheduler>,
+    /// Wakes the background updater, so a new update interval applies to its next run.
+    settings_changed: Arc<tokio::sync::Notify>,
+}
+
+/// Replays what an in-flight transaction has sent so far, then forwards its
+/// remaining events until it finishes.
+async fn follow_transaction(
+    history: Vec<TransactionResult>,
+    mut events: broadcast::Receiver<TransactionResult>,
+    tx: mpsc::Sender<TransactionResult>,
+) {
+    for response in history {
+        if tx.send(response).await.is_err() {
+            return;
+        }
+    }
+
+    loop {
+        let response = match events.recv().await {
+            Ok(v) => v,
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                log::warn!("Attached client missed {} transaction events", n);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+
+        if tx.send(response).await.is_err() {
+            return;
+        }
+    }
 }
 
 #[tonic::async_trait]
@@ -168,40 +386,27 @@ impl pb::pahkat_server::Pahkat for Rpc {
         // log::info!("Peer: {:?}", _request.peer_cred());
 
         let stream = async_stream::try_stream! {
-            use pb::notification_response::ValueType;
             // Do the initial checks
             if requires_reboot {
-                yield pb::NotificationResponse { value: ValueType::RebootRequired as i32 };
+                yield notification_response(&StoreEvent::RebootRequired);
             }
 
             if current_transaction.try_lock().is_err() {
-                yield pb::NotificationResponse { value: ValueType::TransactionLocked as i32 };
+                yield notification_response(&StoreEvent::TransactionLocked);
             }
 
             loop {
-                let response = rx.recv().await;
-                match response {
-                    Ok(response) => {
-                        match response {
-                            Notification::RebootRequired => {
-                                yield pb::NotificationResponse { value: ValueType::RebootRequired as i32 };
-                            }
-                            Notification::RepositoriesChanged => {
-                                yield pb::NotificationResponse { value: ValueType::RepositoriesChanged as i32 };
-                            }
-                            Notification::RpcStopping => {
-                                yield pb::NotificationResponse { value: ValueType::RpcStopping as i32 };
-                                break;
-                            }
-                            Notification::TransactionLocked => {
-                                yield pb::NotificationResponse { value: ValueType::TransactionLocked as i32 };
-                            }
-                            Notification::TransactionUnlocked => {
-                                yield pb::NotificationResponse { value: ValueType::TransactionUnlocked as i32 };
-                            }
+                match rx.recv().await {
+                    Ok(event) => {
+                        yield notification_response(&event);
+                        if event == StoreEvent::Stopping {
+                            break;
                         }
-                    },
-                    Err(_err) => {
+                    }
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        log::warn!("Notification subscriber missed {} events", n);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => {
                         break;
                     }
                 }
@@ -222,7 +427,6 @@ impl pb::pahkat_server::Pahkat for Rpc {
             log::error!("{:#?}", errors);
         };
 
-        let _ = self.notifications.send(Notification::RepositoriesChanged);
         Ok(Response::new(pb::RefreshResponse {}))
     }
 
@@ -254,9 +458,11 @@ impl pb::pahkat_server::Pahkat for Rpc {
         let package_id = PackageKey::try_from(&*request.package_id)
             .map_err(|e| Status::failed_precondition(format!("{}", e)))?;
 
-        let result = pahkat_client::transaction::status_to_i8(
-            self.store.status(&package_id, Default::default()),
-        );
+        let status = self
+            .store
+            .status_async(&package_id, Default::default())
+            .await;
+        let result = pahkat_client::transaction::status_to_i8(status);
         Ok(Response::new(pb::StatusResponse {
             value: result.try_into().unwrap(),
         }))
@@ -272,7 +478,42 @@ impl pb::pahkat_server::Pahkat for Rpc {
 
         let response = self
             .store
-            .dependency_status(&package_id, Default::default());
+            .dependency_status_async(&package_id, Default::default())
+            .await;
+
+        let dependencies = {
+            let key = package_id.clone();
+            let tree = self
+                .store
+                .blocking(move |store| {
+                    pahkat_client::repo::dependency_tree(store, &key, Default::default())
+                })
+                .await;
+            match tree {
+                Ok(tree) => tree,
+                Err(e) => Err(
+                    pahkat_client::transaction::PackageDependencyStatusError::Cancelled(
+                        package_id.clone(),
+                        e,
+                    ),
+                ),
+            }
+        };
+        let dependencies = match dependencies {
+            Ok(nodes) => nodes
+                .into_iter()
+                .map(|node| pb::dependency_status_response::Dependency {
+                    package_key: node.key.to_string(),
+                    status: package_status(node.status),
+                    constraint: node.constraint.to_string(),
+                    path: node.path.iter().map(|x| x.to_string()).collect(),
+                })
+                .collect(),
+            Err(e) => {
+                log::error!("Could not walk dependencies of {}: {}", &package_id, e);
+                vec![]
+            }
+        };
 
         let response = match response {
             Ok(response) => pb::DependencyStatusResponse {
@@ -280,17 +521,9 @@ impl pb::pahkat_server::Pahkat for Rpc {
                     pb::dependency_status_response::Status {
                         statuses: response
                             .into_iter()
-                            .map(|item| {
-                                (
-                                    item.0.to_string(),
-                                    match item.1 {
-                                        PackageStatus::NotInstalled => 0,
-                                        PackageStatus::UpToDate => 1,
-                                        PackageStatus::RequiresUpdate => 2,
-                                    },
-                                )
-                            })
+                            .map(|item| (item.0.to_string(), package_status(item.1)))
                             .collect(),
+                        dependencies,
                     },
                 )),
             },
@@ -307,6 +540,52 @@ impl pb::pahkat_server::Pahkat for Rpc {
         Ok(Response::new(response))
     }
 
+    async fn unpublished_packages(
+        &self,
+        request: Request<pb::UnpublishedPackagesRequest>,
+    ) -> Result<pb::UnpublishedPackagesResponse> {
+        let target = InstallTarget::from(request.into_inner().target as u8);
+        let keys = self
+            .store
+            .blocking(move |store| store.unpublished_packages(target))
+            .await
+            .map_err(|e| Status::unavailable(e.to_string()))?;
+
+        Ok(Response::new(pb::UnpublishedPackagesResponse {
+            package_keys: keys.iter().map(|x| x.to_string()).collect(),
+        }))
+    }
+
+    async fn install_failures(
+        &self,
+        _request: Request<pb::InstallFailuresRequest>,
+    ) -> Result<pb::InstallFailuresResponse> {
+        let (failures, policy) = {
+            let config = self.store.config();
+            let config = config.read().unwrap();
+            let failures = pahkat_client::failures::load(&pahkat_client::failures::path(&config));
+            (failures, config.settings().install_failures())
+        };
+
+        Ok(Response::new(pb::InstallFailuresResponse {
+            failures: failures
+                .into_iter()
+                .map(|x| pb::install_failures_response::InstallFailure {
+                    retry_at: policy
+                        .retry_at(&x)
+                        .map(|x| x.to_rfc3339())
+                        .unwrap_or_default(),
+                    package_key: x.key.to_string(),
+                    version: x.version,
+                    count: x.count,
+                    last_error: x.last_error,
+                    first_seen: x.first_seen.to_rfc3339(),
+                    last_seen: x.last_seen.to_rfc3339(),
+                })
+                .collect(),
+        }))
+    }
+
     async fn repository_indexes(
         &self,
         _request: Request<pb::RepositoryIndexesRequest>,
@@ -328,34 +607,645 @@ impl pb::pahkat_server::Pahkat for Rpc {
     ) -> Result<Self::ProcessTransactionStream> {
         #[cfg(windows)]
         let is_admin = request.has_admin_flag();
+        #[cfg(not(windows))]
+        let is_admin = false;
+
+        Ok(Response::new(
+            self.transaction(request.into_inner(), is_admin),
+        ))
+    }
+
+    async fn set_repo(
+        &self,
+        request: tonic::Request<pb::SetRepoRequest>,
+    ) -> Result<pb::SetRepoResponse> {
+        let is_admin = request.has_admin_flag();
+        let request = request.into_inner();
+
+        // The request is not logged in full, as it may hold an auth token
+        log::debug!("Setting repo: {}", &request.url);
+
+        let url =
+            Url::parse(&request.url).map_err(|e| Status::failed_precondition(format!("{}", e)))?;
+        log::trace!("Url: {:?}", &url);
+        let url = pahkat_client::types::repo::RepoUrl::new(url).map_err(|e| {
+            log::debug!("Bad repo url: {:?}", e);
+            Status::failed_precondition(format!("{}", e))
+        })?;
+        log::trace!("Repo url: {:?}", &url);
+
+        let config = self.store.config();
+        let change = {
+            let mut config = config.write().unwrap();
+            let secrets = config.settings().secret_store();
+            let repos = config.repos_mut();
+
+            // Only the channel, auth token and trusted keys can be set over RPC, so other
+            // fields of an existing record are kept.
+            let existing = repos.get(&url).cloned();
+            let mut record = existing.clone().unwrap_or_default();
+            let mut is_token_set = false;
+
+            if let Some(other_record) = request.settings {
+                record.channel = match other_record.channel.as_str() {
+                    "" => None,
+                    _ => Some(other_record.channel),
+                };
+
+                let token = match other_record.auth_token.as_str() {
+                    _ if other_record.clear_auth_token => Some(None),
+                    "" => None,
+                    token => Some(Some(token)),
+                };
+                if let Some(token) = token {
+                    record
+                        .set_auth_token(&url, token, &secrets)
+                        .map_err(|e| Status::failed_precondition(format!("{}", e)))?;
+                    is_token_set = token.is_some();
+                }
+
+                // Trusted keys decide which indexes are installed from, so only admins
+                // may change them.
+                let is_trust_changed =
+                    other_record.clear_trusted_keys || !other_record.trusted_keys.is_empty();
+                if is_trust_changed && !is_admin {
+                    return Err(Status::permission_denied(
+                        "Changing trusted keys requires administrator privileges",
+                    ));
+                }
+
+                if other_record.clear_trusted_keys {
+                    record.trusted_keys = vec![];
+                } else if !other_record.trusted_keys.is_empty() {
+                    for key in other_record.trusted_keys.iter() {
+                        pahkat_client::trust::check_key(key)
+                            .map_err(|e| Status::invalid_argument(format!("{}", e)))?;
+                    }
+                    record.trusted_keys = other_record.trusted_keys;
+                }
+            }
+
+            let change = match existing {
+                None => pb::set_repo_response::Change::Created,
+                Some(existing) if existing != record || is_token_set => {
+                    pb::set_repo_response::Change::Updated
+                }
+                Some(_) => pb::set_repo_response::Change::Unchanged,
+            };
+
+            if change != pb::set_repo_response::Change::Unchanged {
+                repos
+                    .insert(url.clone(), record)
+                    .map_err(|e| Status::failed_precondition(format!("{}", e)))?;
+            }
+
+            change
+        };
+
+        log::debug!("Repo {} {:?}", &url, change);
+
+        // An unchanged repo is still reloaded if it failed to load previously.
+        let is_loaded = self.store.repos().read().unwrap().contains_key(&url);
+        let errors = if change == pb::set_repo_response::Change::Unchanged && is_loaded {
+            HashMap::new()
+        } else {
+            match self.store.refresh_repo(&url).await {
+                Ok(_) => HashMap::new(),
+                Err(e) => e.into_iter().collect(),
+            }
+        };
+
+        let config = config.read().unwrap();
+        let repos = config.repos();
+
+        Ok(tonic::Response::new(pb::SetRepoResponse {
+            records: repos
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_owned().into()))
+                .collect(),
+            errors: errors
+                .iter()
+                .map(|(k, v)| (k.to_string(), format!("{:?}", v)))
+                .collect(),
+            change: change as i32,
+        }))
+    }
+
+    async fn set_prerelease_channel(
+        &self,
+        request: tonic::Request<pb::SetPrereleaseChannelRequest>,
+    ) -> Result<pb::SetPrereleaseChannelResponse> {
+        let request = request.into_inner();
+        let key = PackageKey::try_from(&*request.package_key)
+            .map_err(|e| Status::failed_precondition(format!("{}", e)))?
+            .without_query_params();
+        let channel = match request.channel.trim() {
+            "" => None,
+            channel => Some(channel.to_string()),
+        };
+        log::debug!("Setting pre-release channel of {}: {:?}", &key, &channel);
+
+        let config = self.store.config();
+        let is_changed = {
+            let mut config = config.write().unwrap();
+            let repos = config.repos_mut();
+            let mut record = repos.get(&key.repository_url).cloned().ok_or_else(|| {
+                Status::not_found(format!("{} is not configured", &key.repository_url))
+            })?;
+
+            let previous = match &channel {
+                Some(channel) => record
+                    .prerelease_channels
+                    .insert(key.id.clone(), channel.clone()),
+                None => record.prerelease_channels.remove(&key.id),
+            };
+
+            let is_changed = previous != channel;
+            if is_changed {
+                repos
+                    .insert(key.repository_url.clone(), record)
+                    .map_err(|e| Status::failed_precondition(format!("{}", e)))?;
+            }
+            is_changed
+        };
+
+        if is_changed {
+            if let Err(errors) = self.store.refresh_repo(&key.repository_url).await {
+                log::error!("Could not reload {}: {:?}", &key.repository_url, errors);
+            }
+        }
+
+        let status = self.store.status_async(&key, Default::default()).await;
+        let status = pahkat_client::transaction::status_to_i8(status);
+        Ok(tonic::Response::new(pb::SetPrereleaseChannelResponse {
+            status: status.into(),
+        }))
+    }
+
+    async fn get_repo_records(
+        &self,
+        _request: tonic::Request<pb::GetRepoRecordsRequest>,
+    ) -> Result<pb::GetRepoRecordsResponse> {
+        let records = {
+            let config = self.store.config();
+            let config = config.read().unwrap();
+            config
+                .repos()
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_owned().into()))
+                .collect()
+        };
+
+        let errors = self.store.errors();
+        let errors = errors.read().unwrap();
+
+        Ok(tonic::Response::new(pb::GetRepoRecordsResponse {
+            records,
+            errors: errors
+                .iter()
+                .map(|(k, v)| (k.to_string(), format!("{:?}", v)))
+                .collect(),
+            statuses: errors
+                .iter()
+                .map(|(k, v)| (k.to_string(), repo_status_to_i32(RepoStatus::of(Some(v)))))
+                .collect(),
+        }))
+    }
+
+    async fn accept_repo_key(
+        &self,
+        request: tonic::Request<pb::AcceptRepoKeyRequest>,
+    ) -> Result<pb::AcceptRepoKeyResponse> {
+        // A pinned key is trusted for every user of the daemon.
+        if !request.has_admin_flag() {
+            return Err(Status::permission_denied(
+                "Accepting signing keys requires administrator privileges",
+            ));
+        }
+
+        let request = request.into_inner();
+        let url =
+            Url::parse(&request.url).map_err(|e| Status::failed_precondition(format!("{}", e)))?;
+        let url = pahkat_client::types::repo::RepoUrl::new(url)
+            .map_err(|e| Status::failed_precondition(format!("{}", e)))?;
+
+        let config = self.store.config();
+        let path = {
+            let config = config.read().unwrap();
+            if config.repos().get(&url).is_none() {
+                return Err(Status::not_found(format!("{} is not configured", &url)));
+            }
+            pahkat_client::trust::path(&config)
+        };
+
+        let key = request.key.trim();
+        if key.is_empty() {
+            return Err(Status::invalid_argument("No signing key given"));
+        }
+
+        log::info!("Accepting signing key of {}: {}", &url, key);
+        match pahkat_client::trust::pin(&path, &url, key) {
+            Ok(()) => {}
+            Err(e @ pahkat_client::trust::TrustError::InvalidKey(_)) => {
+                return Err(Status::invalid_argument(format!("{}", e)))
+            }
+            Err(e) => return Err(Status::internal(format!("{}", e))),
+        }
+
+        let errors = match self.store.refresh_repo(&url).await {
+            Ok(_) => HashMap::new(),
+            Err(e) => e.into_iter().collect(),
+        };
+
+        let config = config.read().unwrap();
+        Ok(tonic::Response::new(pb::AcceptRepoKeyResponse {
+            records: config
+                .repos()
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_owned().into()))
+                .collect(),
+            errors: errors
+                .iter()
+                .map(|(k, v)| (k.to_string(), format!("{:?}", v)))
+                .collect(),
+        }))
+    }
+
+    async fn get_repo_statistics(
+        &self,
+        request: tonic::Request<pb::GetRepoStatisticsRequest>,
+    ) -> Result<pb::GetRepoStatisticsResponse> {
+        let request = request.into_inner();
+
+        let urls = if request.url.is_empty() {
+            let config = self.store.config();
+            let config = config.read().unwrap();
+            config.repos().keys().cloned().collect::<Vec<_>>()
+        } else {
+            let url = Url::parse(&request.url)
+                .map_err(|e| Status::failed_precondition(format!("{}", e)))?;
+            let url = pahkat_client::types::repo::RepoUrl::new(url)
+                .map_err(|e| Status::failed_precondition(format!("{}", e)))?;
+            vec![url]
+        };
+
+        let repos = urls
+            .iter()
+            .filter_map(|url| self.store.repo_statistics(url))
+            .map(|x| pb::get_repo_statistics_response::RepoStatistics {
+                url: x.url,
+                package_count: x.package_count as u64,
+                channels: x.channels,
+                payload_size: x.payload_size,
+                last_refresh: x
+                    .last_refresh
+                    .map(|x| x.to_rfc3339())
+                    .unwrap_or_default(),
+                cache_size: x.cache_size,
+                payload_failures: x
+                    .payload_failures
+                    .into_iter()
+                    .map(|x| pb::get_repo_statistics_response::HostFailures {
+                        host: x.host,
+                        count: x.count,
+                        last_url: x.last_url,
+                        last_error: x.last_error,
+                        last_seen: x.last_seen.to_rfc3339(),
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        Ok(tonic::Response::new(pb::GetRepoStatisticsResponse { repos }))
+    }
+
+    async fn remove_repo(
+        &self,
+        request: tonic::Request<pb::RemoveRepoRequest>,
+    ) -> Result<pb::RemoveRepoResponse> {
+        #[cfg(windows)]
+        let is_admin = request.has_admin_flag();
+        let request = request.into_inner();
+
+        let url =
+            Url::parse(&request.url).map_err(|e| Status::failed_precondition(format!("{}", e)))?;
+        let url = pahkat_client::types::repo::RepoUrl::new(url)
+            .map_err(|e| Status::failed_precondition(format!("{}", e)))?;
+
+        let config = self.store.config();
+
+        // Packages are uninstalled while the repository's index is still loaded
+        let purged = if request.purge {
+            let target = InstallTarget::from(request.target as u8);
+
+            // Purging runs an uninstall transaction, which needs the same rights
+            #[cfg(windows)]
+            if target == InstallTarget::System && !is_admin {
+                return Err(Status::permission_denied(
+                    "Purging system packages requires an elevated client",
+                ));
+            }
+
+            let purge_url = url.clone();
+            let keys = self
+                .store
+                .blocking(move |store| store.installed_from_repo(&purge_url, target))
+                .await
+                .map_err(|e| Status::unavailable(e.to_string()))?;
+
+            if request.dry_run {
+                let config = config.read().unwrap();
+                return Ok(tonic::Response::new(pb::RemoveRepoResponse {
+                    records: config
+                        .repos()
+                        .iter()
+                        .map(|(k, v)| (k.to_string(), v.to_owned().into()))
+                        .collect(),
+                    errors: HashMap::new(),
+                    was_present: config.repos().keys().any(|x| x == &url),
+                    purged_packages: keys.iter().map(|x| x.to_string()).collect(),
+                }));
+            }
+
+            if !keys.is_empty() {
+                self.uninstall_all(keys.clone(), target).await?;
+            }
+            keys
+        } else {
+            vec![]
+        };
+
+        let was_present = self
+            .store
+            .remove_repo(&url)
+            .map_err(|e| Status::failed_precondition(format!("{}", e)))?;
+
+        // Reload the rest so that repositories only linked from this one are dropped too.
+        let errors = if was_present {
+            match self.store.refresh_repos().await {
+                Ok(_) => HashMap::new(),
+                Err(e) => e.into_iter().collect(),
+            }
+        } else {
+            HashMap::new()
+        };
+
+        let config = config.read().unwrap();
+        let repos = config.repos();
+
+        Ok(tonic::Response::new(pb::RemoveRepoResponse {
+            records: repos
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_owned().into()))
+                .collect(),
+            errors: errors
+                .iter()
+                .map(|(k, v)| (k.to_string(), format!("{:?}", v)))
+                .collect(),
+            was_present,
+            purged_packages: purged.iter().map(|x| x.to_string()).collect(),
+        }))
+    }
+
+    async fn get_setting(
+        &self,
+        request: Request<pb::GetSettingRequest>,
+    ) -> Result<pb::GetSettingResponse> {
+        let key = setting_key(request.into_inner().key)?;
+
+        let config = self.store.config();
+        let config = config.read().unwrap();
+
+        Ok(Response::new(pb::GetSettingResponse {
+            value: config.settings().get(key),
+        }))
+    }
+
+    async fn set_setting(
+        &self,
+        request: Request<pb::SetSettingRequest>,
+    ) -> Result<pb::SetSettingResponse> {
+        // Settings apply to the whole daemon, so only admins may change them.
+        if !request.has_admin_flag() {
+            return Err(Status::permission_denied(
+                "Changing settings requires administrator privileges",
+            ));
+        }
+
         let request = request.into_inner();
+        let key = setting_key(request.key)?;
+
+        log::debug!("Setting {} to {:?}", key, &request.value);
+
+        let value = {
+            let config = self.store.config();
+            let mut config = config.write().unwrap();
+            let settings = config.settings_mut();
+
+            settings
+                .set(key, &request.value)
+                .map_err(|e| Status::failed_precondition(format!("{}", e)))?;
+            settings.get(key)
+        };
+
+        self.settings_changed.notify_one();
+
+        Ok(Response::new(pb::SetSettingResponse { value }))
+    }
+
+    #[cfg(feature = "prefix")]
+    async fn get_prefixes(
+        &self,
+        _request: Request<pb::GetPrefixesRequest>,
+    ) -> Result<pb::GetPrefixesResponse> {
+        let prefixes = pahkat_client::config::Prefixes::load_default()
+            .map_err(|e| Status::failed_precondition(format!("{}", e)))?;
+
+        Ok(Response::new(pb::GetPrefixesResponse {
+            prefixes: prefix_records(&prefixes),
+            default: prefixes.default_name().unwrap_or_default().to_string(),
+        }))
+    }
+
+    #[cfg(not(feature = "prefix"))]
+    async fn get_prefixes(
+        &self,
+        _request: Request<pb::GetPrefixesRequest>,
+    ) -> Result<pb::GetPrefixesResponse> {
+        Err(Status::unimplemented(
+            "Named prefixes are only available in prefix builds",
+        ))
+    }
+
+    #[cfg(feature = "prefix")]
+    async fn set_default_prefix(
+        &self,
+        request: Request<pb::SetDefaultPrefixRequest>,
+    ) -> Result<pb::SetDefaultPrefixResponse> {
+        let name = request.into_inner().name;
+        let mut prefixes = pahkat_client::config::Prefixes::load_default()
+            .map_err(|e| Status::failed_precondition(format!("{}", e)))?;
+
+        let is_registered = prefixes
+            .set_default(&name)
+            .map_err(|e| Status::failed_precondition(format!("{}", e)))?;
+        if !is_registered {
+            return Err(Status::not_found(format!(
+                "No prefix named {} is registered",
+                name
+            )));
+        }
+
+        Ok(Response::new(pb::SetDefaultPrefixResponse {
+            prefixes: prefix_records(&prefixes),
+            default: name,
+        }))
+    }
+
+    #[cfg(not(feature = "prefix"))]
+    async fn set_default_prefix(
+        &self,
+        _request: Request<pb::SetDefaultPrefixRequest>,
+    ) -> Result<pb::SetDefaultPrefixResponse> {
+        Err(Status::unimplemented(
+            "Named prefixes are only available in prefix builds",
+        ))
+    }
+
+    async fn resolve_package_query(
+        &self,
+        request: Request<pb::JsonRequest>,
+    ) -> Result<pb::JsonResponse> {
+        log::debug!("Received resolve_package_query request: {:?}", &request);
+        let json = request.into_inner().json;
+        let query: pahkat_client::repo::PackageQuery = serde_json::from_str(&json)
+            .map_err(|e| Status::failed_precondition(format!("{}", e)))?;
+
+        let results = self
+            .store
+            .resolve_package_query(query, &[InstallTarget::System, InstallTarget::User]);
+        log::debug!("resolve_package_query results: {:?}", &results);
+        Ok(tonic::Response::new(pb::JsonResponse {
+            json: serde_json::to_string(&results).unwrap(),
+        }))
+    }
+
+    async fn json_schema(
+        &self,
+        request: Request<pb::JsonSchemaRequest>,
+    ) -> Result<pb::JsonSchemaResponse> {
+        let name = request.into_inner().name;
+        let mut schemas = json_schemas();
+        if !name.is_empty() {
+            schemas.retain(|k, _| *k == name);
+            if schemas.is_empty() {
+                return Err(Status::not_found(format!("No schema named `{}`", name)));
+            }
+        }
+
+        Ok(Response::new(pb::JsonSchemaResponse {
+            schemas: schemas
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), serde_json::to_string(&v).unwrap()))
+                .collect(),
+        }))
+    }
+}
+
+impl Rpc {
+    /// Uninstalls `keys` in one transaction, waiting for the transaction lock. Fails
+    /// if any of them could not be uninstalled.
+    async fn uninstall_all(
+        &self,
+        keys: Vec<PackageKey>,
+        target: InstallTarget,
+    ) -> std::result::Result<(), Status> {
+        use pahkat_client::transaction::TransactionEvent;
+
+        let actions = keys
+            .into_iter()
+            .map(|x| PackageAction::uninstall(x, target))
+            .collect::<Vec<_>>();
+        let transaction = PackageTransaction::new(Arc::clone(&self.store), actions)
+            .map_err(|e| Status::failed_precondition(format!("{}", e)))?;
+        let transaction = observed(transaction);
+
+        log::debug!("Waiting for transaction lock…");
+        let _guard = self.current_transaction.lock().await;
+        self.notifications.publish(StoreEvent::TransactionLocked);
+
+        let (trigger, stream) = transaction.process();
+        self.canceler.set(trigger);
+        futures::pin_mut!(stream);
+
+        let mut errors = vec![];
+        let mut is_complete = false;
+        while let Some(event) = stream.next().await {
+            match event {
+                TransactionEvent::Error(key, e) => errors.push(format!("{}: {}", key, e)),
+                TransactionEvent::RebootRequired(..) => {
+                    self.requires_reboot
+                        .store(true, std::sync::atomic::Ordering::SeqCst);
+                }
+                TransactionEvent::Complete => is_complete = true,
+                event => log::trace!("{:?}", event),
+            }
+        }
+        self.canceler.clear();
+        self.notifications.publish(StoreEvent::TransactionUnlocked);
+
+        if !errors.is_empty() {
+            return Err(Status::aborted(format!(
+                "Could not uninstall packages: {}",
+                errors.join("; ")
+            )));
+        }
+        if !is_complete {
+            return Err(Status::aborted("Uninstalling was cancelled"));
+        }
+        Ok(())
+    }
+
+    /// Runs the transaction requested on `request`, streaming its events back.
+    /// `is_admin` is only checked on Windows, where system installs require it.
+    fn transaction<S>(&self, request: S, is_admin: bool) -> Stream<pb::TransactionResponse>
+    where
+        S: futures::Stream<Item = std::result::Result<pb::TransactionRequest, Status>>
+            + Send
+            + 'static,
+    {
+        #[cfg(not(windows))]
+        let _ = is_admin;
         let store: Arc<dyn PackageStore> = Arc::clone(&self.store as _);
         let current_transaction = Arc::clone(&self.current_transaction);
+        let canceler = self.canceler.clone();
+        let in_flight = Arc::clone(&self.in_flight);
+        let daemon_requires_reboot = Arc::clone(&self.requires_reboot);
         let notifications = self.notifications.clone();
+        let scheduler = Arc::clone(&self.scheduler);
 
         let (tx, rx) = mpsc::channel(1);
         // Get messages
         tokio::spawn(async move {
             let mut has_requested = false;
             let mut has_cancelled = false;
-            let mut requires_reboot = false;
 
             futures::pin_mut!(request);
             let collection = TaskCollection::new(GlobalTokioSpawner);
 
             'listener: loop {
-                let request = request.message().await;
+                let request = request.next().await;
 
                 let value = match request {
-                    Ok(Some(v)) => match v.value {
+                    Some(Ok(v)) => match v.value {
                         Some(v) => v,
                         None => return,
                     },
-                    Err(err) => {
+                    Some(Err(err)) => {
                         log::error!("{:?}", err);
                         return;
                     }
-                    Ok(None) => break 'listener,
+                    None => break 'listener,
                 };
 
                 let request = match value {
@@ -378,14 +1268,44 @@ impl pb::pahkat_server::Pahkat for Rpc {
                     }
                 };
 
-                let actions = request
+                let allow_deprecated = request.allow_deprecated;
+                let is_atomic = request.atomic;
+                let schedule = pb::transaction_request::Schedule::from_i32(request.schedule)
+                    .unwrap_or(pb::transaction_request::Schedule::Immediate);
+                let actions = match request
                     .actions
                     .into_iter()
-                    .map(|x| PackageAction::from(x))
-                    .collect::<Vec<_>>();
-
-                let transaction = match PackageTransaction::new(Arc::clone(&store) as _, actions) {
+                    .map(PackageAction::try_from)
+                    .collect::<std::result::Result<Vec<_>, _>>()
+                {
                     Ok(v) => v,
+                    Err(status) => {
+                        if let Err(err) = tx.send(Err(status)).await {
+                            log::error!("{:?}", err);
+                        }
+                        break 'listener;
+                    }
+                };
+
+                let transaction = match PackageTransaction::new(Arc::clone(&store) as _, actions.clone()) {
+                    Ok(v) => observed(v.with_atomic(is_atomic)),
+                    Err(PackageCandidateError::ClientUpdateRequired(key, version)) => {
+                        let response = pb::TransactionResponse {
+                            value: Some(pb::transaction_response::Value::ClientUpdateRequired(
+                                pb::transaction_response::ClientUpdateRequired {
+                                    package_id: key.to_string(),
+                                    required_version: version,
+                                },
+                            )),
+                        };
+                        match tx.send(Ok(response)).await {
+                            Ok(_) => {}
+                            Err(err) => {
+                                log::error!("{:?}", err);
+                            }
+                        }
+                        break 'listener;
+                    }
                     Err(e) => {
                         let response = pb::TransactionResponse {
                             value: Some(pb::transaction_response::Value::TransactionError(
@@ -405,17 +1325,114 @@ impl pb::pahkat_server::Pahkat for Rpc {
                     }
                 };
 
+                if !allow_deprecated {
+                    if let Some(record) = transaction.critically_deprecated().first() {
+                        let response = pb::TransactionResponse {
+                            value: Some(pb::transaction_response::Value::ReleaseDeprecated(
+                                pb::transaction_response::ReleaseDeprecated {
+                                    package_id: record.action.id.to_string(),
+                                    message: record
+                                        .release
+                                        .deprecation
+                                        .iter()
+                                        .flat_map(|x| x.message.clone())
+                                        .collect(),
+                                },
+                            )),
+                        };
+                        match tx.send(Ok(response)).await {
+                            Ok(_) => {}
+                            Err(err) => {
+                                log::error!("{:?}", err);
+                            }
+                        }
+                        break 'listener;
+                    }
+                }
+
+                if schedule != pb::transaction_request::Schedule::Immediate {
+                    #[cfg(windows)]
+                    let is_denied = !is_admin
+                        && transaction
+                            .actions()
+                            .iter()
+                            .any(|x| x.action.target == InstallTarget::System);
+                    #[cfg(not(windows))]
+                    let is_denied = false;
+
+                    let value = if is_denied {
+                        pb::transaction_response::Value::VerificationFailed(
+                            pb::transaction_response::VerificationFailed {},
+                        )
+                    } else {
+                        scheduler.schedule(schedule, actions);
+                        pb::transaction_response::Value::TransactionScheduled(
+                            pb::transaction_response::TransactionScheduled {
+                                schedule: schedule as i32,
+                            },
+                        )
+                    };
+
+                    let response = pb::TransactionResponse { value: Some(value) };
+                    if let Err(err) = tx.send(Ok(response)).await {
+                        log::error!("{:?}", err);
+                    }
+                    break 'listener;
+                }
+
+                // If another client already submitted the same set of actions, follow
+                // its events rather than running the transaction a second time.
+                let fingerprint = transaction.fingerprint();
+                let attached = {
+                    let mut in_flight = in_flight.lock().unwrap();
+                    match in_flight.entry(fingerprint) {
+                        std::collections::hash_map::Entry::Occupied(x) => {
+                            Some((x.get().history.clone(), x.get().events.subscribe()))
+                        }
+                        std::collections::hash_map::Entry::Vacant(x) => {
+                            x.insert(InFlightTransaction {
+                                history: vec![],
+                                events: broadcast::channel(64).0,
+                            });
+                            None
+                        }
+                    }
+                };
+
+                if let Some((history, events)) = attached {
+                    #[cfg(windows)]
+                    if !is_admin
+                        && transaction
+                            .actions()
+                            .iter()
+                            .any(|x| x.action.target == InstallTarget::System)
+                    {
+                        let response = pb::TransactionResponse {
+                            value: Some(pb::transaction_response::Value::VerificationFailed(
+                                pb::transaction_response::VerificationFailed {},
+                            )),
+                        };
+                        let _ = tx.send(Ok(response)).await;
+                        return;
+                    }
+
+                    log::info!("Attaching to in-flight transaction {:016x}", fingerprint);
+                    follow_transaction(history, events, tx).await;
+                    return;
+                }
+
                 let store = Arc::clone(&store);
                 let current_transaction = Arc::clone(&current_transaction);
+                let in_flight = Arc::clone(&in_flight);
+                let daemon_requires_reboot = Arc::clone(&daemon_requires_reboot);
+                let canceler = canceler.clone();
 
                 let tx = tx.clone();
                 let notifications = notifications.clone();
 
                 collection.spawn(async move {
                     // If there is a running transaction, we must block on this transaction and wait
-                    let tx1 = tx.clone();
                     let stream = async_stream::try_stream! {
-                        let tx = tx1;
                         use pahkat_client::package_store::DownloadEvent;
                         use pb::transaction_response::*;
 
@@ -445,63 +1462,66 @@ impl pb::pahkat_server::Pahkat for Rpc {
                             }
                         };
                         log::debug!("Transaction lock attained.");
-                        let _ = notifications.clone().send(Notification::TransactionLocked);
-
-                        requires_reboot = transaction.is_reboot_required();
+                        notifications.publish(StoreEvent::TransactionLocked);
 
                         yield pb::TransactionResponse {
                             value: Some(Value::TransactionStarted(TransactionStarted {
                                 actions: transaction.actions().iter().cloned().map(|x| x.into()).collect(),
-                                is_reboot_required: requires_reboot,
+                                is_reboot_required: transaction.is_reboot_required(),
                             }))
                         };
 
-                        for record in transaction.actions().iter() {
-                            let _tx = tx.clone();
-
-                            if record.action.action != PackageActionType::Install {
-                                continue;
-                            }
-
-                            let id = record.action.id.clone();
-                            let mut download = store.download(&record.action.id);
+                        let concurrency = {
+                            let config = store.config();
+                            let config = config.read().unwrap();
+                            config.settings().download_concurrency()
+                        };
+                        let mut downloads = transaction.download(concurrency);
 
-                            // TODO: handle cancel here
+                        // TODO: handle cancel here
 
-                            while let Some(event) = download.next().await {
-                                match event {
-                                    DownloadEvent::Error(e) => {
-                                        yield pb::TransactionResponse {
-                                            value: Some(Value::TransactionError(TransactionError {
-                                                package_id: id.to_string(),
-                                                error: format!("{}", e)
-                                            }))
-                                        };
-                                        return;
-                                    }
-                                    DownloadEvent::Progress((current, total)) => {
-                                        yield pb::TransactionResponse {
-                                            value: Some(Value::DownloadProgress(DownloadProgress {
-                                                package_id: id.to_string(),
-                                                current,
-                                                total,
-                                            }))
-                                        };
-                                    }
-                                    DownloadEvent::Complete(_) => {
-                                        yield pb::TransactionResponse {
-                                            value: Some(Value::DownloadComplete(DownloadComplete {
-                                                package_id: id.to_string(),
-                                            }))
-                                        };
-                                    }
+                        while let Some((id, event)) = downloads.next().await {
+                            match event {
+                                DownloadEvent::Error(e) => {
+                                    yield pb::TransactionResponse {
+                                        value: Some(Value::TransactionError(TransactionError {
+                                            package_id: id.to_string(),
+                                            error: format!("{}", e)
+                                        }))
+                                    };
+                                    return;
+                                }
+                                DownloadEvent::Progress(x) => {
+                                    yield pb::TransactionResponse {
+                                        value: Some(Value::DownloadProgress(DownloadProgress {
+                                            package_id: id.to_string(),
+                                            current: x.current,
+                                            total: x.total,
+                                            percent: x.percent.map(u32::from).unwrap_or(0),
+                                            bytes_per_second: x.bytes_per_second.unwrap_or(0),
+                                            eta_seconds: x.eta_seconds.unwrap_or(0),
+                                        }))
+                                    };
+                                }
+                                DownloadEvent::Complete(_) => {
+                                    yield pb::TransactionResponse {
+                                        value: Some(Value::DownloadComplete(DownloadComplete {
+                                            package_id: id.to_string(),
+                                        }))
+                                    };
                                 }
                             }
                         }
                         log::trace!("Ending download stream");
 
-                        let (_canceler, mut tx_stream) = transaction.process();
+                        let (trigger, mut tx_stream) = transaction.process();
+                        canceler.set(trigger);
                         let mut is_completed = false;
+                        let mut reboot_required = transaction
+                            .actions()
+                            .iter()
+                            .map(|x| (x.action.id.to_string(), false))
+                            .collect::<HashMap<_, _>>();
 
                         while let Some(event) = tx_stream.next().await {
                             use pahkat_client::transaction::TransactionEvent;
@@ -533,6 +1553,18 @@ impl pb::pahkat_server::Pahkat for Rpc {
                                         }))
                                     };
                                 }
+                                TransactionEvent::Error(id, pahkat_client::transaction::TransactionError::Install(
+                                    pahkat_client::transaction::install::InstallError::MaliciousArchive(reason),
+                                )) => {
+                                    yield pb::TransactionResponse {
+                                        value: Some(Value::MaliciousArchive(MaliciousArchive {
+                                            package_id: id.to_string(),
+                                            reason: reason.to_string(),
+                                        }))
+                                    };
+
+                                    return;
+                                }
                                 TransactionEvent::Error(id, err) => {
                                     yield pb::TransactionResponse {
                                         value: Some(Value::TransactionError(TransactionError {
@@ -543,14 +1575,26 @@ impl pb::pahkat_server::Pahkat for Rpc {
 
                                     return;
                                 }
+                                TransactionEvent::RebootRequired(id) => {
+                                    reboot_required.insert(id.to_string(), true);
+                                }
                                 TransactionEvent::Complete => {
+                                    let is_reboot_required = reboot_required.values().any(|x| *x);
+                                    if is_reboot_required {
+                                        daemon_requires_reboot.store(true, std::sync::atomic::Ordering::SeqCst);
+                                    }
+
                                     yield pb::TransactionResponse {
-                                        value: Some(Value::TransactionComplete(TransactionComplete {}))
+                                        value: Some(Value::TransactionComplete(TransactionComplete {
+                                            reboot_required: reboot_required.clone(),
+                                            is_reboot_required,
+                                        }))
                                     };
                                     is_completed = true;
                                 }
                             }
                         }
+                        canceler.clear();
                         log::trace!("Ending inner transaction stream");
 
                         if !is_completed {
@@ -569,6 +1613,14 @@ impl pb::pahkat_server::Pahkat for Rpc {
                     futures::pin_mut!(stream);
 
                     while let Some(value) = stream.next().await {
+                        {
+                            let mut in_flight = in_flight.lock().unwrap();
+                            if let Some(x) = in_flight.get_mut(&fingerprint) {
+                                x.history.push(value.clone());
+                                let _ = x.events.send(value.clone());
+                            }
+                        }
+
                         match tx.send(value).await {
                             Ok(_) => {}
                             Err(err) => {
@@ -577,185 +1629,56 @@ impl pb::pahkat_server::Pahkat for Rpc {
                         }
                     }
 
+                    // Dropping the sender ends the streams of any attached clients.
+                    in_flight.lock().unwrap().remove(&fingerprint);
                     log::trace!("Ending outer stream loop");
                 });
             }
 
             collection.await;
-            let _ = notifications.send(Notification::TransactionUnlocked);
-
-            if requires_reboot {
-                let _ = notifications.send(Notification::RebootRequired);
-            }
+            notifications.publish(StoreEvent::TransactionUnlocked);
 
             log::trace!("Ended entire listener loop");
         });
 
-        Ok(Response::new(
-            Box::pin(tokio_stream::wrappers::ReceiverStream::new(rx))
-                as Self::ProcessTransactionStream,
-        ))
-    }
-
-    async fn set_repo(
-        &self,
-        request: tonic::Request<pb::SetRepoRequest>,
-    ) -> Result<pb::SetRepoResponse> {
-        let request = request.into_inner();
-
-        log::debug!("Setting repo: {:?}", &request);
-
-        let url =
-            Url::parse(&request.url).map_err(|e| Status::failed_precondition(format!("{}", e)))?;
-        log::trace!("Url: {:?}", &url);
-        let url = pahkat_client::types::repo::RepoUrl::new(url).map_err(|e| {
-            log::debug!("Bad repo url: {:?}", e);
-            Status::failed_precondition(format!("{}", e))
-        })?;
-        log::trace!("Repo url: {:?}", &url);
-
-        let config = self.store.config();
-        {
-            let mut config = config.write().unwrap();
-            let repos = config.repos_mut();
-
-            let mut record = RepoRecord::default();
-
-            if let Some(other_record) = request.settings {
-                if other_record.channel != "" {
-                    record.channel = Some(other_record.channel);
-                }
-            }
-
-            repos
-                .insert(url, record)
-                .map_err(|e| Status::failed_precondition(format!("{}", e)))?;
-        }
-
-        let errors = match self.store.force_refresh_repos().await {
-            Ok(_) => HashMap::new(),
-            Err(e) => e.into_iter().collect(),
-        };
-
-        let _ = self.notifications.send(Notification::RepositoriesChanged);
-
-        let config = config.read().unwrap();
-        let repos = config.repos();
-
-        Ok(tonic::Response::new(pb::SetRepoResponse {
-            records: repos
-                .iter()
-                .map(|(k, v)| (k.to_string(), v.to_owned().into()))
-                .collect(),
-            errors: errors
-                .iter()
-                .map(|(k, v)| (k.to_string(), format!("{:?}", v)))
-                .collect(),
-        }))
-    }
-
-    async fn get_repo_records(
-        &self,
-        _request: tonic::Request<pb::GetRepoRecordsRequest>,
-    ) -> Result<pb::GetRepoRecordsResponse> {
-        let records = {
-            let config = self.store.config();
-            let config = config.read().unwrap();
-            config
-                .repos()
-                .iter()
-                .map(|(k, v)| (k.to_string(), v.to_owned().into()))
-                .collect()
-        };
-
-        let errors = self.store.errors();
-        let errors = errors.read().unwrap();
-
-        Ok(tonic::Response::new(pb::GetRepoRecordsResponse {
-            records,
-            errors: errors
-                .iter()
-                .map(|(k, v)| (k.to_string(), format!("{:?}", v)))
-                .collect(),
-        }))
-    }
-
-    async fn remove_repo(
-        &self,
-        request: tonic::Request<pb::RemoveRepoRequest>,
-    ) -> Result<pb::RemoveRepoResponse> {
-        let request = request.into_inner();
-
-        let url =
-            Url::parse(&request.url).map_err(|e| Status::failed_precondition(format!("{}", e)))?;
-        let url = pahkat_client::types::repo::RepoUrl::new(url)
-            .map_err(|e| Status::failed_precondition(format!("{}", e)))?;
-
-        let config = self.store.config();
-
-        let _is_success = {
-            let mut config = config.write().unwrap();
-            let repos = config.repos_mut();
-            repos
-                .remove(&url)
-                .map_err(|e| Status::failed_precondition(format!("{}", e)))?
-        };
-
-        let errors = match self.store.force_refresh_repos().await {
-            Ok(_) => HashMap::new(),
-            Err(e) => e.into_iter().collect(),
-        };
-
-        let _ = self.notifications.send(Notification::RepositoriesChanged);
-
-        let config = config.read().unwrap();
-        let repos = config.repos();
-
-        Ok(tonic::Response::new(pb::RemoveRepoResponse {
-            records: repos
-                .iter()
-                .map(|(k, v)| (k.to_string(), v.to_owned().into()))
-                .collect(),
-            errors: errors
-                .iter()
-                .map(|(k, v)| (k.to_string(), format!("{:?}", v)))
-                .collect(),
-        }))
-    }
-
-    async fn resolve_package_query(
-        &self,
-        request: Request<pb::JsonRequest>,
-    ) -> Result<pb::JsonResponse> {
-        log::debug!("Received resolve_package_query request: {:?}", &request);
-        let json = request.into_inner().json;
-        let query: pahkat_client::repo::PackageQuery = serde_json::from_str(&json)
-            .map_err(|e| Status::failed_precondition(format!("{}", e)))?;
-
-        let results = self
-            .store
-            .resolve_package_query(query, &[InstallTarget::System, InstallTarget::User]);
-        log::debug!("resolve_package_query results: {:?}", &results);
-        Ok(tonic::Response::new(pb::JsonResponse {
-            json: serde_json::to_string(&results).unwrap(),
-        }))
+        Box::pin(tokio_stream::wrappers::ReceiverStream::new(rx))
     }
 }
 
 use std::path::Path;
 
+#[cfg(feature = "prefix")]
+fn prefix_records(prefixes: &pahkat_client::config::Prefixes) -> Vec<pb::Prefix> {
+    prefixes
+        .iter()
+        .map(|(name, path)| pb::Prefix {
+            name: name.to_string(),
+            path: path.display().to_string(),
+        })
+        .collect()
+}
+
 #[inline(always)]
 #[cfg(feature = "prefix")]
 async fn store(config_path: Option<&Path>) -> anyhow::Result<Arc<dyn PackageStore>> {
     use anyhow::Context;
 
-    let config_path = config_path.ok_or_else(|| anyhow::anyhow!("No prefix path specified"))?;
+    // Without a path, serve the default registered prefix
+    let default_prefix = match config_path {
+        Some(_) => None,
+        None => pahkat_client::config::Prefixes::load_default()?
+            .resolve(None)
+            .map(Path::to_path_buf),
+    };
+    let config_path = config_path
+        .or(default_prefix.as_deref())
+        .ok_or_else(|| anyhow::anyhow!("No prefix path specified"))?;
     let store = pahkat_client::PrefixPackageStore::open(config_path)
         .await
         .with_context(|| format!("Failed to open prefix store at {config_path:?}",))?;
     let store = Arc::new(store);
 
-    if store.config().read().unwrap().repos().len() == 0 {
+    if store.config().read().unwrap().repos().is_empty() {
         log::warn!("There are no repositories in the given config.");
     }
 
@@ -774,7 +1697,7 @@ async fn store(config_path: Option<&Path>) -> anyhow::Result<Arc<dyn PackageStor
     let store = pahkat_client::MacOSPackageStore::new(config).await;
     let store = Arc::new(store);
 
-    if store.config().read().unwrap().repos().len() == 0 {
+    if store.config().read().unwrap().repos().is_empty() {
         log::warn!("There are no repositories in the given config.");
     }
 
@@ -792,29 +1715,43 @@ async fn store(config_path: Option<&Path>) -> anyhow::Result<Arc<dyn PackageStor
     let store = pahkat_client::WindowsPackageStore::new(config).await;
     let store = Arc::new(store);
 
-    if store.config().read().unwrap().repos().len() == 0 {
+    if store.config().read().unwrap().repos().is_empty() {
         log::warn!("There are no repositories in the given config.");
     }
 
     Ok(store)
 }
 
+fn socket_path(path: Option<&Path>, store: &dyn PackageStore) -> std::path::PathBuf {
+    let config = store.config();
+    let config = config.read().unwrap();
+    ipc::socket_path(
+        path,
+        config.settings().socket_path(),
+        ipc::Session::current(),
+    )
+}
+
 #[cfg(all(unix, not(feature = "launchd")))]
 #[inline(always)]
 fn endpoint(path: &Path) -> std::result::Result<UnixListener, anyhow::Error> {
-    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    let session = ipc::Session::current();
 
-    match std::fs::metadata(path) {
+    match std::fs::symlink_metadata(path) {
         Ok(v) => {
             log::warn!(
                 "Unexpected file found at Unix socket path: {}",
                 &path.display()
             );
-            if v.file_type().is_socket() {
+            // Only the default path is in a directory nobody else can write to. A
+            // configured path is never removed on the daemon's behalf.
+            if v.file_type().is_socket() && path == session.default_path() {
                 std::fs::remove_file(&path)?;
                 log::warn!("Deleted stale socket.");
             } else {
-                log::error!("File is not a Unix socket, refusing to clean up automatically.");
+                log::error!("Refusing to clean up the Unix socket path automatically.");
                 anyhow::bail!("Unexpected file at desired Unix socket path ({}) cannot be automatically cleaned up.", &path.display());
             }
         }
@@ -825,15 +1762,15 @@ fn endpoint(path: &Path) -> std::result::Result<UnixListener, anyhow::Error> {
         }
     };
 
-    use std::os::unix::fs::PermissionsExt;
+    let socket = tokio::net::UnixListener::bind(path)?;
 
-    let socket = tokio::net::UnixListener::bind(&path).unwrap();
-    let meta = std::fs::metadata(&path)?;
-    let mut permissions = meta.permissions();
-    permissions.set_mode(0o777);
-    std::fs::set_permissions(&path, permissions)?;
-
-    // log::trace!("UDS mode: {:o}", permissions.mode());
+    // Any user may talk to the system daemon, but a user session daemon only
+    // serves its owner.
+    let mode = match session {
+        ipc::Session::System => 0o666,
+        ipc::Session::User => 0o600,
+    };
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
 
     Ok(socket)
 }
@@ -841,165 +1778,35 @@ fn endpoint(path: &Path) -> std::result::Result<UnixListener, anyhow::Error> {
 #[cfg(all(unix, feature = "launchd"))]
 #[inline(always)]
 fn endpoint(path: &Path) -> std::result::Result<UnixListener, anyhow::Error> {
+    use anyhow::Context;
     use std::os::unix::io::FromRawFd;
 
     log::debug!("Creating launchd UNIX socket named 'pahkat'...");
     let fds = raunch::activate_socket("pahkat")?;
-    let path = std::env::var("PAHKATD_UDS_PATH").unwrap();
+    let uds_path = std::env::var("PAHKATD_UDS_PATH").context("PAHKATD_UDS_PATH is not set")?;
 
-    log::debug!("UDS path: {:?}", &path);
-    log::debug!("Linking private socket path to /tmp/pahkat.sock");
+    log::debug!("UDS path: {:?}", &uds_path);
+    log::debug!("Linking private socket path to {}", path.display());
 
-    let _ = std::fs::remove_file("/tmp/pahkat.sock").unwrap_or(());
-    std::fs::hard_link(path, "/tmp/pahkat.sock").unwrap();
+    // A link left behind by a previous run is only replaced at the default path.
+    if path == ipc::Session::current().default_path() {
+        match std::fs::remove_file(path) {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    std::fs::hard_link(&uds_path, path)
+        .with_context(|| format!("Could not link {} to {}", uds_path, path.display()))?;
 
     log::debug!("Success! Unsafely converting to a UnixListener from a raw FD");
     let std_listener = unsafe { std::os::unix::net::UnixListener::from_raw_fd(fds[0]) };
-    Ok(tokio::net::UnixListener::from_std(std_listener).unwrap())
-}
-
-fn create_background_update_service(
-    store: Arc<dyn PackageStore>,
-    current_transaction: Arc<tokio::sync::Mutex<()>>,
-    notifications: broadcast::Sender<Notification>,
-) {
-    const UPDATE_INTERVAL: Duration = Duration::from_secs(15 * 60); // 15 minutes
-
-    tokio::spawn(async move {
-        let notifications = notifications.clone();
-        // let current_transaction = Arc::clone(&current_transaction);
-        // let store = Arc::clone(&store);
-
-        use tokio::time;
-
-        let mut interval = time::interval(UPDATE_INTERVAL);
-
-        'main: loop {
-            let notifications = notifications.clone();
-            interval.tick().await;
-
-            time::sleep(Duration::from_secs(2)).await;
-            let _ = store.refresh_repos().await;
-
-            log::info!("Running self-update check…");
-            match server::selfupdate::self_update().await {
-                Ok(v) if v => {
-                    return;
-                }
-                Err(e) => {
-                    log::error!("Self-update check failed: {:?}", e);
-                }
-                _ => {}
-            }
-
-            log::info!("Running update check…");
-
-            // Currently installed packages:
-            log::debug!("Iterating through all known packages...");
-            let updates = {
-                let repos = store.repos();
-                let repos = repos.read().unwrap();
-
-                let mut updates = vec![];
-
-                for (url, _repo) in repos.iter() {
-                    log::debug!("## Repo: {:?}", &url);
-                    let statuses = store.all_statuses(url, pahkat_client::InstallTarget::System);
-
-                    for (key, value) in statuses.into_iter() {
-                        log::debug!(" - {:?}: {:?}", &key, &value);
-                        if let Ok(PackageStatus::RequiresUpdate) = value {
-                            updates.push((
-                                pahkat_client::types::PackageKey {
-                                    repository_url: url.clone(),
-                                    id: key,
-                                    query: Default::default(),
-                                },
-                                pahkat_client::InstallTarget::System,
-                            ));
-                        }
-                    }
-                }
-
-                updates
-            };
-
-            log::debug!("Proposed updates: {:?}", &updates);
-
-            if updates.is_empty() {
-                log::info!("No updates found.");
-                continue;
-            }
-
-            let actions = updates
-                .into_iter()
-                .map(|(package_key, target)| PackageAction::install(package_key, target))
-                .collect::<Vec<_>>();
-
-            log::debug!("Waiting for transaction lock…");
-            let _guard = current_transaction.lock().await;
-            log::debug!("Transaction lock attained.");
-            let _ = notifications.send(Notification::TransactionLocked);
-
-            let transaction = PackageTransaction::new(Arc::clone(&store) as _, actions).unwrap(); // .map_err(|e| Status::failed_precondition(format!("{}", e)))?;
-
-            for record in transaction.actions().iter() {
-                let action = &record.action;
-                // let tx = tx.clone();
-                // let id = action.id.clone();
-                let mut download = store.download(&action.id);
-
-                // TODO: handle cancel here
-
-                use pahkat_client::package_store::DownloadEvent;
-
-                while let Some(event) = download.next().await {
-                    match event {
-                        DownloadEvent::Error(e) => {
-                            log::error!("{:?}", &e);
-                            continue 'main;
-                        }
-                        event => {
-                            log::debug!("{:?}", &event);
-                        }
-                    };
-                    //     DownloadEvent::Progress((current, total)) => {
-                    //         yield pb::TransactionResponse {
-                    //             value: Some(Value::DownloadProgress(DownloadProgress {
-                    //                 package_id: id.to_string(),
-                    //                 current,
-                    //                 total,
-                    //             }))
-                    //         };
-                    //     }
-                    //     DownloadEvent::Complete(_) => {
-                    //         yield pb::TransactionResponse {
-                    //             value: Some(Value::DownloadComplete(DownloadComplete {
-                    //                 package_id: id.to_string(),
-                    //             }))
-                    //         };
-                    //     }
-                    // }
-                }
-            }
-
-            let (_canceler, stream) = transaction.process();
-
-            futures::pin_mut!(stream);
-
-            while let Some(message) = stream.next().await {
-                log::trace!("{:?}", message);
-            }
-
-            let _ = notifications.send(Notification::TransactionUnlocked);
-            log::debug!("Completed background transaction.");
-        }
-    });
+    Ok(tokio::net::UnixListener::from_std(std_listener)?)
 }
 
 #[cfg(unix)]
 pub async fn start(
-    path: &Path,
+    path: Option<&Path>,
     config_path: Option<&Path>,
     shutdown_rx: mpsc::UnboundedReceiver<()>,
 ) -> std::result::Result<(), anyhow::Error> {
@@ -1008,36 +1815,75 @@ pub async fn start(
         env!("CARGO_PKG_NAME"),
         env!("CARGO_PKG_VERSION")
     );
+    pahkat_client::defaults::set_client_version(env!("CARGO_PKG_VERSION"))?;
 
-    let endpoint = endpoint(path)?;
-    log::debug!("Endpoint created successfully `{:?}`.", path);
     let store = store(config_path).await?;
     log::debug!("Created store.");
+    register_observer(Arc::new(ExecObserver::new(store.config())));
+
+    let path = socket_path(path, &*store);
+    let endpoint = endpoint(&path)?;
+    log::debug!("Endpoint created successfully `{:?}`.", path);
 
     let current_transaction = Arc::new(tokio::sync::Mutex::new(()));
+    let canceler = cancel::Canceler::default();
+    let requires_reboot = Arc::new(AtomicBool::new(false));
+
+    let notifications = pahkat_client::events::global().clone();
 
-    // Notifications
-    let (notifications, _notif_rx) = broadcast::channel(5);
+    let settings_changed = Arc::new(tokio::sync::Notify::new());
 
     // Create the background updater
-    create_background_update_service(
-        Arc::clone(&store),
+    updater::spawn(
+        Arc::new(updater::SystemClock),
+        Arc::new(updater::StoreHost(Arc::clone(&store))),
         Arc::clone(&current_transaction),
+        canceler.clone(),
         notifications.clone(),
+        Arc::clone(&requires_reboot),
+        Arc::clone(&settings_changed),
     );
 
+    let scheduler = Arc::new(schedule::Scheduler::new(
+        Arc::clone(&store),
+        Arc::clone(&current_transaction),
+        notifications.clone(),
+        Arc::clone(&requires_reboot),
+    ));
+    schedule::spawn(Arc::clone(&scheduler));
+
     let rpc = Rpc {
         store: Arc::clone(&store),
         notifications: notifications.clone(),
         current_transaction: Arc::clone(&current_transaction),
-        requires_reboot: AtomicBool::new(false),
+        canceler: canceler.clone(),
+        in_flight: Default::default(),
+        requires_reboot: Arc::clone(&requires_reboot),
+        scheduler: Arc::clone(&scheduler),
+        settings_changed,
     };
 
+    #[cfg(feature = "gateway")]
+    {
+        let gateway_port = store.config().read().unwrap().settings().gateway_port();
+        if let Some(port) = gateway_port {
+            tokio::spawn(gateway::serve(port, rpc.clone()));
+        }
+    }
+
     tonic::transport::Server::builder()
         .add_service(pb::pahkat_server::PahkatServer::new(rpc))
+        .add_service(reflection_service()?)
         .serve_with_incoming_shutdown(
             tokio_stream::wrappers::UnixListenerStream::new(endpoint),
-            shutdown_handler(shutdown_rx, notifications, Arc::clone(&current_transaction))?,
+            shutdown_handler(
+                shutdown_rx,
+                notifications,
+                Arc::clone(&current_transaction),
+                canceler,
+                store.config(),
+                scheduler,
+            )?,
         )
         .await?;
 
@@ -1048,11 +1894,65 @@ pub async fn start(
     Ok(())
 }
 
+/// How often the service manager is told that stopping is still in progress.
+const STOP_PENDING_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Waits for the running transaction to finish, cancelling it once `grace_period`
+/// has passed so that it stops after its running action. The lock is only taken
+/// once that action is done, so no installer is left running when the daemon exits.
+async fn lock_for_shutdown<'a>(
+    current_transaction: &'a tokio::sync::Mutex<()>,
+    canceler: &cancel::Canceler,
+    grace_period: std::time::Duration,
+) -> tokio::sync::MutexGuard<'a, ()> {
+    log::info!("Attempting to attain transaction lock...");
+    let deadline = tokio::time::Instant::now() + grace_period;
+    let lock = current_transaction.lock();
+    futures::pin_mut!(lock);
+
+    let mut is_cancelled = false;
+    let mut checkpoint = 0u32;
+    let guard = loop {
+        let wait = if is_cancelled {
+            STOP_PENDING_INTERVAL
+        } else {
+            deadline
+                .saturating_duration_since(tokio::time::Instant::now())
+                .min(STOP_PENDING_INTERVAL)
+        };
+        if let Ok(v) = tokio::time::timeout(wait, &mut lock).await {
+            break v;
+        }
+
+        if !is_cancelled && tokio::time::Instant::now() >= deadline {
+            is_cancelled = true;
+            if canceler.cancel() {
+                log::warn!(
+                    "Transaction still running after {:?}; stopping it after the running action.",
+                    grace_period
+                );
+            }
+        }
+
+        checkpoint += 1;
+        log::info!("Waiting for the running transaction to finish...");
+        #[cfg(windows)]
+        server::windows::service::report_stop_pending(checkpoint, STOP_PENDING_INTERVAL * 2);
+        #[cfg(not(windows))]
+        let _ = checkpoint;
+    };
+    log::info!("Lock attained!");
+    guard
+}
+
 #[cfg(unix)]
 fn shutdown_handler(
     mut shutdown_rx: mpsc::UnboundedReceiver<()>,
-    broadcast_tx: broadcast::Sender<Notification>,
+    events: EventBus,
     current_transaction: Arc<tokio::sync::Mutex<()>>,
+    canceler: cancel::Canceler,
+    config: pahkat_client::package_store::SharedStoreConfig,
+    scheduler: Arc<schedule::Scheduler>,
 ) -> anyhow::Result<Pin<Box<dyn std::future::Future<Output = ()>>>, anyhow::Error> {
     let mut sigint_listener = signal(SignalKind::interrupt())?;
     let mut sigterm_listener = signal(SignalKind::terminate())?;
@@ -1080,18 +1980,19 @@ fn shutdown_handler(
             }
         };
 
-        log::info!("Attempting to attain transaction lock...");
-        let _guard = current_transaction.lock().await;
-        log::info!("Lock attained!");
+        scheduler.run_at_shutdown().await;
 
-        let _ = broadcast_tx.send(Notification::RpcStopping);
+        let grace_period = config.read().unwrap().settings().shutdown_grace_period();
+        let _guard = lock_for_shutdown(&current_transaction, &canceler, grace_period).await;
+
+        events.publish(StoreEvent::Stopping);
         ()
     }))
 }
 
 #[cfg(windows)]
 pub async fn start(
-    path: &Path,
+    path: Option<&Path>,
     config_path: Option<&Path>,
     shutdown_rx: mpsc::UnboundedReceiver<()>,
 ) -> std::result::Result<(), anyhow::Error> {
@@ -1100,7 +2001,13 @@ pub async fn start(
         env!("CARGO_PKG_NAME"),
         env!("CARGO_PKG_VERSION")
     );
+    pahkat_client::defaults::set_client_version(env!("CARGO_PKG_VERSION"))?;
+
+    let store = store(config_path).await?;
+    log::debug!("Created store.");
+    register_observer(Arc::new(ExecObserver::new(store.config())));
 
+    let path = socket_path(path, &*store);
     log::debug!("Creating security descriptor for world...");
     let mut descriptor = tokio_named_pipe::secattr::SecurityDescriptor::world()?;
     log::debug!("Creating endpoint config...");
@@ -1109,10 +2016,7 @@ pub async fn start(
     config.security_attributes =
         tokio_named_pipe::secattr::SecurityAttributes::new(&mut descriptor, false);
     log::debug!("Binding named pipe...");
-    let incoming = tokio_named_pipe::NamedPipeServerListener::bind(path.to_path_buf(), config)?;
-
-    let store = store(config_path).await?;
-    log::debug!("Created store.");
+    let incoming = tokio_named_pipe::NamedPipeServerListener::bind(path, config)?;
 
     let skip_admin = store
         .config()
@@ -1121,34 +2025,71 @@ pub async fn start(
         .settings()
         .skip_admin_verification();
     let current_transaction = Arc::new(tokio::sync::Mutex::new(()));
+    let canceler = cancel::Canceler::default();
+    let requires_reboot = Arc::new(AtomicBool::new(false));
+
+    let notifications = pahkat_client::events::global().clone();
 
-    // Notifications
-    let (notifications, _notif_rx) = broadcast::channel(5);
+    let settings_changed = Arc::new(tokio::sync::Notify::new());
 
     // Create the background updater
 
     use futures::pin_mut;
-    create_background_update_service(
-        Arc::clone(&store),
+    updater::spawn(
+        Arc::new(updater::SystemClock),
+        Arc::new(updater::StoreHost(Arc::clone(&store))),
         Arc::clone(&current_transaction),
+        canceler.clone(),
         notifications.clone(),
+        Arc::clone(&requires_reboot),
+        Arc::clone(&settings_changed),
     );
 
+    let scheduler = Arc::new(schedule::Scheduler::new(
+        Arc::clone(&store),
+        Arc::clone(&current_transaction),
+        notifications.clone(),
+        Arc::clone(&requires_reboot),
+    ));
+    schedule::spawn(Arc::clone(&scheduler));
+
     let rpc = Rpc {
         store: Arc::clone(&store),
         notifications: notifications.clone(),
         current_transaction: Arc::clone(&current_transaction),
-        requires_reboot: AtomicBool::new(false),
+        canceler: canceler.clone(),
+        in_flight: Default::default(),
+        requires_reboot: Arc::clone(&requires_reboot),
+        scheduler: Arc::clone(&scheduler),
+        settings_changed,
     };
 
+    #[cfg(feature = "gateway")]
+    {
+        let gateway_port = store.config().read().unwrap().settings().gateway_port();
+        if let Some(port) = gateway_port {
+            tokio::spawn(gateway::serve(port, rpc.clone()));
+        }
+    }
+
     let http = hyper::server::conn::Http::new().http2_only(true).clone();
-    let svc = pb::pahkat_server::PahkatServer::new(rpc);
+    let svc = tonic::transport::server::Routes::new(pb::pahkat_server::PahkatServer::new(rpc))
+        .add_service(reflection_service()?);
 
     let (tx, rx, inner_rx) = server::watch::channel().await;
 
     let shutdown_transaction = Arc::clone(&current_transaction);
+    let shutdown_config = store.config();
     let shutdown = async move {
-        shutdown_handler(shutdown_rx, notifications, shutdown_transaction).await;
+        shutdown_handler(
+            shutdown_rx,
+            notifications,
+            shutdown_transaction,
+            canceler,
+            shutdown_config,
+            scheduler,
+        )
+        .await;
         tx.drain().await;
     };
 
@@ -1174,6 +2115,9 @@ pub async fn start(
                 hyper::service::service_fn(move |mut req: hyper::Request<hyper::Body>| {
                     let mut svc = svc.clone();
 
+                    // The flag is only ever set here, never taken from the client
+                    req.headers_mut().remove(<hyper::Request<hyper::Body> as AdminCheck>::FLAG);
+
                     if !skip_admin {
                         match server::windows::is_connected_user_admin(handle) {
                             Ok(true) => {
@@ -1204,8 +2148,11 @@ pub async fn start(
 #[cfg(windows)]
 fn shutdown_handler(
     mut shutdown_rx: mpsc::UnboundedReceiver<()>,
-    broadcast_tx: broadcast::Sender<Notification>,
+    events: EventBus,
     current_transaction: Arc<tokio::sync::Mutex<()>>,
+    canceler: cancel::Canceler,
+    config: pahkat_client::package_store::SharedStoreConfig,
+    scheduler: Arc<schedule::Scheduler>,
 ) -> impl std::future::Future<Output = ()> {
     let ctrl_c = tokio::signal::ctrl_c();
 
@@ -1219,11 +2166,12 @@ fn shutdown_handler(
             }
         };
 
-        log::info!("Attempting to attain transaction lock...");
-        let _guard = current_transaction.lock().await;
-        log::info!("Lock attained!");
+        scheduler.run_at_shutdown().await;
 
-        let _ = broadcast_tx.send(Notification::RpcStopping);
+        let grace_period = config.read().unwrap().settings().shutdown_grace_period();
+        let _guard = lock_for_shutdown(&current_transaction, &canceler, grace_period).await;
+
+        events.publish(StoreEvent::Stopping);
         ()
     }
 }
@@ -1264,12 +2212,14 @@ fn shutdown_handler(
 trait AdminCheck {
     const FLAG: &'static str = "is-admin-path";
 
+    #[cfg(windows)]
     fn add_admin_flag(&mut self);
 
     fn has_admin_flag(&self) -> bool;
 }
 
 impl<T> AdminCheck for hyper::Request<T> {
+    #[cfg(windows)]
     fn add_admin_flag(&mut self) {
         self.headers_mut().insert(
             <Self as AdminCheck>::FLAG,
@@ -1283,6 +2233,7 @@ impl<T> AdminCheck for hyper::Request<T> {
 }
 
 impl<T> AdminCheck for Request<T> {
+    #[cfg(windows)]
     fn add_admin_flag(&mut self) {
         self.metadata_mut().insert(
             <Self as AdminCheck>::FLAG,
@@ -1290,7 +2241,21 @@ impl<T> AdminCheck for Request<T> {
         );
     }
 
+    #[cfg(not(unix))]
     fn has_admin_flag(&self) -> bool {
         self.metadata().contains_key(<Self as AdminCheck>::FLAG)
     }
+
+    /// Unix peers are administrators if they run as root or in the root group, or as
+    /// the user the daemon runs as. Requests without peer credentials, such as those
+    /// of the gateway, never are.
+    #[cfg(unix)]
+    fn has_admin_flag(&self) -> bool {
+        let euid = unsafe { libc::geteuid() };
+        self.extensions()
+            .get::<tonic::transport::server::UdsConnectInfo>()
+            .and_then(|x| x.peer_cred)
+            .map(|cred| cred.uid() == 0 || cred.gid() == 0 || cred.uid() == euid)
+            .unwrap_or(false)
+    }
 }
@@ -0,0 +1,240 @@
+//! Transactions deferred until the machine is idle, or until the service stops for
+//! shutdown or the user logs off.
+//!
+//! Scheduled transactions are kept in memory only, so they are lost if the service
+//! is killed rather than stopped.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::stream::StreamExt;
+use once_cell::sync::Lazy;
+use pahkat_client::{
+    events::{EventBus, StoreEvent},
+    package_store::DownloadEvent,
+    power::PowerState,
+    transaction::TransactionEvent,
+    PackageAction, PackageStore, PackageTransaction,
+};
+use tokio::sync::Notify;
+
+use crate::pb::transaction_request::Schedule;
+
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+const IDLE_THRESHOLD: Duration = Duration::from_secs(10 * 60);
+
+static LOGOFF: Lazy<Notify> = Lazy::new(Notify::new);
+
+/// Runs the transactions scheduled for shutdown, as the user logging off is the last
+/// chance to replace components they had in use.
+#[cfg(windows)]
+pub(crate) fn notify_logoff() {
+    LOGOFF.notify_one();
+}
+
+pub(crate) struct Scheduler {
+    store: Arc<dyn PackageStore>,
+    current_transaction: Arc<tokio::sync::Mutex<()>>,
+    notifications: EventBus,
+    requires_reboot: Arc<AtomicBool>,
+    on_idle: Mutex<Vec<Vec<PackageAction>>>,
+    at_shutdown: Mutex<Vec<Vec<PackageAction>>>,
+}
+
+impl Scheduler {
+    pub(crate) fn new(
+        store: Arc<dyn PackageStore>,
+        current_transaction: Arc<tokio::sync::Mutex<()>>,
+        notifications: EventBus,
+        requires_reboot: Arc<AtomicBool>,
+    ) -> Scheduler {
+        Scheduler {
+            store,
+            current_transaction,
+            notifications,
+            requires_reboot,
+            on_idle: Default::default(),
+            at_shutdown: Default::default(),
+        }
+    }
+
+    /// Queues `actions` to run later. Immediate transactions are not handled here.
+    pub(crate) fn schedule(&self, schedule: Schedule, actions: Vec<PackageAction>) {
+        log::info!("Scheduling transaction {:?}: {:?}", schedule, &actions);
+        match schedule {
+            Schedule::Immediate => {
+                log::error!("Immediate transactions cannot be scheduled");
+            }
+            Schedule::OnIdle => self.on_idle.lock().unwrap().push(actions),
+            Schedule::AtShutdown => self.at_shutdown.lock().unwrap().push(actions),
+        }
+    }
+
+    /// Runs every transaction scheduled for shutdown, along with those still waiting
+    /// for idle time. Must be called before the shutdown handler takes the
+    /// transaction lock.
+    pub(crate) async fn run_at_shutdown(&self) {
+        let mut scheduled = std::mem::take(&mut *self.on_idle.lock().unwrap());
+        scheduled.append(&mut self.at_shutdown.lock().unwrap());
+        if !scheduled.is_empty() {
+            log::info!(
+                "Running {} transactions scheduled for shutdown…",
+                scheduled.len()
+            );
+        }
+
+        for actions in scheduled {
+            self.run(actions).await;
+        }
+    }
+
+    async fn run_on_idle(&self) {
+        if self.on_idle.lock().unwrap().is_empty() || !self.is_idle() {
+            return;
+        }
+
+        let scheduled = std::mem::take(&mut *self.on_idle.lock().unwrap());
+        log::info!(
+            "Running {} transactions scheduled for idle time…",
+            scheduled.len()
+        );
+        for actions in scheduled {
+            self.run(actions).await;
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        let policy = {
+            let config = self.store.config();
+            let config = config.read().unwrap();
+            config.settings().power()
+        };
+        if let Some(reason) = policy.defer_reason(&PowerState::current()) {
+            log::debug!("Not idle: {}", reason);
+            return false;
+        }
+
+        // Without a way to tell, the user may well be busy; the transactions then
+        // wait for shutdown instead
+        user_idle_time().is_some_and(|x| x >= IDLE_THRESHOLD)
+    }
+
+    /// Runs `actions` unattended, the same way the background updater does.
+    async fn run(&self, actions: Vec<PackageAction>) {
+        log::debug!("Waiting for transaction lock…");
+        let _guard = self.current_transaction.lock().await;
+        log::debug!("Transaction lock attained.");
+        self.notifications.publish(StoreEvent::TransactionLocked);
+
+        self.process(actions).await;
+
+        self.notifications.publish(StoreEvent::TransactionUnlocked);
+        log::debug!("Completed scheduled transaction.");
+    }
+
+    async fn process(&self, actions: Vec<PackageAction>) {
+        let (priority, concurrency) = {
+            let config = self.store.config();
+            let config = config.read().unwrap();
+            let settings = config.settings();
+            (
+                settings.background_priority(),
+                settings.download_concurrency(),
+            )
+        };
+
+        // Packages may have changed since the transaction was scheduled, so it is
+        // resolved again
+        let transaction = match PackageTransaction::new(Arc::clone(&self.store), actions) {
+            Ok(v) => crate::observed(v.with_priority(priority)),
+            Err(e) => {
+                log::error!("Scheduled transaction failed: {}", e);
+                return;
+            }
+        };
+
+        let mut downloads = transaction.download(concurrency);
+        while let Some((key, event)) = downloads.next().await {
+            if let DownloadEvent::Error(e) = event {
+                log::error!("Scheduled download of {} failed: {:?}", key, e);
+                return;
+            }
+        }
+
+        let (_canceler, mut stream) = transaction.process();
+        let mut is_reboot_required = false;
+        while let Some(event) = stream.next().await {
+            match event {
+                TransactionEvent::Error(key, e) => {
+                    log::error!("Scheduled transaction failed for {}: {}", key, e);
+                }
+                TransactionEvent::RebootRequired(..) => is_reboot_required = true,
+                event => log::trace!("{:?}", event),
+            }
+        }
+
+        if is_reboot_required {
+            self.requires_reboot.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+pub(crate) fn spawn(scheduler: Arc<Scheduler>) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(IDLE_CHECK_INTERVAL) => {
+                    scheduler.run_on_idle().await;
+                }
+                _ = LOGOFF.notified() => {
+                    log::info!("User logged off.");
+                    scheduler.run_at_shutdown().await;
+                }
+            }
+        }
+    })
+}
+
+/// How long since the user last used the keyboard or mouse, if it can be found out
+/// from the service.
+#[cfg(target_os = "macos")]
+fn user_idle_time() -> Option<Duration> {
+    let output = std::process::Command::new("ioreg")
+        .args(&["-c", "IOHIDSystem", "-d", "4"])
+        .output()
+        .ok()
+        .filter(|x| x.status.success())?;
+    parse_hid_idle_time(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Services run in a session of their own on Windows, and there is no one session
+/// to ask on Linux, so idleness is not known there.
+#[cfg(not(target_os = "macos"))]
+fn user_idle_time() -> Option<Duration> {
+    None
+}
+
+/// Finds `"HIDIdleTime" = <nanoseconds>` in the output of `ioreg`.
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+fn parse_hid_idle_time(output: &str) -> Option<Duration> {
+    output.lines().find_map(|line| {
+        let value = line.split("\"HIDIdleTime\" = ").nth(1)?;
+        value.trim().parse().ok().map(Duration::from_nanos)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hid_idle_time() {
+        let output = "    | |   \"HIDIdleTime\" = 734250125\n    | |   \"HIDParameters\" = {}\n";
+        assert_eq!(
+            parse_hid_idle_time(output),
+            Some(Duration::from_nanos(734250125))
+        );
+        assert_eq!(parse_hid_idle_time("\"IOClass\" = \"IOHIDSystem\""), None);
+    }
+}
@@ -39,6 +39,7 @@ fn make_config() -> Config {
             REPO.to_owned(),
             RepoRecord {
                 channel: REPO_CHANNEL.as_ref().map(|x| x.to_string()),
+                ..Default::default()
             },
         )
         .unwrap();
@@ -82,7 +83,7 @@ pub(crate) fn requires_update(store: &dyn PackageStore) -> bool {
                 PackageStatus::RequiresUpdate => {
                     is_requiring_update = true;
                 }
-                PackageStatus::UpToDate => {}
+                PackageStatus::UpToDate | PackageStatus::Unpublished => {}
             },
             Err(err) => {
                 log::error!("{:?}", err);
@@ -142,6 +143,11 @@ pub async fn install(_store: &dyn PackageStore) -> Result<(), Box<dyn Error>> {
 }
 
 pub(crate) async fn self_update() -> Result<bool, Box<dyn Error>> {
+    // Prefixes are not installed by an installer, so there is nothing to update.
+    if cfg!(feature = "prefix") {
+        return Ok(false);
+    }
+
     log::debug!("Getting self-update store...");
     let store = package_store().await;
 
@@ -162,8 +168,8 @@ pub(crate) async fn self_update() -> Result<bool, Box<dyn Error>> {
 
             while let Some(result) = stream.next().await {
                 match result {
-                    DownloadEvent::Progress((current, total)) => {
-                        log::debug!("Downloaded: {}/{}", current, total)
+                    DownloadEvent::Progress(x) => {
+                        log::debug!("Downloaded: {}/{}", x.current, x.total)
                     }
                     DownloadEvent::Error(error) => {
                         log::error!("Error downloading update: {:?}", error);
@@ -1,5 +1,6 @@
 use anyhow::Result;
 use log::{info, warn};
+use once_cell::sync::OnceCell;
 use std::time::Duration;
 use std::{
     ffi::{OsStr, OsString},
@@ -12,8 +13,9 @@ use windows_service::{
     service::{
         ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode,
         ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+        SessionChangeReason,
     },
-    service_control_handler::{self, ServiceControlHandlerResult},
+    service_control_handler::{self, ServiceControlHandlerResult, ServiceStatusHandle},
     service_dispatcher,
     service_manager::{ServiceManager, ServiceManagerAccess},
 };
@@ -21,7 +23,30 @@ use windows_service::{
 pub const SERVICE_NAME: &str = "pahkat-server";
 const SERVICE_TYPE: ServiceType = ServiceType::OWN_PROCESS;
 const SERVICE_DISPLAY_NAME: &str = "Pahkat Service";
-const NAMED_PIPE: &str = "//./pipe/pahkat";
+
+static STATUS_HANDLE: OnceCell<ServiceStatusHandle> = OnceCell::new();
+
+/// Tells the service manager that the service is still stopping, so that it keeps
+/// waiting for the running transaction instead of giving up on the service.
+pub(crate) fn report_stop_pending(checkpoint: u32, wait_hint: Duration) {
+    let handle = match STATUS_HANDLE.get() {
+        Some(v) => v,
+        None => return,
+    };
+
+    let result = handle.set_service_status(ServiceStatus {
+        service_type: SERVICE_TYPE,
+        current_state: ServiceState::StopPending,
+        controls_accepted: ServiceControlAccept::empty(),
+        exit_code: ServiceExitCode::Win32(0),
+        checkpoint,
+        wait_hint,
+        process_id: None,
+    });
+    if let Err(e) = result {
+        warn!("Could not report stop pending: {}", e);
+    }
+}
 
 pub fn install_service(exe_path: &Path) -> Result<()> {
     let manager_access = ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE;
@@ -184,8 +209,17 @@ async fn service_runner() -> Result<()> {
         match control_event {
             ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
 
-            ServiceControl::Stop => {
-                shutdown_tx.send(()).unwrap();
+            // Preshutdown gives transactions scheduled for shutdown time to run. Stop
+            // may still follow it, once the service is already stopping.
+            ServiceControl::Stop | ServiceControl::Preshutdown => {
+                let _ = shutdown_tx.send(());
+                ServiceControlHandlerResult::NoError
+            }
+
+            ServiceControl::SessionChange(param) => {
+                if param.reason == SessionChangeReason::SessionLogoff {
+                    crate::schedule::notify_logoff();
+                }
                 ServiceControlHandlerResult::NoError
             }
 
@@ -194,12 +228,15 @@ async fn service_runner() -> Result<()> {
     };
 
     let status_handle = service_control_handler::register(SERVICE_NAME, event_handler)?;
+    let _ = STATUS_HANDLE.set(status_handle);
 
     // Report service as running
     status_handle.set_service_status(ServiceStatus {
         service_type: SERVICE_TYPE,
         current_state: ServiceState::Running,
-        controls_accepted: ServiceControlAccept::STOP,
+        controls_accepted: ServiceControlAccept::STOP
+            | ServiceControlAccept::PRESHUTDOWN
+            | ServiceControlAccept::SESSION_CHANGE,
         exit_code: ServiceExitCode::Win32(0),
         checkpoint: 0,
         wait_hint: Duration::default(),
@@ -207,7 +244,7 @@ async fn service_runner() -> Result<()> {
     })?;
 
     log::debug!("Service is now running.");
-    crate::start(Path::new(NAMED_PIPE), None, shutdown_rx).await?;
+    crate::start(None, None, shutdown_rx).await?;
     log::info!("Service is shutting down...");
 
     // Tell the system that service has stopped.
@@ -0,0 +1,1165 @@
+//! The background updater.
+//!
+//! The update loop only reaches the outside world through [`Clock`] and [`UpdateHost`], so
+//! tests can drive it with a simulated clock and a scripted store instead of waiting out the
+//! update interval.
+
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use futures::stream::StreamExt;
+use pahkat_client::{
+    config::UpdateHold,
+    desired::{DesiredState, Drift},
+    events::{EventBus, StoreEvent},
+    failures,
+    package_store::{DownloadEvent, InstallTarget, Stream},
+    power::{DeferReason, PowerState},
+    repo::resolve_release,
+    report::ComplianceReport,
+    throttle::ErrorThrottle,
+    transaction::TransactionEvent,
+    types::package::DeprecationSeverity,
+    AsyncPackageStore, PackageAction, PackageKey, PackageStatus, PackageStore, PackageTransaction,
+};
+use tokio::sync::Notify;
+use url::Url;
+
+use crate::cancel::Canceler;
+
+static HOST_ERRORS: once_cell::sync::Lazy<ErrorThrottle> =
+    once_cell::sync::Lazy::new(Default::default);
+
+#[tonic::async_trait]
+pub trait Clock: Send + Sync + 'static {
+    fn now(&self) -> Instant;
+    fn utc_now(&self) -> DateTime<Utc>;
+    async fn sleep_until(&self, deadline: Instant);
+
+    async fn sleep(&self, duration: Duration) {
+        self.sleep_until(self.now() + duration).await
+    }
+}
+
+pub struct SystemClock;
+
+#[tonic::async_trait]
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn utc_now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    async fn sleep_until(&self, deadline: Instant) {
+        tokio::time::sleep_until(deadline.into()).await
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UpdateSettings {
+    pub interval: Duration,
+    pub auto_update: bool,
+    pub report_url: Option<Url>,
+}
+
+/// Everything the updater needs from the package store.
+#[tonic::async_trait]
+pub trait UpdateHost: Send + Sync + 'static {
+    /// Read before every run, and again when a change via SetSetting wakes the updater.
+    fn settings(&self) -> UpdateSettings;
+    async fn refresh_repos(&self);
+    async fn submit_report(
+        &self,
+        url: &Url,
+        requires_reboot: bool,
+        last_update_run: Option<DateTime<Utc>>,
+    );
+    /// Returns `true` if an update was installed and the daemon is about to be restarted.
+    async fn self_update(&self) -> Result<bool, anyhow::Error>;
+    async fn find_updates(&self) -> Vec<PackageKey>;
+    /// Differences from the desired state set by administrators.
+    async fn drift(&self) -> Vec<Drift>;
+    /// Why installing should wait, such as the machine running low on battery.
+    fn defer_reason(&self) -> Option<DeferReason>;
+    /// Whether installing `key` should be attempted, given its earlier failures.
+    fn should_attempt(&self, key: &PackageKey, now: DateTime<Utc>) -> bool;
+    /// Whether the release that installing `key` would install is critically deprecated.
+    /// These are never installed without someone explicitly allowing it.
+    fn is_critically_deprecated(&self, key: &PackageKey) -> bool;
+    fn record_install_failure(&self, key: &PackageKey, error: &str, now: DateTime<Utc>);
+    fn record_install_success(&self, key: &PackageKey);
+    fn transaction(
+        &self,
+        actions: Vec<PackageAction>,
+    ) -> Result<Box<dyn UpdateTransaction>, anyhow::Error>;
+}
+
+#[tonic::async_trait]
+pub trait UpdateTransaction: Send + Sync {
+    /// Packages whose payloads have to be downloaded before processing.
+    fn downloads(&self) -> Vec<PackageKey>;
+    fn download(&self, key: &PackageKey) -> Stream<DownloadEvent>;
+    async fn download_complete(&self, key: &PackageKey, path: &Path);
+    fn process(&self) -> (stream_cancel::Trigger, Stream<TransactionEvent>);
+}
+
+pub struct StoreHost(pub Arc<dyn PackageStore>);
+
+#[tonic::async_trait]
+impl UpdateHost for StoreHost {
+    fn settings(&self) -> UpdateSettings {
+        let config = self.0.config();
+        let config = config.read().unwrap();
+        let settings = config.settings();
+        UpdateSettings {
+            interval: settings.update_interval(),
+            auto_update: settings.auto_update(),
+            report_url: settings.report_url().cloned(),
+        }
+    }
+
+    async fn refresh_repos(&self) {
+        let _ = self.0.refresh_repos().await;
+    }
+
+    async fn submit_report(
+        &self,
+        url: &Url,
+        requires_reboot: bool,
+        last_update_run: Option<DateTime<Utc>>,
+    ) {
+        log::info!("Submitting compliance report to {}…", url);
+        let report = ComplianceReport::generate(&*self.0, InstallTarget::System)
+            .with_daemon_state(requires_reboot, last_update_run);
+        let proxy = {
+            let config = self.0.config();
+            let config = config.read().unwrap();
+            config.settings().proxy().cloned()
+        };
+        match report.submit(url, proxy.as_ref()).await {
+            Ok(_) => HOST_ERRORS.success("compliance report"),
+            Err(e) => HOST_ERRORS.error("compliance report", format!("{:?}", e)),
+        }
+    }
+
+    async fn self_update(&self) -> Result<bool, anyhow::Error> {
+        crate::server::selfupdate::self_update()
+            .await
+            .map_err(|e| anyhow::anyhow!("{}", e))
+    }
+
+    async fn find_updates(&self) -> Vec<PackageKey> {
+        log::debug!("Iterating through all known packages...");
+        let urls = self
+            .0
+            .repos()
+            .read()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let mut updates = vec![];
+
+        for url in urls.iter() {
+            log::debug!("## Repo: {:?}", &url);
+            let statuses = match self.0.all_statuses_async(url, InstallTarget::System).await {
+                Ok(v) => v,
+                Err(e) => {
+                    log::error!("Could not check {} for updates: {}", &url, e);
+                    continue;
+                }
+            };
+
+            for (key, value) in statuses.into_iter() {
+                log::debug!(" - {:?}: {:?}", &key, &value);
+                if let Ok(PackageStatus::RequiresUpdate) = value {
+                    let key = PackageKey {
+                        repository_url: url.clone(),
+                        id: key,
+                        query: Default::default(),
+                    };
+                    match self.update_hold(&key) {
+                        Some(hold) => log::debug!("Not updating {}: {:?}", &key, hold),
+                        None => updates.push(key),
+                    }
+                }
+            }
+        }
+
+        updates
+    }
+
+    async fn drift(&self) -> Vec<Drift> {
+        let desired = match DesiredState::load_system() {
+            Ok(Some(v)) if !v.is_empty() => {
+                HOST_ERRORS.success("desired state");
+                v
+            }
+            Ok(_) => {
+                HOST_ERRORS.success("desired state");
+                return vec![];
+            }
+            Err(e) => {
+                HOST_ERRORS.error("desired state", format!("{:?}", e));
+                return vec![];
+            }
+        };
+
+        match self
+            .0
+            .blocking(move |store| desired.drift(store, InstallTarget::System))
+            .await
+        {
+            Ok(drift) => drift,
+            Err(e) => {
+                log::error!("Could not compare the desired state: {}", e);
+                vec![]
+            }
+        }
+    }
+
+    fn defer_reason(&self) -> Option<DeferReason> {
+        let policy = {
+            let config = self.0.config();
+            let config = config.read().unwrap();
+            config.settings().power()
+        };
+        policy.defer_reason(&PowerState::current())
+    }
+
+    fn should_attempt(&self, key: &PackageKey, now: DateTime<Utc>) -> bool {
+        let version = match self.version(key) {
+            Some(v) => v,
+            None => return true,
+        };
+        let config = self.0.config();
+        let config = config.read().unwrap();
+        let failures = failures::load(&failures::path(&config));
+        config
+            .settings()
+            .install_failures()
+            .should_attempt(&failures, key, &version, now)
+    }
+
+    fn is_critically_deprecated(&self, key: &PackageKey) -> bool {
+        let repos = self.0.repos();
+        let repos = repos.read().unwrap();
+        resolve_release(key, &repos)
+            .and_then(|(release, _)| release.deprecation)
+            .map(|x| x.severity == DeprecationSeverity::Critical)
+            .unwrap_or(false)
+    }
+
+    fn record_install_failure(&self, key: &PackageKey, error: &str, now: DateTime<Utc>) {
+        let version = self.version(key).unwrap_or_default();
+        let config = self.0.config();
+        let config = config.read().unwrap();
+        let failure = failures::record_failure(&failures::path(&config), key, &version, error, now);
+        match config.settings().install_failures().retry_at(&failure) {
+            Some(at) => log::warn!(
+                "Installing {} {} failed {} times; retrying after {}",
+                key,
+                version,
+                failure.count,
+                at
+            ),
+            None => log::warn!(
+                "Installing {} {} failed {} times; not retrying until a newer release",
+                key,
+                version,
+                failure.count
+            ),
+        }
+    }
+
+    fn record_install_success(&self, key: &PackageKey) {
+        let config = self.0.config();
+        let config = config.read().unwrap();
+        failures::record_success(&failures::path(&config), key);
+    }
+
+    fn transaction(
+        &self,
+        actions: Vec<PackageAction>,
+    ) -> Result<Box<dyn UpdateTransaction>, anyhow::Error> {
+        let priority = {
+            let config = self.0.config();
+            let config = config.read().unwrap();
+            config.settings().background_priority()
+        };
+        let transaction =
+            PackageTransaction::new(Arc::clone(&self.0), actions)?.with_priority(priority);
+        Ok(Box::new(StoreTransaction {
+            store: Arc::clone(&self.0),
+            transaction: crate::observed(transaction),
+        }))
+    }
+}
+
+impl StoreHost {
+    /// The release that installing `key` would install.
+    fn version(&self, key: &PackageKey) -> Option<String> {
+        let repos = self.0.repos();
+        let repos = repos.read().unwrap();
+        resolve_release(key, &repos).map(|(release, _)| release.version.to_string())
+    }
+
+    /// Whether the user held back updating `key` to the release it would update to.
+    fn update_hold(&self, key: &PackageKey) -> Option<UpdateHold> {
+        let version = self.version(key)?;
+        let config = self.0.config();
+        let config = config.read().unwrap();
+        config
+            .repos()
+            .get(&key.repository_url)?
+            .update_hold(&key.id, &version)
+    }
+}
+
+struct StoreTransaction {
+    store: Arc<dyn PackageStore>,
+    transaction: PackageTransaction,
+}
+
+#[tonic::async_trait]
+impl UpdateTransaction for StoreTransaction {
+    fn downloads(&self) -> Vec<PackageKey> {
+        self.transaction
+            .actions()
+            .iter()
+            .filter(|record| record.action.is_install())
+            .map(|record| record.action.id.clone())
+            .collect()
+    }
+
+    fn download(&self, key: &PackageKey) -> Stream<DownloadEvent> {
+        self.store.download(key)
+    }
+
+    async fn download_complete(&self, key: &PackageKey, path: &Path) {
+        self.transaction.download_complete(key, path).await
+    }
+
+    fn process(&self) -> (stream_cancel::Trigger, Stream<TransactionEvent>) {
+        self.transaction.process()
+    }
+}
+
+pub(crate) fn spawn(
+    clock: Arc<dyn Clock>,
+    host: Arc<dyn UpdateHost>,
+    current_transaction: Arc<tokio::sync::Mutex<()>>,
+    canceler: Canceler,
+    notifications: EventBus,
+    requires_reboot: Arc<AtomicBool>,
+    settings_changed: Arc<Notify>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(run(
+        clock,
+        host,
+        current_transaction,
+        canceler,
+        notifications,
+        requires_reboot,
+        settings_changed,
+    ))
+}
+
+/// Runs update checks until a self-update requests a restart.
+async fn run(
+    clock: Arc<dyn Clock>,
+    host: Arc<dyn UpdateHost>,
+    current_transaction: Arc<tokio::sync::Mutex<()>>,
+    canceler: Canceler,
+    notifications: EventBus,
+    requires_reboot: Arc<AtomicBool>,
+    settings_changed: Arc<Notify>,
+) {
+    let mut next_check = clock.now();
+    let mut last_check = None;
+    let mut last_update_run = None;
+    let mut last_deferral = None;
+    let errors = ErrorThrottle::default();
+
+    'main: loop {
+        loop {
+            tokio::select! {
+                _ = clock.sleep_until(next_check) => break,
+                _ = settings_changed.notified() => {
+                    // A changed interval counts from the start of the last run
+                    if let Some(last_check) = last_check {
+                        next_check = last_check + host.settings().interval;
+                    }
+                }
+            }
+        }
+
+        let settings = host.settings();
+        last_check = Some(clock.now());
+        next_check = clock.now() + settings.interval;
+
+        clock.sleep(Duration::from_secs(2)).await;
+        host.refresh_repos().await;
+
+        if let Some(report_url) = settings.report_url.as_ref() {
+            host.submit_report(
+                report_url,
+                requires_reboot.load(Ordering::SeqCst),
+                last_update_run,
+            )
+            .await;
+        }
+
+        log::info!("Running self-update check…");
+        match host.self_update().await {
+            Ok(v) if v => {
+                return;
+            }
+            Err(e) => {
+                errors.error("self-update check", format!("{:?}", e));
+            }
+            _ => errors.success("self-update check"),
+        }
+
+        // The desired state is enforced even when automatic updates are disabled
+        let drift = host.drift().await;
+        let drifted = drift.iter().map(|x| x.key().clone()).collect::<Vec<_>>();
+        if !drift.is_empty() {
+            log::info!("Packages differ from the desired state: {:?}", &drift);
+            notifications.publish(StoreEvent::StateDrift(drifted.clone()));
+        }
+
+        let updates = if settings.auto_update {
+            log::info!("Running update check…");
+            let updates = host.find_updates().await;
+            log::debug!("Proposed updates: {:?}", &updates);
+            updates
+        } else {
+            log::info!("Automatic updates are disabled; skipping update check.");
+            vec![]
+        };
+
+        if updates.is_empty() && drift.is_empty() {
+            if settings.auto_update {
+                log::info!("No updates found.");
+                last_update_run = Some(clock.utc_now());
+            }
+            continue;
+        }
+
+        let mut actions = drift
+            .iter()
+            .map(|x| x.action(InstallTarget::System))
+            .collect::<Vec<_>>();
+        for key in updates {
+            let is_drifted = drifted
+                .iter()
+                .any(|x| x.repository_url == key.repository_url && x.id == key.id);
+            if !is_drifted {
+                actions.push(PackageAction::install(key, InstallTarget::System));
+            }
+        }
+
+        // Releases that keep failing are retried with backoff rather than on every run
+        let now = clock.utc_now();
+        actions.retain(|action| {
+            if !action.is_install() {
+                return true;
+            }
+            if host.is_critically_deprecated(&action.id) {
+                log::warn!(
+                    "Skipping {}: the release is critically deprecated",
+                    &action.id
+                );
+                return false;
+            }
+            let is_attempted = host.should_attempt(&action.id, now);
+            if !is_attempted {
+                log::info!("Skipping {} after earlier install failures", &action.id);
+            }
+            is_attempted
+        });
+        if actions.is_empty() {
+            continue;
+        }
+        let installs = actions
+            .iter()
+            .filter(|x| x.is_install())
+            .map(|x| x.id.clone())
+            .collect::<Vec<_>>();
+
+        // Found updates are looked up again on the next run
+        let deferral = host.defer_reason();
+        if deferral != last_deferral {
+            if let Some(reason) = deferral {
+                log::info!("Deferring updates: {}", reason);
+                notifications.publish(StoreEvent::UpdatesDeferred(reason));
+            }
+            last_deferral = deferral;
+        }
+        if deferral.is_some() {
+            continue;
+        }
+
+        log::debug!("Waiting for transaction lock…");
+        let _guard = current_transaction.lock().await;
+        log::debug!("Transaction lock attained.");
+        notifications.publish(StoreEvent::TransactionLocked);
+
+        let transaction = match host.transaction(actions) {
+            Ok(v) => {
+                errors.success("update transaction");
+                v
+            }
+            Err(e) => {
+                errors.error("update transaction", format!("{:?}", e));
+                notifications.publish(StoreEvent::TransactionUnlocked);
+                continue;
+            }
+        };
+
+        for key in transaction.downloads() {
+            let mut download = transaction.download(&key);
+
+            // TODO: handle cancel here
+
+            while let Some(event) = download.next().await {
+                match event {
+                    DownloadEvent::Error(e) => {
+                        errors.error(&key.to_string(), format!("{:?}", &e));
+                        notifications.publish(StoreEvent::TransactionUnlocked);
+                        continue 'main;
+                    }
+                    DownloadEvent::Complete(path) => {
+                        errors.success(&key.to_string());
+                        transaction.download_complete(&key, &path).await;
+                    }
+                    event => {
+                        log::debug!("{:?}", &event);
+                    }
+                };
+            }
+        }
+
+        let (trigger, stream) = transaction.process();
+        canceler.set(trigger);
+
+        futures::pin_mut!(stream);
+
+        let mut is_success = true;
+        let mut is_complete = false;
+        let mut is_reboot_required = false;
+        let mut failed = vec![];
+        while let Some(message) = stream.next().await {
+            log::trace!("{:?}", message);
+            match message {
+                TransactionEvent::Error(key, e) => {
+                    is_success = false;
+                    failed.push((key, e.to_string()));
+                }
+                TransactionEvent::RebootRequired(..) => is_reboot_required = true,
+                TransactionEvent::Complete => is_complete = true,
+                _ => {}
+            }
+        }
+        canceler.clear();
+
+        // The stream also ends early when the daemon is stopping
+        if !is_complete {
+            log::info!("Background transaction was cancelled.");
+            is_success = false;
+        }
+
+        let now = clock.utc_now();
+        for key in installs.iter() {
+            let error = failed
+                .iter()
+                .find(|(x, _)| x.repository_url == key.repository_url && x.id == key.id);
+            match error {
+                Some((_, e)) => host.record_install_failure(key, e, now),
+                // Actions after a cancellation did not run
+                None if is_complete => host.record_install_success(key),
+                None => {}
+            }
+        }
+
+        if is_success && settings.auto_update {
+            last_update_run = Some(clock.utc_now());
+        }
+
+        if is_success && !drifted.is_empty() {
+            notifications.publish(StoreEvent::StateCorrected(drifted));
+        }
+
+        // A failed transaction may still have completed actions that need a restart
+        if is_reboot_required {
+            requires_reboot.store(true, Ordering::SeqCst);
+        }
+
+        notifications.publish(StoreEvent::TransactionUnlocked);
+        log::debug!("Completed background transaction.");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::Mutex;
+
+    use pahkat_client::transaction::TransactionError;
+    use pahkat_client::types::repo::RepoUrl;
+    use tokio::sync::{broadcast, Notify};
+
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+    /// A clock that only moves when the test advances it to the updater's next wake-up.
+    struct SimulatedClock {
+        start: Instant,
+        state: Mutex<ClockState>,
+        parked: Notify,
+        advanced: Notify,
+    }
+
+    #[derive(Default)]
+    struct ClockState {
+        elapsed: Duration,
+        deadline: Option<Duration>,
+    }
+
+    impl SimulatedClock {
+        fn new() -> Arc<SimulatedClock> {
+            Arc::new(SimulatedClock {
+                start: Instant::now(),
+                state: Default::default(),
+                parked: Notify::new(),
+                advanced: Notify::new(),
+            })
+        }
+
+        /// Waits for the updater to go to sleep, then wakes it. Returns the simulated time.
+        async fn advance(&self) -> Duration {
+            loop {
+                let parked = self.parked.notified();
+                let woken = {
+                    let mut state = self.state.lock().unwrap();
+                    state.deadline.take().map(|deadline| {
+                        state.elapsed = deadline;
+                        deadline
+                    })
+                };
+                if let Some(elapsed) = woken {
+                    self.advanced.notify_waiters();
+                    return elapsed;
+                }
+                parked.await;
+            }
+        }
+
+        /// Waits for the updater to go to sleep until `deadline`, without waking it.
+        async fn parked_until(&self, deadline: Duration) {
+            loop {
+                let parked = self.parked.notified();
+                if self.state.lock().unwrap().deadline == Some(deadline) {
+                    return;
+                }
+                parked.await;
+            }
+        }
+    }
+
+    #[tonic::async_trait]
+    impl Clock for SimulatedClock {
+        fn now(&self) -> Instant {
+            self.start + self.state.lock().unwrap().elapsed
+        }
+
+        fn utc_now(&self) -> DateTime<Utc> {
+            let elapsed = self.state.lock().unwrap().elapsed;
+            DateTime::<Utc>::from(std::time::UNIX_EPOCH)
+                + chrono::Duration::from_std(elapsed).unwrap()
+        }
+
+        async fn sleep_until(&self, deadline: Instant) {
+            let deadline = deadline.saturating_duration_since(self.start);
+            loop {
+                let advanced = self.advanced.notified();
+                {
+                    let mut state = self.state.lock().unwrap();
+                    if state.elapsed >= deadline {
+                        return;
+                    }
+                    state.deadline = Some(deadline);
+                }
+                self.parked.notify_waiters();
+                advanced.await;
+            }
+        }
+    }
+
+    #[derive(Default)]
+    struct FakeHost {
+        updates: Mutex<Vec<PackageKey>>,
+        interval: Mutex<Option<Duration>>,
+        drift: Mutex<Vec<Drift>>,
+        auto_update_disabled: AtomicBool,
+        deferral: Mutex<Option<DeferReason>>,
+        self_update: AtomicBool,
+        broken_downloads: Arc<AtomicBool>,
+        failing_installs: AtomicBool,
+        /// Packages that are not attempted after earlier failures.
+        backing_off: Mutex<Vec<String>>,
+        /// Packages whose latest release is critically deprecated.
+        deprecated: Mutex<Vec<String>>,
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl FakeHost {
+        fn record(&self, call: &str) {
+            self.calls.lock().unwrap().push(call.to_string());
+        }
+
+        fn calls(&self) -> Vec<String> {
+            self.calls.lock().unwrap().clone()
+        }
+    }
+
+    #[tonic::async_trait]
+    impl UpdateHost for FakeHost {
+        fn settings(&self) -> UpdateSettings {
+            UpdateSettings {
+                interval: self.interval.lock().unwrap().unwrap_or(INTERVAL),
+                auto_update: !self.auto_update_disabled.load(Ordering::SeqCst),
+                report_url: None,
+            }
+        }
+
+        async fn refresh_repos(&self) {
+            self.record("refresh");
+        }
+
+        async fn submit_report(&self, _: &Url, _: bool, _: Option<DateTime<Utc>>) {
+            self.record("report");
+        }
+
+        async fn self_update(&self) -> Result<bool, anyhow::Error> {
+            self.record("self-update");
+            Ok(self.self_update.load(Ordering::SeqCst))
+        }
+
+        async fn find_updates(&self) -> Vec<PackageKey> {
+            self.record("find-updates");
+            std::mem::take(&mut *self.updates.lock().unwrap())
+        }
+
+        async fn drift(&self) -> Vec<Drift> {
+            std::mem::take(&mut *self.drift.lock().unwrap())
+        }
+
+        fn defer_reason(&self) -> Option<DeferReason> {
+            *self.deferral.lock().unwrap()
+        }
+
+        fn should_attempt(&self, key: &PackageKey, _now: DateTime<Utc>) -> bool {
+            !self.backing_off.lock().unwrap().contains(&key.id)
+        }
+
+        fn is_critically_deprecated(&self, key: &PackageKey) -> bool {
+            self.deprecated.lock().unwrap().contains(&key.id)
+        }
+
+        fn record_install_failure(&self, key: &PackageKey, _error: &str, _now: DateTime<Utc>) {
+            self.record(&format!("failed {}", key.id));
+        }
+
+        fn record_install_success(&self, key: &PackageKey) {
+            self.record(&format!("succeeded {}", key.id));
+        }
+
+        fn transaction(
+            &self,
+            actions: Vec<PackageAction>,
+        ) -> Result<Box<dyn UpdateTransaction>, anyhow::Error> {
+            self.record("transaction");
+            Ok(Box::new(FakeTransaction {
+                actions,
+                broken_downloads: self.broken_downloads.load(Ordering::SeqCst),
+                failing_installs: self.failing_installs.load(Ordering::SeqCst),
+                calls: Arc::clone(&self.calls),
+            }))
+        }
+    }
+
+    struct FakeTransaction {
+        actions: Vec<PackageAction>,
+        broken_downloads: bool,
+        failing_installs: bool,
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[tonic::async_trait]
+    impl UpdateTransaction for FakeTransaction {
+        fn downloads(&self) -> Vec<PackageKey> {
+            self.actions
+                .iter()
+                .filter(|x| x.is_install())
+                .map(|x| x.id.clone())
+                .collect()
+        }
+
+        fn download(&self, key: &PackageKey) -> Stream<DownloadEvent> {
+            let event = if self.broken_downloads {
+                DownloadEvent::Error(pahkat_client::DownloadError::InvalidUrl)
+            } else {
+                DownloadEvent::Complete(PathBuf::from(&key.id))
+            };
+            Box::pin(futures::stream::iter(vec![event]))
+        }
+
+        async fn download_complete(&self, key: &PackageKey, _path: &Path) {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("downloaded {}", key.id));
+        }
+
+        fn process(&self) -> (stream_cancel::Trigger, Stream<TransactionEvent>) {
+            let (trigger, _) = stream_cancel::Tripwire::new();
+            let mut events = vec![];
+            for action in self.actions.iter() {
+                let verb = if action.is_install() {
+                    "installed"
+                } else {
+                    "uninstalled"
+                };
+                self.calls
+                    .lock()
+                    .unwrap()
+                    .push(format!("{} {}", verb, action.id.id));
+                events.push(TransactionEvent::Installing(action.id.clone()));
+                if self.failing_installs && action.is_install() {
+                    events.push(TransactionEvent::Error(
+                        action.id.clone(),
+                        TransactionError::ValidationFailed,
+                    ));
+                }
+            }
+            events.push(TransactionEvent::Complete);
+            (trigger, Box::pin(futures::stream::iter(events)))
+        }
+    }
+
+    struct Harness {
+        clock: Arc<SimulatedClock>,
+        host: Arc<FakeHost>,
+        lock: Arc<tokio::sync::Mutex<()>>,
+        notifications: broadcast::Receiver<StoreEvent>,
+        settings_changed: Arc<Notify>,
+        task: tokio::task::JoinHandle<()>,
+    }
+
+    fn harness(host: FakeHost) -> Harness {
+        let clock = SimulatedClock::new();
+        let host = Arc::new(host);
+        let lock = Arc::new(tokio::sync::Mutex::new(()));
+        let bus = EventBus::new(5);
+        let notifications = bus.subscribe();
+        let settings_changed = Arc::new(Notify::new());
+        let task = spawn(
+            Arc::clone(&clock) as _,
+            Arc::clone(&host) as _,
+            Arc::clone(&lock),
+            Canceler::default(),
+            bus,
+            Arc::new(AtomicBool::new(false)),
+            Arc::clone(&settings_changed),
+        );
+        Harness {
+            clock,
+            host,
+            lock,
+            notifications,
+            settings_changed,
+            task,
+        }
+    }
+
+    fn key(id: &str) -> PackageKey {
+        let url = RepoUrl::new("https://pahkat.example/repo/".parse().unwrap()).unwrap();
+        PackageKey::new_unchecked(url, id.to_string(), None)
+    }
+
+    #[tokio::test]
+    async fn installs_found_updates() {
+        let host = FakeHost::default();
+        *host.updates.lock().unwrap() = vec![key("speller")];
+        let mut h = harness(host);
+
+        assert_eq!(h.clock.advance().await, Duration::from_secs(2));
+        assert!(matches!(
+            h.notifications.recv().await,
+            Ok(StoreEvent::TransactionLocked)
+        ));
+        assert!(matches!(
+            h.notifications.recv().await,
+            Ok(StoreEvent::TransactionUnlocked)
+        ));
+
+        // The next run is one interval after the first
+        assert_eq!(h.clock.advance().await, INTERVAL);
+        assert_eq!(
+            h.host.calls(),
+            [
+                "refresh",
+                "self-update",
+                "find-updates",
+                "transaction",
+                "downloaded speller",
+                "installed speller",
+                "succeeded speller",
+            ]
+        );
+
+        assert_eq!(h.clock.advance().await, INTERVAL + Duration::from_secs(2));
+        assert_eq!(h.clock.advance().await, INTERVAL * 2);
+        assert_eq!(h.host.calls().last().unwrap(), "find-updates");
+        h.task.abort();
+    }
+
+    #[tokio::test]
+    async fn changed_interval_applies_to_the_next_run() {
+        let h = harness(FakeHost::default());
+        let interval = Duration::from_secs(5 * 60);
+
+        assert_eq!(h.clock.advance().await, Duration::from_secs(2));
+        h.clock.parked_until(INTERVAL).await;
+
+        *h.host.interval.lock().unwrap() = Some(interval);
+        h.settings_changed.notify_one();
+        h.clock.parked_until(interval).await;
+        assert_eq!(h.clock.advance().await, interval);
+        h.task.abort();
+    }
+
+    #[tokio::test]
+    async fn failed_download_skips_install() {
+        let host = FakeHost::default();
+        *host.updates.lock().unwrap() = vec![key("speller")];
+        host.broken_downloads.store(true, Ordering::SeqCst);
+        let mut h = harness(host);
+
+        h.clock.advance().await;
+        assert!(matches!(
+            h.notifications.recv().await,
+            Ok(StoreEvent::TransactionLocked)
+        ));
+        assert!(matches!(
+            h.notifications.recv().await,
+            Ok(StoreEvent::TransactionUnlocked)
+        ));
+
+        assert_eq!(h.clock.advance().await, INTERVAL);
+        assert_eq!(
+            h.host.calls(),
+            ["refresh", "self-update", "find-updates", "transaction"]
+        );
+        h.task.abort();
+    }
+
+    #[tokio::test]
+    async fn records_install_failures_and_skips_backed_off_packages() {
+        let host = FakeHost::default();
+        *host.updates.lock().unwrap() = vec![key("speller"), key("keyboard")];
+        host.failing_installs.store(true, Ordering::SeqCst);
+        let mut h = harness(host);
+
+        h.clock.advance().await;
+        assert!(matches!(
+            h.notifications.recv().await,
+            Ok(StoreEvent::TransactionLocked)
+        ));
+        assert!(matches!(
+            h.notifications.recv().await,
+            Ok(StoreEvent::TransactionUnlocked)
+        ));
+        assert!(h.host.calls().contains(&"failed speller".to_string()));
+        assert!(h.host.calls().contains(&"failed keyboard".to_string()));
+
+        // Only the package that is not backing off is installed on the next run
+        h.host.failing_installs.store(false, Ordering::SeqCst);
+        *h.host.backing_off.lock().unwrap() = vec!["speller".into()];
+        *h.host.updates.lock().unwrap() = vec![key("speller"), key("keyboard")];
+        h.clock.advance().await;
+        h.clock.advance().await;
+        assert!(matches!(
+            h.notifications.recv().await,
+            Ok(StoreEvent::TransactionLocked)
+        ));
+        assert!(matches!(
+            h.notifications.recv().await,
+            Ok(StoreEvent::TransactionUnlocked)
+        ));
+
+        let calls = h.host.calls();
+        assert!(calls.contains(&"succeeded keyboard".to_string()));
+        assert!(!calls.contains(&"succeeded speller".to_string()));
+        assert_eq!(
+            calls.iter().filter(|x| *x == "installed speller").count(),
+            1
+        );
+        h.task.abort();
+    }
+
+    #[tokio::test]
+    async fn skips_critically_deprecated_releases() {
+        let host = FakeHost::default();
+        *host.updates.lock().unwrap() = vec![key("speller"), key("keyboard")];
+        *host.deprecated.lock().unwrap() = vec!["speller".into()];
+        let mut h = harness(host);
+
+        h.clock.advance().await;
+        assert!(matches!(
+            h.notifications.recv().await,
+            Ok(StoreEvent::TransactionLocked)
+        ));
+        assert!(matches!(
+            h.notifications.recv().await,
+            Ok(StoreEvent::TransactionUnlocked)
+        ));
+
+        let calls = h.host.calls();
+        assert!(calls.contains(&"installed keyboard".to_string()));
+        assert!(!calls.iter().any(|x| x.ends_with("speller")));
+        h.task.abort();
+    }
+
+    #[tokio::test]
+    async fn waits_for_transaction_lock() {
+        let host = FakeHost::default();
+        *host.updates.lock().unwrap() = vec![key("speller")];
+        let mut h = harness(host);
+
+        let guard = h.lock.lock().await;
+        h.clock.advance().await;
+        while !h.host.calls().contains(&"find-updates".to_string()) {
+            tokio::task::yield_now().await;
+        }
+        tokio::task::yield_now().await;
+
+        assert!(h.notifications.try_recv().is_err());
+        assert!(!h.host.calls().contains(&"transaction".to_string()));
+
+        drop(guard);
+        assert!(matches!(
+            h.notifications.recv().await,
+            Ok(StoreEvent::TransactionLocked)
+        ));
+        assert!(matches!(
+            h.notifications.recv().await,
+            Ok(StoreEvent::TransactionUnlocked)
+        ));
+        assert!(h.host.calls().contains(&"installed speller".to_string()));
+        h.task.abort();
+    }
+
+    #[tokio::test]
+    async fn self_update_stops_updater_for_restart() {
+        let host = FakeHost::default();
+        *host.updates.lock().unwrap() = vec![key("speller")];
+        host.self_update.store(true, Ordering::SeqCst);
+        let h = harness(host);
+
+        h.clock.advance().await;
+        h.task.await.unwrap();
+
+        assert_eq!(h.host.calls(), ["refresh", "self-update"]);
+    }
+
+    #[tokio::test]
+    async fn corrects_drift_with_auto_update_disabled() {
+        let host = FakeHost::default();
+        host.auto_update_disabled.store(true, Ordering::SeqCst);
+        *host.updates.lock().unwrap() = vec![key("speller")];
+        *host.drift.lock().unwrap() = vec![
+            Drift::Missing(key("keyboard")),
+            Drift::Present(key("legacy")),
+        ];
+        let mut h = harness(host);
+
+        h.clock.advance().await;
+        match h.notifications.recv().await {
+            Ok(StoreEvent::StateDrift(keys)) => {
+                assert_eq!(keys, [key("keyboard"), key("legacy")])
+            }
+            other => panic!("unexpected notification: {:?}", other),
+        }
+        assert!(matches!(
+            h.notifications.recv().await,
+            Ok(StoreEvent::TransactionLocked)
+        ));
+        assert!(matches!(
+            h.notifications.recv().await,
+            Ok(StoreEvent::StateCorrected(_))
+        ));
+        assert!(matches!(
+            h.notifications.recv().await,
+            Ok(StoreEvent::TransactionUnlocked)
+        ));
+
+        h.clock.advance().await;
+        assert_eq!(
+            h.host.calls(),
+            [
+                "refresh",
+                "self-update",
+                "transaction",
+                "downloaded keyboard",
+                "installed keyboard",
+                "uninstalled legacy",
+                "succeeded keyboard",
+            ]
+        );
+        h.task.abort();
+    }
+
+    #[tokio::test]
+    async fn defers_updates_while_on_battery() {
+        let host = FakeHost::default();
+        *host.updates.lock().unwrap() = vec![key("speller")];
+        *host.deferral.lock().unwrap() = Some(DeferReason::OnBattery { percent: 20 });
+        let mut h = harness(host);
+
+        h.clock.advance().await;
+        assert!(matches!(
+            h.notifications.recv().await,
+            Ok(StoreEvent::UpdatesDeferred(DeferReason::OnBattery {
+                percent: 20
+            }))
+        ));
+
+        // Only a change of reason is announced again
+        *h.host.updates.lock().unwrap() = vec![key("speller")];
+        assert_eq!(h.clock.advance().await, INTERVAL);
+        h.clock.advance().await;
+        assert_eq!(h.clock.advance().await, INTERVAL * 2);
+        assert!(h.notifications.try_recv().is_err());
+        assert!(!h.host.calls().contains(&"transaction".to_string()));
+
+        *h.host.deferral.lock().unwrap() = None;
+        *h.host.updates.lock().unwrap() = vec![key("speller")];
+        h.clock.advance().await;
+        assert!(matches!(
+            h.notifications.recv().await,
+            Ok(StoreEvent::TransactionLocked)
+        ));
+        h.task.abort();
+    }
+}
@@ -0,0 +1,83 @@
+//! The daemon, running on its own thread against a prefix store.
+
+use std::convert::TryFrom;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use pahkat_client::PrefixPackageStore;
+use pahkat_rpc::pb;
+use tokio::net::UnixStream;
+use tokio::sync::mpsc;
+use tonic::transport::{Channel, Endpoint, Uri};
+use tower::service_fn;
+
+pub type Client = pb::pahkat_client::PahkatClient<Channel>;
+
+pub struct Daemon {
+    socket: PathBuf,
+    shutdown: mpsc::UnboundedSender<()>,
+    thread: Option<std::thread::JoinHandle<()>>,
+    _prefix: tempfile::TempDir,
+}
+
+impl Daemon {
+    pub async fn start() -> Daemon {
+        let prefix = tempfile::tempdir().unwrap();
+        let socket = prefix.path().join("pahkat.sock");
+
+        // The background updater would otherwise race the tests for the
+        // transaction lock.
+        {
+            let store = PrefixPackageStore::create(prefix.path()).await.unwrap();
+            let config = pahkat_client::PackageStore::config(&store);
+            let mut config = config.write().unwrap();
+            config.settings_mut().set_auto_update(false).unwrap();
+        }
+
+        let (shutdown, shutdown_rx) = mpsc::unbounded_channel();
+        let thread = {
+            let socket = socket.clone();
+            let prefix = prefix.path().to_path_buf();
+            std::thread::spawn(move || {
+                tokio::runtime::Runtime::new()
+                    .unwrap()
+                    .block_on(pahkat_rpc::start(Some(&socket), Some(&prefix), shutdown_rx))
+                    .unwrap();
+            })
+        };
+
+        for _ in 0..200 {
+            if socket.exists() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert!(socket.exists(), "daemon did not create its socket");
+
+        Daemon {
+            socket,
+            shutdown,
+            thread: Some(thread),
+            _prefix: prefix,
+        }
+    }
+
+    pub async fn client(&self) -> Client {
+        let path = self.socket.clone();
+        let channel = Endpoint::try_from("file://tmp/pahkat.sock")
+            .unwrap()
+            .connect_with_connector(service_fn(move |_: Uri| UnixStream::connect(path.clone())))
+            .await
+            .unwrap();
+        Client::new(channel)
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.shutdown.send(());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
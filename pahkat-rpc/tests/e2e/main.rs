@@ -0,0 +1,248 @@
+//! End-to-end tests: a daemon with a prefix store, a repository served from
+//! fixture descriptors and the gRPC client, asserting the events of each flow.
+//!
+//! Run with `cargo test -p pahkat-rpc --features e2e --test e2e`.
+
+mod daemon;
+mod repo;
+
+use std::time::Duration;
+
+use futures::stream::{self, StreamExt};
+use pahkat_rpc::pb;
+use pb::transaction_response::Value;
+
+use daemon::{Client, Daemon};
+use repo::Repo;
+
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+const INSTALL: u32 = 0;
+const UNINSTALL: u32 = 1;
+
+const NOT_INSTALLED: i32 = 0;
+const UP_TO_DATE: i32 = 1;
+const REQUIRES_UPDATE: i32 = 2;
+
+/// A repository with `example` 1.0.0, and a daemon following it.
+async fn setup() -> (Repo, Daemon) {
+    let mut repo = Repo::serve().await;
+    repo.publish("example", "1.0.0");
+
+    pahkat_client::add_root_certificate(&repo.certificate).unwrap();
+    let daemon = Daemon::start().await;
+    (repo, daemon)
+}
+
+async fn add_repo(client: &mut Client, repo: &Repo) {
+    client
+        .set_repo(pb::SetRepoRequest {
+            url: repo.url.to_string(),
+            settings: Some(pb::RepoRecord::default()),
+        })
+        .await
+        .unwrap();
+    refresh(client).await;
+}
+
+async fn refresh(client: &mut Client) {
+    client.refresh(pb::RefreshRequest {}).await.unwrap();
+}
+
+async fn status(client: &mut Client, repo: &Repo, id: &str) -> i32 {
+    client
+        .status(pb::StatusRequest {
+            package_id: repo.key(id),
+            target: 0,
+        })
+        .await
+        .unwrap()
+        .into_inner()
+        .value
+}
+
+fn transaction(actions: Vec<pb::PackageAction>) -> pb::TransactionRequest {
+    pb::TransactionRequest {
+        value: Some(pb::transaction_request::Value::Transaction(
+            pb::transaction_request::Transaction {
+                actions,
+                allow_deprecated: false,
+                schedule: pb::transaction_request::Schedule::Immediate as i32,
+                atomic: false,
+            },
+        )),
+    }
+}
+
+fn action(repo: &Repo, id: &str, action: u32) -> pb::PackageAction {
+    pb::PackageAction {
+        id: repo.key(id),
+        action,
+        target: 0,
+        options: Default::default(),
+    }
+}
+
+/// Sends the requests and collects every event until the daemon ends the stream.
+async fn process(client: &mut Client, requests: Vec<pb::TransactionRequest>) -> Vec<Value> {
+    let response = client
+        .process_transaction(stream::iter(requests))
+        .await
+        .unwrap();
+
+    tokio::time::timeout(
+        TIMEOUT,
+        response
+            .into_inner()
+            .filter_map(|x| async move { x.unwrap().value })
+            .collect::<Vec<_>>(),
+    )
+    .await
+    .expect("transaction stream did not end")
+}
+
+/// Names the events, leaving out progress, which depends on timing.
+fn kinds(events: &[Value]) -> Vec<&'static str> {
+    events
+        .iter()
+        .filter_map(|x| match x {
+            Value::TransactionStarted(_) => Some("started"),
+            Value::TransactionComplete(_) => Some("complete"),
+            Value::TransactionError(_) => Some("error"),
+            Value::DownloadComplete(_) => Some("downloaded"),
+            Value::InstallStarted(_) => Some("installing"),
+            Value::UninstallStarted(_) => Some("uninstalling"),
+            Value::VerificationFailed(_) => Some("verification failed"),
+            Value::ClientUpdateRequired(_) => Some("client update required"),
+            Value::MaliciousArchive(_) => Some("malicious archive"),
+            Value::ReleaseDeprecated(_) => Some("deprecated"),
+            Value::TransactionScheduled(_) => Some("scheduled"),
+            Value::TransactionProgress(_)
+            | Value::TransactionQueued(_)
+            | Value::DownloadProgress(_) => None,
+        })
+        .collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn install() {
+    let (repo, daemon) = setup().await;
+    let mut client = daemon.client().await;
+    add_repo(&mut client, &repo).await;
+
+    assert_eq!(status(&mut client, &repo, "example").await, NOT_INSTALLED);
+
+    let events = process(
+        &mut client,
+        vec![transaction(vec![action(&repo, "example", INSTALL)])],
+    )
+    .await;
+    assert_eq!(
+        kinds(&events),
+        ["started", "downloaded", "installing", "complete"]
+    );
+
+    match &events[0] {
+        Value::TransactionStarted(x) => {
+            assert_eq!(x.actions.len(), 1);
+            assert_eq!(x.actions[0].version, "1.0.0");
+        }
+        _ => unreachable!(),
+    }
+
+    assert_eq!(status(&mut client, &repo, "example").await, UP_TO_DATE);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn uninstall() {
+    let (repo, daemon) = setup().await;
+    let mut client = daemon.client().await;
+    add_repo(&mut client, &repo).await;
+
+    process(
+        &mut client,
+        vec![transaction(vec![action(&repo, "example", INSTALL)])],
+    )
+    .await;
+
+    let events = process(
+        &mut client,
+        vec![transaction(vec![action(&repo, "example", UNINSTALL)])],
+    )
+    .await;
+    assert_eq!(kinds(&events), ["started", "uninstalling", "complete"]);
+    assert_eq!(status(&mut client, &repo, "example").await, NOT_INSTALLED);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn update() {
+    let (mut repo, daemon) = setup().await;
+    let mut client = daemon.client().await;
+    add_repo(&mut client, &repo).await;
+
+    process(
+        &mut client,
+        vec![transaction(vec![action(&repo, "example", INSTALL)])],
+    )
+    .await;
+
+    repo.publish("example", "1.1.0");
+    refresh(&mut client).await;
+    assert_eq!(status(&mut client, &repo, "example").await, REQUIRES_UPDATE);
+
+    let events = process(
+        &mut client,
+        vec![transaction(vec![action(&repo, "example", INSTALL)])],
+    )
+    .await;
+    assert_eq!(
+        kinds(&events),
+        ["started", "downloaded", "installing", "complete"]
+    );
+    assert_eq!(status(&mut client, &repo, "example").await, UP_TO_DATE);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn cancel() {
+    let (repo, daemon) = setup().await;
+    let mut client = daemon.client().await;
+    add_repo(&mut client, &repo).await;
+
+    let cancel = pb::TransactionRequest {
+        value: Some(pb::transaction_request::Value::Cancel(
+            pb::transaction_request::Cancel {},
+        )),
+    };
+    // Depending on timing the transaction is cut short or has already finished;
+    // either way the stream has to end.
+    process(
+        &mut client,
+        vec![transaction(vec![action(&repo, "example", INSTALL)]), cancel],
+    )
+    .await;
+
+    // A cancelled transaction must not keep holding the transaction lock.
+    if status(&mut client, &repo, "example").await == UP_TO_DATE {
+        let events = process(
+            &mut client,
+            vec![transaction(vec![action(&repo, "example", UNINSTALL)])],
+        )
+        .await;
+        assert_eq!(kinds(&events).last(), Some(&"complete"));
+    }
+    assert_eq!(status(&mut client, &repo, "example").await, NOT_INSTALLED);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn missing_package() {
+    let (repo, daemon) = setup().await;
+    let mut client = daemon.client().await;
+    add_repo(&mut client, &repo).await;
+
+    let events = process(
+        &mut client,
+        vec![transaction(vec![action(&repo, "missing", INSTALL)])],
+    )
+    .await;
+    assert_eq!(kinds(&events), ["error"]);
+}
@@ -0,0 +1,172 @@
+//! A repository served over HTTPS from a temporary directory.
+
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use hyper::service::service_fn;
+use hyper::{Body, Request, Response, StatusCode};
+use pahkat_client::types::package::{Descriptor, DescriptorData, Package, Release, Version};
+use pahkat_client::types::payload::{tarball, Payload, Target};
+use pahkat_client::types::repo::{Agent, Index, RepoUrl, RepositoryData};
+
+pub struct Repo {
+    pub url: RepoUrl,
+    /// DER-encoded self-signed certificate the repository is served with
+    pub certificate: Vec<u8>,
+    dir: tempfile::TempDir,
+    packages: BTreeMap<String, Package>,
+    server: tokio::task::JoinHandle<()>,
+}
+
+impl Repo {
+    pub async fn serve() -> Repo {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("packages")).unwrap();
+        std::fs::create_dir_all(dir.path().join("payloads")).unwrap();
+
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let certificate = cert.serialize_der().unwrap();
+        let config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![rustls::Certificate(certificate.clone())],
+                rustls::PrivateKey(cert.serialize_private_key_der()),
+            )
+            .unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let url: RepoUrl = format!("https://localhost:{}/repo/", port).parse().unwrap();
+
+        let root: Arc<Path> = Arc::from(dir.path());
+        let server = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let acceptor = acceptor.clone();
+                let root = Arc::clone(&root);
+                tokio::spawn(async move {
+                    let stream = match acceptor.accept(stream).await {
+                        Ok(v) => v,
+                        Err(_) => return,
+                    };
+                    let service = service_fn(move |req| serve_file(Arc::clone(&root), req));
+                    let _ = hyper::server::conn::Http::new()
+                        .serve_connection(stream, service)
+                        .await;
+                });
+            }
+        });
+
+        let repo = Repo {
+            url,
+            certificate,
+            dir,
+            packages: BTreeMap::new(),
+            server,
+        };
+        repo.write_index();
+        repo
+    }
+
+    /// The key of a package in this repository, as the daemon expects it.
+    pub fn key(&self, id: &str) -> String {
+        format!("{}packages/{}", self.url, id)
+    }
+
+    /// Makes `version` the only release of the package, with a tarball payload
+    /// holding a single file.
+    pub fn publish(&mut self, id: &str, version: &str) {
+        let file_name = format!("{}-{}.txz", id, version);
+        let content = format!("{} {}\n", id, version);
+        let payload = archive(&format!("bin/{}", id), content.as_bytes());
+        std::fs::write(self.dir.path().join("payloads").join(&file_name), &payload).unwrap();
+
+        let target = Target::builder()
+            .platform(std::env::consts::OS.into())
+            .payload(Payload::TarballPackage(
+                tarball::Package::builder()
+                    .url(self.url.join(&format!("payloads/{}", file_name)).unwrap())
+                    .size(payload.len() as u64)
+                    .installed_size(content.len() as u64)
+                    .build(),
+            ))
+            .build();
+
+        let descriptor = Descriptor::builder()
+            .package(DescriptorData::builder().id(id.into()).build())
+            .release(vec![Release::builder()
+                .version(Version::new(version).unwrap())
+                .target(vec![target])
+                .build()])
+            .build();
+
+        self.packages
+            .insert(id.to_string(), Package::Concrete(descriptor));
+        self.write_index();
+    }
+
+    fn write_index(&self) {
+        let index = Index::builder()
+            .repository(RepositoryData::builder().url(self.url.clone()).build())
+            .agent(
+                Agent::builder()
+                    .name("pahkat".into())
+                    .version("e2e".into())
+                    .build(),
+            )
+            .build();
+        std::fs::write(
+            self.dir.path().join("index.toml"),
+            toml::to_string(&index).unwrap(),
+        )
+        .unwrap();
+
+        let packages = self.packages.values().cloned().collect::<Vec<_>>();
+        let packages = pahkat_repomgr::repo::indexing::encode_index(&packages).unwrap();
+        std::fs::write(self.dir.path().join("packages").join("index.bin"), packages).unwrap();
+    }
+}
+
+impl Drop for Repo {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+fn archive(path: &str, content: &[u8]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(vec![]);
+    let mut header = tar::Header::new_gnu();
+    header.set_size(content.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append_data(&mut header, path, content).unwrap();
+    let tar = builder.into_inner().unwrap();
+
+    let mut encoder = xz2::write::XzEncoder::new(vec![], 6);
+    encoder.write_all(&tar).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// Serves files below `root`, with the `/repo/` prefix of the repository URL
+/// and any empty path segments removed.
+async fn serve_file(root: Arc<Path>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let path = req
+        .uri()
+        .path()
+        .split('/')
+        .filter(|x| !x.is_empty() && *x != "..")
+        .skip(1)
+        .fold(PathBuf::from(&*root), |path, x| path.join(x));
+
+    Ok(match tokio::fs::read(&path).await {
+        Ok(data) => Response::new(Body::from(data)),
+        Err(_) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())
+            .unwrap(),
+    })
+}
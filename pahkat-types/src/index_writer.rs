@@ -0,0 +1,917 @@
+//! Encoding packages into `packages/index.bin`, shared by pahkat-repomgr, which
+//! writes the index, and clients that convert other repository layouts into one.
+
+use fbs::FlatBufferBuilder;
+
+#[derive(Debug, thiserror::Error)]
+pub enum EncodeError {
+    #[error("Unsupported package type: {0}")]
+    Unsupported(String),
+
+    #[error("{0}: {1}")]
+    InvalidInstallDir(String, #[source] crate::payload::tarball::InvalidInstallDir),
+}
+
+/// Validates and encodes packages into the contents of `packages/index.bin`.
+pub fn encode_index(packages: &[crate::package::Package]) -> Result<Vec<u8>, EncodeError> {
+    use crate::package::Package;
+
+    // Callers give packages in no particular order, so sort them by id to keep the
+    // generated index byte-for-byte reproducible.
+    let mut packages = packages.iter().collect::<Vec<_>>();
+    packages.sort_by(|a, b| a.id().cmp(b.id()));
+
+    let mut writer = IndexWriter::new();
+    for package in packages {
+        match package {
+            Package::Concrete(v) => writer.push(v)?,
+            _ => return Err(EncodeError::Unsupported(package.id().to_string())),
+        }
+    }
+    Ok(writer.finish())
+}
+
+fn validate_payloads(descriptor: &crate::package::Descriptor) -> Result<(), EncodeError> {
+    use crate::payload::Payload;
+
+    for release in descriptor.release.iter() {
+        for target in release.target.iter() {
+            if let Payload::TarballPackage(p) = &target.payload {
+                p.install_dir().map_err(|e| {
+                    let release = format!("{} {}", descriptor.package.id, release.version);
+                    EncodeError::InvalidInstallDir(release, e)
+                })?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn vectorize_strings<'a>(
+    keys: Vec<fbs::WIPOffset<&'a str>>,
+    builder: &mut FlatBufferBuilder<'a>,
+) -> fbs::WIPOffset<fbs::Vector<'a, fbs::ForwardsUOffset<&'a str>>> {
+    let len = keys.len();
+    builder.start_vector::<fbs::ForwardsUOffset<&'_ str>>(len);
+    for key in keys.into_iter().rev() {
+        builder.push(key);
+    }
+    builder.end_vector(len)
+}
+
+type StringKeys<'a> = std::collections::HashMap<String, fbs::WIPOffset<&'a str>>;
+
+/// Writes `value` once, however many descriptors refer to it. The keys are owned, as
+/// descriptors are dropped once they are written.
+fn shared_string<'a>(
+    keys: &mut StringKeys<'a>,
+    value: &str,
+    builder: &mut FlatBufferBuilder<'a>,
+) -> fbs::WIPOffset<&'a str> {
+    if let Some(v) = keys.get(value) {
+        return *v;
+    }
+    let v = builder.create_string(value);
+    keys.insert(value.to_string(), v);
+    v
+}
+
+fn vectorize_lang_map<'a>(
+    lang_map: &crate::LangTagMap<String>,
+    lang_keys: &mut StringKeys<'a>,
+    builder: &mut FlatBufferBuilder<'a>,
+) -> (
+    Option<fbs::WIPOffset<fbs::Vector<'a, fbs::ForwardsUOffset<&'a str>>>>,
+    Option<fbs::WIPOffset<fbs::Vector<'a, fbs::ForwardsUOffset<&'a str>>>>,
+) {
+    let (name_keys, name_values): (Vec<_>, Vec<_>) = lang_map
+        .iter()
+        .map(|(key, value)| {
+            let lang_key_ref = shared_string(lang_keys, key, builder);
+            let value_ref = builder.create_string(value);
+            (lang_key_ref, value_ref)
+        })
+        .unzip();
+
+    let (name_keys_ref, name_values_ref) = if name_keys.is_empty() {
+        (None, None)
+    } else {
+        let keys_ref = vectorize_strings(name_keys, builder);
+        let values_ref = vectorize_strings(name_values, builder);
+        (Some(keys_ref), Some(values_ref))
+    };
+
+    (name_keys_ref, name_values_ref)
+}
+
+fn create_actions<'a>(
+    actions: &[crate::payload::Action],
+    builder: &mut FlatBufferBuilder<'a>,
+) -> Option<fbs::WIPOffset<fbs::Vector<'a, fbs::ForwardsUOffset<&'a str>>>> {
+    if actions.is_empty() {
+        return None;
+    }
+
+    let actions = actions
+        .iter()
+        .map(|x| builder.create_string(&x.to_string()))
+        .collect::<Vec<_>>();
+    Some(vectorize_strings(actions, builder))
+}
+
+fn create_mirrors<'a>(
+    mirrors: &[url::Url],
+    builder: &mut FlatBufferBuilder<'a>,
+) -> Option<fbs::WIPOffset<fbs::Vector<'a, fbs::ForwardsUOffset<&'a str>>>> {
+    if mirrors.is_empty() {
+        return None;
+    }
+    let mirrors = mirrors
+        .iter()
+        .map(|x| builder.create_string(x.as_str()))
+        .collect::<Vec<_>>();
+    Some(vectorize_strings(mirrors, builder))
+}
+
+fn create_deltas<'a>(
+    deltas: &[crate::payload::delta::Delta],
+    builder: &mut FlatBufferBuilder<'a>,
+) -> Option<
+    fbs::WIPOffset<fbs::Vector<'a, fbs::ForwardsUOffset<crate::fbs::pahkat::Delta<&'a [u8]>>>>,
+> {
+    if deltas.is_empty() {
+        return None;
+    }
+
+    use crate::fbs::pahkat::DeltaFormat;
+    use crate::payload::delta;
+
+    let deltas = deltas
+        .iter()
+        .map(|x| {
+            let format = match x.format {
+                delta::DeltaFormat::Bsdiff => DeltaFormat::Bsdiff,
+            };
+            let args = crate::fbs::pahkat::DeltaArgs {
+                from_version: builder.create_string(&x.from_version),
+                url: builder.create_string(x.url.as_str()),
+                size: x.size,
+                format,
+                payload_sha256: x.payload_sha256.as_ref().map(|x| builder.create_string(x)),
+            };
+            crate::fbs::pahkat::Delta::create(builder, &args)
+        })
+        .collect::<Vec<_>>();
+
+    let len = deltas.len();
+    builder.start_vector::<fbs::ForwardsUOffset<crate::fbs::pahkat::Delta<&'_ [u8]>>>(len);
+    for delta in deltas.into_iter().rev() {
+        builder.push(delta);
+    }
+    Some(builder.end_vector(len))
+}
+
+fn create_options<'a>(
+    options: &[crate::payload::InstallOption],
+    builder: &mut FlatBufferBuilder<'a>,
+) -> Option<
+    fbs::WIPOffset<
+        fbs::Vector<'a, fbs::ForwardsUOffset<crate::fbs::pahkat::InstallOption<&'a [u8]>>>,
+    >,
+> {
+    if options.is_empty() {
+        return None;
+    }
+
+    let options = options
+        .iter()
+        .map(|x| {
+            let values = x
+                .values
+                .iter()
+                .map(|x| builder.create_string(x))
+                .collect::<Vec<_>>();
+            let args = crate::fbs::pahkat::InstallOptionArgs {
+                id: builder.create_string(&x.id),
+                values: if values.is_empty() {
+                    None
+                } else {
+                    Some(vectorize_strings(values, builder))
+                },
+                default: x.default.as_ref().map(|x| builder.create_string(x)),
+            };
+            crate::fbs::pahkat::InstallOption::create(builder, &args)
+        })
+        .collect::<Vec<_>>();
+
+    let len = options.len();
+    builder.start_vector::<fbs::ForwardsUOffset<crate::fbs::pahkat::InstallOption<&'_ [u8]>>>(len);
+    for option in options.into_iter().rev() {
+        builder.push(option);
+    }
+    Some(builder.end_vector(len))
+}
+
+fn create_payload_windows_exe<'a>(
+    payload: &crate::payload::windows::Executable,
+    builder: &mut FlatBufferBuilder<'a>,
+) -> fbs::WIPOffset<fbs::UnionWIPOffset> {
+    let url = builder.create_string(payload.url.as_str());
+    let product_code = builder.create_string(payload.product_code.as_str());
+
+    use crate::fbs::pahkat::WindowsExecutableKind;
+    use crate::payload::windows::InstallerKind;
+    let kind = match payload.kind.as_ref() {
+        Some(InstallerKind::Msi) => WindowsExecutableKind::Msi,
+        Some(InstallerKind::Nsis) => WindowsExecutableKind::Nsis,
+        Some(InstallerKind::InnoSetup) => WindowsExecutableKind::Inno,
+        Some(InstallerKind::InstallShield) => WindowsExecutableKind::InstallShield,
+        Some(InstallerKind::Other(_)) => WindowsExecutableKind::Other,
+        None => WindowsExecutableKind::NONE,
+    };
+    let kind_name = match payload.kind.as_ref() {
+        Some(InstallerKind::Other(name)) => Some(builder.create_string(name)),
+        _ => None,
+    };
+
+    let args = payload
+        .args
+        .as_ref()
+        .map(|x| builder.create_string(x.as_str()));
+    let uninstall_args = payload
+        .uninstall_args
+        .as_ref()
+        .map(|x| builder.create_string(x.as_str()));
+    let publisher = payload
+        .publisher
+        .as_ref()
+        .map(|x| builder.create_string(x.as_str()));
+
+    let (msi_properties_keys, msi_properties_values): (Vec<_>, Vec<_>) = payload
+        .msi_properties
+        .iter()
+        .map(|(key, value)| (builder.create_string(key), builder.create_string(value)))
+        .unzip();
+    let (msi_properties_keys, msi_properties_values) = if msi_properties_keys.is_empty() {
+        (None, None)
+    } else {
+        (
+            Some(vectorize_strings(msi_properties_keys, builder)),
+            Some(vectorize_strings(msi_properties_values, builder)),
+        )
+    };
+    let msi_transforms = if payload.msi_transforms.is_empty() {
+        None
+    } else {
+        let transforms = payload
+            .msi_transforms
+            .iter()
+            .map(|x| builder.create_string(x))
+            .collect::<Vec<_>>();
+        Some(vectorize_strings(transforms, builder))
+    };
+    let actions = create_actions(&payload.actions, builder);
+    let uninstall_registry_key = payload
+        .uninstall
+        .as_ref()
+        .and_then(|x| x.registry_key.as_ref())
+        .map(|x| builder.create_string(x));
+    let uninstall_quiet_command = payload
+        .uninstall
+        .as_ref()
+        .and_then(|x| x.quiet_command.as_ref())
+        .map(|x| builder.create_string(x));
+    let mirrors = create_mirrors(&payload.mirrors, builder);
+    let deltas = create_deltas(&payload.deltas, builder);
+    let options = create_options(&payload.options, builder);
+
+    use crate::fbs::pahkat::WindowsExecutableFlag;
+    use crate::payload::windows::RebootSpec;
+
+    let mut flags = 0u8;
+    if payload.requires_reboot.contains(&RebootSpec::Install) {
+        flags |= WindowsExecutableFlag::RequiresRebootOnInstall as u8;
+    }
+    if payload.requires_reboot.contains(&RebootSpec::Update) {
+        flags |= WindowsExecutableFlag::RequiresRebootOnUpdate as u8;
+    }
+    if payload.requires_reboot.contains(&RebootSpec::Install) {
+        flags |= WindowsExecutableFlag::RequiresRebootOnUninstall as u8;
+    }
+
+    let args = crate::fbs::pahkat::WindowsExecutableArgs {
+        url,
+        product_code,
+        flags,
+        kind,
+        size: payload.size,
+        installed_size: payload.installed_size,
+        args,
+        uninstall_args,
+        publisher,
+        msi_properties_keys,
+        msi_properties_values,
+        msi_transforms,
+        actions,
+        kind_name,
+        uninstall_registry_key,
+        uninstall_quiet_command,
+        mirrors,
+        deltas,
+        options,
+    };
+
+    crate::fbs::pahkat::WindowsExecutable::create(builder, &args).as_union_value()
+}
+
+fn create_payload_macos_pkg<'a>(
+    payload: &crate::payload::macos::Package,
+    builder: &mut FlatBufferBuilder<'a>,
+) -> fbs::WIPOffset<fbs::UnionWIPOffset> {
+    let url = builder.create_string(payload.url.as_str());
+    let pkg_id = builder.create_string(payload.pkg_id.as_str());
+    let team_id = payload
+        .team_id
+        .as_ref()
+        .map(|x| builder.create_string(x.as_str()));
+    let choice_changes = payload
+        .choice_changes
+        .as_ref()
+        .map(|x| builder.create_string(x.as_str()));
+    let user_choice_changes = payload
+        .user_choice_changes
+        .as_ref()
+        .map(|x| builder.create_string(x.as_str()));
+    let choice_changes_sha256 = payload
+        .choice_changes_sha256
+        .as_ref()
+        .map(|x| builder.create_string(x.as_str()));
+    let user_choice_changes_sha256 = payload
+        .user_choice_changes_sha256
+        .as_ref()
+        .map(|x| builder.create_string(x.as_str()));
+    let actions = create_actions(&payload.actions, builder);
+    let mirrors = create_mirrors(&payload.mirrors, builder);
+    let deltas = create_deltas(&payload.deltas, builder);
+    let options = create_options(&payload.options, builder);
+
+    use crate::fbs::pahkat::MacOSPackageFlag;
+    use crate::payload::macos::RebootSpec;
+
+    let mut flags = 0u8;
+    if payload.requires_reboot.contains(&RebootSpec::Install) {
+        flags |= MacOSPackageFlag::RequiresRebootOnInstall as u8;
+    }
+    if payload.requires_reboot.contains(&RebootSpec::Update) {
+        flags |= MacOSPackageFlag::RequiresRebootOnUpdate as u8;
+    }
+    if payload.requires_reboot.contains(&RebootSpec::Install) {
+        flags |= MacOSPackageFlag::RequiresRebootOnUninstall as u8;
+    }
+
+    use crate::payload::macos::InstallTarget;
+
+    if payload.targets.is_empty() {
+        flags |= MacOSPackageFlag::TargetSystem as u8;
+    } else {
+        for target in payload.targets.iter() {
+            match target {
+                InstallTarget::System => flags |= MacOSPackageFlag::TargetSystem as u8,
+                InstallTarget::User => flags |= MacOSPackageFlag::TargetUser as u8,
+            }
+        }
+    }
+
+    let args = crate::fbs::pahkat::MacOSPackageArgs {
+        url,
+        pkg_id,
+        flags,
+        size: payload.size,
+        installed_size: payload.installed_size,
+        team_id,
+        choice_changes,
+        user_choice_changes,
+        choice_changes_sha256,
+        user_choice_changes_sha256,
+        actions,
+        mirrors,
+        deltas,
+        options,
+    };
+
+    crate::fbs::pahkat::MacOSPackage::create(builder, &args).as_union_value()
+}
+
+fn create_payload_tarball_pkg<'a>(
+    payload: &crate::payload::tarball::Package,
+    builder: &mut FlatBufferBuilder<'a>,
+) -> fbs::WIPOffset<fbs::UnionWIPOffset> {
+    log::debug!("Tarball: {}", &payload.url);
+    let url = builder.create_string(payload.url.as_str());
+    let install_dir = payload
+        .install_dir
+        .as_ref()
+        .map(|x| builder.create_string(x.as_str()));
+    let mirrors = create_mirrors(&payload.mirrors, builder);
+    let deltas = create_deltas(&payload.deltas, builder);
+    let options = create_options(&payload.options, builder);
+    let args = crate::fbs::pahkat::TarballPackageArgs {
+        url,
+        size: payload.size,
+        installed_size: payload.installed_size,
+        install_dir,
+        strip_components: payload.strip_components,
+        mirrors,
+        deltas,
+        options,
+    };
+
+    crate::fbs::pahkat::TarballPackage::create(builder, &args).as_union_value()
+}
+
+fn create_payload_debian_pkg<'a>(
+    payload: &crate::payload::debian::Package,
+    builder: &mut FlatBufferBuilder<'a>,
+) -> fbs::WIPOffset<fbs::UnionWIPOffset> {
+    log::debug!("Debian: {}", &payload.url);
+    let url = builder.create_string(payload.url.as_str());
+    let package = builder.create_string(&payload.package);
+    let mirrors = create_mirrors(&payload.mirrors, builder);
+    let deltas = create_deltas(&payload.deltas, builder);
+    let args = crate::fbs::pahkat::DebianPackageArgs {
+        url,
+        package,
+        size: payload.size,
+        installed_size: payload.installed_size,
+        mirrors,
+        deltas,
+    };
+
+    crate::fbs::pahkat::DebianPackage::create(builder, &args).as_union_value()
+}
+
+fn create_payload_windows_msix<'a>(
+    payload: &crate::payload::windows::Msix,
+    builder: &mut FlatBufferBuilder<'a>,
+) -> fbs::WIPOffset<fbs::UnionWIPOffset> {
+    log::debug!("MSIX: {}", &payload.url);
+    let url = builder.create_string(payload.url.as_str());
+    let package_family_name = builder.create_string(&payload.package_family_name);
+    let actions = create_actions(&payload.actions, builder);
+    let mirrors = create_mirrors(&payload.mirrors, builder);
+    let deltas = create_deltas(&payload.deltas, builder);
+    let args = crate::fbs::pahkat::WindowsMsixArgs {
+        url,
+        package_family_name,
+        size: payload.size,
+        installed_size: payload.installed_size,
+        actions,
+        mirrors,
+        deltas,
+    };
+
+    crate::fbs::pahkat::WindowsMsix::create(builder, &args).as_union_value()
+}
+
+fn create_payload_macos_app_bundle<'a>(
+    payload: &crate::payload::macos::AppBundle,
+    builder: &mut FlatBufferBuilder<'a>,
+) -> fbs::WIPOffset<fbs::UnionWIPOffset> {
+    log::debug!("App bundle: {}", &payload.url);
+    let url = builder.create_string(payload.url.as_str());
+    let bundle_id = builder.create_string(&payload.bundle_id);
+    let app_name = builder.create_string(&payload.app_name);
+    let actions = create_actions(&payload.actions, builder);
+    let mirrors = create_mirrors(&payload.mirrors, builder);
+    let deltas = create_deltas(&payload.deltas, builder);
+    let args = crate::fbs::pahkat::MacOSAppBundleArgs {
+        url,
+        bundle_id,
+        app_name,
+        size: payload.size,
+        installed_size: payload.installed_size,
+        actions,
+        mirrors,
+        deltas,
+    };
+
+    crate::fbs::pahkat::MacOSAppBundle::create(builder, &args).as_union_value()
+}
+
+fn create_payload_rpm_pkg<'a>(
+    payload: &crate::payload::rpm::Package,
+    builder: &mut FlatBufferBuilder<'a>,
+) -> fbs::WIPOffset<fbs::UnionWIPOffset> {
+    log::debug!("RPM: {}", &payload.url);
+    let url = builder.create_string(payload.url.as_str());
+    let package = builder.create_string(&payload.package);
+    let mirrors = create_mirrors(&payload.mirrors, builder);
+    let deltas = create_deltas(&payload.deltas, builder);
+    let args = crate::fbs::pahkat::RpmPackageArgs {
+        url,
+        package,
+        size: payload.size,
+        installed_size: payload.installed_size,
+        mirrors,
+        deltas,
+    };
+
+    crate::fbs::pahkat::RpmPackage::create(builder, &args).as_union_value()
+}
+
+fn create_payload_flatpak<'a>(
+    payload: &crate::payload::flatpak::Package,
+    builder: &mut FlatBufferBuilder<'a>,
+) -> fbs::WIPOffset<fbs::UnionWIPOffset> {
+    log::debug!("Flatpak: {} {}", &payload.url, &payload.flatpak_ref);
+    let url = builder.create_string(payload.url.as_str());
+    let remote = builder.create_string(&payload.remote);
+    let flatpak_ref = builder.create_string(&payload.flatpak_ref);
+    let args = crate::fbs::pahkat::FlatpakArgs {
+        url,
+        remote,
+        flatpak_ref,
+        size: payload.size,
+        installed_size: payload.installed_size,
+    };
+
+    crate::fbs::pahkat::Flatpak::create(builder, &args).as_union_value()
+}
+
+fn create_payload_snap<'a>(
+    payload: &crate::payload::snap::Package,
+    builder: &mut FlatBufferBuilder<'a>,
+) -> fbs::WIPOffset<fbs::UnionWIPOffset> {
+    log::debug!("Snap: {} {}", &payload.url, &payload.name);
+    let url = builder.create_string(payload.url.as_str());
+    let name = builder.create_string(&payload.name);
+    let channel = payload
+        .channel
+        .as_ref()
+        .map(|x| builder.create_string(x.as_str()));
+    let args = crate::fbs::pahkat::SnapArgs {
+        url,
+        name,
+        channel,
+        classic: payload.classic,
+        size: payload.size,
+        installed_size: payload.installed_size,
+    };
+
+    crate::fbs::pahkat::Snap::create(builder, &args).as_union_value()
+}
+
+fn create_targets<'d, 'a>(
+    targets: &'d Vec<crate::payload::Target>,
+    builder: &mut FlatBufferBuilder<'a>,
+) -> fbs::WIPOffset<fbs::Vector<'a, fbs::ForwardsUOffset<crate::fbs::pahkat::Target<&'a [u8]>>>> {
+    let targets = targets
+        .iter()
+        .map(|target| {
+            let platform = builder.create_string(&target.platform);
+
+            // TODO: cache keys
+            let (dependencies_keys, dependencies_values): (Vec<_>, Vec<_>) = target
+                .dependencies
+                .iter()
+                .map(|(key, value)| {
+                    (
+                        builder.create_string(key.as_str()),
+                        builder.create_string(&value.to_string()),
+                    )
+                })
+                .unzip();
+            let (dependencies_keys, dependencies_values) = if dependencies_keys.is_empty() {
+                (None, None)
+            } else {
+                (
+                    Some(vectorize_strings(dependencies_keys, builder)),
+                    Some(vectorize_strings(dependencies_values, builder)),
+                )
+            };
+
+            let arch = target.arch.as_ref().map(|x| builder.create_string(&x));
+
+            use crate::fbs::pahkat::fbs_gen::PayloadType;
+            use crate::payload::Payload;
+
+            let (payload_type, payload) = match &target.payload {
+                Payload::WindowsExecutable(p) => (
+                    PayloadType::WindowsExecutable,
+                    create_payload_windows_exe(p, builder),
+                ),
+                Payload::MacOSPackage(p) => (
+                    PayloadType::MacOSPackage,
+                    create_payload_macos_pkg(p, builder),
+                ),
+                Payload::TarballPackage(p) => (
+                    PayloadType::TarballPackage,
+                    create_payload_tarball_pkg(p, builder),
+                ),
+                Payload::DebianPackage(p) => (
+                    PayloadType::DebianPackage,
+                    create_payload_debian_pkg(p, builder),
+                ),
+                Payload::RpmPackage(p) => {
+                    (PayloadType::RpmPackage, create_payload_rpm_pkg(p, builder))
+                }
+                Payload::Flatpak(p) => (PayloadType::Flatpak, create_payload_flatpak(p, builder)),
+                Payload::Snap(p) => (PayloadType::Snap, create_payload_snap(p, builder)),
+                Payload::WindowsMsix(p) => (
+                    PayloadType::WindowsMsix,
+                    create_payload_windows_msix(p, builder),
+                ),
+                Payload::MacOSAppBundle(p) => (
+                    PayloadType::MacOSAppBundle,
+                    create_payload_macos_app_bundle(p, builder),
+                ),
+            };
+
+            let args = crate::fbs::pahkat::TargetArgs {
+                platform,
+                arch,
+                dependencies_keys,
+                dependencies_values,
+                payload_type,
+                payload,
+            };
+
+            crate::fbs::pahkat::Target::create(builder, &args)
+        })
+        .collect::<Vec<_>>();
+
+    let len = targets.len();
+    builder.start_vector::<fbs::ForwardsUOffset<crate::fbs::pahkat::Target<&'_ [u8]>>>(len);
+    for target in targets.into_iter().rev() {
+        builder.push(target);
+    }
+    builder.end_vector(len)
+}
+
+fn create_releases<'a>(
+    releases: &[crate::package::Release],
+    release_keys: &mut StringKeys<'a>,
+    str_keys: &mut StringKeys<'a>,
+    builder: &mut FlatBufferBuilder<'a>,
+) -> fbs::WIPOffset<fbs::Vector<'a, fbs::ForwardsUOffset<crate::fbs::pahkat::Release<&'a [u8]>>>> {
+    let releases = releases
+        .iter()
+        .map(|release| {
+            // TODO: handle version type properly
+            use crate::package::version::Version;
+            let (version_type, version) = match &release.version {
+                // Version::Opaque => 1u8,
+                Version::Semantic(v) => (2u8, v.to_string()),
+            };
+            let version = shared_string(release_keys, &version, builder);
+            let channel = release
+                .channel
+                .as_ref()
+                .map(|x| shared_string(str_keys, x, builder));
+
+            let authors = release
+                .authors
+                .iter()
+                .map(|x| shared_string(str_keys, x, builder))
+                .collect::<Vec<_>>();
+            let authors = if authors.is_empty() {
+                None
+            } else {
+                Some(vectorize_strings(authors, builder))
+            };
+
+            let license = release
+                .license
+                .as_ref()
+                .map(|x| shared_string(str_keys, x, builder));
+            let license_url = release
+                .license_url
+                .as_ref()
+                .map(|x| shared_string(str_keys, x.as_str(), builder));
+            let target = Some(create_targets(&release.target, builder));
+            let available_from = release
+                .available_from
+                .as_ref()
+                .map(|x| builder.create_string(x.as_str()));
+            let min_client_version = release
+                .min_client_version
+                .as_ref()
+                .map(|x| builder.create_string(x.as_str()));
+            let (deprecation_message_keys, deprecation_message_values) =
+                match release.deprecation.as_ref() {
+                    Some(x) => vectorize_lang_map(&x.message, str_keys, builder),
+                    None => (None, None),
+                };
+            let provenance = release.provenance.as_ref();
+            let provenance_source_url = provenance
+                .and_then(|x| x.source_url.as_ref())
+                .map(|x| shared_string(str_keys, x.as_str(), builder));
+            let provenance_commit = provenance
+                .and_then(|x| x.commit.as_ref())
+                .map(|x| builder.create_string(x.as_str()));
+            let provenance_ci_run_url = provenance
+                .and_then(|x| x.ci_run_url.as_ref())
+                .map(|x| builder.create_string(x.as_str()));
+            let provenance_builder = provenance
+                .and_then(|x| x.builder.as_ref())
+                .map(|x| shared_string(str_keys, x, builder));
+
+            let args = crate::fbs::pahkat::ReleaseArgs {
+                version_type,
+                version,
+                channel,
+                authors,
+                license,
+                license_url,
+                target,
+                available_from,
+                rollout: release.rollout.map(|x| x.min(100)).unwrap_or(100),
+                min_client_version,
+                deprecation_severity: release
+                    .deprecation
+                    .as_ref()
+                    .map(|x| x.severity.to_u8())
+                    .unwrap_or(0),
+                deprecation_message_keys,
+                deprecation_message_values,
+                provenance_source_url,
+                provenance_commit,
+                provenance_ci_run_url,
+                provenance_builder,
+            };
+
+            crate::fbs::pahkat::Release::create(builder, &args)
+        })
+        .collect::<Vec<_>>();
+
+    let len = releases.len();
+    builder.start_vector::<fbs::ForwardsUOffset<crate::fbs::pahkat::Release<&'_ [u8]>>>(len);
+    for release in releases.into_iter().rev() {
+        builder.push(release);
+    }
+    builder.end_vector(len)
+}
+
+type DescriptorOffset<'a> = fbs::WIPOffset<crate::fbs::pahkat::Descriptor<&'a [u8]>>;
+
+/// Writes descriptors into a package index as they are pushed, keeping only their
+/// offsets until the index is finished.
+pub struct IndexWriter<'a> {
+    builder: FlatBufferBuilder<'a>,
+    release_keys: StringKeys<'a>,
+    str_keys: StringKeys<'a>,
+    packages: Vec<(fbs::WIPOffset<&'a str>, DescriptorOffset<'a>)>,
+}
+
+impl Default for IndexWriter<'_> {
+    fn default() -> Self {
+        IndexWriter::new()
+    }
+}
+
+impl<'a> IndexWriter<'a> {
+    pub fn new() -> IndexWriter<'a> {
+        IndexWriter {
+            builder: FlatBufferBuilder::new(),
+            release_keys: StringKeys::new(),
+            str_keys: StringKeys::new(),
+            packages: vec![],
+        }
+    }
+
+    /// Packages are kept in the order they are pushed.
+    pub fn push(&mut self, descriptor: &crate::package::Descriptor) -> Result<(), EncodeError> {
+        validate_payloads(descriptor)?;
+
+        let builder = &mut self.builder;
+        let str_keys = &mut self.str_keys;
+
+        let id_ref = builder.create_string(&descriptor.package.id);
+
+        let tags = if descriptor.package.tags.is_empty() {
+            None
+        } else {
+            let tags = descriptor
+                .package
+                .tags
+                .iter()
+                .map(|x| shared_string(str_keys, x, builder))
+                .collect::<Vec<_>>();
+            Some(vectorize_strings(tags, builder))
+        };
+
+        let (name_keys, name_values) = vectorize_lang_map(&descriptor.name, str_keys, builder);
+        let (description_keys, description_values) =
+            vectorize_lang_map(&descriptor.description, str_keys, builder);
+
+        let release = create_releases(
+            &descriptor.release,
+            &mut self.release_keys,
+            str_keys,
+            builder,
+        );
+
+        let args = crate::fbs::pahkat::DescriptorArgs {
+            id: id_ref,
+            name_keys,
+            name_values,
+            description_keys,
+            description_values,
+            tags,
+            release: Some(release),
+        };
+        let value = crate::fbs::pahkat::Descriptor::create(builder, &args);
+        self.packages.push((id_ref, value));
+
+        Ok(())
+    }
+
+    /// The contents of `packages/index.bin`.
+    pub fn finish(mut self) -> Vec<u8> {
+        let builder = &mut self.builder;
+        let len = self.packages.len();
+
+        builder.start_vector::<fbs::ForwardsUOffset<&'_ str>>(len);
+        for (id, _) in self.packages.iter().rev() {
+            builder.push(*id);
+        }
+        let packages_keys = Some(builder.end_vector(len));
+
+        builder.start_vector::<u8>(len);
+        for _ in 0..len {
+            builder.push(crate::fbs::pahkat::fbs_gen::PackageType::Descriptor as u8);
+        }
+        let packages_values_types = Some(builder.end_vector::<u8>(len));
+
+        builder.start_vector::<fbs::ForwardsUOffset<crate::fbs::pahkat::Descriptor<&'_ [u8]>>>(len);
+        for (_, value) in self.packages.iter().rev() {
+            builder.push(*value);
+        }
+        let packages_values = Some(builder.end_vector(len));
+
+        let args = crate::fbs::pahkat::PackagesArgs {
+            packages_values_types,
+            packages_keys,
+            packages_values,
+        };
+
+        let root = crate::fbs::pahkat::Packages::create(builder, &args);
+
+        builder.finish_minimal(root);
+        builder.finished_data().to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package::{Descriptor, DescriptorData, Package, Release, Version};
+    use crate::payload::{tarball, Payload, Target};
+
+    fn package(id: &str) -> Package {
+        let mut name = crate::LangTagMap::new();
+        name.insert("en".into(), format!("{} name", id));
+        name.insert("se".into(), format!("{} namma", id));
+
+        let target = Target::builder()
+            .platform("linux".into())
+            .payload(Payload::TarballPackage(
+                tarball::Package::builder()
+                    .url(format!("https://example.com/{}.txz", id).parse().unwrap())
+                    .size(1)
+                    .installed_size(2)
+                    .build(),
+            ))
+            .build();
+
+        Package::Concrete(
+            Descriptor::builder()
+                .package(
+                    DescriptorData::builder()
+                        .id(id.into())
+                        .tags(vec!["cat:spellers".into()])
+                        .build(),
+                )
+                .name(name)
+                .release(vec![Release::builder()
+                    .version(Version::new("1.0.0").unwrap())
+                    .target(vec![target])
+                    .build()])
+                .build(),
+        )
+    }
+
+    #[test]
+    fn index_is_reproducible() {
+        let forward = vec![package("a"), package("b"), package("c")];
+        let backward = forward.iter().rev().cloned().collect::<Vec<_>>();
+
+        let first = encode_index(&forward).unwrap();
+        let second = encode_index(&forward).unwrap();
+        let reversed = encode_index(&backward).unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(first, reversed);
+    }
+}
@@ -0,0 +1,352 @@
+//! Reading repositories in the JSON-LD layout of pahkat 1, with `index.json`,
+//! `packages/index.json` and `virtuals/index.json`.
+//!
+//! Legacy packages had a single installer, which becomes the only release of the
+//! converted descriptor. Virtual packages become synthetic descriptors.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Deserialize;
+use url::Url;
+
+use crate::package::{self, Descriptor, DescriptorData, Release, Version};
+use crate::payload::{self, Payload, Target};
+use crate::repo::{Agent, Index, RepoUrl, RepositoryData};
+use crate::synth;
+use crate::{DependencyKey, DependencyMap, LangTagMap};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Failed to parse legacy JSON")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Legacy index has no valid `base` URL")]
+    MissingUrl,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LegacyIndex {
+    #[serde(default)]
+    base: Option<Url>,
+    #[serde(default)]
+    name: LangTagMap<String>,
+    #[serde(default)]
+    description: LangTagMap<String>,
+    #[serde(default)]
+    channels: Vec<String>,
+    agent: LegacyAgent,
+}
+
+#[derive(Debug, Deserialize)]
+struct LegacyAgent {
+    name: String,
+    version: String,
+    #[serde(default)]
+    url: Option<Url>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LegacyPackages {
+    #[serde(default)]
+    packages: BTreeMap<String, LegacyPackage>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LegacyPackage {
+    id: String,
+    #[serde(default)]
+    name: LangTagMap<String>,
+    #[serde(default)]
+    description: LangTagMap<String>,
+    version: String,
+    #[serde(default)]
+    channel: Option<String>,
+    #[serde(default)]
+    category: Option<String>,
+    #[serde(default)]
+    languages: Vec<String>,
+    /// Minimum OS version by platform
+    #[serde(default)]
+    platform: BTreeMap<String, String>,
+    #[serde(default)]
+    dependencies: BTreeMap<String, String>,
+    #[serde(default)]
+    installer: Option<LegacyInstaller>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "@type")]
+enum LegacyInstaller {
+    WindowsInstaller(LegacyWindowsInstaller),
+    MacOSInstaller(LegacyMacOSInstaller),
+    TarballInstaller(LegacyTarballInstaller),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LegacyWindowsInstaller {
+    url: Url,
+    product_code: String,
+    #[serde(default, rename = "type")]
+    kind: Option<String>,
+    #[serde(default)]
+    args: Option<String>,
+    #[serde(default)]
+    uninstall_args: Option<String>,
+    #[serde(default)]
+    requires_reboot: bool,
+    #[serde(default)]
+    requires_uninstall_reboot: bool,
+    size: u64,
+    installed_size: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LegacyMacOSInstaller {
+    url: Url,
+    pkg_id: String,
+    #[serde(default)]
+    targets: BTreeSet<payload::macos::InstallTarget>,
+    #[serde(default)]
+    requires_reboot: bool,
+    #[serde(default)]
+    requires_uninstall_reboot: bool,
+    size: u64,
+    installed_size: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LegacyTarballInstaller {
+    url: Url,
+    size: u64,
+    installed_size: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct LegacyVirtuals {
+    #[serde(default)]
+    virtuals: BTreeMap<String, LegacyVirtual>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LegacyVirtual {
+    id: String,
+    #[serde(default)]
+    name: LangTagMap<String>,
+    #[serde(default)]
+    description: LangTagMap<String>,
+    version: String,
+    #[serde(default)]
+    channel: Option<String>,
+    target: LegacyVirtualTarget,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LegacyVirtualTarget {
+    #[serde(default)]
+    registry_key: Option<LegacyRegistryKey>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LegacyRegistryKey {
+    path: String,
+    name: String,
+}
+
+/// Converts the contents of a legacy `index.json`. The repository URL is `url` if
+/// given, otherwise the `base` of the index.
+pub fn read_index(data: &[u8], url: Option<&RepoUrl>) -> Result<Index, Error> {
+    let index: LegacyIndex = serde_json::from_slice(data)?;
+    let url = match url {
+        Some(v) => v.clone(),
+        None => index
+            .base
+            .and_then(|x| RepoUrl::new(x).ok())
+            .ok_or(Error::MissingUrl)?,
+    };
+
+    Ok(Index::builder()
+        .repository(
+            RepositoryData::builder()
+                .url(url)
+                .channels(index.channels)
+                .build(),
+        )
+        .name(index.name)
+        .description(index.description)
+        .agent(
+            Agent::builder()
+                .name(index.agent.name)
+                .version(index.agent.version)
+                .url(index.agent.url)
+                .build(),
+        )
+        .build())
+}
+
+/// Converts the contents of a legacy `packages/index.json`. Packages without an
+/// installer or a semantic version are logged and left out.
+pub fn read_packages(data: &[u8]) -> Result<Vec<Descriptor>, Error> {
+    let packages: LegacyPackages = serde_json::from_slice(data)?;
+    Ok(packages
+        .packages
+        .into_iter()
+        .filter_map(|(id, package)| {
+            let descriptor = convert_package(package);
+            if descriptor.is_none() {
+                log::warn!("Skipping legacy package `{}`", id);
+            }
+            descriptor
+        })
+        .collect())
+}
+
+/// Converts the contents of a legacy `virtuals/index.json`. Only registry key targets
+/// have a synthetic counterpart; other virtuals are logged and left out.
+pub fn read_virtuals(data: &[u8]) -> Result<Vec<synth::Descriptor>, Error> {
+    let virtuals: LegacyVirtuals = serde_json::from_slice(data)?;
+    Ok(virtuals
+        .virtuals
+        .into_iter()
+        .filter_map(|(id, virtual_)| {
+            let descriptor = convert_virtual(virtual_);
+            if descriptor.is_none() {
+                log::warn!("Skipping legacy virtual `{}`", id);
+            }
+            descriptor
+        })
+        .collect())
+}
+
+fn convert_package(package: LegacyPackage) -> Option<Descriptor> {
+    let version = Version::new(&package.version).ok()?;
+
+    let (platform, payload) = match package.installer? {
+        LegacyInstaller::WindowsInstaller(x) => {
+            let mut requires_reboot = BTreeSet::new();
+            if x.requires_reboot {
+                requires_reboot.insert(payload::windows::RebootSpec::Install);
+                requires_reboot.insert(payload::windows::RebootSpec::Update);
+            }
+            if x.requires_uninstall_reboot {
+                requires_reboot.insert(payload::windows::RebootSpec::Uninstall);
+            }
+
+            let payload = payload::windows::Executable::builder()
+                .url(x.url)
+                .product_code(x.product_code)
+                .size(x.size)
+                .installed_size(x.installed_size)
+                .kind(x.kind.and_then(|x| x.parse().ok()))
+                .args(x.args)
+                .uninstall_args(x.uninstall_args)
+                .requires_reboot(requires_reboot)
+                .build();
+            ("windows".to_string(), Payload::WindowsExecutable(payload))
+        }
+        LegacyInstaller::MacOSInstaller(x) => {
+            let mut requires_reboot = BTreeSet::new();
+            if x.requires_reboot {
+                requires_reboot.insert(payload::macos::RebootSpec::Install);
+                requires_reboot.insert(payload::macos::RebootSpec::Update);
+            }
+            if x.requires_uninstall_reboot {
+                requires_reboot.insert(payload::macos::RebootSpec::Uninstall);
+            }
+
+            let payload = payload::macos::Package::builder()
+                .url(x.url)
+                .pkg_id(x.pkg_id)
+                .targets(x.targets)
+                .requires_reboot(requires_reboot)
+                .size(x.size)
+                .installed_size(x.installed_size)
+                .build();
+            ("macos".to_string(), Payload::MacOSPackage(payload))
+        }
+        LegacyInstaller::TarballInstaller(x) => {
+            let platform = package
+                .platform
+                .keys()
+                .next()
+                .cloned()
+                .unwrap_or_else(|| "linux".to_string());
+            let payload = payload::tarball::Package::builder()
+                .url(x.url)
+                .size(x.size)
+                .installed_size(x.installed_size)
+                .build();
+            (platform, Payload::TarballPackage(payload))
+        }
+    };
+
+    let dependencies = package
+        .dependencies
+        .into_iter()
+        .map(|(id, req)| {
+            // Legacy indexes give a bare minimum version.
+            let req = match req.parse() {
+                Err(package::version::Error::MissingOperator(_)) => format!(">={}", req).parse(),
+                result => result,
+            }
+            .unwrap_or(package::VersionReq::STAR);
+            (DependencyKey::from(id), req)
+        })
+        .collect::<DependencyMap>();
+
+    let mut tags = package
+        .category
+        .iter()
+        .map(|x| format!("category:{}", x))
+        .collect::<Vec<_>>();
+    tags.extend(package.languages.iter().map(|x| format!("language:{}", x)));
+
+    Some(
+        Descriptor::builder()
+            .package(DescriptorData::builder().id(package.id).tags(tags).build())
+            .name(package.name)
+            .description(package.description)
+            .release(vec![Release::builder()
+                .version(version)
+                .channel(package.channel.filter(|x| !x.is_empty()))
+                .target(vec![Target::builder()
+                    .platform(platform)
+                    .dependencies(dependencies)
+                    .payload(payload)
+                    .build()])
+                .build()])
+            .build(),
+    )
+}
+
+fn convert_virtual(virtual_: LegacyVirtual) -> Option<synth::Descriptor> {
+    let key = virtual_.target.registry_key?;
+    let verifier = synth::Verifier::WindowsRegistryKey(
+        synth::windows::RegistryKey::builder()
+            .path(key.path)
+            .name(key.name)
+            .build(),
+    );
+
+    Some(
+        synth::Descriptor::builder()
+            .synthetic(synth::SyntheticData::builder().id(virtual_.id).build())
+            .name(virtual_.name)
+            .description(virtual_.description)
+            .releases(vec![synth::Release::builder()
+                .version(virtual_.version)
+                .channel(virtual_.channel.unwrap_or_default())
+                .targets(vec![synth::Target::builder()
+                    .platform("windows".to_string())
+                    .verifier(verifier)
+                    .build()])
+                .build()])
+            .build(),
+    )
+}
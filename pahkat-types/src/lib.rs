@@ -1,7 +1,85 @@
+/// Implements `JsonSchema` for a type that serializes as a string.
+#[cfg(feature = "schemars")]
+macro_rules! string_schema {
+    ($ty:ident, $format:expr) => {
+        impl schemars::JsonSchema for $ty {
+            fn schema_name() -> String {
+                stringify!($ty).to_string()
+            }
+
+            fn json_schema(_: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+                schemars::schema::SchemaObject {
+                    instance_type: Some(schemars::schema::InstanceType::String.into()),
+                    format: $format.map(str::to_string),
+                    ..Default::default()
+                }
+                .into()
+            }
+        }
+    };
+}
+
+/// Implements the poem-openapi traits for a type that serializes as a string of
+/// the given format.
+#[cfg(feature = "poem-openapi")]
+macro_rules! string_openapi {
+    ($ty:ident, $format:literal) => {
+        impl poem_openapi::types::Type for $ty {
+            const IS_REQUIRED: bool = true;
+
+            type RawValueType = Self;
+
+            type RawElementValueType = Self;
+
+            fn name() -> std::borrow::Cow<'static, str> {
+                concat!("string(", $format, ")").into()
+            }
+
+            fn schema_ref() -> poem_openapi::registry::MetaSchemaRef {
+                poem_openapi::registry::MetaSchemaRef::Inline(Box::new(
+                    poem_openapi::registry::MetaSchema::new_with_format("string", $format),
+                ))
+            }
+
+            fn as_raw_value(&self) -> Option<&Self::RawValueType> {
+                Some(self)
+            }
+
+            fn raw_element_iter<'a>(
+                &'a self,
+            ) -> Box<dyn Iterator<Item = &'a Self::RawElementValueType> + 'a> {
+                Box::new(self.as_raw_value().into_iter())
+            }
+        }
+
+        impl poem_openapi::types::ParseFromJSON for $ty {
+            fn parse_from_json(
+                value: Option<serde_json::Value>,
+            ) -> poem_openapi::types::ParseResult<Self> {
+                match value.unwrap_or_default() {
+                    serde_json::Value::String(s) => Ok(s.parse()?),
+                    value => Err(poem_openapi::types::ParseError::expected_type(value)),
+                }
+            }
+        }
+
+        impl poem_openapi::types::ToJSON for $ty {
+            fn to_json(&self) -> Option<serde_json::Value> {
+                Some(serde_json::Value::String(self.to_string()))
+            }
+        }
+    };
+}
+
+pub mod index_writer;
+#[cfg(feature = "legacy")]
+pub mod legacy;
 pub mod package;
 pub mod package_key;
 pub mod payload;
 pub mod repo;
+#[cfg(any(test, feature = "proptest"))]
+pub mod strategy;
 pub mod synth;
 
 use serde::{Deserialize, Serialize};
@@ -17,6 +95,7 @@ pub type LangTagMap<T> = std::collections::BTreeMap<String, T>;
 #[serde(untagged)]
 #[cfg_attr(feature = "poem-openapi", derive(poem_openapi::Union))]
 #[cfg_attr(feature = "poem-openapi", oai(discriminator_name = "type"))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub enum DependencyKey {
     Remote(Url),
     Local(String),
@@ -77,7 +156,7 @@ impl From<&str> for DependencyKey {
 }
 
 /// Will be replaced with a validating Map in the future.
-pub type DependencyMap = std::collections::BTreeMap<DependencyKey, String>;
+pub type DependencyMap = std::collections::BTreeMap<DependencyKey, package::VersionReq>;
 
 pub use package_key::PackageKey;
 pub use payload::AsDownloadUrl;
@@ -96,7 +175,7 @@ mod tests {
         let mut deps = DependencyMap::new();
         deps.insert(
             DependencyKey::Local("some-dependency".to_string()),
-            "*".to_string(),
+            package::VersionReq::STAR,
         );
 
         let package1 = package::Descriptor::builder()
@@ -221,7 +300,7 @@ mod tests {
         let mut deps = DependencyMap::new();
         deps.insert(
             DependencyKey::Local("some-other-dependency".to_string()),
-            "*".to_string(),
+            package::VersionReq::STAR,
         );
 
         let package2 = package::Descriptor::builder()
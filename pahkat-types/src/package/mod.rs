@@ -6,7 +6,7 @@ use typed_builder::TypedBuilder;
 use url::Url;
 
 use crate::LangTagMap;
-pub use version::Version;
+pub use version::{Version, VersionReq};
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 #[cfg_attr(feature = "async-graphql", derive(async_graphql::Union))]
@@ -188,11 +188,148 @@ pub struct Release {
     #[builder(default)]
     pub license_url: Option<Url>,
 
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    /// RFC 3339 timestamp; clients ignore the release until this time has passed
+    pub available_from: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    /// Percentage (0-100) of machines that are offered this release
+    pub rollout: Option<u8>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    /// Oldest Pahkat client version (semver) able to install this release
+    pub min_client_version: Option<String>,
+
+    // Tables have to come last in TOML
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub deprecation: Option<Deprecation>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub provenance: Option<Provenance>,
+
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     #[builder(default)]
     pub target: Vec<crate::payload::Target>,
 }
 
+/// How strongly a deprecated release is discouraged.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "async-graphql", derive(async_graphql::Enum))]
+#[serde(rename_all = "lowercase")]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub enum DeprecationSeverity {
+    /// Shown to the user, but does not affect installing
+    Notice,
+    /// Shown prominently before installing, but does not block it
+    Warning,
+    /// Only installed when the user explicitly overrides it
+    Critical,
+}
+
+impl Default for DeprecationSeverity {
+    fn default() -> Self {
+        DeprecationSeverity::Warning
+    }
+}
+
+impl DeprecationSeverity {
+    pub fn to_u8(&self) -> u8 {
+        match self {
+            DeprecationSeverity::Notice => 1,
+            DeprecationSeverity::Warning => 2,
+            DeprecationSeverity::Critical => 3,
+        }
+    }
+
+    /// Unknown severities are treated as critical, so that older clients do
+    /// not silently install releases newer tooling warns hard about.
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            1 => DeprecationSeverity::Notice,
+            2 => DeprecationSeverity::Warning,
+            _ => DeprecationSeverity::Critical,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash, TypedBuilder)]
+#[cfg_attr(feature = "async-graphql", derive(async_graphql::SimpleObject))]
+#[cfg_attr(feature = "async-graphql", graphql(name = "ReleaseDeprecation"))]
+#[non_exhaustive]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "schemars", schemars(rename = "ReleaseDeprecation"))]
+pub struct Deprecation {
+    #[serde(default)]
+    #[builder(default)]
+    pub severity: DeprecationSeverity,
+
+    /// Why the release should no longer be installed, and what to use instead
+    #[serde(default)]
+    #[builder(default)]
+    pub message: LangTagMap<String>,
+}
+
+impl Deprecation {
+    /// The message in `language`, falling back to English and then to any
+    /// language available.
+    pub fn message_for(&self, language: &str) -> Option<&str> {
+        self.message
+            .get(language)
+            .or_else(|| self.message.get("en"))
+            .or_else(|| self.message.values().next())
+            .map(|x| &**x)
+    }
+}
+
+/// Where and how a release was built, for tracing a broken release back to the
+/// build that made it.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq, Hash, TypedBuilder)]
+#[cfg_attr(feature = "structopt", derive(structopt::StructOpt))]
+#[cfg_attr(feature = "async-graphql", derive(async_graphql::SimpleObject))]
+#[cfg_attr(feature = "async-graphql", graphql(name = "ReleaseProvenance"))]
+#[non_exhaustive]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "schemars", schemars(rename = "ReleaseProvenance"))]
+pub struct Provenance {
+    /// Repository the release was built from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    #[cfg_attr(feature = "structopt", structopt(long))]
+    pub source_url: Option<Url>,
+
+    /// Hash of the commit that was built
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    #[cfg_attr(feature = "structopt", structopt(long))]
+    pub commit: Option<String>,
+
+    /// The CI run that built the release
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    #[cfg_attr(feature = "structopt", structopt(long))]
+    pub ci_run_url: Option<Url>,
+
+    /// Who or what built the release, such as the name of a CI workflow
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    #[cfg_attr(feature = "structopt", structopt(long))]
+    pub builder: Option<String>,
+}
+
+impl Provenance {
+    pub fn is_empty(&self) -> bool {
+        self.source_url.is_none()
+            && self.commit.is_none()
+            && self.ci_run_url.is_none()
+            && self.builder.is_none()
+    }
+}
+
 impl PartialOrd for Release {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         self.version.partial_cmp(&other.version)
@@ -68,6 +68,9 @@ impl async_graphql::ScalarType for Version {
 pub enum Error {
     #[error("Unhandled input: {0}")]
     UnhandledInput(String),
+
+    #[error("Version requirement `{0}` needs an operator, such as `^{0}` or `={0}`")]
+    MissingOperator(String),
 }
 
 impl Version {
@@ -113,6 +116,117 @@ impl FromStr for Version {
     }
 }
 
+/// A requirement on the version of a dependency, such as `*` or `>=1.2, <2`.
+///
+/// Stored as a string in descriptors and the index. Dependencies added before
+/// requirements were checked may have an empty string, which is read as `*`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct VersionReq(semver::VersionReq);
+
+impl VersionReq {
+    /// Matches any version.
+    pub const STAR: VersionReq = VersionReq(semver::VersionReq::STAR);
+
+    pub fn matches(&self, version: &Version) -> bool {
+        match version {
+            Version::Semantic(v) => self.0.matches(v),
+        }
+    }
+
+    pub fn is_star(&self) -> bool {
+        self.0 == semver::VersionReq::STAR
+    }
+}
+
+impl Default for VersionReq {
+    fn default() -> Self {
+        VersionReq::STAR
+    }
+}
+
+impl Display for VersionReq {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for VersionReq {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "" | "*" => Ok(VersionReq::STAR),
+            s => {
+                let req = s
+                    .parse::<semver::VersionReq>()
+                    .map_err(|_| Error::UnhandledInput(s.to_string()))?;
+
+                // semver reads a bare version as `^`, which would quietly allow newer
+                // releases than the one written.
+                let bare = s
+                    .split(',')
+                    .map(str::trim)
+                    .zip(req.comparators.iter())
+                    .find(|(part, c)| c.op == semver::Op::Caret && !part.starts_with('^'));
+                match bare {
+                    Some((part, _)) => Err(Error::MissingOperator(part.to_string())),
+                    None => Ok(VersionReq(req)),
+                }
+            }
+        }
+    }
+}
+
+// semver does not order requirements, but maps of dependencies need them to be.
+impl PartialOrd for VersionReq {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for VersionReq {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.to_string().cmp(&other.to_string())
+    }
+}
+
+#[cfg(feature = "schemars")]
+string_schema!(Version, None::<&str>);
+
+#[cfg(feature = "schemars")]
+string_schema!(VersionReq, None::<&str>);
+
+impl Serialize for VersionReq {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for VersionReq {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(feature = "async-graphql")]
+#[cfg_attr(feature = "async-graphql", async_graphql::Scalar)]
+impl async_graphql::ScalarType for VersionReq {
+    fn parse(value: async_graphql::Value) -> async_graphql::InputValueResult<Self> {
+        match &value {
+            async_graphql::Value::String(s) => Ok(s.parse()?),
+            _ => Err(async_graphql::InputValueError::expected_type(value)),
+        }
+    }
+
+    fn to_value(&self) -> async_graphql::Value {
+        async_graphql::Value::String(self.to_string())
+    }
+}
+
+#[cfg(feature = "poem-openapi")]
+string_openapi!(VersionReq, "version-req");
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -185,6 +299,34 @@ mod tests {
         assert_ne!(my, other);
     }
 
+    #[test]
+    fn version_req_accepts_legacy_values() {
+        assert_eq!("*".parse::<VersionReq>().unwrap(), VersionReq::STAR);
+        assert_eq!("".parse::<VersionReq>().unwrap(), VersionReq::STAR);
+        assert!(">= 1.2".parse::<VersionReq>().is_ok());
+        assert!("latest".parse::<VersionReq>().is_err());
+    }
+
+    #[test]
+    fn version_req_rejects_bare_versions() {
+        assert!(matches!(
+            "1.0".parse::<VersionReq>(),
+            Err(Error::MissingOperator(x)) if x == "1.0"
+        ));
+        assert!(">=1.0, 2".parse::<VersionReq>().is_err());
+        assert!("^1.0".parse::<VersionReq>().is_ok());
+        assert!("1.*".parse::<VersionReq>().is_ok());
+    }
+
+    #[test]
+    fn version_req_matches() {
+        let req = ">=1.2, <2".parse::<VersionReq>().unwrap();
+
+        assert!(req.matches(&Version::new("1.4.0").unwrap()));
+        assert!(!req.matches(&Version::new("2.0.0").unwrap()));
+        assert!(VersionReq::STAR.matches(&Version::new("0.1.0").unwrap()));
+    }
+
     #[test]
     fn test_greater_my_semver() {
         let my = Version::new("5.1.2").unwrap();
@@ -106,20 +106,34 @@ impl<'a> From<&'a PackageKey> for String {
 #[derive(Debug, Clone, thiserror::Error)]
 #[non_exhaustive]
 pub enum TryFromError {
-    #[error("Invalid URL")]
-    InvalidUrl,
+    #[error("Invalid URL: {0}")]
+    InvalidUrl(#[from] url::ParseError),
 
-    #[error("Invalid repository URL")]
+    #[error("Invalid repository URL: {0}")]
     InvalidRepoUrl(#[from] crate::repo::RepoUrlError),
 
-    #[error("URL must not be a base")]
+    #[error("URL has no path")]
     BaseForbidden,
 
     #[error("URL does not contain `/packages/` segment")]
     MissingPackagesSegment,
 
-    #[error("Invalid package segment")]
-    InvalidPackageSegment,
+    #[error("URL has no package id after `/packages/`")]
+    MissingPackageId,
+
+    #[error("Unexpected path after package id: `{0}`")]
+    InvalidPackageSegment(String),
+
+    #[error(
+        "Unknown query parameter `{0}`, expected one of `arch`, `channel`, `platform` or `version`"
+    )]
+    UnknownQueryParameter(String),
+
+    #[error("Query parameter `{0}` is given more than once")]
+    DuplicateQueryParameter(String),
+
+    #[error("Query parameter `{0}` is empty")]
+    EmptyQueryParameter(String),
 }
 
 impl TryFrom<Url> for PackageKey {
@@ -143,13 +157,20 @@ impl<'a> TryFrom<&'a Url> for PackageKey {
         let mut query = PackageKeyParams::default();
 
         for (k, v) in query_pairs {
-            match &*k {
-                "version" => query.version = Some(v.to_string()),
-                "channel" => query.channel = Some(v.to_string()),
-                "platform" => query.platform = Some(v.to_string()),
-                "arch" => query.arch = Some(v.to_string()),
-                _ => {}
+            let field = match &*k {
+                "version" => &mut query.version,
+                "channel" => &mut query.channel,
+                "platform" => &mut query.platform,
+                "arch" => &mut query.arch,
+                _ => return Err(TryFromError::UnknownQueryParameter(k.to_string())),
+            };
+            if field.is_some() {
+                return Err(TryFromError::DuplicateQueryParameter(k.to_string()));
+            }
+            if v.is_empty() {
+                return Err(TryFromError::EmptyQueryParameter(k.to_string()));
             }
+            *field = Some(v.to_string());
         }
 
         let (left, id) = {
@@ -164,8 +185,10 @@ impl<'a> TryFrom<&'a Url> for PackageKey {
                 return Err(TryFromError::MissingPackagesSegment);
             }
 
-            if sides[1].len() != 1 {
-                return Err(TryFromError::InvalidPackageSegment);
+            match sides[1] {
+                [] | [""] => return Err(TryFromError::MissingPackageId),
+                [_] => {}
+                [_, rest @ ..] => return Err(TryFromError::InvalidPackageSegment(rest.join("/"))),
             }
 
             let id = sides[1][0].to_string();
@@ -199,11 +222,14 @@ impl<'a> TryFrom<&'a str> for PackageKey {
     type Error = TryFromError;
 
     fn try_from(url: &'a str) -> Result<PackageKey, Self::Error> {
-        let url = Url::parse(url).map_err(|_| TryFromError::InvalidUrl)?;
+        let url = Url::parse(url)?;
         PackageKey::try_from(&url)
     }
 }
 
+#[cfg(feature = "schemars")]
+string_schema!(PackageKey, Some("uri"));
+
 impl Serialize for PackageKey {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -238,3 +264,105 @@ impl<'de> Visitor<'de> for PackageKeyVisitor {
         PackageKey::try_from(value).map_err(|e| E::custom(e))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use proptest::option;
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn parse(input: &str) -> Result<PackageKey, TryFromError> {
+        PackageKey::try_from(input)
+    }
+
+    #[test]
+    fn diagnostics() {
+        assert!(matches!(
+            parse("not a url"),
+            Err(TryFromError::InvalidUrl(_))
+        ));
+        assert!(matches!(
+            parse("http://pahkat.example/repo/packages/speller"),
+            Err(TryFromError::InvalidRepoUrl(
+                crate::repo::RepoUrlError::InvalidScheme(_)
+            ))
+        ));
+        assert!(matches!(
+            parse("mailto:speller@pahkat.example"),
+            Err(TryFromError::BaseForbidden)
+        ));
+        assert!(matches!(
+            parse("https://pahkat.example/repo/speller"),
+            Err(TryFromError::MissingPackagesSegment)
+        ));
+        assert!(matches!(
+            parse("https://pahkat.example/repo/packages/"),
+            Err(TryFromError::MissingPackageId)
+        ));
+        assert!(matches!(
+            parse("https://pahkat.example/repo/packages/speller/1.0"),
+            Err(TryFromError::InvalidPackageSegment(x)) if x == "1.0"
+        ));
+        assert!(matches!(
+            parse("https://pahkat.example/repo/packages/speller?channel=beta&chanel=beta"),
+            Err(TryFromError::UnknownQueryParameter(x)) if x == "chanel"
+        ));
+        assert!(matches!(
+            parse("https://pahkat.example/repo/packages/speller?arch=x86&arch=arm64"),
+            Err(TryFromError::DuplicateQueryParameter(x)) if x == "arch"
+        ));
+        assert!(matches!(
+            parse("https://pahkat.example/repo/packages/speller?version="),
+            Err(TryFromError::EmptyQueryParameter(x)) if x == "version"
+        ));
+    }
+
+    fn param() -> impl Strategy<Value = Option<String>> {
+        option::of("[a-z0-9._-]{1,8}")
+    }
+
+    fn package_key() -> impl Strategy<Value = PackageKey> {
+        (
+            "[a-z][a-z0-9]{0,15}",
+            "[a-z][a-z0-9-]{0,15}",
+            "[a-z][a-z0-9-]{0,15}",
+            param(),
+            param(),
+            param(),
+            param(),
+        )
+            .prop_map(|(host, path, id, channel, platform, version, arch)| {
+                let url = format!("https://{}.example/{}/", host, path);
+                PackageKey::new_unchecked(
+                    RepoUrl::new(url.parse().unwrap()).unwrap(),
+                    id,
+                    Some(PackageKeyParams {
+                        channel,
+                        platform,
+                        version,
+                        arch,
+                    }),
+                )
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn parse_never_panics(input in "\\PC*") {
+            let _ = parse(&input);
+        }
+
+        #[test]
+        fn parse_url_like_never_panics(
+            input in "(https|http|file|ftp)://[a-z.]{0,8}(/[a-z%.]{0,8}|/packages){0,4}/?(\\?[a-z]{0,8}=?[a-z%]{0,4}(&[a-z]{0,8}=?[a-z]{0,4}){0,3})?"
+        ) {
+            let _ = parse(&input);
+        }
+
+        #[test]
+        fn string_round_trip(key in package_key()) {
+            prop_assert_eq!(parse(&key.to_string()).unwrap(), key);
+        }
+    }
+}
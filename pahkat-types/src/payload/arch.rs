@@ -0,0 +1,100 @@
+//! Architecture names used in payload targets.
+//!
+//! Targets name architectures the way Rust's `target_arch` does, but the names used by
+//! other tooling (`amd64`, `arm64`, `i686`…) are accepted as aliases.
+
+pub const X86_64: &str = "x86_64";
+pub const X86: &str = "x86";
+pub const AARCH64: &str = "aarch64";
+pub const ARM: &str = "arm";
+/// A macOS payload containing code for every architecture it supports.
+pub const UNIVERSAL: &str = "universal";
+
+/// The canonical name of an architecture, or the name itself if it is not known.
+pub fn canonical(name: &str) -> &str {
+    match name {
+        "x86_64" | "amd64" | "x64" => X86_64,
+        "x86" | "i386" | "i486" | "i586" | "i686" => X86,
+        "aarch64" | "arm64" => AARCH64,
+        "arm" | "armv7" => ARM,
+        "universal" | "universal2" => UNIVERSAL,
+        other => other,
+    }
+}
+
+pub fn is_same(a: &str, b: &str) -> bool {
+    canonical(a) == canonical(b)
+}
+
+/// Whether a target built for `target_arch` runs natively on `arch`.
+pub fn is_native(target_arch: &str, arch: &str) -> bool {
+    canonical(target_arch) == UNIVERSAL || is_same(target_arch, arch)
+}
+
+/// The first Windows build, that of Windows 11, able to run x86_64 code on ARM64.
+pub const WINDOWS_X86_64_EMULATION_BUILD: u32 = 22000;
+
+/// Architectures the platform can run through emulation on a machine of the `native`
+/// architecture, in order of preference. `windows_build` is the build number of the
+/// Windows the targets are for, if known.
+///
+/// Targets for the native architecture are always preferred. After those, Windows
+/// on ARM64 takes x86_64 and then x86 targets, and Windows on x86_64 takes x86
+/// targets through WOW64. Apple silicon Macs take x86_64 targets through Rosetta.
+pub fn emulated(
+    platform: &str,
+    native: &str,
+    windows_build: Option<u32>,
+) -> &'static [&'static str] {
+    match (platform, canonical(native)) {
+        ("windows", AARCH64) => windows_arm64_emulated(windows_build),
+        ("windows", X86_64) => &[X86],
+        // Only when Rosetta is installed, which the platform has to check
+        ("macos", AARCH64) => &[X86_64],
+        _ => &[],
+    }
+}
+
+/// Architectures Windows on ARM64 can emulate, in order of preference. Emulating
+/// x86_64 needs Windows 11, so it is left out unless `build` is known to be recent
+/// enough, but x86 always works.
+pub fn windows_arm64_emulated(build: Option<u32>) -> &'static [&'static str] {
+    if build.is_some_and(|x| x >= WINDOWS_X86_64_EMULATION_BUILD) {
+        &[X86_64, X86]
+    } else {
+        &[X86]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aliases() {
+        assert!(is_same("arm64", "aarch64"));
+        assert!(is_same("amd64", X86_64));
+        assert!(is_same("i686", X86));
+        assert!(!is_same("x86", "x86_64"));
+        assert_eq!(canonical("riscv64"), "riscv64");
+    }
+
+    #[test]
+    fn universal_is_native_everywhere() {
+        assert!(is_native("universal2", AARCH64));
+        assert!(is_native(UNIVERSAL, X86_64));
+        assert!(!is_native(X86_64, AARCH64));
+    }
+
+    #[test]
+    fn emulation_order() {
+        assert_eq!(emulated("windows", "arm64", Some(22631)), [X86_64, X86]);
+        assert_eq!(emulated("windows", "arm64", Some(19045)), [X86]);
+        assert_eq!(emulated("windows", "arm64", None), [X86]);
+        assert_eq!(emulated("windows", X86_64, None), [X86]);
+        assert!(emulated("windows", X86, None).is_empty());
+        assert_eq!(emulated("macos", "arm64", None), [X86_64]);
+        assert!(emulated("macos", X86_64, None).is_empty());
+        assert!(emulated("linux", AARCH64, None).is_empty());
+    }
+}
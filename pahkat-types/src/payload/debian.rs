@@ -0,0 +1,99 @@
+use serde::{Deserialize, Serialize};
+use typed_builder::TypedBuilder;
+
+#[derive(
+    Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, TypedBuilder,
+)]
+#[cfg_attr(feature = "structopt", derive(structopt::StructOpt))]
+#[cfg_attr(feature = "poem-openapi", derive(poem_openapi::Object))]
+#[cfg_attr(feature = "poem-openapi", oai(rename = "DebianPackage"))]
+#[cfg_attr(feature = "async-graphql", derive(async_graphql::SimpleObject))]
+#[cfg_attr(feature = "async-graphql", graphql(name = "DebianPackage"))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "schemars", schemars(rename = "DebianPackage"))]
+pub struct Package {
+    #[cfg_attr(feature = "structopt", structopt(short, long))]
+    pub url: url::Url,
+
+    /// Alternative locations of the same file, tried in order if `url` fails
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[builder(default)]
+    #[cfg_attr(feature = "structopt", structopt(long = "mirror"))]
+    #[cfg_attr(feature = "poem-openapi", oai(default))]
+    pub mirrors: Vec<url::Url>,
+
+    /// Patches from earlier releases, used instead of downloading the whole payload
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[builder(default)]
+    #[cfg_attr(feature = "structopt", structopt(skip))]
+    #[cfg_attr(feature = "poem-openapi", oai(default))]
+    pub deltas: Vec<super::delta::Delta>,
+
+    /// The name dpkg knows the package by, as in the `Package` field of its control file
+    #[cfg_attr(feature = "structopt", structopt(short, long))]
+    pub package: String,
+
+    #[cfg_attr(feature = "structopt", structopt(short, long))]
+    pub size: u64,
+
+    #[cfg_attr(feature = "structopt", structopt(short, long))]
+    pub installed_size: u64,
+}
+
+impl super::AsDownloadUrl for Package {
+    fn as_download_url(&self) -> &url::Url {
+        &self.url
+    }
+}
+
+/// Whether `name` follows Debian policy for package names: at least two characters of
+/// lowercase letters, digits, `+`, `-` and `.`, starting with a letter or digit.
+pub fn is_valid_name(name: &str) -> bool {
+    name.len() >= 2
+        && name.starts_with(|x: char| x.is_ascii_lowercase() || x.is_ascii_digit())
+        && name
+            .chars()
+            .all(|x| x.is_ascii_lowercase() || x.is_ascii_digit() || matches!(x, '+' | '-' | '.'))
+}
+
+/// The upstream part of a Debian version, without its epoch and Debian revision, for
+/// comparing with the version of a release.
+///
+/// A trailing `-` part is only taken as a revision if it starts with a digit, so that
+/// a prerelease such as `1.0.0-beta.1` is kept whole.
+pub fn upstream_version(version: &str) -> &str {
+    let version = match version.split_once(':') {
+        Some((epoch, rest)) if epoch.chars().all(|x| x.is_ascii_digit()) => rest,
+        _ => version,
+    };
+
+    match version.rsplit_once('-') {
+        Some((upstream, revision)) if revision.starts_with(|x: char| x.is_ascii_digit()) => {
+            upstream
+        }
+        _ => version,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        assert!(is_valid_name("divvun-speller-sme"));
+        assert!(is_valid_name("libhfst55"));
+        assert!(!is_valid_name("-oAPT::Get=x"));
+        assert!(!is_valid_name("Speller"));
+        assert!(!is_valid_name("s"));
+    }
+
+    #[test]
+    fn upstream() {
+        assert_eq!(upstream_version("1.2.3"), "1.2.3");
+        assert_eq!(upstream_version("1:1.2.3-0ubuntu2"), "1.2.3");
+        assert_eq!(upstream_version("1.2.3-1"), "1.2.3");
+        assert_eq!(upstream_version("1.0.0-beta.1"), "1.0.0-beta.1");
+        assert_eq!(upstream_version("1.0.0-beta.1-2"), "1.0.0-beta.1");
+    }
+}
@@ -0,0 +1,47 @@
+use serde::{Deserialize, Serialize};
+use typed_builder::TypedBuilder;
+
+#[derive(
+    Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord,
+)]
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+#[cfg_attr(feature = "poem-openapi", derive(poem_openapi::Enum))]
+#[cfg_attr(
+    feature = "poem-openapi",
+    oai(rename = "DeltaFormat", rename_all = "lowercase")
+)]
+#[cfg_attr(feature = "async-graphql", derive(async_graphql::Enum))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub enum DeltaFormat {
+    /// A patch as written by the `bsdiff` crate.
+    #[default]
+    Bsdiff,
+}
+
+/// A patch that turns the payload of an earlier release into this payload, so that an
+/// update only downloads what changed.
+#[derive(
+    Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, TypedBuilder,
+)]
+#[cfg_attr(feature = "poem-openapi", derive(poem_openapi::Object))]
+#[cfg_attr(feature = "poem-openapi", oai(rename = "PayloadDelta"))]
+#[cfg_attr(feature = "async-graphql", derive(async_graphql::SimpleObject))]
+#[cfg_attr(feature = "async-graphql", graphql(name = "PayloadDelta"))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "schemars", schemars(rename = "PayloadDelta"))]
+pub struct Delta {
+    /// Version of the release whose payload, for the same target, is patched
+    pub from_version: String,
+    pub url: url::Url,
+    pub size: u64,
+    #[serde(default)]
+    #[builder(default)]
+    #[cfg_attr(feature = "poem-openapi", oai(default))]
+    pub format: DeltaFormat,
+    /// SHA-256 of the payload the patch produces, hex encoded. Clients only use
+    /// deltas that have one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub payload_sha256: Option<String>,
+}
@@ -0,0 +1,87 @@
+use serde::{Deserialize, Serialize};
+use typed_builder::TypedBuilder;
+
+/// An application or runtime installed by flatpak from a remote, rather than from a
+/// file downloaded by Pahkat.
+#[derive(
+    Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, TypedBuilder,
+)]
+#[cfg_attr(feature = "structopt", derive(structopt::StructOpt))]
+#[cfg_attr(feature = "poem-openapi", derive(poem_openapi::Object))]
+#[cfg_attr(feature = "poem-openapi", oai(rename = "Flatpak"))]
+#[cfg_attr(feature = "async-graphql", derive(async_graphql::SimpleObject))]
+#[cfg_attr(feature = "async-graphql", graphql(name = "Flatpak"))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "schemars", schemars(rename = "Flatpak"))]
+pub struct Package {
+    /// Location of the flatpak repository, added as `remote` if it is not already
+    #[cfg_attr(feature = "structopt", structopt(short, long))]
+    pub url: url::Url,
+
+    /// Name of the remote, such as `flathub`
+    #[cfg_attr(feature = "structopt", structopt(short, long))]
+    pub remote: String,
+
+    /// Ref to install, either in full as `app/org.example.App/x86_64/stable` or only
+    /// its name
+    #[serde(rename = "ref")]
+    #[cfg_attr(feature = "structopt", structopt(long = "ref"))]
+    #[cfg_attr(feature = "poem-openapi", oai(rename = "ref"))]
+    #[cfg_attr(feature = "async-graphql", graphql(name = "ref"))]
+    pub flatpak_ref: String,
+
+    #[cfg_attr(feature = "structopt", structopt(short, long))]
+    pub size: u64,
+
+    #[cfg_attr(feature = "structopt", structopt(short, long))]
+    pub installed_size: u64,
+}
+
+impl super::AsDownloadUrl for Package {
+    fn as_download_url(&self) -> &url::Url {
+        &self.url
+    }
+}
+
+fn is_valid_segment(segment: &str) -> bool {
+    segment.starts_with(|x: char| x.is_ascii_alphanumeric() || x == '_')
+        && segment
+            .chars()
+            .all(|x| x.is_ascii_alphanumeric() || matches!(x, '.' | '_' | '-'))
+}
+
+/// Whether `name` can be the name of a remote given to flatpak.
+pub fn is_valid_remote(name: &str) -> bool {
+    is_valid_segment(name)
+}
+
+/// Whether `flatpak_ref` is a name or a partial or full ref, with each part usable
+/// as an argument to flatpak. A full ref starts with `app` or `runtime`.
+pub fn is_valid_ref(flatpak_ref: &str) -> bool {
+    let parts = flatpak_ref.split('/').collect::<Vec<_>>();
+    let is_kind_valid = match parts.len() {
+        1..=3 => true,
+        4 => matches!(parts[0], "app" | "runtime"),
+        _ => false,
+    };
+    is_kind_valid && parts.iter().all(|x| is_valid_segment(x))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refs() {
+        assert!(is_valid_ref("org.divvun.Manager"));
+        assert!(is_valid_ref("app/org.divvun.Manager/x86_64/stable"));
+        assert!(is_valid_ref(
+            "runtime/org.freedesktop.Platform/x86_64/23.08"
+        ));
+        assert!(!is_valid_ref("lib/org.divvun.Manager/x86_64/stable"));
+        assert!(!is_valid_ref("--system"));
+        assert!(!is_valid_ref("app//x86_64/stable"));
+        assert!(is_valid_remote("flathub"));
+        assert!(!is_valid_remote("-flathub"));
+    }
+}
@@ -17,6 +17,8 @@ use super::parse_set;
 )]
 #[cfg_attr(feature = "async-graphql", derive(async_graphql::Enum))]
 #[cfg_attr(feature = "async-graphql", graphql(name = "MacOSRebootSpec"))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "schemars", schemars(rename = "MacOSRebootSpec"))]
 pub enum RebootSpec {
     Install,
     Uninstall,
@@ -48,10 +50,26 @@ impl FromStr for RebootSpec {
 #[cfg_attr(feature = "poem-openapi", oai(rename = "MacOSPackage"))]
 #[cfg_attr(feature = "async-graphql", derive(async_graphql::SimpleObject))]
 #[cfg_attr(feature = "async-graphql", graphql(name = "MacOSPackage"))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "schemars", schemars(rename = "MacOSPackage"))]
 pub struct Package {
     #[cfg_attr(feature = "structopt", structopt(short, long))]
     pub url: url::Url,
 
+    /// Alternative locations of the same file, tried in order if `url` fails
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[builder(default)]
+    #[cfg_attr(feature = "structopt", structopt(long = "mirror"))]
+    #[cfg_attr(feature = "poem-openapi", oai(default))]
+    pub mirrors: Vec<url::Url>,
+
+    /// Patches from earlier releases, used instead of downloading the whole payload
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[builder(default)]
+    #[cfg_attr(feature = "structopt", structopt(skip))]
+    #[cfg_attr(feature = "poem-openapi", oai(default))]
+    pub deltas: Vec<super::delta::Delta>,
+
     #[cfg_attr(feature = "structopt", structopt(short, long))]
     pub pkg_id: String,
 
@@ -72,6 +90,94 @@ pub struct Package {
 
     #[cfg_attr(feature = "structopt", structopt(short, long))]
     pub installed_size: u64,
+
+    /// Expected Developer ID team identifier; unsigned or differently signed packages are rejected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    #[cfg_attr(feature = "structopt", structopt(long))]
+    pub team_id: Option<String>,
+
+    /// Choice changes XML for `installer -applyChoiceChangesXML`, either inline
+    /// or as an https URL to download it from alongside the package
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    #[cfg_attr(feature = "structopt", structopt(long))]
+    pub choice_changes: Option<String>,
+
+    /// SHA-256 of the file at `choice_changes`, required if it is a URL
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    #[cfg_attr(feature = "structopt", structopt(long))]
+    pub choice_changes_sha256: Option<String>,
+
+    /// As `choice_changes`, but used instead when installing for the current user
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    #[cfg_attr(feature = "structopt", structopt(long))]
+    pub user_choice_changes: Option<String>,
+
+    /// SHA-256 of the file at `user_choice_changes`, required if it is a URL
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    #[cfg_attr(feature = "structopt", structopt(long))]
+    pub user_choice_changes_sha256: Option<String>,
+
+    /// Integration with the OS after installing, such as enabling an input source
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[builder(default)]
+    #[cfg_attr(feature = "structopt", structopt(long = "action"))]
+    #[cfg_attr(feature = "poem-openapi", oai(default))]
+    pub actions: Vec<super::Action>,
+
+    /// Options the user may choose when installing
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[builder(default)]
+    #[cfg_attr(feature = "structopt", structopt(skip))]
+    #[cfg_attr(feature = "poem-openapi", oai(default))]
+    pub options: Vec<super::InstallOption>,
+}
+
+impl Package {
+    /// The choice changes to apply for the given target and their checksum,
+    /// falling back to the system variant for user installs.
+    pub fn choice_changes_for(&self, target: InstallTarget) -> Option<(&str, Option<&str>)> {
+        let system = || {
+            self.choice_changes
+                .as_deref()
+                .map(|x| (x, self.choice_changes_sha256.as_deref()))
+        };
+        match target {
+            InstallTarget::User => self
+                .user_choice_changes
+                .as_deref()
+                .map(|x| (x, self.user_choice_changes_sha256.as_deref()))
+                .or_else(system),
+            InstallTarget::System => system(),
+        }
+    }
+
+    /// The URLs of any choice changes that must be downloaded before installing.
+    pub fn choice_changes_urls(&self) -> Vec<url::Url> {
+        self.choice_changes
+            .iter()
+            .chain(self.user_choice_changes.iter())
+            .filter_map(|x| choice_changes_url(x))
+            .collect()
+    }
+}
+
+/// Returns the URL if the choice changes value refers to a remote file. Only https
+/// is accepted, as the choices decide what the installer runs.
+pub fn choice_changes_url(value: &str) -> Option<url::Url> {
+    if !value.starts_with("https://") {
+        return None;
+    }
+    url::Url::parse(value).ok()
+}
+
+/// Whether the choice changes value is the XML itself rather than a URL.
+pub fn is_inline_choice_changes(value: &str) -> bool {
+    value.trim_start().starts_with('<')
 }
 
 impl super::AsDownloadUrl for Package {
@@ -80,11 +186,81 @@ impl super::AsDownloadUrl for Package {
     }
 }
 
+/// A zipped `.app` bundle, installed by copying it into `/Applications`, or
+/// `~/Applications` for user installs.
+#[derive(
+    Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, TypedBuilder,
+)]
+#[cfg_attr(feature = "structopt", derive(structopt::StructOpt))]
+#[cfg_attr(feature = "poem-openapi", derive(poem_openapi::Object))]
+#[cfg_attr(feature = "poem-openapi", oai(rename = "MacOSAppBundle"))]
+#[cfg_attr(feature = "async-graphql", derive(async_graphql::SimpleObject))]
+#[cfg_attr(feature = "async-graphql", graphql(name = "MacOSAppBundle"))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "schemars", schemars(rename = "MacOSAppBundle"))]
+pub struct AppBundle {
+    #[cfg_attr(feature = "structopt", structopt(short, long))]
+    pub url: url::Url,
+
+    /// Alternative locations of the same file, tried in order if `url` fails
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[builder(default)]
+    #[cfg_attr(feature = "structopt", structopt(long = "mirror"))]
+    #[cfg_attr(feature = "poem-openapi", oai(default))]
+    pub mirrors: Vec<url::Url>,
+
+    /// Patches from earlier releases, used instead of downloading the whole payload
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[builder(default)]
+    #[cfg_attr(feature = "structopt", structopt(skip))]
+    #[cfg_attr(feature = "poem-openapi", oai(default))]
+    pub deltas: Vec<super::delta::Delta>,
+
+    /// The `CFBundleIdentifier` of the bundle
+    #[cfg_attr(feature = "structopt", structopt(short, long))]
+    pub bundle_id: String,
+
+    /// The name of the bundle in the archive and once installed, such as
+    /// `Divvun Manager.app`
+    #[cfg_attr(feature = "structopt", structopt(short = "n", long))]
+    pub app_name: String,
+
+    #[cfg_attr(feature = "structopt", structopt(short, long))]
+    pub size: u64,
+
+    #[cfg_attr(feature = "structopt", structopt(short, long))]
+    pub installed_size: u64,
+
+    /// Integration with the OS after installing, such as enabling an input source
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[builder(default)]
+    #[cfg_attr(feature = "structopt", structopt(long = "action"))]
+    #[cfg_attr(feature = "poem-openapi", oai(default))]
+    pub actions: Vec<super::Action>,
+}
+
+impl super::AsDownloadUrl for AppBundle {
+    fn as_download_url(&self) -> &url::Url {
+        &self.url
+    }
+}
+
+/// Whether `name` is the file name of an app bundle, and so safe to join to the
+/// applications directory.
+pub fn is_valid_app_name(name: &str) -> bool {
+    name.len() > ".app".len()
+        && name.ends_with(".app")
+        && !name.starts_with('.')
+        && !name.contains(['/', ':', '\0'])
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Copy, Hash, Eq)]
 #[serde(rename_all = "lowercase")]
 #[cfg_attr(feature = "poem-openapi", derive(poem_openapi::Enum))]
 #[cfg_attr(feature = "poem-openapi", oai(rename_all = "lowercase"))]
 #[cfg_attr(feature = "async-graphql", derive(async_graphql::Enum))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "schemars", schemars(rename = "MacOSInstallTarget"))]
 pub enum InstallTarget {
     System,
     User,
@@ -147,3 +323,17 @@ impl Ord for InstallTarget {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn app_names() {
+        assert!(is_valid_app_name("Divvun Manager.app"));
+        assert!(!is_valid_app_name(".app"));
+        assert!(!is_valid_app_name("Divvun Manager"));
+        assert!(!is_valid_app_name("../Divvun Manager.app"));
+        assert!(!is_valid_app_name(".hidden.app"));
+    }
+}
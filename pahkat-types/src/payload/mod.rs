@@ -1,11 +1,18 @@
+pub mod arch;
+pub mod debian;
+pub mod delta;
+pub mod flatpak;
 pub mod macos;
+pub mod rpm;
+pub mod snap;
 pub mod tarball;
 pub mod windows;
 
+use std::collections::BTreeMap;
 #[cfg(feature = "structopt")]
 use std::collections::BTreeSet;
 use std::convert::TryFrom;
-#[cfg(feature = "structopt")]
+use std::fmt;
 use std::str::FromStr;
 
 use crate::DependencyMap;
@@ -30,14 +37,16 @@ pub(crate) fn parse_dep_map(s: &str) -> Result<DependencyMap, &'static str> {
         return Ok(map);
     }
 
-    s.split(",")
-        .map(|x| {
-            let v = x.split("::").collect::<Vec<_>>();
-            (v[0].to_string(), v[1].to_string())
-        })
-        .for_each(|(k, v)| {
-            map.insert(k.into(), v);
-        });
+    for pair in s.split(",") {
+        let mut it = pair.splitn(2, "::");
+        let key = it.next().unwrap().trim();
+        let value = it
+            .next()
+            .unwrap_or("*")
+            .parse()
+            .map_err(|_| "Invalid version requirement")?;
+        map.insert(key.into(), value);
+    }
 
     Ok(map)
 }
@@ -49,27 +58,47 @@ pub(crate) fn parse_dep_map(s: &str) -> Result<DependencyMap, &'static str> {
 #[cfg_attr(feature = "poem-openapi", derive(poem_openapi::Union))]
 #[cfg_attr(feature = "poem-openapi", oai(discriminator_name = "type"))]
 #[cfg_attr(feature = "async-graphql", derive(async_graphql::Union))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub enum Payload {
     WindowsExecutable(windows::Executable),
+    WindowsMsix(windows::Msix),
     #[cfg_attr(feature = "structopt", structopt(name = "macos-package"))]
     MacOSPackage(macos::Package),
+    #[cfg_attr(feature = "structopt", structopt(name = "macos-app-bundle"))]
+    MacOSAppBundle(macos::AppBundle),
     TarballPackage(tarball::Package),
+    DebianPackage(debian::Package),
+    RpmPackage(rpm::Package),
+    Flatpak(flatpak::Package),
+    Snap(snap::Package),
 }
 
 impl Payload {
     pub fn size(&self) -> u64 {
         match self {
             Payload::WindowsExecutable(x) => x.size,
+            Payload::WindowsMsix(x) => x.size,
             Payload::MacOSPackage(x) => x.size,
+            Payload::MacOSAppBundle(x) => x.size,
             Payload::TarballPackage(x) => x.size,
+            Payload::DebianPackage(x) => x.size,
+            Payload::RpmPackage(x) => x.size,
+            Payload::Flatpak(x) => x.size,
+            Payload::Snap(x) => x.size,
         }
     }
 
     pub fn installed_size(&self) -> u64 {
         match self {
             Payload::WindowsExecutable(x) => x.installed_size,
+            Payload::WindowsMsix(x) => x.installed_size,
             Payload::MacOSPackage(x) => x.installed_size,
+            Payload::MacOSAppBundle(x) => x.installed_size,
             Payload::TarballPackage(x) => x.installed_size,
+            Payload::DebianPackage(x) => x.installed_size,
+            Payload::RpmPackage(x) => x.installed_size,
+            Payload::Flatpak(x) => x.installed_size,
+            Payload::Snap(x) => x.installed_size,
         }
     }
 
@@ -78,20 +107,80 @@ impl Payload {
             Payload::WindowsExecutable(x) => {
                 x.url = url;
             }
+            Payload::WindowsMsix(x) => {
+                x.url = url;
+            }
             Payload::MacOSPackage(x) => {
                 x.url = url;
             }
+            Payload::MacOSAppBundle(x) => {
+                x.url = url;
+            }
             Payload::TarballPackage(x) => {
                 x.url = url;
             }
+            Payload::DebianPackage(x) => {
+                x.url = url;
+            }
+            Payload::RpmPackage(x) => {
+                x.url = url;
+            }
+            Payload::Flatpak(x) => {
+                x.url = url;
+            }
+            Payload::Snap(x) => {
+                x.url = url;
+            }
         }
     }
 
     pub fn url(&self) -> &url::Url {
         match self {
             Payload::WindowsExecutable(x) => &x.url,
+            Payload::WindowsMsix(x) => &x.url,
             Payload::MacOSPackage(x) => &x.url,
+            Payload::MacOSAppBundle(x) => &x.url,
             Payload::TarballPackage(x) => &x.url,
+            Payload::DebianPackage(x) => &x.url,
+            Payload::RpmPackage(x) => &x.url,
+            Payload::Flatpak(x) => &x.url,
+            Payload::Snap(x) => &x.url,
+        }
+    }
+
+    pub fn mirrors(&self) -> &[url::Url] {
+        match self {
+            Payload::WindowsExecutable(x) => &x.mirrors,
+            Payload::WindowsMsix(x) => &x.mirrors,
+            Payload::MacOSPackage(x) => &x.mirrors,
+            Payload::MacOSAppBundle(x) => &x.mirrors,
+            Payload::TarballPackage(x) => &x.mirrors,
+            Payload::DebianPackage(x) => &x.mirrors,
+            Payload::RpmPackage(x) => &x.mirrors,
+            Payload::Flatpak(_) | Payload::Snap(_) => &[],
+        }
+    }
+
+    pub fn deltas(&self) -> &[delta::Delta] {
+        match self {
+            Payload::WindowsExecutable(x) => &x.deltas,
+            Payload::WindowsMsix(x) => &x.deltas,
+            Payload::MacOSPackage(x) => &x.deltas,
+            Payload::MacOSAppBundle(x) => &x.deltas,
+            Payload::TarballPackage(x) => &x.deltas,
+            Payload::DebianPackage(x) => &x.deltas,
+            Payload::RpmPackage(x) => &x.deltas,
+            Payload::Flatpak(_) | Payload::Snap(_) => &[],
+        }
+    }
+
+    /// Options the user may choose when installing, for payloads that support them.
+    pub fn install_options(&self) -> &[InstallOption] {
+        match self {
+            Payload::WindowsExecutable(x) => &x.options,
+            Payload::MacOSPackage(x) => &x.options,
+            Payload::TarballPackage(x) => &x.options,
+            _ => &[],
         }
     }
 }
@@ -103,6 +192,8 @@ impl Payload {
 #[cfg_attr(feature = "poem-openapi", derive(poem_openapi::Object))]
 #[cfg_attr(feature = "async-graphql", derive(async_graphql::SimpleObject))]
 #[cfg_attr(feature = "async-graphql", graphql(name = "PayloadTarget"))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "schemars", schemars(rename = "PayloadTarget"))]
 pub struct Target {
     #[cfg_attr(feature = "structopt", structopt(short, long))]
     pub platform: String,
@@ -119,6 +210,145 @@ pub struct Target {
     pub payload: Payload,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+#[cfg_attr(feature = "poem-openapi", derive(poem_openapi::Enum))]
+#[cfg_attr(feature = "poem-openapi", oai(rename_all = "kebab-case"))]
+#[cfg_attr(feature = "async-graphql", derive(async_graphql::Enum))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub enum ActionKind {
+    /// Enables a keyboard layout or input method. The ID is an input source ID
+    /// on macOS (`com.apple.keylayout.X`) and a profile as accepted by
+    /// `InstallLayoutOrTip` on Windows (`0409:00000409` or `0409:{CLSID}{GUID}`).
+    /// Input sources are enabled per user, so when installing from the system
+    /// service this is left to the clients in each user session.
+    EnableInputSource,
+    /// Makes a newly installed spell checking service available. The ID is the
+    /// service bundle identifier on macOS.
+    RegisterSpellService,
+}
+
+impl ActionKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ActionKind::EnableInputSource => "enable-input-source",
+            ActionKind::RegisterSpellService => "register-spell-service",
+        }
+    }
+}
+
+/// Integration with the OS performed after a payload is installed, so that for
+/// example a keyboard can be used without running a separate tool.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "poem-openapi", derive(poem_openapi::Object))]
+#[cfg_attr(feature = "poem-openapi", oai(rename = "PayloadAction"))]
+#[cfg_attr(feature = "async-graphql", derive(async_graphql::SimpleObject))]
+#[cfg_attr(feature = "async-graphql", graphql(name = "PayloadAction"))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "schemars", schemars(rename = "PayloadAction"))]
+pub struct Action {
+    pub kind: ActionKind,
+    pub id: String,
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("Expected `enable-input-source:ID` or `register-spell-service:ID`")]
+pub struct ParseActionError;
+
+/// Parses the `kind:id` form used on the command line and in the index.
+impl FromStr for Action {
+    type Err = ParseActionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut it = s.splitn(2, ':');
+        let kind = match it.next() {
+            Some("enable-input-source") => ActionKind::EnableInputSource,
+            Some("register-spell-service") => ActionKind::RegisterSpellService,
+            _ => return Err(ParseActionError),
+        };
+        match it.next() {
+            Some(id) if !id.is_empty() => Ok(Action {
+                kind,
+                id: id.to_string(),
+            }),
+            _ => Err(ParseActionError),
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.kind.as_str(), self.id)
+    }
+}
+
+/// An optional part of a payload chosen when installing, such as an extra layout of
+/// a keyboard. The chosen value is applied as an MSI public property named `id` by
+/// Windows executables, and selects (`1`) or deselects (`0`) the choice `id` of macOS
+/// packages. The values of a tarball option name top-level directories of the
+/// archive, of which only the chosen one is extracted.
+#[derive(
+    Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, TypedBuilder,
+)]
+#[cfg_attr(feature = "poem-openapi", derive(poem_openapi::Object))]
+#[cfg_attr(feature = "async-graphql", derive(async_graphql::SimpleObject))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct InstallOption {
+    pub id: String,
+
+    /// The values that may be chosen; any value if empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[builder(default)]
+    #[cfg_attr(feature = "poem-openapi", oai(default))]
+    pub values: Vec<String>,
+
+    /// Used when no value is chosen. Without a default, an option that is not chosen
+    /// is left out.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub default: Option<String>,
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum InstallOptionError {
+    #[error("Unknown install option `{0}`")]
+    Unknown(String),
+
+    #[error("`{value}` is not an allowed value of install option `{id}`")]
+    Value { id: String, value: String },
+}
+
+/// The value of each option in `declared`, as chosen or by default. Options that
+/// are not declared, or values that are not allowed, are refused.
+pub fn resolve_install_options(
+    declared: &[InstallOption],
+    chosen: &BTreeMap<String, String>,
+) -> Result<BTreeMap<String, String>, InstallOptionError> {
+    if let Some(id) = chosen
+        .keys()
+        .find(|k| !declared.iter().any(|x| &x.id == *k))
+    {
+        return Err(InstallOptionError::Unknown(id.clone()));
+    }
+
+    let mut out = BTreeMap::new();
+    for option in declared {
+        let value = match chosen.get(&option.id).or(option.default.as_ref()) {
+            Some(v) => v,
+            None => continue,
+        };
+        if !option.values.is_empty() && !option.values.contains(value) {
+            return Err(InstallOptionError::Value {
+                id: option.id.clone(),
+                value: value.clone(),
+            });
+        }
+        out.insert(option.id.clone(), value.clone());
+    }
+    Ok(out)
+}
+
 pub trait AsDownloadUrl {
     fn as_download_url(&self) -> &url::Url;
 }
@@ -128,8 +358,14 @@ impl AsDownloadUrl for Payload {
         use Payload::*;
         match self {
             WindowsExecutable(p) => p.as_download_url(),
+            WindowsMsix(p) => p.as_download_url(),
             MacOSPackage(p) => p.as_download_url(),
+            MacOSAppBundle(p) => p.as_download_url(),
             TarballPackage(p) => p.as_download_url(),
+            DebianPackage(p) => p.as_download_url(),
+            RpmPackage(p) => p.as_download_url(),
+            Flatpak(p) => p.as_download_url(),
+            Snap(p) => p.as_download_url(),
         }
     }
 }
@@ -199,3 +435,178 @@ impl<'a> TryFrom<&'a Payload> for &'a tarball::Package {
         }
     }
 }
+
+impl TryFrom<Payload> for debian::Package {
+    type Error = Payload;
+
+    fn try_from(value: Payload) -> Result<Self, Self::Error> {
+        match value {
+            Payload::DebianPackage(v) => Ok(v),
+            x => Err(x),
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a Payload> for &'a debian::Package {
+    type Error = &'a Payload;
+
+    fn try_from(value: &'a Payload) -> Result<Self, Self::Error> {
+        match value {
+            Payload::DebianPackage(v) => Ok(v),
+            x => Err(x),
+        }
+    }
+}
+
+impl TryFrom<Payload> for rpm::Package {
+    type Error = Payload;
+
+    fn try_from(value: Payload) -> Result<Self, Self::Error> {
+        match value {
+            Payload::RpmPackage(v) => Ok(v),
+            x => Err(x),
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a Payload> for &'a rpm::Package {
+    type Error = &'a Payload;
+
+    fn try_from(value: &'a Payload) -> Result<Self, Self::Error> {
+        match value {
+            Payload::RpmPackage(v) => Ok(v),
+            x => Err(x),
+        }
+    }
+}
+
+impl TryFrom<Payload> for flatpak::Package {
+    type Error = Payload;
+
+    fn try_from(value: Payload) -> Result<Self, Self::Error> {
+        match value {
+            Payload::Flatpak(v) => Ok(v),
+            x => Err(x),
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a Payload> for &'a flatpak::Package {
+    type Error = &'a Payload;
+
+    fn try_from(value: &'a Payload) -> Result<Self, Self::Error> {
+        match value {
+            Payload::Flatpak(v) => Ok(v),
+            x => Err(x),
+        }
+    }
+}
+
+impl TryFrom<Payload> for windows::Msix {
+    type Error = Payload;
+
+    fn try_from(value: Payload) -> Result<Self, Self::Error> {
+        match value {
+            Payload::WindowsMsix(v) => Ok(v),
+            x => Err(x),
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a Payload> for &'a windows::Msix {
+    type Error = &'a Payload;
+
+    fn try_from(value: &'a Payload) -> Result<Self, Self::Error> {
+        match value {
+            Payload::WindowsMsix(v) => Ok(v),
+            x => Err(x),
+        }
+    }
+}
+
+impl TryFrom<Payload> for macos::AppBundle {
+    type Error = Payload;
+
+    fn try_from(value: Payload) -> Result<Self, Self::Error> {
+        match value {
+            Payload::MacOSAppBundle(v) => Ok(v),
+            x => Err(x),
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a Payload> for &'a macos::AppBundle {
+    type Error = &'a Payload;
+
+    fn try_from(value: &'a Payload) -> Result<Self, Self::Error> {
+        match value {
+            Payload::MacOSAppBundle(v) => Ok(v),
+            x => Err(x),
+        }
+    }
+}
+
+impl TryFrom<Payload> for snap::Package {
+    type Error = Payload;
+
+    fn try_from(value: Payload) -> Result<Self, Self::Error> {
+        match value {
+            Payload::Snap(v) => Ok(v),
+            x => Err(x),
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a Payload> for &'a snap::Package {
+    type Error = &'a Payload;
+
+    fn try_from(value: &'a Payload) -> Result<Self, Self::Error> {
+        match value {
+            Payload::Snap(v) => Ok(v),
+            x => Err(x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn install_options() {
+        let declared = vec![
+            InstallOption::builder()
+                .id("LAYOUT".into())
+                .values(vec!["sme".into(), "sma".into()])
+                .default(Some("sme".into()))
+                .build(),
+            InstallOption::builder().id("SPELLER".into()).build(),
+        ];
+        let chosen = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<BTreeMap<_, _>>()
+        };
+
+        assert_eq!(
+            resolve_install_options(&declared, &chosen(&[])),
+            Ok(chosen(&[("LAYOUT", "sme")]))
+        );
+        assert_eq!(
+            resolve_install_options(&declared, &chosen(&[("LAYOUT", "sma"), ("SPELLER", "1")])),
+            Ok(chosen(&[("LAYOUT", "sma"), ("SPELLER", "1")]))
+        );
+        assert_eq!(
+            resolve_install_options(&declared, &chosen(&[("LAYOUT", "smj")])),
+            Err(InstallOptionError::Value {
+                id: "LAYOUT".into(),
+                value: "smj".into()
+            })
+        );
+        assert_eq!(
+            resolve_install_options(&declared, &chosen(&[("THEME", "dark")])),
+            Err(InstallOptionError::Unknown("THEME".into()))
+        );
+    }
+}
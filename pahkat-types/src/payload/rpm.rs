@@ -0,0 +1,70 @@
+use serde::{Deserialize, Serialize};
+use typed_builder::TypedBuilder;
+
+#[derive(
+    Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, TypedBuilder,
+)]
+#[cfg_attr(feature = "structopt", derive(structopt::StructOpt))]
+#[cfg_attr(feature = "poem-openapi", derive(poem_openapi::Object))]
+#[cfg_attr(feature = "poem-openapi", oai(rename = "RpmPackage"))]
+#[cfg_attr(feature = "async-graphql", derive(async_graphql::SimpleObject))]
+#[cfg_attr(feature = "async-graphql", graphql(name = "RpmPackage"))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "schemars", schemars(rename = "RpmPackage"))]
+pub struct Package {
+    #[cfg_attr(feature = "structopt", structopt(short, long))]
+    pub url: url::Url,
+
+    /// Alternative locations of the same file, tried in order if `url` fails
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[builder(default)]
+    #[cfg_attr(feature = "structopt", structopt(long = "mirror"))]
+    #[cfg_attr(feature = "poem-openapi", oai(default))]
+    pub mirrors: Vec<url::Url>,
+
+    /// Patches from earlier releases, used instead of downloading the whole payload
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[builder(default)]
+    #[cfg_attr(feature = "structopt", structopt(skip))]
+    #[cfg_attr(feature = "poem-openapi", oai(default))]
+    pub deltas: Vec<super::delta::Delta>,
+
+    /// The name rpm knows the package by, as in the `Name` tag of its spec file
+    #[cfg_attr(feature = "structopt", structopt(short, long))]
+    pub package: String,
+
+    #[cfg_attr(feature = "structopt", structopt(short, long))]
+    pub size: u64,
+
+    #[cfg_attr(feature = "structopt", structopt(short, long))]
+    pub installed_size: u64,
+}
+
+impl super::AsDownloadUrl for Package {
+    fn as_download_url(&self) -> &url::Url {
+        &self.url
+    }
+}
+
+/// Whether `name` can be an RPM package name: letters, digits, `+`, `-`, `.` and `_`,
+/// starting with a letter or digit.
+pub fn is_valid_name(name: &str) -> bool {
+    name.starts_with(|x: char| x.is_ascii_alphanumeric())
+        && name
+            .chars()
+            .all(|x| x.is_ascii_alphanumeric() || matches!(x, '+' | '-' | '.' | '_'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        assert!(is_valid_name("divvun-speller-sme"));
+        assert!(is_valid_name("NetworkManager"));
+        assert!(is_valid_name("python3.11_tools"));
+        assert!(!is_valid_name("--nodeps"));
+        assert!(!is_valid_name(""));
+    }
+}
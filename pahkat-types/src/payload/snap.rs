@@ -0,0 +1,131 @@
+use serde::{Deserialize, Serialize};
+use typed_builder::TypedBuilder;
+
+/// A snap installed by snapd from the Snap Store. Snaps are always installed
+/// system wide.
+#[derive(
+    Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, TypedBuilder,
+)]
+#[cfg_attr(feature = "structopt", derive(structopt::StructOpt))]
+#[cfg_attr(feature = "poem-openapi", derive(poem_openapi::Object))]
+#[cfg_attr(feature = "poem-openapi", oai(rename = "Snap"))]
+#[cfg_attr(feature = "async-graphql", derive(async_graphql::SimpleObject))]
+#[cfg_attr(feature = "async-graphql", graphql(name = "Snap"))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "schemars", schemars(rename = "Snap"))]
+pub struct Package {
+    /// Store page of the snap, such as `https://snapcraft.io/divvun-manager`
+    #[cfg_attr(feature = "structopt", structopt(short, long))]
+    pub url: url::Url,
+
+    #[cfg_attr(feature = "structopt", structopt(short, long))]
+    pub name: String,
+
+    /// Snap channel to track, such as `2.x/stable`. Without one, the risk level
+    /// is taken from the channel of the release.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    #[cfg_attr(feature = "structopt", structopt(long))]
+    pub channel: Option<String>,
+
+    /// Whether the snap uses classic confinement
+    #[serde(default)]
+    #[builder(default)]
+    #[cfg_attr(feature = "structopt", structopt(long))]
+    pub classic: bool,
+
+    #[cfg_attr(feature = "structopt", structopt(short, long))]
+    pub size: u64,
+
+    #[cfg_attr(feature = "structopt", structopt(short, long))]
+    pub installed_size: u64,
+}
+
+impl Package {
+    /// The snap channel to install from for a release in the pahkat `channel`.
+    pub fn channel_for(&self, channel: Option<&str>) -> String {
+        match self.channel.as_ref() {
+            Some(v) => v.clone(),
+            None => risk_level(channel).to_string(),
+        }
+    }
+}
+
+impl super::AsDownloadUrl for Package {
+    fn as_download_url(&self) -> &url::Url {
+        &self.url
+    }
+}
+
+/// The snap risk level closest to a pahkat channel. Releases without a channel
+/// are stable, and channels snapd does not know are taken as `edge`.
+pub fn risk_level(channel: Option<&str>) -> &'static str {
+    match channel {
+        None | Some("stable") => "stable",
+        Some("candidate") | Some("rc") => "candidate",
+        Some("beta") => "beta",
+        Some(_) => "edge",
+    }
+}
+
+/// Whether `name` is a snap name: lowercase letters, digits and single hyphens,
+/// with at least one letter and no hyphen at either end.
+pub fn is_valid_name(name: &str) -> bool {
+    (2..=40).contains(&name.len())
+        && name
+            .chars()
+            .all(|x| x.is_ascii_lowercase() || x.is_ascii_digit() || x == '-')
+        && name.chars().any(|x| x.is_ascii_lowercase())
+        && !name.starts_with('-')
+        && !name.ends_with('-')
+        && !name.contains("--")
+}
+
+fn is_risk_level(value: &str) -> bool {
+    matches!(value, "stable" | "candidate" | "beta" | "edge")
+}
+
+/// Whether `channel` is a snap channel, `[track/]risk[/branch]`, usable as an
+/// argument to snap.
+pub fn is_valid_channel(channel: &str) -> bool {
+    let parts = channel.split('/').collect::<Vec<_>>();
+    let risk = match parts.as_slice() {
+        [risk] => risk,
+        [_, risk] if is_risk_level(risk) => risk,
+        [risk, _] => risk,
+        [_, risk, _] => risk,
+        _ => return false,
+    };
+
+    is_risk_level(risk)
+        && !channel.starts_with('-')
+        && parts.iter().all(|x| {
+            !x.is_empty()
+                && x.chars()
+                    .all(|x| x.is_ascii_alphanumeric() || matches!(x, '.' | '_' | '-'))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_and_channels() {
+        assert!(is_valid_name("divvun-manager"));
+        assert!(!is_valid_name("Divvun"));
+        assert!(!is_valid_name("-divvun"));
+        assert!(!is_valid_name("divvun--manager"));
+        assert!(!is_valid_name("1234"));
+
+        assert!(is_valid_channel("stable"));
+        assert!(is_valid_channel("2.x/beta"));
+        assert!(is_valid_channel("latest/edge/fix-123"));
+        assert!(!is_valid_channel("nightly"));
+        assert!(!is_valid_channel("--classic"));
+
+        assert_eq!(risk_level(None), "stable");
+        assert_eq!(risk_level(Some("beta")), "beta");
+        assert_eq!(risk_level(Some("nightly")), "edge");
+    }
+}
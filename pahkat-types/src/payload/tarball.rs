@@ -1,6 +1,18 @@
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::path::{Component, Path, PathBuf};
+
 use serde::{Deserialize, Serialize};
 use typed_builder::TypedBuilder;
 
+#[derive(thiserror::Error, Debug)]
+#[error("Install directory must be a relative path without `..` components")]
+pub struct InvalidInstallDir;
+
+fn is_zero(v: &u32) -> bool {
+    *v == 0
+}
+
 #[derive(
     Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, TypedBuilder,
 )]
@@ -9,15 +21,109 @@ use typed_builder::TypedBuilder;
 #[cfg_attr(feature = "poem-openapi", oai(rename = "TarballPackage"))]
 #[cfg_attr(feature = "async-graphql", derive(async_graphql::SimpleObject))]
 #[cfg_attr(feature = "async-graphql", graphql(name = "TarballPackage"))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "schemars", schemars(rename = "TarballPackage"))]
 pub struct Package {
     #[cfg_attr(feature = "structopt", structopt(short, long))]
     pub url: url::Url,
 
+    /// Alternative locations of the same file, tried in order if `url` fails
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[builder(default)]
+    #[cfg_attr(feature = "structopt", structopt(long = "mirror"))]
+    #[cfg_attr(feature = "poem-openapi", oai(default))]
+    pub mirrors: Vec<url::Url>,
+
+    /// Patches from earlier releases, used instead of downloading the whole payload
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[builder(default)]
+    #[cfg_attr(feature = "structopt", structopt(skip))]
+    #[cfg_attr(feature = "poem-openapi", oai(default))]
+    pub deltas: Vec<super::delta::Delta>,
+
     #[cfg_attr(feature = "structopt", structopt(short, long))]
     pub size: u64,
 
     #[cfg_attr(feature = "structopt", structopt(short, long))]
     pub installed_size: u64,
+
+    /// Subdirectory of the package directory to extract into
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    #[cfg_attr(feature = "structopt", structopt(long))]
+    pub install_dir: Option<String>,
+
+    /// Number of leading path components to remove from each entry, like
+    /// `tar --strip-components`. Leading `./` is not counted.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    #[builder(default)]
+    #[cfg_attr(feature = "structopt", structopt(long, default_value = "0"))]
+    #[cfg_attr(feature = "poem-openapi", oai(default))]
+    pub strip_components: u32,
+
+    /// Options the user may choose when installing
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[builder(default)]
+    #[cfg_attr(feature = "structopt", structopt(skip))]
+    #[cfg_attr(feature = "poem-openapi", oai(default))]
+    pub options: Vec<super::InstallOption>,
+}
+
+impl Package {
+    pub fn install_dir(&self) -> Result<Option<&Path>, InvalidInstallDir> {
+        let dir = match self.install_dir.as_ref() {
+            Some(v) => Path::new(v),
+            None => return Ok(None),
+        };
+
+        if dir
+            .components()
+            .all(|x| matches!(x, Component::Normal(_) | Component::CurDir))
+        {
+            Ok(Some(dir))
+        } else {
+            Err(InvalidInstallDir)
+        }
+    }
+
+    /// Applies `strip_components` to the path of an archive entry. Returns `None`
+    /// if nothing is left of the path, or if it is not a plain relative path.
+    pub fn strip_path(&self, path: &Path) -> Option<PathBuf> {
+        let mut components = vec![];
+        for component in path.components() {
+            match component {
+                Component::CurDir => continue,
+                Component::Normal(x) => components.push(x),
+                _ => return None,
+            }
+        }
+
+        let out = components
+            .into_iter()
+            .skip(self.strip_components as usize)
+            .collect::<PathBuf>();
+
+        if out.as_os_str().is_empty() {
+            None
+        } else {
+            Some(out)
+        }
+    }
+
+    /// Whether an entry at `path`, as returned by `strip_path`, is extracted with the
+    /// resolved install `options`. A top-level directory named by a value of an
+    /// option is only extracted if that value is chosen.
+    pub fn is_selected(&self, path: &Path, options: &BTreeMap<String, String>) -> bool {
+        let first = match path.components().next() {
+            Some(Component::Normal(x)) => x,
+            _ => return true,
+        };
+
+        self.options.iter().all(|option| {
+            !option.values.iter().any(|x| OsStr::new(x) == first)
+                || options.get(&option.id).map(OsStr::new) == Some(first)
+        })
+    }
 }
 
 impl super::AsDownloadUrl for Package {
@@ -25,3 +131,60 @@ impl super::AsDownloadUrl for Package {
         &self.url
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package(strip_components: u32) -> Package {
+        Package::builder()
+            .url("https://example.com/a.txz".parse().unwrap())
+            .size(1)
+            .installed_size(1)
+            .strip_components(strip_components)
+            .build()
+    }
+
+    #[test]
+    fn strip_path() {
+        let pkg = package(2);
+        assert_eq!(
+            pkg.strip_path(Path::new("./usr/local/bin/tool")),
+            Some(PathBuf::from("bin/tool"))
+        );
+        assert_eq!(pkg.strip_path(Path::new("./usr/local/")), None);
+        assert_eq!(pkg.strip_path(Path::new("usr/../../etc/passwd")), None);
+        assert_eq!(
+            package(0).strip_path(Path::new("bin/tool")),
+            Some(PathBuf::from("bin/tool"))
+        );
+    }
+
+    #[test]
+    fn selected_directories() {
+        let mut pkg = package(0);
+        pkg.options = vec![super::super::InstallOption::builder()
+            .id("layout".into())
+            .values(vec!["sme".into(), "sma".into()])
+            .build()];
+        let options = vec![("layout".to_string(), "sma".to_string())]
+            .into_iter()
+            .collect::<BTreeMap<_, _>>();
+
+        assert!(pkg.is_selected(Path::new("sma/layout.klc"), &options));
+        assert!(!pkg.is_selected(Path::new("sme/layout.klc"), &options));
+        assert!(pkg.is_selected(Path::new("common/speller.zhfst"), &options));
+        assert!(!pkg.is_selected(Path::new("sma"), &BTreeMap::new()));
+    }
+
+    #[test]
+    fn install_dir() {
+        let mut pkg = package(0);
+        pkg.install_dir = Some("share/tool".into());
+        assert!(pkg.install_dir().is_ok());
+        pkg.install_dir = Some("../tool".into());
+        assert!(pkg.install_dir().is_err());
+        pkg.install_dir = Some("/opt/tool".into());
+        assert!(pkg.install_dir().is_err());
+    }
+}
@@ -1,5 +1,6 @@
 use std::cmp::Ordering;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
 use std::str::FromStr;
 
 use serde::{Deserialize, Serialize};
@@ -8,6 +9,24 @@ use typed_builder::TypedBuilder;
 #[cfg(feature = "structopt")]
 use super::parse_set;
 
+#[cfg(feature = "structopt")]
+fn parse_property_map(s: &str) -> Result<BTreeMap<String, String>, &'static str> {
+    let mut map = BTreeMap::new();
+
+    if s == "" {
+        return Ok(map);
+    }
+
+    for pair in s.split(",") {
+        let mut it = pair.splitn(2, "=");
+        let key = it.next().unwrap().trim();
+        let value = it.next().ok_or("Expected KEY=VALUE")?;
+        map.insert(key.to_string(), value.to_string());
+    }
+
+    Ok(map)
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 #[serde(rename_all = "lowercase")]
 #[cfg_attr(feature = "poem-openapi", derive(poem_openapi::Enum))]
@@ -17,6 +36,8 @@ use super::parse_set;
 )]
 #[cfg_attr(feature = "async-graphql", derive(async_graphql::Enum))]
 #[cfg_attr(feature = "async-graphql", graphql(name = "WindowsRebootSpec"))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "schemars", schemars(rename = "WindowsRebootSpec"))]
 pub enum RebootSpec {
     Install,
     Uninstall,
@@ -40,6 +61,131 @@ impl FromStr for RebootSpec {
     }
 }
 
+/// The installer technology of a Windows executable, which decides how it is run silently.
+///
+/// Serialized as its name, such as `msi` or `inno`. Unknown names are kept as `Other`,
+/// which have no silent arguments of their own.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum InstallerKind {
+    Msi,
+    InnoSetup,
+    Nsis,
+    InstallShield,
+    Other(String),
+}
+
+impl InstallerKind {
+    pub fn as_str(&self) -> &str {
+        match self {
+            InstallerKind::Msi => "msi",
+            InstallerKind::InnoSetup => "inno",
+            InstallerKind::Nsis => "nsis",
+            InstallerKind::InstallShield => "installshield",
+            InstallerKind::Other(name) => name,
+        }
+    }
+
+    /// Arguments for installing without user interaction. MSI packages are run through
+    /// `msiexec` instead, so have none.
+    pub fn silent_install_args(&self) -> Option<&'static str> {
+        match self {
+            InstallerKind::InnoSetup => Some("/VERYSILENT /SP- /SUPPRESSMSGBOXES /NORESTART"),
+            InstallerKind::Nsis => Some("/S"),
+            InstallerKind::InstallShield => Some(r#"/s /v"/qn /norestart""#),
+            InstallerKind::Msi | InstallerKind::Other(_) => None,
+        }
+    }
+
+    /// Arguments added to the registered uninstaller to run it without user interaction.
+    pub fn silent_uninstall_args(&self) -> Option<&'static str> {
+        match self {
+            InstallerKind::InnoSetup => Some("/VERYSILENT /SP- /SUPPRESSMSGBOXES /NORESTART"),
+            InstallerKind::Nsis => Some("/S"),
+            InstallerKind::InstallShield => Some(r#"/s /x /v"/qn /norestart""#),
+            InstallerKind::Msi | InstallerKind::Other(_) => None,
+        }
+    }
+
+    /// Detects the kind of installer from the start of its file, for payloads that do
+    /// not declare one.
+    pub fn detect(bytes: &[u8]) -> Option<InstallerKind> {
+        const OLE_MAGIC: &[u8] = &[0xD0, 0xCF, 0x11, 0xE0, 0xA1, 0xB1, 0x1A, 0xE1];
+
+        if bytes.starts_with(OLE_MAGIC) {
+            return Some(InstallerKind::Msi);
+        }
+
+        if !bytes.starts_with(b"MZ") {
+            return None;
+        }
+
+        let contains = |needle: &[u8]| bytes.windows(needle.len()).any(|x| x == needle);
+        if contains(b"Inno Setup Setup Data") {
+            Some(InstallerKind::InnoSetup)
+        } else if contains(b"NullsoftInst") {
+            Some(InstallerKind::Nsis)
+        } else if contains(b"InstallShield") {
+            Some(InstallerKind::InstallShield)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for InstallerKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for InstallerKind {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match &*s.to_lowercase() {
+            "msi" => InstallerKind::Msi,
+            "inno" | "innosetup" => InstallerKind::InnoSetup,
+            "nsis" => InstallerKind::Nsis,
+            "installshield" => InstallerKind::InstallShield,
+            _ => InstallerKind::Other(s.to_string()),
+        })
+    }
+}
+
+#[cfg(feature = "schemars")]
+string_schema!(InstallerKind, None::<&str>);
+
+impl Serialize for InstallerKind {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for InstallerKind {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        Ok(value.parse().unwrap())
+    }
+}
+
+#[cfg(feature = "async-graphql")]
+#[cfg_attr(feature = "async-graphql", async_graphql::Scalar)]
+impl async_graphql::ScalarType for InstallerKind {
+    fn parse(value: async_graphql::Value) -> async_graphql::InputValueResult<Self> {
+        match &value {
+            async_graphql::Value::String(s) => Ok(s.parse().unwrap()),
+            _ => Err(async_graphql::InputValueError::expected_type(value)),
+        }
+    }
+
+    fn to_value(&self) -> async_graphql::Value {
+        async_graphql::Value::String(self.to_string())
+    }
+}
+
+#[cfg(feature = "poem-openapi")]
+string_openapi!(InstallerKind, "installer-kind");
+
 #[derive(
     Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, TypedBuilder,
 )]
@@ -48,9 +194,25 @@ impl FromStr for RebootSpec {
 #[cfg_attr(feature = "poem-openapi", oai(rename = "WindowsExecutable"))]
 #[cfg_attr(feature = "async-graphql", derive(async_graphql::SimpleObject))]
 #[cfg_attr(feature = "async-graphql", graphql(name = "WindowsExecutable"))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "schemars", schemars(rename = "WindowsExecutable"))]
 pub struct Executable {
     #[cfg_attr(feature = "structopt", structopt(short, long))]
     pub url: url::Url,
+
+    /// Alternative locations of the same file, tried in order if `url` fails
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[builder(default)]
+    #[cfg_attr(feature = "structopt", structopt(long = "mirror"))]
+    #[cfg_attr(feature = "poem-openapi", oai(default))]
+    pub mirrors: Vec<url::Url>,
+
+    /// Patches from earlier releases, used instead of downloading the whole payload
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[builder(default)]
+    #[cfg_attr(feature = "structopt", structopt(skip))]
+    #[cfg_attr(feature = "poem-openapi", oai(default))]
+    pub deltas: Vec<super::delta::Delta>,
     #[cfg_attr(feature = "structopt", structopt(short, long))]
     pub product_code: String,
     #[cfg_attr(feature = "structopt", structopt(short, long))]
@@ -58,11 +220,11 @@ pub struct Executable {
     #[cfg_attr(feature = "structopt", structopt(short, long))]
     pub installed_size: u64,
 
-    /// The type of installer (msi, nsis, etc)
+    /// The type of installer. Detected from the downloaded file if not given.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     #[builder(default)]
     #[cfg_attr(feature = "structopt", structopt(short, long))]
-    pub kind: Option<String>,
+    pub kind: Option<InstallerKind>,
 
     #[serde(default, skip_serializing_if = "Option::is_none")]
     #[builder(default)]
@@ -79,6 +241,97 @@ pub struct Executable {
     #[cfg_attr(feature = "poem-openapi", oai(default))]
     #[builder(default)]
     pub requires_reboot: BTreeSet<RebootSpec>,
+
+    /// Expected Authenticode signer; unsigned or differently signed installers are rejected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    #[cfg_attr(feature = "structopt", structopt(long))]
+    pub publisher: Option<String>,
+
+    /// Transforms applied in order when installing. Paths starting with `:` refer
+    /// to transforms embedded in the MSI, others to files on the target machine.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[builder(default)]
+    #[cfg_attr(feature = "structopt", structopt(long))]
+    #[cfg_attr(feature = "poem-openapi", oai(default))]
+    pub msi_transforms: Vec<String>,
+
+    // Tables have to come last in TOML
+    /// Public properties passed to msiexec when installing, such as `ALLUSERS=1`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    #[builder(default)]
+    #[cfg_attr(feature = "structopt", structopt(default_value = "", long, parse(try_from_str = parse_property_map)))]
+    #[cfg_attr(feature = "poem-openapi", oai(default))]
+    pub msi_properties: BTreeMap<String, String>,
+
+    /// Integration with the OS after installing, such as enabling an input source
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[builder(default)]
+    #[cfg_attr(feature = "structopt", structopt(long = "action"))]
+    #[cfg_attr(feature = "poem-openapi", oai(default))]
+    pub actions: Vec<super::Action>,
+
+    /// Options the user may choose when installing
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[builder(default)]
+    #[cfg_attr(feature = "structopt", structopt(skip))]
+    #[cfg_attr(feature = "poem-openapi", oai(default))]
+    pub options: Vec<super::InstallOption>,
+
+    /// How to find and run the uninstaller, for installers that do not register
+    /// themselves under the product code
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    #[cfg_attr(feature = "structopt", structopt(skip))]
+    pub uninstall: Option<Uninstall>,
+}
+
+#[derive(
+    Debug,
+    Default,
+    Serialize,
+    Deserialize,
+    Clone,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+    TypedBuilder,
+)]
+#[cfg_attr(feature = "poem-openapi", derive(poem_openapi::Object))]
+#[cfg_attr(feature = "poem-openapi", oai(rename = "WindowsUninstall"))]
+#[cfg_attr(feature = "async-graphql", derive(async_graphql::SimpleObject))]
+#[cfg_attr(feature = "async-graphql", graphql(name = "WindowsUninstall"))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "schemars", schemars(rename = "WindowsUninstall"))]
+pub struct Uninstall {
+    /// The key under `Software\Microsoft\Windows\CurrentVersion\Uninstall` the
+    /// installer registers, if it is not the product code
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub registry_key: Option<String>,
+
+    /// Command line that uninstalls without user interaction. `{product_code}` and
+    /// `{uninstall_string}`, the installer's registered `UninstallString`, are substituted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub quiet_command: Option<String>,
+}
+
+impl Uninstall {
+    /// Returns `None` if there is no command, or it needs an uninstall string that
+    /// was not registered.
+    pub fn command(&self, product_code: &str, uninstall_string: Option<&str>) -> Option<String> {
+        let command = self.quiet_command.as_ref()?;
+        let command = command.replace("{product_code}", product_code);
+
+        if command.contains("{uninstall_string}") {
+            Some(command.replace("{uninstall_string}", uninstall_string?))
+        } else {
+            Some(command)
+        }
+    }
 }
 
 impl super::AsDownloadUrl for Executable {
@@ -87,9 +340,115 @@ impl super::AsDownloadUrl for Executable {
     }
 }
 
+#[derive(
+    Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, TypedBuilder,
+)]
+#[cfg_attr(feature = "structopt", derive(structopt::StructOpt))]
+#[cfg_attr(feature = "poem-openapi", derive(poem_openapi::Object))]
+#[cfg_attr(feature = "poem-openapi", oai(rename = "WindowsMsix"))]
+#[cfg_attr(feature = "async-graphql", derive(async_graphql::SimpleObject))]
+#[cfg_attr(feature = "async-graphql", graphql(name = "WindowsMsix"))]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "schemars", schemars(rename = "WindowsMsix"))]
+pub struct Msix {
+    #[cfg_attr(feature = "structopt", structopt(short, long))]
+    pub url: url::Url,
+
+    /// Alternative locations of the same file, tried in order if `url` fails
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[builder(default)]
+    #[cfg_attr(feature = "structopt", structopt(long = "mirror"))]
+    #[cfg_attr(feature = "poem-openapi", oai(default))]
+    pub mirrors: Vec<url::Url>,
+
+    /// Patches from earlier releases, used instead of downloading the whole payload
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[builder(default)]
+    #[cfg_attr(feature = "structopt", structopt(skip))]
+    #[cfg_attr(feature = "poem-openapi", oai(default))]
+    pub deltas: Vec<super::delta::Delta>,
+
+    /// The package family name, such as `Divvun.Keyboard_8wekyb3d8bbwe`, which the
+    /// installed package is found by
+    #[cfg_attr(feature = "structopt", structopt(short = "f", long))]
+    pub package_family_name: String,
+
+    #[cfg_attr(feature = "structopt", structopt(short, long))]
+    pub size: u64,
+
+    #[cfg_attr(feature = "structopt", structopt(short, long))]
+    pub installed_size: u64,
+
+    /// Integration with the OS after installing, such as enabling an input source
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[builder(default)]
+    #[cfg_attr(feature = "structopt", structopt(long = "action"))]
+    #[cfg_attr(feature = "poem-openapi", oai(default))]
+    pub actions: Vec<super::Action>,
+}
+
+impl super::AsDownloadUrl for Msix {
+    fn as_download_url(&self) -> &url::Url {
+        &self.url
+    }
+}
+
+/// Whether `name` is a package family name: a package name of letters, digits, `.` and
+/// `-`, then `_` and the 13 character publisher ID.
+pub fn is_valid_family_name(name: &str) -> bool {
+    let (name, publisher_id) = match name.rsplit_once('_') {
+        Some(v) => v,
+        None => return false,
+    };
+
+    (3..=50).contains(&name.len())
+        && name
+            .chars()
+            .all(|x| x.is_ascii_alphanumeric() || matches!(x, '.' | '-'))
+        && publisher_id.len() == 13
+        && publisher_id
+            .chars()
+            .all(|x| x.is_ascii_lowercase() || x.is_ascii_digit())
+}
+
+/// Whether `name` is a public MSI property name, of uppercase letters, digits, `_` and
+/// `.` and not starting with a digit or `.`. Only these are put on the `msiexec`
+/// command line, where any other character could end the property.
+pub fn is_msi_property_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_uppercase() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_' || c == '.')
+}
+
+/// Whether `transform` can be listed in the quoted, `;` separated `TRANSFORMS` property.
+pub fn is_valid_msi_transform(transform: &str) -> bool {
+    !transform.is_empty() && !transform.contains([';', '"'])
+}
+
+/// The version of an MSIX package, `major.minor.build.revision`, as a version
+/// comparable with releases. The revision is dropped, as the Store requires it to be 0.
+pub fn msix_version(version: &str) -> Option<String> {
+    let parts = version.trim().split('.').collect::<Vec<_>>();
+    if !(3..=4).contains(&parts.len()) || parts.iter().any(|x| x.parse::<u16>().is_err()) {
+        return None;
+    }
+
+    let parts = parts
+        .iter()
+        .take(3)
+        .map(|x| x.parse::<u16>().unwrap().to_string())
+        .collect::<Vec<_>>();
+    Some(parts.join("."))
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, Hash)]
 #[cfg_attr(feature = "async-graphql", derive(async_graphql::Enum))]
 #[serde(rename_all = "lowercase")]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "schemars", schemars(rename = "WindowsInstallTarget"))]
 pub enum InstallTarget {
     System,
     User,
@@ -152,3 +511,88 @@ impl Ord for InstallTarget {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn installer_kind_names() {
+        assert_eq!(
+            "inno".parse::<InstallerKind>(),
+            Ok(InstallerKind::InnoSetup)
+        );
+        assert_eq!("MSI".parse::<InstallerKind>(), Ok(InstallerKind::Msi));
+        assert_eq!(
+            "wix-burn".parse::<InstallerKind>(),
+            Ok(InstallerKind::Other("wix-burn".into()))
+        );
+        assert_eq!(InstallerKind::InnoSetup.to_string(), "inno");
+        assert_eq!(
+            InstallerKind::Other("wix-burn".into()).to_string(),
+            "wix-burn"
+        );
+    }
+
+    #[test]
+    fn uninstall_command_template() {
+        let uninstall = Uninstall::builder()
+            .quiet_command(Some("{uninstall_string} /quiet /id {product_code}".into()))
+            .build();
+        assert_eq!(
+            uninstall.command("{A}", Some(r#""C:\App\unins000.exe""#)),
+            Some(r#""C:\App\unins000.exe" /quiet /id {A}"#.to_string())
+        );
+        assert_eq!(uninstall.command("{A}", None), None);
+        assert_eq!(Uninstall::default().command("{A}", None), None);
+    }
+
+    #[test]
+    fn msi_property_names() {
+        assert!(is_msi_property_name("LICENSEKEY"));
+        assert!(is_msi_property_name("_ADDLOCAL.2"));
+        assert!(!is_msi_property_name("licenseKey"));
+        assert!(!is_msi_property_name("2FA"));
+        assert!(!is_msi_property_name(""));
+        assert!(!is_msi_property_name("A=1 B"));
+        assert!(!is_msi_property_name("A\" /x"));
+
+        assert!(is_valid_msi_transform(":sv.mst"));
+        assert!(!is_valid_msi_transform("a.mst;b.mst"));
+        assert!(!is_valid_msi_transform("a.mst\" /x \""));
+        assert!(!is_valid_msi_transform(""));
+    }
+
+    #[test]
+    fn msix_names_and_versions() {
+        assert!(is_valid_family_name("Divvun.Keyboard-sme_8wekyb3d8bbwe"));
+        assert!(!is_valid_family_name("Divvun.Keyboard"));
+        assert!(!is_valid_family_name("Divvun Keyboard_8wekyb3d8bbwe"));
+        assert!(!is_valid_family_name("Divvun.Keyboard_8WEKYB3D8BBWE"));
+
+        assert_eq!(msix_version("1.2.3.0").as_deref(), Some("1.2.3"));
+        assert_eq!(msix_version("01.2.3").as_deref(), Some("1.2.3"));
+        assert_eq!(msix_version("1.2"), None);
+        assert_eq!(msix_version("1.2.3.70000"), None);
+    }
+
+    #[test]
+    fn detect_installer_kind() {
+        let exe = |marker: &[u8]| [&b"MZ\x90\x00"[..], &[0; 64], marker].concat();
+
+        assert_eq!(
+            InstallerKind::detect(&[0xD0, 0xCF, 0x11, 0xE0, 0xA1, 0xB1, 0x1A, 0xE1, 0]),
+            Some(InstallerKind::Msi)
+        );
+        assert_eq!(
+            InstallerKind::detect(&exe(b"Inno Setup Setup Data (6.2.0)")),
+            Some(InstallerKind::InnoSetup)
+        );
+        assert_eq!(
+            InstallerKind::detect(&exe(b"\xef\xbe\xad\xdeNullsoftInst")),
+            Some(InstallerKind::Nsis)
+        );
+        assert_eq!(InstallerKind::detect(&exe(b"setup")), None);
+        assert_eq!(InstallerKind::detect(b"NullsoftInst"), None);
+    }
+}
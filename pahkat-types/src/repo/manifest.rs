@@ -0,0 +1,73 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use typed_builder::TypedBuilder;
+
+use super::RepoUrl;
+
+/// The `manifest.toml` of a signed repository, which is what its key signs, in
+/// `manifest.toml.sig`. It binds the index files of one publication of the repository
+/// together, and clients refuse a manifest with a lower serial than one they have seen
+/// before, so that an older index cannot be served in place of a newer one.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, TypedBuilder)]
+#[non_exhaustive]
+pub struct Manifest {
+    /// The repository the manifest was signed for, so that the signed index of one
+    /// repository cannot be served in place of another signed with the same key.
+    pub url: RepoUrl,
+
+    pub serial: u64,
+
+    /// Lowercase hex SHA-256 of each index file, by path relative to the repository.
+    pub files: BTreeMap<String, String>,
+}
+
+impl Manifest {
+    pub const PATH: &'static str = "manifest.toml";
+
+    /// The index files a signed repository must list.
+    pub const FILES: &'static [&'static str] = &["index.toml", "packages/index.bin"];
+
+    pub fn new<'a>(
+        url: RepoUrl,
+        serial: u64,
+        files: impl IntoIterator<Item = (&'a str, &'a [u8])>,
+    ) -> Manifest {
+        Manifest {
+            url,
+            serial,
+            files: files
+                .into_iter()
+                .map(|(path, data)| (path.to_string(), sha256(data)))
+                .collect(),
+        }
+    }
+
+    /// Whether `data` is the file at `path` that this manifest was made for.
+    pub fn contains(&self, path: &str, data: &[u8]) -> bool {
+        self.files.get(path) == Some(&sha256(data))
+    }
+}
+
+fn sha256(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contains_only_listed_files() {
+        let url: RepoUrl = "https://pahkat.example/repo/".parse().unwrap();
+        let manifest = Manifest::new(url.clone(), 7, [("index.toml", &b"index"[..])]);
+        let manifest: Manifest = toml::from_str(&toml::to_string(&manifest).unwrap()).unwrap();
+
+        assert_eq!(manifest.url, url);
+        assert_eq!(manifest.serial, 7);
+        assert!(manifest.contains("index.toml", b"index"));
+        assert!(!manifest.contains("index.toml", b"index\n"));
+        assert!(!manifest.contains("packages/index.bin", b"index"));
+    }
+}
@@ -1,5 +1,7 @@
+mod manifest;
 mod url;
 
+pub use self::manifest::Manifest;
 pub use self::url::{RepoUrl, RepoUrlError};
 
 use ::url::Url;
@@ -43,6 +45,13 @@ pub struct RepositoryData {
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     #[builder(default)]
     pub accepted_redirections: Vec<RepoUrl>,
+
+    /// Base64 ed25519 public key that the [`Manifest`] of the repository is signed
+    /// with. Clients that have no key configured for the repository trust the first
+    /// key they see, and refuse the repository if it changes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub signing_key: Option<String>,
 }
 
 #[derive(
@@ -66,6 +75,31 @@ pub struct Index {
     pub description: BTreeMap<String, String>,
 
     pub agent: Agent,
+
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    #[builder(default)]
+    pub sets: BTreeMap<String, PackageSet>,
+}
+
+#[derive(
+    Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, TypedBuilder,
+)]
+#[cfg_attr(feature = "async-graphql", derive(async_graphql::SimpleObject))]
+#[non_exhaustive]
+/// A named group of packages in this repository that are installed and uninstalled as a unit.
+///
+/// A set is addressed by a package key with the set's identifier in place of a package id.
+pub struct PackageSet {
+    #[serde(default)]
+    #[builder(default)]
+    pub name: BTreeMap<String, String>,
+
+    #[serde(default)]
+    #[builder(default)]
+    pub description: BTreeMap<String, String>,
+
+    /// Identifiers of the member packages
+    pub packages: Vec<String>,
 }
 
 #[derive(
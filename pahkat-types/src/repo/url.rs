@@ -36,7 +36,7 @@ impl std::ops::Deref for RepoUrl {
 
 #[derive(Debug, Clone, thiserror::Error)]
 pub enum RepoUrlError {
-    #[error("Repositories must be `https`. Got: {0}")]
+    #[error("Repositories must be `https` or `file`. Got: {0}")]
     InvalidScheme(String),
 
     #[error("URL has no path segments. (Likely an invalid URL)")]
@@ -47,8 +47,10 @@ pub enum RepoUrlError {
 }
 
 impl RepoUrl {
+    /// `file` URLs are accepted so that a repository built locally can be used
+    /// without serving it.
     pub fn new(mut url: Url) -> Result<RepoUrl, RepoUrlError> {
-        if url.scheme() != "https" {
+        if !matches!(url.scheme(), "https" | "file") {
             return Err(RepoUrlError::InvalidScheme(url.scheme().to_string()));
         }
 
@@ -65,6 +67,10 @@ impl RepoUrl {
     pub fn into_inner(self) -> Url {
         self.0
     }
+
+    pub fn is_local(&self) -> bool {
+        self.0.scheme() == "file"
+    }
 }
 
 impl FromStr for RepoUrl {
@@ -111,3 +117,28 @@ impl<'de> Visitor<'de> for RepoUrlVisitor {
         RepoUrl::new(url).map_err(|_| E::custom("Invalid URL"))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_https_and_file() {
+        assert_eq!(
+            "https://example.com/repo"
+                .parse::<RepoUrl>()
+                .unwrap()
+                .as_str(),
+            "https://example.com/repo/"
+        );
+
+        let url = "file:///srv/repo".parse::<RepoUrl>().unwrap();
+        assert!(url.is_local());
+        assert_eq!(url.as_str(), "file:///srv/repo/");
+
+        assert!(matches!(
+            "http://example.com/repo".parse::<RepoUrl>(),
+            Err(RepoUrlError::InvalidScheme(_))
+        ));
+    }
+}
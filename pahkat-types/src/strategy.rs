@@ -0,0 +1,501 @@
+//! Proptest strategies for repository data.
+//!
+//! Generated values stay within what every format used by Pahkat can hold:
+//! sizes fit in a TOML integer, local dependency ids do not parse as URLs and
+//! Windows installer kinds are ones the index can encode.
+
+use proptest::collection::{btree_map, btree_set, vec};
+use proptest::option;
+use proptest::prelude::*;
+use url::Url;
+
+use crate::package::{
+    Deprecation, DeprecationSeverity, Descriptor, DescriptorData, Provenance, Release, Version,
+    VersionReq,
+};
+use crate::payload::{
+    debian, flatpak, macos, rpm, snap, tarball, windows, Action, ActionKind, InstallOption,
+    Payload, Target,
+};
+use crate::{DependencyKey, DependencyMap, LangTagMap};
+
+fn id() -> impl Strategy<Value = String> {
+    "[a-z][a-z0-9-]{0,15}"
+}
+
+fn text() -> impl Strategy<Value = String> {
+    "[A-Za-z0-9 .,'-]{0,24}"
+}
+
+fn size() -> impl Strategy<Value = u64> {
+    0..=i64::MAX as u64
+}
+
+fn url() -> impl Strategy<Value = Url> {
+    ("[a-z][a-z0-9]{0,15}", id()).prop_map(|(host, path)| {
+        format!("https://{}.example/{}", host, path)
+            .parse()
+            .unwrap()
+    })
+}
+
+fn lang_tag_map() -> impl Strategy<Value = LangTagMap<String>> {
+    btree_map("[a-z]{2,3}", text(), 0..3)
+}
+
+pub fn version() -> impl Strategy<Value = Version> {
+    (
+        0..100u64,
+        0..100u64,
+        0..100u64,
+        option::of(("alpha|beta|rc|nightly", 0..20u64)),
+    )
+        .prop_map(|(major, minor, patch, pre)| {
+            let version = match pre {
+                Some((tag, n)) => format!("{}.{}.{}-{}.{}", major, minor, patch, tag, n),
+                None => format!("{}.{}.{}", major, minor, patch),
+            };
+            Version::new(&version).unwrap()
+        })
+}
+
+pub fn dependency_map() -> impl Strategy<Value = DependencyMap> {
+    btree_map(
+        id().prop_map(DependencyKey::Local),
+        prop_oneof![
+            Just(VersionReq::STAR),
+            version().prop_map(|x| format!(">={}", x).parse::<VersionReq>().unwrap()),
+        ],
+        0..3,
+    )
+}
+
+pub fn action() -> impl Strategy<Value = Action> {
+    (
+        prop_oneof![
+            Just(ActionKind::EnableInputSource),
+            Just(ActionKind::RegisterSpellService),
+        ],
+        "[a-z][a-z0-9.]{0,15}",
+    )
+        .prop_map(|(kind, id)| Action { kind, id })
+}
+
+pub fn install_option() -> impl Strategy<Value = InstallOption> {
+    (
+        "[A-Z][A-Z0-9_]{0,11}",
+        vec("[a-z]{2,4}", 0..3),
+        option::of("[a-z]{2,4}"),
+    )
+        .prop_map(|(id, values, default)| {
+            InstallOption::builder()
+                .id(id)
+                .values(values)
+                .default(default)
+                .build()
+        })
+}
+
+pub fn windows_executable() -> impl Strategy<Value = windows::Executable> {
+    let reboot = prop_oneof![
+        Just(windows::RebootSpec::Install),
+        Just(windows::RebootSpec::Uninstall),
+        Just(windows::RebootSpec::Update),
+    ];
+
+    (
+        (url(), "\\{[0-9a-f]{8}\\}", size(), size()),
+        (
+            option::of(prop_oneof![
+                Just(windows::InstallerKind::Msi),
+                Just(windows::InstallerKind::Nsis),
+                Just(windows::InstallerKind::InnoSetup),
+            ]),
+            option::of(text()),
+            option::of(text()),
+            btree_set(reboot, 0..3),
+        ),
+        (
+            option::of(text()),
+            btree_map("[A-Z]{1,8}", text(), 0..3),
+            vec(id(), 0..3),
+            vec(action(), 0..3),
+        ),
+    )
+        .prop_map(
+            |(
+                (url, product_code, size, installed_size),
+                (kind, args, uninstall_args, requires_reboot),
+                (publisher, msi_properties, msi_transforms, actions),
+            )| {
+                windows::Executable::builder()
+                    .url(url)
+                    .product_code(product_code)
+                    .size(size)
+                    .installed_size(installed_size)
+                    .kind(kind)
+                    .args(args)
+                    .uninstall_args(uninstall_args)
+                    .requires_reboot(requires_reboot)
+                    .publisher(publisher)
+                    .msi_properties(msi_properties)
+                    .msi_transforms(msi_transforms)
+                    .actions(actions)
+                    .build()
+            },
+        )
+}
+
+pub fn macos_package() -> impl Strategy<Value = macos::Package> {
+    let target = prop_oneof![
+        Just(macos::InstallTarget::System),
+        Just(macos::InstallTarget::User),
+    ];
+    let reboot = prop_oneof![
+        Just(macos::RebootSpec::Install),
+        Just(macos::RebootSpec::Uninstall),
+        Just(macos::RebootSpec::Update),
+    ];
+
+    (
+        (url(), "[a-z]{2,5}(\\.[a-z]{1,8}){1,3}", size(), size()),
+        (btree_set(target, 0..3), btree_set(reboot, 0..3)),
+        (
+            option::of("[A-Z0-9]{10}"),
+            option::of(text()),
+            option::of(text()),
+            vec(action(), 0..3),
+        ),
+        (option::of("[0-9a-f]{64}"), option::of("[0-9a-f]{64}")),
+    )
+        .prop_map(
+            |(
+                (url, pkg_id, size, installed_size),
+                (targets, requires_reboot),
+                (team_id, choice_changes, user_choice_changes, actions),
+                (choice_changes_sha256, user_choice_changes_sha256),
+            )| {
+                macos::Package::builder()
+                    .url(url)
+                    .pkg_id(pkg_id)
+                    .size(size)
+                    .installed_size(installed_size)
+                    .targets(targets)
+                    .requires_reboot(requires_reboot)
+                    .team_id(team_id)
+                    .choice_changes(choice_changes)
+                    .choice_changes_sha256(choice_changes_sha256)
+                    .user_choice_changes(user_choice_changes)
+                    .user_choice_changes_sha256(user_choice_changes_sha256)
+                    .actions(actions)
+                    .build()
+            },
+        )
+}
+
+pub fn macos_app_bundle() -> impl Strategy<Value = macos::AppBundle> {
+    (
+        url(),
+        "[a-z]{2,5}(\\.[a-z]{1,8}){1,3}",
+        "[A-Za-z][A-Za-z0-9 ]{0,15}\\.app",
+        size(),
+        size(),
+        vec(action(), 0..3),
+    )
+        .prop_map(
+            |(url, bundle_id, app_name, size, installed_size, actions)| {
+                macos::AppBundle::builder()
+                    .url(url)
+                    .bundle_id(bundle_id)
+                    .app_name(app_name)
+                    .size(size)
+                    .installed_size(installed_size)
+                    .actions(actions)
+                    .build()
+            },
+        )
+}
+
+pub fn tarball_package() -> impl Strategy<Value = tarball::Package> {
+    (
+        url(),
+        size(),
+        size(),
+        option::of("[a-z]{1,8}(/[a-z]{1,8}){0,2}"),
+        0..4u32,
+        vec(install_option(), 0..3),
+    )
+        .prop_map(
+            |(url, size, installed_size, install_dir, strip_components, options)| {
+                tarball::Package::builder()
+                    .url(url)
+                    .size(size)
+                    .installed_size(installed_size)
+                    .install_dir(install_dir)
+                    .strip_components(strip_components)
+                    .options(options)
+                    .build()
+            },
+        )
+}
+
+pub fn debian_package() -> impl Strategy<Value = debian::Package> {
+    (url(), "[a-z0-9][a-z0-9.+-]{1,15}", size(), size()).prop_map(
+        |(url, package, size, installed_size)| {
+            debian::Package::builder()
+                .url(url)
+                .package(package)
+                .size(size)
+                .installed_size(installed_size)
+                .build()
+        },
+    )
+}
+
+pub fn windows_msix() -> impl Strategy<Value = windows::Msix> {
+    (
+        url(),
+        "[A-Za-z][A-Za-z0-9.-]{2,15}_[a-z0-9]{13}",
+        size(),
+        size(),
+        vec(action(), 0..3),
+    )
+        .prop_map(
+            |(url, package_family_name, size, installed_size, actions)| {
+                windows::Msix::builder()
+                    .url(url)
+                    .package_family_name(package_family_name)
+                    .size(size)
+                    .installed_size(installed_size)
+                    .actions(actions)
+                    .build()
+            },
+        )
+}
+
+pub fn rpm_package() -> impl Strategy<Value = rpm::Package> {
+    (url(), "[A-Za-z0-9][A-Za-z0-9._+-]{0,15}", size(), size()).prop_map(
+        |(url, package, size, installed_size)| {
+            rpm::Package::builder()
+                .url(url)
+                .package(package)
+                .size(size)
+                .installed_size(installed_size)
+                .build()
+        },
+    )
+}
+
+pub fn flatpak() -> impl Strategy<Value = flatpak::Package> {
+    (
+        url(),
+        "[a-z][a-z0-9-]{0,11}",
+        "(app|runtime)/[a-z]{2,5}(\\.[A-Za-z][A-Za-z0-9_]{0,8}){2}/(x86_64|aarch64)/[a-z0-9][a-z0-9.]{0,7}",
+        size(),
+        size(),
+    )
+        .prop_map(|(url, remote, flatpak_ref, size, installed_size)| {
+            flatpak::Package::builder()
+                .url(url)
+                .remote(remote)
+                .flatpak_ref(flatpak_ref)
+                .size(size)
+                .installed_size(installed_size)
+                .build()
+        })
+}
+
+pub fn snap() -> impl Strategy<Value = snap::Package> {
+    (
+        url(),
+        "[a-z][a-z0-9]{1,7}(-[a-z0-9]{1,8}){0,2}",
+        option::of("([a-z0-9.]{1,6}/)?(stable|candidate|beta|edge)"),
+        any::<bool>(),
+        size(),
+        size(),
+    )
+        .prop_map(|(url, name, channel, classic, size, installed_size)| {
+            snap::Package::builder()
+                .url(url)
+                .name(name)
+                .channel(channel)
+                .classic(classic)
+                .size(size)
+                .installed_size(installed_size)
+                .build()
+        })
+}
+
+pub fn payload() -> impl Strategy<Value = Payload> {
+    prop_oneof![
+        windows_executable().prop_map(Payload::WindowsExecutable),
+        windows_msix().prop_map(Payload::WindowsMsix),
+        macos_package().prop_map(Payload::MacOSPackage),
+        macos_app_bundle().prop_map(Payload::MacOSAppBundle),
+        tarball_package().prop_map(Payload::TarballPackage),
+        debian_package().prop_map(Payload::DebianPackage),
+        rpm_package().prop_map(Payload::RpmPackage),
+        flatpak().prop_map(Payload::Flatpak),
+        snap().prop_map(Payload::Snap),
+    ]
+}
+
+pub fn target() -> impl Strategy<Value = Target> {
+    (
+        "windows|macos|linux",
+        option::of("x86_64|i686|arm64"),
+        dependency_map(),
+        payload(),
+    )
+        .prop_map(|(platform, arch, dependencies, payload)| {
+            Target::builder()
+                .platform(platform)
+                .arch(arch)
+                .dependencies(dependencies)
+                .payload(payload)
+                .build()
+        })
+}
+
+pub fn deprecation() -> impl Strategy<Value = Deprecation> {
+    let severity = prop_oneof![
+        Just(DeprecationSeverity::Notice),
+        Just(DeprecationSeverity::Warning),
+        Just(DeprecationSeverity::Critical),
+    ];
+
+    (severity, lang_tag_map()).prop_map(|(severity, message)| {
+        Deprecation::builder()
+            .severity(severity)
+            .message(message)
+            .build()
+    })
+}
+
+pub fn provenance() -> impl Strategy<Value = Provenance> {
+    (
+        option::of(url()),
+        "[0-9a-f]{40}",
+        option::of(url()),
+        option::of("ci|[a-z]{1,8}/build"),
+    )
+        .prop_map(|(source_url, commit, ci_run_url, builder)| {
+            Provenance::builder()
+                .source_url(source_url)
+                .commit(Some(commit))
+                .ci_run_url(ci_run_url)
+                .builder(builder)
+                .build()
+        })
+}
+
+pub fn release() -> impl Strategy<Value = Release> {
+    (
+        (
+            version(),
+            option::of("nightly|beta"),
+            vec(text(), 0..3),
+            option::of("MIT|Apache-2.0|CC-BY-4.0"),
+            option::of(url()),
+        ),
+        (
+            option::of("20[0-9]{2}-0[1-9]-[12][0-9]T[01][0-9]:00:00Z"),
+            option::of(0..100u8),
+            option::of(version().prop_map(|x| x.to_string())),
+            option::of(deprecation()),
+            option::of(provenance()),
+            vec(target(), 0..3),
+        ),
+    )
+        .prop_map(
+            |(
+                (version, channel, authors, license, license_url),
+                (available_from, rollout, min_client_version, deprecation, provenance, target),
+            )| {
+                Release::builder()
+                    .version(version)
+                    .channel(channel)
+                    .authors(authors)
+                    .license(license)
+                    .license_url(license_url)
+                    .available_from(available_from)
+                    .rollout(rollout)
+                    .min_client_version(min_client_version)
+                    .deprecation(deprecation)
+                    .provenance(provenance)
+                    .target(target)
+                    .build()
+            },
+        )
+}
+
+pub fn descriptor() -> impl Strategy<Value = Descriptor> {
+    (
+        id(),
+        vec("cat:[a-z]{1,8}", 0..3),
+        lang_tag_map(),
+        lang_tag_map(),
+        vec(release(), 0..3),
+    )
+        .prop_map(|(id, tags, name, description, release)| {
+            Descriptor::builder()
+                .package(DescriptorData::builder().id(id).tags(tags).build())
+                .name(name)
+                .description(description)
+                .release(release)
+                .build()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use super::*;
+
+    proptest! {
+        #[test]
+        fn descriptor_json_round_trip(descriptor in descriptor()) {
+            let json = serde_json::to_string(&descriptor).unwrap();
+            prop_assert_eq!(serde_json::from_str::<Descriptor>(&json).unwrap(), descriptor);
+        }
+
+        #[test]
+        fn descriptor_toml_round_trip(descriptor in descriptor()) {
+            let toml = toml::to_string(&descriptor).unwrap();
+            prop_assert_eq!(toml::from_str::<Descriptor>(&toml).unwrap(), descriptor);
+        }
+
+        #[test]
+        fn payload_toml_round_trip(payload in payload()) {
+            let toml = toml::to_string(&payload).unwrap();
+            prop_assert_eq!(toml::from_str::<Payload>(&toml).unwrap(), payload);
+        }
+
+        #[test]
+        fn version_string_round_trip(version in version()) {
+            prop_assert_eq!(version.to_string().parse::<Version>().unwrap(), version);
+        }
+
+        #[test]
+        fn version_order_is_consistent(a in version(), b in version()) {
+            let ord = a.partial_cmp(&b).unwrap();
+            prop_assert_eq!(b.partial_cmp(&a), Some(ord.reverse()));
+            prop_assert_eq!(a == b, ord == Ordering::Equal);
+        }
+
+        #[test]
+        fn prerelease_precedes_release(version in version()) {
+            let release = Version::new(version.to_string().split('-').next().unwrap()).unwrap();
+            prop_assert!(version <= release);
+        }
+
+        #[test]
+        fn releases_sort_by_version(mut releases in vec(release(), 0..8)) {
+            releases.sort();
+            for pair in releases.windows(2) {
+                prop_assert!(pair[0].version <= pair[1].version);
+            }
+        }
+    }
+}
@@ -1,5 +1,11 @@
 use serde::{Deserialize, Serialize};
 use typed_builder::TypedBuilder;
+
+/// A virtual dependency on Rosetta, which x86_64 payloads need on Apple Silicon.
+///
+/// It is satisfied by the system rather than by a package, so no repository provides it.
+pub const ROSETTA: &str = "@rosetta";
+
 #[derive(
     Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, TypedBuilder,
 )]
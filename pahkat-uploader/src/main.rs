@@ -1,4 +1,5 @@
 use anyhow::{Context, Result};
+use pahkat_types::package::Provenance;
 use pahkat_types::LangTagMap;
 use serde::{Deserialize, Serialize};
 use serde_json;
@@ -11,6 +12,32 @@ use structopt::StructOpt;
 enum Args {
     Release(Release),
     Upload(Upload),
+    StoreToken(StoreToken),
+}
+
+/// Saves an API token for the given server in the OS keyring, read from stdin.
+#[derive(StructOpt)]
+struct StoreToken {
+    #[structopt(short, long)]
+    pub url: String,
+}
+
+const KEYRING_SERVICE: &str = "pahkat-uploader";
+
+fn keyring_entry(url: &str) -> Result<keyring::Entry> {
+    let url = url::Url::parse(url).with_context(|| format!("invalid url {url:?}"))?;
+    let host = url.host_str().context("url has no host")?;
+    Ok(keyring::Entry::new(KEYRING_SERVICE, host)?)
+}
+
+fn api_token(url: &str) -> Result<String> {
+    if let Ok(token) = std::env::var("PAHKAT_API_KEY") {
+        return Ok(token);
+    }
+
+    keyring_entry(url)?
+        .get_password()
+        .context("could not read env PAHKAT_API_KEY or a stored token from the keyring")
 }
 
 #[derive(StructOpt, Serialize, Deserialize)]
@@ -63,9 +90,26 @@ pub struct Release {
     #[structopt(long)]
     pub license_url: Option<String>,
 
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[structopt(long)]
+    pub available_from: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[structopt(long)]
+    pub rollout: Option<u8>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[structopt(long)]
+    pub min_client_version: Option<String>,
+
     #[structopt(flatten)]
     pub target: pahkat_types::payload::Target,
 
+    // taken from the CI environment
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[structopt(skip)]
+    pub provenance: Option<Provenance>,
+
     // loaded from metadata file
     #[serde(default, skip_serializing_if = "Option::is_none")]
     #[structopt(skip)]
@@ -82,15 +126,24 @@ async fn main() -> anyhow::Result<()> {
     let args = Args::from_args();
 
     match args {
-        Args::Release(release) => {
+        Args::Release(mut release) => {
+            release.provenance = provenance_from_env();
             println!("{}", toml::to_string_pretty(&release)?);
         }
+        Args::StoreToken(store) => {
+            let mut token = String::new();
+            std::io::stdin().read_line(&mut token)?;
+            keyring_entry(&store.url)?.set_password(token.trim())?;
+            println!("Token stored.");
+        }
         Args::Upload(upload) => {
-            let auth =
-                std::env::var("PAHKAT_API_KEY").context("could not read env PAHKAT_API_KEY")?;
+            let auth = api_token(&upload.url)?;
 
             let release = std::fs::read_to_string(upload.release_meta)?;
             let mut release: Release = toml::from_str(&release)?;
+            if release.provenance.is_none() {
+                release.provenance = provenance_from_env();
+            }
 
             if let Some(path) = upload.metadata_json {
                 names_and_descs(&mut release, &path)
@@ -142,6 +195,39 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+fn env(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|x| !x.is_empty())
+}
+
+/// Where the release is being built, from the variables set by GitHub Actions or
+/// GitLab CI.
+fn provenance_from_env() -> Option<Provenance> {
+    let provenance = if let Some(repo) = env("GITHUB_REPOSITORY") {
+        let server = env("GITHUB_SERVER_URL").unwrap_or_else(|| "https://github.com".into());
+        let source_url = format!("{}/{}", server, repo);
+        Provenance::builder()
+            .source_url(source_url.parse().ok())
+            .commit(env("GITHUB_SHA"))
+            .ci_run_url(
+                env("GITHUB_RUN_ID")
+                    .and_then(|id| format!("{}/actions/runs/{}", source_url, id).parse().ok()),
+            )
+            .builder(env("GITHUB_WORKFLOW").map(|x| format!("GitHub Actions: {}", x)))
+            .build()
+    } else if env("GITLAB_CI").is_some() {
+        Provenance::builder()
+            .source_url(env("CI_PROJECT_URL").and_then(|x| x.parse().ok()))
+            .commit(env("CI_COMMIT_SHA"))
+            .ci_run_url(env("CI_PIPELINE_URL").and_then(|x| x.parse().ok()))
+            .builder(env("CI_JOB_NAME").map(|x| format!("GitLab CI: {}", x)))
+            .build()
+    } else {
+        return None;
+    };
+
+    Some(provenance).filter(|x| !x.is_empty())
+}
+
 fn names_and_descs(release: &mut Release, metadata_json: &Path) -> Result<()> {
     let metadata = std::fs::read_to_string(metadata_json)?;
     // assume json is like: {en: {name: "", description: ""}}